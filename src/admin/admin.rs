@@ -2,10 +2,10 @@
 use tuwunel_core::Result;
 
 use crate::{
-	appservice, appservice::AppserviceCommand, check, check::CheckCommand, context::Context,
-	debug, debug::DebugCommand, federation, federation::FederationCommand, media,
+	appservice, appservice::AppserviceCommand, check, check::CheckCommand, context::Context, db,
+	db::DbCommand, debug, debug::DebugCommand, federation, federation::FederationCommand, media,
 	media::MediaCommand, query, query::QueryCommand, room, room::RoomCommand, server,
-	server::ServerCommand, user, user::UserCommand,
+	server::ServerCommand, sync, sync::SyncCommand, user, user::UserCommand,
 };
 
 #[derive(Debug, Parser)]
@@ -35,10 +35,18 @@ pub(super) enum AdminCommand {
 	/// - Commands for managing media
 	Media(MediaCommand),
 
+	#[command(subcommand)]
+	/// - Commands for inspecting sliding sync connections
+	Sync(SyncCommand),
+
 	#[command(subcommand)]
 	/// - Commands for checking integrity
 	Check(CheckCommand),
 
+	#[command(subcommand)]
+	/// - Commands for maintaining the database
+	Db(DbCommand),
+
 	#[command(subcommand)]
 	/// - Commands for debugging things
 	Debug(DebugCommand),
@@ -61,6 +69,8 @@ pub(super) async fn process(command: AdminCommand, context: &Context<'_>) -> Res
 		| Server(command) => server::process(command, context).await,
 		| Debug(command) => debug::process(command, context).await,
 		| Query(command) => query::process(command, context).await,
+		| Sync(command) => sync::process(command, context).await,
 		| Check(command) => check::process(command, context).await,
+		| Db(command) => db::process(command, context).await,
 	}
 }
@@ -1,5 +1,6 @@
 use futures::{FutureExt, StreamExt, TryFutureExt};
-use tuwunel_core::{Err, Result, checked};
+use ruma::api::appservice::Registration;
+use tuwunel_core::{Err, Result, checked, utils::time::rfc2822_from_seconds};
 
 use crate::admin_command;
 
@@ -16,21 +17,31 @@ pub(super) async fn register(&self) -> Result {
 
 	let range = 1..checked!(body_len - 1)?;
 	let appservice_config_body = body[range].join("\n");
-	let parsed_config = serde_yaml::from_str(&appservice_config_body);
-	match parsed_config {
+	let registration: Registration = match serde_yaml::from_str(&appservice_config_body) {
 		| Err(e) => return Err!("Could not parse appservice config as YAML: {e}"),
-		| Ok(registration) => match self
-			.services
-			.appservice
-			.register_appservice(&registration, &appservice_config_body)
-			.await
-			.map(|()| registration.id)
-		{
-			| Err(e) => return Err!("Failed to register appservice: {e}"),
-			| Ok(id) => write!(self, "Appservice registered with ID: {id}"),
-		},
+		| Ok(registration) => registration,
+	};
+
+	if let Err(e) = self
+		.services
+		.appservice
+		.register_appservice(&registration, &appservice_config_body)
+		.await
+	{
+		return Err!("Failed to register appservice: {e}");
 	}
-	.await
+
+	let ping = match self
+		.services
+		.appservice
+		.ping(&registration, None)
+		.await
+	{
+		| Ok(duration) => format!("Ping succeeded in {duration:?}"),
+		| Err(e) => format!("Ping failed: {e}"),
+	};
+
+	write!(self, "Appservice registered with ID: {}\n{ping}", registration.id).await
 }
 
 #[admin_command]
@@ -47,6 +58,44 @@ pub(super) async fn unregister(&self, appservice_identifier: String) -> Result {
 	.await
 }
 
+#[admin_command]
+pub(super) async fn reload(&self, appservice_identifier: String) -> Result {
+	let body = &self.body;
+	let body_len = self.body.len();
+	let appservice_config_body = match body_len {
+		| 0 => None,
+		| _ if body_len < 2
+			|| !body[0].trim().starts_with("```")
+			|| body.last().unwrap_or(&"").trim() != "```" =>
+			return Err!("Expected code block in command body. Add --help for details."),
+		| _ => Some(body[1..checked!(body_len - 1)?].join("\n")),
+	};
+
+	let registration = match appservice_config_body
+		.as_deref()
+		.map(serde_yaml::from_str)
+	{
+		| None => None,
+		| Some(Err(e)) => return Err!("Could not parse appservice config as YAML: {e}"),
+		| Some(Ok(registration)) => Some(registration),
+	};
+
+	let update = registration
+		.as_ref()
+		.zip(appservice_config_body.as_deref());
+
+	match self
+		.services
+		.appservice
+		.reload_appservice(&appservice_identifier, update)
+		.await
+	{
+		| Err(e) => return Err!("Failed to reload appservice: {e}"),
+		| Ok(registration) => write!(self, "Appservice reloaded with ID: {}", registration.id),
+	}
+	.await
+}
+
 #[admin_command]
 pub(super) async fn show_appservice_config(&self, appservice_identifier: String) -> Result {
 	match self
@@ -56,7 +105,9 @@ pub(super) async fn show_appservice_config(&self, appservice_identifier: String)
 		.await
 	{
 		| None => return Err!("Appservice does not exist."),
-		| Some(config) => {
+		| Some(mut config) => {
+			config.as_token = "<redacted>".to_owned();
+			config.hs_token = "<redacted>".to_owned();
 			let config_str = serde_yaml::to_string(&config)?;
 			write!(self, "Config for {appservice_identifier}:\n\n```yaml\n{config_str}\n```")
 		},
@@ -78,3 +129,42 @@ pub(super) async fn list_registered(&self) -> Result {
 		})
 		.await
 }
+
+#[admin_command]
+pub(super) async fn appservice_status(&self, appservice_identifier: String) -> Result {
+	if self
+		.services
+		.appservice
+		.get_registration(&appservice_identifier)
+		.await
+		.is_none()
+	{
+		return Err!("Appservice does not exist.");
+	}
+
+	let queue = self
+		.services
+		.sending
+		.appservice_queue(&appservice_identifier)
+		.await;
+
+	let last_error = queue
+		.last_error
+		.map(|(at, error)| {
+			let at = i64::try_from(at / 1000)
+				.map(rfc2822_from_seconds)
+				.unwrap_or_default();
+
+			format!("{at}: {error}")
+		})
+		.unwrap_or_else(|| "none".to_owned());
+
+	write!(
+		self,
+		"Appservice {appservice_identifier}:\n- Queued events: {}\n- Events being delivered: \
+		 {}\n- Transactions awaiting acceptance: {}\n- Failures since last accepted: {}\n- Last \
+		 error: {last_error}",
+		queue.queued, queue.active, queue.transactions, queue.failures,
+	)
+	.await
+}
@@ -15,19 +15,32 @@ pub(super) enum AppserviceCommand {
 	///
 	/// Registering a new bridge using the ID of an existing bridge will replace
 	/// the old one.
+	#[mutating]
 	Register,
 
 	/// - Unregister an appservice using its ID
 	///
 	/// You can find the ID using the `list-appservices` command.
+	#[mutating]
 	Unregister {
 		/// The appservice to unregister
 		appservice_identifier: String,
 	},
 
+	/// - Reload an appservice's registration using its ID
+	///
+	/// Re-reads the stored registration, or replaces it with a YAML provided
+	/// in a Markdown code block below the command. The registration is
+	/// validated before it takes effect; on failure the old one is kept.
+	Reload {
+		/// The appservice to reload
+		appservice_identifier: String,
+	},
+
 	/// - Show an appservice's config using its ID
 	///
-	/// You can find the ID using the `list-appservices` command.
+	/// You can find the ID using the `list-appservices` command. Tokens are
+	/// redacted.
 	#[clap(alias("show"))]
 	ShowAppserviceConfig {
 		/// The appservice to show
@@ -37,4 +50,14 @@ pub(super) enum AppserviceCommand {
 	/// - List all the currently registered appservices
 	#[clap(alias("list"))]
 	ListRegistered,
+
+	/// - Show the delivery of transactions to an appservice using its ID
+	///
+	/// Shows the events awaiting delivery, the transactions kept until the
+	/// appservice accepts them, and the failures since the last accepted.
+	#[clap(name = "status")]
+	AppserviceStatus {
+		/// The appservice to show
+		appservice_identifier: String,
+	},
 }
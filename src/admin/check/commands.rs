@@ -1,5 +1,7 @@
+use std::fmt::Write;
+
 use futures::StreamExt;
-use tuwunel_core::Result;
+use tuwunel_core::{Result, utils::rand};
 use tuwunel_macros::implement;
 
 use crate::Context;
@@ -29,3 +31,38 @@ pub(super) async fn check_all_users(&self) -> Result {
 	))
 	.await
 }
+
+#[implement(Context, params = "<'_>")]
+pub(super) async fn check_shared_rooms(&self, sample: usize) -> Result {
+	let state_cache = &self.services.state_cache;
+	if !state_cache.shared_rooms_indexed() {
+		return self
+			.write_str("The shared rooms index has not been built yet.")
+			.await;
+	}
+
+	let timer = tokio::time::Instant::now();
+	let mut users = state_cache.local_joined_users().await;
+
+	rand::shuffle(&mut users);
+	users.truncate(sample);
+
+	let mismatches = state_cache.check_shared_rooms(&users).await;
+	let query_time = timer.elapsed();
+
+	let mut out = format!(
+		"Checked {} local users in {query_time:?}; found {} mismatched entries.\n",
+		users.len(),
+		mismatches.len()
+	);
+
+	if !mismatches.is_empty() {
+		writeln!(out, "\n```\nServer | User | Indexed | Computed")?;
+		for (server, user_id, indexed, computed) in &mismatches {
+			writeln!(out, "{server} | {user_id} | {indexed} | {computed}")?;
+		}
+		writeln!(out, "```")?;
+	}
+
+	self.write_str(&out).await
+}
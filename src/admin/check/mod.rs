@@ -9,4 +9,12 @@
 #[derive(Debug, Subcommand)]
 pub(super) enum CheckCommand {
 	CheckAllUsers,
+
+	/// Compares the index of rooms shared between servers and local users
+	/// against a recomputation for a random sample of local users.
+	CheckSharedRooms {
+		/// Number of local users to check.
+		#[arg(short, long, default_value = "100")]
+		sample: usize,
+	},
 }
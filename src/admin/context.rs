@@ -6,7 +6,7 @@
 	lock::Mutex,
 };
 use ruma::EventId;
-use tuwunel_core::Result;
+use tuwunel_core::{Err, Result};
 use tuwunel_service::Services;
 
 pub(crate) struct Context<'a> {
@@ -18,6 +18,15 @@ pub(crate) struct Context<'a> {
 }
 
 impl Context<'_> {
+	/// Refuses commands writing to the database when it is read-only.
+	pub(crate) fn check_writable(&self) -> Result {
+		if self.services.db.is_read_only() {
+			return Err!("The database is read-only; this command would write to it.");
+		}
+
+		Ok(())
+	}
+
 	pub(crate) fn write_fmt(
 		&self,
 		arguments: fmt::Arguments<'_>,
@@ -0,0 +1,122 @@
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+use clap::Subcommand;
+use tuwunel_core::{
+	Err, Result,
+	utils::{bytes, time},
+};
+use tuwunel_database::BackupEntry;
+
+use crate::{admin_command, admin_command_dispatch};
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(crate) enum DbBackupCommand {
+	/// - Starts an incremental online backup of the database
+	///
+	/// The backup runs in the background; its outcome is posted to the admin
+	/// room. Only one backup runs at a time. The oldest backups beyond
+	/// `database_backups_to_keep` are deleted afterwards.
+	#[clap(name = "create")]
+	BackupCreate {
+		/// Directory of the backups; defaults to `database_backup_path`.
+		path: Option<PathBuf>,
+	},
+
+	/// - Lists the backups with their sizes and timestamps
+	#[clap(name = "list")]
+	BackupList {
+		/// Directory of the backups; defaults to `database_backup_path`.
+		path: Option<PathBuf>,
+	},
+
+	/// - Shows the progress of the backup in progress, or the outcome of the
+	///   last one
+	#[clap(name = "status")]
+	BackupStatus,
+}
+
+#[admin_command]
+async fn backup_create(&self, path: Option<PathBuf>) -> Result {
+	if let Some(run) = self.services.db.db.backup_status()? {
+		if run.finished.is_none() {
+			return Err!("A backup into {:?} is already in progress.", run.path);
+		}
+	}
+
+	let db = Arc::clone(&self.services.db);
+	let admin = Arc::clone(&self.services.admin);
+	let runtime = self.services.server.runtime();
+	let backup = runtime.spawn_blocking(move || db.db.backup_create(path.as_deref()));
+	runtime.spawn(async move {
+		let body = match backup.await {
+			| Ok(Ok(entry)) => format!("Created database backup {entry}."),
+			| Ok(Err(e)) => format!("Database backup failed: {e}"),
+			| Err(e) => format!("Database backup failed: {e}"),
+		};
+
+		admin.notice(&body).await;
+	});
+
+	self.write_str("Started the backup; see `db backup status` for its progress.")
+		.await
+}
+
+#[admin_command]
+async fn backup_list(&self, path: Option<PathBuf>) -> Result {
+	let entries = self
+		.services
+		.db
+		.db
+		.backup_entries(path.as_deref())?;
+
+	if entries.is_empty() {
+		return self.write_str("No backups found.").await;
+	}
+
+	let total = entries
+		.iter()
+		.map(|entry| entry.size)
+		.fold(0_u64, u64::saturating_add);
+
+	let list = entries
+		.iter()
+		.map(BackupEntry::to_string)
+		.collect::<Vec<_>>()
+		.join("\n");
+
+	self.write_str(&format!(
+		"{} backups using {}:\n```\n{list}\n```",
+		entries.len(),
+		pretty_bytes(total),
+	))
+	.await
+}
+
+#[admin_command]
+async fn backup_status(&self) -> Result {
+	let Some(run) = self.services.db.db.backup_status()? else {
+		return self
+			.write_str("No backup has run since startup.")
+			.await;
+	};
+
+	let since = |time: SystemTime| time::pretty(time.elapsed().unwrap_or_default());
+	let path = run.path.display();
+	let out = match run.finished {
+		| None => format!(
+			"Backup into `{path}` in progress for {}: {} written so far.",
+			since(run.started),
+			pretty_bytes(run.written),
+		),
+		| Some((finished, Ok(entry))) =>
+			format!("Last backup into `{path}` finished {} ago: {entry}.", since(finished),),
+		| Some((finished, Err(e))) => {
+			format!("Last backup into `{path}` failed {} ago: {e}", since(finished))
+		},
+	};
+
+	self.write_str(&out).await
+}
+
+fn pretty_bytes(size: u64) -> String { bytes::pretty(size.try_into().unwrap_or(usize::MAX)) }
@@ -0,0 +1,106 @@
+use futures::StreamExt;
+use ruma::{OwnedRoomId, OwnedRoomOrAliasId};
+use tuwunel_core::{Result, utils::bytes};
+
+use crate::admin_command;
+
+#[admin_command]
+pub(super) async fn compress_state(
+	&self,
+	room: Option<OwnedRoomOrAliasId>,
+	all: bool,
+	dry_run: bool,
+) -> Result {
+	let room_id = match room {
+		| Some(room) if !all => Some(self.services.alias.resolve(&room).await?),
+		| _ => None,
+	};
+
+	let timer = tokio::time::Instant::now();
+	let stats = self
+		.services
+		.state_compressor
+		.purge_orphaned_states(room_id.as_deref(), dry_run)
+		.await?;
+
+	let elapsed = timer.elapsed();
+	let bytes = bytes::pretty(stats.bytes);
+	let out = if dry_run {
+		format!(
+			"Dry run in {elapsed:?}: {} of {} state groups are orphaned ({bytes}).",
+			stats.orphans, stats.groups
+		)
+	} else {
+		format!(
+			"Deleted {} of {} orphaned state groups ({bytes}) out of {} in {elapsed:?}.",
+			stats.deleted, stats.orphans, stats.groups
+		)
+	};
+
+	self.write_str(&out).await
+}
+
+/// Rooms rebuilt between progress reports.
+const REBUILD_PROGRESS_INTERVAL: usize = 100;
+
+#[admin_command]
+pub(super) async fn rebuild_search(
+	&self,
+	room_id: Option<OwnedRoomOrAliasId>,
+	all: bool,
+) -> Result {
+	let timer = tokio::time::Instant::now();
+	if let Some(room_id) = room_id.filter(|_| !all) {
+		let room_id = self.services.alias.resolve(&room_id).await?;
+		let indexed = self
+			.services
+			.search
+			.rebuild_index(&room_id)
+			.await?;
+
+		let elapsed = timer.elapsed();
+		return self
+			.write_str(&format!("Indexed {indexed} messages of {room_id} in {elapsed:?}."))
+			.await;
+	}
+
+	let room_ids: Vec<OwnedRoomId> = self
+		.services
+		.metadata
+		.iter_ids()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let (mut indexed, mut failed) = (0_usize, 0_usize);
+	for (i, room_id) in room_ids.iter().enumerate() {
+		match self.services.search.rebuild_index(room_id).await {
+			| Ok(count) => indexed = indexed.saturating_add(count),
+			| Err(e) => {
+				failed = failed.saturating_add(1);
+				self.services
+					.admin
+					.send_text(&format!("Failed to rebuild the search index of {room_id}: {e}"))
+					.await;
+			},
+		}
+
+		let done = i.saturating_add(1);
+		if done.is_multiple_of(REBUILD_PROGRESS_INTERVAL) {
+			self.services
+				.admin
+				.send_text(&format!(
+					"Rebuilt the search index of {done} of {} rooms...",
+					room_ids.len()
+				))
+				.await;
+		}
+	}
+
+	let elapsed = timer.elapsed();
+	self.write_str(&format!(
+		"Indexed {indexed} messages of {} rooms in {elapsed:?}; {failed} failed.",
+		room_ids.len()
+	))
+	.await
+}
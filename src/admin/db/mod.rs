@@ -0,0 +1,57 @@
+mod backup;
+mod commands;
+
+use clap::Subcommand;
+use ruma::OwnedRoomOrAliasId;
+use tuwunel_core::Result;
+
+use self::backup::DbBackupCommand;
+use crate::admin_command_dispatch;
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(super) enum DbCommand {
+	/// - Online backups of the database
+	#[command(subcommand)]
+	Backup(DbBackupCommand),
+
+	/// - Deletes state groups nothing refers to anymore
+	///
+	/// State resets and purged rooms leave behind state groups which are never
+	/// read again. With a room, only orphans sharing a layer with the current
+	/// state of the room are deleted; orphans of rooms which are gone entirely
+	/// require `--all`. A purge can be interrupted and run again.
+	#[mutating]
+	CompressState {
+		/// Room to compress the state of.
+		#[arg(
+			long,
+			required_unless_present = "all",
+			conflicts_with = "all"
+		)]
+		room: Option<OwnedRoomOrAliasId>,
+
+		/// Compress the state of every room.
+		#[arg(long)]
+		all: bool,
+
+		/// Only count the orphaned state groups.
+		#[arg(long)]
+		dry_run: bool,
+	},
+
+	/// - Rebuilds the full-text search index of rooms
+	///
+	/// Deletes the search index of the room and indexes its messages again
+	/// with the tokenizer configured in `[global.search]`.
+	#[mutating]
+	RebuildSearch {
+		/// Room to rebuild the index of.
+		#[arg(required_unless_present = "all", conflicts_with = "all")]
+		room_id: Option<OwnedRoomOrAliasId>,
+
+		/// Rebuild the index of every room.
+		#[arg(long)]
+		all: bool,
+	},
+}
@@ -1,5 +1,5 @@
 use std::{
-	collections::HashMap,
+	collections::{BTreeSet, HashMap, HashSet, VecDeque},
 	fmt::Write,
 	iter::once,
 	str::FromStr,
@@ -18,22 +18,29 @@
 	Err, Result, debug_error, err, info, jwt,
 	matrix::{
 		Event,
-		pdu::{PduEvent, PduId, RawPduId},
+		pdu::{PduCount, PduEvent, PduId, RawPduId},
 	},
 	trace, utils,
 	utils::{
-		stream::{IterStream, ReadyExt},
+		stream::{IterStream, ReadyExt, TryIgnore},
 		string::EMPTY,
 		time::now_secs,
 	},
 	warn,
 };
-use tuwunel_service::rooms::{
-	short::{ShortEventId, ShortRoomId},
-	state_compressor::HashSetCompressStateEvent,
+use tuwunel_service::{
+	Services,
+	rooms::{
+		short::{ShortEventId, ShortRoomId},
+		state_compressor::{HashSetCompressStateEvent, parse_compressed_state_event},
+	},
+	server_keys::{self, SignatureCheck},
 };
 
-use crate::admin_command;
+use crate::{EXTREMITY_COUNT_MAX, admin_command};
+
+/// Most state events listed by kind of change after forcing the state.
+const STATE_DIFF_LIST_MAX: usize = 25;
 
 #[admin_command]
 pub(super) async fn echo(&self, message: Vec<String>) -> Result {
@@ -105,7 +112,14 @@ pub(super) async fn parse_pdu(&self) -> Result {
 }
 
 #[admin_command]
-pub(super) async fn get_pdu(&self, event_id: OwnedEventId) -> Result {
+pub(super) async fn get_pdu(
+	&self,
+	event_id: OwnedEventId,
+	raw: bool,
+	context: Option<usize>,
+	state: bool,
+	auth_chain: bool,
+) -> Result {
 	let mut outlier = false;
 	let mut pdu_json = self
 		.services
@@ -122,19 +136,211 @@ pub(super) async fn get_pdu(&self, event_id: OwnedEventId) -> Result {
 			.await;
 	}
 
-	match pdu_json {
-		| Err(_) => return Err!("PDU not found locally."),
-		| Ok(json) => {
-			let text = serde_json::to_string_pretty(&json)?;
-			let msg = if outlier {
-				"Outlier (Rejected / Soft Failed) PDU found in our database"
-			} else {
-				"PDU found in our database"
-			};
-			write!(self, "{msg}\n```json\n{text}\n```",)
-		},
+	let Ok(json) = pdu_json else {
+		return Err!("PDU not found locally.");
+	};
+
+	let text = if raw {
+		serde_json::to_string(&json)?
+	} else {
+		serde_json::to_string_pretty(&json)?
+	};
+
+	let msg = if outlier {
+		"Outlier (Rejected / Soft Failed) PDU found in our database"
+	} else {
+		"PDU found in our database"
+	};
+
+	let mut out = format!("{msg}\n```json\n{text}\n```\n");
+	writeln!(out, "Signatures:\n{}", format_signatures(&json))?;
+
+	if context.is_some() || state || auth_chain {
+		let pdu = self.services.timeline.get_pdu(&event_id).await?;
+
+		if let Some(context) = context {
+			write_pdu_context(self.services, &mut out, &pdu, context).await?;
+		}
+
+		if state {
+			write_pdu_state(self.services, &mut out, &pdu).await?;
+		}
+
+		if auth_chain {
+			write_pdu_auth_chain(self.services, &mut out, &pdu).await?;
+		}
 	}
-	.await
+
+	self.write_str(&out).await
+}
+
+/// Most events listed in each section of `get-pdu`.
+const GET_PDU_LIST_MAX: usize = 100;
+
+/// The server and key IDs of the signatures of the event.
+pub(crate) fn format_signatures(json: &CanonicalJsonObject) -> String {
+	let Some(CanonicalJsonValue::Object(signatures)) = json.get("signatures") else {
+		return "- none".to_owned();
+	};
+
+	signatures
+		.iter()
+		.map(|(server, keys)| match keys {
+			| CanonicalJsonValue::Object(keys) => {
+				let key_ids: Vec<_> = keys.keys().map(String::as_str).collect();
+				format!("- {server}: {}", key_ids.join(", "))
+			},
+			| _ => format!("- {server}: invalid"),
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+async fn write_pdu_context(
+	services: &Services,
+	out: &mut String,
+	pdu: &PduEvent,
+	context: usize,
+) -> Result {
+	writeln!(out, "\nContext:")?;
+	let Ok(count) = services
+		.timeline
+		.get_pdu_count(pdu.event_id())
+		.await
+	else {
+		writeln!(out, "The event is an outlier, which is not in the timeline.")?;
+		return Ok(());
+	};
+
+	let limit = context.min(GET_PDU_LIST_MAX);
+	let before: Vec<_> = services
+		.timeline
+		.pdus_rev(None, pdu.room_id(), Some(count))
+		.ignore_err()
+		.take(limit)
+		.collect()
+		.await;
+
+	let after: Vec<_> = services
+		.timeline
+		.pdus(None, pdu.room_id(), Some(count))
+		.ignore_err()
+		.take(limit)
+		.collect()
+		.await;
+
+	let line = |count: &PduCount, pdu: &PduEvent| {
+		format!("{count} | {} | {} | {}", pdu.event_id(), pdu.kind(), pdu.sender())
+	};
+
+	for (count, pdu) in before.iter().rev() {
+		writeln!(out, "{}", line(count, pdu))?;
+	}
+
+	writeln!(out, "{} <- this event", line(&count, pdu))?;
+	for (count, pdu) in &after {
+		writeln!(out, "{}", line(count, pdu))?;
+	}
+
+	if context > limit {
+		writeln!(out, "Limited to {limit} events on each side.")?;
+	}
+
+	Ok(())
+}
+
+async fn write_pdu_state(services: &Services, out: &mut String, pdu: &PduEvent) -> Result {
+	writeln!(out, "\nState:")?;
+	let Ok(shortstatehash) = services
+		.state_accessor
+		.pdu_shortstatehash(pdu.event_id())
+		.await
+	else {
+		writeln!(out, "No state is stored for the event.")?;
+		return Ok(());
+	};
+
+	let shortstatekeys: Vec<_> = services
+		.state_accessor
+		.state_full_shortids(shortstatehash)
+		.ignore_err()
+		.map(|(shortstatekey, _)| shortstatekey)
+		.collect()
+		.await;
+
+	let keys: Vec<_> = services
+		.short
+		.multi_get_statekey_from_short(
+			shortstatekeys
+				.iter()
+				.take(GET_PDU_LIST_MAX)
+				.copied()
+				.stream(),
+		)
+		.ignore_err()
+		.collect()
+		.await;
+
+	for (event_type, state_key) in &keys {
+		writeln!(out, "{event_type} | {state_key:?}")?;
+	}
+
+	let more = shortstatekeys.len().saturating_sub(keys.len());
+	if more > 0 {
+		writeln!(out, "+{more} more")?;
+	}
+
+	Ok(())
+}
+
+/// Walks the auth chain of the event breadth-first, so each event is listed
+/// with its shortest distance from the event.
+async fn write_pdu_auth_chain(services: &Services, out: &mut String, pdu: &PduEvent) -> Result {
+	writeln!(out, "\nAuth chain:")?;
+	let mut seen: HashSet<OwnedEventId> = HashSet::new();
+	let mut queue: VecDeque<(OwnedEventId, usize)> = pdu
+		.auth_events()
+		.map(|event_id| (event_id.to_owned(), 1))
+		.collect();
+
+	let mut listed: usize = 0;
+	while let Some((event_id, depth)) = queue.pop_front() {
+		if !seen.insert(event_id.clone()) {
+			continue;
+		}
+
+		if listed >= GET_PDU_LIST_MAX {
+			writeln!(out, "Stopped after {listed} events.")?;
+			break;
+		}
+
+		listed = listed.saturating_add(1);
+		let Ok(auth_pdu) = services.timeline.get_pdu(&event_id).await else {
+			writeln!(out, "{depth} | {event_id} | not found")?;
+			continue;
+		};
+
+		writeln!(
+			out,
+			"{depth} | {event_id} | {} | {:?}",
+			auth_pdu.kind(),
+			auth_pdu.state_key().unwrap_or_default(),
+		)?;
+
+		let next = depth.saturating_add(1);
+		queue.extend(
+			auth_pdu
+				.auth_events()
+				.filter(|auth_event| !seen.contains(*auth_event))
+				.map(|auth_event| (auth_event.to_owned(), next)),
+		);
+	}
+
+	if listed == 0 {
+		writeln!(out, "The event has no auth events.")?;
+	}
+
+	Ok(())
 }
 
 #[admin_command]
@@ -454,55 +660,114 @@ pub(super) async fn sign_json(&self) -> Result {
 }
 
 #[admin_command]
-pub(super) async fn verify_json(&self) -> Result {
+pub(super) async fn verify_json(&self, server_name: Option<OwnedServerName>) -> Result {
 	if self.body.len() < 2
 		|| !self.body[0].trim().starts_with("```")
-		|| self.body.last().unwrap_or(&"").trim() != "```"
+		|| self.body.last().unwrap_or(&EMPTY).trim() != "```"
 	{
 		return Err!("Expected code block in command body. Add --help for details.");
 	}
 
-	let string = self.body[1..self.body.len().checked_sub(1).unwrap()].join("\n");
-	match serde_json::from_str::<CanonicalJsonObject>(&string) {
+	let string = self.body[1..self.body.len().saturating_sub(1)].join("\n");
+	let value: CanonicalJsonObject = match serde_json::from_str(&string) {
 		| Err(e) => return Err!("Invalid json: {e}"),
-		| Ok(value) => match self
-			.services
-			.server_keys
-			.verify_json(&value, None)
-			.await
-		{
-			| Err(e) => return Err!("Signature verification failed: {e}"),
-			| Ok(()) => write!(self, "Signature correct"),
-		},
+		| Ok(value) => value,
+	};
+
+	let checks = self
+		.services
+		.server_keys
+		.check_signatures(&value, server_name.as_deref())
+		.await?;
+
+	if checks.is_empty() {
+		return Err!("No signatures to verify.");
 	}
-	.await
+
+	let mut out = String::new();
+	write_signature_checks(&mut out, &checks, &server_keys::signed_json(&value)?)?;
+	self.write_str(&out).await
 }
 
 #[admin_command]
 pub(super) async fn verify_pdu(&self, event_id: OwnedEventId) -> Result {
-	use ruma::signatures::Verified;
-
 	let mut event = self
 		.services
 		.timeline
 		.get_pdu_json(&event_id)
 		.await?;
 
-	event.remove("event_id");
-	let msg = match self
+	let room_id = event
+		.get("room_id")
+		.and_then(CanonicalJsonValue::as_str)
+		.and_then(|room_id| <&RoomId>::try_from(room_id).ok())
+		.ok_or_else(|| err!(Database("Invalid room_id in event {event_id}")))?;
+
+	let room_version_id = self
 		.services
-		.server_keys
-		.verify_event(&event, None)
+		.state
+		.get_room_version(room_id)
 		.await
-	{
-		| Err(e) => return Err(e),
-		| Ok(Verified::Signatures) => "signatures OK, but content hash failed (redaction).",
-		| Ok(Verified::All) => "signatures and hashes OK.",
-	};
+		.map_err(|e| err!("Failed to get the room version of {room_id}: {e}"))?;
+
+	let rules = room_version_id
+		.rules()
+		.ok_or_else(|| err!("Unsupported room version {room_version_id}"))?;
+
+	// The event_id is only part of the event as sent in v1 and v2 rooms.
+	if !rules.event_format.require_event_id {
+		event.remove("event_id");
+	}
+
+	let mut out = String::new();
+	writeln!(out, "Room version: {room_version_id}")?;
+
+	let reference_hash = ruma::signatures::reference_hash(&event, &rules)?;
+	if rules.event_format.require_event_id {
+		writeln!(out, "Reference hash: {reference_hash}")?;
+	} else {
+		let result = pass_fail(event_id.as_str() == format!("${reference_hash}"));
+		writeln!(out, "Reference hash: {reference_hash} ({result} for {event_id})")?;
+	}
+
+	let (computed, claimed) = server_keys::content_hash(&event)?;
+	match claimed {
+		| Some(claimed) => {
+			let result = pass_fail(computed == claimed);
+			writeln!(out, "Content hash: {computed} ({result}, claimed {claimed})")?;
+		},
+		| None => writeln!(out, "Content hash: {computed} (FAILED, none claimed)")?,
+	}
+
+	// Signatures cover the redacted event, whatever was redacted since.
+	let redacted = ruma::canonical_json::redact(event, &rules.redaction, None)
+		.map_err(|e| err!("Failed to redact event: {e}"))?;
+
+	let checks = self
+		.services
+		.server_keys
+		.check_signatures(&redacted, None)
+		.await?;
 
-	self.write_str(msg).await
+	write_signature_checks(&mut out, &checks, &server_keys::signed_json(&redacted)?)?;
+	self.write_str(&out).await
 }
 
+fn write_signature_checks(out: &mut String, checks: &[SignatureCheck], signed: &str) -> Result {
+	writeln!(out, "Signatures:")?;
+	for SignatureCheck { server, key_id, result } in checks {
+		match result {
+			| Ok(()) => writeln!(out, "- {server} {key_id}: OK")?,
+			| Err(e) => writeln!(out, "- {server} {key_id}: FAILED: {e}")?,
+		}
+	}
+
+	writeln!(out, "\nSigned JSON:\n```json\n{signed}\n```")?;
+	Ok(())
+}
+
+fn pass_fail(passed: bool) -> &'static str { if passed { "OK" } else { "FAILED" } }
+
 #[admin_command]
 #[tracing::instrument(skip(self))]
 pub(super) async fn first_pdu_in_room(&self, room_id: OwnedRoomId) -> Result {
@@ -549,12 +814,138 @@ pub(super) async fn latest_pdu_in_room(&self, room_id: OwnedRoomId) -> Result {
 	self.write_str(&out).await
 }
 
+#[admin_command]
+pub(super) async fn room_extremities(&self, room_id: OwnedRoomId) -> Result {
+	let extremities: Vec<_> = self
+		.services
+		.state
+		.get_forward_extremities(&room_id)
+		.take(EXTREMITY_COUNT_MAX)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	if extremities.is_empty() {
+		return Err!("No forward extremities found for {room_id}.");
+	}
+
+	let mut extremities: Vec<_> = extremities
+		.into_iter()
+		.stream()
+		.then(async |event_id| {
+			let pdu = self
+				.services
+				.timeline
+				.get_pdu(&event_id)
+				.await
+				.ok();
+			(event_id, pdu)
+		})
+		.collect()
+		.await;
+
+	let out = format_extremities(&mut extremities);
+	self.write_str(&format!("Forward extremities of {room_id}:\n```\n{out}\n```"))
+		.await
+}
+
+#[admin_command]
+pub(super) async fn room_integrity(&self, room_id: OwnedRoomOrAliasId) -> Result {
+	let room_id = self.services.alias.resolve(&room_id).await?;
+
+	let mut shortstatehashes: BTreeSet<_> = self
+		.services
+		.timeline
+		.pdus(None, &room_id, None)
+		.ignore_err()
+		.then(async |(_, pdu)| {
+			self.services
+				.state_accessor
+				.pdu_shortstatehash(&pdu.event_id)
+				.await
+		})
+		.ready_filter_map(Result::ok)
+		.collect()
+		.await;
+
+	if let Ok(current) = self
+		.services
+		.state
+		.get_room_shortstatehash(&room_id)
+		.await
+	{
+		shortstatehashes.insert(current);
+	}
+
+	shortstatehashes.extend(
+		self.services
+			.state_compressor
+			.reported_corrupt(&room_id),
+	);
+
+	let report = self
+		.services
+		.state_compressor
+		.check_integrity(&room_id, shortstatehashes)
+		.await;
+
+	let mut out = format!(
+		"Checked {} state groups of {room_id}: {} corrupt.",
+		report.checked,
+		report.corrupt.len()
+	);
+
+	for (shortstatehash, error) in &report.corrupt {
+		write!(out, "\n- {shortstatehash}: {error}")?;
+	}
+
+	self.write_str(&out).await
+}
+
+const EXTREMITIES_SHOWN: usize = 20;
+
+/// Lists the deepest extremities first, noting how many more were not shown.
+pub(crate) fn format_extremities(
+	extremities: &mut Vec<(OwnedEventId, Option<PduEvent>)>,
+) -> String {
+	let total = extremities.len();
+	extremities.sort_by(|(a_id, a), (b_id, b)| {
+		let depth = |pdu: &Option<PduEvent>| pdu.as_ref().map(|pdu| pdu.depth);
+		depth(b)
+			.cmp(&depth(a))
+			.then_with(|| a_id.cmp(b_id))
+	});
+	extremities.truncate(EXTREMITIES_SHOWN);
+
+	let mut out: Vec<_> = extremities
+		.iter()
+		.map(|(event_id, pdu)| match pdu {
+			| Some(pdu) => format!(
+				"{event_id} | depth: {} | ts: {} | sender: {}",
+				pdu.depth,
+				pdu.origin_server_ts,
+				pdu.sender(),
+			),
+			| None => format!("{event_id} | not found"),
+		})
+		.collect();
+
+	let more = total.saturating_sub(extremities.len());
+	if more > 0 {
+		let plus = if total >= EXTREMITY_COUNT_MAX { "+" } else { "" };
+		out.push(format!("+{more}{plus} more"));
+	}
+
+	out.join("\n")
+}
+
 #[admin_command]
 #[tracing::instrument(skip(self))]
 pub(super) async fn force_set_room_state_from_server(
 	&self,
 	room_id: OwnedRoomId,
 	server_name: OwnedServerName,
+	yes_i_know: bool,
 ) -> Result {
 	if !self
 		.services
@@ -565,12 +956,30 @@ pub(super) async fn force_set_room_state_from_server(
 		return Err!("We are not participating in the room / we don't know about the room ID.");
 	}
 
-	let first_pdu = self
+	let local_users = self
 		.services
-		.timeline
-		.latest_pdu_in_room(&room_id)
+		.state_cache
+		.local_users_in_room(&room_id)
+		.count()
+		.await;
+
+	if local_users > 0 && !yes_i_know {
+		return Err!(
+			"{room_id} has {local_users} local users whose membership may change with the \
+			 state. Pass --yes-i-know to replace it anyway."
+		);
+	}
+
+	let extremity: OwnedEventId = self
+		.services
+		.state
+		.get_forward_extremities(&room_id)
+		.map(ToOwned::to_owned)
+		.collect::<Vec<_>>()
 		.await
-		.map_err(|_| err!(Database("Failed to find the latest PDU in database")))?;
+		.into_iter()
+		.next()
+		.ok_or_else(|| err!(Database("Failed to find a forward extremity of the room")))?;
 
 	let room_version = self
 		.services
@@ -585,7 +994,7 @@ pub(super) async fn force_set_room_state_from_server(
 		.sending
 		.send_federation_request(&server_name, get_room_state::v1::Request {
 			room_id: room_id.clone(),
-			event_id: first_pdu.event_id().to_owned(),
+			event_id: extremity,
 		})
 		.await?;
 
@@ -663,6 +1072,7 @@ pub(super) async fn force_set_room_state_from_server(
 			.add_pdu_outlier(&event_id, &value);
 	}
 
+	let state_lock = self.services.state.mutex.lock(&*room_id).await;
 	let new_room_state = self
 		.services
 		.event_handler
@@ -680,13 +1090,23 @@ pub(super) async fn force_set_room_state_from_server(
 		.save_state(room_id.clone().as_ref(), new_room_state)
 		.await?;
 
-	let state_lock = self.services.state.mutex.lock(&*room_id).await;
+	let added_keys: BTreeSet<_> = added
+		.iter()
+		.map(|&event| parse_compressed_state_event(event).0)
+		.collect();
+
+	let removed_keys: BTreeSet<_> = removed
+		.iter()
+		.map(|&event| parse_compressed_state_event(event).0)
+		.collect();
 
 	self.services
 		.state
 		.force_state(room_id.clone().as_ref(), short_state_hash, added, removed, &state_lock)
 		.await?;
 
+	drop(state_lock);
+
 	info!(
 		"Updating joined counts for room just in case (e.g. we may have found a difference in \
 		 the room's m.room.member state"
@@ -696,8 +1116,47 @@ pub(super) async fn force_set_room_state_from_server(
 		.update_joined_count(&room_id)
 		.await;
 
-	self.write_str("Successfully forced the room state from the requested remote server.")
-		.await
+	let mut out =
+		String::from("Successfully forced the room state from the requested remote server.");
+	let changes: [(&str, Vec<_>); 3] = [
+		(
+			"Added",
+			added_keys
+				.difference(&removed_keys)
+				.copied()
+				.collect(),
+		),
+		(
+			"Replaced",
+			added_keys
+				.intersection(&removed_keys)
+				.copied()
+				.collect(),
+		),
+		(
+			"Removed",
+			removed_keys
+				.difference(&added_keys)
+				.copied()
+				.collect(),
+		),
+	];
+
+	for (label, shortstatekeys) in changes {
+		writeln!(out, "\n{label}: {}", shortstatekeys.len())?;
+		for &shortstatekey in shortstatekeys.iter().take(STATE_DIFF_LIST_MAX) {
+			if let Ok((event_type, state_key)) = self
+				.services
+				.short
+				.get_statekey_from_short(shortstatekey)
+				.await
+			{
+				writeln!(out, "- {event_type} {state_key:?}")?;
+			}
+		}
+	}
+
+	self.write_str(&out).await
 }
 
 #[admin_command]
@@ -1,4 +1,4 @@
-mod commands;
+pub(crate) mod commands;
 pub(crate) mod tester;
 
 use clap::Subcommand;
@@ -33,9 +33,30 @@ pub(super) enum DebugCommand {
 	ParsePdu,
 
 	/// - Retrieve and print a PDU by EventID from the tuwunel database
+	///
+	/// The signatures of the event are listed after it; `verify-pdu` checks
+	/// them. Long lists are cut short with a note.
 	GetPdu {
 		/// An event ID (a $ followed by the base64 reference hash)
 		event_id: OwnedEventId,
+
+		/// Print the canonical JSON of the event instead of formatting it.
+		#[arg(long)]
+		raw: bool,
+
+		/// Print this many events of the timeline before and after the event.
+		#[arg(long, value_name = "N")]
+		context: Option<usize>,
+
+		/// Print the type and state key of the events in the state of the
+		/// event.
+		#[arg(long)]
+		state: bool,
+
+		/// Print the auth chain of the event, each with its distance from the
+		/// event.
+		#[arg(long)]
+		auth_chain: bool,
 	},
 
 	/// - Retrieve and print a PDU by PduId from the tuwunel database
@@ -50,6 +71,7 @@ pub(super) enum DebugCommand {
 	/// - Attempts to retrieve a PDU from a remote server. Inserts it into our
 	///   database/timeline if found and we do not have this PDU already
 	///   (following normal event auth rules, handles it as an incoming PDU).
+	#[mutating]
 	GetRemotePdu {
 		/// An event ID (a $ followed by the base64 reference hash)
 		event_id: OwnedEventId,
@@ -61,6 +83,7 @@ pub(super) enum DebugCommand {
 
 	/// - Same as `get-remote-pdu` but accepts a codeblock newline delimited
 	///   list of PDUs and a single server to fetch from
+	#[mutating]
 	GetRemotePduList {
 		/// Argument for us to attempt to fetch all the events from the
 		/// specified remote server.
@@ -102,6 +125,7 @@ pub(super) enum DebugCommand {
 
 	/// - Forces device lists for all local and remote users to be updated (as
 	///   having new keys available)
+	#[mutating]
 	ForceDeviceListUpdates,
 
 	/// - Change tracing log level/filter on the fly
@@ -125,12 +149,18 @@ pub(super) enum DebugCommand {
 	/// - Verify JSON signatures
 	///
 	/// This command needs a JSON blob provided in a Markdown code block below
-	/// the command.
-	VerifyJson,
+	/// the command. Each signature is checked on its own with the keys of its
+	/// server, only those of the given server if any, and the canonical JSON
+	/// they cover is shown.
+	VerifyJson {
+		server_name: Option<OwnedServerName>,
+	},
 
 	/// - Verify PDU
 	///
-	/// This re-verifies a PDU existing in the database found by ID.
+	/// This re-verifies a PDU existing in the database found by ID, reporting
+	/// its reference and content hashes, each of its signatures and the
+	/// canonical JSON they cover.
 	VerifyPdu {
 		event_id: OwnedEventId,
 	},
@@ -149,6 +179,21 @@ pub(super) enum DebugCommand {
 		room_id: OwnedRoomId,
 	},
 
+	/// - Lists the forward extremities of the room with the depth, timestamp
+	///   and sender of each, to find the servers forking the room
+	RoomExtremities {
+		/// The room ID
+		room_id: OwnedRoomId,
+	},
+
+	/// - Checks that every state group of the room decodes
+	///
+	/// The current state, the state at each event and any state reported
+	/// corrupt while loading are checked along with the layers beneath them.
+	RoomIntegrity {
+		room_id: OwnedRoomOrAliasId,
+	},
+
 	/// - Forcefully replaces the room state of our local copy of the specified
 	///   room, with the copy (auth chain and room state events) the specified
 	///   remote server says.
@@ -160,14 +205,22 @@ pub(super) enum DebugCommand {
 	/// their room state. Such example is your server saying users are in a
 	/// room, but other servers are saying they're not in the room in question.
 	///
-	/// This command will get the latest PDU in the room we know about, and
-	/// request the room state at that point in time via
-	/// `/_matrix/federation/v1/state/{roomId}`.
+	/// This command will get a forward extremity of the room we know about,
+	/// and request the room state at that point in time via
+	/// `/_matrix/federation/v1/state/{roomId}`. The state events changed are
+	/// listed after.
+	///
+	/// Rooms with local users are refused unless `--yes-i-know` is passed, as
+	/// their membership may change with the state.
+	#[mutating]
 	ForceSetRoomStateFromServer {
 		/// The impacted room ID
 		room_id: OwnedRoomId,
 		/// The server we will use to query the room state for
 		server_name: OwnedServerName,
+		/// Replace the state of a room with local users
+		#[arg(long)]
+		yes_i_know: bool,
 	},
 
 	/// - Runs a server name through tuwunel's true destination resolution
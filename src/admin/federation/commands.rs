@@ -1,6 +1,13 @@
+use std::fmt::Write;
+
 use futures::StreamExt;
 use ruma::{OwnedRoomId, OwnedServerName, OwnedUserId};
-use tuwunel_core::{Err, Result};
+use tuwunel_core::{Err, Result, utils::time::rfc2822_from_seconds};
+use tuwunel_service::{
+	federation::describe_skew,
+	rooms::event_handler::Summary,
+	sending::{Destination, DestinationHealth},
+};
 
 use crate::{admin_command, get_room_info};
 
@@ -99,3 +106,153 @@ pub(super) async fn remote_user_in_rooms(&self, user_id: OwnedUserId) -> Result
 	self.write_str(&format!("Rooms {user_id} shares with us ({num}):\n```\n{body}\n```",))
 		.await
 }
+
+#[admin_command]
+pub(super) async fn origin_stats(&self, server_name: Option<OwnedServerName>) -> Result {
+	let Some(server_name) = server_name else {
+		let summaries = self.services.event_handler.origin_summaries();
+		let mut skews = self.services.federation.clock_skews();
+		if summaries.is_empty() && skews.is_empty() {
+			return Err!("No PDUs or timestamps received from any origin yet.");
+		}
+
+		let mut out = String::new();
+		if let Some(own_skew) = self.services.federation.own_clock_skew() {
+			writeln!(
+				out,
+				"Our clock seems to be {} most other servers; check your system clock.\n",
+				describe_skew(own_skew)
+			)?;
+		}
+
+		let num = summaries.len();
+		writeln!(out, "Origins by mean PDU time ({num}):\n```")?;
+		for (origin, summary) in &summaries {
+			writeln!(out, "{origin} | {}", summary_line(summary))?;
+		}
+
+		skews.sort_by_key(|(_, skew)| std::cmp::Reverse(skew.unsigned_abs()));
+		let num = skews.len();
+		writeln!(out, "```\nClock skew of origins ({num}):\n```")?;
+		for (origin, skew) in &skews {
+			writeln!(out, "{origin} | {} ours", describe_skew(*skew))?;
+		}
+
+		writeln!(out, "```")?;
+		return self.write_str(&out).await;
+	};
+
+	let summary = self
+		.services
+		.event_handler
+		.origin_summary(&server_name);
+
+	let skew = self.services.federation.clock_skew(&server_name);
+	if summary.is_none() && skew.is_none() {
+		return Err!("No recent PDUs or timestamps received from {server_name}.");
+	}
+
+	let mut out = format!("{server_name}\n");
+	if let Some(skew) = skew {
+		writeln!(out, "Clock skew: {} ours", describe_skew(skew))?;
+	}
+
+	if let Some(summary) = summary {
+		writeln!(out, "{}\n\nSlowest PDUs:\n```", summary_line(&summary))?;
+		for sample in &summary.slowest {
+			writeln!(out, "{:?} | {} | {}", sample.elapsed, sample.kind, sample.event_id)?;
+		}
+
+		writeln!(out, "```")?;
+	}
+
+	self.write_str(&out).await
+}
+
+fn summary_line(summary: &Summary) -> String {
+	format!(
+		"PDUs: {} | mean: {:?} | p50: {:?} | p90: {:?} | p99: {:?} | max: {:?}",
+		summary.count, summary.mean, summary.p50, summary.p90, summary.p99, summary.max,
+	)
+}
+
+#[admin_command]
+pub(super) async fn destinations(&self, server_name: Option<OwnedServerName>) -> Result {
+	let Some(server_name) = server_name else {
+		let health = self.services.sending.destinations_health();
+		if health.is_empty() {
+			return self
+				.write_str("No federation destination failed lately.")
+				.await;
+		}
+
+		let dormant = health
+			.iter()
+			.filter(|(_, health)| health.dormant_since.is_some())
+			.count();
+
+		let mut out =
+			format!("Destinations failing lately ({}), {dormant} dormant:\n```\n", health.len());
+		for (server_name, health) in &health {
+			writeln!(out, "{server_name} | {}", health_line(health)?)?;
+		}
+
+		writeln!(out, "```")?;
+		return self.write_str(&out).await;
+	};
+
+	let dest = Destination::Federation(server_name.clone());
+	let queued = self
+		.services
+		.sending
+		.db
+		.queued_requests(&dest)
+		.count()
+		.await;
+
+	let active = self
+		.services
+		.sending
+		.db
+		.active_requests_for(&dest)
+		.count()
+		.await;
+
+	let health = match self
+		.services
+		.sending
+		.destination_health(&server_name)
+	{
+		| Some(health) => health_line(&health)?,
+		| None => "not failing".to_owned(),
+	};
+
+	write!(
+		self,
+		"{server_name}: {health}\n- Queued events: {queued}\n- Events being delivered: {active}",
+	)
+	.await
+}
+
+fn health_line(health: &DestinationHealth) -> Result<String> {
+	let date = |millis: u64| {
+		i64::try_from(millis / 1000)
+			.map(rfc2822_from_seconds)
+			.unwrap_or_default()
+	};
+
+	let mut line = format!("{} failures", health.failures);
+	if let Some(since) = health.failing_since {
+		write!(line, " since {}", date(since))?;
+	}
+
+	if let Some(since) = health.dormant_since {
+		write!(line, ", dormant since {}", date(since))?;
+	}
+
+	if let Some((at, reason)) = &health.woken {
+		write!(line, ", woken by {reason} at {}", date(*at))?;
+	}
+
+	Ok(line)
+}
@@ -13,11 +13,13 @@ pub(super) enum FederationCommand {
 	IncomingFederation,
 
 	/// - Disables incoming federation handling for a room.
+	#[mutating]
 	DisableRoom {
 		room_id: OwnedRoomId,
 	},
 
 	/// - Enables incoming federation handling for a room again.
+	#[mutating]
 	EnableRoom {
 		room_id: OwnedRoomId,
 	},
@@ -39,4 +41,21 @@ pub(super) enum FederationCommand {
 	RemoteUserInRooms {
 		user_id: OwnedUserId,
 	},
+
+	/// - Times taken handling the recent PDUs of each origin and the skew of
+	///   their clocks
+	///
+	/// Without a server name, lists every recently active origin with the
+	/// slowest first. With one, also lists its slowest recent PDUs.
+	OriginStats {
+		server_name: Option<OwnedServerName>,
+	},
+
+	/// - Federation destinations failing lately, and those gone dormant
+	///
+	/// Dormant destinations get no transactions until the server is heard
+	/// from again. With a server name, also counts its queued events.
+	Destinations {
+		server_name: Option<OwnedServerName>,
+	},
 }
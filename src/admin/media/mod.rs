@@ -12,6 +12,7 @@
 pub(super) enum MediaCommand {
 	/// - Deletes a single media file from our database and on the filesystem
 	///   via a single MXC URL or event ID (not redacted)
+	#[mutating]
 	Delete {
 		/// The MXC URL to delete
 		#[arg(long)]
@@ -25,12 +26,14 @@ pub(super) enum MediaCommand {
 
 	/// - Deletes a codeblock list of MXC URLs from our database and on the
 	///   filesystem. This will always ignore errors.
+	#[mutating]
 	DeleteList,
 
 	/// - Deletes all remote (and optionally local) media created before or
 	///   after [duration] time using filesystem metadata first created at date,
 	///   or fallback to last modified date. This will always ignore errors by
 	///   default.
+	#[mutating]
 	DeletePastRemoteMedia {
 		/// - The relative time (e.g. 30s, 5m, 7d) within which to search
 		duration: String,
@@ -50,12 +53,14 @@ pub(super) enum MediaCommand {
 
 	/// - Deletes all the local media from a local user on our server. This will
 	///   always ignore errors by default.
+	#[mutating]
 	DeleteAllFromUser {
 		username: String,
 	},
 
 	/// - Deletes all remote media from the specified remote server. This will
 	///   always ignore errors by default.
+	#[mutating]
 	DeleteAllFromServer {
 		server_name: OwnedServerName,
 
@@ -69,6 +74,7 @@ pub(super) enum MediaCommand {
 		mxc: OwnedMxcUri,
 	},
 
+	#[mutating]
 	GetRemoteFile {
 		/// The MXC URL to fetch
 		mxc: OwnedMxcUri,
@@ -80,6 +86,7 @@ pub(super) enum MediaCommand {
 		timeout: u32,
 	},
 
+	#[mutating]
 	GetRemoteThumbnail {
 		/// The MXC URL to fetch
 		mxc: OwnedMxcUri,
@@ -10,12 +10,14 @@
 
 pub(crate) mod appservice;
 pub(crate) mod check;
+pub(crate) mod db;
 pub(crate) mod debug;
 pub(crate) mod federation;
 pub(crate) mod media;
 pub(crate) mod query;
 pub(crate) mod room;
 pub(crate) mod server;
+pub(crate) mod sync;
 pub(crate) mod user;
 
 pub(crate) use tuwunel_macros::{admin_command, admin_command_dispatch};
@@ -24,6 +26,9 @@
 
 pub(crate) const PAGE_SIZE: usize = 100;
 
+/// Forward extremities are counted no further than this.
+pub(crate) const EXTREMITY_COUNT_MAX: usize = 1_000;
+
 tuwunel_core::mod_ctor! {}
 tuwunel_core::mod_dtor! {}
 tuwunel_core::rustc_flags_capture! {}
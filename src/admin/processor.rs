@@ -1,7 +1,9 @@
 use std::{
 	fmt::Write,
+	iter::Peekable,
 	mem::take,
 	panic::AssertUnwindSafe,
+	str::Chars,
 	sync::{Arc, Mutex},
 	time::SystemTime,
 };
@@ -18,7 +20,7 @@
 use tracing::Level;
 use tracing_subscriber::{EnvFilter, filter::LevelFilter};
 use tuwunel_core::{
-	Error, Result, debug, error,
+	Err, Error, Result, debug, error,
 	log::{
 		capture,
 		capture::Capture,
@@ -184,8 +186,8 @@ fn parse<'a>(
 		.clone()
 		.next()
 		.expect("command missing first line");
-	let body = lines.skip(1).collect();
-	match parse_command(command_line) {
+	let body: Vec<_> = lines.skip(1).collect();
+	match parse_command(command_line, &body) {
 		| Ok((command, args)) => Ok((command, args, body)),
 		| Err(error) => {
 			let message = error
@@ -196,14 +198,53 @@ fn parse<'a>(
 	}
 }
 
-fn parse_command(line: &str) -> Result<(AdminCommand, Vec<String>)> {
-	let argv = parse_line(line);
+/// Parses the command line; a fenced code block in the body is passed as the
+/// `--body` argument to commands which declare one and weren't given it.
+pub(super) fn parse_command(line: &str, body: &[&str]) -> Result<(AdminCommand, Vec<String>)> {
+	let mut argv = parse_line(line)?;
+	if let Some(body) = fenced_body(body)
+		&& !argv
+			.iter()
+			.any(|arg| arg == "--body" || arg.starts_with("--body="))
+		&& accepts_body(AdminCommand::command(), &argv)
+	{
+		argv.push(format!("--body={body}"));
+	}
+
 	let command = AdminCommand::try_parse_from(&argv)?;
 	Ok((command, argv))
 }
 
+/// The contents of a body consisting of a single ```-fenced code block.
+pub(super) fn fenced_body(body: &[&str]) -> Option<String> {
+	let (first, rest) = body.split_first()?;
+	let (last, inner) = rest.split_last()?;
+	if !first.trim().starts_with("```") || last.trim() != "```" {
+		return None;
+	}
+
+	Some(inner.join("\n"))
+}
+
+/// Whether the subcommand named by the arguments declares a `body` argument.
+fn accepts_body(mut cmd: clap::Command, argv: &[String]) -> bool {
+	for token in argv.iter().skip(1) {
+		let Some(sub) = cmd.find_subcommand(token).cloned() else {
+			break;
+		};
+
+		cmd = sub;
+	}
+
+	cmd.get_arguments()
+		.any(|arg| arg.get_id() == "body")
+}
+
 fn complete_command(mut cmd: clap::Command, line: &str) -> String {
-	let argv = parse_line(line);
+	let Ok(argv) = parse_line(line) else {
+		return line.to_owned();
+	};
+
 	let mut ret = Vec::<String>::with_capacity(argv.len().saturating_add(1));
 
 	'token: for token in argv.into_iter().skip(1) {
@@ -246,11 +287,8 @@ fn complete_command(mut cmd: clap::Command, line: &str) -> String {
 }
 
 /// Parse chat messages from the admin room into an AdminCommand object
-fn parse_line(command_line: &str) -> Vec<String> {
-	let mut argv = command_line
-		.split_whitespace()
-		.map(str::to_owned)
-		.collect::<Vec<String>>();
+fn parse_line(command_line: &str) -> Result<Vec<String>> {
+	let mut argv = split_args(command_line)?;
 
 	// Remove any escapes that came with a server-side escape command
 	if !argv.is_empty() && argv[0].ends_with("admin") {
@@ -286,7 +324,62 @@ fn parse_line(command_line: &str) -> Vec<String> {
 	}
 
 	trace!(?command_line, ?argv, "parse");
-	argv
+	Ok(argv)
+}
+
+/// Splits a command line into arguments the way a shell would: whitespace
+/// separates arguments unless quoted. Single quotes keep everything
+/// literally. Unquoted and within double quotes, a backslash escapes a quote,
+/// a backslash or whitespace and is kept otherwise, so regular expressions
+/// need no extra escaping.
+pub(super) fn split_args(line: &str) -> Result<Vec<String>> {
+	let mut args = Vec::new();
+	let mut arg: Option<String> = None;
+	let mut chars = line.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			| c if c.is_whitespace() => args.extend(arg.take()),
+			| '\'' => {
+				let arg = arg.get_or_insert_default();
+				loop {
+					match chars.next() {
+						| Some('\'') => break,
+						| Some(c) => arg.push(c),
+						| None => return Err!("Unterminated single quote in command line."),
+					}
+				}
+			},
+			| '"' => {
+				let arg = arg.get_or_insert_default();
+				loop {
+					match chars.next() {
+						| Some('"') => break,
+						| Some('\\') =>
+							push_escaped(arg, &mut chars, |c| matches!(c, '"' | '\\')),
+						| Some(c) => arg.push(c),
+						| None => return Err!("Unterminated double quote in command line."),
+					}
+				}
+			},
+			| '\\' => push_escaped(arg.get_or_insert_default(), &mut chars, |c| {
+				c.is_whitespace() || matches!(c, '"' | '\'' | '\\')
+			}),
+			| c => arg.get_or_insert_default().push(c),
+		}
+	}
+
+	args.extend(arg);
+	Ok(args)
+}
+
+fn push_escaped<F>(arg: &mut String, chars: &mut Peekable<Chars<'_>>, escapable: F)
+where
+	F: Fn(char) -> bool,
+{
+	match chars.next_if(|&c| escapable(c)) {
+		| Some(c) => arg.push(c),
+		| None => arg.push('\\'),
+	}
 }
 
 fn reply(
@@ -10,6 +10,7 @@
 mod room_timeline;
 mod sending;
 mod short;
+mod sync;
 mod users;
 
 use clap::Subcommand;
@@ -20,7 +21,7 @@
 	presence::PresenceCommand, pusher::PusherCommand, raw::RawCommand, resolver::ResolverCommand,
 	room_alias::RoomAliasCommand, room_state_cache::RoomStateCacheCommand,
 	room_timeline::RoomTimelineCommand, sending::SendingCommand, short::ShortCommand,
-	users::UsersCommand,
+	sync::SyncCommand, users::UsersCommand,
 };
 use crate::admin_command_dispatch;
 
@@ -60,6 +61,10 @@ pub(super) enum QueryCommand {
 	#[command(subcommand)]
 	Sending(SendingCommand),
 
+	/// - sync service
+	#[command(subcommand)]
+	Sync(SyncCommand),
+
 	/// - users.rs iterators and getters
 	#[command(subcommand)]
 	Users(UsersCommand),
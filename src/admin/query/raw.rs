@@ -38,6 +38,7 @@ pub(crate) enum RawCommand {
 	},
 
 	/// - Raw database delete (for string keys)
+	#[mutating]
 	RawDel {
 		/// Map name
 		map: String,
@@ -136,6 +137,7 @@ pub(crate) enum RawCommand {
 	},
 
 	/// - Compact database
+	#[mutating]
 	Compact {
 		#[arg(short, long, alias("column"))]
 		map: Option<Vec<String>>,
@@ -0,0 +1,40 @@
+use clap::Subcommand;
+use ruma::OwnedUserId;
+use tuwunel_core::Result;
+
+use crate::Context;
+
+#[derive(Debug, Subcommand)]
+/// All the getters and iterators from src/service/sync
+pub(crate) enum SyncCommand {
+	/// - Sliding sync connections with cached state and the number of known
+	///   rooms in each of their lists
+	Connections {
+		user_id: Option<OwnedUserId>,
+	},
+}
+
+/// All the getters and iterators from src/service/sync
+pub(super) async fn process(subcommand: SyncCommand, context: &Context<'_>) -> Result {
+	let services = context.services;
+
+	match subcommand {
+		| SyncCommand::Connections { user_id } => {
+			let timer = tokio::time::Instant::now();
+			let results: Vec<_> = services
+				.sync
+				.snake_connections_known_rooms()
+				.into_iter()
+				.filter(|((conn_user, ..), _)| {
+					user_id
+						.as_ref()
+						.is_none_or(|user_id| conn_user == user_id)
+				})
+				.collect();
+			let query_time = timer.elapsed();
+
+			write!(context, "Query completed in {query_time:?}:\n\n```rs\n{results:#?}\n```")
+		},
+	}
+	.await
+}
@@ -43,6 +43,10 @@ pub(crate) enum RoomAliasCommand {
 }
 
 pub(super) async fn process(command: RoomAliasCommand, context: &Context<'_>) -> Result {
+	if matches!(command, RoomAliasCommand::Set { .. } | RoomAliasCommand::Remove { .. }) {
+		context.check_writable()?;
+	}
+
 	let services = context.services;
 	let server_user = &services.globals.server_user;
 
@@ -2,7 +2,8 @@
 use ruma::OwnedRoomId;
 use tuwunel_core::{Err, Result};
 
-use crate::{PAGE_SIZE, admin_command, get_room_info};
+use super::list::{self, ListedRoom, RoomFilter, RoomKind, RoomSort, creation_info};
+use crate::{EXTREMITY_COUNT_MAX, PAGE_SIZE, admin_command, get_room_info};
 
 #[admin_command]
 pub(super) async fn list_rooms(
@@ -11,10 +12,14 @@ pub(super) async fn list_rooms(
 	exclude_disabled: bool,
 	exclude_banned: bool,
 	no_details: bool,
+	room_type: RoomKind,
+	public_only: bool,
+	local_only: bool,
+	sort: RoomSort,
 ) -> Result {
 	// TODO: i know there's a way to do this with clap, but i can't seem to find it
 	let page = page.unwrap_or(1);
-	let mut rooms = self
+	let room_ids = self
 		.services
 		.metadata
 		.iter_ids()
@@ -25,13 +30,11 @@ pub(super) async fn list_rooms(
 		.filter_map(async |room_id| {
 			(!exclude_banned || !self.services.metadata.is_banned(room_id).await)
 				.then_some(room_id)
-		})
-		.then(|room_id| get_room_info(self.services, room_id))
-		.collect::<Vec<_>>()
-		.await;
+		});
 
-	rooms.sort_by_key(|r| r.1);
-	rooms.reverse();
+	let filter = RoomFilter { kind: room_type, public_only, local_only };
+	let mut rooms = list::list_rooms(self.services, room_ids, filter).await;
+	list::sort_rooms(&mut rooms, sort);
 
 	let rooms = rooms
 		.into_iter()
@@ -45,11 +48,16 @@ pub(super) async fn list_rooms(
 
 	let body = rooms
 		.iter()
-		.map(|(id, members, name)| {
+		.map(|ListedRoom { room_id, members, name, creation, .. }| {
 			if no_details {
-				format!("{id}")
+				format!("{room_id}")
+			} else if sort == RoomSort::Created {
+				format!(
+					"{room_id}\tMembers: {members}\tName: {name}\t{}",
+					creation_info(creation.as_ref())
+				)
 			} else {
-				format!("{id}\tMembers: {members}\tName: {name}")
+				format!("{room_id}\tMembers: {members}\tName: {name}")
 			}
 		})
 		.collect::<Vec<_>>()
@@ -59,6 +67,78 @@ pub(super) async fn list_rooms(
 		.await
 }
 
+#[admin_command]
+pub(super) async fn stats(&self, room_id: OwnedRoomId) -> Result {
+	let (_, joined, name) = get_room_info(self.services, &room_id).await;
+	let invited = self
+		.services
+		.state_cache
+		.room_invited_count(&room_id)
+		.await
+		.unwrap_or(0);
+
+	let creation = self
+		.services
+		.metadata
+		.creation(&room_id)
+		.await
+		.ok();
+
+	let extremities = self
+		.services
+		.state
+		.forward_extremity_count(&room_id, EXTREMITY_COUNT_MAX)
+		.await;
+
+	let extremities = if extremities >= EXTREMITY_COUNT_MAX {
+		format!("{extremities}+")
+	} else {
+		extremities.to_string()
+	};
+
+	self.write_str(&format!(
+		"Room {room_id} ({name})\nJoined members: {joined}\nInvited members: {invited}\nForward \
+		 extremities: {extremities}\n{}",
+		creation_info(creation.as_ref())
+	))
+	.await
+}
+
+#[admin_command]
+pub(super) async fn directory_audit(
+	&self,
+	room_id: Option<OwnedRoomId>,
+	page: Option<usize>,
+) -> Result {
+	let page = page.unwrap_or(1);
+	let entries: Vec<_> = self
+		.services
+		.directory
+		.audit_log(room_id.as_deref())
+		.skip(page.saturating_sub(1).saturating_mul(PAGE_SIZE))
+		.take(PAGE_SIZE)
+		.collect()
+		.await;
+
+	if entries.is_empty() {
+		return Err!("No more directory changes.");
+	}
+
+	let body = entries
+		.iter()
+		.map(|entry| {
+			format!(
+				"{} | {} | {} | {} -> {}",
+				entry.ts, entry.room_id, entry.user_id, entry.old, entry.new
+			)
+		})
+		.collect::<Vec<_>>()
+		.join("\n");
+
+	self.write_str(&format!("Directory changes (page {page}):\n```\n{body}\n```"))
+		.await
+}
+
 #[admin_command]
 pub(super) async fn exists(&self, room_id: OwnedRoomId) -> Result {
 	let result = self.services.metadata.exists(&room_id).await;
@@ -1,9 +1,9 @@
 use clap::Subcommand;
-use futures::StreamExt;
 use ruma::OwnedRoomId;
 use tuwunel_core::Result;
 
-use crate::{Context, PAGE_SIZE, get_room_info};
+use super::list::{self, ListedRoom, RoomFilter, RoomKind, RoomSort};
+use crate::{Context, PAGE_SIZE};
 
 #[derive(Debug, Subcommand)]
 pub(crate) enum RoomDirectoryCommand {
@@ -22,33 +22,57 @@ pub(crate) enum RoomDirectoryCommand {
 	/// - List rooms that are published
 	List {
 		page: Option<usize>,
+
+		/// Only list spaces or only rooms which are not spaces
+		#[arg(long = "type", value_enum, default_value_t)]
+		room_type: RoomKind,
+
+		/// Only list rooms with local members
+		#[arg(long)]
+		local_only: bool,
+
+		/// Order of the rooms
+		#[arg(long, value_enum, default_value_t)]
+		sort: RoomSort,
 	},
 }
 
 pub(super) async fn process(command: RoomDirectoryCommand, context: &Context<'_>) -> Result {
+	if matches!(
+		command,
+		RoomDirectoryCommand::Publish { .. } | RoomDirectoryCommand::Unpublish { .. }
+	) {
+		context.check_writable()?;
+	}
+
 	let services = context.services;
 	match command {
 		| RoomDirectoryCommand::Publish { room_id } => {
-			services.directory.set_public(&room_id);
+			services
+				.directory
+				.set_public(&room_id, &services.globals.server_user)
+				.await;
 			context.write_str("Room published").await
 		},
 		| RoomDirectoryCommand::Unpublish { room_id } => {
-			services.directory.set_not_public(&room_id);
+			services
+				.directory
+				.set_not_public(&room_id, &services.globals.server_user)
+				.await;
 			context.write_str("Room unpublished").await
 		},
-		| RoomDirectoryCommand::List { page } => {
+		| RoomDirectoryCommand::List { page, room_type, local_only, sort } => {
 			// TODO: i know there's a way to do this with clap, but i can't seem to find it
 			let page = page.unwrap_or(1);
-			let mut rooms: Vec<_> = services
-				.directory
-				.public_rooms()
-				.then(|room_id| get_room_info(services, room_id))
-				.collect()
-				.await;
-
-			rooms.sort_by_key(|r| r.1);
-			rooms.reverse();
+			let filter = RoomFilter {
+				kind: room_type,
+				local_only,
+				..Default::default()
+			};
+			let mut rooms =
+				list::list_rooms(services, services.directory.public_rooms(), filter).await;
 
+			list::sort_rooms(&mut rooms, sort);
 			let rooms: Vec<_> = rooms
 				.into_iter()
 				.skip(page.saturating_sub(1).saturating_mul(PAGE_SIZE))
@@ -65,7 +89,9 @@ pub(super) async fn process(command: RoomDirectoryCommand, context: &Context<'_>
 
 			let body = rooms
 				.iter()
-				.map(|(id, members, name)| format!("{id} | Members: {members} | Name: {name}"))
+				.map(|ListedRoom { room_id, members, name, .. }| {
+					format!("{room_id} | Members: {members} | Name: {name}")
+				})
 				.collect::<Vec<_>>()
 				.join("\n");
 
@@ -0,0 +1,156 @@
+use clap::ValueEnum;
+use futures::{Stream, StreamExt};
+use ruma::{OwnedRoomId, RoomId, room::RoomType};
+use tuwunel_core::utils::{
+	IterStream, ReadyExt, stream::BroadbandExt, time::rfc2822_from_seconds,
+};
+use tuwunel_service::{Services, rooms::metadata::Creation};
+
+use crate::get_room_info;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub(crate) enum RoomKind {
+	/// Only spaces
+	Space,
+
+	/// Only rooms which are not spaces
+	Room,
+
+	/// Spaces and rooms
+	#[default]
+	All,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub(crate) enum RoomSort {
+	/// Most joined members first
+	#[default]
+	Members,
+
+	/// Alphabetically by name
+	Name,
+
+	/// Most recently created first, showing by whom and how
+	Created,
+}
+
+/// Which rooms a listing shows.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RoomFilter {
+	pub(crate) kind: RoomKind,
+	pub(crate) public_only: bool,
+	pub(crate) local_only: bool,
+}
+
+/// A room as shown in room listings.
+#[derive(Debug)]
+pub(crate) struct ListedRoom {
+	pub(crate) room_id: OwnedRoomId,
+	pub(crate) members: u64,
+	pub(crate) name: String,
+	pub(crate) room_type: Option<RoomType>,
+	pub(crate) public: bool,
+	pub(crate) local: bool,
+	pub(crate) creation: Option<Creation>,
+}
+
+impl RoomKind {
+	pub(crate) fn matches(self, room_type: Option<&RoomType>) -> bool {
+		let is_space = room_type.is_some_and(|room_type| *room_type == RoomType::Space);
+		match self {
+			| Self::Space => is_space,
+			| Self::Room => !is_space,
+			| Self::All => true,
+		}
+	}
+}
+
+impl RoomFilter {
+	pub(crate) fn matches(&self, room: &ListedRoom) -> bool {
+		self.kind.matches(room.room_type.as_ref())
+			&& (!self.public_only || room.public)
+			&& (!self.local_only || room.local)
+	}
+}
+
+/// Looks up the rooms for a listing, dropping those not passing the filter.
+/// The room types are fetched in one concurrent batch; the other details only
+/// for rooms of the requested kind.
+pub(crate) async fn list_rooms<'a, S>(
+	services: &Services,
+	room_ids: S,
+	filter: RoomFilter,
+) -> Vec<ListedRoom>
+where
+	S: Stream<Item = &'a RoomId> + Send + 'a,
+{
+	let room_ids: Vec<OwnedRoomId> = room_ids.map(ToOwned::to_owned).collect().await;
+
+	services
+		.state_accessor
+		.get_room_types(room_ids.into_iter().stream())
+		.ready_filter(|(_, room_type)| filter.kind.matches(room_type.as_ref()))
+		.broad_filter_map(async |(room_id, room_type)| {
+			let public = services.metadata.is_public(&room_id).await;
+			let local = services
+				.state_cache
+				.local_users_in_room(&room_id)
+				.ready_any(|_| true)
+				.await;
+
+			let creation = services.metadata.creation(&room_id).await.ok();
+			let (room_id, members, name) = get_room_info(services, &room_id).await;
+			let room = ListedRoom {
+				room_id,
+				members,
+				name,
+				room_type,
+				public,
+				local,
+				creation,
+			};
+
+			filter.matches(&room).then_some(room)
+		})
+		.collect()
+		.await
+}
+
+/// Orders the rooms of a listing; ties are broken by room ID so that pages
+/// stay stable.
+pub(crate) fn sort_rooms(rooms: &mut [ListedRoom], sort: RoomSort) {
+	rooms.sort_by(|a, b| {
+		match sort {
+			| RoomSort::Members => b.members.cmp(&a.members),
+			| RoomSort::Name => a.name.cmp(&b.name),
+			| RoomSort::Created => b
+				.creation
+				.as_ref()
+				.map(|creation| creation.created_at)
+				.cmp(
+					&a.creation
+						.as_ref()
+						.map(|creation| creation.created_at),
+				),
+		}
+		.then_with(|| a.room_id.cmp(&b.room_id))
+	});
+}
+
+pub(crate) fn creation_info(creation: Option<&Creation>) -> String {
+	let Some(creation) = creation else {
+		return "Creation: unknown".to_owned();
+	};
+
+	let created_at = i64::try_from(u64::from(creation.created_at.as_secs()))
+		.map(rfc2822_from_seconds)
+		.unwrap_or_default();
+
+	let origin = match (creation.local, &creation.created_via) {
+		| (true, _) => "created locally".to_owned(),
+		| (false, Some(via)) => format!("joined via {via}"),
+		| (false, None) => "joined remotely".to_owned(),
+	};
+
+	format!("Created by {} at {created_at}, {origin}", creation.creator)
+}
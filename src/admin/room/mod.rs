@@ -2,15 +2,21 @@
 mod commands;
 mod directory;
 mod info;
+pub(crate) mod list;
 mod moderation;
+mod retention;
 
 use clap::Subcommand;
 use ruma::OwnedRoomId;
 use tuwunel_core::Result;
 
 use self::{
-	alias::RoomAliasCommand, directory::RoomDirectoryCommand, info::RoomInfoCommand,
+	alias::RoomAliasCommand,
+	directory::RoomDirectoryCommand,
+	info::RoomInfoCommand,
+	list::{RoomKind, RoomSort},
 	moderation::RoomModerationCommand,
+	retention::RoomRetentionCommand,
 };
 use crate::admin_command_dispatch;
 
@@ -34,6 +40,23 @@ pub(super) enum RoomCommand {
 		/// Whether to only output room IDs without supplementary room
 		/// information
 		no_details: bool,
+
+		/// Only list spaces or only rooms which are not spaces
+		#[arg(long = "type", value_enum, default_value_t)]
+		room_type: RoomKind,
+
+		/// Only list rooms published to the directory or with a public join
+		/// rule
+		#[arg(long)]
+		public_only: bool,
+
+		/// Only list rooms with local members
+		#[arg(long)]
+		local_only: bool,
+
+		/// Order of the rooms
+		#[arg(long, value_enum, default_value_t)]
+		sort: RoomSort,
 	},
 
 	#[command(subcommand)]
@@ -52,12 +75,32 @@ pub(super) enum RoomCommand {
 	/// - Manage the room directory
 	Directory(RoomDirectoryCommand),
 
+	#[command(subcommand)]
+	/// - Inspect and trigger purging of expired events
+	Retention(RoomRetentionCommand),
+
+	/// - Show changes of rooms' visibility in the room directory, most recent
+	///   first
+	DirectoryAudit {
+		/// Only show changes of this room
+		room_id: Option<OwnedRoomId>,
+
+		#[arg(long)]
+		page: Option<usize>,
+	},
+
+	/// - Show members and creation of a room
+	Stats {
+		room_id: OwnedRoomId,
+	},
+
 	/// - Check if we know about a room
 	Exists {
 		room_id: OwnedRoomId,
 	},
 
 	/// - Delete room
+	#[mutating]
 	DeleteRoom {
 		room_id: OwnedRoomId,
 
@@ -1,11 +1,30 @@
+use std::time::Duration;
+
 use clap::Subcommand;
 use futures::{FutureExt, StreamExt};
-use ruma::{OwnedRoomId, OwnedRoomOrAliasId, RoomAliasId, RoomId, RoomOrAliasId};
+use ruma::{
+	EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedRoomOrAliasId,
+	OwnedUserId, RoomAliasId, RoomId, RoomOrAliasId,
+	api::Direction,
+	events::{
+		StateEventType, TimelineEventType,
+		room::power_levels::{RoomPowerLevelsEventContent, UserPowerLevel},
+	},
+	int,
+};
+use tokio::time::sleep;
 use tuwunel_core::{
-	Err, Result, debug,
-	utils::{IterStream, ReadyExt},
+	Err, Result, debug, err,
+	matrix::{Event, pdu::PduBuilder},
+	messages::Message,
+	utils::{
+		IterStream, ReadyExt, millis_since_unix_epoch,
+		stream::TryIgnore,
+		time::{self, parse_duration},
+	},
 	warn,
 };
+use tuwunel_service::rooms::timeline::PurgeSummary;
 
 use crate::{admin_command, admin_command_dispatch, get_room_info};
 
@@ -17,6 +36,7 @@ pub(crate) enum RoomModerationCommand {
 	/// admins)
 	///   from the room. Also blocks any invites (local and remote) for the
 	///   banned room, and disables federation entirely with it.
+	#[mutating]
 	BanRoom {
 		/// The room in the format of `!roomid:example.com` or a room alias in
 		/// the format of `#roomalias:example.com`
@@ -26,9 +46,11 @@ pub(crate) enum RoomModerationCommand {
 	/// - Bans a list of rooms (room IDs and room aliases) from a newline
 	///   delimited codeblock similar to `user deactivate-all`. Applies the same
 	///   steps as ban-room
+	#[mutating]
 	BanListOfRooms,
 
 	/// - Unbans a room to allow local users to join again
+	#[mutating]
 	UnbanRoom {
 		/// The room in the format of `!roomid:example.com` or a room alias in
 		/// the format of `#roomalias:example.com`
@@ -42,8 +64,101 @@ pub(crate) enum RoomModerationCommand {
 		/// information
 		no_details: bool,
 	},
+
+	/// - Redacts the events a user sent in a room, most recent first
+	///
+	/// State events are skipped unless `--include-state` is given. Redactions
+	/// are sent by the server user unless another admin is specified, who must
+	/// be joined to the room with enough power to redact.
+	#[mutating]
+	RedactUser {
+		room_id: OwnedRoomId,
+
+		user_id: OwnedUserId,
+
+		/// Only redact events sent within this duration, e.g. `2h` or `7d`
+		#[arg(long)]
+		since: Option<String>,
+
+		/// Redact at most this many events
+		#[arg(long)]
+		limit: Option<usize>,
+
+		/// Also redact the user's state events where allowed
+		#[arg(long)]
+		include_state: bool,
+
+		/// Also redact the user's soft-failed events, which other servers may
+		/// have accepted
+		#[arg(long)]
+		include_soft_failed: bool,
+
+		/// Local admin sending the redactions instead of the server user
+		#[arg(long)]
+		redact_as: Option<OwnedUserId>,
+
+		/// Reason for the redactions; quote it when it contains spaces.
+		#[arg(short, long)]
+		reason: Option<String>,
+	},
+
+	/// - Lists the events soft-failed in a room, most recent first
+	///
+	/// Soft-failed events were accepted as valid but kept out of the
+	/// timeline, e.g. for failing checks against the current state. Target
+	/// them with `redact-user --include-soft-failed`.
+	ListSoftFailed {
+		room: OwnedRoomOrAliasId,
+
+		/// List at most this many events
+		#[arg(long, default_value_t = 50)]
+		limit: usize,
+	},
+
+	/// - Makes a user admin of a room only this server is in, overriding its
+	///   power levels
+	///
+	/// For rooms all moderators left. The power levels are sent by the room
+	/// creator, or else the server user, or else the most powerful member,
+	/// without checking they may send them. Refused when any other server is
+	/// in the room.
+	#[mutating]
+	MakeAdmin {
+		room: OwnedRoomOrAliasId,
+
+		user_id: OwnedUserId,
+	},
+
+	/// - Purges the history of a room before an event or a timestamp
+	///
+	/// Events before the cutoff are deleted from the database with their
+	/// search tokens, relations and threads; clients paginating past it get
+	/// no more events. The create event, the forward extremities, the auth
+	/// chain of the events kept and, unless `--purge-state` is given, the
+	/// events of the current state are kept.
+	#[mutating]
+	PurgeHistory {
+		room_id: OwnedRoomId,
+
+		/// Event ID, or timestamp in milliseconds since the Unix epoch, the
+		/// history before which is purged
+		before: String,
+
+		/// Also purge the events of the current state before the cutoff. The
+		/// room is left without its state, so this is refused while local
+		/// users are in it
+		#[arg(long)]
+		purge_state: bool,
+
+		/// Only count the events which would be purged
+		#[arg(long)]
+		dry_run: bool,
+	},
 }
 
+/// Pause between redactions so a large purge does not flood federation.
+const REDACT_INTERVAL: Duration = Duration::from_millis(100);
+
 #[admin_command]
 async fn ban_room(&self, room: OwnedRoomOrAliasId) -> Result {
 	debug!("Got room alias or ID: {}", room);
@@ -184,7 +299,10 @@ async fn ban_room(&self, room: OwnedRoomOrAliasId) -> Result {
 		.await;
 
 	// unpublish from room directory
-	self.services.directory.set_not_public(&room_id);
+	self.services
+		.directory
+		.set_not_public(&room_id, &self.services.globals.server_user)
+		.await;
 
 	self.services.metadata.disable_room(&room_id);
 
@@ -360,7 +478,10 @@ async fn ban_list_of_rooms(&self) -> Result {
 			.await;
 
 		// unpublish from room directory, ignore errors
-		self.services.directory.set_not_public(&room_id);
+		self.services
+			.directory
+			.set_not_public(&room_id, &self.services.globals.server_user)
+			.await;
 
 		self.services.metadata.disable_room(&room_id);
 	}
@@ -498,3 +619,359 @@ async fn list_banned_rooms(&self, no_details: bool) -> Result {
 	self.write_str(&format!("Rooms Banned ({num}):\n```\n{body}\n```",))
 		.await
 }
+
+#[admin_command]
+async fn redact_user(
+	&self,
+	room_id: OwnedRoomId,
+	user_id: OwnedUserId,
+	since: Option<String>,
+	limit: Option<usize>,
+	include_state: bool,
+	include_soft_failed: bool,
+	redact_as: Option<OwnedUserId>,
+	reason: Option<String>,
+) -> Result {
+	let sender = redact_as.unwrap_or_else(|| self.services.globals.server_user.clone());
+	if !self.services.globals.user_is_local(&sender) {
+		return Err!("Redactions can only be sent by a local user.");
+	}
+
+	if sender != self.services.globals.server_user
+		&& !self.services.admin.user_is_admin(&sender).await
+	{
+		return Err!("{sender} is not a server admin.");
+	}
+
+	if !self
+		.services
+		.state_cache
+		.is_joined(&sender, &room_id)
+		.await
+	{
+		return Err!("{sender} is not joined to {room_id}.");
+	}
+
+	let cutoff = since
+		.as_deref()
+		.map(parse_duration)
+		.transpose()?
+		.map(|since| {
+			let since = u64::try_from(since.as_millis()).unwrap_or(u64::MAX);
+			millis_since_unix_epoch().saturating_sub(since)
+		});
+
+	let limit = limit.unwrap_or(usize::MAX);
+	let mut event_ids: Vec<OwnedEventId> = self
+		.services
+		.timeline
+		.pdus_rev(None, &room_id, None)
+		.ignore_err()
+		.ready_take_while(|(_, pdu)| {
+			cutoff.is_none_or(|cutoff| u64::from(pdu.origin_server_ts().get()) >= cutoff)
+		})
+		.ready_filter(|(_, pdu)| {
+			*pdu.sender() == *user_id
+				&& !pdu.is_redacted()
+				&& *pdu.kind() != TimelineEventType::RoomRedaction
+				&& (include_state || pdu.state_key().is_none())
+		})
+		.map(|(_, pdu)| pdu.event_id().to_owned())
+		.take(limit)
+		.collect()
+		.await;
+
+	if include_soft_failed {
+		let remaining = limit.saturating_sub(event_ids.len());
+		let soft_failed: Vec<_> = self
+			.services
+			.pdu_metadata
+			.soft_failed_events(&room_id)
+			.ready_take_while(|event| cutoff.is_none_or(|cutoff| event.soft_failed_at >= cutoff))
+			.ready_filter(|event| event.sender == user_id)
+			.map(|event| event.event_id)
+			.take(remaining)
+			.collect()
+			.await;
+
+		event_ids.extend(soft_failed);
+	}
+
+	let reason = reason.unwrap_or_else(|| {
+		let server_name = self.services.globals.server_name().as_str();
+		self.services
+			.globals
+			.message(Message::UserRedacted, &[("server_name", server_name)])
+	});
+
+	let (mut redacted, mut denied, mut failed) = (0_usize, 0_usize, 0_usize);
+	for event_id in &event_ids {
+		if !self.services.server.running() {
+			break;
+		}
+
+		if !self
+			.services
+			.state_accessor
+			.user_can_redact(event_id, &sender, &room_id, false)
+			.await
+			.unwrap_or(false)
+		{
+			denied = denied.saturating_add(1);
+			continue;
+		}
+
+		let state_lock = self.services.state.mutex.lock(&room_id).await;
+		let result = self
+			.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::redaction(event_id, Some(reason.clone())),
+				&sender,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await;
+
+		drop(state_lock);
+		match result {
+			| Ok(_) => redacted = redacted.saturating_add(1),
+			| Err(e) => {
+				warn!(%event_id, "Failed to redact event: {e}");
+				failed = failed.saturating_add(1);
+			},
+		}
+
+		sleep(REDACT_INTERVAL).await;
+	}
+
+	let skipped = event_ids
+		.len()
+		.saturating_sub(redacted)
+		.saturating_sub(denied)
+		.saturating_sub(failed);
+
+	self.write_str(&format!(
+		"Redacted {redacted} of {} events from {user_id} in {room_id}: {denied} not permitted, \
+		 {failed} failed, {skipped} skipped by shutdown.",
+		event_ids.len(),
+	))
+	.await
+}
+
+#[admin_command]
+async fn list_soft_failed(&self, room: OwnedRoomOrAliasId, limit: usize) -> Result {
+	let room_id = self.services.alias.resolve(&room).await?;
+	let now = millis_since_unix_epoch();
+	let events: Vec<_> = self
+		.services
+		.pdu_metadata
+		.soft_failed_events(&room_id)
+		.take(limit)
+		.map(|event| {
+			let ago = Duration::from_millis(now.saturating_sub(event.soft_failed_at));
+			format!(
+				"- {} from {} {} ago: {}",
+				event.event_id,
+				event.sender,
+				time::pretty(ago),
+				event.reason
+			)
+		})
+		.collect()
+		.await;
+
+	if events.is_empty() {
+		return self
+			.write_str(&format!("No events were soft-failed in {room_id}."))
+			.await;
+	}
+
+	self.write_str(&format!(
+		"Soft-failed events in {room_id}, most recent first ({}):\n```\n{}\n```",
+		events.len(),
+		events.join("\n"),
+	))
+	.await
+}
+
+#[admin_command]
+async fn make_admin(&self, room: OwnedRoomOrAliasId, user_id: OwnedUserId) -> Result {
+	let room_id = self.services.alias.resolve(&room).await?;
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+
+	let servers: Vec<_> = self
+		.services
+		.state_cache
+		.room_servers(&room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	if let Some(server) = servers
+		.iter()
+		.find(|server| !self.services.globals.server_is_ours(server))
+	{
+		return Err!(
+			"{server} is in {room_id}; only rooms no other server is in can be overridden."
+		);
+	}
+
+	let power_levels = self
+		.services
+		.state_accessor
+		.get_power_levels(&room_id)
+		.await?;
+
+	if power_levels.for_user(&user_id) == UserPowerLevel::Infinite {
+		return Err!("{user_id} is a creator of {room_id} already.");
+	}
+
+	let members: Vec<OwnedUserId> = self
+		.services
+		.state_cache
+		.room_members(&room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let creator = self
+		.services
+		.state_accessor
+		.room_state_get(&room_id, &StateEventType::RoomCreate, "")
+		.await
+		.map(|create| create.sender().to_owned())
+		.ok();
+
+	let server_user = &self.services.globals.server_user;
+	let sender = creator
+		.filter(|creator| members.contains(creator))
+		.or_else(|| {
+			members
+				.contains(server_user)
+				.then(|| server_user.clone())
+		})
+		.or_else(|| {
+			members.iter().cloned().reduce(|a, b| {
+				if power_levels.for_user(&b) > power_levels.for_user(&a) {
+					b
+				} else {
+					a
+				}
+			})
+		});
+
+	let Some(sender) = sender else {
+		return Err!("No member of {room_id} can send the power levels.");
+	};
+
+	let mut content: RoomPowerLevelsEventContent = power_levels.try_into()?;
+	content.users.insert(user_id.clone(), int!(100));
+
+	let event_id = self
+		.services
+		.timeline
+		.append_privileged_pdu(
+			PduBuilder::state(String::new(), &content),
+			&sender,
+			&room_id,
+			&state_lock,
+		)
+		.await?;
+
+	drop(state_lock);
+
+	warn!(%room_id, %user_id, %sender, %event_id, "Overrode power levels to make user admin");
+	self.services
+		.admin
+		.notice(&format!(
+			"Power levels of {room_id} were overridden to make {user_id} admin; {sender} sent \
+			 them without authorization checks in {event_id}."
+		))
+		.await;
+
+	self.write_str(&format!("Made {user_id} admin of {room_id} as {sender} in {event_id}."))
+		.await
+}
+
+#[admin_command]
+async fn purge_history(
+	&self,
+	room_id: OwnedRoomId,
+	before: String,
+	purge_state: bool,
+	dry_run: bool,
+) -> Result {
+	if self.services.admin.is_admin_room(&room_id).await {
+		return Err!("Not allowed to purge the history of the admin room.");
+	}
+
+	let cutoff = if before.starts_with('$') {
+		let event_id = EventId::parse(&before)?;
+		let in_room = self
+			.services
+			.timeline
+			.get_pdu(&event_id)
+			.await
+			.is_ok_and(|pdu| *pdu.room_id() == *room_id);
+
+		if !in_room {
+			return Err!("{event_id} is not in the timeline of {room_id}.");
+		}
+
+		self.services
+			.timeline
+			.get_pdu_count(&event_id)
+			.await?
+	} else {
+		let ts: u64 = before
+			.parse()
+			.map_err(|_| err!("{before} is neither an event ID nor a timestamp."))?;
+
+		let ts = MilliSecondsSinceUnixEpoch(ts.try_into()?);
+		match self
+			.services
+			.timeline
+			.local_event_by_timestamp(&room_id, ts, Direction::Forward)
+			.await
+		{
+			| Some((event_id, _)) =>
+				self.services
+					.timeline
+					.get_pdu_count(&event_id)
+					.await?,
+			| None => self
+				.services
+				.timeline
+				.last_timeline_count(None, &room_id, None)
+				.await?
+				.saturating_add(1),
+		}
+	};
+
+	let summary = if dry_run {
+		self.services
+			.timeline
+			.purge_history_dry_run(&room_id, cutoff, purge_state)
+			.await?
+	} else {
+		self.services
+			.timeline
+			.purge_history(&room_id, cutoff, purge_state)
+			.await?
+	};
+
+	let PurgeSummary {
+		purged,
+		kept_state,
+		kept_required,
+		outliers,
+	} = summary;
+	let action = if dry_run { "Would purge" } else { "Purged" };
+	self.write_str(&format!(
+		"{action} {purged} events and {outliers} outliers of {room_id} before {cutoff}, keeping \
+		 {kept_state} events of the current state and {kept_required} required events."
+	))
+	.await
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use clap::Subcommand;
+use ruma::OwnedRoomId;
+use tuwunel_core::{Result, utils::time};
+
+use crate::{admin_command, admin_command_dispatch};
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(crate) enum RoomRetentionCommand {
+	/// - Show the retention configuration and the last purge, or the retention
+	///   policy of a room
+	Status {
+		room_id: Option<OwnedRoomId>,
+	},
+
+	/// - Purge expired events from a room now
+	#[mutating]
+	Run {
+		room_id: OwnedRoomId,
+	},
+}
+
+#[admin_command]
+async fn status(&self, room_id: Option<OwnedRoomId>) -> Result {
+	if let Some(room_id) = room_id {
+		let Some(policy) = self
+			.services
+			.retention
+			.room_policy(&room_id)
+			.await
+		else {
+			return self
+				.write_str("Room has no retention policy; its events are kept forever.")
+				.await;
+		};
+
+		return self
+			.write_str(&format!(
+				"Events older than {} are purged; events younger than {} are always kept.",
+				time::pretty(policy.max_lifetime),
+				time::pretty(policy.min_lifetime),
+			))
+			.await;
+	}
+
+	let config = &self.services.config.retention;
+	let last_run = self.services.retention.last_run().map_or_else(
+		|| "never".to_owned(),
+		|summary| {
+			let ago = summary.finished.elapsed().unwrap_or_default();
+			format!(
+				"{} ago; purged {} events from {} rooms",
+				time::pretty(ago),
+				summary.purged,
+				summary.rooms
+			)
+		},
+	);
+
+	let default_max_lifetime = config.default_max_lifetime.map_or_else(
+		|| "none; events are kept forever".to_owned(),
+		|secs| time::pretty(Duration::from_secs(secs)),
+	);
+
+	self.write_str(&format!(
+		"Enabled: {}\nDefault max lifetime: {default_max_lifetime}\nMin lifetime: {}\nPurge \
+		 interval: {}\nLast purge: {last_run}",
+		config.enabled,
+		time::pretty(Duration::from_secs(config.min_lifetime)),
+		time::pretty(Duration::from_secs(config.purge_interval)),
+	))
+	.await
+}
+
+#[admin_command]
+async fn run(&self, room_id: OwnedRoomId) -> Result {
+	let purged = self
+		.services
+		.retention
+		.purge_room(&room_id)
+		.await?;
+
+	self.write_str(&format!("Purged {purged} expired events from {room_id}."))
+		.await
+}
@@ -1,13 +1,18 @@
-use std::{fmt::Write, path::PathBuf, sync::Arc};
+use std::{fmt::Write, path::PathBuf, sync::Arc, time::Duration};
 
-use futures::TryStreamExt;
+use futures::{StreamExt, TryStreamExt};
+use ruma::OwnedUserId;
+use tokio::time::sleep;
 use tuwunel_core::{
 	Err, Result, info,
-	utils::{stream::IterStream, time},
+	utils::{ReadyExt, stream::IterStream, time},
 	warn,
 };
 
-use crate::admin_command;
+use crate::{admin_command, utils::parse_active_local_user_id};
+
+/// Delay between notices when sending to all users.
+const NOTICE_ALL_INTERVAL: Duration = Duration::from_millis(100);
 
 #[admin_command]
 pub(super) async fn uptime(&self) -> Result {
@@ -31,9 +36,26 @@ pub(super) async fn show_config(&self) -> Result {
 #[admin_command]
 pub(super) async fn reload_config(&self, path: Option<PathBuf>) -> Result {
 	let path = path.as_deref().into_iter();
-	self.services.config.reload(path)?;
+	let reloaded = self.services.config.reload(path)?;
+
+	let list = |names: &[&str]| {
+		names
+			.iter()
+			.map(|name| format!("`{name}`"))
+			.collect::<Vec<_>>()
+			.join(", ")
+	};
+
+	let mut out = String::from("Successfully reconfigured.");
+	if !reloaded.applied.is_empty() {
+		write!(out, "\n\nApplied: {}", list(&reloaded.applied))?;
+	}
+
+	if !reloaded.restart.is_empty() {
+		write!(out, "\n\nChanged, taking effect after a restart: {}", list(&reloaded.restart))?;
+	}
 
-	self.write_str("Successfully reconfigured.").await
+	self.write_str(&out).await
 }
 
 #[admin_command]
@@ -78,8 +100,11 @@ pub(super) async fn memory_usage(&self) -> Result {
 }
 
 #[admin_command]
-pub(super) async fn clear_caches(&self) -> Result {
-	self.services.clear_cache().await;
+pub(super) async fn clear_caches(&self, service: Option<String>) -> Result {
+	match service {
+		| Some(name) => self.services.clear_service_cache(&name).await?,
+		| None => self.services.clear_cache().await,
+	}
 
 	self.write_str("Done.").await
 }
@@ -121,6 +146,143 @@ pub(super) async fn admin_notice(&self, message: Vec<String>) -> Result {
 	self.write_str("Notice was sent to #admins").await
 }
 
+#[admin_command]
+pub(super) async fn notice(&self, all: bool, args: Vec<String>, body: Option<String>) -> Result {
+	let (recipient, message) = notice_parts(all, args, body)?;
+	if let Some(user_id) = recipient {
+		let user_id = parse_active_local_user_id(self.services, &user_id).await?;
+		self.services
+			.server_notices
+			.send_notice(&user_id, &message)
+			.await?;
+
+		return self
+			.write_str(&format!("Sent server notice to {user_id}."))
+			.await;
+	}
+
+	let notices_user = self.services.server_notices.notices_user()?;
+	let users: Vec<OwnedUserId> = self
+		.services
+		.users
+		.list_local_users()
+		.ready_filter(|user_id| {
+			notices_user != *user_id && self.services.globals.server_user != *user_id
+		})
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	// The notices are paced, which takes minutes on large servers; the outcome is
+	// posted to the admin room instead of holding the command.
+	let count = users.len();
+	let admin = Arc::clone(&self.services.admin);
+	let server_notices = Arc::clone(&self.services.server_notices);
+	self.services.server.runtime().spawn(async move {
+		let (mut sent, mut failed) = (0_usize, 0_usize);
+		for user_id in users {
+			match server_notices
+				.send_notice(&user_id, &message)
+				.await
+			{
+				| Ok(_) => sent = sent.saturating_add(1),
+				| Err(e) => {
+					warn!(%user_id, "Failed to send server notice: {e}");
+					failed = failed.saturating_add(1);
+				},
+			}
+
+			// Pace the notices so a large server isn't flooded with new rooms at once.
+			sleep(NOTICE_ALL_INTERVAL).await;
+		}
+
+		admin
+			.notice(&format!("Sent server notice to {sent} users; {failed} failed."))
+			.await;
+	});
+
+	self.write_str(&format!(
+		"Sending server notice to {count} users; the outcome will be posted here."
+	))
+	.await
+}
+
+/// The recipient and the message of a notice. Only `--all` broadcasts; the
+/// message is either in the arguments or in the body, never both.
+pub(crate) fn notice_parts(
+	all: bool,
+	args: Vec<String>,
+	body: Option<String>,
+) -> Result<(Option<String>, String)> {
+	let mut args = args.into_iter();
+	let recipient = match all {
+		| true => None,
+		| false => match args.next() {
+			| Some(recipient) => Some(recipient),
+			| None =>
+				return Err!("Missing recipient; use --all to send the notice to every user."),
+		},
+	};
+
+	let rest = args.collect::<Vec<_>>().join(" ");
+	let message = match body {
+		| Some(_) if !rest.is_empty() =>
+			return Err!(
+				"Give the message either after the recipient or in a code block, not both."
+			),
+		| Some(body) => body,
+		| None => rest,
+	};
+
+	if message.is_empty() {
+		return Err!("Missing notice message.");
+	}
+
+	Ok((recipient, message))
+}
+
+#[admin_command]
+pub(super) async fn check_canonical_aliases(&self, fix: bool) -> Result {
+	let found = self
+		.services
+		.alias
+		.check_canonical_aliases()
+		.await;
+
+	if found.is_empty() {
+		return self
+			.write_str("No canonical alias names a dangling alias.")
+			.await;
+	}
+
+	let mut out = format!("Found {} rooms with dangling canonical aliases:\n\n", found.len());
+	for dangling in &found {
+		let aliases = dangling
+			.dangling
+			.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<_>>()
+			.join(", ");
+
+		let status = if !fix {
+			String::new()
+		} else if let Err(e) = self
+			.services
+			.alias
+			.repair_canonical_alias(dangling)
+			.await
+		{
+			format!(" (not repaired: {e})")
+		} else {
+			" (repaired)".to_owned()
+		};
+
+		writeln!(out, "- {}: {aliases}{status}", dangling.room_id)?;
+	}
+
+	self.write_str(&out).await
+}
+
 #[admin_command]
 pub(super) async fn reload_mods(&self) -> Result {
 	self.services.server.reload()?;
@@ -5,6 +5,7 @@
 use clap::Subcommand;
 use tuwunel_core::Result;
 
+pub(super) use self::commands::notice_parts;
 use crate::admin_command_dispatch;
 
 #[admin_command_dispatch]
@@ -17,6 +18,9 @@ pub(super) enum ServerCommand {
 	ShowConfig,
 
 	/// - Reload configuration values
+	///
+	/// Options which can change while running take effect at once; the others
+	/// are listed as needing a restart.
 	ReloadConfig {
 		path: Option<PathBuf>,
 	},
@@ -36,8 +40,13 @@ pub(super) enum ServerCommand {
 	/// - Print database memory usage statistics
 	MemoryUsage,
 
-	/// - Clears all of Tuwunel's caches
-	ClearCaches,
+	/// - Clears all of Tuwunel's caches, or those of one service
+	///
+	/// Services are named by their module, e.g.
+	/// `rooms::state_cache` or `resolver`.
+	ClearCaches {
+		service: Option<String>,
+	},
 
 	/// - Performs an online backup of the database (only available for RocksDB
 	///   at the moment)
@@ -47,10 +56,41 @@ pub(super) enum ServerCommand {
 	ListBackups,
 
 	/// - Send a message to the admin room.
+	#[mutating]
 	AdminNotice {
 		message: Vec<String>,
 	},
 
+	/// - Send a server notice to a user, or to all local users with --all.
+	///
+	/// Each user receives notices in a dedicated room shared only with the
+	/// server notices user; it is created on first use.
+	#[mutating]
+	Notice {
+		/// Send the notice to every active local user.
+		#[arg(short, long)]
+		all: bool,
+
+		/// The recipient (unless --all) followed by the message.
+		args: Vec<String>,
+
+		/// The message, instead of giving it in the arguments. A code block
+		/// below the command is passed here, keeping its line breaks.
+		#[arg(long)]
+		body: Option<String>,
+	},
+
+	/// - Find rooms whose canonical alias event names aliases of this server no
+	///   longer resolving to the room.
+	///
+	/// With --fix the dangling aliases are removed from the event, moving up
+	/// a remaining alt alias; rooms where the server user lacks permission
+	/// are only reported.
+	CheckCanonicalAliases {
+		#[arg(long)]
+		fix: bool,
+	},
+
 	/// - Hot-reload the server
 	#[clap(alias = "reload")]
 	ReloadMods,
@@ -0,0 +1,172 @@
+use std::fmt::Write;
+
+use clap::Subcommand;
+use ruma::{DeviceId, OwnedDeviceId, OwnedUserId, UserId};
+use tuwunel_core::{Err, Result};
+use tuwunel_service::{Services, sync::SnakeConnectionsKey};
+
+use crate::{admin_command, admin_command_dispatch};
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(crate) enum SyncConnectionsCommand {
+	/// - List the sliding sync connections whose state is cached, with the size
+	///   of their state
+	List {
+		user_id: Option<OwnedUserId>,
+	},
+
+	/// - Show the cached state of the connections of a device
+	///
+	/// Without a connection ID, every connection of the device is shown. The
+	/// state requested of rooms is only counted unless `--full` is passed.
+	Show {
+		user_id: OwnedUserId,
+		device_id: OwnedDeviceId,
+		conn_id: Option<String>,
+
+		/// Also list the state requested of rooms.
+		#[arg(long)]
+		full: bool,
+	},
+
+	/// - Forget the cached state of the connections of a device, sending the
+	///   client back to an initial sync
+	///
+	/// Without a connection ID, every connection of the device is forgotten.
+	Forget {
+		user_id: OwnedUserId,
+		device_id: OwnedDeviceId,
+		conn_id: Option<String>,
+	},
+}
+
+#[admin_command]
+async fn list(&self, user_id: Option<OwnedUserId>) -> Result {
+	let connections: Vec<_> = self
+		.services
+		.sync
+		.iter_snake_connections()
+		.into_iter()
+		.filter(|((conn_user, ..), _)| {
+			user_id
+				.as_ref()
+				.is_none_or(|user_id| conn_user == user_id)
+		})
+		.collect();
+
+	if connections.is_empty() {
+		return self
+			.write_str("No sliding sync connections are cached.")
+			.await;
+	}
+
+	let mut out = format!("{} sliding sync connections:\n", connections.len());
+	for ((user_id, device_id, conn_id), summary) in &connections {
+		let conn_id = conn_id.as_deref().unwrap_or("-");
+		let extensions = &summary.extensions;
+		let enabled: Vec<_> = [
+			("e2ee", extensions.e2ee),
+			("to_device", extensions.to_device),
+			("account_data", extensions.account_data),
+			("receipts", extensions.receipts),
+			("typing", extensions.typing),
+		]
+		.into_iter()
+		.filter_map(|(name, enabled)| enabled.then_some(name))
+		.collect();
+
+		writeln!(
+			out,
+			"- {user_id} {device_id} {conn_id}: {} lists, {} subscriptions, {} known rooms; \
+			 extensions: {}",
+			summary.lists,
+			summary.subscriptions,
+			summary.known_rooms,
+			if enabled.is_empty() {
+				"none".to_owned()
+			} else {
+				enabled.join(", ")
+			},
+		)?;
+	}
+
+	self.write_str(&out).await
+}
+
+#[admin_command]
+async fn show(
+	&self,
+	user_id: OwnedUserId,
+	device_id: OwnedDeviceId,
+	conn_id: Option<String>,
+	full: bool,
+) -> Result {
+	let keys = device_connections(self.services, &user_id, &device_id, conn_id.as_deref());
+	if keys.is_empty() {
+		return Err!("No sliding sync connection of {user_id} {device_id} is cached.");
+	}
+
+	let mut out = String::new();
+	for key in &keys {
+		let Some(snapshot) = self.services.sync.snapshot_snake_connection(key) else {
+			continue;
+		};
+
+		let snapshot = if full { snapshot } else { snapshot.summarized() };
+		let conn_id = key.2.as_deref().unwrap_or("-");
+		writeln!(
+			out,
+			"Connection {conn_id}:\n```json\n{}\n```",
+			serde_json::to_string_pretty(&snapshot)?
+		)?;
+	}
+
+	self.write_str(&out).await
+}
+
+#[admin_command]
+async fn forget(
+	&self,
+	user_id: OwnedUserId,
+	device_id: OwnedDeviceId,
+	conn_id: Option<String>,
+) -> Result {
+	let keys = device_connections(self.services, &user_id, &device_id, conn_id.as_deref());
+	if keys.is_empty() {
+		return Err!("No sliding sync connection of {user_id} {device_id} is cached.");
+	}
+
+	for key in &keys {
+		self.services
+			.sync
+			.forget_snake_sync_connection(key);
+	}
+
+	self.write_str(&format!(
+		"Forgot {} sliding sync connections of {user_id} {device_id}; the client will start \
+		 over with an initial sync.",
+		keys.len()
+	))
+	.await
+}
+
+/// The cached connections of the device, or only the one given.
+fn device_connections(
+	services: &Services,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	conn_id: Option<&str>,
+) -> Vec<SnakeConnectionsKey> {
+	services
+		.sync
+		.iter_snake_connections()
+		.into_iter()
+		.map(|(key, _)| key)
+		.filter(|(conn_user, conn_device, conn)| {
+			conn_user == user_id
+				&& conn_device == device_id
+				&& conn_id.is_none_or(|conn_id| conn.as_deref() == Some(conn_id))
+		})
+		.collect()
+}
@@ -0,0 +1,15 @@
+mod connections;
+
+use clap::Subcommand;
+use tuwunel_core::Result;
+
+use self::connections::SyncConnectionsCommand;
+use crate::admin_command_dispatch;
+
+#[admin_command_dispatch]
+#[derive(Debug, Subcommand)]
+pub(super) enum SyncCommand {
+	#[command(subcommand)]
+	/// - Inspect and reset the state of sliding sync connections
+	Connections(SyncConnectionsCommand),
+}
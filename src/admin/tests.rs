@@ -24,3 +24,564 @@ fn get_help_inner(input: &str) {
 	assert!(error.contains("Commands:"));
 	assert!(error.contains("Options:"));
 }
+
+#[test]
+fn split_args_quoted() {
+	use crate::processor::split_args;
+
+	let argv = split_args(r#"!admin user redact-event $ev --reason "spam and 'abuse'""#)
+		.expect("parsed");
+	assert_eq!(argv, ["!admin", "user", "redact-event", "$ev", "--reason", "spam and 'abuse'"]);
+
+	let argv = split_args(r#"a 'say "hi"' "it's" b"c d"e"#).expect("parsed");
+	assert_eq!(argv, ["a", r#"say "hi""#, "it's", "bc de"]);
+
+	let argv = split_args("a '' \"\"").expect("parsed");
+	assert_eq!(argv, ["a", "", ""]);
+}
+
+#[test]
+fn split_args_escaped() {
+	use crate::processor::split_args;
+
+	let argv = split_args(r#""say \"hi\"" a\ b c\\d"#).expect("parsed");
+	assert_eq!(argv, [r#"say "hi""#, "a b", r"c\d"]);
+
+	// Escapes of other characters are kept for regular expressions
+	let argv = split_args(r#"@.*:example\.com "\d+""#).expect("parsed");
+	assert_eq!(argv, [r"@.*:example\.com", r"\d+"]);
+
+	// Single quotes keep backslashes
+	let argv = split_args(r"'a\'").expect("parsed");
+	assert_eq!(argv, [r"a\"]);
+}
+
+#[test]
+fn split_args_unterminated() {
+	use crate::processor::split_args;
+
+	assert!(split_args(r#"a "b"#).is_err());
+	assert!(split_args("a 'b").is_err());
+	assert!(split_args(r#"a "b\""#).is_err());
+}
+
+#[test]
+fn fenced_body_passed() {
+	use crate::{
+		admin::AdminCommand,
+		processor::{fenced_body, parse_command},
+		server::ServerCommand,
+	};
+
+	let body = ["```", "line one", "", "line two", "```"];
+	assert_eq!(fenced_body(&body).as_deref(), Some("line one\n\nline two"));
+	assert_eq!(fenced_body(&["```", "unterminated"]), None);
+	assert_eq!(fenced_body(&["no fence"]), None);
+
+	let (command, argv) = parse_command("!admin server notice --all", &body).expect("parsed");
+	assert!(argv.iter().any(|arg| arg.starts_with("--body=")));
+	let AdminCommand::Server(ServerCommand::Notice { all, body, .. }) = command else {
+		panic!("unexpected command");
+	};
+
+	assert!(all);
+	assert_eq!(body.as_deref(), Some("line one\n\nline two"));
+
+	// Commands without a body argument keep reading the raw body
+	let (_, argv) = parse_command("!admin appservice register", &body).expect("parsed");
+	assert!(!argv.iter().any(|arg| arg.starts_with("--body=")));
+
+	// An explicit --body is not given a second time from the code block
+	let (_, argv) =
+		parse_command("!admin server notice --all --body=inline", &body).expect("parsed");
+	let bodies = argv
+		.iter()
+		.filter(|arg| arg.starts_with("--body="))
+		.count();
+	assert_eq!(bodies, 1, "the body is passed once");
+}
+
+#[test]
+fn notice_needs_recipient_or_all() {
+	use crate::server::notice_parts;
+
+	let args = |args: &[&str]| args.iter().map(ToString::to_string).collect();
+
+	assert!(notice_parts(false, args(&[]), Some("hi".into())).is_err(), "no recipient");
+	assert!(notice_parts(false, args(&["@alice:example.com"]), None).is_err(), "no message");
+	assert!(
+		notice_parts(false, args(&["@alice:example.com", "hi"]), Some("hi".into())).is_err(),
+		"message given twice"
+	);
+
+	let (recipient, message) =
+		notice_parts(false, args(&["@alice:example.com", "hello", "there"]), None)
+			.expect("recipient and message");
+	assert_eq!(recipient.as_deref(), Some("@alice:example.com"), "recipient is first");
+	assert_eq!(message, "hello there", "message is the rest");
+
+	let (recipient, message) =
+		notice_parts(false, args(&["@alice:example.com"]), Some("hi".into())).expect("body");
+	assert_eq!(recipient.as_deref(), Some("@alice:example.com"), "recipient with a body");
+	assert_eq!(message, "hi", "message from the body");
+
+	let (recipient, message) = notice_parts(true, args(&["hello"]), None).expect("broadcast");
+	assert_eq!(recipient, None, "only --all broadcasts");
+	assert_eq!(message, "hello", "whole arguments are the message");
+}
+
+#[test]
+fn room_list_filters_and_sorts() {
+	use ruma::{owned_room_id, room::RoomType};
+
+	use crate::room::list::{ListedRoom, RoomFilter, RoomKind, RoomSort, sort_rooms};
+
+	let room =
+		|id: &str, name: &str, members: u64, space: bool, public: bool, local: bool| ListedRoom {
+			room_id: id.try_into().expect("valid room id"),
+			members,
+			name: name.to_owned(),
+			room_type: space.then_some(RoomType::Space),
+			public,
+			local,
+			creation: None,
+		};
+
+	let fixture = || {
+		vec![
+			room("!a:example.com", "Lobby", 10, false, true, true),
+			room("!b:example.com", "Community", 30, true, true, false),
+			room("!c:example.com", "Backroom", 20, false, false, true),
+			room("!d:example.com", "Team", 5, true, false, true),
+		]
+	};
+
+	let listed = |filter: RoomFilter| -> Vec<_> {
+		fixture()
+			.into_iter()
+			.filter(|room| filter.matches(room))
+			.map(|room| room.room_id)
+			.collect()
+	};
+
+	let spaces = RoomFilter {
+		kind: RoomKind::Space,
+		..Default::default()
+	};
+	assert_eq!(listed(spaces), [
+		owned_room_id!("!b:example.com"),
+		owned_room_id!("!d:example.com")
+	]);
+
+	let rooms = RoomFilter {
+		kind: RoomKind::Room,
+		..Default::default()
+	};
+	assert_eq!(listed(rooms), [
+		owned_room_id!("!a:example.com"),
+		owned_room_id!("!c:example.com")
+	]);
+
+	let public = RoomFilter { public_only: true, ..Default::default() };
+	assert_eq!(listed(public), [
+		owned_room_id!("!a:example.com"),
+		owned_room_id!("!b:example.com")
+	]);
+
+	let local_rooms = RoomFilter {
+		kind: RoomKind::Room,
+		local_only: true,
+		..Default::default()
+	};
+	assert_eq!(listed(local_rooms), [
+		owned_room_id!("!a:example.com"),
+		owned_room_id!("!c:example.com")
+	]);
+
+	let local_spaces = RoomFilter {
+		kind: RoomKind::Space,
+		local_only: true,
+		..Default::default()
+	};
+	assert_eq!(listed(local_spaces), [owned_room_id!("!d:example.com")]);
+
+	assert_eq!(listed(RoomFilter::default()).len(), 4);
+
+	let sorted = |sort: RoomSort| -> Vec<_> {
+		let mut rooms = fixture();
+		sort_rooms(&mut rooms, sort);
+		rooms.into_iter().map(|room| room.name).collect()
+	};
+
+	assert_eq!(sorted(RoomSort::Members), ["Community", "Backroom", "Lobby", "Team"]);
+	assert_eq!(sorted(RoomSort::Name), ["Backroom", "Community", "Lobby", "Team"]);
+}
+
+#[test]
+fn room_extremities_listed_deepest_first() {
+	use ruma::{OwnedEventId, UInt, events::TimelineEventType};
+	use serde_json::json;
+	use tuwunel_core::matrix::pdu::PduEvent;
+
+	use crate::debug::commands::format_extremities;
+
+	let leaf = |id: &str, depth: u32, sender: &str| {
+		let content = json!({ "msgtype": "m.text", "body": "fork" });
+		let pdu = PduEvent {
+			depth: UInt::from(depth),
+			..PduEvent::fake(id, sender, TimelineEventType::RoomMessage, &content)
+		};
+
+		(pdu.event_id.clone(), Some(pdu))
+	};
+
+	let missing: OwnedEventId = "$missing:example.com"
+		.try_into()
+		.expect("valid event id");
+
+	// The deepest extremities come last, so only a sort before the cut keeps them.
+	let mut extremities: Vec<_> = (1..=24_u32)
+		.map(|depth| leaf(&format!("${depth}:example.com"), depth, "@alice:example.com"))
+		.chain([(missing.clone(), None)])
+		.collect();
+
+	let out = format_extremities(&mut extremities);
+	let lines: Vec<_> = out.lines().collect();
+	assert_eq!(lines.len(), 21, "twenty extremities shown and a note of the rest");
+	assert_eq!(
+		lines[0], "$24:example.com | depth: 24 | ts: 1000 | sender: @alice:example.com",
+		"deepest extremity first"
+	);
+	assert_eq!(
+		lines[19], "$5:example.com | depth: 5 | ts: 1000 | sender: @alice:example.com",
+		"shallower extremities cut"
+	);
+	assert_eq!(lines[20], "+5 more", "extremities not shown counted");
+
+	let mut extremities = vec![
+		leaf("$a:example.com", 10, "@alice:example.com"),
+		leaf("$b:forked.example", 12, "@bob:forked.example"),
+		(missing, None),
+	];
+
+	let out = format_extremities(&mut extremities);
+	let lines: Vec<_> = out.lines().collect();
+	assert_eq!(
+		lines,
+		[
+			"$b:forked.example | depth: 12 | ts: 1000 | sender: @bob:forked.example",
+			"$a:example.com | depth: 10 | ts: 1000 | sender: @alice:example.com",
+			"$missing:example.com | not found",
+		],
+		"all extremities shown when few"
+	);
+}
+
+#[test]
+fn db_compress_state_scope() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "db", "compress-state"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&["--all", "--dry-run"]).is_ok(), "all rooms");
+	assert!(parse(&["--room", "!room:example.com"]).is_ok(), "one room");
+	assert!(parse(&[]).is_err(), "a scope is required");
+	assert!(parse(&["--all", "--room", "!room:example.com"]).is_err(), "scopes conflict");
+}
+
+#[test]
+fn db_rebuild_search_scope() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "db", "rebuild-search"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&["--all"]).is_ok(), "all rooms");
+	assert!(parse(&["!room:example.com"]).is_ok(), "one room");
+	assert!(parse(&["#room:example.com"]).is_ok(), "one room by alias");
+	assert!(parse(&[]).is_err(), "a scope is required");
+	assert!(parse(&["--all", "!room:example.com"]).is_err(), "scopes conflict");
+}
+
+#[test]
+fn user_deactivate_purge_media() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "users", "deactivate"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&["--purge-media", "@alice:example.com"]).is_ok(), "purging media");
+	assert!(parse(&["--no-leave-rooms", "@alice:example.com"]).is_ok(), "staying in rooms");
+	assert!(
+		parse(&["--no-leave-rooms", "--purge-media", "@alice:example.com"]).is_err(),
+		"media is only purged by a full deactivation"
+	);
+}
+
+#[test]
+fn room_moderation_soft_failed() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "rooms", "moderation"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&["list-soft-failed", "!room:example.com"]).is_ok(), "default limit");
+	assert!(
+		parse(&["list-soft-failed", "#room:example.com", "--limit", "5"]).is_ok(),
+		"aliases and a limit"
+	);
+	assert!(
+		parse(&[
+			"redact-user",
+			"!room:example.com",
+			"@alice:example.com",
+			"--include-soft-failed"
+		])
+		.is_ok(),
+		"soft-failed events can be redacted"
+	);
+}
+
+#[test]
+fn room_moderation_purge_history() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "rooms", "moderation", "purge-history"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(
+		parse(&["!room:example.com", "$event:example.com"]).is_ok(),
+		"an event ID cutoff"
+	);
+	assert!(
+		parse(&["!room:example.com", "1700000000000", "--purge-state", "--dry-run"]).is_ok(),
+		"a timestamp cutoff with the state purged on a dry run"
+	);
+	assert!(parse(&["!room:example.com"]).is_err(), "a cutoff is required");
+	assert!(
+		parse(&["#room:example.com", "$event:example.com"]).is_err(),
+		"a room ID is required"
+	);
+}
+
+#[test]
+fn user_key_backup_files() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |sub: &str, args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "users", sub]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(
+		parse("export-key-backup", &["@alice:example.com", "/tmp/keys"]).is_ok(),
+		"latest"
+	);
+	assert!(
+		parse("export-key-backup", &["@alice:example.com", "/tmp/keys", "--version", "3"])
+			.is_ok(),
+		"a version"
+	);
+	assert!(
+		parse("export-key-backup", &["@alice:example.com"]).is_err(),
+		"a path is required"
+	);
+	assert!(parse("import-key-backup", &["@bob:example.com", "/tmp/keys"]).is_ok(), "import");
+	assert!(
+		parse("import-key-backup", &["@bob:example.com", "/tmp/keys", "--version", "3"]).is_err(),
+		"imports always create a new version"
+	);
+}
+
+#[test]
+fn sync_connections_commands() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |sub: &str, args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "sync", "connections", sub]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse("list", &[]).is_ok(), "every connection");
+	assert!(parse("list", &["@alice:example.com"]).is_ok(), "connections of a user");
+	assert!(parse("show", &["@alice:example.com", "DEVICE"]).is_ok(), "every connection");
+	assert!(
+		parse("show", &["@alice:example.com", "DEVICE", "conn", "--full"]).is_ok(),
+		"one connection in full"
+	);
+	assert!(parse("show", &["@alice:example.com"]).is_err(), "a device is required");
+	assert!(parse("forget", &["@alice:example.com", "DEVICE", "conn"]).is_ok(), "forget");
+}
+
+#[test]
+fn user_forget_left() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "users", "forget-left"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&["@alice:example.com"]).is_ok(), "a user");
+	assert!(parse(&[]).is_err(), "the user is required");
+}
+
+#[test]
+fn debug_get_pdu_sections() {
+	use clap::Parser;
+	use ruma::CanonicalJsonObject;
+	use serde_json::json;
+
+	use crate::{admin::AdminCommand, debug::commands::format_signatures};
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "debug", "get-pdu", "$event:example.com"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&[]).is_ok(), "the event alone");
+	assert!(
+		parse(&["--raw", "--context", "5", "--state", "--auth-chain"]).is_ok(),
+		"every section"
+	);
+	assert!(parse(&["--context"]).is_err(), "the context needs a count");
+
+	let json: CanonicalJsonObject = serde_json::from_value(json!({
+		"type": "m.room.message",
+		"signatures": {
+			"example.com": { "ed25519:a": "sig", "ed25519:b": "sig" },
+			"other.example": { "ed25519:c": "sig" },
+		},
+	}))
+	.expect("valid json");
+
+	assert_eq!(
+		format_signatures(&json),
+		"- example.com: ed25519:a, ed25519:b\n- other.example: ed25519:c",
+		"signatures are listed by server"
+	);
+	assert_eq!(format_signatures(&CanonicalJsonObject::new()), "- none", "unsigned event");
+}
+
+#[test]
+fn user_prune_devices_window() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "users", "prune-devices"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&["@alice:example.com", "--inactive-for", "90d"]).is_ok(), "a window");
+	assert!(parse(&["@alice:example.com"]).is_err(), "the window is required");
+}
+
+#[test]
+fn db_backup_commands() {
+	use clap::Parser;
+
+	use crate::admin::AdminCommand;
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "db", "backup"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	assert!(parse(&["create"]).is_ok(), "into the configured path");
+	assert!(parse(&["create", "/var/backups/tuwunel"]).is_ok(), "into another path");
+	assert!(parse(&["list"]).is_ok(), "backups of the configured path");
+	assert!(parse(&["list", "/var/backups/tuwunel"]).is_ok(), "backups of another path");
+	assert!(parse(&["status"]).is_ok(), "progress of the backup");
+	assert!(parse(&["status", "/var/backups/tuwunel"]).is_err(), "status takes no path");
+}
+
+#[test]
+fn clear_caches_of_service() {
+	use clap::Parser;
+
+	use crate::{admin::AdminCommand, server::ServerCommand};
+
+	let parse = |args: &[&str]| {
+		AdminCommand::try_parse_from(
+			["argv[0] doesn't matter", "server", "clear-caches"]
+				.iter()
+				.chain(args),
+		)
+	};
+
+	let Ok(AdminCommand::Server(ServerCommand::ClearCaches { service })) = parse(&[]) else {
+		panic!("clear-caches parses without a service");
+	};
+	assert_eq!(service, None, "all services are cleared by default");
+
+	let Ok(AdminCommand::Server(ServerCommand::ClearCaches { service })) =
+		parse(&["rooms::state_cache"])
+	else {
+		panic!("clear-caches parses with a service");
+	};
+	assert_eq!(service.as_deref(), Some("rooms::state_cache"), "one service is named");
+
+	assert!(parse(&["resolver", "pusher"]).is_err(), "only one service is named");
+}
@@ -1,21 +1,19 @@
-use std::{collections::BTreeMap, fmt::Write as _};
+use std::{collections::BTreeMap, fmt::Write as _, path::PathBuf};
 
 use futures::{FutureExt, StreamExt};
 use ruma::{
 	Int, OwnedEventId, OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId, UserId,
 	events::{
 		RoomAccountDataEventType, StateEventType,
-		room::{
-			power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent, UserPowerLevel},
-			redaction::RoomRedactionEventContent,
-		},
+		room::power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent, UserPowerLevel},
 		tag::{TagEvent, TagEventContent, TagInfo},
 	},
 };
 use tuwunel_core::{
 	Err, Result, debug, debug_warn, error, info, is_equal_to,
 	matrix::{Event, pdu::PduBuilder},
-	utils::{self, ReadyExt},
+	messages::Message,
+	utils::{self, ReadyExt, time::parse_duration},
 	warn,
 };
 use tuwunel_service::Services;
@@ -29,14 +27,30 @@
 const BULK_JOIN_REASON: &str = "Bulk force joining this room as initiated by the server admin.";
 
 #[admin_command]
-pub(super) async fn list_users(&self) -> Result {
-	let users: Vec<_> = self
-		.services
-		.users
-		.list_local_users()
-		.map(ToString::to_string)
-		.collect()
-		.await;
+pub(super) async fn list_users(&self, details: bool) -> Result {
+	let users: Vec<_> = if details {
+		self.services
+			.users
+			.bulk_profiles(self.services.users.list_local_users())
+			.map(|(user_id, displayname, avatar_url)| {
+				let displayname = displayname.unwrap_or_default();
+				let avatar_url = avatar_url
+					.as_ref()
+					.map(ToString::to_string)
+					.unwrap_or_default();
+
+				format!("{user_id} | {displayname} | {avatar_url}")
+			})
+			.collect()
+			.await
+	} else {
+		self.services
+			.users
+			.list_local_users()
+			.map(ToString::to_string)
+			.collect()
+			.await
+	};
 
 	let mut plain_msg = format!("Found {} local user account(s):\n```\n", users.len());
 	plain_msg += users.join("\n").as_str();
@@ -148,7 +162,12 @@ pub(super) async fn create_user(&self, username: String, password: Option<String
 					.join(
 						&user_id,
 						&room_id,
-						Some("Automatically joining this room upon registration".to_owned()),
+						Some(
+							self.services
+								.users
+								.message(&user_id, Message::AutoJoin, &[])
+								.await,
+						),
 						&[
 							self.services.globals.server_name().to_owned(),
 							room_server_name.to_owned(),
@@ -209,7 +228,12 @@ pub(super) async fn create_user(&self, username: String, password: Option<String
 }
 
 #[admin_command]
-pub(super) async fn deactivate(&self, no_leave_rooms: bool, user_id: String) -> Result {
+pub(super) async fn deactivate(
+	&self,
+	no_leave_rooms: bool,
+	purge_media: bool,
+	user_id: String,
+) -> Result {
 	// Validate user id
 	let user_id = parse_local_user_id(self.services, &user_id)?;
 
@@ -218,10 +242,25 @@ pub(super) async fn deactivate(&self, no_leave_rooms: bool, user_id: String) ->
 		return Err!("Not allowed to deactivate the server service account.",);
 	}
 
-	deactivate_user(self.services, &user_id, no_leave_rooms).await?;
+	if no_leave_rooms {
+		self.services
+			.users
+			.deactivate_account(&user_id)
+			.await?;
 
-	self.write_str(&format!("User {user_id} has been deactivated"))
-		.await
+		return self
+			.write_str(&format!("User {user_id} has been deactivated"))
+			.await;
+	}
+
+	let summary = self
+		.services
+		.deactivate
+		.full_deactivate(&user_id, purge_media)
+		.boxed()
+		.await?;
+
+	self.write_str(&summary.to_string()).await
 }
 
 #[admin_command]
@@ -341,7 +380,7 @@ async fn deactivate_user(services: &Services, user_id: &UserId, no_leave_rooms:
 	if !no_leave_rooms {
 		services
 			.deactivate
-			.full_deactivate(user_id)
+			.full_deactivate(user_id, false)
 			.boxed()
 			.await?;
 	} else {
@@ -351,6 +390,42 @@ async fn deactivate_user(services: &Services, user_id: &UserId, no_leave_rooms:
 	Ok(())
 }
 
+#[admin_command]
+pub(super) async fn merge(&self, from_user: String, to_user: String, dry_run: bool) -> Result {
+	let from = parse_local_user_id(self.services, &from_user)?;
+	let to = parse_active_local_user_id(self.services, &to_user).await?;
+
+	if from == to {
+		return Err!("Cannot merge {from} into itself.");
+	}
+
+	if from == self.services.globals.server_user || to == self.services.globals.server_user {
+		return Err!("Not allowed to merge the server service account.");
+	}
+
+	if !self.services.users.exists(&from).await {
+		return Err!("User {from} does not exist on this server.");
+	}
+
+	let plan = self
+		.services
+		.deactivate
+		.plan_merge(&from, &to)
+		.await;
+	if dry_run {
+		return self.write_str(&plan.report(true)).await;
+	}
+
+	let done = self
+		.services
+		.deactivate
+		.merge(&plan)
+		.boxed()
+		.await?;
+
+	self.write_str(&done.report(false)).await
+}
+
 #[admin_command]
 pub(super) async fn list_joined_rooms(&self, user_id: String) -> Result {
 	// Validate user id
@@ -381,6 +456,33 @@ pub(super) async fn list_joined_rooms(&self, user_id: String) -> Result {
 		.await
 }
 
+#[admin_command]
+pub(super) async fn prune_devices(&self, user_id: String, inactive_for: String) -> Result {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+	let inactive_for = parse_duration(&inactive_for)?;
+
+	let pruned = self
+		.services
+		.users
+		.prune_inactive_devices(&user_id, inactive_for)
+		.await?;
+
+	if pruned.is_empty() {
+		return self
+			.write_str(&format!("No device of {user_id} was inactive for that long."))
+			.await;
+	}
+
+	let body = pruned
+		.iter()
+		.map(ToString::to_string)
+		.collect::<Vec<_>>()
+		.join("\n");
+
+	self.write_str(&format!("Removed {} devices of {user_id}:\n```\n{body}\n```", pruned.len()))
+		.await
+}
+
 #[admin_command]
 pub(super) async fn force_join_list_of_local_users(
 	&self,
@@ -675,6 +777,27 @@ pub(super) async fn force_leave_room(
 		.await
 }
 
+#[admin_command]
+pub(super) async fn forget_left(&self, user_id: String) -> Result {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+	let room_ids: Vec<OwnedRoomId> = self
+		.services
+		.state_cache
+		.rooms_left(&user_id)
+		.map(at!(0))
+		.collect()
+		.await;
+
+	for room_id in &room_ids {
+		self.services
+			.state_cache
+			.forget(room_id, &user_id);
+	}
+
+	self.write_str(&format!("{user_id} forgot {} left rooms.", room_ids.len()))
+		.await
+}
+
 #[admin_command]
 pub(super) async fn force_demote(&self, user_id: String, room_id: OwnedRoomOrAliasId) -> Result {
 	let user_id = parse_local_user_id(self.services, &user_id)?;
@@ -931,7 +1054,11 @@ pub(super) async fn get_room_tags(&self, user_id: String, room_id: OwnedRoomId)
 }
 
 #[admin_command]
-pub(super) async fn redact_event(&self, event_id: OwnedEventId) -> Result {
+pub(super) async fn redact_event(
+	&self,
+	event_id: OwnedEventId,
+	reason: Option<String>,
+) -> Result {
 	let Ok(event) = self
 		.services
 		.timeline
@@ -953,10 +1080,12 @@ pub(super) async fn redact_event(&self, event_id: OwnedEventId) -> Result {
 		return Err!("This command only works on local users.");
 	}
 
-	let reason = format!(
-		"The administrator(s) of {} has redacted this user's message.",
-		self.services.globals.server_name()
-	);
+	let reason = reason.unwrap_or_else(|| {
+		let server_name = self.services.globals.server_name().as_str();
+		self.services
+			.globals
+			.message(Message::MessageRedacted, &[("server_name", server_name)])
+	});
 
 	let redaction_event_id = {
 		let state_lock = self
@@ -969,13 +1098,7 @@ pub(super) async fn redact_event(&self, event_id: OwnedEventId) -> Result {
 		self.services
 			.timeline
 			.build_and_append_pdu(
-				PduBuilder {
-					redacts: Some(event.event_id().to_owned()),
-					..PduBuilder::timeline(&RoomRedactionEventContent {
-						redacts: Some(event.event_id().to_owned()),
-						reason: Some(reason),
-					})
-				},
+				PduBuilder::redaction(event.event_id(), Some(reason)),
 				event.sender(),
 				event.room_id(),
 				&state_lock,
@@ -988,3 +1111,59 @@ pub(super) async fn redact_event(&self, event_id: OwnedEventId) -> Result {
 	))
 	.await
 }
+
+#[admin_command]
+pub(super) async fn export_key_backup(
+	&self,
+	user_id: String,
+	path: PathBuf,
+	version: Option<String>,
+) -> Result {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+	let (version, count) = self
+		.services
+		.key_backups
+		.export_backup(&user_id, version.as_deref(), &path)
+		.await?;
+
+	self.write_str(&format!(
+		"Exported {count} session keys of backup version {version} of {user_id} to {}.",
+		path.display()
+	))
+	.await
+}
+
+#[admin_command]
+pub(super) async fn import_key_backup(&self, user_id: String, path: PathBuf) -> Result {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+	if !self.services.users.exists(&user_id).await {
+		return Err!("User {user_id} does not exist.");
+	}
+
+	let (version, count) = self
+		.services
+		.key_backups
+		.import_backup(&user_id, &path)
+		.await?;
+
+	self.write_str(&format!(
+		"Imported {count} session keys from {} as backup version {version} of {user_id}.",
+		path.display()
+	))
+	.await
+}
+
+#[admin_command]
+pub(super) async fn copy_key_backup(&self, user_id: String, from: String, to: String) -> Result {
+	let user_id = parse_local_user_id(self.services, &user_id)?;
+	let count = self
+		.services
+		.key_backups
+		.copy_backup(&user_id, &from, &to)
+		.await?;
+
+	self.write_str(&format!(
+		"Copied {count} session keys of {user_id} from backup version {from} into {to}."
+	))
+	.await
+}
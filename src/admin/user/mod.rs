@@ -1,5 +1,7 @@
 mod commands;
 
+use std::path::PathBuf;
+
 use clap::Subcommand;
 use ruma::{OwnedEventId, OwnedRoomId, OwnedRoomOrAliasId};
 use tuwunel_core::Result;
@@ -11,6 +13,7 @@
 pub(super) enum UserCommand {
 	/// - Create a new user
 	#[clap(alias = "create")]
+	#[mutating]
 	CreateUser {
 		/// Username of the new user
 		username: String,
@@ -19,6 +22,7 @@ pub(super) enum UserCommand {
 	},
 
 	/// - Reset user password
+	#[mutating]
 	ResetPassword {
 		/// Username of the user for whom the password should be reset
 		username: String,
@@ -30,9 +34,18 @@ pub(super) enum UserCommand {
 	///
 	/// User will be removed from all rooms by default.
 	/// Use --no-leave-rooms to not leave all rooms by default.
+	///
+	/// A summary of the rooms left, the power levels demoted and the media
+	/// purged is returned and noticed to the admin room.
+	#[mutating]
 	Deactivate {
 		#[arg(short, long)]
 		no_leave_rooms: bool,
+
+		/// Also delete all media the user uploaded
+		#[arg(long, conflicts_with = "no_leave_rooms")]
+		purge_media: bool,
+
 		user_id: String,
 	},
 
@@ -50,6 +63,7 @@ pub(super) enum UserCommand {
 	///
 	/// This command needs a newline separated list of users provided in a
 	/// Markdown code block below the command.
+	#[mutating]
 	DeactivateAll {
 		#[arg(short, long)]
 		/// Does not leave any rooms the user is in on deactivation
@@ -59,9 +73,33 @@ pub(super) enum UserCommand {
 		force: bool,
 	},
 
+	/// - Merge a local user into another
+	///
+	/// Meant for accounts differing only by the case of their localpart. The
+	/// account data and room tags the target lacks are copied to it and the
+	/// uploads of the source are attributed to it; the source then leaves all
+	/// its rooms and is deactivated. Event history and encryption keys are
+	/// not merged.
+	#[mutating]
+	Merge {
+		/// The account to merge and deactivate
+		from_user: String,
+
+		/// The active account receiving the data
+		to_user: String,
+
+		/// Only report what merging would do
+		#[arg(long)]
+		dry_run: bool,
+	},
+
 	/// - List local users in the database
 	#[clap(alias = "list")]
-	ListUsers,
+	ListUsers {
+		/// Also show the displayname and avatar of each user
+		#[arg(long)]
+		details: bool,
+	},
 
 	/// - Lists all the rooms (local and remote) that the specified user is
 	///   joined in
@@ -70,31 +108,57 @@ pub(super) enum UserCommand {
 	},
 
 	/// - Manually join a local user to a room.
+	#[mutating]
 	ForceJoinRoom {
 		user_id: String,
 		room_id: OwnedRoomOrAliasId,
 	},
 
 	/// - Manually leave a local user from a room.
+	#[mutating]
 	ForceLeaveRoom {
 		user_id: String,
 		room_id: OwnedRoomOrAliasId,
 	},
 
+	/// - Forgets every room a local user left or was removed from, as if the
+	///   user called /forget on each; they stop appearing in sync.
+	#[mutating]
+	ForgetLeft {
+		user_id: String,
+	},
+
+	/// - Removes the devices of a local user not seen for a while
+	///
+	/// Their access tokens, one-time keys and queued to-device messages are
+	/// removed with them, and the users sharing rooms with the user are told
+	/// the device list changed. Devices never seen are kept.
+	#[mutating]
+	PruneDevices {
+		user_id: String,
+
+		/// How long the devices were not seen, e.g. "90d"; at least a day.
+		#[arg(long)]
+		inactive_for: String,
+	},
+
 	/// - Forces the specified user to drop their power levels to the room
 	///   default, if their permissions allow and the auth check permits
+	#[mutating]
 	ForceDemote {
 		user_id: String,
 		room_id: OwnedRoomOrAliasId,
 	},
 
 	/// - Force promote
+	#[mutating]
 	ForcePromote {
 		user_id: String,
 		room_id: OwnedRoomOrAliasId,
 	},
 
 	/// - Grant server-admin privileges to a user.
+	#[mutating]
 	MakeUserAdmin {
 		user_id: String,
 	},
@@ -106,6 +170,7 @@ pub(super) enum UserCommand {
 	/// permanently see your admin room without it being buried away in your
 	/// favourites or rooms. To do this, you would pass your user, your admin
 	/// room's internal ID, and the tag name `m.server_notice`.
+	#[mutating]
 	PutRoomTag {
 		user_id: String,
 		room_id: OwnedRoomId,
@@ -113,6 +178,7 @@ pub(super) enum UserCommand {
 	},
 
 	/// - Deletes the room tag for the specified user and room ID
+	#[mutating]
 	DeleteRoomTag {
 		user_id: String,
 		room_id: OwnedRoomId,
@@ -129,8 +195,13 @@ pub(super) enum UserCommand {
 	///   user
 	///
 	/// This is only valid for local users
+	#[mutating]
 	RedactEvent {
 		event_id: OwnedEventId,
+
+		/// Reason for the redaction; quote it when it contains spaces.
+		#[arg(short, long)]
+		reason: Option<String>,
 	},
 
 	/// - Force joins a specified list of local users to join the specified
@@ -141,6 +212,7 @@ pub(super) enum UserCommand {
 	/// At least 1 server admin must be in the room to reduce abuse.
 	///
 	/// Requires the `--yes-i-want-to-do-this` flag.
+	#[mutating]
 	ForceJoinListOfLocalUsers {
 		room_id: OwnedRoomOrAliasId,
 
@@ -153,10 +225,52 @@ pub(super) enum UserCommand {
 	/// At least 1 server admin must be in the room to reduce abuse.
 	///
 	/// Requires the `--yes-i-want-to-do-this` flag.
+	#[mutating]
 	ForceJoinAllLocalUsers {
 		room_id: OwnedRoomOrAliasId,
 
 		#[arg(long)]
 		yes_i_want_to_do_this: bool,
 	},
+
+	/// - Exports a key backup of a user to a file
+	///
+	/// The session keys stay encrypted; the file only moves them, one JSON
+	/// object per line, to be imported by `import-key-backup`.
+	ExportKeyBackup {
+		user_id: String,
+
+		/// Path on the server of the file to write.
+		path: PathBuf,
+
+		/// Version of the backup; the latest by default.
+		#[arg(long)]
+		version: Option<String>,
+	},
+
+	/// - Imports a key backup from a file as a new backup version of a user
+	///
+	/// The file is one written by `export-key-backup`.
+	#[mutating]
+	ImportKeyBackup {
+		user_id: String,
+
+		/// Path on the server of the file to read.
+		path: PathBuf,
+	},
+
+	/// - Copies the session keys of a backup version of a user into another
+	///
+	/// Keys already in the target version are only replaced by better ones,
+	/// so an interrupted copy is resumed by running it again.
+	#[mutating]
+	CopyKeyBackup {
+		user_id: String,
+
+		/// Version to copy the session keys from.
+		from: String,
+
+		/// Version to copy the session keys into.
+		to: String,
+	},
 }
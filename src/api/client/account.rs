@@ -1,13 +1,24 @@
-use axum::extract::State;
+use axum::{Form, Json, extract::State, response::IntoResponse};
 use axum_client_ip::InsecureClientIp;
 use futures::{FutureExt, StreamExt};
-use ruma::api::client::account::{
-	ThirdPartyIdRemovalStatus, change_password, deactivate, get_3pids,
-	request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn, whoami,
+use ruma::{
+	OwnedSessionId,
+	api::client::account::{
+		ThirdPartyIdRemovalStatus, add_3pid, change_password, deactivate, delete_3pid, get_3pids,
+		request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
+		request_password_change_token_via_email, request_password_change_token_via_msisdn,
+		whoami,
+	},
 };
-use tuwunel_core::{Err, Result, info, utils::ReadyExt};
+use serde::Deserialize;
+use tuwunel_core::{Err, Result, err, info, utils::ReadyExt};
+use tuwunel_service::{ratelimit::Action, threepid::Purpose};
 
-use crate::{Ruma, router::auth_uiaa};
+use crate::{
+	ClientAddr, Ruma,
+	client::utils::{rate_limit, rate_limit_client},
+	router::auth_uiaa,
+};
 
 /// # `POST /_matrix/client/r0/account/password`
 ///
@@ -101,17 +112,11 @@ pub(crate) async fn deactivate_route(
 
 	services
 		.deactivate
-		.full_deactivate(sender_user)
+		.full_deactivate(sender_user, false)
 		.boxed()
 		.await?;
 
 	info!("User {sender_user} deactivated their account.");
-	if services.server.config.admin_room_notices {
-		services
-			.admin
-			.notice(&format!("User {sender_user} deactivated their account."))
-			.await;
-	}
 
 	Ok(deactivate::v3::Response {
 		id_server_unbind_result: ThirdPartyIdRemovalStatus::NoSupport,
@@ -121,17 +126,17 @@ pub(crate) async fn deactivate_route(
 /// # `GET _matrix/client/v3/account/3pid`
 ///
 /// Get a list of third party identifiers associated with this account.
-///
-/// - Currently always returns empty list
 pub(crate) async fn third_party_route(
+	State(services): State<crate::State>,
 	body: Ruma<get_3pids::v3::Request>,
 ) -> Result<get_3pids::v3::Response> {
-	let _sender_user = body
-		.sender_user
-		.as_ref()
-		.expect("user is authenticated");
+	let threepids = services
+		.threepid
+		.threepids(body.sender_user())
+		.collect()
+		.await;
 
-	Ok(get_3pids::v3::Response::new(Vec::new()))
+	Ok(get_3pids::v3::Response::new(threepids))
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/email/requestToken`
@@ -141,10 +146,28 @@ pub(crate) async fn third_party_route(
 ///
 /// - 403 signals that The homeserver does not allow the third party identifier
 ///   as a contact option.
+/// - 400 M_THREEPID_IN_USE when the address belongs to an account already.
 pub(crate) async fn request_3pid_management_token_via_email_route(
-	_body: Ruma<request_3pid_management_token_via_email::v3::Request>,
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	body: Ruma<request_3pid_management_token_via_email::v3::Request>,
 ) -> Result<request_3pid_management_token_via_email::v3::Response> {
-	Err!(Request(ThreepidDenied("Third party identifiers are not implemented")))
+	rate_limit(&services, &body, addr, Action::Email).await?;
+
+	let sid = services
+		.threepid
+		.request_email_token(
+			&body.email,
+			body.client_secret.as_str(),
+			body.send_attempt,
+			Purpose::Add,
+		)
+		.await?;
+
+	Ok(request_3pid_management_token_via_email::v3::Response {
+		sid: session_id(sid)?,
+		submit_url: Some(services.threepid.submit_url()),
+	})
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/msisdn/requestToken`
@@ -152,10 +175,132 @@ pub(crate) async fn request_3pid_management_token_via_email_route(
 /// "This API should be used to request validation tokens when adding an phone
 /// number to an account"
 ///
-/// - 403 signals that The homeserver does not allow the third party identifier
-///   as a contact option.
+/// - Phone numbers are not supported.
 pub(crate) async fn request_3pid_management_token_via_msisdn_route(
 	_body: Ruma<request_3pid_management_token_via_msisdn::v3::Request>,
 ) -> Result<request_3pid_management_token_via_msisdn::v3::Response> {
-	Err!(Request(ThreepidDenied("Third party identifiers are not implemented")))
+	Err!(Request(ThreepidMediumNotSupported("Phone numbers are not supported.")))
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/add`
+///
+/// Adds the email address validated by the session to the account.
+///
+/// - Requires UIAA to verify user password
+pub(crate) async fn add_3pid_route(
+	State(services): State<crate::State>,
+	body: Ruma<add_3pid::v3::Request>,
+) -> Result<add_3pid::v3::Response> {
+	let ref sender_user = auth_uiaa(&services, &body).await?;
+
+	services
+		.threepid
+		.add(sender_user, body.client_secret.as_str(), body.sid.as_str())
+		.await?;
+
+	Ok(add_3pid::v3::Response {})
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/delete`
+///
+/// Removes a third party identifier from the account. Identity servers are
+/// not contacted.
+pub(crate) async fn delete_3pid_route(
+	State(services): State<crate::State>,
+	body: Ruma<delete_3pid::v3::Request>,
+) -> Result<delete_3pid::v3::Response> {
+	services
+		.threepid
+		.remove(body.sender_user(), &body.medium, &body.address)
+		.await;
+
+	Ok(delete_3pid::v3::Response {
+		id_server_unbind_result: ThirdPartyIdRemovalStatus::NoSupport,
+	})
+}
+
+/// # `POST /_matrix/client/v3/account/password/email/requestToken`
+///
+/// Mails a token to reset the password of the account the address belongs to.
+///
+/// - 400 M_THREEPID_NOT_FOUND when the address belongs to no account.
+pub(crate) async fn request_password_change_token_via_email_route(
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	body: Ruma<request_password_change_token_via_email::v3::Request>,
+) -> Result<request_password_change_token_via_email::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Email).await?;
+
+	let sid = services
+		.threepid
+		.request_email_token(
+			&body.email,
+			body.client_secret.as_str(),
+			body.send_attempt,
+			Purpose::PasswordReset,
+		)
+		.await?;
+
+	Ok(request_password_change_token_via_email::v3::Response {
+		sid: session_id(sid)?,
+		submit_url: Some(services.threepid.submit_url()),
+	})
+}
+
+/// # `POST /_matrix/client/v3/account/password/msisdn/requestToken`
+///
+/// - Phone numbers are not supported.
+pub(crate) async fn request_password_change_token_via_msisdn_route(
+	_body: Ruma<request_password_change_token_via_msisdn::v3::Request>,
+) -> Result<request_password_change_token_via_msisdn::v3::Response> {
+	Err!(Request(ThreepidMediumNotSupported("Phone numbers are not supported.")))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SubmitToken {
+	client_secret: String,
+	sid: String,
+	token: String,
+}
+
+/// # `GET /_tuwunel/threepid/email/submit_token`
+///
+/// Validates an email address from the link mailed to it.
+pub(crate) async fn submit_threepid_token_link_route(
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	Form(query): Form<SubmitToken>,
+) -> Result<impl IntoResponse> {
+	rate_limit_client(&services, addr, Action::Email).await?;
+
+	services
+		.threepid
+		.submit_token(&query.client_secret, &query.sid, &query.token)
+		.await?;
+
+	Ok("Your email address has been validated. You may now return to your client.")
+}
+
+/// # `POST /_tuwunel/threepid/email/submit_token`
+///
+/// Validates an email address with the token a client was given; this is the
+/// `submit_url` of the requests for a token.
+pub(crate) async fn submit_threepid_token_route(
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	Json(body): Json<SubmitToken>,
+) -> Result<impl IntoResponse> {
+	rate_limit_client(&services, addr, Action::Email).await?;
+
+	services
+		.threepid
+		.submit_token(&body.client_secret, &body.sid, &body.token)
+		.await?;
+
+	Ok(Json(serde_json::json!({ "success": true })))
+}
+
+fn session_id(sid: String) -> Result<OwnedSessionId> {
+	sid.try_into()
+		.map_err(|e| err!("Generated an invalid session ID: {e}"))
 }
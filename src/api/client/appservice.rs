@@ -1,5 +1,5 @@
 use axum::extract::State;
-use ruma::api::{appservice::ping, client::appservice::request_ping};
+use ruma::api::client::appservice::request_ping;
 use tuwunel_core::{Err, Result, err};
 
 use crate::Ruma;
@@ -22,30 +22,10 @@ pub(crate) async fn appservice_ping(
 		)));
 	}
 
-	if appservice_info.registration.url.is_none()
-		|| appservice_info
-			.registration
-			.url
-			.as_ref()
-			.is_some_and(|url| url.is_empty() || url == "null")
-	{
-		return Err!(Request(UrlNotSet(
-			"Appservice does not have a URL set, there is nothing to ping."
-		)));
-	}
-
-	let timer = tokio::time::Instant::now();
-
-	let _response = services
-		.sending
-		.send_appservice_request(
-			appservice_info.registration.clone(),
-			ping::send_ping::v1::Request {
-				transaction_id: body.transaction_id.clone(),
-			},
-		)
-		.await?
-		.expect("We already validated if an appservice URL exists above");
+	let duration = services
+		.appservice
+		.ping(&appservice_info.registration, body.transaction_id.clone())
+		.await?;
 
-	Ok(request_ping::v1::Response { duration: timer.elapsed() })
+	Ok(request_ping::v1::Response { duration })
 }
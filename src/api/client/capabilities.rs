@@ -3,26 +3,33 @@
 use axum::extract::State;
 use ruma::{
 	RoomVersionId,
-	api::client::discovery::{
-		get_capabilities,
-		get_capabilities::v3::{
-			Capabilities, GetLoginTokenCapability, ProfileFieldsCapability, RoomVersionStability,
-			RoomVersionsCapability, ThirdPartyIdChangesCapability,
+	api::client::{
+		discovery::{
+			get_capabilities,
+			get_capabilities::v3::{
+				Capabilities, ChangePasswordCapability, GetLoginTokenCapability,
+				ProfileFieldsCapability, RoomVersionStability, RoomVersionsCapability,
+				ThirdPartyIdChangesCapability,
+			},
 		},
+		profile::ProfileFieldName,
 	},
 };
 use serde_json::json;
 use tuwunel_core::{Result, Server};
 
-use crate::Ruma;
+use crate::{Ruma, client::profile_field_allowed};
 
 /// # `GET /_matrix/client/v3/capabilities`
 ///
 /// Get information on the supported feature set and other relevant capabilities
 /// of this server.
+///
+/// - Password changes are unavailable to users authenticating through LDAP
+/// - Changes of the displayname and avatar follow the config
 pub(crate) async fn get_capabilities_route(
 	State(services): State<crate::State>,
-	_body: Ruma<get_capabilities::v3::Request>,
+	body: Ruma<get_capabilities::v3::Request>,
 ) -> Result<get_capabilities::v3::Response> {
 	let available: BTreeMap<RoomVersionId, RoomVersionStability> =
 		Server::available_room_versions().collect();
@@ -37,18 +44,44 @@ pub(crate) async fn get_capabilities_route(
 			.clone(),
 	};
 
-	// we do not implement 3PID stuff
-	capabilities.thirdparty_id_changes = ThirdPartyIdChangesCapability { enabled: false };
+	capabilities.change_password = ChangePasswordCapability::new(
+		services
+			.users
+			.is_password_changeable(body.sender_user())
+			.await,
+	);
+
+	// email addresses can only be added once they can be validated
+	capabilities.thirdparty_id_changes = ThirdPartyIdChangesCapability {
+		enabled: services.threepid.email_enabled(),
+	};
 
 	capabilities.get_login_token = GetLoginTokenCapability {
 		enabled: services.server.config.login_via_existing_session,
 	};
 
-	capabilities.profile_fields = ProfileFieldsCapability::new(true).into();
+	let disallowed: Vec<_> = [ProfileFieldName::DisplayName, ProfileFieldName::AvatarUrl]
+		.into_iter()
+		.filter(|field| !profile_field_allowed(&services, field))
+		.collect();
+
+	capabilities
+		.set("m.set_displayname", json!({"enabled": services.config.allow_set_displayname}))?;
+
+	capabilities
+		.set("m.set_avatar_url", json!({"enabled": services.config.allow_set_avatar_url}))?;
+
+	let mut profile_fields = ProfileFieldsCapability::new(true);
+	profile_fields.disallowed = (!disallowed.is_empty()).then_some(disallowed);
+	capabilities.profile_fields = profile_fields.into();
+
+	// MSC4267 forgets every room left, including kicks and bans.
+	let forget_forced_upon_leave =
+		services.config.forget_rooms_on_leave && services.config.forget_rooms_on_kick_ban;
 
 	capabilities.set(
 		"org.matrix.msc4267.forget_forced_upon_leave",
-		json!({"enabled": services.config.forget_forced_upon_leave}),
+		json!({"enabled": forget_forced_upon_leave}),
 	)?;
 
 	Ok(get_capabilities::v3::Response { capabilities })
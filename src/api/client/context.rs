@@ -12,14 +12,17 @@
 		stream::{BroadbandExt, ReadyExt, TryIgnore, WidebandExt},
 	},
 };
-use tuwunel_service::rooms::{lazy_loading, lazy_loading::Options, short::ShortStateKey};
+use tuwunel_service::rooms::{
+	lazy_loading,
+	lazy_loading::{Options, Witness},
+	short::ShortStateKey,
+};
 
 use crate::{
 	Ruma,
 	client::message::{event_filter, ignored_filter, lazy_loading_witness, visibility_filter},
 };
 
-const LIMIT_MAX: usize = 100;
 const LIMIT_DEFAULT: usize = 10;
 
 /// # `GET /_matrix/client/r0/rooms/{roomId}/context/{eventId}`
@@ -42,12 +45,12 @@ pub(crate) async fn get_context_route(
 		return Err!(Request(Forbidden("Room does not exist to this server")));
 	}
 
-	// Use limit or else 10, with maximum 100
+	// Use limit or else 10, with maximum per config
 	let limit: usize = body
 		.limit
 		.try_into()
 		.unwrap_or(LIMIT_DEFAULT)
-		.min(LIMIT_MAX);
+		.min(services.config.context_limit_max);
 
 	let base_id = services
 		.timeline
@@ -153,24 +156,14 @@ pub(crate) async fn get_context_route(
 	let state_ids: Vec<(ShortStateKey, OwnedEventId)> = state_ids?;
 	let shortstatekeys = state_ids.iter().map(at!(0)).stream();
 	let shorteventids = state_ids.iter().map(ref_at!(1)).stream();
-	let lazy_loading_witnessed = lazy_loading_witnessed.unwrap_or_default();
 	let state: Vec<_> = services
 		.short
 		.multi_get_statekey_from_short(shortstatekeys)
 		.zip(shorteventids)
 		.ready_filter_map(|item| Some((item.0.ok()?, item.1)))
 		.ready_filter_map(|((event_type, state_key), event_id)| {
-			if filter.lazy_load_options.is_enabled()
-				&& event_type == StateEventType::RoomMember
-				&& state_key
-					.as_str()
-					.try_into()
-					.is_ok_and(|user_id: &UserId| !lazy_loading_witnessed.contains(user_id))
-			{
-				return None;
-			}
-
-			Some(event_id)
+			include_state(&event_type, state_key.as_str(), lazy_loading_witnessed.as_ref())
+				.then_some(event_id)
 		})
 		.broad_filter_map(|event_id: &OwnedEventId| {
 			services.timeline.get_pdu(event_id.as_ref()).ok()
@@ -211,3 +204,100 @@ pub(crate) async fn get_context_route(
 		state,
 	})
 }
+
+/// Whether the state event is returned; with lazy loading, only the members
+/// witnessed in the window of events are.
+fn include_state(
+	event_type: &StateEventType,
+	state_key: &str,
+	witness: Option<&Witness>,
+) -> bool {
+	let Some(witness) = witness else {
+		return true;
+	};
+
+	*event_type != StateEventType::RoomMember
+		|| <&UserId>::try_from(state_key)
+			.ok()
+			.is_none_or(|user_id| witness.contains(user_id))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use ruma::{
+		UserId,
+		events::{StateEventType, TimelineEventType},
+		owned_user_id,
+	};
+	use serde_json::json;
+	use tuwunel_core::matrix::pdu::{PduCount, PduEvent};
+
+	use super::include_state;
+	use crate::client::message::chunk_senders;
+
+	fn message(count: u64, sender: &str) -> (PduCount, PduEvent) {
+		let content = json!({ "msgtype": "m.text", "body": "hi" });
+		let pdu = PduEvent::fake(
+			"$event:example.com",
+			sender,
+			TimelineEventType::RoomMessage,
+			&content,
+		);
+
+		(PduCount::Normal(count), pdu)
+	}
+
+	fn state() -> [(StateEventType, &'static str); 6] {
+		[
+			(StateEventType::RoomCreate, ""),
+			(StateEventType::RoomMember, "@alice:example.com"),
+			(StateEventType::RoomMember, "@bob:example.com"),
+			(StateEventType::RoomMember, "@carol:example.com"),
+			(StateEventType::RoomMember, "@dave:example.com"),
+			(StateEventType::RoomMember, "@erin:example.com"),
+		]
+	}
+
+	#[test]
+	fn lazy_members_match_witness() {
+		let before = [message(1, "@alice:example.com"), message(2, "@bob:example.com")];
+		let event = Some(message(3, "@alice:example.com"));
+		let after = [message(4, "@carol:example.com")];
+
+		// senders of the window, and a reader whose receipt falls in it
+		let mut witness = chunk_senders(
+			event
+				.iter()
+				.chain(before.iter())
+				.chain(after.iter()),
+		);
+		witness.insert(owned_user_id!("@dave:example.com"));
+
+		let members: HashSet<_> = state()
+			.into_iter()
+			.filter(|(event_type, state_key)| {
+				include_state(event_type, state_key, Some(&witness))
+			})
+			.filter(|(event_type, _)| *event_type == StateEventType::RoomMember)
+			.map(|(_, state_key)| UserId::parse(state_key).expect("valid user id"))
+			.collect();
+
+		assert_eq!(members, witness, "members are exactly the senders and readers of the window");
+		assert!(
+			include_state(&StateEventType::RoomCreate, "", Some(&witness)),
+			"other state is kept"
+		);
+	}
+
+	#[test]
+	fn full_state_without_lazy_loading() {
+		assert!(
+			state()
+				.iter()
+				.all(|(event_type, state_key)| include_state(event_type, state_key, None)),
+			"every state event is returned"
+		);
+	}
+}
@@ -13,20 +13,33 @@
 
 /// # `GET /_matrix/client/r0/devices`
 ///
-/// Get metadata on all devices of the sender user.
+/// Get metadata on all devices of the sender user, the most recently seen
+/// first.
 pub(crate) async fn get_devices_route(
 	State(services): State<crate::State>,
 	body: Ruma<get_devices::v3::Request>,
 ) -> Result<get_devices::v3::Response> {
-	let devices: Vec<device::Device> = services
+	let mut devices: Vec<device::Device> = services
 		.users
 		.all_devices_metadata(body.sender_user())
 		.collect()
 		.await;
 
+	sort_devices(&mut devices);
+
 	Ok(get_devices::v3::Response { devices })
 }
 
+/// Sorts the devices by when they were last seen, the most recent first and
+/// those never seen last.
+fn sort_devices(devices: &mut [device::Device]) {
+	devices.sort_by(|a, b| {
+		b.last_seen_ts
+			.cmp(&a.last_seen_ts)
+			.then_with(|| a.device_id.cmp(&b.device_id))
+	});
+}
+
 /// # `GET /_matrix/client/r0/devices/{deviceId}`
 ///
 /// Get metadata on a single device of the sender user.
@@ -117,7 +130,7 @@ pub(crate) async fn update_device_route(
 /// - Invalidates access token
 /// - Deletes device metadata (device id, device display name, last seen ip,
 ///   last seen ts)
-/// - Forgets to-device events
+/// - Forgets to-device events and one-time keys
 /// - Triggers device list updates
 pub(crate) async fn delete_device_route(
 	State(services): State<crate::State>,
@@ -160,7 +173,7 @@ pub(crate) async fn delete_device_route(
 /// - Invalidates access token
 /// - Deletes device metadata (device id, device display name, last seen ip,
 ///   last seen ts)
-/// - Forgets to-device events
+/// - Forgets to-device events and one-time keys
 /// - Triggers device list updates
 pub(crate) async fn delete_devices_route(
 	State(services): State<crate::State>,
@@ -195,3 +208,46 @@ pub(crate) async fn delete_devices_route(
 
 	Ok(delete_devices::v3::Response {})
 }
+
+#[cfg(test)]
+mod tests {
+	use ruma::{MilliSecondsSinceUnixEpoch, UInt, api::client::device::Device, owned_device_id};
+
+	use super::sort_devices;
+
+	#[test]
+	fn devices_most_recently_seen_first() {
+		let device = |device_id: &str, last_seen: Option<u32>| Device {
+			device_id: device_id.into(),
+			display_name: None,
+			last_seen_ip: None,
+			last_seen_ts: last_seen
+				.map(UInt::from)
+				.map(MilliSecondsSinceUnixEpoch),
+		};
+
+		let mut devices = vec![
+			device("OLD", Some(1_000)),
+			device("NEVER", None),
+			device("NEW", Some(3_000)),
+			device("MIDDLE", Some(2_000)),
+		];
+
+		sort_devices(&mut devices);
+
+		let ids: Vec<_> = devices
+			.into_iter()
+			.map(|device| device.device_id)
+			.collect();
+		assert_eq!(
+			ids,
+			[
+				owned_device_id!("NEW"),
+				owned_device_id!("MIDDLE"),
+				owned_device_id!("OLD"),
+				owned_device_id!("NEVER"),
+			],
+			"the most recently seen device is first and one never seen is last"
+		);
+	}
+}
@@ -1,11 +1,8 @@
 use axum::extract::State;
 use axum_client_ip::InsecureClientIp;
-use futures::{
-	FutureExt, StreamExt, TryFutureExt,
-	future::{join, join4, join5},
-};
+use futures::StreamExt;
 use ruma::{
-	OwnedRoomId, RoomId, ServerName, UInt, UserId,
+	ServerName, UInt,
 	api::{
 		client::{
 			directory::{
@@ -17,19 +14,12 @@
 		federation,
 	},
 	directory::{Filter, PublicRoomsChunk, RoomNetwork, RoomTypeFilter},
-	events::{
-		StateEventType,
-		room::join_rules::{JoinRule, RoomJoinRulesEventContent},
-	},
 	uint,
 };
 use tuwunel_core::{
 	Err, Result, err, info, is_true,
-	matrix::Event,
 	utils::{
-		TryFutureExtExt,
 		math::Expected,
-		result::FlatOk,
 		stream::{IterStream, ReadyExt, WidebandExt},
 	},
 };
@@ -126,18 +116,23 @@ pub(crate) async fn set_room_visibility_route(
 		return Err!(Request(Forbidden("Guests cannot publish to room directories")));
 	}
 
-	if !user_can_publish_room(&services, sender_user, &body.room_id).await? {
+	if !services
+		.directory
+		.user_can_publish(sender_user, &body.room_id)
+		.await?
+	{
 		return Err!(Request(Forbidden("User is not allowed to publish this room")));
 	}
 
+	let is_admin = services.users.is_admin(sender_user).await;
+
 	match &body.visibility {
 		| room::Visibility::Public => {
 			if services
 				.server
 				.config
 				.lockdown_public_room_directory
-				&& !services.users.is_admin(sender_user).await
-				&& body.appservice_info.is_none()
+				&& !is_admin && body.appservice_info.is_none()
 			{
 				info!(
 					"Non-admin user {sender_user} tried to publish {0} to the room directory \
@@ -161,7 +156,10 @@ pub(crate) async fn set_room_visibility_route(
 				)));
 			}
 
-			services.directory.set_public(&body.room_id);
+			services
+				.directory
+				.set_public(&body.room_id, sender_user)
+				.await;
 
 			if services.server.config.admin_room_notices {
 				services
@@ -174,7 +172,11 @@ pub(crate) async fn set_room_visibility_route(
 			}
 			info!("{sender_user} made {0} public to the room directory", body.room_id);
 		},
-		| room::Visibility::Private => services.directory.set_not_public(&body.room_id),
+		| room::Visibility::Private =>
+			services
+				.directory
+				.set_not_public(&body.room_id, sender_user)
+				.await,
 		| _ => {
 			return Err!(Request(InvalidParam("Room visibility type is not supported.",)));
 		},
@@ -291,7 +293,7 @@ pub(crate) async fn get_public_rooms_filtered_helper(
 		.public_rooms()
 		.map(ToOwned::to_owned)
 		.chain(meta_public_rooms)
-		.wide_then(|room_id| public_rooms_chunk(services, room_id))
+		.wide_then(|room_id| services.directory.public_room_summary(room_id))
 		.ready_filter_map(|chunk| {
 			if !filter.room_types.is_empty()
 				&& !filter
@@ -365,114 +367,6 @@ pub(crate) async fn get_public_rooms_filtered_helper(
 	})
 }
 
-/// Check whether the user can publish to the room directory via power levels of
-/// room history visibility event or room creator
-async fn user_can_publish_room(
-	services: &Services,
-	user_id: &UserId,
-	room_id: &RoomId,
-) -> Result<bool> {
-	match services
-		.state_accessor
-		.get_power_levels(room_id)
-		.await
-	{
-		| Ok(power_levels) =>
-			Ok(power_levels.user_can_send_state(user_id, StateEventType::RoomHistoryVisibility)),
-		| _ => {
-			match services
-				.state_accessor
-				.room_state_get(room_id, &StateEventType::RoomCreate, "")
-				.await
-			{
-				| Ok(event) => Ok(event.sender() == user_id),
-				| _ => Err!(Request(Forbidden("User is not allowed to publish this room"))),
-			}
-		},
-	}
-}
-
-async fn public_rooms_chunk(services: &Services, room_id: OwnedRoomId) -> PublicRoomsChunk {
-	let name = services.state_accessor.get_name(&room_id).ok();
-
-	let room_type = services
-		.state_accessor
-		.get_room_type(&room_id)
-		.ok();
-
-	let canonical_alias = services
-		.state_accessor
-		.get_canonical_alias(&room_id)
-		.ok()
-		.then(async |alias| {
-			if let Some(alias) = alias
-				&& services.globals.alias_is_local(&alias)
-				&& let Ok(alias_room_id) = services.alias.resolve_local_alias(&alias).await
-				&& alias_room_id == room_id
-			{
-				Some(alias)
-			} else {
-				None
-			}
-		});
-
-	let avatar_url = services
-		.state_accessor
-		.get_avatar(&room_id)
-		.map_ok(|content| content.url)
-		.ok();
-
-	let topic = services
-		.state_accessor
-		.get_room_topic(&room_id)
-		.ok();
-
-	let world_readable = services
-		.state_accessor
-		.is_world_readable(&room_id);
-
-	let join_rule = services
-		.state_accessor
-		.room_state_get_content(&room_id, &StateEventType::RoomJoinRules, "")
-		.map_ok(|c: RoomJoinRulesEventContent| match c.join_rule {
-			| JoinRule::Public => "public".into(),
-			| JoinRule::Knock => "knock".into(),
-			| JoinRule::KnockRestricted(_) => "knock_restricted".into(),
-			| _ => "invite".into(),
-		});
-
-	let guest_can_join = services.state_accessor.guest_can_join(&room_id);
-
-	let num_joined_members = services.state_cache.room_joined_count(&room_id);
-
-	let (
-		(avatar_url, canonical_alias, guest_can_join, join_rule, name),
-		(num_joined_members, room_type, topic, world_readable),
-	) = join(
-		join5(avatar_url, canonical_alias, guest_can_join, join_rule, name),
-		join4(num_joined_members, room_type, topic, world_readable),
-	)
-	.boxed()
-	.await;
-
-	PublicRoomsChunk {
-		avatar_url: avatar_url.flatten(),
-		canonical_alias,
-		guest_can_join,
-		join_rule: join_rule.unwrap_or_default(),
-		name,
-		num_joined_members: num_joined_members
-			.map(TryInto::try_into)
-			.map(Result::ok)
-			.flat_ok()
-			.unwrap_or_else(|| uint!(0)),
-		room_id,
-		room_type,
-		topic,
-		world_readable,
-	}
-}
-
 fn check_server_banned(services: &Services, server: Option<&ServerName>) -> Result {
 	let Some(server) = server else {
 		return Ok(());
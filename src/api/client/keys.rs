@@ -1,7 +1,7 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 use axum::extract::State;
-use futures::{StreamExt, stream::FuturesUnordered};
+use futures::{StreamExt, future::join, stream::FuturesUnordered};
 use ruma::{
 	CanonicalJsonObject, CanonicalJsonValue, OneTimeKeyAlgorithm, OwnedDeviceId, OwnedUserId,
 	UserId,
@@ -65,6 +65,28 @@ pub(crate) async fn upload_keys_route(
 			.await?;
 	}
 
+	// Each fallback key replaces the one the device had for its algorithm
+	for (key_id, fallback_key) in &body.fallback_keys {
+		if fallback_key
+			.deserialize()
+			.inspect_err(|e| {
+				debug_warn!(
+					?key_id,
+					?fallback_key,
+					"Invalid fallback key JSON submitted by client, skipping: {e}"
+				);
+			})
+			.is_err()
+		{
+			continue;
+		}
+
+		services
+			.users
+			.add_fallback_key(sender_user, sender_device, key_id, fallback_key)
+			.await?;
+	}
+
 	if let Some(device_keys) = &body.device_keys {
 		let deser_device_keys = device_keys.deserialize().map_err(|e| {
 			err!(Request(BadJson(debug_warn!(
@@ -441,14 +463,21 @@ pub(crate) async fn get_keys_helper<F>(
 		let user_id: &UserId = user_id;
 
 		if !services.globals.user_is_local(user_id) {
-			get_over_federation
-				.entry(user_id.server_name())
-				.or_insert_with(Vec::new)
-				.push((user_id, device_ids));
-			continue;
-		}
+			let Some(devices) = services.users.remote_device_keys(user_id).await else {
+				get_over_federation
+					.entry(user_id.server_name())
+					.or_insert_with(Vec::new)
+					.push((user_id, device_ids));
+				continue;
+			};
+
+			let container = devices
+				.into_iter()
+				.filter(|(device_id, _)| device_ids.is_empty() || device_ids.contains(device_id))
+				.collect();
 
-		if device_ids.is_empty() {
+			device_keys.insert(user_id.to_owned(), container);
+		} else if device_ids.is_empty() {
 			let mut container = BTreeMap::new();
 			let mut devices = services.users.all_device_ids(user_id).boxed();
 
@@ -525,6 +554,15 @@ pub(crate) async fn get_keys_helper<F>(
 
 	let mut failures = BTreeMap::new();
 
+	// The missing device lists are cached alongside the query for the next one
+	let cache_misses: Vec<&UserId> = get_over_federation
+		.values()
+		.flatten()
+		.map(|&(user_id, _)| user_id)
+		.collect();
+
+	let caching = services.users.cache_remote_devices(cache_misses);
+
 	let mut futures: FuturesUnordered<_> = get_over_federation
 		.into_iter()
 		.map(async |(server, vec)| {
@@ -545,45 +583,56 @@ pub(crate) async fn get_keys_helper<F>(
 		})
 		.collect();
 
-	while let Some((server, response)) = futures.next().await {
-		match response {
-			| Ok(response) => {
-				for (user, master_key) in response.master_keys {
-					let (master_key_id, mut master_key) = parse_master_key(&user, &master_key)?;
-
-					if let Ok(our_master_key) = services
-						.users
-						.get_key(&master_key_id, sender_user, &user, &allowed_signatures)
-						.await
-					{
-						let (_, mut our_master_key) = parse_master_key(&user, &our_master_key)?;
-						master_key
-							.signatures
-							.append(&mut our_master_key.signatures);
-					}
-					let json = serde_json::to_value(master_key).expect("to_value always works");
-					let raw = serde_json::from_value(json).expect("Raw::from_value always works");
-					services
-						.users
-						.add_cross_signing_keys(
-							&user, &raw, &None, &None,
-							false, /* Dont notify. A notification would trigger another key
-							       * request resulting in an endless loop */
-						)
-						.await?;
-					if let Some(raw) = raw {
-						master_keys.insert(user.clone(), raw);
+	let receiving = async {
+		while let Some((server, response)) = futures.next().await {
+			match response {
+				| Ok(response) => {
+					for (user, master_key) in response.master_keys {
+						let (master_key_id, mut master_key) =
+							parse_master_key(&user, &master_key)?;
+
+						if let Ok(our_master_key) = services
+							.users
+							.get_key(&master_key_id, sender_user, &user, &allowed_signatures)
+							.await
+						{
+							let (_, mut our_master_key) =
+								parse_master_key(&user, &our_master_key)?;
+							master_key
+								.signatures
+								.append(&mut our_master_key.signatures);
+						}
+						let json =
+							serde_json::to_value(master_key).expect("to_value always works");
+						let raw =
+							serde_json::from_value(json).expect("Raw::from_value always works");
+						services
+							.users
+							.add_cross_signing_keys(
+								&user, &raw, &None, &None,
+								false, /* Dont notify. A notification would trigger another key
+								       * request resulting in an endless loop */
+							)
+							.await?;
+						if let Some(raw) = raw {
+							master_keys.insert(user.clone(), raw);
+						}
 					}
-				}
 
-				self_signing_keys.extend(response.self_signing_keys);
-				device_keys.extend(response.device_keys);
-			},
-			| _ => {
-				failures.insert(server.to_string(), json!({}));
-			},
+					self_signing_keys.extend(response.self_signing_keys);
+					device_keys.extend(response.device_keys);
+				},
+				| _ => {
+					failures.insert(server.to_string(), json!({}));
+				},
+			}
 		}
-	}
+
+		Ok::<_, Error>(())
+	};
+
+	let ((), received) = join(caching, receiving).await;
+	received?;
 
 	Ok(get_keys::v3::Response {
 		failures,
@@ -641,7 +690,7 @@ pub(crate) async fn claim_keys_helper(
 		for (device_id, key_algorithm) in map {
 			if let Ok(one_time_keys) = services
 				.users
-				.take_one_time_key(user_id, device_id, key_algorithm)
+				.claim_one_time_key(user_id, device_id, key_algorithm)
 				.await
 			{
 				let mut c = BTreeMap::new();
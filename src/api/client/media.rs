@@ -4,13 +4,13 @@
 use axum_client_ip::InsecureClientIp;
 use reqwest::Url;
 use ruma::{
-	Mxc, UserId,
+	MilliSecondsSinceUnixEpoch, Mxc, UserId,
 	api::client::{
 		authenticated_media::{
 			get_content, get_content_as_filename, get_content_thumbnail, get_media_config,
 			get_media_preview,
 		},
-		media::create_content,
+		media::{create_content, create_content_async, create_mxc_uri},
 	},
 };
 use tuwunel_core::{
@@ -20,9 +20,10 @@
 use tuwunel_service::{
 	Services,
 	media::{CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN, Dim, FileMeta, MXC_LENGTH},
+	ratelimit::Action,
 };
 
-use crate::Ruma;
+use crate::{ClientAddr, Ruma, client::utils::rate_limit};
 
 /// # `GET /_matrix/client/v1/media/config`
 pub(crate) async fn get_media_config_route(
@@ -49,8 +50,11 @@ pub(crate) async fn get_media_config_route(
 pub(crate) async fn create_content_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<create_content::v3::Request>,
 ) -> Result<create_content::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Media).await?;
+
 	let user = body.sender_user();
 
 	let filename = body.filename.as_deref();
@@ -61,6 +65,11 @@ pub(crate) async fn create_content_route(
 		media_id: &utils::random_string(MXC_LENGTH),
 	};
 
+	services
+		.moderation
+		.check_media_for_spam(user, content_type, &body.file)
+		.await?;
+
 	services
 		.media
 		.create(mxc, Some(user), Some(&content_disposition), content_type, &body.file)
@@ -80,6 +89,79 @@ pub(crate) async fn create_content_route(
 	})
 }
 
+/// # `POST /_matrix/media/v1/create`
+///
+/// Reserve a media ID for the user to upload its content to later.
+#[tracing::instrument(
+	name = "media_create",
+	level = "debug",
+	skip_all,
+	fields(%client),
+)]
+pub(crate) async fn create_mxc_uri_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
+	body: Ruma<create_mxc_uri::v1::Request>,
+) -> Result<create_mxc_uri::v1::Response> {
+	rate_limit(&services, &body, addr, Action::Media).await?;
+
+	let (content_uri, expires_at) = services
+		.media
+		.create_pending(body.sender_user())
+		.await?;
+
+	Ok(create_mxc_uri::v1::Response {
+		content_uri,
+		unused_expires_at: Some(MilliSecondsSinceUnixEpoch(
+			expires_at.try_into().unwrap_or_default(),
+		)),
+	})
+}
+
+/// # `PUT /_matrix/media/v3/upload/{serverName}/{mediaId}`
+///
+/// Upload the content of a media ID reserved by the user.
+#[tracing::instrument(
+	name = "media_upload_async",
+	level = "debug",
+	skip_all,
+	fields(%client),
+)]
+pub(crate) async fn create_content_async_route(
+	State(services): State<crate::State>,
+	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
+	body: Ruma<create_content_async::v3::Request>,
+) -> Result<create_content_async::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Media).await?;
+
+	let user = body.sender_user();
+	if !services.globals.server_is_ours(&body.server_name) {
+		return Err!(Request(NotFound("Unknown media ID.")));
+	}
+
+	let filename = body.filename.as_deref();
+	let content_type = body.content_type.as_deref();
+	let content_disposition = make_content_disposition(None, content_type, filename);
+	let ref mxc = Mxc {
+		server_name: &body.server_name,
+		media_id: &body.media_id,
+	};
+
+	services
+		.moderation
+		.check_media_for_spam(user, content_type, &body.file)
+		.await?;
+
+	services
+		.media
+		.upload_pending(mxc, user, Some(&content_disposition), content_type, &body.file)
+		.await?;
+
+	Ok(create_content_async::v3::Response::new())
+}
+
 /// # `GET /_matrix/client/v1/media/thumbnail/{serverName}/{mediaId}`
 ///
 /// Load media thumbnail from our server or over federation.
@@ -201,8 +283,11 @@ pub(crate) async fn get_content_as_filename_route(
 pub(crate) async fn get_media_preview_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<get_media_preview::v1::Request>,
 ) -> Result<get_media_preview::v1::Response> {
+	rate_limit(&services, &body, addr, Action::Media).await?;
+
 	let sender_user = body.sender_user();
 
 	let url = &body.url;
@@ -301,7 +386,16 @@ async fn fetch_thumbnail_meta(
 	}
 
 	if services.globals.server_is_ours(mxc.server_name) {
-		return Err!(Request(NotFound("Local thumbnail not found.")));
+		services
+			.media
+			.wait_pending(mxc, timeout_ms)
+			.await?;
+
+		return services
+			.media
+			.get_thumbnail(mxc, dim)
+			.await?
+			.ok_or_else(|| err!(Request(NotFound("Local thumbnail not found."))));
 	}
 
 	services
@@ -321,7 +415,16 @@ async fn fetch_file_meta(
 	}
 
 	if services.globals.server_is_ours(mxc.server_name) {
-		return Err!(Request(NotFound("Local media not found.")));
+		services
+			.media
+			.wait_pending(mxc, timeout_ms)
+			.await?;
+
+		return services
+			.media
+			.get(mxc)
+			.await?
+			.ok_or_else(|| err!(Request(NotFound("Local media not found."))));
 	}
 
 	services
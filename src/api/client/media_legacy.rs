@@ -14,9 +14,15 @@
 	Err, Result, err,
 	utils::{content_disposition::make_content_disposition, math::ruma_from_usize},
 };
-use tuwunel_service::media::{CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN, Dim, FileMeta};
+use tuwunel_service::{
+	media::{CACHE_CONTROL_IMMUTABLE, CORP_CROSS_ORIGIN, Dim, FileMeta},
+	ratelimit::Action,
+};
 
-use crate::{Ruma, RumaResponse, client::create_content_route};
+use crate::{
+	ClientAddr, Ruma, RumaResponse,
+	client::{create_content_route, utils::rate_limit},
+};
 
 /// # `GET /_matrix/media/v3/config`
 ///
@@ -53,8 +59,11 @@ pub(crate) async fn get_media_config_legacy_legacy_route(
 pub(crate) async fn get_media_preview_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<get_media_preview::v3::Request>,
 ) -> Result<get_media_preview::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Media).await?;
+
 	let sender_user = body.sender_user();
 
 	let url = &body.url;
@@ -99,9 +108,10 @@ pub(crate) async fn get_media_preview_legacy_route(
 pub(crate) async fn get_media_preview_legacy_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<get_media_preview::v3::Request>,
 ) -> Result<RumaResponse<get_media_preview::v3::Response>> {
-	get_media_preview_legacy_route(State(services), InsecureClientIp(client), body)
+	get_media_preview_legacy_route(State(services), InsecureClientIp(client), addr, body)
 		.await
 		.map(RumaResponse)
 }
@@ -119,9 +129,10 @@ pub(crate) async fn get_media_preview_legacy_legacy_route(
 pub(crate) async fn create_content_legacy_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<create_content::v3::Request>,
 ) -> Result<RumaResponse<create_content::v3::Response>> {
-	create_content_route(State(services), InsecureClientIp(client), body)
+	create_content_route(State(services), InsecureClientIp(client), addr, body)
 		.await
 		.map(RumaResponse)
 }
@@ -57,6 +57,11 @@ pub(crate) async fn invite_user_route(
 		}
 	}
 
+	services
+		.state_accessor
+		.user_can_invite(room_id, sender_user, user_id)
+		.await?;
+
 	if recipient_ignored_by_sender {
 		// silently drop the invite to the recipient if they've been ignored by the
 		// sender, pretend it worked
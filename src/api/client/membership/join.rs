@@ -6,9 +6,16 @@
 	api::client::membership::{join_room_by_id, join_room_by_id_or_alias},
 };
 use tuwunel_core::Result;
+use tuwunel_service::ratelimit::Action;
 
 use super::banned_room_check;
-use crate::{Ruma, client::membership::get_join_params};
+use crate::{
+	ClientAddr, Ruma,
+	client::{
+		membership::get_join_params,
+		utils::{guest_access_check, rate_limit},
+	},
+};
 
 /// # `POST /_matrix/client/r0/rooms/{roomId}/join`
 ///
@@ -22,8 +29,11 @@
 pub(crate) async fn join_room_by_id_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<join_room_by_id::v3::Request>,
 ) -> Result<join_room_by_id::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Joins).await?;
+
 	let sender_user = body.sender_user();
 
 	let room_id: &RoomId = &body.room_id;
@@ -31,8 +41,26 @@ pub(crate) async fn join_room_by_id_route(
 	banned_room_check(&services, sender_user, Some(room_id), room_id.server_name(), client)
 		.await?;
 
-	let (room_id, servers) =
-		get_join_params(&services, sender_user, <&RoomOrAliasId>::from(room_id), &[]).await?;
+	// Rooms joinable locally skip resolving servers to join through; the server
+	// of the room ID remains for when the local join fails.
+	let (room_id, servers) = if services
+		.membership
+		.can_join_locally(sender_user, room_id)
+		.await
+	{
+		(
+			room_id.to_owned(),
+			room_id
+				.server_name()
+				.map(ToOwned::to_owned)
+				.into_iter()
+				.collect(),
+		)
+	} else {
+		get_join_params(&services, sender_user, <&RoomOrAliasId>::from(room_id), &[]).await?
+	};
+
+	guest_access_check(&services, sender_user, &room_id).await?;
 
 	let state_lock = services.state.mutex.lock(&room_id).await;
 
@@ -67,17 +95,37 @@ pub(crate) async fn join_room_by_id_route(
 pub(crate) async fn join_room_by_id_or_alias_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<join_room_by_id_or_alias::v3::Request>,
 ) -> Result<join_room_by_id_or_alias::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Joins).await?;
+
 	let sender_user = body.sender_user();
 	let appservice_info = &body.appservice_info;
 
-	let (room_id, servers) =
-		get_join_params(&services, sender_user, &body.room_id_or_alias, &body.via).await?;
+	// Rooms joinable locally skip resolving servers to join through; the via
+	// servers remain for when the local join fails.
+	let local_room_id = match <&RoomId>::try_from(&*body.room_id_or_alias) {
+		| Ok(room_id)
+			if services
+				.membership
+				.can_join_locally(sender_user, room_id)
+				.await =>
+			Some(room_id),
+		| _ => None,
+	};
+
+	let (room_id, servers) = match local_room_id {
+		| Some(room_id) => (room_id.to_owned(), body.via.clone()),
+		| None =>
+			get_join_params(&services, sender_user, &body.room_id_or_alias, &body.via).await?,
+	};
 
 	banned_room_check(&services, sender_user, Some(&room_id), room_id.server_name(), client)
 		.await?;
 
+	guest_access_check(&services, sender_user, &room_id).await?;
+
 	let state_lock = services.state.mutex.lock(&room_id).await;
 
 	services
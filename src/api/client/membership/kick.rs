@@ -20,6 +20,11 @@ pub(crate) async fn kick_user_route(
 
 	let state_lock = services.state.mutex.lock(&body.room_id).await;
 
+	services
+		.state_accessor
+		.user_can_kick(&body.room_id, sender_user, &body.user_id)
+		.await?;
+
 	services
 		.membership
 		.kick(&body.room_id, &body.user_id, body.reason.as_ref(), sender_user, &state_lock)
@@ -52,6 +52,10 @@ pub(crate) async fn knock_room_route(
 	let sender_user = body.sender_user();
 	let body = &body.body;
 
+	if services.users.is_guest(sender_user).await {
+		return Err!(Request(GuestAccessForbidden("Guests cannot knock on rooms.")));
+	}
+
 	let (room_id, servers) =
 		get_join_params(&services, sender_user, &body.room_id_or_alias, &body.via).await?;
 
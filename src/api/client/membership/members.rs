@@ -90,22 +90,10 @@ pub(crate) async fn joined_members_route(
 	Ok(joined_members::v3::Response {
 		joined: services
 			.state_accessor
-			.room_state_full(&body.room_id)
+			.room_members_with_profiles(&body.room_id)
 			.ready_filter_map(Result::ok)
-			.ready_filter(|((ty, _), _)| *ty == StateEventType::RoomMember)
-			.map(at!(1))
-			.ready_filter_map(|pdu| {
-				membership_filter(pdu, Some(&MembershipEventFilter::Join), None)
-			})
-			.ready_filter_map(|pdu| {
-				let content = pdu.get_content::<RoomMemberEventContent>().ok()?;
-				let sender = pdu.sender().to_owned();
-				let member = RoomMember {
-					display_name: content.displayname,
-					avatar_url: content.avatar_url,
-				};
-
-				Some((sender, member))
+			.map(|(user_id, display_name, avatar_url)| {
+				(user_id, RoomMember { display_name, avatar_url })
 			})
 			.collect()
 			.boxed()
@@ -134,7 +134,7 @@ async fn maybe_deactivate(services: &Services, user_id: &UserId, client_ip: IpAd
 
 		services
 			.deactivate
-			.full_deactivate(user_id)
+			.full_deactivate(user_id, false)
 			.boxed()
 			.await?;
 	}
@@ -179,8 +179,6 @@ async fn get_join_params(
 		services
 			.state_cache
 			.servers_invite_via(&room_id)
-			.map(ToOwned::to_owned)
-			.collect::<Vec<_>>()
 			.await,
 	);
 
@@ -14,6 +14,11 @@ pub(crate) async fn unban_user_route(
 ) -> Result<unban_user::v3::Response> {
 	let state_lock = services.state.mutex.lock(&body.room_id).await;
 
+	services
+		.state_accessor
+		.user_can_unban(&body.room_id, body.sender_user(), &body.user_id)
+		.await?;
+
 	services
 		.membership
 		.unban(
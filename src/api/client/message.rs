@@ -1,5 +1,5 @@
 use axum::extract::State;
-use futures::{FutureExt, StreamExt, TryFutureExt, future::OptionFuture, pin_mut};
+use futures::{FutureExt, Stream, StreamExt, TryFutureExt, future::OptionFuture, pin_mut};
 use ruma::{
 	RoomId, UserId,
 	api::{
@@ -24,14 +24,16 @@
 };
 use tuwunel_service::{
 	Services,
+	ratelimit::Action,
 	rooms::{
 		lazy_loading,
 		lazy_loading::{Options, Witness},
+		short::ShortStateHash,
 		timeline::PdusIterItem,
 	},
 };
 
-use crate::Ruma;
+use crate::{ClientAddr, Ruma, client::utils::rate_limit};
 
 /// list of safe and common non-state events to ignore if the user is ignored
 const IGNORED_MESSAGE_TYPES: &[TimelineEventType] = &[
@@ -65,9 +67,12 @@
 ///   where the user was joined, depending on `history_visibility`)
 pub(crate) async fn get_message_events_route(
 	State(services): State<crate::State>,
+	addr: ClientAddr,
 	body: Ruma<get_message_events::v3::Request>,
 ) -> Result<get_message_events::v3::Response> {
 	debug_assert!(IGNORED_MESSAGE_TYPES.is_sorted(), "IGNORED_MESSAGE_TYPES is not sorted");
+	rate_limit(&services, &body, addr, Action::Messages).await?;
+
 	let sender_user = body.sender_user();
 	let sender_device = body.sender_device.as_deref();
 	let room_id = &body.room_id;
@@ -77,15 +82,32 @@ pub(crate) async fn get_message_events_route(
 		return Err!(Request(Forbidden("Room does not exist to this server")));
 	}
 
-	let from: PduCount = body
-		.from
-		.as_deref()
-		.map(str::parse)
-		.transpose()?
-		.unwrap_or_else(|| match body.dir {
-			| Direction::Forward => PduCount::min(),
-			| Direction::Backward => PduCount::max(),
+	// History before the point it was purged at is gone; tokens into it paginate
+	// to an empty page rather than to the events kept there, and paginating
+	// forward from the start begins with the first event of the history kept.
+	let kept_from = services
+		.timeline
+		.purged_before(room_id)
+		.await
+		.map(|purged| purged.saturating_sub(1));
+
+	let from: Option<PduCount> = body.from.as_deref().map(str::parse).transpose()?;
+
+	if let Some(from) = from
+		&& kept_from.is_some_and(|kept_from| from < kept_from)
+	{
+		return Ok(get_message_events::v3::Response {
+			start: from.to_string(),
+			end: None,
+			chunk: Vec::new(),
+			state: Vec::new(),
 		});
+	}
+
+	let from = from.unwrap_or_else(|| match body.dir {
+		| Direction::Forward => kept_from.unwrap_or_else(PduCount::min),
+		| Direction::Backward => PduCount::max(),
+	});
 
 	let to: Option<PduCount> = body.to.as_deref().map(str::parse).flat_ok();
 
@@ -118,14 +140,19 @@ pub(crate) async fn get_message_events_route(
 			.boxed(),
 	};
 
-	let events: Vec<_> = it
+	let scan_max = limit
+		.saturating_mul(services.config.messages_scan_factor)
+		.max(limit);
+
+	let events = it
 		.ready_take_while(|(count, _)| Some(*count) != to)
-		.ready_filter_map(|item| event_filter(item, filter))
-		.wide_filter_map(|item| ignored_filter(&services, item, sender_user))
-		.wide_filter_map(|item| visibility_filter(&services, item, sender_user))
-		.take(limit)
-		.collect()
-		.await;
+		.ready_take_while(|(count, _)| kept_from.is_none_or(|kept_from| *count > kept_from));
+	let (events, next_token) = paginate(events, limit, scan_max, async |item| {
+		let item = event_filter(item, filter)?;
+		let item = ignored_filter(&services, item, sender_user).await?;
+		visibility_filter(&services, item, sender_user).await
+	})
+	.await;
 
 	let lazy_loading_context = lazy_loading::Context {
 		user_id: sender_user,
@@ -141,18 +168,29 @@ pub(crate) async fn get_message_events_route(
 		.then(|| lazy_loading_witness(&services, &lazy_loading_context, events.iter()))
 		.into();
 
+	let shortstatehash: OptionFuture<_> = events
+		.iter()
+		.max_by_key(|(count, _)| *count)
+		.map(|(_, pdu)| {
+			services
+				.state_accessor
+				.pdu_shortstatehash(pdu.event_id())
+		})
+		.into();
+
+	let shortstatehash = shortstatehash.map(FlatOk::flat_ok).await;
 	let state = witness
 		.map(Option::into_iter)
 		.map(|option| option.flat_map(Witness::into_iter))
 		.map(IterStream::stream)
 		.into_stream()
 		.flatten()
-		.broad_filter_map(async |user_id| get_member_event(&services, room_id, &user_id).await)
+		.broad_filter_map(async |user_id| {
+			get_member_event(&services, room_id, shortstatehash, &user_id).await
+		})
 		.collect()
 		.await;
 
-	let next_token = events.last().map(at!(0));
-
 	let chunk = events
 		.into_iter()
 		.map(at!(1))
@@ -167,6 +205,44 @@ pub(crate) async fn get_message_events_route(
 	})
 }
 
+/// Collects up to `limit` events passing the filter, scanning at most
+/// `scan_max` events so a filter matching few of them does not scan the whole
+/// room. Returns the events with the token the next page starts from: the
+/// last scanned event when the page was cut short by the scan limit, else the
+/// last event returned.
+pub(crate) async fn paginate<S, F, Fut>(
+	events: S,
+	limit: usize,
+	scan_max: usize,
+	filter: F,
+) -> (Vec<PdusIterItem>, Option<PduCount>)
+where
+	S: Stream<Item = PdusIterItem> + Send,
+	F: Fn(PdusIterItem) -> Fut + Send,
+	Fut: Future<Output = Option<PdusIterItem>> + Send,
+{
+	let mut scanned = 0_usize;
+	let mut last_scanned = None;
+	let events: Vec<_> = events
+		.take(scan_max)
+		.inspect(|(count, _)| {
+			scanned = scanned.saturating_add(1);
+			last_scanned = Some(*count);
+		})
+		.wide_filter_map(filter)
+		.take(limit)
+		.collect()
+		.await;
+
+	let next_token = if events.len() < limit && scanned >= scan_max {
+		last_scanned
+	} else {
+		events.last().map(at!(0))
+	};
+
+	(events, next_token)
+}
+
 pub(crate) async fn lazy_loading_witness<'a, I>(
 	services: &Services,
 	lazy_loading_context: &lazy_loading::Context<'_>,
@@ -196,17 +272,12 @@ pub(crate) async fn lazy_loading_witness<'a, I>(
 	);
 
 	pin_mut!(receipts);
-	let witness: Witness = events
-		.stream()
-		.map(ref_at!(1))
-		.map(Event::sender)
-		.map(ToOwned::to_owned)
-		.chain(
-			receipts
-				.ready_take_while(|(_, c, _)| *c <= newest.into_unsigned())
-				.map(|(user_id, ..)| user_id.to_owned()),
-		)
-		.collect()
+	let mut witness = chunk_senders(events);
+	receipts
+		.ready_take_while(|(_, c, _)| *c <= newest.into_unsigned())
+		.ready_for_each(|(user_id, ..)| {
+			witness.insert(user_id.to_owned());
+		})
 		.await;
 
 	services
@@ -215,17 +286,42 @@ pub(crate) async fn lazy_loading_witness<'a, I>(
 		.await
 }
 
+/// The distinct senders of the events.
+pub(crate) fn chunk_senders<'a, I>(events: I) -> Witness
+where
+	I: Iterator<Item = &'a PdusIterItem>,
+{
+	events
+		.map(ref_at!(1))
+		.map(Event::sender)
+		.map(ToOwned::to_owned)
+		.collect()
+}
+
+/// The member event of the user at the given state, or in the current state
+/// of the room when the state is not known.
 async fn get_member_event(
 	services: &Services,
 	room_id: &RoomId,
+	shortstatehash: Option<ShortStateHash>,
 	user_id: &UserId,
 ) -> Option<Raw<AnyStateEvent>> {
-	services
-		.state_accessor
-		.room_state_get(room_id, &StateEventType::RoomMember, user_id.as_str())
-		.map_ok(Event::into_format)
-		.await
-		.ok()
+	let event_type = &StateEventType::RoomMember;
+	let state_key = user_id.as_str();
+	match shortstatehash {
+		| Some(shortstatehash) => services
+			.state_accessor
+			.state_get(shortstatehash, event_type, state_key)
+			.map_ok(Event::into_format)
+			.await
+			.ok(),
+		| None => services
+			.state_accessor
+			.room_state_get(room_id, event_type, state_key)
+			.map_ok(Event::into_format)
+			.await
+			.ok(),
+	}
 }
 
 #[inline]
@@ -307,3 +403,114 @@ fn _is_sorted() {
 		"IGNORED_MESSAGE_TYPES must be sorted by the developer"
 	);
 }
+
+#[cfg(test)]
+mod tests {
+	use futures::stream;
+	use ruma::{
+		api::client::filter::{RoomEventFilter, UrlFilter},
+		events::TimelineEventType,
+		user_id,
+	};
+	use serde_json::{json, value::to_raw_value};
+	use tuwunel_core::matrix::pdu::{PduCount, PduEvent};
+
+	use super::{chunk_senders, event_filter, paginate};
+
+	fn message(count: u64, sender: &str) -> (PduCount, PduEvent) {
+		let content = json!({ "msgtype": "m.text", "body": "hi" });
+		let pdu = PduEvent::fake(
+			"$event:example.com",
+			sender,
+			TimelineEventType::RoomMessage,
+			&content,
+		);
+
+		(PduCount::Normal(count), pdu)
+	}
+
+	#[test]
+	fn chunk_senders_distinct() {
+		let events = [
+			message(1, "@alice:example.com"),
+			message(2, "@bob:example.com"),
+			message(3, "@alice:example.com"),
+			message(4, "@carol:example.com"),
+		];
+
+		let senders = chunk_senders(events.iter());
+		assert_eq!(senders.len(), 3);
+		assert!(senders.contains(user_id!("@alice:example.com")));
+		assert!(senders.contains(user_id!("@bob:example.com")));
+		assert!(senders.contains(user_id!("@carol:example.com")));
+	}
+
+	/// The timeline of a room paginated backwards from its newest event, where
+	/// only every 10th event has a URL.
+	fn timeline(len: u64) -> Vec<(PduCount, PduEvent)> {
+		(1..=len)
+			.rev()
+			.map(|count| {
+				let (count, mut pdu) = message(count, "@alice:example.com");
+				if count.into_unsigned() % 10 == 0 {
+					let content =
+						json!({ "msgtype": "m.image", "body": "a", "url": "mxc://a/b" });
+					pdu.content = to_raw_value(&content).unwrap();
+				}
+
+				(count, pdu)
+			})
+			.collect()
+	}
+
+	fn url_filter() -> RoomEventFilter {
+		let mut filter = RoomEventFilter::default();
+		filter.url_filter = Some(UrlFilter::EventsWithUrl);
+		filter
+	}
+
+	#[tokio::test]
+	async fn paginate_stops_at_scan_limit() {
+		let filter = url_filter();
+		let (events, next_token) = paginate(stream::iter(timeline(1000)), 10, 50, async |item| {
+			event_filter(item, &filter)
+		})
+		.await;
+
+		let counts: Vec<_> = events
+			.iter()
+			.map(|(count, _)| count.into_unsigned())
+			.collect();
+		assert_eq!(counts, [1000, 990, 980, 970, 960], "only events with a URL are returned");
+		assert_eq!(
+			next_token,
+			Some(PduCount::Normal(951)),
+			"the next page starts after the last scanned event, not the last match"
+		);
+	}
+
+	#[tokio::test]
+	async fn paginate_fills_page() {
+		let filter = url_filter();
+		let (events, next_token) =
+			paginate(stream::iter(timeline(1000)), 10, 500, async |item| {
+				event_filter(item, &filter)
+			})
+			.await;
+
+		assert_eq!(events.len(), 10, "the page is filled within the scan limit");
+		assert_eq!(
+			next_token,
+			Some(PduCount::Normal(910)),
+			"the next page starts after the last event returned"
+		);
+
+		let (events, next_token) = paginate(stream::iter(timeline(35)), 10, 500, async |item| {
+			event_filter(item, &filter)
+		})
+		.await;
+
+		assert_eq!(events.len(), 3, "all events with a URL are returned");
+		assert_eq!(next_token, Some(PduCount::Normal(10)), "the timeline ended");
+	}
+}
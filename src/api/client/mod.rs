@@ -34,6 +34,7 @@
 pub(super) mod threads;
 pub(super) mod to_device;
 pub(super) mod typing;
+pub(super) mod uiaa;
 pub(super) mod unstable;
 pub(super) mod unversioned;
 pub(super) mod user_directory;
@@ -78,6 +79,7 @@
 pub(super) use threads::*;
 pub(super) use to_device::*;
 pub(super) use typing::*;
+pub(super) use uiaa::*;
 pub(super) use unstable::*;
 pub(super) use unversioned::*;
 pub(super) use user_directory::*;
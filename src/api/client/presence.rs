@@ -21,6 +21,10 @@ pub(crate) async fn set_presence_route(
 		return Err!(Request(InvalidParam("Not allowed to set presence of other users")));
 	}
 
+	services
+		.presence
+		.check_update(&body.presence, body.status_msg.as_deref())?;
+
 	services
 		.presence
 		.set_presence(body.sender_user(), &body.presence, None, None, body.status_msg.clone())
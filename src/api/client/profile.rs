@@ -7,15 +7,14 @@
 };
 use ruma::{
 	OwnedRoomId,
-	api::{
-		client::profile::{
-			get_avatar_url, get_display_name, get_profile, set_avatar_url, set_display_name,
-		},
-		federation,
+	api::client::profile::{
+		ProfileFieldName, get_avatar_url, get_display_name, get_profile, set_avatar_url,
+		set_display_name,
 	},
 	presence::PresenceState,
 };
 use tuwunel_core::{Err, Result, utils::future::TryExtExt};
+use tuwunel_service::Services;
 
 use crate::Ruma;
 
@@ -34,6 +33,12 @@ pub(crate) async fn set_displayname_route(
 		return Err!(Request(Forbidden("You cannot update the profile of another user")));
 	}
 
+	check_profile_field_allowed(
+		&services,
+		&ProfileFieldName::DisplayName,
+		body.appservice_info.is_some(),
+	)?;
+
 	let all_joined_rooms: Vec<OwnedRoomId> = services
 		.state_cache
 		.rooms_joined(&body.user_id)
@@ -57,6 +62,29 @@ pub(crate) async fn set_displayname_route(
 	Ok(set_display_name::v3::Response {})
 }
 
+/// Refuses changes of the displayname or avatar which the config disallows,
+/// unless made by an appservice.
+pub(crate) fn check_profile_field_allowed(
+	services: &Services,
+	field: &ProfileFieldName,
+	appservice: bool,
+) -> Result {
+	if !appservice && !profile_field_allowed(services, field) {
+		let field = field.as_str();
+		return Err!(Request(Forbidden("Changing your {field} is not allowed on this server.")));
+	}
+
+	Ok(())
+}
+
+pub(crate) fn profile_field_allowed(services: &Services, field: &ProfileFieldName) -> bool {
+	match field {
+		| ProfileFieldName::DisplayName => services.config.allow_set_displayname,
+		| ProfileFieldName::AvatarUrl => services.config.allow_set_avatar_url,
+		| _ => true,
+	}
+}
+
 /// # `GET /_matrix/client/v3/profile/{userId}/displayname`
 ///
 /// Returns the displayname of the user.
@@ -68,36 +96,11 @@ pub(crate) async fn get_displayname_route(
 	body: Ruma<get_display_name::v3::Request>,
 ) -> Result<get_display_name::v3::Response> {
 	if !services.globals.user_is_local(&body.user_id) {
-		// Create and update our local copy of the user
-		if let Ok(response) = services
-			.sending
-			.send_federation_request(
-				body.user_id.server_name(),
-				federation::query::get_profile_information::v1::Request {
-					user_id: body.user_id.clone(),
-					field: None, // we want the full user's profile to update locally too
-				},
-			)
-			.await
-		{
-			if !services.users.exists(&body.user_id).await {
-				services
-					.users
-					.create(&body.user_id, None, None)
-					.await?;
-			}
-
-			services
-				.users
-				.set_displayname(&body.user_id, response.displayname.clone());
-			services
-				.users
-				.set_avatar_url(&body.user_id, response.avatar_url.clone());
-			services
-				.users
-				.set_blurhash(&body.user_id, response.blurhash.clone());
-
-			return Ok(get_display_name::v3::Response { displayname: response.displayname });
+		// Fetches the profile, updating our local copy of the user
+		if let Ok(response) = services.users.remote_profile(&body.user_id).await {
+			return Ok(get_display_name::v3::Response {
+				displayname: response.displayname.clone(),
+			});
 		}
 	}
 
@@ -131,6 +134,12 @@ pub(crate) async fn set_avatar_url_route(
 		return Err!(Request(Forbidden("You cannot update the profile of another user")));
 	}
 
+	check_profile_field_allowed(
+		&services,
+		&ProfileFieldName::AvatarUrl,
+		body.appservice_info.is_some(),
+	)?;
+
 	let all_joined_rooms: Vec<OwnedRoomId> = services
 		.state_cache
 		.rooms_joined(&body.user_id)
@@ -171,38 +180,11 @@ pub(crate) async fn get_avatar_url_route(
 	body: Ruma<get_avatar_url::v3::Request>,
 ) -> Result<get_avatar_url::v3::Response> {
 	if !services.globals.user_is_local(&body.user_id) {
-		// Create and update our local copy of the user
-		if let Ok(response) = services
-			.sending
-			.send_federation_request(
-				body.user_id.server_name(),
-				federation::query::get_profile_information::v1::Request {
-					user_id: body.user_id.clone(),
-					field: None, // we want the full user's profile to update locally as well
-				},
-			)
-			.await
-		{
-			if !services.users.exists(&body.user_id).await {
-				services
-					.users
-					.create(&body.user_id, None, None)
-					.await?;
-			}
-
-			services
-				.users
-				.set_displayname(&body.user_id, response.displayname.clone());
-			services
-				.users
-				.set_avatar_url(&body.user_id, response.avatar_url.clone());
-			services
-				.users
-				.set_blurhash(&body.user_id, response.blurhash.clone());
-
+		// Fetches the profile, updating our local copy of the user
+		if let Ok(response) = services.users.remote_profile(&body.user_id).await {
 			return Ok(get_avatar_url::v3::Response {
-				avatar_url: response.avatar_url,
-				blurhash: response.blurhash,
+				avatar_url: response.avatar_url.clone(),
+				blurhash: response.blurhash.clone(),
 			});
 		}
 	}
@@ -233,58 +215,20 @@ pub(crate) async fn get_profile_route(
 	body: Ruma<get_profile::v3::Request>,
 ) -> Result<get_profile::v3::Response> {
 	if !services.globals.user_is_local(&body.user_id) {
-		// Create and update our local copy of the user
-		if let Ok(response) = services
-			.sending
-			.send_federation_request(
-				body.user_id.server_name(),
-				federation::query::get_profile_information::v1::Request {
-					user_id: body.user_id.clone(),
-					field: None,
-				},
-			)
-			.await
-		{
-			if !services.users.exists(&body.user_id).await {
-				services
-					.users
-					.create(&body.user_id, None, None)
-					.await?;
-			}
-
-			services
-				.users
-				.set_displayname(&body.user_id, response.displayname.clone());
-			services
-				.users
-				.set_avatar_url(&body.user_id, response.avatar_url.clone());
-			services
-				.users
-				.set_blurhash(&body.user_id, response.blurhash.clone());
-			services
-				.users
-				.set_timezone(&body.user_id, response.tz.clone());
-
-			for (profile_key, profile_key_value) in &response.custom_profile_fields {
-				services.users.set_profile_key(
-					&body.user_id,
-					profile_key,
-					Some(profile_key_value.clone()),
-				);
-			}
-
+		// Fetches the profile, updating our local copy of the user
+		if let Ok(response) = services.users.remote_profile(&body.user_id).await {
 			let canonical_fields = [
-				("avatar_url", response.avatar_url.map(Into::into)),
-				("blurhash", response.blurhash),
-				("displayname", response.displayname),
-				("tz", response.tz),
+				("avatar_url", response.avatar_url.clone().map(Into::into)),
+				("blurhash", response.blurhash.clone()),
+				("displayname", response.displayname.clone()),
+				("tz", response.tz.clone()),
 			];
 
 			let response = canonical_fields
 				.into_iter()
 				.filter_map(|(key, val)| val.map(|val| (key, val)))
 				.map(|(key, val)| (key.to_owned(), val.into()))
-				.chain(response.custom_profile_fields.into_iter());
+				.chain(response.custom_profile_fields.clone());
 
 			return Ok(response.collect::<get_profile::v3::Response>());
 		}
@@ -2,14 +2,15 @@
 
 use axum::extract::State;
 use ruma::{
-	MilliSecondsSinceUnixEpoch,
+	EventId, MilliSecondsSinceUnixEpoch, RoomId, UserId,
 	api::client::{read_marker::set_read_marker, receipt::create_receipt},
 	events::{
 		RoomAccountDataEventType,
 		receipt::{ReceiptThread, ReceiptType},
 	},
 };
-use tuwunel_core::{Err, PduCount, Result, err};
+use tuwunel_core::{Err, PduCount, Result};
+use tuwunel_service::Services;
 
 use crate::Ruma;
 
@@ -42,10 +43,17 @@ pub(crate) async fn set_read_marker_route(
 			.await?;
 	}
 
-	if body.private_read_receipt.is_some() || body.read_receipt.is_some() {
+	if let Some(event) = body
+		.read_receipt
+		.as_ref()
+		.or(body.private_read_receipt.as_ref())
+	{
 		services
 			.user
-			.reset_notification_counts(sender_user, &body.room_id);
+			.reset_all_notification_counts(sender_user, &body.room_id)
+			.await;
+
+		recount_notifications(&services, sender_user, &body.room_id, event).await;
 	}
 
 	if let Some(event) = &body.read_receipt {
@@ -78,24 +86,22 @@ pub(crate) async fn set_read_marker_route(
 				},
 			)
 			.await;
-	}
 
-	if let Some(event) = &body.private_read_receipt {
-		let count = services
+		if services
 			.timeline
 			.get_pdu_count(event)
 			.await
-			.map_err(|_| err!(Request(NotFound("Event not found."))))?;
-
-		let PduCount::Normal(count) = count else {
-			return Err!(Request(InvalidParam(
-				"Event is a backfilled PDU and cannot be marked as read."
-			)));
-		};
+			.is_err()
+		{
+			services
+				.read_receipt
+				.pending_receipt_add(&body.room_id, sender_user, event, false)
+				.await;
+		}
+	}
 
-		services
-			.read_receipt
-			.private_read_set(&body.room_id, sender_user, count);
+	if let Some(event) = &body.private_read_receipt {
+		private_read_set(&services, &body.room_id, sender_user, event).await?;
 	}
 
 	Ok(set_read_marker::v3::Response {})
@@ -114,9 +120,24 @@ pub(crate) async fn create_receipt_route(
 		&body.receipt_type,
 		create_receipt::v3::ReceiptType::Read | create_receipt::v3::ReceiptType::ReadPrivate
 	) {
-		services
-			.user
-			.reset_notification_counts(sender_user, &body.room_id);
+		match &body.thread {
+			| ReceiptThread::Thread(thread_root) => services
+				.user
+				.reset_thread_notification_counts(sender_user, &body.room_id, thread_root),
+			| ReceiptThread::Main => {
+				recount_notifications(&services, sender_user, &body.room_id, &body.event_id)
+					.await;
+			},
+			| _ => {
+				services
+					.user
+					.reset_all_notification_counts(sender_user, &body.room_id)
+					.await;
+
+				recount_notifications(&services, sender_user, &body.room_id, &body.event_id)
+					.await;
+			},
+		}
 	}
 
 	match body.receipt_type {
@@ -169,23 +190,21 @@ pub(crate) async fn create_receipt_route(
 					},
 				)
 				.await;
-		},
-		| create_receipt::v3::ReceiptType::ReadPrivate => {
-			let count = services
+
+			if services
 				.timeline
 				.get_pdu_count(&body.event_id)
 				.await
-				.map_err(|_| err!(Request(NotFound("Event not found."))))?;
-
-			let PduCount::Normal(count) = count else {
-				return Err!(Request(InvalidParam(
-					"Event is a backfilled PDU and cannot be marked as read."
-				)));
-			};
-
-			services
-				.read_receipt
-				.private_read_set(&body.room_id, sender_user, count);
+				.is_err()
+			{
+				services
+					.read_receipt
+					.pending_receipt_add(&body.room_id, sender_user, &body.event_id, false)
+					.await;
+			}
+		},
+		| create_receipt::v3::ReceiptType::ReadPrivate => {
+			private_read_set(&services, &body.room_id, sender_user, &body.event_id).await?;
 		},
 		| _ => {
 			return Err!(Request(InvalidParam(warn!(
@@ -197,3 +216,53 @@ pub(crate) async fn create_receipt_route(
 
 	Ok(create_receipt::v3::Response {})
 }
+
+/// Sets the private read marker at the event, or defers it until the event
+/// arrives when we don't have it yet.
+async fn private_read_set(
+	services: &Services,
+	room_id: &RoomId,
+	user_id: &UserId,
+	event_id: &EventId,
+) -> Result {
+	let Ok(count) = services.timeline.get_pdu_count(event_id).await else {
+		services
+			.read_receipt
+			.pending_receipt_add(room_id, user_id, event_id, true)
+			.await;
+
+		return Ok(());
+	};
+
+	let PduCount::Normal(count) = count else {
+		return Err!(Request(InvalidParam(
+			"Event is a backfilled PDU and cannot be marked as read."
+		)));
+	};
+
+	services
+		.read_receipt
+		.private_read_set(room_id, user_id, count);
+
+	Ok(())
+}
+
+/// Recounts the notifications of the main timeline after the event read;
+/// they are all read when the event is not known yet.
+async fn recount_notifications(
+	services: &Services,
+	user_id: &UserId,
+	room_id: &RoomId,
+	event_id: &EventId,
+) {
+	if services
+		.timeline
+		.recount_notifications(user_id, room_id, event_id)
+		.await
+		.is_err()
+	{
+		services
+			.user
+			.reset_notification_counts(user_id, room_id);
+	}
+}
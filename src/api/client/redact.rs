@@ -1,7 +1,5 @@
 use axum::extract::State;
-use ruma::{
-	api::client::redact::redact_event, events::room::redaction::RoomRedactionEventContent,
-};
+use ruma::api::client::redact::redact_event;
 use tuwunel_core::{Result, matrix::pdu::PduBuilder};
 
 use crate::Ruma;
@@ -10,32 +8,44 @@
 ///
 /// Tries to send a redaction event into the room.
 ///
-/// - TODO: Handle txn id
+/// - Is a NOOP if the txn id was already used before and returns the same event
+///   id again
 pub(crate) async fn redact_event_route(
 	State(services): State<crate::State>,
 	body: Ruma<redact_event::v3::Request>,
 ) -> Result<redact_event::v3::Response> {
 	let sender_user = body.sender_user();
+	let sender_device = body.sender_device.as_deref();
 	let body = &body.body;
 
 	let state_lock = services.state.mutex.lock(&body.room_id).await;
 
+	// Check if this is a new transaction id
+	if let Some(event_id) = services
+		.transaction_ids
+		.existing_event_id(sender_user, sender_device, &body.txn_id)
+		.await?
+	{
+		return Ok(redact_event::v3::Response { event_id });
+	}
+
 	let event_id = services
 		.timeline
 		.build_and_append_pdu(
-			PduBuilder {
-				redacts: Some(body.event_id.clone()),
-				..PduBuilder::timeline(&RoomRedactionEventContent {
-					redacts: Some(body.event_id.clone()),
-					reason: body.reason.clone(),
-				})
-			},
+			PduBuilder::redaction(&body.event_id, body.reason.clone()),
 			sender_user,
 			&body.room_id,
 			&state_lock,
 		)
 		.await?;
 
+	services.transaction_ids.add_txnid(
+		sender_user,
+		sender_device,
+		&body.txn_id,
+		event_id.as_bytes(),
+	);
+
 	drop(state_lock);
 
 	Ok(redact_event::v3::Response { event_id })
@@ -5,7 +5,7 @@
 use futures::FutureExt;
 use register::RegistrationKind;
 use ruma::{
-	UserId,
+	CanonicalJsonValue, OwnedUserId, UserId,
 	api::client::{
 		account::{
 			check_registration_token_validity, get_username_availability,
@@ -16,11 +16,13 @@
 	events::GlobalAccountDataEventType,
 	push,
 };
-use tuwunel_core::{Err, Error, Result, debug_info, error, info, is_equal_to, utils, warn};
-use tuwunel_service::users::device::generate_refresh_token;
+use tuwunel_core::{
+	Err, Error, Result, debug_info, error, info, is_equal_to, messages::Message, utils, warn,
+};
+use tuwunel_service::{Services, ratelimit::Action, users::device::generate_refresh_token};
 
 use super::{DEVICE_ID_LENGTH, SESSION_ID_LENGTH};
-use crate::Ruma;
+use crate::{ClientAddr, Ruma, client::utils::rate_limit};
 
 const RANDOM_USER_ID_LENGTH: usize = 10;
 
@@ -133,6 +135,8 @@ pub(crate) async fn get_register_available_route(
 /// - If sender is not appservice: Requires UIAA (but we only use a dummy stage)
 /// - If type is not guest and no username is given: Always fails after UIAA
 ///   check
+/// - If a `guest_access_token` is given: Upgrades that guest to a real account
+///   keeping its user ID
 /// - Creates a new account and populates it with default account data
 /// - If `inhibit_login` is false: Creates a device and returns device id and
 ///   access_token
@@ -141,8 +145,11 @@ pub(crate) async fn get_register_available_route(
 pub(crate) async fn register_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<register::v3::Request>,
 ) -> Result<register::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Registration).await?;
+
 	let is_guest = body.kind == RegistrationKind::Guest;
 	let emergency_mode_enabled = services.config.emergency_password.is_some();
 
@@ -184,7 +191,7 @@ pub(crate) async fn register_route(
 	if is_guest
 		&& (!services.config.allow_guest_registration
 			|| (services.config.allow_registration
-				&& services.globals.registration_token.is_some()))
+				&& services.globals.registration_token().is_some()))
 	{
 		info!(
 			"Guest registration disabled / registration enabled with token configured, \
@@ -209,8 +216,15 @@ pub(crate) async fn register_route(
 		return Err!(Request(Forbidden("Registration is temporarily disabled.")));
 	}
 
-	let user_id = match (body.username.as_ref(), is_guest) {
-		| (Some(username), false) => {
+	let upgraded_guest = if is_guest {
+		None
+	} else {
+		upgraded_guest(&services, &body).await?
+	};
+
+	let user_id = match (body.username.as_ref(), is_guest, upgraded_guest.clone()) {
+		| (_, false, Some(guest_id)) => guest_id,
+		| (Some(username), false, None) => {
 			// workaround for https://github.com/matrix-org/matrix-appservice-irc/issues/1780 due to inactivity of fixing the issue
 			let is_matrix_appservice_irc =
 				body.appservice_info
@@ -309,7 +323,7 @@ pub(crate) async fn register_route(
 
 	// UIAA
 	let mut uiaainfo;
-	let skip_auth = if services.globals.registration_token.is_some() {
+	let skip_auth = if services.globals.registration_token().is_some() {
 		// Registration token required
 		uiaainfo = UiaaInfo {
 			flows: vec![AuthFlow {
@@ -375,43 +389,46 @@ pub(crate) async fn register_route(
 	// Create user
 	services
 		.users
-		.create(&user_id, password, None)
+		.create(&user_id, password, is_guest.then_some("guest"))
 		.await?;
 
-	// Default to pretty displayname
-	let mut displayname = user_id.localpart().to_owned();
-
-	// If `new_user_displayname_suffix` is set, registration will push whatever
-	// content is set to the user's display name with a space before it
-	if !services
-		.globals
-		.new_user_displayname_suffix()
-		.is_empty()
-		&& body.appservice_info.is_none()
-	{
-		write!(displayname, " {}", services.server.config.new_user_displayname_suffix)?;
-	}
+	// An upgraded guest keeps its profile and account data.
+	if upgraded_guest.is_none() {
+		// Default to pretty displayname
+		let mut displayname = user_id.localpart().to_owned();
+
+		// If `new_user_displayname_suffix` is set, registration will push whatever
+		// content is set to the user's display name with a space before it
+		if !services
+			.globals
+			.new_user_displayname_suffix()
+			.is_empty()
+			&& body.appservice_info.is_none()
+		{
+			write!(displayname, " {}", services.server.config.new_user_displayname_suffix)?;
+		}
 
-	services
-		.users
-		.set_displayname(&user_id, Some(displayname.clone()));
+		services
+			.users
+			.set_displayname(&user_id, Some(displayname));
 
-	// Initial account data
-	services
-		.account_data
-		.update(
-			None,
-			&user_id,
-			GlobalAccountDataEventType::PushRules
-				.to_string()
-				.into(),
-			&serde_json::to_value(ruma::events::push_rules::PushRulesEvent {
-				content: ruma::events::push_rules::PushRulesEventContent {
-					global: push::Ruleset::server_default(&user_id),
-				},
-			})?,
-		)
-		.await?;
+		// Initial account data
+		services
+			.account_data
+			.update(
+				None,
+				&user_id,
+				GlobalAccountDataEventType::PushRules
+					.to_string()
+					.into(),
+				&serde_json::to_value(ruma::events::push_rules::PushRulesEvent {
+					content: ruma::events::push_rules::PushRulesEventContent {
+						global: push::Ruleset::server_default(&user_id),
+					},
+				})?,
+			)
+			.await?;
+	}
 
 	if (!is_guest && body.inhibit_login)
 		|| body
@@ -562,7 +579,12 @@ pub(crate) async fn register_route(
 					.join(
 						&user_id,
 						&room_id,
-						Some("Automatically joining this room upon registration".to_owned()),
+						Some(
+							services
+								.users
+								.message(&user_id, Message::AutoJoin, &[])
+								.await,
+						),
 						&[services.globals.server_name().to_owned(), room_server_name.to_owned()],
 						&body.appservice_info,
 						&state_lock,
@@ -605,9 +627,48 @@ pub(crate) async fn check_registration_token_validity(
 	State(services): State<crate::State>,
 	body: Ruma<check_registration_token_validity::v1::Request>,
 ) -> Result<check_registration_token_validity::v1::Response> {
-	let Some(reg_token) = services.globals.registration_token.clone() else {
+	let Some(reg_token) = services.globals.registration_token() else {
 		return Err!(Request(Forbidden("Server does not allow token registration")));
 	};
 
 	Ok(check_registration_token_validity::v1::Response { valid: reg_token == body.token })
 }
+
+/// The guest upgraded to a real account, keeping its user ID, by a
+/// registration carrying the access token of the guest.
+async fn upgraded_guest(
+	services: &Services,
+	body: &Ruma<register::v3::Request>,
+) -> Result<Option<OwnedUserId>> {
+	let Some(token) = body
+		.json_body
+		.as_ref()
+		.and_then(CanonicalJsonValue::as_object)
+		.and_then(|json| json.get("guest_access_token"))
+		.and_then(CanonicalJsonValue::as_str)
+	else {
+		return Ok(None);
+	};
+
+	let Ok((user_id, ..)) = services.users.find_from_token(token).await else {
+		return Err!(Request(Forbidden("Guest access token is not recognised.")));
+	};
+
+	if !services.users.is_guest(&user_id).await {
+		return Err!(Request(Forbidden("Access token is not that of a guest account.")));
+	}
+
+	if body
+		.username
+		.as_deref()
+		.is_some_and(|username| username.to_lowercase() != user_id.localpart())
+	{
+		return Err!(Request(InvalidUsername("Username must be that of the guest account.")));
+	}
+
+	if body.password.is_none() {
+		return Err!(Request(MissingParam("A password is required to upgrade a guest account.")));
+	}
+
+	Ok(Some(user_id))
+}
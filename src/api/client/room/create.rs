@@ -37,6 +37,12 @@
 
 use crate::{Ruma, client::utils::invite_check};
 
+/// Room types of MatrixRTC call rooms, unstable and stable.
+const RTC_ROOM_TYPES: [&str; 2] = ["org.matrix.msc3417.call", "m.call"];
+
+/// Call membership state events of MatrixRTC, unstable and stable.
+const RTC_MEMBER_EVENT_TYPES: [&str; 2] = ["org.matrix.msc3401.call.member", "m.call.member"];
+
 /// # `POST /_matrix/client/v3/createRoom`
 ///
 /// Creates a new room.
@@ -111,6 +117,11 @@ pub(crate) async fn create_room_route(
 				})?,
 	};
 
+	services
+		.metadata
+		.record_creation(&room_id, None)
+		.await;
+
 	// 2. Let the room creator join
 	let sender_user = body.sender_user();
 	services
@@ -167,11 +178,23 @@ pub(crate) async fn create_room_route(
 		}
 	}
 
+	let rtc = services.config.rtc_call_member_power_levels
+		&& body
+			.creation_content
+			.as_ref()
+			.and_then(|c| {
+				c.deserialize_as_unchecked::<CreationContent>()
+					.ok()
+			})
+			.and_then(|c| c.room_type)
+			.is_some_and(|room_type| RTC_ROOM_TYPES.contains(&room_type.as_str()));
+
 	let power_levels_content = default_power_levels_content(
 		&version_rules,
 		body.power_level_content_override.as_ref(),
 		&body.visibility,
 		users,
+		rtc,
 	)?;
 
 	services
@@ -375,7 +398,10 @@ pub(crate) async fn create_room_route(
 	}
 
 	if body.visibility == room::Visibility::Public {
-		services.directory.set_public(&room_id);
+		services
+			.directory
+			.set_public(&room_id, sender_user)
+			.await;
 
 		if services.server.config.admin_room_notices {
 			services
@@ -601,14 +627,16 @@ async fn create_create_event_legacy(
 	Ok((room_id, state_lock))
 }
 
-/// creates the power_levels_content for the PDU builder
+/// creates the power_levels_content for the PDU builder; call rooms let every
+/// member send the call membership events unless overridden
 fn default_power_levels_content(
 	version_rules: &RoomVersionRules,
 	power_level_content_override: Option<&Raw<RoomPowerLevelsEventContent>>,
 	visibility: &room::Visibility,
 	users: BTreeMap<OwnedUserId, Int>,
+	rtc: bool,
 ) -> Result<serde_json::Value> {
-	use serde_json::to_value;
+	use serde_json::{Value, to_value};
 
 	let mut power_levels_content = RoomPowerLevelsEventContent::new(&version_rules.authorization);
 	power_levels_content.users = users;
@@ -646,12 +674,37 @@ fn default_power_levels_content(
 		power_levels_content["events"]["org.matrix.msc3401.call.member"] = to_value(50)?;
 	}
 
-	if let Some(power_level_content_override) = power_level_content_override {
-		let json: JsonObject = serde_json::from_str(power_level_content_override.json().get())
-			.map_err(|e| err!(Request(BadJson("Invalid power_level_content_override: {e:?}"))))?;
+	let power_level_content_override: Option<JsonObject> = power_level_content_override
+		.map(|content| serde_json::from_str(content.json().get()))
+		.transpose()
+		.map_err(|e| err!(Request(BadJson("Invalid power_level_content_override: {e:?}"))))?;
 
+	if let Some(json) = &power_level_content_override {
 		for (key, value) in json {
-			power_levels_content[key] = value;
+			power_levels_content[key.as_str()] = value.clone();
+		}
+	}
+
+	// the call membership events are merged into the events of the override,
+	// which keeps the levels it gives them.
+	if rtc {
+		let overridden = |event_type: &str| {
+			power_level_content_override
+				.as_ref()
+				.and_then(|json| json.get("events"))
+				.and_then(|events| events.get(event_type))
+				.is_some()
+		};
+
+		if let Some(events) = power_levels_content
+			.get_mut("events")
+			.and_then(Value::as_object_mut)
+		{
+			for event_type in RTC_MEMBER_EVENT_TYPES {
+				if !overridden(event_type) {
+					events.insert(event_type.into(), to_value(0)?);
+				}
+			}
 		}
 	}
 
@@ -805,5 +858,73 @@ async fn can_create_room_check(
 		return Err!(Request(Forbidden("Room creation has been disabled.",)));
 	}
 
-	Ok(())
+	services
+		.moderation
+		.user_may_create_room(body.sender_user())
+		.await
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use ruma::{RoomVersionId, api::client::room::Visibility, serde::Raw};
+	use serde_json::{json, value::to_raw_value};
+
+	use super::default_power_levels_content;
+
+	fn power_levels(
+		power_level_content_override: Option<serde_json::Value>,
+		rtc: bool,
+	) -> serde_json::Value {
+		let rules = RoomVersionId::V11
+			.rules()
+			.expect("known room version");
+		let power_level_content_override = power_level_content_override
+			.map(|content| Raw::from_json(to_raw_value(&content).expect("valid json")));
+
+		default_power_levels_content(
+			&rules,
+			power_level_content_override.as_ref(),
+			&Visibility::Public,
+			BTreeMap::new(),
+			rtc,
+		)
+		.expect("power levels")
+	}
+
+	#[test]
+	fn rtc_call_members_open() {
+		let content = power_levels(None, true);
+		assert_eq!(content["events"]["m.call.member"], 0, "everyone may join calls");
+		assert_eq!(
+			content["events"]["org.matrix.msc3401.call.member"], 0,
+			"everyone may join calls with the unstable event"
+		);
+
+		let content = power_levels(None, false);
+		assert_eq!(
+			content["events"]["m.call.member"], 50,
+			"public rooms which are not call rooms keep calls to moderators"
+		);
+	}
+
+	#[test]
+	fn rtc_override_precedence() {
+		let content = power_levels(
+			Some(json!({
+				"events": { "m.call.member": 100, "m.room.name": 75 },
+				"state_default": 25,
+			})),
+			true,
+		);
+
+		assert_eq!(content["events"]["m.call.member"], 100, "the override level is kept");
+		assert_eq!(
+			content["events"]["org.matrix.msc3401.call.member"], 0,
+			"call events missing from the override are merged in"
+		);
+		assert_eq!(content["events"]["m.room.name"], 75, "the override events are kept");
+		assert_eq!(content["state_default"], 25, "other override keys are kept");
+	}
 }
@@ -292,10 +292,7 @@ async fn user_can_see_summary<'a, I>(
 				.state_accessor
 				.user_can_see_state_events(sender_user, room_id);
 
-			let is_guest = services
-				.users
-				.is_deactivated(sender_user)
-				.unwrap_or(false);
+			let is_guest = services.users.is_guest(sender_user);
 
 			let user_in_allowed_restricted_room = allowed_room_ids
 				.stream()
@@ -0,0 +1,32 @@
+use axum::extract::State;
+use ruma::api::client::room::get_event_by_timestamp;
+use tuwunel_core::{Err, Result};
+
+use crate::Ruma;
+
+/// # `GET /_matrix/client/v1/rooms/{roomId}/timestamp_to_event`
+///
+/// Gets the event closest to a timestamp in the given direction.
+///
+/// - Asks other servers in the room when no event on that side of the timestamp
+///   is known locally
+pub(crate) async fn get_event_by_timestamp_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_event_by_timestamp::v1::Request>,
+) -> Result<get_event_by_timestamp::v1::Response> {
+	let room_id = &body.room_id;
+	if !services
+		.state_accessor
+		.user_can_see_state_events(body.sender_user(), room_id)
+		.await
+	{
+		return Err!(Request(Forbidden("You don't have permission to view this room.")));
+	}
+
+	let (event_id, origin_server_ts) = services
+		.timeline
+		.event_by_timestamp(room_id, body.ts, body.dir)
+		.await?;
+
+	Ok(get_event_by_timestamp::v1::Response { event_id, origin_server_ts })
+}
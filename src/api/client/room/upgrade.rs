@@ -20,6 +20,7 @@
 use tuwunel_core::{
 	Err, Result, err,
 	matrix::{Event, StateKey, pdu::PduBuilder, room_version},
+	messages::Message,
 };
 
 use crate::Ruma;
@@ -94,7 +95,9 @@ pub(crate) async fn upgrade_room_route(
 		.timeline
 		.build_and_append_pdu(
 			PduBuilder::state(StateKey::new(), &RoomTombstoneEventContent {
-				body: "This room has been replaced".to_owned(),
+				body: services
+					.globals
+					.message(Message::RoomReplaced, &[]),
 				replacement_room: replacement_room.clone(),
 			}),
 			sender_user,
@@ -33,8 +33,8 @@
 ///
 /// Searches rooms for messages.
 ///
-/// - Only works if the user is currently joined to the room (TODO: Respect
-///   history visibility)
+/// - Only works if the user is currently joined to the room
+/// - Only returns the messages the user could see when they were sent
 pub(crate) async fn search_events_route(
 	State(services): State<crate::State>,
 	body: Ruma<Request>,
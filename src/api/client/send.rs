@@ -3,9 +3,13 @@
 use axum::extract::State;
 use ruma::{api::client::message::send_message_event, events::MessageLikeEventType};
 use serde_json::from_str;
-use tuwunel_core::{Err, Result, err, matrix::pdu::PduBuilder, utils};
+use tuwunel_core::{Err, Result, err, matrix::pdu::PduBuilder};
+use tuwunel_service::ratelimit::Action;
 
-use crate::Ruma;
+use crate::{
+	ClientAddr, Ruma,
+	client::utils::{guest_access_check, rate_limit},
+};
 
 /// # `PUT /_matrix/client/v3/rooms/{roomId}/send/{eventType}/{txnId}`
 ///
@@ -18,8 +22,11 @@
 ///   allowed
 pub(crate) async fn send_message_event_route(
 	State(services): State<crate::State>,
+	addr: ClientAddr,
 	body: Ruma<send_message_event::v3::Request>,
 ) -> Result<send_message_event::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Messages).await?;
+
 	let sender_user = body.sender_user();
 	let sender_device = body.sender_device.as_deref();
 	let appservice_info = body.appservice_info.as_ref();
@@ -30,6 +37,8 @@ pub(crate) async fn send_message_event_route(
 		return Err!(Request(Forbidden("Encryption has been disabled")));
 	}
 
+	guest_access_check(&services, sender_user, &body.room_id).await?;
+
 	let state_lock = services.state.mutex.lock(&body.room_id).await;
 
 	if body.event_type == MessageLikeEventType::CallInvite
@@ -42,24 +51,12 @@ pub(crate) async fn send_message_event_route(
 	}
 
 	// Check if this is a new transaction id
-	if let Ok(response) = services
+	if let Some(event_id) = services
 		.transaction_ids
-		.existing_txnid(sender_user, sender_device, &body.txn_id)
-		.await
+		.existing_event_id(sender_user, sender_device, &body.txn_id)
+		.await?
 	{
-		// The client might have sent a txnid of the /sendToDevice endpoint
-		// This txnid has no response associated with it
-		if response.is_empty() {
-			return Err!(Request(InvalidParam(
-				"Tried to use txn id already used for an incompatible endpoint."
-			)));
-		}
-
-		return Ok(send_message_event::v3::Response {
-			event_id: utils::string_from_bytes(&response)
-				.map(TryInto::try_into)
-				.map_err(|e| err!(Database("Invalid event_id in txnid data: {e:?}")))??,
-		});
+		return Ok(send_message_event::v3::Response { event_id });
 	}
 
 	let mut unsigned = BTreeMap::new();
@@ -4,6 +4,7 @@
 mod logout;
 mod password;
 mod refresh;
+mod sso;
 mod token;
 
 use axum::extract::State;
@@ -13,7 +14,7 @@
 		self,
 		v3::{
 			ApplicationServiceLoginType, JwtLoginType, LoginType, PasswordLoginType,
-			TokenLoginType,
+			SsoLoginType, TokenLoginType,
 		},
 	},
 	login::{
@@ -22,16 +23,17 @@
 	},
 };
 use tuwunel_core::{Err, Result, info, utils, utils::stream::ReadyExt};
-use tuwunel_service::users::device::generate_refresh_token;
+use tuwunel_service::{ratelimit::Action, users::device::generate_refresh_token};
 
 use self::{ldap::ldap_login, password::password_login};
 pub(crate) use self::{
 	logout::{logout_all_route, logout_route},
 	refresh::refresh_token_route,
+	sso::{sso_callback_route, sso_login_route, sso_redirect},
 	token::login_token_route,
 };
 use super::{DEVICE_ID_LENGTH, TOKEN_LENGTH};
-use crate::Ruma;
+use crate::{ClientAddr, Ruma, client::utils::rate_limit};
 
 /// # `GET /_matrix/client/v3/login`
 ///
@@ -43,14 +45,20 @@ pub(crate) async fn get_login_types_route(
 	InsecureClientIp(client): InsecureClientIp,
 	_body: Ruma<get_login_types::v3::Request>,
 ) -> Result<get_login_types::v3::Response> {
-	Ok(get_login_types::v3::Response::new(vec![
+	let mut flows = vec![
 		LoginType::Password(PasswordLoginType::default()),
 		LoginType::ApplicationService(ApplicationServiceLoginType::default()),
 		LoginType::Jwt(JwtLoginType::default()),
 		LoginType::Token(TokenLoginType {
 			get_login_token: services.config.login_via_existing_session,
 		}),
-	]))
+	];
+
+	if services.sso.enabled() {
+		flows.push(LoginType::Sso(SsoLoginType::default()));
+	}
+
+	Ok(get_login_types::v3::Response::new(flows))
 }
 
 /// # `POST /_matrix/client/v3/login`
@@ -71,8 +79,11 @@ pub(crate) async fn get_login_types_route(
 pub(crate) async fn login_route(
 	State(services): State<crate::State>,
 	InsecureClientIp(client): InsecureClientIp,
+	addr: ClientAddr,
 	body: Ruma<login::v3::Request>,
 ) -> Result<login::v3::Response> {
+	rate_limit(&services, &body, addr, Action::Login).await?;
+
 	// Validate login method
 	let user_id = match &body.login_info {
 		| LoginInfo::Password(info) => password::handle_login(&services, &body, info).await?,
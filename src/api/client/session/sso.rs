@@ -0,0 +1,164 @@
+use axum::{
+	extract::{Form, State},
+	response::{Html, IntoResponse, Response},
+};
+use axum_extra::{TypedHeader, headers::Cookie};
+use http::{
+	StatusCode,
+	header::{LOCATION, SET_COOKIE},
+};
+use serde::Deserialize;
+use tuwunel_core::{Err, Result, utils::HtmlEscape};
+use tuwunel_service::{
+	ratelimit::Action,
+	sso::{CALLBACK_PATH, Completion, STATE_COOKIE, Started},
+};
+
+use crate::{
+	ClientAddr,
+	client::{uiaa::done_page, utils::rate_limit_client},
+};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SsoRedirectQuery {
+	#[serde(rename = "redirectUrl")]
+	redirect_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SsoCallbackQuery {
+	state: String,
+	code: Option<String>,
+	error: Option<String>,
+	error_description: Option<String>,
+}
+
+/// # `GET /_matrix/client/v3/login/sso/redirect?redirectUrl=...`
+///
+/// Sends the user to the single sign-on provider to log in; the client at
+/// `redirectUrl` then logs in with the `loginToken` it is redirected with.
+pub(crate) async fn sso_login_route(
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	Form(query): Form<SsoRedirectQuery>,
+) -> Result<Response> {
+	rate_limit_client(&services, addr, Action::Login).await?;
+
+	let started = services
+		.sso
+		.start_login(&query.redirect_url)
+		.await?;
+
+	Ok(sso_redirect(started, services.config.sso.state_ttl))
+}
+
+/// # `GET /_tuwunel/sso/callback?state=...&code=...`
+///
+/// Completes the login or the stage of user-interactive authentication the
+/// provider redirects back for.
+pub(crate) async fn sso_callback_route(
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	cookie: Option<TypedHeader<Cookie>>,
+	Form(query): Form<SsoCallbackQuery>,
+) -> Result<Response> {
+	rate_limit_client(&services, addr, Action::Login).await?;
+
+	let Some(code) = query.code else {
+		let error = query.error.as_deref().unwrap_or("no code");
+		let description = query.error_description.unwrap_or_default();
+		return Err!(Request(Forbidden("The provider denied the login: {error} {description}")));
+	};
+
+	let cookie = cookie
+		.as_ref()
+		.and_then(|TypedHeader(cookie)| cookie.get(STATE_COOKIE));
+
+	let completion = services
+		.sso
+		.complete(&query.state, cookie, &code)
+		.await?;
+
+	let response = match completion {
+		| Completion::Login(location) => found(location),
+		| Completion::Confirm { location, host } =>
+			Html(confirm_page(services.globals.server_name().as_str(), &host, &location))
+				.into_response(),
+		| Completion::Uiaa => done_page(),
+	};
+
+	Ok(([(SET_COOKIE, state_cookie("", 0))], response).into_response())
+}
+
+/// Sends the user to the provider, with the state in the cookie of the
+/// browser for the callback to check.
+pub(crate) fn sso_redirect(started: Started, ttl: u64) -> Response {
+	let Started { location, state } = started;
+
+	([(SET_COOKIE, state_cookie(&state, ttl))], found(location)).into_response()
+}
+
+fn state_cookie(state: &str, max_age: u64) -> String {
+	format!(
+		"{STATE_COOKIE}={state}; Path={CALLBACK_PATH}; Max-Age={max_age}; HttpOnly; Secure; \
+		 SameSite=Lax"
+	)
+}
+
+/// Redirects with `302 Found`, as the specification has it.
+fn found(location: String) -> Response {
+	(StatusCode::FOUND, [(LOCATION, location)]).into_response()
+}
+
+/// Asks the user to confirm continuing to a client not in the allowlist,
+/// which is handed the login token.
+fn confirm_page(server_name: &str, host: &str, location: &str) -> String {
+	format!(
+		r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Continue to {host}?</title>
+<style>
+body {{ font-family: sans-serif; max-width: 24em; margin: 4em auto; padding: 0 1em; }}
+</style>
+</head>
+<body>
+<h1>Continue to {host}?</h1>
+<p>{host} will be signed in to your account on {server_name}.</p>
+<p>If you did not start this sign-in, close this window: whoever sent you here
+could take over your account.</p>
+<p><a href="{location}">Continue to {host}</a></p>
+</body>
+</html>
+"#,
+		host = HtmlEscape(host),
+		server_name = HtmlEscape(server_name),
+		location = HtmlEscape(location),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{confirm_page, state_cookie};
+
+	#[test]
+	fn confirm_page_escapes() {
+		let page =
+			confirm_page("example.com", "evil.example", "https://evil.example/?a=\"><script>");
+
+		assert!(page.contains("Continue to evil.example?"), "the host of the client is named");
+		assert!(!page.contains("<script>"), "the URL is escaped");
+	}
+
+	#[test]
+	fn state_cookie_scoped() {
+		let cookie = state_cookie("state", 600);
+
+		assert!(cookie.starts_with("tuwunel_sso_state=state;"), "the cookie carries the state");
+		assert!(cookie.contains("Path=/_tuwunel/sso/callback"), "only the callback gets it");
+		assert!(cookie.contains("HttpOnly"), "scripts cannot read it");
+		assert!(cookie.contains("SameSite=Lax"), "it follows the redirect of the provider");
+	}
+}
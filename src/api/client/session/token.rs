@@ -25,7 +25,8 @@ pub(super) async fn handle_login(
 ) -> Result<OwnedUserId> {
 	let Token { token } = info;
 
-	if !services.config.login_via_existing_session {
+	// Single sign-on completes with a login token too.
+	if !services.config.login_via_existing_session && !services.sso.enabled() {
 		return Err!(Request(Unknown("Token login is not enabled.")));
 	}
 
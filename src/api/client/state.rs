@@ -1,5 +1,5 @@
-use axum::extract::State;
-use futures::{FutureExt, TryFutureExt, TryStreamExt};
+use axum::extract::{RawQuery, State};
+use futures::{FutureExt, StreamExt, TryFutureExt};
 use ruma::{
 	OwnedEventId, RoomId, UserId,
 	api::client::state::{get_state_event_for_key, get_state_events, send_state_event},
@@ -15,15 +15,16 @@
 	},
 	serde::Raw,
 };
+use serde::Deserialize;
 use serde_json::json;
 use tuwunel_core::{
 	Err, Result, err, is_false,
 	matrix::{Event, pdu::PduBuilder},
 	utils::BoolExt,
 };
-use tuwunel_service::Services;
+use tuwunel_service::{Services, rooms::short::ShortStateHash};
 
-use crate::{Ruma, RumaResponse};
+use crate::{Ruma, RumaResponse, client::utils::guest_access_check};
 
 /// # `PUT /_matrix/client/*/rooms/{roomId}/state/{eventType}/{stateKey}`
 ///
@@ -71,27 +72,113 @@ pub(crate) async fn send_state_event_for_empty_key_route(
 ///
 /// - If not joined: Only works if current room history visibility is world
 ///   readable
+/// - With `?at=`, an event ID or sync token, the state at that point is
+///   returned to server admins and to users who were joined then
 pub(crate) async fn get_state_events_route(
 	State(services): State<crate::State>,
+	RawQuery(query): RawQuery,
 	body: Ruma<get_state_events::v3::Request>,
 ) -> Result<get_state_events::v3::Response> {
 	let sender_user = body.sender_user();
+	let room_id = &body.room_id;
 
-	if !services
-		.state_accessor
-		.user_can_see_state_events(sender_user, &body.room_id)
-		.await
-	{
-		return Err!(Request(Forbidden("You don't have permission to view the room state.")));
-	}
+	let StateQuery { at } = serde_html_form::from_str(query.as_deref().unwrap_or_default())
+		.map_err(|e| err!(Request(InvalidParam("Failed to read query parameters: {e}"))))?;
+
+	let shortstatehash = match at.as_deref() {
+		| Some(at) => {
+			let shortstatehash = state_at(&services, room_id, &parse_state_at(at)?).await?;
+			if !services.users.is_admin(sender_user).await
+				&& !services
+					.state_accessor
+					.user_was_joined(shortstatehash, sender_user)
+					.await
+			{
+				return Err!(Request(Forbidden(
+					"You don't have permission to view the room state at this point."
+				)));
+			}
+
+			shortstatehash
+		},
+		| None => {
+			if !services
+				.state_accessor
+				.user_can_see_state_events(sender_user, room_id)
+				.await
+			{
+				return Err!(Request(Forbidden(
+					"You don't have permission to view the room state."
+				)));
+			}
+
+			services
+				.state
+				.get_room_shortstatehash(room_id)
+				.await
+				.map_err(|e| err!(Database("Missing state for {room_id:?}: {e:?}")))?
+		},
+	};
 
 	Ok(get_state_events::v3::Response {
 		room_state: services
 			.state_accessor
-			.room_state_full_pdus(&body.room_id)
-			.map_ok(Event::into_format)
-			.try_collect()
-			.await?,
+			.state_full_pdus(shortstatehash)
+			.map(Event::into_format)
+			.collect()
+			.await,
+	})
+}
+
+/// The `at` parameter of `/state`, which the Ruma request does not have.
+#[derive(Deserialize)]
+struct StateQuery {
+	at: Option<String>,
+}
+
+/// A past point of a room named by the `at` parameter.
+#[derive(Debug, Eq, PartialEq)]
+enum StateAt {
+	Event(OwnedEventId),
+	Token(u64),
+}
+
+fn parse_state_at(at: &str) -> Result<StateAt> {
+	if at.starts_with('$') {
+		return OwnedEventId::try_from(at)
+			.map(StateAt::Event)
+			.map_err(|e| err!(Request(InvalidParam("Invalid event ID {at:?}: {e}"))));
+	}
+
+	at.parse().map(StateAt::Token).map_err(|_| {
+		err!(Request(InvalidParam("{at:?} is neither an event ID nor a sync token.")))
+	})
+}
+
+/// The state of the room at the point; errors when no snapshot of it is
+/// known.
+async fn state_at(services: &Services, room_id: &RoomId, at: &StateAt) -> Result<ShortStateHash> {
+	let shortstatehash = match at {
+		| StateAt::Event(event_id) => {
+			let pdu = services.timeline.get_pdu(event_id).await;
+			if !pdu.is_ok_and(|pdu| pdu.room_id() == room_id) {
+				return Err!(Request(NotFound("Event {event_id} is not known in this room.")));
+			}
+
+			services
+				.state_accessor
+				.pdu_shortstatehash(event_id)
+				.await
+		},
+		| StateAt::Token(token) =>
+			services
+				.user
+				.get_token_shortstatehash(room_id, *token)
+				.await,
+	};
+
+	shortstatehash.map_err(|_| {
+		err!(Request(NotFound("No snapshot of the room state is known at this point.")))
 	})
 }
 
@@ -179,6 +266,7 @@ async fn send_state_event_for_key_helper(
 	state_key: &str,
 	timestamp: Option<ruma::MilliSecondsSinceUnixEpoch>,
 ) -> Result<OwnedEventId> {
+	guest_access_check(services, sender, room_id).await?;
 	allowed_to_send_state_event(services, room_id, event_type, state_key, json).await?;
 	let state_lock = services.state.mutex.lock(room_id).await;
 	let event_id = services
@@ -319,27 +407,10 @@ async fn allowed_to_send_state_event(
 		| StateEventType::RoomCanonicalAlias => {
 			match json.deserialize_as_unchecked::<RoomCanonicalAliasEventContent>() {
 				| Ok(canonical_alias_content) => {
-					let mut aliases = canonical_alias_content.alt_aliases.clone();
-
-					if let Some(alias) = canonical_alias_content.alias {
-						aliases.push(alias);
-					}
-
-					for alias in aliases {
-						let (alias_room_id, _servers) = services
-							.alias
-							.resolve_alias(&alias, None)
-							.await
-							.map_err(|e| {
-								err!(Request(BadAlias("Failed resolving alias \"{alias}\": {e}")))
-							})?;
-
-						if alias_room_id != room_id {
-							return Err!(Request(BadAlias(
-								"Room alias {alias} does not belong to room {room_id}"
-							)));
-						}
-					}
+					services
+						.alias
+						.validate_canonical_alias(room_id, &canonical_alias_content)
+						.await?;
 				},
 				| Err(e) => {
 					return Err!(Request(InvalidParam(debug_warn!(
@@ -399,3 +470,26 @@ async fn allowed_to_send_state_event(
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use ruma::owned_event_id;
+
+	use super::{StateAt, parse_state_at};
+
+	#[test]
+	fn state_at_event_or_token() {
+		assert_eq!(
+			parse_state_at("$topic:example.com").ok(),
+			Some(StateAt::Event(owned_event_id!("$topic:example.com"))),
+			"an event ID names the state at the event"
+		);
+		assert_eq!(
+			parse_state_at("1234").ok(),
+			Some(StateAt::Token(1234)),
+			"a sync token names the state at the token"
+		);
+		assert!(parse_state_at("s1234_5").is_err(), "other tokens are refused");
+		assert!(parse_state_at("$").is_err(), "invalid event IDs are refused");
+	}
+}
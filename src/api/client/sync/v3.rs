@@ -211,6 +211,13 @@ async fn build_empty_response(
 			.count_one_time_keys(body.sender_user(), body.sender_device())
 			.await,
 
+		device_unused_fallback_key_types: Some(
+			services
+				.users
+				.unused_fallback_key_types(body.sender_user(), body.sender_device())
+				.await,
+		),
+
 		..sync_events::v3::Response::new(next_batch.to_string())
 	}
 }
@@ -296,51 +303,73 @@ async fn build_sync_events(
 		.ready_filter_map(|(room_id, left_room)| left_room.map(|left_room| (room_id, left_room)))
 		.collect();
 
-	let invited_rooms = services
-		.state_cache
-		.rooms_invited(sender_user)
-		.fold_default(async |mut invited_rooms: BTreeMap<_, _>, (room_id, invite_state)| {
-			let invite_count = services
-				.state_cache
-				.get_invite_count(&room_id, sender_user)
-				.await
-				.ok();
+	let invited_rooms = async {
+		if services
+			.state_cache
+			.invited_rooms_count(sender_user)
+			.await == 0
+		{
+			return BTreeMap::new();
+		}
 
-			// Invited before last sync
-			if Some(since) >= invite_count || Some(next_batch) < invite_count {
-				return invited_rooms;
-			}
+		services
+			.state_cache
+			.rooms_invited(sender_user)
+			.fold_default(async |mut invited_rooms: BTreeMap<_, _>, (room_id, invite_state)| {
+				let invite_count = services
+					.state_cache
+					.get_invite_count(&room_id, sender_user)
+					.await
+					.ok();
+
+				// Invited before last sync
+				if Some(since) >= invite_count || Some(next_batch) < invite_count {
+					return invited_rooms;
+				}
 
-			let invited_room = InvitedRoom {
-				invite_state: InviteState { events: invite_state },
-			};
+				let invited_room = InvitedRoom {
+					invite_state: InviteState { events: invite_state },
+				};
 
-			invited_rooms.insert(room_id, invited_room);
-			invited_rooms
-		});
+				invited_rooms.insert(room_id, invited_room);
+				invited_rooms
+			})
+			.await
+	};
 
-	let knocked_rooms = services
-		.state_cache
-		.rooms_knocked(sender_user)
-		.fold_default(async |mut knocked_rooms: BTreeMap<_, _>, (room_id, knock_state)| {
-			let knock_count = services
-				.state_cache
-				.get_knock_count(&room_id, sender_user)
-				.await
-				.ok();
+	let knocked_rooms = async {
+		if services
+			.state_cache
+			.knocked_rooms_count(sender_user)
+			.await == 0
+		{
+			return BTreeMap::new();
+		}
 
-			// Knocked before last sync; or after the cutoff for this sync
-			if Some(since) >= knock_count || Some(next_batch) < knock_count {
-				return knocked_rooms;
-			}
+		services
+			.state_cache
+			.rooms_knocked(sender_user)
+			.fold_default(async |mut knocked_rooms: BTreeMap<_, _>, (room_id, knock_state)| {
+				let knock_count = services
+					.state_cache
+					.get_knock_count(&room_id, sender_user)
+					.await
+					.ok();
+
+				// Knocked before last sync; or after the cutoff for this sync
+				if Some(since) >= knock_count || Some(next_batch) < knock_count {
+					return knocked_rooms;
+				}
 
-			let knocked_room = KnockedRoom {
-				knock_state: KnockState { events: knock_state },
-			};
+				let knocked_room = KnockedRoom {
+					knock_state: KnockState { events: knock_state },
+				};
 
-			knocked_rooms.insert(room_id, knocked_room);
-			knocked_rooms
-		});
+				knocked_rooms.insert(room_id, knocked_room);
+				knocked_rooms
+			})
+			.await
+	};
 
 	let presence_updates: OptionFuture<_> = services
 		.config
@@ -366,9 +395,14 @@ async fn build_sync_events(
 		.get_to_device_events(sender_user, sender_device, Some(since), Some(next_batch))
 		.collect::<Vec<_>>();
 
-	let device_one_time_keys_count = services
-		.users
-		.count_one_time_keys(sender_user, sender_device);
+	let device_keys_counts = join(
+		services
+			.users
+			.count_one_time_keys(sender_user, sender_device),
+		services
+			.users
+			.unused_fallback_key_types(sender_user, sender_device),
+	);
 
 	// Remove all to-device events the device received *last time*
 	let remove_to_device_events =
@@ -376,13 +410,19 @@ async fn build_sync_events(
 			.users
 			.remove_to_device_events(sender_user, sender_device, since);
 
+	// Members of encrypted rooms this account left
+	let left_room_members =
+		services
+			.sync
+			.left_encrypted_room_members(sender_user, since, next_batch);
+
 	let (
 		account_data,
 		keys_changed,
-		device_one_time_keys_count,
-		((), to_device_events, presence_updates),
+		(device_one_time_keys_count, device_unused_fallback_key_types),
+		((), to_device_events, presence_updates, left_room_members),
 		(
-			(joined_rooms, mut device_list_updates, left_encrypted_users),
+			(joined_rooms, mut device_list_updates, mut left_encrypted_users),
 			left_rooms,
 			invited_rooms,
 			knocked_rooms,
@@ -390,14 +430,15 @@ async fn build_sync_events(
 	) = join5(
 		account_data,
 		keys_changed,
-		device_one_time_keys_count,
-		join3(remove_to_device_events, to_device_events, presence_updates),
+		device_keys_counts,
+		join4(remove_to_device_events, to_device_events, presence_updates, left_room_members),
 		join4(joined_rooms, left_rooms, invited_rooms, knocked_rooms),
 	)
 	.boxed()
 	.await;
 
 	device_list_updates.extend(keys_changed);
+	left_encrypted_users.extend(left_room_members);
 
 	// If the user doesn't share an encrypted room with the target anymore, we need
 	// to tell them
@@ -428,8 +469,7 @@ async fn build_sync_events(
 			left: device_list_left.into_iter().collect(),
 		},
 		device_one_time_keys_count,
-		// Fallback keys are not yet supported
-		device_unused_fallback_key_types: None,
+		device_unused_fallback_key_types: Some(device_unused_fallback_key_types),
 		next_batch: next_batch.to_string(),
 		presence: Presence { events: presence_events },
 		rooms: Rooms {
@@ -854,23 +894,52 @@ async fn load_joined_room(
 		})
 		.flatten();
 
+	// Clients requesting the counts of each thread get those of the main timeline
+	// for the room; the others get those of the main timeline and threads.
+	let thread_notifications = filter.room.timeline.unread_thread_notifications;
+
 	let notification_count: OptionFuture<_> = send_notification_counts
-		.then(|| {
-			services
-				.user
-				.notification_count(sender_user, room_id)
-				.map(TryInto::try_into)
-				.unwrap_or(uint!(0))
+		.then(|| async {
+			let count = if thread_notifications {
+				services
+					.user
+					.main_notification_count(sender_user, room_id)
+					.await
+			} else {
+				services
+					.user
+					.notification_count(sender_user, room_id)
+					.await
+			};
+
+			count.try_into().unwrap_or(uint!(0))
 		})
 		.into();
 
 	let highlight_count: OptionFuture<_> = send_notification_counts
+		.then(|| async {
+			let count = if thread_notifications {
+				services
+					.user
+					.main_highlight_count(sender_user, room_id)
+					.await
+			} else {
+				services
+					.user
+					.highlight_count(sender_user, room_id)
+					.await
+			};
+
+			count.try_into().unwrap_or(uint!(0))
+		})
+		.into();
+
+	let thread_counts: OptionFuture<_> = (send_notification_counts && thread_notifications)
 		.then(|| {
 			services
 				.user
-				.highlight_count(sender_user, room_id)
-				.map(TryInto::try_into)
-				.unwrap_or(uint!(0))
+				.thread_notification_counts(sender_user, room_id)
+				.collect::<Vec<_>>()
 		})
 		.into();
 
@@ -971,11 +1040,11 @@ async fn load_joined_room(
 		.collect();
 
 	let (
-		(notification_count, highlight_count),
+		(notification_count, highlight_count, thread_counts),
 		((mut device_list_updates, left_encrypted_users), device_updates),
 		(room_events, account_data_events, typing_events, private_read_event),
 	) = join3(
-		join(notification_count, highlight_count),
+		join3(notification_count, highlight_count, thread_counts),
 		join(device_list_updates, device_updates),
 		join4(room_events, account_data_events, typing_events, private_read_event),
 	)
@@ -1015,6 +1084,17 @@ async fn load_joined_room(
 		.chain(private_read_event.flatten().into_iter())
 		.collect();
 
+	let unread_thread_notifications = thread_counts
+		.map(|thread_counts| {
+			unread_thread_notifications(
+				thread_counts,
+				services
+					.config
+					.client_sync_thread_notifications_max,
+			)
+		})
+		.unwrap_or_default();
+
 	let joined_room = JoinedRoom {
 		account_data: RoomAccountData { events: account_data_events },
 		ephemeral: Ephemeral { events: edus },
@@ -1033,12 +1113,41 @@ async fn load_joined_room(
 				.collect(),
 		},
 		unread_notifications: UnreadNotificationsCount { highlight_count, notification_count },
-		unread_thread_notifications: BTreeMap::new(),
+		unread_thread_notifications,
 	};
 
 	Ok((joined_room, device_list_updates, left_encrypted_users))
 }
 
+/// The counts of the threads with unread notifications by thread root, of at
+/// most `max` threads; those with the most highlights and notifications are
+/// kept.
+fn unread_thread_notifications(
+	mut thread_counts: Vec<(OwnedEventId, u64, u64)>,
+	max: usize,
+) -> BTreeMap<OwnedEventId, UnreadNotificationsCount> {
+	thread_counts.retain(|&(_, notification_count, highlight_count)| {
+		notification_count > 0 || highlight_count > 0
+	});
+
+	thread_counts.sort_by(
+		|(_, a_notifications, a_highlights), (_, b_notifications, b_highlights)| {
+			(b_highlights, b_notifications).cmp(&(a_highlights, a_notifications))
+		},
+	);
+
+	thread_counts
+		.into_iter()
+		.take(max)
+		.map(|(thread_root, notification_count, highlight_count)| {
+			(thread_root, UnreadNotificationsCount {
+				highlight_count: Some(ruma_from_u64(highlight_count)),
+				notification_count: Some(ruma_from_u64(notification_count)),
+			})
+		})
+		.collect()
+}
+
 #[tracing::instrument(
 	name = "state",
 	level = "trace",
@@ -1277,3 +1386,49 @@ async fn typings_event_for_user(
 		},
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use ruma::{owned_event_id, uint};
+
+	use super::unread_thread_notifications;
+
+	#[test]
+	fn unread_thread_notifications_two_threads() {
+		let read = owned_event_id!("$read:example.com");
+		let unread = owned_event_id!("$unread:example.com");
+		let counts = vec![(read.clone(), 0, 0), (unread.clone(), 2, 1)];
+
+		let threads = unread_thread_notifications(counts, 100);
+
+		assert!(!threads.contains_key(&read), "the read thread is not listed");
+		let counts = threads
+			.get(&unread)
+			.expect("the unread thread is listed");
+
+		assert_eq!(counts.notification_count, Some(uint!(2)), "notifications of the thread");
+		assert_eq!(counts.highlight_count, Some(uint!(1)), "highlights of the thread");
+	}
+
+	#[test]
+	fn unread_thread_notifications_capped() {
+		let counts = (1..=5_u64)
+			.map(|i| {
+				let thread_root = format!("$thread{i}:example.com")
+					.try_into()
+					.unwrap();
+				(thread_root, i, 0)
+			})
+			.collect();
+
+		let threads = unread_thread_notifications(counts, 2);
+
+		assert_eq!(threads.len(), 2, "no more threads than the limit are listed");
+		assert!(
+			threads
+				.values()
+				.all(|counts| counts.notification_count >= Some(uint!(4))),
+			"the threads with the most notifications are kept"
+		);
+	}
+}
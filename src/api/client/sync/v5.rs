@@ -20,7 +20,9 @@
 	},
 	directory::RoomTypeFilter,
 	events::{
-		AnyRawAccountDataEvent, AnySyncEphemeralRoomEvent, StateEventType, TimelineEventType,
+		AnyRawAccountDataEvent, AnyStrippedStateEvent, AnySyncEphemeralRoomEvent,
+		GlobalAccountDataEventType, StateEventType, TimelineEventType,
+		direct::DirectEvent,
 		receipt::SyncReceiptEvent,
 		room::member::{MembershipState, RoomMemberEventContent},
 		typing::TypingEventContent,
@@ -58,6 +60,7 @@
 type TodoRooms = BTreeMap<OwnedRoomId, TodoRoom>;
 type TodoRoom = (BTreeSet<TypeStateKey>, usize, u64);
 type ResponseLists = BTreeMap<String, response::List>;
+type DirectRooms = HashSet<OwnedRoomId>;
 
 /// `POST /_matrix/client/unstable/org.matrix.simplified_msc3575/sync`
 /// ([MSC4186])
@@ -117,17 +120,39 @@ pub(crate) async fn sync_events_v5_route(
 		.map(ToOwned::to_owned)
 		.collect::<Vec<OwnedRoomId>>();
 
-	let all_invited_rooms = services
-		.state_cache
-		.rooms_invited(sender_user)
-		.map(|r| r.0)
-		.collect::<Vec<OwnedRoomId>>();
+	let all_invited_rooms = async {
+		if services
+			.state_cache
+			.invited_rooms_count(sender_user)
+			.await == 0
+		{
+			return Vec::new();
+		}
 
-	let all_knocked_rooms = services
-		.state_cache
-		.rooms_knocked(sender_user)
-		.map(|r| r.0)
-		.collect::<Vec<OwnedRoomId>>();
+		services
+			.state_cache
+			.rooms_invited(sender_user)
+			.map(|r| r.0)
+			.collect::<Vec<OwnedRoomId>>()
+			.await
+	};
+
+	let all_knocked_rooms = async {
+		if services
+			.state_cache
+			.knocked_rooms_count(sender_user)
+			.await == 0
+		{
+			return Vec::new();
+		}
+
+		services
+			.state_cache
+			.rooms_knocked(sender_user)
+			.map(|r| r.0)
+			.collect::<Vec<OwnedRoomId>>()
+			.await
+	};
 
 	let (all_joined_rooms, all_invited_rooms, all_knocked_rooms) =
 		join3(all_joined_rooms, all_invited_rooms, all_knocked_rooms).await;
@@ -140,6 +165,23 @@ pub(crate) async fn sync_events_v5_route(
 		.chain(all_invited_rooms.clone())
 		.chain(all_knocked_rooms.clone());
 
+	// Forget rooms the user departed; the client learns of that through the lists.
+	let known_rooms = if known_rooms.is_empty() {
+		known_rooms
+	} else {
+		services
+			.sync
+			.prune_snake_sync_known_rooms(&snake_key)
+			.await
+	};
+
+	let direct_rooms: DirectRooms = services
+		.account_data
+		.get_global(sender_user, GlobalAccountDataEventType::Direct)
+		.await
+		.map(|event: DirectEvent| direct_rooms(&event))
+		.unwrap_or_default();
+
 	let sync_info: SyncInfo<'_> = (sender_user, sender_device, globalsince, &request);
 	let (known_rooms, todo_rooms, lists) = handle_lists(
 		services,
@@ -186,6 +228,7 @@ pub(crate) async fn sync_events_v5_route(
 				&known_rooms,
 				&todo_rooms,
 				all_invited_rooms.clone(),
+				&direct_rooms,
 			)
 			.map_ok(|rooms| response.rooms = rooms);
 
@@ -475,6 +518,7 @@ async fn handle_rooms<'a, Rooms>(
 	_known_rooms: &KnownRooms,
 	todo_rooms: &TodoRooms,
 	all_invited_rooms: Rooms,
+	direct_rooms: &DirectRooms,
 ) -> Result<BTreeMap<OwnedRoomId, response::Room>>
 where
 	Rooms: Iterator<Item = &'a RoomId> + Clone + Send + Sync + 'a,
@@ -487,9 +531,11 @@ async fn handle_rooms<'a, Rooms>(
 				.clone()
 				.any(is_equal_to!(room_id));
 
-			let room =
-				handle_room(services, next_batch, sync_info, room_id, todo_room, is_invited)
-					.await?;
+			let is_direct = direct_rooms.contains(room_id);
+			let room = handle_room(
+				services, next_batch, sync_info, room_id, todo_room, is_invited, is_direct,
+			)
+			.await?;
 
 			Ok((room_id, room))
 		})
@@ -510,6 +556,7 @@ async fn handle_room(
 	room_id: &RoomId,
 	(required_state_request, timeline_limit, roomsince): &TodoRoom,
 	is_invited: bool,
+	is_direct: bool,
 ) -> Result<Option<response::Room>> {
 	let timeline: OptionFuture<_> = is_invited
 		.eq(&false)
@@ -626,8 +673,7 @@ async fn handle_room(
 		.state_accessor
 		.get_avatar(room_id)
 		.map_ok(|content| content.url)
-		.ok()
-		.map(Option::flatten);
+		.ok();
 
 	let highlight_count = services
 		.user
@@ -673,20 +719,26 @@ async fn handle_room(
 		sender_user,
 		room_id,
 		room_name.as_deref(),
-		room_avatar.as_deref(),
+		room_avatar.as_ref().and_then(Option::as_deref),
 	)
 	.await?;
 
+	let invite_state = invite_state.flatten();
+	let is_dm = is_direct
+		|| invite_state
+			.as_deref()
+			.is_some_and(|invite_state| invited_as_direct(invite_state, sender_user));
+
 	let num_live = None; // Count events in timeline greater than global sync counter
 
 	Ok(Some(response::Room {
 		initial: Some(*roomsince == 0),
 		name: room_name.or(hero_name),
-		avatar: JsOption::from_option(room_avatar.or(heroes_avatar)),
-		invite_state: invite_state.flatten(),
+		avatar: room_avatar_of(room_avatar, heroes_avatar),
+		invite_state,
 		required_state,
 		timeline,
-		is_dm: None,
+		is_dm: is_dm.then_some(true),
 		prev_batch,
 		limited,
 		bump_stamp,
@@ -698,6 +750,42 @@ async fn handle_room(
 	}))
 }
 
+/// The avatar of the room: `Null` when its avatar event has no url, telling
+/// clients the room has no avatar; otherwise its url or the avatar of the hero
+/// when it has no avatar event, `Undefined` when neither is known.
+fn room_avatar_of(
+	room_avatar: Option<Option<OwnedMxcUri>>,
+	heroes_avatar: Option<OwnedMxcUri>,
+) -> JsOption<OwnedMxcUri> {
+	match room_avatar {
+		| Some(Some(url)) if !url.as_str().is_empty() => JsOption::Some(url),
+		| Some(_) => JsOption::Null,
+		| None => JsOption::from_option(heroes_avatar),
+	}
+}
+
+/// The rooms the user's `m.direct` account data names.
+fn direct_rooms(event: &DirectEvent) -> DirectRooms {
+	event
+		.content
+		.values()
+		.flatten()
+		.cloned()
+		.collect()
+}
+
+/// Whether the invite of the user was sent as a direct message.
+fn invited_as_direct(invite_state: &[Raw<AnyStrippedStateEvent>], user_id: &UserId) -> bool {
+	invite_state
+		.iter()
+		.filter_map(|event| event.deserialize().ok())
+		.any(|event| match event {
+			| AnyStrippedStateEvent::RoomMember(member) =>
+				*member.state_key == *user_id && member.content.is_direct == Some(true),
+			| _ => false,
+		})
+}
+
 #[tracing::instrument(level = "debug", skip_all, fields(room_id, roomsince))]
 #[allow(clippy::type_complexity)]
 async fn calculate_heroes(
@@ -708,44 +796,45 @@ async fn calculate_heroes(
 	room_avatar: Option<&MxcUri>,
 ) -> Result<(Option<Vec<response::Hero>>, Option<String>, Option<OwnedMxcUri>)> {
 	const MAX_HEROES: usize = 5;
-	let heroes: Vec<_> = services
+	let members: Vec<_> = services
 		.state_cache
 		.room_members(room_id)
 		.ready_filter(|&member| member != sender_user)
 		.ready_filter_map(|member| room_name.is_none().then_some(member))
 		.map(ToOwned::to_owned)
 		.broadn_filter_map(MAX_HEROES, async |user_id| {
-			let content = services
+			services
 				.state_accessor
 				.get_member(room_id, &user_id)
 				.await
-				.ok()?;
-
-			let name: OptionFuture<_> = content
-				.displayname
-				.is_none()
-				.then(|| services.users.displayname(&user_id).ok())
-				.into();
-
-			let avatar: OptionFuture<_> = content
-				.avatar_url
-				.is_none()
-				.then(|| services.users.avatar_url(&user_id).ok())
-				.into();
-
-			let (name, avatar) = join(name, avatar).await;
-			let hero = response::Hero {
-				user_id,
-				name: name.unwrap_or(content.displayname),
-				avatar: avatar.unwrap_or(content.avatar_url),
-			};
-
-			Some(hero)
+				.ok()
+				.map(|content| (user_id, content))
 		})
 		.take(MAX_HEROES)
 		.collect()
 		.await;
 
+	let profiles: Vec<_> = services
+		.users
+		.bulk_profiles(
+			members
+				.iter()
+				.map(|(user_id, _)| &**user_id)
+				.stream(),
+		)
+		.collect()
+		.await;
+
+	let heroes: Vec<_> = members
+		.into_iter()
+		.zip(profiles)
+		.map(|((user_id, content), (_, displayname, avatar_url))| response::Hero {
+			user_id,
+			name: content.displayname.or(displayname),
+			avatar: content.avatar_url.or(avatar_url),
+		})
+		.collect();
+
 	let hero_name = match heroes.len().cmp(&(1_usize)) {
 		| Ordering::Less => None,
 		| Ordering::Equal => Some(
@@ -881,7 +970,7 @@ async fn collect_account_data(
 	)
 	.stream()
 	.broad_filter_map(async |room_id| {
-		let &(_, _, roomsince) = todo_rooms.get(room_id)?;
+		let roomsince = extension_room_since(services, sync_info, todo_rooms, room_id).await?;
 		let changes: Vec<_> = services
 			.account_data
 			.changes_since(Some(room_id), sender_user, roomsince, Some(next_batch))
@@ -936,12 +1025,13 @@ async fn collect_receipts(
 
 async fn collect_receipt(
 	services: &Services,
-	(sender_user, ..): SyncInfo<'_>,
+	sync_info: SyncInfo<'_>,
 	next_batch: u64,
 	todo_rooms: &TodoRooms,
 	room_id: &RoomId,
 ) -> Option<(OwnedRoomId, Raw<SyncReceiptEvent>)> {
-	let &(_, _, roomsince) = todo_rooms.get(room_id)?;
+	let (sender_user, ..) = sync_info;
+	let roomsince = extension_room_since(services, sync_info, todo_rooms, room_id).await?;
 	let private_receipt = services
 		.read_receipt
 		.last_privateread_update(sender_user, room_id)
@@ -1004,6 +1094,7 @@ async fn collect_typing(
 	)
 	.stream()
 	.filter_map(async |room_id| {
+		extension_room_since(services, sync_info, todo_rooms, room_id).await?;
 		services
 			.typing
 			.typing_users_for_user(room_id, sender_user)
@@ -1229,6 +1320,14 @@ async fn collect_e2ee<'a, Rooms>(
 		);
 	}
 
+	// Members of encrypted rooms the sender left
+	left_encrypted_users.extend(
+		services
+			.sync
+			.left_encrypted_room_members(sender_user, globalsince, next_batch)
+			.await,
+	);
+
 	for user_id in left_encrypted_users {
 		let dont_share_encrypted_room =
 			!share_encrypted_room(services, sender_user, &user_id, None).await;
@@ -1254,10 +1353,15 @@ async fn collect_e2ee<'a, Rooms>(
 		})
 		.into();
 
+	let device_unused_fallback_key_types = services
+		.users
+		.unused_fallback_key_types(sender_user, sender_device)
+		.await;
+
 	Ok(response::E2EE {
 		device_one_time_keys_count: device_otk_count.await.unwrap_or_default(),
 
-		device_unused_fallback_key_types: None,
+		device_unused_fallback_key_types: Some(device_unused_fallback_key_types),
 
 		device_lists: DeviceLists {
 			changed: device_list_changes.into_iter().collect(),
@@ -1268,6 +1372,36 @@ async fn collect_e2ee<'a, Rooms>(
 
 // ----------------------------------------------------------------------------
 
+/// The point from which an extension reports changes in a room. Rooms named
+/// by an extension but absent from the lists and subscriptions have no todo
+/// entry; those are served from `globalsince` as long as the user is joined.
+async fn extension_room_since(
+	services: &Services,
+	(sender_user, _, globalsince, _): SyncInfo<'_>,
+	todo_rooms: &TodoRooms,
+	room_id: &RoomId,
+) -> Option<u64> {
+	let joined = todo_rooms.contains_key(room_id)
+		|| services
+			.state_cache
+			.is_joined(sender_user, room_id)
+			.await;
+
+	select_room_since(todo_rooms, globalsince, room_id, joined)
+}
+
+fn select_room_since(
+	todo_rooms: &TodoRooms,
+	globalsince: u64,
+	room_id: &RoomId,
+	joined: bool,
+) -> Option<u64> {
+	todo_rooms
+		.get(room_id)
+		.map(|&(_, _, roomsince)| roomsince)
+		.or_else(|| joined.then_some(globalsince))
+}
+
 fn extension_rooms_todo<'a>(
 	(_, _, _, request): SyncInfo<'a>,
 	known_rooms: &'a KnownRooms,
@@ -1305,3 +1439,196 @@ fn extension_rooms_todo<'a>(
 		.chain(rooms_explicit)
 		.chain(rooms_implicit)
 }
+
+#[cfg(test)]
+mod tests {
+	use std::collections::{BTreeMap, BTreeSet};
+
+	use ruma::{
+		JsOption, OwnedMxcUri,
+		api::client::sync::sync_events::v5::{Request, request::ExtensionRoomConfig},
+		device_id, mxc_uri, owned_room_id, room_id, user_id,
+	};
+
+	use super::{TodoRooms, extension_rooms_todo, room_avatar_of, select_room_since};
+
+	#[test]
+	fn rooms_outside_lists_served() {
+		let subscribed = owned_room_id!("!subscribed:example.com");
+		let listed = room_id!("!listed:example.com");
+
+		let mut request = Request::new();
+		request.extensions.receipts.enabled = Some(true);
+		request.extensions.receipts.rooms =
+			Some(vec![ExtensionRoomConfig::Room(subscribed.clone())]);
+
+		let todo_rooms: TodoRooms =
+			BTreeMap::from([(listed.to_owned(), (BTreeSet::new(), 10, 5))]);
+
+		let sync_info = (user_id!("@user:example.com"), device_id!("DEVICE"), 42, &request);
+		let data = &request.extensions.receipts;
+		let rooms: Vec<_> = extension_rooms_todo(
+			sync_info,
+			&BTreeMap::new(),
+			&todo_rooms,
+			data.lists.as_ref(),
+			data.rooms.as_ref(),
+		)
+		.collect();
+
+		assert_eq!(rooms, [&*subscribed], "explicitly named room is requested");
+
+		assert_eq!(
+			select_room_since(&todo_rooms, 42, &subscribed, true),
+			Some(42),
+			"room without a todo entry is served from globalsince"
+		);
+		assert_eq!(
+			select_room_since(&todo_rooms, 42, &subscribed, false),
+			None,
+			"room the user is not joined to is not served"
+		);
+		assert_eq!(
+			select_room_since(&todo_rooms, 42, listed, true),
+			Some(5),
+			"room with a todo entry keeps its roomsince"
+		);
+	}
+
+	#[tokio::test]
+	async fn receipts_for_room_outside_lists() {
+		use ruma::{
+			MilliSecondsSinceUnixEpoch,
+			events::receipt::{
+				Receipt, ReceiptEvent, ReceiptEventContent, ReceiptThread, ReceiptType,
+			},
+		};
+		use tuwunel_service::fixture::Fixture;
+
+		use super::collect_receipts;
+
+		let fixture = Fixture::start().await;
+		let embedded = fixture.embedded();
+		let alice = embedded
+			.create_user("alice", None)
+			.await
+			.expect("user created");
+		let bob = embedded
+			.create_user("bob", None)
+			.await
+			.expect("user created");
+
+		let room_id = embedded
+			.create_room(&alice, None)
+			.await
+			.expect("room created");
+		embedded
+			.join_room(&bob, &room_id)
+			.await
+			.expect("room joined");
+
+		let event_id = embedded
+			.send_message(&alice, &room_id, "hello")
+			.await
+			.expect("message sent");
+
+		let receipt = Receipt {
+			ts: Some(MilliSecondsSinceUnixEpoch::now()),
+			thread: ReceiptThread::Unthreaded,
+		};
+		let content = ReceiptEventContent(BTreeMap::from([(
+			event_id.clone(),
+			BTreeMap::from([(ReceiptType::Read, BTreeMap::from([(bob.clone(), receipt)]))]),
+		)]));
+
+		fixture
+			.read_receipt
+			.readreceipt_update(&bob, &room_id, &ReceiptEvent {
+				content,
+				room_id: room_id.clone(),
+			})
+			.await;
+
+		// The room is in no list nor subscription, only named by the extension.
+		let mut request = Request::new();
+		request.extensions.receipts.enabled = Some(true);
+		request.extensions.receipts.rooms =
+			Some(vec![ExtensionRoomConfig::Room(room_id.clone())]);
+
+		let next_batch = fixture.globals.current_count();
+		let sync_info = (&*alice, device_id!("DEVICE"), 0, &request);
+		let receipts = collect_receipts(
+			&fixture,
+			sync_info,
+			next_batch,
+			&BTreeMap::new(),
+			&TodoRooms::new(),
+		)
+		.await
+		.expect("receipts collected");
+
+		let receipt = receipts
+			.rooms
+			.get(&room_id)
+			.expect("receipts of the room are returned")
+			.deserialize()
+			.expect("valid receipt event");
+
+		assert!(
+			receipt
+				.content
+				.0
+				.get(&event_id)
+				.and_then(|receipts| receipts.get(&ReceiptType::Read))
+				.is_some_and(|users| users.contains_key(&bob)),
+			"the receipt of the other member is returned"
+		);
+
+		drop(embedded);
+		fixture.stop().await;
+	}
+
+	#[test]
+	fn avatar_of_room_with_avatar() {
+		let url: OwnedMxcUri = mxc_uri!("mxc://example.com/avatar").to_owned();
+		let hero: OwnedMxcUri = mxc_uri!("mxc://example.com/hero").to_owned();
+
+		assert_eq!(
+			room_avatar_of(Some(Some(url.clone())), Some(hero)),
+			JsOption::Some(url),
+			"the url of the avatar event wins over the hero"
+		);
+	}
+
+	#[test]
+	fn avatar_of_room_with_avatar_removed() {
+		let hero: OwnedMxcUri = mxc_uri!("mxc://example.com/hero").to_owned();
+
+		assert_eq!(
+			room_avatar_of(Some(None), Some(hero)),
+			JsOption::Null,
+			"an avatar event without url is an explicit null"
+		);
+		assert_eq!(
+			room_avatar_of(Some(Some(OwnedMxcUri::from(""))), None),
+			JsOption::Null,
+			"an avatar event with an empty url is an explicit null"
+		);
+	}
+
+	#[test]
+	fn avatar_of_room_without_avatar() {
+		let hero: OwnedMxcUri = mxc_uri!("mxc://example.com/hero").to_owned();
+
+		assert_eq!(
+			room_avatar_of(None, Some(hero.clone())),
+			JsOption::Some(hero),
+			"without an avatar event the hero's avatar is used"
+		);
+		assert_eq!(
+			room_avatar_of(None, None),
+			JsOption::Undefined,
+			"without an avatar event or hero the avatar is left out"
+		);
+	}
+}
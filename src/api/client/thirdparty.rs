@@ -1,16 +1,100 @@
-use std::collections::BTreeMap;
-
-use ruma::api::client::thirdparty::get_protocols;
-use tuwunel_core::Result;
+use axum::extract::State;
+use ruma::api::client::thirdparty::{
+	get_location_for_protocol, get_location_for_room_alias, get_protocol, get_protocols,
+	get_user_for_protocol, get_user_for_user_id,
+};
+use tuwunel_core::{Err, Result};
 
 use crate::Ruma;
 
-/// # `GET /_matrix/client/r0/thirdparty/protocols`
+/// # `GET /_matrix/client/v3/thirdparty/protocols`
 ///
-/// TODO: Fetches all metadata about protocols supported by the homeserver.
+/// Fetches the protocols of the third-party networks bridged by the
+/// appservices.
 pub(crate) async fn get_protocols_route(
+	State(services): State<crate::State>,
 	_body: Ruma<get_protocols::v3::Request>,
 ) -> Result<get_protocols::v3::Response> {
-	// TODO
-	Ok(get_protocols::v3::Response { protocols: BTreeMap::new() })
+	let protocols = services.appservice.thirdparty_protocols().await;
+
+	Ok(get_protocols::v3::Response::new(protocols))
+}
+
+/// # `GET /_matrix/client/v3/thirdparty/protocol/{protocol}`
+///
+/// Fetches the protocol of a third-party network bridged by the appservices.
+pub(crate) async fn get_protocol_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_protocol::v3::Request>,
+) -> Result<get_protocol::v3::Response> {
+	let Some(protocol) = services
+		.appservice
+		.thirdparty_protocols()
+		.await
+		.remove(&body.protocol)
+	else {
+		return Err!(Request(NotFound("No appservice provides the protocol.")));
+	};
+
+	Ok(get_protocol::v3::Response::new(protocol))
+}
+
+/// # `GET /_matrix/client/v3/thirdparty/location/{protocol}`
+///
+/// Looks up the locations of a third-party network matching the fields.
+pub(crate) async fn get_location_for_protocol_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_location_for_protocol::v3::Request>,
+) -> Result<get_location_for_protocol::v3::Response> {
+	let locations = services
+		.appservice
+		.thirdparty_locations_for_protocol(&body.protocol, &body.fields)
+		.await?;
+
+	Ok(get_location_for_protocol::v3::Response::new(locations))
+}
+
+/// # `GET /_matrix/client/v3/thirdparty/location?alias={alias}`
+///
+/// Looks up the third-party locations bridged to a room alias.
+pub(crate) async fn get_location_for_room_alias_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_location_for_room_alias::v3::Request>,
+) -> Result<get_location_for_room_alias::v3::Response> {
+	let locations = services
+		.appservice
+		.thirdparty_locations_for_alias(&body.alias)
+		.await;
+
+	Ok(get_location_for_room_alias::v3::Response::new(locations))
+}
+
+/// # `GET /_matrix/client/v3/thirdparty/user/{protocol}`
+///
+/// Looks up the users of a third-party network matching the fields.
+pub(crate) async fn get_user_for_protocol_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_user_for_protocol::v3::Request>,
+) -> Result<get_user_for_protocol::v3::Response> {
+	let users = services
+		.appservice
+		.thirdparty_users_for_protocol(&body.protocol, &body.fields)
+		.await?;
+
+	Ok(get_user_for_protocol::v3::Response::new(users))
+}
+
+/// # `GET /_matrix/client/v3/thirdparty/user?userid={userid}`
+///
+/// Looks up the third-party users bridged to a Matrix user.
+pub(crate) async fn get_user_for_user_id_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_user_for_user_id::v3::Request>,
+) -> Result<get_user_for_user_id::v3::Response> {
+	let users = services
+		.appservice
+		.thirdparty_users_for_user_id(&body.userid)
+		.await;
+
+	Ok(get_user_for_user_id::v3::Response::new(users))
 }
@@ -3,10 +3,13 @@
 use axum::extract::State;
 use futures::StreamExt;
 use ruma::{
+	OwnedServerName, OwnedUserId, UserId,
 	api::{
 		client::{error::ErrorKind, to_device::send_event_to_device},
 		federation::{self, transactions::edu::DirectDeviceContent},
 	},
+	events::AnyToDeviceEventContent,
+	serde::Raw,
 	to_device::DeviceIdOrAllDevices,
 };
 use tuwunel_core::{Error, Result};
@@ -14,9 +17,18 @@
 
 use crate::Ruma;
 
+/// Most messages carried by one `m.direct_to_device` EDU.
+const EDU_MESSAGES_MAX: usize = 100;
+
+type Messages =
+	BTreeMap<OwnedUserId, BTreeMap<DeviceIdOrAllDevices, Raw<AnyToDeviceEventContent>>>;
+
 /// # `PUT /_matrix/client/r0/sendToDevice/{eventType}/{txnId}`
 ///
 /// Send a to-device event to a set of client devices.
+///
+/// Messages for local devices are queued in one write batch; those for remote
+/// users are sent in one EDU per server, or a few when they are too many.
 pub(crate) async fn send_event_to_device_route(
 	State(services): State<crate::State>,
 	body: Ruma<send_event_to_device::v3::Request>,
@@ -34,73 +46,64 @@ pub(crate) async fn send_event_to_device_route(
 		return Ok(send_event_to_device::v3::Response {});
 	}
 
+	let mut local_events = Vec::new();
 	for (target_user_id, map) in &body.messages {
-		for (target_device_id_maybe, event) in map {
-			if !services.globals.user_is_local(target_user_id) {
-				let mut map = BTreeMap::new();
-				map.insert(target_device_id_maybe.clone(), event.clone());
-				let mut messages = BTreeMap::new();
-				messages.insert(target_user_id.clone(), map);
-
-				let mut buf = EduBuf::new();
-				serde_json::to_writer(
-					&mut buf,
-					&federation::transactions::edu::Edu::DirectToDevice(DirectDeviceContent {
-						sender: sender_user.to_owned(),
-						ev_type: body.event_type.clone(),
-						message_id: services.globals.next_count().to_string().into(),
-						messages,
-					}),
-				)
-				.expect("DirectToDevice EDU can be serialized");
-
-				services
-					.sending
-					.send_edu_server(target_user_id.server_name(), buf)?;
-
-				continue;
-			}
-
-			let event_type = &body.event_type.to_string();
+		if !services.globals.user_is_local(target_user_id) {
+			continue;
+		}
 
-			let event = event
+		for (target_device_id_maybe, event) in map {
+			let event: serde_json::Value = event
 				.deserialize_as()
 				.map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Event is invalid"))?;
 
 			match target_device_id_maybe {
 				| DeviceIdOrAllDevices::DeviceId(target_device_id) => {
-					services
-						.users
-						.add_to_device_event(
-							sender_user,
-							target_user_id,
-							target_device_id,
-							event_type,
-							event,
-						)
-						.await;
+					local_events.push((target_user_id.clone(), target_device_id.clone(), event));
 				},
 
 				| DeviceIdOrAllDevices::AllDevices => {
-					let (event_type, event) = (&event_type, &event);
-					services
+					let target_device_ids: Vec<_> = services
 						.users
 						.all_device_ids(target_user_id)
-						.for_each(|target_device_id| {
-							services.users.add_to_device_event(
-								sender_user,
-								target_user_id,
-								target_device_id,
-								event_type,
-								event.clone(),
-							)
-						})
+						.map(ToOwned::to_owned)
+						.collect()
 						.await;
+
+					local_events.extend(
+						target_device_ids
+							.into_iter()
+							.map(|target_device_id| {
+								(target_user_id.clone(), target_device_id, event.clone())
+							}),
+					);
 				},
 			}
 		}
 	}
 
+	for (server, messages) in
+		remote_chunks(&body.messages, |user_id| services.globals.user_is_local(user_id))
+	{
+		let mut buf = EduBuf::new();
+		serde_json::to_writer(
+			&mut buf,
+			&federation::transactions::edu::Edu::DirectToDevice(DirectDeviceContent {
+				sender: sender_user.to_owned(),
+				ev_type: body.event_type.clone(),
+				message_id: services.globals.next_count().to_string().into(),
+				messages,
+			}),
+		)
+		.expect("DirectToDevice EDU can be serialized");
+
+		services.sending.send_edu_server(&server, buf)?;
+	}
+
+	services
+		.users
+		.add_to_device_events(sender_user, &body.event_type.to_string(), local_events);
+
 	// Save transaction id with empty data
 	services
 		.transaction_ids
@@ -108,3 +111,117 @@ pub(crate) async fn send_event_to_device_route(
 
 	Ok(send_event_to_device::v3::Response {})
 }
+
+/// The messages for remote users grouped by the server of the users, in
+/// chunks of at most `EDU_MESSAGES_MAX` messages, each for one EDU.
+fn remote_chunks<F>(messages: &Messages, is_local: F) -> Vec<(OwnedServerName, Messages)>
+where
+	F: Fn(&UserId) -> bool,
+{
+	let mut chunks: BTreeMap<OwnedServerName, Vec<(usize, Messages)>> = BTreeMap::new();
+	for (target_user_id, map) in messages {
+		if is_local(target_user_id) {
+			continue;
+		}
+
+		let server_chunks = chunks
+			.entry(target_user_id.server_name().to_owned())
+			.or_default();
+
+		for (target_device_id_maybe, event) in map {
+			if server_chunks
+				.last()
+				.is_none_or(|(count, _)| *count >= EDU_MESSAGES_MAX)
+			{
+				server_chunks.push((0, Messages::new()));
+			}
+
+			let (count, chunk) = server_chunks
+				.last_mut()
+				.expect("a chunk with room was pushed");
+
+			*count = count.saturating_add(1);
+			chunk
+				.entry(target_user_id.clone())
+				.or_default()
+				.insert(target_device_id_maybe.clone(), event.clone());
+		}
+	}
+
+	chunks
+		.into_iter()
+		.flat_map(|(server, server_chunks)| {
+			server_chunks
+				.into_iter()
+				.map(move |(_, chunk)| (server.clone(), chunk))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use ruma::{
+		OwnedUserId, UserId, owned_device_id, serde::Raw, server_name,
+		to_device::DeviceIdOrAllDevices,
+	};
+	use serde_json::{json, value::to_raw_value};
+
+	use super::{EDU_MESSAGES_MAX, Messages, remote_chunks};
+
+	/// Messages for `count` users of each server, with one device each.
+	fn recipients(servers: &[&str], count: usize) -> Messages {
+		let event = Raw::from_json(to_raw_value(&json!({ "algorithm": "m.megolm.v1" })).unwrap());
+		let mut messages = Messages::new();
+		for server in servers {
+			for i in 0..count {
+				let user_id = OwnedUserId::try_from(format!("@user{i}:{server}")).unwrap();
+				let device = DeviceIdOrAllDevices::DeviceId(owned_device_id!("DEVICE"));
+				messages.insert(user_id, BTreeMap::from([(device, event.clone())]));
+			}
+		}
+
+		messages
+	}
+
+	fn is_local(user_id: &UserId) -> bool { user_id.server_name() == server_name!("local.test") }
+
+	#[test]
+	fn remote_chunks_500_recipients() {
+		let messages = recipients(&["local.test", "a.test", "b.test", "c.test", "d.test"], 100);
+		let chunks = remote_chunks(&messages, is_local);
+
+		assert_eq!(chunks.len(), 4, "one EDU per remote server");
+		assert!(
+			chunks
+				.iter()
+				.all(|(_, chunk)| chunk.values().map(BTreeMap::len).sum::<usize>() == 100),
+			"each EDU carries all the messages for its server"
+		);
+		assert!(
+			chunks.iter().all(|(server, chunk)| chunk
+				.keys()
+				.all(|user_id| *user_id.server_name() == **server)),
+			"each EDU only carries messages for users of its server"
+		);
+	}
+
+	#[test]
+	fn remote_chunks_respect_edu_limit() {
+		let messages = recipients(&["a.test"], 500);
+		let chunks = remote_chunks(&messages, is_local);
+
+		assert_eq!(chunks.len(), 5, "500 messages are sent in five EDUs");
+		assert!(
+			chunks
+				.iter()
+				.all(|(_, chunk)| chunk.values().map(BTreeMap::len).sum::<usize>()
+					<= EDU_MESSAGES_MAX),
+			"no EDU carries more than the limit"
+		);
+
+		let messages = recipients(&["local.test"], 500);
+		assert!(remote_chunks(&messages, is_local).is_empty(), "local messages send no EDU");
+	}
+}
@@ -0,0 +1,290 @@
+//! Fallback web pages for the stages of user-interactive authentication, for
+//! clients which cannot complete a stage themselves. The page completes the
+//! stage in the session; the client then finishes its flow with the session.
+
+use axum::{
+	extract::{Form, Path, State},
+	response::{Html, IntoResponse, Response},
+};
+use http::{StatusCode, header::CONTENT_SECURITY_POLICY};
+use ruma::api::client::uiaa::{AuthData, AuthType, Password, RegistrationToken, UserIdentifier};
+use serde::Deserialize;
+use tuwunel_core::{
+	Error,
+	utils::{self, HtmlEscape},
+};
+use tuwunel_service::ratelimit::Action;
+
+use crate::{
+	ClientAddr,
+	client::{sso_redirect, utils::rate_limit_client},
+};
+
+/// Length of the nonce allowing the script of the done page.
+const NONCE_LENGTH: usize = 24;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct FallbackQuery {
+	session: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct FallbackForm {
+	session: String,
+	password: Option<String>,
+	token: Option<String>,
+}
+
+/// # `GET /_matrix/client/v3/auth/{auth_type}/fallback/web?session=...`
+///
+/// Renders the form of the stage; the single sign-on stage sends the user to
+/// the provider instead.
+pub(crate) async fn get_uiaa_fallback_route(
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	Path(auth_type): Path<String>,
+	Form(query): Form<FallbackQuery>,
+) -> Response {
+	if let Err(e) = rate_limit_client(&services, addr, Action::Login).await {
+		return fallback_page(e.status_code(), error_page(&error_message(&e)), None);
+	}
+
+	let stage = AuthType::from(auth_type.as_str());
+	if stage == AuthType::Sso && services.sso.enabled() {
+		return match services.sso.start_uiaa(&query.session).await {
+			| Ok(started) => sso_redirect(started, services.config.sso.state_ttl),
+			| Err(e) => fallback_page(StatusCode::OK, error_page(&error_message(&e)), None),
+		};
+	}
+
+	if !has_fallback(&stage) {
+		return fallback_page(StatusCode::NOT_FOUND, unsupported_page(&stage), None);
+	}
+
+	let error = services
+		.uiaa
+		.find_uiaa_session(&query.session)
+		.await
+		.err()
+		.map(|e| error_message(&e));
+
+	fallback_page(StatusCode::OK, form_page(&stage, &query.session, error.as_deref()), None)
+}
+
+/// # `POST /_matrix/client/v3/auth/{auth_type}/fallback/web`
+///
+/// Completes the stage with the submitted form; renders the form again with
+/// the error when the stage failed.
+pub(crate) async fn post_uiaa_fallback_route(
+	State(services): State<crate::State>,
+	addr: ClientAddr,
+	Path(auth_type): Path<String>,
+	Form(form): Form<FallbackForm>,
+) -> Response {
+	let stage = AuthType::from(auth_type.as_str());
+	if let Err(e) = rate_limit_client(&services, addr, Action::Login).await {
+		let page = form_page(&stage, &form.session, Some(&error_message(&e)));
+		return fallback_page(e.status_code(), page, None);
+	}
+
+	let auth = match (&stage, form.password, form.token) {
+		| (AuthType::Password, Some(password), _) => {
+			let identifier = UserIdentifier::UserIdOrLocalpart(String::new());
+			AuthData::Password(Password::new(identifier, password))
+		},
+		| (AuthType::RegistrationToken, _, Some(token)) =>
+			AuthData::RegistrationToken(RegistrationToken::new(token)),
+		| _ => return fallback_page(StatusCode::NOT_FOUND, unsupported_page(&stage), None),
+	};
+
+	match services
+		.uiaa
+		.fallback_auth(&form.session, &auth)
+		.await
+	{
+		| Ok(()) => done_page(),
+		| Err(e) => {
+			let page = form_page(&stage, &form.session, Some(&error_message(&e)));
+			fallback_page(StatusCode::OK, page, None)
+		},
+	}
+}
+
+/// Responds with a page of the fallback under a policy of its own. The policy
+/// of the API forbids forms, styles and scripts, while the pages post their
+/// form back to the server, carry an inline style and, for the done page, run
+/// the script given the nonce.
+pub(super) fn fallback_page(status: StatusCode, page: String, nonce: Option<&str>) -> Response {
+	let script = nonce
+		.map(|nonce| format!("; script-src 'nonce-{nonce}'"))
+		.unwrap_or_default();
+
+	let policy = format!(
+		"default-src 'none'; style-src 'unsafe-inline'; form-action 'self'; frame-ancestors \
+		 'none'; base-uri 'none'{script}"
+	);
+
+	(status, [(CONTENT_SECURITY_POLICY, policy)], Html(page)).into_response()
+}
+
+fn has_fallback(stage: &AuthType) -> bool {
+	matches!(stage, AuthType::Password | AuthType::RegistrationToken)
+}
+
+fn error_message(error: &Error) -> String {
+	match error {
+		| Error::Request(_, message, _) => message.to_string(),
+		| error => error.sanitized_message(),
+	}
+}
+
+/// The form of the stage, posting back to the page, with the error of the
+/// last attempt.
+pub(super) fn form_page(stage: &AuthType, session: &str, error: Option<&str>) -> String {
+	let (title, input) = match stage {
+		| AuthType::Password => (
+			"Confirm your password",
+			r#"<input type="password" name="password" autocomplete="current-password" required autofocus>"#,
+		),
+		| _ => (
+			"Enter your registration token",
+			r#"<input type="text" name="token" autocomplete="off" required autofocus>"#,
+		),
+	};
+
+	let error = error
+		.map(|error| format!(r#"<p class="error">{}</p>"#, HtmlEscape(error)))
+		.unwrap_or_default();
+
+	format!(
+		r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 24em; margin: 4em auto; padding: 0 1em; }}
+input {{ display: block; width: 100%; margin: 0.5em 0; padding: 0.4em; box-sizing: border-box; }}
+.error {{ color: #c00; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{error}
+<form method="post">
+<input type="hidden" name="session" value="{session}">
+{input}
+<input type="submit" value="Continue">
+</form>
+</body>
+</html>
+"#,
+		session = HtmlEscape(session),
+	)
+}
+
+fn error_page(error: &str) -> String {
+	format!("<!DOCTYPE html>\n<html><body><p>{}</p></body></html>\n", HtmlEscape(error))
+}
+
+fn unsupported_page(stage: &AuthType) -> String {
+	format!(
+		"<!DOCTYPE html>\n<html><body><p>Stage {} has no fallback.</p></body></html>\n",
+		HtmlEscape(stage.as_str())
+	)
+}
+
+/// Signals the client the stage is complete, as the specification requires.
+pub(super) fn done_page() -> Response {
+	let nonce = utils::random_string(NONCE_LENGTH);
+	let page = format!(
+		r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Authentication complete</title>
+<script nonce="{nonce}">
+if (window.onAuthDone) {{
+	window.onAuthDone();
+}} else if (window.opener && window.opener.postMessage) {{
+	window.opener.postMessage("authDone", "*");
+}}
+</script>
+</head>
+<body>
+<p>Thank you.</p>
+<p>You may now close this window and return to the application.</p>
+</body>
+</html>
+"#
+	);
+
+	fallback_page(StatusCode::OK, page, Some(&nonce))
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::body::to_bytes;
+	use http::header::CONTENT_SECURITY_POLICY;
+	use ruma::api::client::uiaa::AuthType;
+
+	use super::{done_page, form_page};
+
+	#[test]
+	fn form_page_escapes() {
+		let page = form_page(&AuthType::RegistrationToken, "\"><script>", Some("<b>wrong</b>"));
+
+		assert!(!page.contains("<script>"), "the session is escaped");
+		assert!(!page.contains("<b>"), "the error is escaped");
+		assert!(page.contains(r#"name="token""#), "the token stage asks for the token");
+		assert!(
+			page.contains("&lt;b&gt;wrong&lt;/b&gt;"),
+			"the error of the last attempt is shown"
+		);
+	}
+
+	#[test]
+	fn form_page_password() {
+		let page = form_page(&AuthType::Password, "session", None);
+
+		assert!(page.contains(r#"type="password""#), "the password stage asks for the password");
+		assert!(!page.contains(r#"class="error""#), "no error is shown before an attempt");
+	}
+
+	#[tokio::test]
+	async fn done_page_policy() {
+		let response = done_page();
+		let policy = response
+			.headers()
+			.get(CONTENT_SECURITY_POLICY)
+			.and_then(|value| value.to_str().ok())
+			.expect("page has its own policy");
+
+		let nonce = policy
+			.split("'nonce-")
+			.nth(1)
+			.and_then(|rest| rest.split('\'').next())
+			.map(ToOwned::to_owned)
+			.expect("policy allows the script by nonce");
+
+		assert!(!nonce.is_empty(), "the nonce is not empty");
+		assert!(policy.contains("form-action 'self'"), "the form may post back");
+		assert!(policy.contains("style-src 'unsafe-inline'"), "the inline style applies");
+		assert!(!policy.contains("sandbox"), "the page is not sandboxed");
+		assert_ne!(
+			done_page().headers().get(CONTENT_SECURITY_POLICY),
+			response.headers().get(CONTENT_SECURITY_POLICY),
+			"each response has a nonce of its own"
+		);
+
+		let body = to_bytes(response.into_body(), usize::MAX)
+			.await
+			.expect("page body");
+		let page = String::from_utf8(body.to_vec()).expect("page is text");
+		assert!(
+			page.contains(&format!(r#"<script nonce="{nonce}">"#)),
+			"the script carries the nonce of the policy"
+		);
+	}
+}
@@ -18,7 +18,7 @@
 };
 use tuwunel_core::{Err, Error, Result};
 
-use crate::Ruma;
+use crate::{Ruma, client::check_profile_field_allowed};
 
 /// # `GET /_matrix/client/unstable/uk.half-shot.msc2666/user/mutual_rooms`
 ///
@@ -133,6 +133,12 @@ pub(crate) async fn set_profile_field_route(
 		return Err!(Request(BadJson("Key names cannot be longer than 128 bytes")));
 	}
 
+	check_profile_field_allowed(
+		&services,
+		&body.value.field_name(),
+		body.appservice_info.is_some(),
+	)?;
+
 	if body.value.field_name() == ProfileFieldName::DisplayName {
 		let all_joined_rooms: Vec<OwnedRoomId> = services
 			.state_cache
@@ -197,6 +203,8 @@ pub(crate) async fn delete_profile_field_route(
 		return Err!(Request(Forbidden("You cannot update the profile of another user")));
 	}
 
+	check_profile_field_allowed(&services, &body.field, body.appservice_info.is_some())?;
+
 	if body.field == ProfileFieldName::DisplayName {
 		let all_joined_rooms: Vec<OwnedRoomId> = services
 			.state_cache
@@ -1,6 +1,9 @@
+use std::collections::BTreeMap;
+
 use axum::extract::State;
 use futures::{FutureExt, StreamExt, pin_mut};
 use ruma::{
+	OwnedUserId, UserId,
 	api::client::user_directory::search_users::{self},
 	events::room::join_rules::JoinRule,
 };
@@ -11,6 +14,7 @@
 		stream::{BroadbandExt, ReadyExt},
 	},
 };
+use tuwunel_service::Services;
 
 use crate::Ruma;
 
@@ -22,8 +26,11 @@
 ///
 /// Searches all known users for a match.
 ///
-/// - Hides any local users that aren't in any public rooms (i.e. those that
-///   have the join rule set to public) and don't share a room with the sender
+/// - Only returns users sharing a room with the sender, users in public rooms
+///   (i.e. those that have the join rule set to public) and, if
+///   `search_all_users` is enabled, any local user
+/// - Ranks exact localpart matches first, then prefix matches, then any other
+///   match
 pub(crate) async fn search_users_route(
 	State(services): State<crate::State>,
 	body: Ruma<search_users::v3::Request>,
@@ -34,57 +41,179 @@ pub(crate) async fn search_users_route(
 		.min(LIMIT_MAX);
 
 	let search_term = body.search_term.to_lowercase();
-	let mut users = services
+	let (results, limited) = services
 		.users
 		.stream()
 		.ready_filter(|&user_id| user_id != sender_user)
 		.map(ToOwned::to_owned)
 		.broad_filter_map(async |user_id| {
 			let display_name = services.users.displayname(&user_id).await.ok();
+			let rank = match_rank(&search_term, &user_id, display_name.as_deref())?;
 
-			let user_id_matches = user_id
-				.as_str()
-				.to_lowercase()
-				.contains(&search_term);
-
-			let display_name_matches = display_name
-				.as_deref()
-				.map(str::to_lowercase)
-				.is_some_and(|display_name| display_name.contains(&search_term));
-
-			if !user_id_matches && !display_name_matches {
+			if !user_is_visible(&services, sender_user, &user_id).await {
 				return None;
 			}
 
-			let user_in_public_room = services
-				.state_cache
-				.rooms_joined(&user_id)
-				.map(ToOwned::to_owned)
-				.broad_any(async |room_id| {
-					services
-						.state_accessor
-						.get_join_rules(&room_id)
-						.map(|rule| matches!(rule, JoinRule::Public))
-						.await
-				});
+			Some((rank, search_users::v3::User {
+				avatar_url: services.users.avatar_url(&user_id).await.ok(),
+				user_id,
+				display_name,
+			}))
+		})
+		.ready_fold(RankedPage::new(limit), |mut page, (rank, user)| {
+			page.push(rank, user);
+			page
+		})
+		.await
+		.finish();
 
-			let user_sees_user = services
-				.state_cache
-				.user_sees_user(sender_user, &user_id);
+	Ok(search_users::v3::Response { results, limited })
+}
+
+/// Whether the sender may find the user in the directory.
+async fn user_is_visible(services: &Services, sender_user: &UserId, user_id: &UserId) -> bool {
+	if services.config.user_directory.search_all_users && services.globals.user_is_local(user_id)
+	{
+		return true;
+	}
 
-			pin_mut!(user_in_public_room, user_sees_user);
-			user_in_public_room
-				.or(user_sees_user)
+	let user_in_public_room = services
+		.state_cache
+		.rooms_joined(user_id)
+		.map(ToOwned::to_owned)
+		.broad_any(async |room_id| {
+			services
+				.state_accessor
+				.get_join_rules(&room_id)
+				.map(|rule| matches!(rule, JoinRule::Public))
 				.await
-				.then_some(search_users::v3::User {
-					user_id: user_id.clone(),
-					display_name,
-					avatar_url: services.users.avatar_url(&user_id).await.ok(),
-				})
 		});
 
-	let results = users.by_ref().take(limit).collect().await;
-	let limited = users.next().await.is_some();
+	let user_sees_user = services
+		.state_cache
+		.user_sees_user(sender_user, user_id);
 
-	Ok(search_users::v3::Response { results, limited })
+	pin_mut!(user_in_public_room, user_sees_user);
+	user_sees_user.or(user_in_public_room).await
+}
+
+/// Ranks a user matching the lowercased search term, lower ranking first:
+/// an exact localpart or user ID match, then a prefix of the localpart, user
+/// ID or display name, then a substring of the user ID or display name.
+/// `None` when the user does not match at all.
+fn match_rank(search_term: &str, user_id: &UserId, display_name: Option<&str>) -> Option<u8> {
+	let user_id_str = user_id.as_str().to_lowercase();
+	let localpart = user_id.localpart().to_lowercase();
+	let display_name = display_name.map(str::to_lowercase);
+
+	if localpart == search_term || user_id_str == search_term {
+		return Some(0);
+	}
+
+	if localpart.starts_with(search_term)
+		|| user_id_str.starts_with(search_term)
+		|| display_name
+			.as_deref()
+			.is_some_and(|name| name.starts_with(search_term))
+	{
+		return Some(1);
+	}
+
+	let substring = user_id_str.contains(search_term)
+		|| display_name
+			.as_deref()
+			.is_some_and(|name| name.contains(search_term));
+
+	substring.then_some(2)
+}
+
+/// The best ranked matches so far, ordered by rank then user ID. One match
+/// past the limit is kept to tell whether any were cut, so searching a large
+/// directory holds no more than the page.
+struct RankedPage {
+	matches: BTreeMap<(u8, OwnedUserId), search_users::v3::User>,
+	limit: usize,
+}
+
+impl RankedPage {
+	fn new(limit: usize) -> Self { Self { matches: BTreeMap::new(), limit } }
+
+	fn push(&mut self, rank: u8, user: search_users::v3::User) {
+		self.matches
+			.insert((rank, user.user_id.clone()), user);
+
+		if self.matches.len() > self.limit.saturating_add(1) {
+			self.matches.pop_last();
+		}
+	}
+
+	/// The matches up to the limit, and whether any were cut.
+	fn finish(self) -> (Vec<search_users::v3::User>, bool) {
+		let limited = self.matches.len() > self.limit;
+		let results = self
+			.matches
+			.into_values()
+			.take(self.limit)
+			.collect();
+
+		(results, limited)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use ruma::{api::client::user_directory::search_users, user_id};
+
+	use super::{RankedPage, match_rank};
+
+	#[test]
+	fn rank_exact_prefix_substring() {
+		let alice = user_id!("@alice:example.com");
+		assert_eq!(match_rank("alice", alice, None), Some(0));
+		assert_eq!(match_rank("@alice:example.com", alice, None), Some(0));
+		assert_eq!(match_rank("ali", alice, None), Some(1));
+		assert_eq!(match_rank("wonder", alice, Some("Wonderland")), Some(1));
+		assert_eq!(match_rank("lic", alice, None), Some(2));
+		assert_eq!(match_rank("land", alice, Some("Wonderland")), Some(2));
+		assert_eq!(match_rank("bob", alice, Some("Wonderland")), None);
+	}
+
+	#[test]
+	fn ranked_page_orders_and_limits() {
+		let page = |limit| {
+			let mut page = RankedPage::new(limit);
+			for (rank, user_id) in [
+				(2, user_id!("@xalice:example.com")),
+				(1, user_id!("@alice2:example.com")),
+				(0, user_id!("@alice:example.com")),
+				(1, user_id!("@alice1:example.com")),
+			] {
+				page.push(rank, search_users::v3::User {
+					user_id: user_id.to_owned(),
+					display_name: None,
+					avatar_url: None,
+				});
+
+				assert!(
+					page.matches.len() <= limit.saturating_add(1),
+					"no more than the page is kept"
+				);
+			}
+
+			page
+		};
+
+		let (results, limited) = page(2).finish();
+		let ids: Vec<_> = results
+			.iter()
+			.map(|user| user.user_id.as_str())
+			.collect();
+
+		assert!(limited, "matches past the limit were cut");
+		assert_eq!(ids, ["@alice:example.com", "@alice1:example.com"], "best ranked first");
+
+		let (results, limited) = page(4).finish();
+		assert!(!limited, "nothing was cut");
+		assert_eq!(results.len(), 4, "every match fits");
+	}
 }
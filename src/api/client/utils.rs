@@ -1,6 +1,8 @@
 use ruma::{RoomId, UserId};
 use tuwunel_core::{Err, Result, warn};
-use tuwunel_service::Services;
+use tuwunel_service::{Services, ratelimit::Action};
+
+use crate::{ClientAddr, Ruma};
 
 pub(crate) async fn invite_check(
 	services: &Services,
@@ -14,3 +16,56 @@ pub(crate) async fn invite_check(
 
 	Ok(())
 }
+
+/// Forbids guests from rooms which do not allow guest access, and from all
+/// rooms when guest access is disabled.
+pub(crate) async fn guest_access_check(
+	services: &Services,
+	sender_user: &UserId,
+	room_id: &RoomId,
+) -> Result {
+	if !services.users.is_guest(sender_user).await {
+		return Ok(());
+	}
+
+	if !services.config.allow_guest_access {
+		return Err!(Request(GuestAccessForbidden("Guest access is disabled on this server.")));
+	}
+
+	if !services
+		.state_accessor
+		.guest_can_join(room_id)
+		.await
+	{
+		return Err!(Request(GuestAccessForbidden("Guest access is not allowed in {room_id}.")));
+	}
+
+	Ok(())
+}
+
+/// Takes the request from the rate limit of the action, counted against its
+/// sender or, before logging in, its client address.
+pub(crate) async fn rate_limit<T>(
+	services: &Services,
+	body: &Ruma<T>,
+	ClientAddr(client): ClientAddr,
+	action: Action,
+) -> Result {
+	services
+		.ratelimit
+		.check(action, body.sender_user.as_deref(), client, body.appservice_info.is_some())
+		.await
+}
+
+/// Takes the request from the rate limit of the action, counted against its
+/// client address, for routes served outside of ruma before logging in.
+pub(crate) async fn rate_limit_client(
+	services: &Services,
+	ClientAddr(client): ClientAddr,
+	action: Action,
+) -> Result {
+	services
+		.ratelimit
+		.check(action, None, client, false)
+		.await
+}
@@ -1,29 +1,47 @@
 use axum::{Json, extract::State, response::IntoResponse};
-use ruma::api::client::discovery::{
-	discover_homeserver::{self, HomeserverInfo},
-	discover_support::{self, Contact},
-};
-use tuwunel_core::{Err, Result};
+use http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+use ruma::api::client::discovery::discover_support::{self, Contact};
+use serde_json::{Map, Value, json};
+use tuwunel_core::{Err, Result, config::WellKnownConfig};
 
 use crate::Ruma;
 
 /// # `GET /.well-known/matrix/client`
 ///
-/// Returns the .well-known URL if it is configured, otherwise returns 404.
+/// Returns the .well-known document if a client URL is configured, otherwise
+/// returns 404.
 pub(crate) async fn well_known_client(
 	State(services): State<crate::State>,
-	_body: Ruma<discover_homeserver::Request>,
-) -> Result<discover_homeserver::Response> {
-	let client_url = match services.server.config.well_known.client.as_ref() {
-		| Some(url) => url.to_string(),
-		| None => return Err!(Request(NotFound("Not found."))),
+) -> Result<impl IntoResponse> {
+	let Some(document) = client_document(&services.server.config.well_known) else {
+		return Err!(Request(NotFound("Not found.")));
 	};
 
-	Ok(discover_homeserver::Response {
-		homeserver: HomeserverInfo { base_url: client_url },
-		identity_server: None,
-		tile_server: None,
-	})
+	Ok(([(ACCESS_CONTROL_ALLOW_ORIGIN, "*")], Json(document)))
+}
+
+/// The client well-known document of the configuration: the additional
+/// fields, then those of the options over them.
+pub(crate) fn client_document(config: &WellKnownConfig) -> Option<Value> {
+	let client = config.client.as_ref()?;
+
+	let mut document: Map<String, Value> = config
+		.client_additional
+		.iter()
+		.map(|(key, value)| (key.clone(), value.clone()))
+		.collect();
+
+	document.insert("m.homeserver".into(), json!({ "base_url": client }));
+
+	if let Some(tile_server) = &config.tile_server {
+		document.insert("m.tile_server".into(), json!({ "map_style_url": tile_server }));
+	}
+
+	if let Some(proxy) = &config.sliding_sync_proxy {
+		document.insert("org.matrix.msc3575.proxy".into(), json!({ "url": proxy }));
+	}
+
+	Some(Value::Object(document))
 }
 
 /// # `GET /.well-known/matrix/support`
@@ -109,3 +127,75 @@ pub(crate) async fn syncv3_client_server_json(
 		"version": tuwunel_core::version(),
 	})))
 }
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use tuwunel_core::config::WellKnownConfig;
+
+	use super::client_document;
+
+	#[test]
+	fn client_document_unconfigured() {
+		let config = WellKnownConfig {
+			tile_server: Some(
+				"https://tiles.example.com/style.json"
+					.try_into()
+					.unwrap(),
+			),
+			..Default::default()
+		};
+
+		assert_eq!(client_document(&config), None, "nothing is served without a client URL");
+	}
+
+	#[test]
+	fn client_document_fields() {
+		let config = WellKnownConfig {
+			client: Some("https://matrix.example.com".try_into().unwrap()),
+			sliding_sync_proxy: Some(
+				"https://slidingsync.example.com"
+					.try_into()
+					.unwrap(),
+			),
+			tile_server: Some(
+				"https://tiles.example.com/style.json"
+					.try_into()
+					.unwrap(),
+			),
+			..Default::default()
+		};
+
+		assert_eq!(
+			client_document(&config),
+			Some(json!({
+				"m.homeserver": { "base_url": "https://matrix.example.com/" },
+				"m.tile_server": { "map_style_url": "https://tiles.example.com/style.json" },
+				"org.matrix.msc3575.proxy": { "url": "https://slidingsync.example.com/" },
+			})),
+			"every configured field is served"
+		);
+	}
+
+	#[test]
+	fn client_document_additional() {
+		let config = WellKnownConfig {
+			client: Some("https://matrix.example.com".try_into().unwrap()),
+			client_additional: [
+				("io.element.e2ee".to_owned(), json!({ "default": false })),
+				("m.homeserver".to_owned(), json!({ "base_url": "https://elsewhere.example" })),
+			]
+			.into(),
+			..Default::default()
+		};
+
+		assert_eq!(
+			client_document(&config),
+			Some(json!({
+				"io.element.e2ee": { "default": false },
+				"m.homeserver": { "base_url": "https://matrix.example.com/" },
+			})),
+			"additional fields are merged under the configured ones"
+		);
+	}
+}
@@ -5,7 +5,7 @@
 pub mod router;
 pub mod server;
 
-pub(crate) use self::router::{Ruma, RumaResponse, State};
+pub(crate) use self::router::{ClientAddr, Ruma, RumaResponse, State};
 
 tuwunel_core::mod_ctor! {}
 tuwunel_core::mod_dtor! {}
@@ -1,6 +1,8 @@
 mod args;
 mod auth;
+mod client_addr;
 mod handler;
+mod read_only;
 mod request;
 mod response;
 pub mod state;
@@ -17,7 +19,8 @@
 
 use self::handler::RouterExt;
 pub(super) use self::{
-	args::Args as Ruma, auth::auth_uiaa, response::RumaResponse, state::State,
+	args::Args as Ruma, auth::auth_uiaa, client_addr::ClientAddr, response::RumaResponse,
+	state::State,
 };
 use crate::{client, server};
 
@@ -36,6 +39,9 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::register_route)
 		.ruma_route(&client::get_login_types_route)
 		.ruma_route(&client::login_route)
+		.route("/_matrix/client/r0/login/sso/redirect", get(client::sso_login_route))
+		.route("/_matrix/client/v3/login/sso/redirect", get(client::sso_login_route))
+		.route(tuwunel_service::sso::CALLBACK_PATH, get(client::sso_callback_route))
 		.ruma_route(&client::login_token_route)
 		.ruma_route(&client::refresh_token_route)
 		.ruma_route(&client::whoami_route)
@@ -46,6 +52,15 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::third_party_route)
 		.ruma_route(&client::request_3pid_management_token_via_email_route)
 		.ruma_route(&client::request_3pid_management_token_via_msisdn_route)
+		.ruma_route(&client::add_3pid_route)
+		.ruma_route(&client::delete_3pid_route)
+		.ruma_route(&client::request_password_change_token_via_email_route)
+		.ruma_route(&client::request_password_change_token_via_msisdn_route)
+		.route(
+			tuwunel_service::threepid::SUBMIT_TOKEN_PATH,
+			get(client::submit_threepid_token_link_route)
+				.post(client::submit_threepid_token_route),
+		)
 		.ruma_route(&client::check_registration_token_validity)
 		.ruma_route(&client::get_capabilities_route)
 		.ruma_route(&client::get_pushrules_all_route)
@@ -58,6 +73,7 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::set_pushrule_actions_route)
 		.ruma_route(&client::delete_pushrule_route)
 		.ruma_route(&client::get_room_event_route)
+		.ruma_route(&client::get_event_by_timestamp_route)
 		.ruma_route(&client::get_room_aliases_route)
 		.ruma_route(&client::get_filter_route)
 		.ruma_route(&client::create_filter_route)
@@ -118,6 +134,11 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::search_users_route)
 		.ruma_route(&client::get_member_events_route)
 		.ruma_route(&client::get_protocols_route)
+		.ruma_route(&client::get_protocol_route)
+		.ruma_route(&client::get_location_for_protocol_route)
+		.ruma_route(&client::get_location_for_room_alias_route)
+		.ruma_route(&client::get_user_for_protocol_route)
+		.ruma_route(&client::get_user_for_user_id_route)
 		.ruma_route(&client::send_message_event_route)
 		.ruma_route(&client::send_state_event_for_key_route)
 		.ruma_route(&client::get_state_events_route)
@@ -152,7 +173,17 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 		.ruma_route(&client::search_events_route)
 		.ruma_route(&client::turn_server_route)
 		.ruma_route(&client::send_event_to_device_route)
+		.route(
+			"/_matrix/client/r0/auth/{auth_type}/fallback/web",
+			get(client::get_uiaa_fallback_route).post(client::post_uiaa_fallback_route),
+		)
+		.route(
+			"/_matrix/client/v3/auth/{auth_type}/fallback/web",
+			get(client::get_uiaa_fallback_route).post(client::post_uiaa_fallback_route),
+		)
 		.ruma_route(&client::create_content_route)
+		.ruma_route(&client::create_mxc_uri_route)
+		.ruma_route(&client::create_content_async_route)
 		.ruma_route(&client::get_content_thumbnail_route)
 		.ruma_route(&client::get_content_route)
 		.ruma_route(&client::get_content_as_filename_route)
@@ -184,7 +215,7 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 			get(client::get_room_summary_legacy)
 		)
 		.ruma_route(&client::well_known_support)
-		.ruma_route(&client::well_known_client)
+		.route("/.well-known/matrix/client", get(client::well_known_client))
 		.route("/_tuwunel/server_version", get(client::tuwunel_server_version))
 		.ruma_route(&client::room_initial_sync_route)
 		.route("/client/server.json", get(client::syncv3_client_server_json));
@@ -202,6 +233,7 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 			.ruma_route(&server::send_transaction_message_route)
 			.ruma_route(&server::get_event_route)
 			.ruma_route(&server::get_backfill_route)
+			.ruma_route(&server::get_event_by_timestamp_route)
 			.ruma_route(&server::get_missing_events_route)
 			.ruma_route(&server::get_event_authorization_route)
 			.ruma_route(&server::get_room_state_route)
@@ -222,7 +254,7 @@ pub fn build(router: Router<State>, server: &Server) -> Router<State> {
 			.ruma_route(&server::claim_keys_route)
 			.ruma_route(&server::get_openid_userinfo_route)
 			.ruma_route(&server::get_hierarchy_route)
-			.ruma_route(&server::well_known_server)
+			.route("/.well-known/matrix/server", get(server::well_known_server))
 			.ruma_route(&server::get_content_route)
 			.ruma_route(&server::get_content_thumbnail_route)
 			.route("/_tuwunel/local_user_count", get(client::tuwunel_local_user_count));
@@ -1,6 +1,7 @@
 use std::{fmt::Debug, mem, ops::Deref};
 
-use axum::{body::Body, extract::FromRequest};
+use axum::{RequestPartsExt, body::Body, extract::FromRequest};
+use axum_client_ip::InsecureClientIp;
 use bytes::{BufMut, Bytes, BytesMut};
 use ruma::{
 	CanonicalJsonObject, CanonicalJsonValue, DeviceId, OwnedDeviceId, OwnedServerName,
@@ -9,7 +10,7 @@
 use tuwunel_core::{Error, Result, debug, debug_warn, err, trace, utils::string::EMPTY};
 use tuwunel_service::{Services, appservice::RegistrationInfo};
 
-use super::{auth, auth::Auth, request, request::Request};
+use super::{auth, auth::Auth, read_only, request, request::Request};
 use crate::State;
 
 /// Extractor for Ruma request structs
@@ -111,7 +112,9 @@ async fn from_request(
 			);
 			json_body = Some(CanonicalJsonValue::Object(CanonicalJsonObject::new()));
 		}
+		read_only::check::<T>(services)?;
 		let auth = auth::auth(services, &mut request, json_body.as_ref(), &T::METADATA).await?;
+		note_device_seen(services, &mut request, &auth).await;
 		Ok(Self {
 			body: make_body::<T>(services, &mut request, json_body.as_mut(), &auth)?,
 			origin: auth.origin,
@@ -123,6 +126,29 @@ async fn from_request(
 	}
 }
 
+/// Notes when and from where the device of the user was last seen.
+async fn note_device_seen(services: &Services, request: &mut Request, auth: &Auth) {
+	let (Some(user_id), Some(device_id)) = (&auth.sender_user, &auth.sender_device) else {
+		return;
+	};
+
+	if services.db.is_read_only() {
+		return;
+	}
+
+	let client_ip = request
+		.parts
+		.extract::<InsecureClientIp>()
+		.await
+		.ok()
+		.map(|InsecureClientIp(ip)| ip.to_string());
+
+	services
+		.users
+		.update_device_last_seen(user_id, device_id, client_ip)
+		.await;
+}
+
 fn make_body<T>(
 	services: &Services,
 	request: &mut Request,
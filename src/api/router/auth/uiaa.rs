@@ -2,7 +2,7 @@
 	CanonicalJsonValue, OwnedUserId,
 	api::{
 		IncomingRequest,
-		client::uiaa::{AuthData, AuthFlow, AuthType, Jwt, UiaaInfo},
+		client::uiaa::{AuthData, AuthFlow, AuthType, EmailIdentity, Jwt, UiaaInfo},
 	},
 };
 use tuwunel_core::{Err, Error, Result, err, utils};
@@ -14,15 +14,20 @@ pub(crate) async fn auth_uiaa<T>(services: &Services, body: &Ruma<T>) -> Result<
 where
 	T: IncomingRequest + Send + Sync,
 {
-	let flows = [
+	let mut flows = vec![
 		AuthFlow::new([AuthType::Password].into()),
 		AuthFlow::new([AuthType::Jwt].into()),
 	];
 
-	let mut uiaainfo = UiaaInfo {
-		flows: flows.into(),
-		..Default::default()
-	};
+	if services.threepid.email_enabled() {
+		flows.push(AuthFlow::new([AuthType::EmailIdentity].into()));
+	}
+
+	if services.sso.enabled() {
+		flows.push(AuthFlow::new([AuthType::Sso].into()));
+	}
+
+	let mut uiaainfo = UiaaInfo { flows, ..Default::default() };
 
 	match body
 		.json_body
@@ -43,6 +48,21 @@ pub(crate) async fn auth_uiaa<T>(services: &Services, body: &Ruma<T>) -> Result<
 			// Success!
 			Ok(sender_user)
 		},
+		| Some(AuthData::EmailIdentity(EmailIdentity { ref thirdparty_id_creds, .. })) => {
+			// Without an access token, as when resetting a password, the owner of
+			// the address is authenticated.
+			let user_id = services
+				.threepid
+				.authenticate(
+					thirdparty_id_creds.client_secret.as_str(),
+					thirdparty_id_creds.sid.as_str(),
+					body.sender_user.as_deref(),
+				)
+				.await?;
+
+			// Success!
+			Ok(user_id)
+		},
 		| Some(ref auth) => {
 			let sender_user = body
 				.sender_user
@@ -0,0 +1,141 @@
+use std::{
+	convert::Infallible,
+	net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use http::{HeaderMap, request::Parts};
+
+use super::State;
+
+/// Extractor for the address of the client which the client cannot choose:
+/// the peer of the connection, or the address given by a trusted reverse
+/// proxy. Unlike `InsecureClientIp`, it is fit to key limits on.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ClientAddr(pub(crate) IpAddr);
+
+impl FromRequestParts<State> for ClientAddr {
+	type Rejection = Infallible;
+
+	async fn from_request_parts(
+		parts: &mut Parts,
+		services: &State,
+	) -> Result<Self, Self::Rejection> {
+		// Connections over a unix socket have no peer address.
+		let peer = parts
+			.extensions
+			.get::<ConnectInfo<SocketAddr>>()
+			.map_or(Ipv4Addr::UNSPECIFIED.into(), |ConnectInfo(addr)| addr.ip());
+
+		let trusted = &services.config.rate_limit.trusted_proxies;
+
+		Ok(Self(client_addr(peer, &parts.headers, trusted)))
+	}
+}
+
+/// The address of the client of a request from the peer. Proxies append the
+/// address they were connected from to `X-Forwarded-For`, so the addresses
+/// are walked from the last while they are of trusted proxies; anything
+/// before the first untrusted one may have been sent by the client.
+fn client_addr(peer: IpAddr, headers: &HeaderMap, trusted: &[IpAddr]) -> IpAddr {
+	if !peer.is_unspecified() && !trusted.contains(&peer) {
+		return peer;
+	}
+
+	let forwarded = headers
+		.get_all("x-forwarded-for")
+		.iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.map(str::trim)
+		.rev();
+
+	let mut client = peer;
+	for addr in forwarded {
+		let Ok(addr) = addr.parse::<IpAddr>() else {
+			break;
+		};
+
+		client = addr;
+		if !trusted.contains(&client) {
+			break;
+		}
+	}
+
+	client
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::{IpAddr, Ipv4Addr};
+
+	use http::{HeaderMap, HeaderValue};
+
+	use super::client_addr;
+
+	fn forwarded(values: &[&'static str]) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		for value in values {
+			headers.append("x-forwarded-for", HeaderValue::from_static(value));
+		}
+
+		headers
+	}
+
+	fn ip(addr: &str) -> IpAddr { addr.parse().expect("valid address") }
+
+	#[test]
+	fn header_of_untrusted_peer_ignored() {
+		let trusted = [ip("127.0.0.1")];
+		let headers = forwarded(&["198.51.100.1"]);
+
+		assert_eq!(
+			client_addr(ip("203.0.113.7"), &headers, &trusted),
+			ip("203.0.113.7"),
+			"a client connecting directly cannot pick its address"
+		);
+	}
+
+	#[test]
+	fn address_appended_by_trusted_proxy() {
+		let trusted = [ip("127.0.0.1"), ip("10.0.0.2")];
+
+		let headers = forwarded(&["198.51.100.1, 203.0.113.7"]);
+		assert_eq!(
+			client_addr(ip("127.0.0.1"), &headers, &trusted),
+			ip("203.0.113.7"),
+			"the addresses sent by the client before the proxy's are ignored"
+		);
+
+		let headers = forwarded(&["198.51.100.1, 203.0.113.7", "10.0.0.2"]);
+		assert_eq!(
+			client_addr(ip("127.0.0.1"), &headers, &trusted),
+			ip("203.0.113.7"),
+			"trusted proxies in a chain are skipped"
+		);
+
+		let headers = forwarded(&["not an address, 10.0.0.2"]);
+		assert_eq!(
+			client_addr(ip("127.0.0.1"), &headers, &trusted),
+			ip("10.0.0.2"),
+			"an invalid address ends the walk at the last proxy"
+		);
+
+		assert_eq!(
+			client_addr(ip("127.0.0.1"), &HeaderMap::new(), &trusted),
+			ip("127.0.0.1"),
+			"without the header the proxy is the client"
+		);
+	}
+
+	#[test]
+	fn unix_socket_trusted() {
+		let headers = forwarded(&["203.0.113.7"]);
+
+		assert_eq!(
+			client_addr(Ipv4Addr::UNSPECIFIED.into(), &headers, &[]),
+			ip("203.0.113.7"),
+			"the proxy of a unix socket is trusted"
+		);
+	}
+}
@@ -0,0 +1,135 @@
+//! Refusal of the requests which would write to a read-only database, before
+//! they reach their handler and fail there with an internal error.
+
+use http::Method;
+use ruma::api::{
+	IncomingRequest, Metadata,
+	client::{
+		directory::get_public_rooms_filtered, error::ErrorKind, keys::get_keys,
+		search::search_events, sync::sync_events, user_directory::search_users,
+	},
+	federation::{
+		directory::get_public_rooms_filtered as federation_get_public_rooms_filtered,
+		discovery::get_remote_server_keys_batch, event::get_missing_events,
+		keys::get_keys as federation_get_keys,
+	},
+};
+use tuwunel_core::{Error, Result, http::StatusCode};
+use tuwunel_service::Services;
+
+/// Requests using a writing method which only read; the database is not
+/// written to serve them.
+const READING: &[&Metadata] = &[
+	&get_keys::v3::Request::METADATA,
+	&get_public_rooms_filtered::v3::Request::METADATA,
+	&search_events::v3::Request::METADATA,
+	&search_users::v3::Request::METADATA,
+	&sync_events::v5::Request::METADATA,
+	&federation_get_keys::v1::Request::METADATA,
+	&federation_get_public_rooms_filtered::v1::Request::METADATA,
+	&get_missing_events::v1::Request::METADATA,
+	&get_remote_server_keys_batch::v2::Request::METADATA,
+];
+
+/// Refuses the request when the database is read-only and the endpoint
+/// writes to it.
+pub(super) fn check<T: IncomingRequest>(services: &Services) -> Result {
+	let well_known = &services.server.config.well_known;
+	let admin_contact = well_known
+		.support_page
+		.as_ref()
+		.map(ToString::to_string)
+		.or_else(|| {
+			well_known
+				.support_email
+				.as_ref()
+				.map(|email| format!("mailto:{email}"))
+		})
+		.unwrap_or_default();
+
+	check_metadata(services.db.is_read_only(), &T::METADATA, admin_contact)
+}
+
+pub(super) fn check_metadata(
+	read_only: bool,
+	metadata: &Metadata,
+	admin_contact: String,
+) -> Result {
+	if !read_only || !is_mutating(metadata) {
+		return Ok(());
+	}
+
+	Err(Error::Request(
+		ErrorKind::ResourceLimitExceeded { admin_contact },
+		"The server is running read-only; this request would write to its database.".into(),
+		StatusCode::SERVICE_UNAVAILABLE,
+	))
+}
+
+/// Whether serving the endpoint writes to the database.
+pub(super) fn is_mutating(metadata: &Metadata) -> bool {
+	match metadata.method {
+		| Method::GET | Method::HEAD | Method::OPTIONS => false,
+		| _ => !READING.contains(&metadata),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use ruma::api::{
+		IncomingRequest,
+		client::{
+			error::ErrorKind,
+			keys::get_keys,
+			message::{get_message_events, send_message_event},
+			sync::sync_events,
+		},
+	};
+	use tuwunel_core::Error;
+
+	use super::{check_metadata, is_mutating};
+
+	#[test]
+	fn send_message_refused_when_read_only() {
+		let metadata = &send_message_event::v3::Request::METADATA;
+
+		assert!(
+			check_metadata(false, metadata, String::new()).is_ok(),
+			"messages are sent when the database is writable"
+		);
+
+		let result = check_metadata(true, metadata, "mailto:admin@example.com".into());
+		assert!(
+			matches!(
+				result,
+				Err(Error::Request(ErrorKind::ResourceLimitExceeded { ref admin_contact }, ..))
+					if admin_contact == "mailto:admin@example.com"
+			),
+			"messages are refused when the database is read-only: {result:?}"
+		);
+	}
+
+	#[test]
+	fn reads_allowed_when_read_only() {
+		for metadata in [
+			&get_message_events::v3::Request::METADATA,
+			&sync_events::v3::Request::METADATA,
+			&sync_events::v5::Request::METADATA,
+			&get_keys::v3::Request::METADATA,
+		] {
+			assert!(!is_mutating(metadata), "{metadata:?} only reads");
+			assert!(
+				check_metadata(true, metadata, String::new()).is_ok(),
+				"{metadata:?} is served when the database is read-only"
+			);
+		}
+	}
+
+	#[test]
+	fn writes_are_mutating() {
+		assert!(
+			is_mutating(&send_message_event::v3::Request::METADATA),
+			"sending a message writes"
+		);
+	}
+}
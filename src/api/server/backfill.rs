@@ -1,12 +1,13 @@
 use std::cmp;
 
 use axum::extract::State;
-use futures::{FutureExt, StreamExt, TryStreamExt};
+use futures::{FutureExt, StreamExt, TryStreamExt, future::ready};
 use ruma::{MilliSecondsSinceUnixEpoch, api::federation::backfill::get_backfill};
 use tuwunel_core::{
 	PduCount, Result,
 	utils::{IterStream, ReadyExt, stream::TryTools},
 };
+use tuwunel_service::rooms::timeline::ResponseBudget;
 
 use super::AccessCheck;
 use crate::Ruma;
@@ -20,7 +21,8 @@
 /// # `GET /_matrix/federation/v1/backfill/<room_id>`
 ///
 /// Retrieves events from before the sender joined the room, if the room's
-/// history visibility allows.
+/// history visibility allows. The response stops short of the limit once the
+/// events would exceed `backfill_max_response_bytes`.
 pub(crate) async fn get_backfill_route(
 	State(services): State<crate::State>,
 	ref body: Ruma<get_backfill::v1::Request>,
@@ -53,6 +55,8 @@ pub(crate) async fn get_backfill_route(
 		.ready_fold(PduCount::min(), cmp::max)
 		.await;
 
+	let mut budget = ResponseBudget::new(services.config.backfill_max_response_bytes);
+
 	Ok(get_backfill::v1::Response {
 		origin_server_ts: MilliSecondsSinceUnixEpoch::now(),
 
@@ -82,6 +86,7 @@ pub(crate) async fn get_backfill_route(
 					.format_pdu_into(pdu, None)
 					.map(Ok)
 			})
+			.try_take_while(|pdu| ready(Ok(budget.admit(pdu))))
 			.try_collect()
 			.await?,
 	})
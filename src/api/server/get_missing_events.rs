@@ -1,6 +1,7 @@
 use axum::extract::State;
 use ruma::api::federation::event::get_missing_events;
 use tuwunel_core::{Result, debug, debug_error, utils::to_canonical_object};
+use tuwunel_service::rooms::timeline::ResponseBudget;
 
 use super::AccessCheck;
 use crate::Ruma;
@@ -12,7 +13,8 @@
 
 /// # `POST /_matrix/federation/v1/get_missing_events/{roomId}`
 ///
-/// Retrieves events that the sender is missing.
+/// Retrieves events that the sender is missing. The response stops short of
+/// the limit once the events would exceed `backfill_max_response_bytes`.
 pub(crate) async fn get_missing_events_route(
 	State(services): State<crate::State>,
 	body: Ruma<get_missing_events::v1::Request>,
@@ -36,6 +38,8 @@ pub(crate) async fn get_missing_events_route(
 	// the vec will never have more entries the limit
 	let mut events = Vec::with_capacity(limit);
 
+	let mut budget = ResponseBudget::new(services.config.backfill_max_response_bytes);
+
 	let mut i: usize = 0;
 	while i < queued_events.len() && events.len() < limit {
 		let Ok(pdu) = services.timeline.get_pdu(&queued_events[i]).await else {
@@ -52,6 +56,16 @@ pub(crate) async fn get_missing_events_route(
 			continue;
 		}
 
+		// Soft-failed events never became part of the room's DAG for us.
+		if services
+			.pdu_metadata
+			.is_event_soft_failed(&queued_events[i])
+			.await
+		{
+			i = i.saturating_add(1);
+			continue;
+		}
+
 		if !services
 			.state_accessor
 			.server_can_see_event(body.origin(), &body.room_id, &queued_events[i])
@@ -81,8 +95,14 @@ pub(crate) async fn get_missing_events_route(
 			.format_pdu_into(event, None)
 			.await;
 
+		if !budget.admit(&event) {
+			debug!(?body.origin, count = events.len(), "Response size limit reached");
+			break;
+		}
+
 		queued_events.extend(prev_events);
 		events.push(event);
+		i = i.saturating_add(1);
 	}
 
 	Ok(get_missing_events::v1::Response { events })
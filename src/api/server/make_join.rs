@@ -1,5 +1,5 @@
 use axum::extract::State;
-use futures::{StreamExt, TryFutureExt, pin_mut};
+use futures::{StreamExt, TryFutureExt};
 use ruma::{
 	OwnedUserId, RoomId, RoomVersionId, UserId,
 	api::{client::error::ErrorKind, federation::membership::prepare_join_event},
@@ -8,11 +8,12 @@
 		room::{
 			join_rules::{AllowRule, JoinRule, RoomJoinRulesEventContent},
 			member::{MembershipState, RoomMemberEventContent},
+			power_levels::RoomPowerLevels,
 		},
 	},
 };
 use tuwunel_core::{
-	Err, Error, Result, at, debug_info, matrix::pdu::PduBuilder, utils::IterStream, warn,
+	Err, Error, Result, at, debug_info, err, matrix::pdu::PduBuilder, utils::IterStream, warn,
 };
 use tuwunel_service::Services;
 
@@ -93,27 +94,7 @@ pub(crate) async fn create_join_event_template_route(
 		)
 		.await?
 		{
-			let users = services
-				.state_cache
-				.local_users_in_room(&body.room_id)
-				.filter(|user| {
-					services.state_accessor.user_can_invite(
-						&body.room_id,
-						user,
-						&body.user_id,
-						&state_lock,
-					)
-				})
-				.map(ToOwned::to_owned);
-
-			pin_mut!(users);
-			let Some(auth_user) = users.next().await else {
-				return Err!(Request(UnableToGrantJoin(
-					"No user on this server is able to assist in joining."
-				)));
-			};
-
-			Some(auth_user)
+			Some(select_authorising_user(&services, &body.room_id).await?)
 		} else {
 			None
 		}
@@ -198,27 +179,144 @@ pub(crate) async fn user_can_perform_restricted_join(
 		return Ok(false);
 	}
 
-	if r.allow
+	let allow_rooms: Vec<_> = r
+		.allow
 		.iter()
 		.filter_map(|rule| {
 			if let AllowRule::RoomMembership(membership) = rule {
-				Some(membership)
+				Some(&*membership.room_id)
 			} else {
 				None
 			}
 		})
 		.stream()
-		.any(|m| {
-			services
+		.then(async |allow_room: &RoomId| {
+			let joined = services
 				.state_cache
-				.is_joined(user_id, &m.room_id)
+				.is_joined(user_id, allow_room)
+				.await;
+
+			(allow_room, joined)
 		})
-		.await
+		.collect()
+		.await;
+
+	authorise_by_membership(allow_rooms)
+}
+
+/// Picks the local member of the room to authorise a restricted join: the one
+/// with the highest power level among those able to invite.
+async fn select_authorising_user(services: &Services, room_id: &RoomId) -> Result<OwnedUserId> {
+	let power_levels = services
+		.state_accessor
+		.get_power_levels(room_id)
+		.await?;
+
+	let members: Vec<OwnedUserId> = services
+		.state_cache
+		.local_users_in_room(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	authorising_user(&power_levels, members)
+}
+
+/// Authorises a restricted join when the joining user is in any of the allow
+/// rooms, given as pairs of the room and whether the user is joined to it.
+fn authorise_by_membership<'a, I>(allow_rooms: I) -> Result<bool>
+where
+	I: IntoIterator<Item = (&'a RoomId, bool)>,
+{
+	if let Some((room_id, _)) = allow_rooms
+		.into_iter()
+		.find(|&(_, joined)| joined)
 	{
-		Ok(true)
-	} else {
-		Err!(Request(UnableToAuthorizeJoin(
-			"Joining user is not known to be in any required room."
-		)))
+		debug_info!("Restricted join authorised through membership of {room_id}");
+		return Ok(true);
+	}
+
+	Err!(Request(UnableToAuthorizeJoin(
+		"Joining user is not known to be in any required room."
+	)))
+}
+
+fn authorising_user(
+	power_levels: &RoomPowerLevels,
+	members: Vec<OwnedUserId>,
+) -> Result<OwnedUserId> {
+	members
+		.into_iter()
+		.filter(|member| power_levels.user_can_invite(member))
+		.fold(None, |selected: Option<OwnedUserId>, member| match selected {
+			| Some(selected)
+				if power_levels.for_user(&selected) >= power_levels.for_user(&member) =>
+				Some(selected),
+			| _ => Some(member),
+		})
+		.ok_or_else(|| {
+			err!(Request(UnableToGrantJoin(
+				"No user on this server is able to assist in joining."
+			)))
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::iter;
+
+	use ruma::{
+		OwnedUserId, api::client::error::ErrorKind, events::room::power_levels::RoomPowerLevels,
+		room_id, room_version_rules::AuthorizationRules, user_id,
+	};
+	use serde_json::json;
+
+	use super::{authorise_by_membership, authorising_user};
+
+	fn power_levels() -> RoomPowerLevels {
+		let content = serde_json::from_value(json!({
+			"invite": 50,
+			"users": {
+				"@alice:example.com": 100,
+				"@bob:example.com": 50,
+				"@carol:example.com": 0,
+			},
+		}))
+		.expect("valid power levels");
+
+		RoomPowerLevels::new(Some(content).into(), &AuthorizationRules::V6, iter::empty())
+	}
+
+	fn members(users: &[&str]) -> Vec<OwnedUserId> {
+		users
+			.iter()
+			.map(|user| user.try_into().expect("valid user ID"))
+			.collect()
+	}
+
+	#[test]
+	fn authorising_user_most_powerful() {
+		let members = members(&["@carol:example.com", "@bob:example.com", "@alice:example.com"]);
+		let selected = authorising_user(&power_levels(), members).expect("user selected");
+		assert_eq!(selected, user_id!("@alice:example.com"));
+	}
+
+	#[test]
+	fn authorising_user_unable_to_grant() {
+		let members = members(&["@carol:example.com", "@dave:example.com"]);
+		let error = authorising_user(&power_levels(), members).expect_err("nobody can invite");
+		assert!(matches!(error.kind(), ErrorKind::UnableToGrantJoin));
+	}
+
+	#[test]
+	fn membership_unable_to_authorise() {
+		let allowed = room_id!("!allowed:example.com");
+		let other = room_id!("!other:example.com");
+
+		assert!(authorise_by_membership([(other, false), (allowed, true)]).expect("authorised"));
+
+		let error = authorise_by_membership([(other, false), (allowed, false)])
+			.expect_err("not in any allow room");
+		assert!(matches!(error.kind(), ErrorKind::UnableToAuthorizeJoin));
 	}
 }
@@ -5,7 +5,8 @@
 use futures::{FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
 use itertools::Itertools;
 use ruma::{
-	CanonicalJsonObject, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, ServerName, UserId,
+	CanonicalJsonObject, CanonicalJsonValue, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId,
+	ServerName, UserId,
 	api::{
 		client::error::ErrorKind,
 		federation::transactions::{
@@ -36,6 +37,7 @@
 use tuwunel_service::{
 	Services,
 	sending::{EDU_LIMIT, PDU_LIMIT},
+	transaction_ids::PduResults,
 };
 
 use crate::Ruma;
@@ -78,6 +80,70 @@ pub(crate) async fn send_transaction_message_route(
 		)));
 	}
 
+	services
+		.federation
+		.observe_clock(body.origin(), body.origin_server_ts.get().into())
+		.await;
+
+	services
+		.sending
+		.wake_destination(body.origin(), "transaction");
+
+	// A retry waits for the transaction to be handled, then gets its results.
+	let origin = body.origin();
+	let _txn_lock = services
+		.transaction_ids
+		.mutex_federation
+		.lock(origin)
+		.await;
+
+	let existing = services
+		.transaction_ids
+		.existing_federation_txn(origin, &body.transaction_id);
+
+	let store = |results: &PduResults| {
+		services
+			.transaction_ids
+			.add_federation_txn(origin, &body.transaction_id, results)
+			.log_err()
+			.ok();
+	};
+
+	let pdus = handle_once(existing, async || handle_txn(&services, &client, &body).await, store)
+		.await?;
+
+	Ok(send_transaction_message::v1::Response { pdus })
+}
+
+/// The results stored for a transaction handled already, or else those of
+/// handling it, which are then stored.
+pub(super) async fn handle_once<E, H, Fut, S>(
+	existing: E,
+	handle: H,
+	store: S,
+) -> Result<PduResults>
+where
+	E: Future<Output = Option<PduResults>> + Send,
+	H: FnOnce() -> Fut + Send,
+	Fut: Future<Output = Result<PduResults>> + Send,
+	S: FnOnce(&PduResults) + Send,
+{
+	if let Some(results) = existing.await {
+		debug!("Transaction handled already");
+		return Ok(results);
+	}
+
+	let results = handle().await?;
+	store(&results);
+
+	Ok(results)
+}
+
+async fn handle_txn(
+	services: &Services,
+	client: &IpAddr,
+	body: &Ruma<send_transaction_message::v1::Request>,
+) -> Result<PduResults> {
 	let txn_start_time = Instant::now();
 	trace!(
 		pdus = body.pdus.len(),
@@ -104,7 +170,7 @@ pub(crate) async fn send_transaction_message_route(
 		.filter_map(Result::ok)
 		.stream();
 
-	let results = handle(&services, &client, body.origin(), txn_start_time, pdus, edus).await?;
+	let results = handle(services, client, body.origin(), txn_start_time, pdus, edus).await?;
 
 	debug!(
 		pdus = body.pdus.len(),
@@ -122,12 +188,10 @@ pub(crate) async fn send_transaction_message_route(
 		}
 	}
 
-	Ok(send_transaction_message::v1::Response {
-		pdus: results
-			.into_iter()
-			.map(|(e, r)| (e, r.map_err(error::sanitized_message)))
-			.collect(),
-	})
+	Ok(results
+		.into_iter()
+		.map(|(e, r)| (e, r.map_err(error::sanitized_message)))
+		.collect())
 }
 
 async fn handle(
@@ -189,6 +253,12 @@ async fn handle_room(
 	pdus.try_stream()
 		.and_then(async |(room_id, event_id, value)| {
 			services.server.check_running()?;
+			let kind = value
+				.get("type")
+				.and_then(CanonicalJsonValue::as_str)
+				.unwrap_or_default()
+				.to_owned();
+
 			let pdu_start_time = Instant::now();
 			let result = services
 				.event_handler
@@ -196,8 +266,20 @@ async fn handle_room(
 				.map_ok(|_| ())
 				.await;
 
+			let pdu_elapsed = pdu_start_time.elapsed();
+			services
+				.event_handler
+				.record_pdu(origin, &event_id, &kind, pdu_elapsed);
+
+			services.metrics.observe(
+				"tuwunel_federation_pdu_duration_seconds",
+				"Time taken to handle PDUs received over federation.",
+				&[],
+				pdu_elapsed,
+			);
+
 			debug!(
-				pdu_elapsed = ?pdu_start_time.elapsed(),
+				?pdu_elapsed,
 				txn_elapsed = ?txn_start_time.elapsed(),
 				"Finished PDU {event_id}",
 			);
@@ -439,7 +521,7 @@ async fn handle_edu_device_list_update(
 	origin: &ServerName,
 	content: DeviceListUpdateContent,
 ) {
-	let DeviceListUpdateContent { user_id, .. } = content;
+	let user_id = &content.user_id;
 
 	if user_id.server_name() != origin {
 		debug_warn!(
@@ -451,7 +533,7 @@ async fn handle_edu_device_list_update(
 
 	services
 		.users
-		.mark_device_key_update(&user_id)
+		.update_remote_device_list(&content)
 		.await;
 }
 
@@ -546,19 +628,18 @@ async fn handle_edu_direct_to_device_event(
 		},
 
 		| DeviceIdOrAllDevices::AllDevices => {
-			services
+			let targets: Vec<_> = services
 				.users
 				.all_device_ids(target_user_id)
-				.for_each(|target_device_id| {
-					services.users.add_to_device_event(
-						sender,
-						target_user_id,
-						target_device_id,
-						ev_type,
-						event.clone(),
-					)
+				.map(|target_device_id| {
+					(target_user_id.to_owned(), target_device_id.to_owned(), event.clone())
 				})
+				.collect()
 				.await;
+
+			services
+				.users
+				.add_to_device_events(sender, ev_type, targets);
 		},
 	}
 }
@@ -586,3 +667,52 @@ async fn handle_edu_signing_key_update(
 		.log_err()
 		.ok();
 }
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		future::ready,
+		sync::{
+			Mutex,
+			atomic::{AtomicUsize, Ordering},
+		},
+	};
+
+	use ruma::owned_event_id;
+	use tuwunel_service::transaction_ids::PduResults;
+
+	use super::handle_once;
+
+	#[tokio::test]
+	async fn replayed_transaction_handled_once() {
+		let stored: Mutex<Option<PduResults>> = Mutex::default();
+		let handled = AtomicUsize::new(0);
+
+		let deliver = async || {
+			let existing = ready(stored.lock().unwrap().clone());
+			let handle = async || {
+				handled.fetch_add(1, Ordering::Relaxed);
+				Ok(PduResults::from([
+					(owned_event_id!("$accepted:example.com"), Ok(())),
+					(
+						owned_event_id!("$rejected:example.com"),
+						Err("Event is invalid".to_owned()),
+					),
+				]))
+			};
+
+			let store = |results: &PduResults| {
+				*stored.lock().unwrap() = Some(results.clone());
+			};
+
+			handle_once(existing, handle, store).await
+		};
+
+		let first = deliver().await.expect("handled");
+		let retry = deliver().await.expect("answered");
+
+		assert_eq!(handled.load(Ordering::Relaxed), 1, "the retry is not handled again");
+		assert_eq!(first, retry, "the retry gets the original results");
+		assert_eq!(first.len(), 2, "the results of every PDU are kept");
+	}
+}
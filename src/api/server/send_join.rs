@@ -161,6 +161,18 @@ async fn create_join_event(
 			)));
 		}
 
+		if !services
+			.state_accessor
+			.get_power_levels(room_id)
+			.await?
+			.user_can_invite(&authorising_user)
+		{
+			return Err!(Request(UnableToGrantJoin(
+				"Authorising user {authorising_user} lacks the power to invite, they cannot \
+				 authorise your join."
+			)));
+		}
+
 		if !super::user_can_perform_restricted_join(
 			services,
 			&state_key,
@@ -0,0 +1,33 @@
+use axum::extract::State;
+use ruma::api::federation::event::get_event_by_timestamp;
+use tuwunel_core::{Result, err};
+
+use super::AccessCheck;
+use crate::Ruma;
+
+/// # `GET /_matrix/federation/v1/timestamp_to_event/{roomId}`
+///
+/// Gets the event closest to a timestamp in the given direction.
+///
+/// - Only searches the local timeline
+pub(crate) async fn get_event_by_timestamp_route(
+	State(services): State<crate::State>,
+	body: Ruma<get_event_by_timestamp::v1::Request>,
+) -> Result<get_event_by_timestamp::v1::Response> {
+	AccessCheck {
+		services: &services,
+		origin: body.origin(),
+		room_id: &body.room_id,
+		event_id: None,
+	}
+	.check()
+	.await?;
+
+	let (event_id, origin_server_ts) = services
+		.timeline
+		.local_event_by_timestamp(&body.room_id, body.ts, body.dir)
+		.await
+		.ok_or_else(|| err!(Request(NotFound("No event found near the timestamp."))))?;
+
+	Ok(get_event_by_timestamp::v1::Response { event_id, origin_server_ts })
+}
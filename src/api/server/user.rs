@@ -90,6 +90,10 @@ pub(crate) async fn get_keys_route(
 		));
 	}
 
+	services
+		.sending
+		.wake_destination(body.origin(), "key query");
+
 	let result = get_keys_helper(
 		&services,
 		None,
@@ -1,20 +1,53 @@
-use axum::extract::State;
-use ruma::api::{client::error::ErrorKind, federation::discovery::discover_homeserver};
-use tuwunel_core::{Error, Result};
-
-use crate::Ruma;
+use axum::{Json, extract::State, response::IntoResponse};
+use http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+use serde_json::{Value, json};
+use tuwunel_core::{Err, Result, config::WellKnownConfig};
 
 /// # `GET /.well-known/matrix/server`
 ///
-/// Returns the .well-known URL if it is configured, otherwise returns 404.
+/// Returns the .well-known document if a server is configured, otherwise
+/// returns 404.
 pub(crate) async fn well_known_server(
 	State(services): State<crate::State>,
-	_body: Ruma<discover_homeserver::Request>,
-) -> Result<discover_homeserver::Response> {
-	Ok(discover_homeserver::Response {
-		server: match services.server.config.well_known.server.as_ref() {
-			| Some(server_name) => server_name.to_owned(),
-			| None => return Err(Error::BadRequest(ErrorKind::NotFound, "Not found.")),
-		},
-	})
+) -> Result<impl IntoResponse> {
+	let Some(document) = server_document(&services.server.config.well_known) else {
+		return Err!(Request(NotFound("Not found.")));
+	};
+
+	Ok(([(ACCESS_CONTROL_ALLOW_ORIGIN, "*")], Json(document)))
+}
+
+/// The server well-known document of the configuration.
+pub(crate) fn server_document(config: &WellKnownConfig) -> Option<Value> {
+	let server = config.server.as_ref()?;
+
+	Some(json!({ "m.server": server }))
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+	use tuwunel_core::config::WellKnownConfig;
+
+	use super::server_document;
+
+	#[test]
+	fn server_document_delegates() {
+		assert_eq!(
+			server_document(&WellKnownConfig::default()),
+			None,
+			"nothing is served without a server"
+		);
+
+		let config = WellKnownConfig {
+			server: Some("matrix.example.com:443".try_into().unwrap()),
+			..Default::default()
+		};
+
+		assert_eq!(
+			server_document(&config),
+			Some(json!({ "m.server": "matrix.example.com:443" })),
+			"the server is delegated"
+		);
+	}
 }
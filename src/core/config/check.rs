@@ -68,6 +68,15 @@ pub fn check(config: &Config) -> Result {
 		return Err!(Config("port", "No ports were specified to listen on"));
 	}
 
+	if config.prune_inactive_devices_after != 0
+		&& config.prune_inactive_devices_after < 60 * 60 * 24
+	{
+		return Err!(Config(
+			"prune_inactive_devices_after",
+			"Devices can't be pruned for being inactive less than a day."
+		));
+	}
+
 	if !config.listening {
 		warn!("Configuration item `listening` is set to `false`. Cannot hear anyone.");
 	}
@@ -121,6 +130,14 @@ pub fn check(config: &Config) -> Result {
 		));
 	}
 
+	// with no room for a bucket every request would find a full one
+	if config.rate_limit.capacity == 0 {
+		return Err!(Config(
+			"rate_limit.capacity",
+			"rate_limit.capacity cannot be 0. Please set a value at least 1."
+		));
+	}
+
 	// yeah, unless the user built a debug build hopefully for local testing only
 	if cfg!(not(debug_assertions)) && config.server_name == "your.server.name" {
 		return Err!(Config(
@@ -0,0 +1,67 @@
+use super::Config;
+
+fn config(options: &str) -> Config {
+	let toml = format!(
+		"[global]\nserver_name = \"example.com\"\ndatabase_path = \"/nonexistent\"\n{options}"
+	);
+
+	Config::from_toml(&toml).expect("valid config")
+}
+
+#[test]
+fn reload_takes_live_options() {
+	let old = config("allow_registration = false\nturn_ttl = 86400\nport = 8008");
+	let new = config(
+		"allow_registration = true\nregistration_token = \"secret\"\nturn_ttl = 3600\nport = \
+		 8448\nforbidden_remote_server_names = [\"evil\\\\.example\"]",
+	);
+
+	let (config, mut applied, restart) = old.reloaded(&new);
+	applied.sort_unstable();
+
+	assert_eq!(
+		applied,
+		[
+			"allow_registration",
+			"forbidden_remote_server_names",
+			"registration_token",
+			"turn_ttl"
+		],
+		"the changed options read while running are applied"
+	);
+	assert_eq!(restart, ["port"], "the listening ports need a restart");
+	assert!(config.allow_registration, "registration is toggled without a restart");
+	assert_eq!(config.registration_token.as_deref(), Some("secret"), "the token is taken");
+	assert_eq!(config.turn_ttl, 3600, "the TURN settings are taken");
+	assert!(
+		config
+			.forbidden_remote_server_names
+			.is_match("evil.example"),
+		"options not listed anywhere are taken"
+	);
+	assert_eq!(
+		config.get_bind_addrs(),
+		old.get_bind_addrs(),
+		"the ports are kept until the restart"
+	);
+}
+
+#[test]
+fn reload_unchanged() {
+	let old = config("allow_registration = false");
+	let (_, applied, restart) = old.reloaded(&old.clone());
+
+	assert!(applied.is_empty(), "nothing changed to apply: {applied:?}");
+	assert!(restart.is_empty(), "nothing changed to restart for: {restart:?}");
+}
+
+#[test]
+fn rate_limit_capacity_zero_rejected() {
+	assert!(config("").check().is_ok(), "the default capacity is valid");
+	assert!(
+		config("[global.rate_limit]\ncapacity = 0")
+			.check()
+			.is_err(),
+		"no room for any bucket"
+	);
+}
@@ -18,7 +18,9 @@
 
 /// Logging subsystem. This is a singleton member of super::Server which holds
 /// all logging and tracing related state rather than shoving it all in
-/// super::Server directly.
+/// super::Server directly. The default installs no reload handles, as when the
+/// server is embedded and the embedder owns the subscriber.
+#[derive(Default)]
 pub struct Log {
 	/// General log level reload handles.
 	pub reload: LogLevelReloadHandles,
@@ -111,6 +111,40 @@ pub fn from_val(json: &CanonicalJsonObject) -> Result<Self> {
 			.and_then(serde_json::from_value)
 			.map_err(Into::into)
 	}
+
+	/// An unsigned event of the kind sent by the user to `!room:example.com`,
+	/// for tests which need an event but no room. The other fields hold
+	/// placeholders, to override as in `PduEvent { depth, ..PduEvent::fake(..)
+	/// }`. Only built for tests, and for those of other crates through the
+	/// `test` feature.
+	#[cfg(any(test, feature = "test"))]
+	#[must_use]
+	pub fn fake<C: Serialize>(
+		event_id: &str,
+		sender: &str,
+		kind: TimelineEventType,
+		content: &C,
+	) -> Self {
+		Self {
+			event_id: event_id.try_into().expect("valid event id"),
+			room_id: ruma::owned_room_id!("!room:example.com"),
+			sender: sender.try_into().expect("valid user id"),
+			origin: None,
+			origin_server_ts: UInt::from(1_000_u32),
+			kind,
+			content: serde_json::value::to_raw_value(content).expect("serializable content"),
+			state_key: None,
+			prev_events: Vec::new(),
+			depth: UInt::from(1_u32),
+			auth_events: Vec::new(),
+			redacts: None,
+			unsigned: None,
+			hashes: EventHash::default(),
+			signatures: None,
+			#[cfg(test)]
+			rejected: false,
+		}
+	}
 }
 
 impl Event for Pdu
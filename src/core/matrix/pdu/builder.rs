@@ -1,8 +1,11 @@
 use std::collections::BTreeMap;
 
 use ruma::{
-	MilliSecondsSinceUnixEpoch, OwnedEventId,
-	events::{MessageLikeEventContent, StateEventContent, TimelineEventType},
+	EventId, MilliSecondsSinceUnixEpoch, OwnedEventId,
+	events::{
+		MessageLikeEventContent, StateEventContent, TimelineEventType,
+		room::redaction::RoomRedactionEventContent,
+	},
 };
 use serde::Deserialize;
 use serde_json::value::{RawValue as RawJsonValue, to_raw_value};
@@ -56,6 +59,18 @@ pub fn timeline<T>(content: &T) -> Self
 			..Self::default()
 		}
 	}
+
+	/// A redaction of the event, setting `redacts` both at the top level and
+	/// in the content to suit every room version.
+	pub fn redaction(redacts: &EventId, reason: Option<String>) -> Self {
+		Self {
+			redacts: Some(redacts.to_owned()),
+			..Self::timeline(&RoomRedactionEventContent {
+				redacts: Some(redacts.to_owned()),
+				reason,
+			})
+		}
+	}
 }
 
 impl Default for Builder {
@@ -0,0 +1,146 @@
+//! Messages the server generates for users, translated by the
+//! `[global.messages.<LANG>]` sections of the config and in English
+//! otherwise.
+
+use std::collections::BTreeMap;
+
+use crate::{Config, config::MessagesConfig};
+
+/// A message the server generates for users.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Message {
+	RoomDeleted,
+	AdminRevoked,
+	AutoJoin,
+	RoomReplaced,
+	UserRedacted,
+	MessageRedacted,
+}
+
+impl Message {
+	/// The key of the message in the config sections.
+	#[must_use]
+	pub fn id(self) -> &'static str {
+		match self {
+			| Self::RoomDeleted => "room_deleted",
+			| Self::AdminRevoked => "admin_revoked",
+			| Self::AutoJoin => "auto_join",
+			| Self::RoomReplaced => "room_replaced",
+			| Self::UserRedacted => "user_redacted",
+			| Self::MessageRedacted => "message_redacted",
+		}
+	}
+
+	#[must_use]
+	fn english(self) -> &'static str {
+		match self {
+			| Self::RoomDeleted => "Room Deleted",
+			| Self::AdminRevoked => "Admin Revoked",
+			| Self::AutoJoin => "Automatically joining this room upon registration",
+			| Self::RoomReplaced => "This room has been replaced",
+			| Self::UserRedacted =>
+				"The administrator(s) of {server_name} have redacted this user's messages.",
+			| Self::MessageRedacted =>
+				"The administrator(s) of {server_name} has redacted this user's message.",
+		}
+	}
+
+	fn translation(self, messages: &MessagesConfig) -> Option<&str> {
+		match self {
+			| Self::RoomDeleted => messages.room_deleted.as_deref(),
+			| Self::AdminRevoked => messages.admin_revoked.as_deref(),
+			| Self::AutoJoin => messages.auto_join.as_deref(),
+			| Self::RoomReplaced => messages.room_replaced.as_deref(),
+			| Self::UserRedacted => messages.user_redacted.as_deref(),
+			| Self::MessageRedacted => messages.message_redacted.as_deref(),
+		}
+	}
+}
+
+/// Renders the message in the language, or in the `server_language` when
+/// none is given. Without a translation for either the message is in
+/// English. Each `{name}` in the template is replaced by the value of the
+/// argument of that name.
+#[must_use]
+pub fn render(
+	config: &Config,
+	language: Option<&str>,
+	message: Message,
+	args: &[(&str, &str)],
+) -> String {
+	let languages = language
+		.into_iter()
+		.chain([config.server_language.as_str()]);
+
+	fill(template(&config.messages, languages, message), args)
+}
+
+fn template<'a, I>(
+	messages: &'a BTreeMap<String, MessagesConfig>,
+	languages: I,
+	message: Message,
+) -> &'a str
+where
+	I: Iterator<Item = &'a str>,
+{
+	languages
+		.filter_map(|language| messages.get(language))
+		.find_map(|messages| message.translation(messages))
+		.unwrap_or_else(|| message.english())
+}
+
+fn fill(template: &str, args: &[(&str, &str)]) -> String {
+	args.iter()
+		.fold(template.to_owned(), |out, (name, value)| {
+			out.replace(&format!("{{{name}}}"), value)
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use super::{Message, fill, template};
+	use crate::config::MessagesConfig;
+
+	fn messages() -> BTreeMap<String, MessagesConfig> {
+		BTreeMap::from([("de".to_owned(), MessagesConfig {
+			room_deleted: Some("Raum gelöscht".to_owned()),
+			user_redacted: Some(
+				"Die Administratoren von {server_name} haben die Nachrichten dieses Nutzers \
+				 entfernt."
+					.to_owned(),
+			),
+			..MessagesConfig::default()
+		})])
+	}
+
+	#[test]
+	fn render_secondary_language() {
+		let messages = messages();
+		let text = template(&messages, ["de", "en"].into_iter(), Message::UserRedacted);
+		assert_eq!(
+			fill(text, &[("server_name", "example.com")]),
+			"Die Administratoren von example.com haben die Nachrichten dieses Nutzers entfernt."
+		);
+
+		let text = template(&messages, ["fr", "de"].into_iter(), Message::RoomDeleted);
+		assert_eq!(text, "Raum gelöscht");
+	}
+
+	#[test]
+	fn render_falls_back_to_english() {
+		let messages = messages();
+		let text = template(&messages, ["de"].into_iter(), Message::AdminRevoked);
+		assert_eq!(text, "Admin Revoked");
+
+		let text = template(&messages, ["fr"].into_iter(), Message::RoomDeleted);
+		assert_eq!(text, "Room Deleted");
+
+		let text = template(&messages, ["en"].into_iter(), Message::MessageRedacted);
+		assert_eq!(
+			fill(text, &[("server_name", "example.com")]),
+			"The administrator(s) of example.com has redacted this user's message."
+		);
+	}
+}
@@ -7,6 +7,7 @@
 pub mod info;
 pub mod log;
 pub mod matrix;
+pub mod messages;
 pub mod metrics;
 pub mod mods;
 pub mod server;
@@ -1,3 +1,5 @@
+use std::hint::black_box;
+
 use bytesize::ByteSize;
 
 use crate::{Result, err};
@@ -58,3 +60,16 @@ pub fn u64_from_bytes(bytes: &[u8]) -> Result<u64> { Ok(u64_from_u8x8(*u8x8_from
 
 #[inline]
 pub fn u8x8_from_bytes(bytes: &[u8]) -> Result<&[u8; 8]> { Ok(bytes.try_into()?) }
+
+/// Compare two byte strings in time independent of their contents; only the
+/// lengths are not secret.
+#[inline]
+#[must_use]
+pub fn eq_constant_time(a: &[u8], b: &[u8]) -> bool {
+	let diff = a
+		.iter()
+		.zip(b.iter())
+		.fold(0_u8, |acc, (a, b)| black_box(acc | (a ^ b)));
+
+	a.len() == b.len() && diff == 0
+}
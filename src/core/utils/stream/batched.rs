@@ -0,0 +1,52 @@
+//! Batched deletion over a stream of keys
+
+use futures::{Stream, StreamExt, pin_mut};
+use tokio::task::yield_now;
+
+use crate::{Err, Result};
+
+/// Default number of items deleted between yields.
+pub const DELETE_BATCH_SIZE: usize = 1024;
+
+/// Applies `delete` to every item of the stream in batches of `batch_size`,
+/// yielding to the runtime between batches so deleting a large keyset does
+/// not stall other tasks. `cancelled` is checked before each batch; once it
+/// returns true the deletion stops with an error. Returns the number of items
+/// deleted.
+///
+/// Items are handed to `delete` as they are produced rather than collected,
+/// since keys borrowed from a database iterator don't outlive the next one.
+pub async fn delete_batched<S, T, D, C>(
+	items: S,
+	batch_size: usize,
+	mut delete: D,
+	cancelled: C,
+) -> Result<usize>
+where
+	S: Stream<Item = T> + Send,
+	D: FnMut(T) -> Result + Send,
+	C: Fn() -> bool + Send,
+{
+	let batch_size = batch_size.max(1);
+	let mut deleted: usize = 0;
+
+	pin_mut!(items);
+	loop {
+		if cancelled() {
+			return Err!("Deletion cancelled after {deleted} items.");
+		}
+
+		let mut batch: usize = 0;
+		while batch < batch_size {
+			let Some(item) = items.next().await else {
+				return Ok(deleted.saturating_add(batch));
+			};
+
+			delete(item)?;
+			batch = batch.saturating_add(1);
+		}
+
+		deleted = deleted.saturating_add(batch);
+		yield_now().await;
+	}
+}
@@ -1,4 +1,5 @@
 mod band;
+mod batched;
 mod broadband;
 mod cloned;
 mod expect;
@@ -17,6 +18,7 @@
 	AMPLIFICATION_LIMIT, WIDTH_LIMIT, automatic_amplification, automatic_width,
 	set_amplification, set_width,
 };
+pub use batched::{DELETE_BATCH_SIZE, delete_batched};
 pub use broadband::BroadbandExt;
 pub use cloned::Cloned;
 pub use expect::TryExpect;
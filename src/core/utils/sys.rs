@@ -1,10 +1,10 @@
 pub mod compute;
+pub mod ephemeral;
 pub mod storage;
 
 use std::path::PathBuf;
 
-pub use compute::available_parallelism;
-
+pub use self::{compute::available_parallelism, ephemeral::EphemeralDir};
 use crate::{Result, debug};
 
 /// This is needed for opening lots of file descriptors, which tends to
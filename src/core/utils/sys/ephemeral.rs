@@ -0,0 +1,39 @@
+//! Directories which only live as long as the server
+
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	process,
+};
+
+use crate::{Result, debug_warn, utils::random_string};
+
+/// A uniquely named directory under the system's temporary directory which is
+/// deleted with everything in it when dropped.
+#[derive(Debug)]
+pub struct EphemeralDir {
+	path: PathBuf,
+}
+
+impl EphemeralDir {
+	/// Creates the directory, its name starting with the prefix.
+	pub fn create(prefix: &str) -> Result<Self> {
+		let name = format!("{prefix}-{}-{}", process::id(), random_string(8));
+		let path = std::env::temp_dir().join(name);
+		fs::create_dir_all(&path)?;
+
+		Ok(Self { path })
+	}
+
+	#[inline]
+	#[must_use]
+	pub fn path(&self) -> &Path { &self.path }
+}
+
+impl Drop for EphemeralDir {
+	fn drop(&mut self) {
+		if let Err(e) = fs::remove_dir_all(&self.path) {
+			debug_warn!(path = ?self.path, "Failed to remove ephemeral directory: {e}");
+		}
+	}
+}
@@ -1,6 +1,6 @@
 #![allow(clippy::disallowed_methods)]
 
-use crate::utils;
+use crate::{Err, utils};
 
 #[test]
 fn increment_none() {
@@ -36,6 +36,15 @@ fn increment_wrap() {
 	assert_eq!(res, 0);
 }
 
+#[test]
+fn eq_constant_time() {
+	assert!(utils::bytes::eq_constant_time(b"", b""));
+	assert!(utils::bytes::eq_constant_time(b"abcdef", b"abcdef"));
+	assert!(!utils::bytes::eq_constant_time(b"abcdef", b"abcdeg"));
+	assert!(!utils::bytes::eq_constant_time(b"abcdef", b"abcde"));
+	assert!(!utils::bytes::eq_constant_time(b"abc", b"abcdef"));
+}
+
 #[test]
 fn checked_add() {
 	use crate::checked;
@@ -276,3 +285,134 @@ async fn set_intersection_sorted_stream2() {
 		.await;
 	assert!(r.eq(&["ccc", "ggg", "iii"]));
 }
+
+#[tokio::test]
+async fn delete_batched_complete() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use utils::{IterStream, stream::delete_batched};
+
+	let keys: Vec<u32> = (0..10_000).collect();
+	let mut deleted = Vec::new();
+	let checks = AtomicUsize::new(0);
+
+	let count = delete_batched(
+		keys.iter().stream(),
+		1000,
+		|key| {
+			deleted.push(*key);
+			Ok(())
+		},
+		|| {
+			checks.fetch_add(1, Ordering::Relaxed);
+			false
+		},
+	)
+	.await
+	.expect("not cancelled");
+
+	assert_eq!(count, keys.len());
+	assert_eq!(deleted, keys);
+
+	// Once before each full batch and once more to find the stream exhausted.
+	assert_eq!(checks.load(Ordering::Relaxed), 11);
+}
+
+#[tokio::test]
+async fn delete_batched_partial_batch() {
+	use utils::{IterStream, stream::delete_batched};
+
+	let keys: Vec<u32> = (0..2500).collect();
+	let mut deleted = 0_usize;
+
+	let count = delete_batched(
+		keys.iter().stream(),
+		1000,
+		|_| {
+			deleted += 1;
+			Ok(())
+		},
+		|| false,
+	)
+	.await
+	.expect("not cancelled");
+
+	assert_eq!(count, 2500);
+	assert_eq!(deleted, 2500);
+}
+
+#[tokio::test]
+async fn delete_batched_cancelled() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use utils::{IterStream, stream::delete_batched};
+
+	let keys: Vec<u32> = (0..10_000).collect();
+	let mut deleted = Vec::new();
+	let batches = AtomicUsize::new(0);
+
+	let result = delete_batched(
+		keys.iter().stream(),
+		1000,
+		|key| {
+			deleted.push(*key);
+			Ok(())
+		},
+		|| batches.fetch_add(1, Ordering::Relaxed) >= 3,
+	)
+	.await;
+
+	assert!(result.is_err(), "deletion should be cancelled");
+	assert_eq!(deleted, keys[..3000]);
+}
+
+#[tokio::test]
+async fn delete_batched_error() {
+	use utils::{IterStream, stream::delete_batched};
+
+	let keys: Vec<u32> = (0..100).collect();
+	let mut deleted = 0_usize;
+
+	let result = delete_batched(
+		keys.iter().stream(),
+		10,
+		|key| {
+			if *key == 42 {
+				return Err!("bad key");
+			}
+
+			deleted += 1;
+			Ok(())
+		},
+		|| false,
+	)
+	.await;
+
+	assert!(result.is_err(), "error should stop the deletion");
+	assert_eq!(deleted, 42);
+}
+
+#[test]
+fn ephemeral_dir_removed_on_drop() {
+	use std::fs;
+
+	use utils::sys::EphemeralDir;
+
+	let dir = EphemeralDir::create("tuwunel-test").expect("created");
+	let path = dir.path().to_owned();
+	fs::write(path.join("file"), b"data").expect("written");
+	assert!(path.is_dir());
+
+	drop(dir);
+	assert!(!path.exists(), "directory removed");
+}
+
+#[test]
+fn http_date_to_millis() {
+	use utils::time::millis_from_http_date;
+
+	let millis = millis_from_http_date("Wed, 21 Oct 2015 07:28:00 GMT").expect("valid date");
+	assert_eq!(millis, 1_445_412_480_000, "IMF-fixdate parsed");
+
+	assert!(millis_from_http_date("yesterday").is_err(), "invalid date rejected");
+}
@@ -70,6 +70,17 @@ pub fn rfc2822_from_seconds(epoch: i64) -> String {
 		.to_rfc2822()
 }
 
+/// Milliseconds since the epoch of an HTTP date such as the `Date` header.
+pub fn millis_from_http_date(date: &str) -> Result<u64> {
+	use chrono::DateTime;
+
+	let millis = DateTime::parse_from_rfc2822(date)
+		.map_err(|e| err!("{date:?} is not a valid HTTP date: {e}"))?
+		.timestamp_millis();
+
+	u64::try_from(millis).map_err(|e| err!(Arithmetic("HTTP date {date:?} is before epoch: {e}")))
+}
+
 #[must_use]
 pub fn format(ts: SystemTime, str: &str) -> String {
 	use chrono::{DateTime, Utc};
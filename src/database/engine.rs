@@ -12,8 +12,8 @@
 use std::{
 	ffi::CStr,
 	sync::{
-		Arc,
-		atomic::{AtomicU32, Ordering},
+		Arc, Mutex,
+		atomic::{AtomicU32, AtomicU64, Ordering},
 	},
 };
 
@@ -23,6 +23,7 @@
 };
 use tuwunel_core::{Err, Result, debug, info, warn};
 
+pub use self::backup::{BackupEntry, BackupRun};
 use crate::{
 	Context,
 	pool::Pool,
@@ -37,6 +38,10 @@ pub struct Engine {
 	pub(super) secondary: bool,
 	pub(crate) checksums: bool,
 	corks: AtomicU32,
+	flushes: AtomicU64,
+	#[cfg(any(test, feature = "test"))]
+	queries: AtomicU64,
+	backup_run: Mutex<Option<BackupRun>>,
 }
 
 pub(crate) type Db = DBWithThreadMode<MultiThreaded>;
@@ -87,7 +92,28 @@ pub fn update(&self) -> Result {
 	pub fn sync(&self) -> Result { result(DBCommon::flush_wal(&self.db, true)) }
 
 	#[tracing::instrument(level = "debug", skip_all)]
-	pub fn flush(&self) -> Result { result(DBCommon::flush_wal(&self.db, false)) }
+	pub fn flush(&self) -> Result {
+		self.flushes.fetch_add(1, Ordering::Relaxed);
+		result(DBCommon::flush_wal(&self.db, false))
+	}
+
+	/// Flushes of the write-ahead log since the database was opened. Writes
+	/// outside of a cork flush one at a time; those under a cork are batched.
+	#[inline]
+	pub fn flush_count(&self) -> u64 { self.flushes.load(Ordering::Relaxed) }
+
+	/// Point queries since the database was opened. Keys queried together in a
+	/// batch count once for each chunk sent to the pool. Only counted for
+	/// tests.
+	#[cfg(any(test, feature = "test"))]
+	#[inline]
+	pub fn query_count(&self) -> u64 { self.queries.load(Ordering::Relaxed) }
+
+	#[inline]
+	pub(crate) fn queried(&self) {
+		#[cfg(any(test, feature = "test"))]
+		self.queries.fetch_add(1, Ordering::Relaxed);
+	}
 
 	#[inline]
 	pub(crate) fn cork(&self) { self.corks.fetch_add(1, Ordering::Relaxed); }
@@ -1,6 +1,11 @@
-use std::{ffi::OsString, path::PathBuf};
+use std::{
+	ffi::OsString,
+	fmt,
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
 
-use rocksdb::backup::{BackupEngine, BackupEngineOptions};
+use rocksdb::backup::{BackupEngine, BackupEngineInfo, BackupEngineOptions};
 use tuwunel_core::{
 	Err, Result, error, implement, info, utils::time::rfc2822_from_seconds, warn,
 };
@@ -8,72 +13,153 @@
 use super::Engine;
 use crate::util::map_err;
 
+/// A backup held by the backup engine.
+#[derive(Clone, Debug)]
+pub struct BackupEntry {
+	pub id: u32,
+	pub timestamp: i64,
+	pub size: u64,
+	pub num_files: u32,
+}
+
+/// The backup in progress, or the last one since startup.
+#[derive(Clone, Debug)]
+pub struct BackupRun {
+	pub path: PathBuf,
+	pub started: SystemTime,
+
+	/// Bytes written into the path since the backup started.
+	pub written: u64,
+
+	/// When the backup finished, with the backup created or why it failed.
+	pub finished: Option<(SystemTime, Result<BackupEntry, String>)>,
+
+	size_at_start: u64,
+}
+
 #[implement(Engine)]
 #[tracing::instrument(skip(self))]
 pub fn backup(&self) -> Result {
-	let mut engine = self.backup_engine()?;
 	let config = &self.ctx.server.config;
 	if config.database_backups_to_keep > 0 {
-		let flush = !self.is_read_only();
-		engine
-			.create_new_backup_flush(&self.db, flush)
-			.map_err(map_err)?;
-
-		let engine_info = engine.get_backup_info();
-		let info = &engine_info
-			.last()
-			.expect("backup engine info is not empty");
-		info!(
-			"Created database backup #{} using {} bytes in {} files",
-			info.backup_id, info.size, info.num_files,
-		);
+		self.backup_create(None)?;
 	}
 
-	if config.database_backups_to_keep >= 0 {
-		let keep = u32::try_from(config.database_backups_to_keep)?;
-		if let Err(e) = engine.purge_old_backups(keep.try_into()?) {
+	if config.database_backups_to_keep == 0 {
+		if let Err(e) = self.backup_engine(None)?.purge_old_backups(0) {
 			error!("Failed to purge old backup: {e:?}");
 		}
-	}
 
-	if config.database_backups_to_keep == 0 {
 		warn!("Configuration item `database_backups_to_keep` is set to 0.");
 	}
 
 	Ok(())
 }
 
+/// Creates an incremental backup into the path, or the configured
+/// `database_backup_path`, then purges the oldest beyond
+/// `database_backups_to_keep`. Blocks until the backup is complete; only one
+/// backup runs at a time.
+#[implement(Engine)]
+#[tracing::instrument(skip(self))]
+pub fn backup_create(&self, path: Option<&Path>) -> Result<BackupEntry> {
+	let path = match path {
+		| Some(path) => path.to_owned(),
+		| None => self.backup_path()?.into(),
+	};
+
+	BackupRun::begin(&mut self.backup_run.lock()?, &path, SystemTime::now())?;
+
+	let result = self.backup_into(&path);
+	if let Some(run) = self.backup_run.lock()?.as_mut() {
+		run.finish(&result, SystemTime::now());
+	}
+
+	let entry = result?;
+	info!(
+		"Created database backup #{} using {} bytes in {} files",
+		entry.id, entry.size, entry.num_files,
+	);
+
+	Ok(entry)
+}
+
+#[implement(Engine)]
+fn backup_into(&self, path: &Path) -> Result<BackupEntry> {
+	let mut engine = self.backup_engine(Some(path))?;
+	let flush = !self.is_read_only();
+	engine
+		.create_new_backup_flush(&self.db, flush)
+		.map_err(map_err)?;
+
+	let Some(entry) = engine
+		.get_backup_info()
+		.last()
+		.map(BackupEntry::from)
+	else {
+		return Err!(Database("Backup engine holds no backup after creating one."));
+	};
+
+	let keep = self.ctx.server.config.database_backups_to_keep;
+	if keep > 0 {
+		let keep = u32::try_from(keep)?.try_into()?;
+		if let Err(e) = engine.purge_old_backups(keep) {
+			error!("Failed to purge old backup: {e:?}");
+		}
+	}
+
+	Ok(entry)
+}
+
+/// The backup in progress with the bytes it wrote so far, or else the last
+/// backup since startup.
+#[implement(Engine)]
+pub fn backup_status(&self) -> Result<Option<BackupRun>> {
+	let Some(mut run) = self.backup_run.lock()?.clone() else {
+		return Ok(None);
+	};
+
+	if run.finished.is_none() {
+		run.written = dir_size(&run.path).saturating_sub(run.size_at_start);
+	}
+
+	Ok(Some(run))
+}
+
+/// The backups in the path, or the configured `database_backup_path`, oldest
+/// first.
+#[implement(Engine)]
+pub fn backup_entries(&self, path: Option<&Path>) -> Result<Vec<BackupEntry>> {
+	let info = self.backup_engine(path)?.get_backup_info();
+
+	Ok(info.iter().map(BackupEntry::from).collect())
+}
+
 #[implement(Engine)]
 pub fn backup_list(&self) -> Result<impl Iterator<Item = String> + Send> {
-	let info = self.backup_engine()?.get_backup_info();
+	let entries = self.backup_entries(None)?;
 
-	if info.is_empty() {
+	if entries.is_empty() {
 		return Err!("No backups found.");
 	}
 
-	let list = info.into_iter().map(|info| {
-		format!(
-			"#{} {}: {} bytes, {} files",
-			info.backup_id,
-			rfc2822_from_seconds(info.timestamp),
-			info.size,
-			info.num_files,
-		)
-	});
-
-	Ok(list)
+	Ok(entries.into_iter().map(|entry| entry.to_string()))
 }
 
 #[implement(Engine)]
 pub fn backup_count(&self) -> Result<usize> {
-	let info = self.backup_engine()?.get_backup_info();
+	let info = self.backup_engine(None)?.get_backup_info();
 
 	Ok(info.len())
 }
 
 #[implement(Engine)]
-fn backup_engine(&self) -> Result<BackupEngine> {
-	let path = self.backup_path()?;
+fn backup_engine(&self, path: Option<&Path>) -> Result<BackupEngine> {
+	let path = match path {
+		| Some(path) => path.as_os_str().to_owned(),
+		| None => self.backup_path()?,
+	};
+
 	let options = BackupEngineOptions::new(path).map_err(map_err)?;
 	BackupEngine::open(&options, &*self.ctx.env.lock()?).map_err(map_err)
 }
@@ -95,3 +181,79 @@ fn backup_path(&self) -> Result<OsString> {
 
 	Ok(path)
 }
+
+impl BackupRun {
+	/// Starts a run into the path unless one is in progress.
+	pub(crate) fn begin(run: &mut Option<Self>, path: &Path, now: SystemTime) -> Result {
+		if let Some(running) = run.as_ref().filter(|run| run.finished.is_none()) {
+			return Err!(
+				"A backup into {:?} is already in progress since {}.",
+				running.path,
+				rfc2822_from_seconds(epoch_secs(running.started)),
+			);
+		}
+
+		run.replace(Self {
+			path: path.to_owned(),
+			started: now,
+			written: 0,
+			finished: None,
+			size_at_start: dir_size(path),
+		});
+
+		Ok(())
+	}
+
+	pub(crate) fn finish(&mut self, result: &Result<BackupEntry>, now: SystemTime) {
+		let result = match result {
+			| Ok(entry) => Ok(entry.clone()),
+			| Err(e) => Err(e.to_string()),
+		};
+
+		self.finished = Some((now, result));
+	}
+}
+
+impl From<&BackupEngineInfo> for BackupEntry {
+	fn from(info: &BackupEngineInfo) -> Self {
+		Self {
+			id: info.backup_id,
+			timestamp: info.timestamp,
+			size: info.size,
+			num_files: info.num_files,
+		}
+	}
+}
+
+impl fmt::Display for BackupEntry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"#{} {}: {} bytes, {} files",
+			self.id,
+			rfc2822_from_seconds(self.timestamp),
+			self.size,
+			self.num_files,
+		)
+	}
+}
+
+fn epoch_secs(time: SystemTime) -> i64 {
+	time.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|since| since.as_secs().try_into().unwrap_or(i64::MAX))
+		.unwrap_or_default()
+}
+
+/// Total size of the files under the path.
+fn dir_size(path: &Path) -> u64 {
+	std::fs::read_dir(path)
+		.into_iter()
+		.flatten()
+		.flatten()
+		.map(|entry| match entry.metadata() {
+			| Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+			| Ok(metadata) => metadata.len(),
+			| Err(_) => 0,
+		})
+		.fold(0, u64::saturating_add)
+}
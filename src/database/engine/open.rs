@@ -1,7 +1,10 @@
 use std::{
 	collections::BTreeSet,
 	path::Path,
-	sync::{Arc, atomic::AtomicU32},
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicU32, AtomicU64},
+	},
 };
 
 use rocksdb::{ColumnFamilyDescriptor, Options};
@@ -63,6 +66,10 @@ pub(crate) async fn open(ctx: Arc<Context>, desc: &[Descriptor]) -> Result<Arc<S
 		secondary: config.rocksdb_secondary,
 		checksums: config.rocksdb_checksums,
 		corks: AtomicU32::new(0),
+		flushes: AtomicU64::new(0),
+		#[cfg(any(test, feature = "test"))]
+		queries: AtomicU64::new(0),
+		backup_run: Mutex::default(),
 	}))
 }
 
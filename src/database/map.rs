@@ -3,6 +3,7 @@
 mod contains;
 mod count;
 mod del;
+mod del_prefix;
 mod get;
 mod get_batch;
 mod insert;
@@ -36,12 +37,14 @@
 use rocksdb::{AsColumnFamilyRef, ColumnFamily, ReadOptions, WriteOptions};
 use tuwunel_core::Result;
 
-pub(crate) use self::options::{
-	cache_iter_options_default, cache_read_options_default, iter_options_default,
-	read_options_default, write_options_default,
-};
-use self::watch::Watch;
 pub use self::{get_batch::Get, qry_batch::Qry};
+pub(crate) use self::{
+	options::{
+		cache_iter_options_default, cache_read_options_default, iter_options_default,
+		read_options_default, write_options_default,
+	},
+	watch::Watch,
+};
 use crate::Engine;
 
 pub struct Map {
@@ -0,0 +1,32 @@
+use std::{fmt::Debug, sync::Arc};
+
+use serde::Serialize;
+use tuwunel_core::{
+	Result, implement, trace,
+	utils::stream::{DELETE_BATCH_SIZE, TryIgnore, delete_batched},
+};
+
+/// Delete all keys starting with the prefix in batches, yielding between
+/// them. Stops with an error when the server begins shutting down. Returns
+/// the number of keys deleted.
+#[implement(super::Map)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn del_prefix<P>(self: &Arc<Self>, prefix: &P) -> Result<usize>
+where
+	P: Serialize + ?Sized + Debug,
+{
+	let server = &self.db.ctx.server;
+	let keys = self.keys_prefix_raw(prefix).ignore_err();
+
+	delete_batched(
+		keys,
+		DELETE_BATCH_SIZE,
+		|key| {
+			trace!("Removing key: {key:?}");
+			self.remove(key);
+			Ok(())
+		},
+		|| server.is_stopping(),
+	)
+	.await
+}
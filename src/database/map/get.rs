@@ -23,6 +23,7 @@ pub fn get<K>(
 {
 	use crate::pool::Get;
 
+	self.db.queried();
 	let cached = self.get_cached(key);
 	if matches!(cached, Err(_) | Ok(Some(_))) {
 		return task::consume_budget()
@@ -64,6 +65,7 @@ pub fn get_blocking<K>(&self, key: &K) -> Result<Handle<'_>>
 where
 	K: AsRef<[u8]> + ?Sized,
 {
+	self.db.queried();
 	let res = self.get_blocking_opts(key, &self.read_options);
 	handle_from(res)
 }
@@ -48,6 +48,7 @@ pub(crate) fn get_batch<'a, S, K>(
 
 	keys.ready_chunks(automatic_amplification())
 		.widen_then(automatic_width(), |chunk| {
+			self.db.queried();
 			self.db.pool.execute_get(Get {
 				map: self.clone(),
 				key: chunk
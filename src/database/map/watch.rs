@@ -15,7 +15,7 @@
 type Watchers = Mutex<BTreeMap<KeyBuf, Sender<()>>>;
 
 #[derive(Default)]
-pub(super) struct Watch {
+pub(crate) struct Watch {
 	watchers: Watchers,
 }
 
@@ -33,27 +33,7 @@ pub fn watch_raw_prefix<'a, K>(&self, prefix: &'a K) -> impl Future<Output = ()>
 where
 	K: AsRef<[u8]> + ?Sized + 'a,
 {
-	let rx = match self
-		.watch
-		.watchers
-		.lock()
-		.expect("locked")
-		.entry(prefix.as_ref().into())
-	{
-		| Entry::Occupied(node) => node.get().subscribe(),
-		| Entry::Vacant(node) => {
-			let (tx, rx) = channel(());
-			node.insert(tx);
-			rx
-		},
-	};
-
-	async move {
-		pin_mut!(rx);
-		rx.changed()
-			.await
-			.expect("watcher sender dropped");
-	}
+	self.watch.prefix(prefix.as_ref())
 }
 
 #[implement(super::Map)]
@@ -61,19 +41,51 @@ pub(crate) fn notify<K>(&self, key: &K)
 where
 	K: AsRef<[u8]> + Ord + ?Sized,
 {
-	let range = RangeToInclusive::<KeyBuf> { end: key.as_ref().into() };
+	self.watch.notify(key.as_ref());
+}
+
+impl Watch {
+	/// Subscribe to writes of any key starting with `prefix`. The future
+	/// resolves on the first such write after this call.
+	pub(crate) fn prefix(&self, prefix: &[u8]) -> impl Future<Output = ()> + Send + use<> {
+		let rx = match self
+			.watchers
+			.lock()
+			.expect("locked")
+			.entry(prefix.into())
+		{
+			| Entry::Occupied(node) => node.get().subscribe(),
+			| Entry::Vacant(node) => {
+				let (tx, rx) = channel(());
+				node.insert(tx);
+				rx
+			},
+		};
 
-	let mut watchers = self.watch.watchers.lock().expect("locked");
+		async move {
+			pin_mut!(rx);
+			rx.changed()
+				.await
+				.expect("watcher sender dropped");
+		}
+	}
+
+	/// Wake all subscribers whose prefix matches `key`.
+	pub(crate) fn notify(&self, key: &[u8]) {
+		let range = RangeToInclusive::<KeyBuf> { end: key.into() };
 
-	watchers
-		.range(range)
-		.rev()
-		.take_while(|(k, _)| key.as_ref().starts_with(k))
-		.filter_map(|(k, tx)| tx.send(()).is_err().then_some(k))
-		.cloned()
-		.collect::<Vec<_>>()
-		.into_iter()
-		.for_each(|k| {
-			watchers.remove(&k);
-		});
+		let mut watchers = self.watchers.lock().expect("locked");
+
+		watchers
+			.range(range)
+			.rev()
+			.take_while(|(k, _)| key.starts_with(k))
+			.filter_map(|(k, tx)| tx.send(()).is_err().then_some(k))
+			.cloned()
+			.collect::<Vec<_>>()
+			.into_iter()
+			.for_each(|k| {
+				watchers.remove(&k);
+			});
+	}
 }
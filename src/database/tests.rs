@@ -2,6 +2,7 @@
 
 use std::fmt::Debug;
 
+use futures::FutureExt;
 use serde::Serialize;
 use tuwunel_core::{
 	arrayvec::ArrayVec,
@@ -9,8 +10,11 @@
 };
 
 use crate::{
-	Ignore, Interfix, de, ser,
+	BackupRun, Ignore, Interfix, de,
+	map::Watch,
+	ser,
 	ser::{Json, serialize_to_vec},
+	serialize_key,
 };
 
 #[test]
@@ -611,3 +615,48 @@ fn serde_tuple_option_none_none_none() {
 	assert_eq!(None, cc.0);
 	assert_eq!(bb, cc);
 }
+
+#[test]
+fn watch_prefix_knockedstate() {
+	let room_id: &RoomId = "!room:example.com".try_into().unwrap();
+	let user_id: &UserId = "@user:example.com".try_into().unwrap();
+	let other_id: &UserId = "@other:example.com".try_into().unwrap();
+
+	let watch = Watch::default();
+	let prefix = serialize_key((user_id, Interfix)).unwrap();
+	let pending = watch.prefix(&prefix);
+	let unrelated = watch.prefix(&serialize_key((other_id, Interfix)).unwrap());
+
+	// userroomid_knockedstate key written by mark_as_knocked()
+	let key = serialize_key((user_id, room_id)).unwrap();
+	watch.notify(&key);
+
+	assert!(pending.now_or_never().is_some(), "watch on user prefix not woken");
+	assert!(unrelated.now_or_never().is_none(), "watch on other user woken");
+}
+
+#[test]
+fn backup_run_one_at_a_time() {
+	use std::time::SystemTime;
+
+	use tuwunel_core::Err;
+
+	let path = std::env::temp_dir().join("tuwunel-backup-run-test");
+	let mut run = None;
+
+	BackupRun::begin(&mut run, &path, SystemTime::now()).expect("no backup is in progress");
+	BackupRun::begin(&mut run, &path, SystemTime::now()).expect_err("a backup is in progress");
+
+	run.as_mut()
+		.expect("a backup started")
+		.finish(&Err!("failed"), SystemTime::now());
+
+	assert!(
+		run.as_ref()
+			.and_then(|run| run.finished.as_ref())
+			.is_some_and(|(_, result)| result.is_err()),
+		"the failure of the backup is kept"
+	);
+
+	BackupRun::begin(&mut run, &path, SystemTime::now()).expect("the last backup finished");
+}
@@ -15,11 +15,11 @@ pub(super) fn command(mut item: ItemFn, _args: &[Meta]) -> Result<TokenStream> {
 	Ok(item.into_token_stream().into())
 }
 
-pub(super) fn command_dispatch(item: ItemEnum, _args: &[Meta]) -> Result<TokenStream> {
+pub(super) fn command_dispatch(mut item: ItemEnum, _args: &[Meta]) -> Result<TokenStream> {
 	let name = &item.ident;
 	let arm: Vec<TokenStream2> = item
 		.variants
-		.iter()
+		.iter_mut()
 		.map(dispatch_arm)
 		.try_collect()?;
 	let switch = quote! {
@@ -42,7 +42,13 @@ pub(super) async fn process(
 		.into())
 }
 
-fn dispatch_arm(v: &Variant) -> Result<TokenStream2> {
+fn dispatch_arm(v: &mut Variant) -> Result<TokenStream2> {
+	// Commands marked `#[mutating]` write to the database; they are refused
+	// when it is read-only. The marker is not emitted with the enum.
+	let mutating = v.attrs.iter().any(is_mutating);
+	v.attrs.retain(|attr| !is_mutating(attr));
+	let guard = mutating.then(|| quote! { context.check_writable()?; });
+
 	let name = &v.ident;
 	let target = camel_to_snake_string(&format!("{name}"));
 	let handler = Ident::new(&target, Span::call_site().into());
@@ -55,6 +61,7 @@ fn dispatch_arm(v: &Variant) -> Result<TokenStream2> {
 			let arg = field.clone();
 			quote! {
 				#name { #( #field ),* } => {
+					#guard
 					Box::pin(context.#handler(#( #arg ),*)).await
 				},
 			}
@@ -65,6 +72,7 @@ fn dispatch_arm(v: &Variant) -> Result<TokenStream2> {
 			};
 			quote! {
 				#name ( #field ) => {
+					#guard
 					Box::pin(#handler::process(#field, context)).await
 				}
 			}
@@ -72,6 +80,7 @@ fn dispatch_arm(v: &Variant) -> Result<TokenStream2> {
 		| Fields::Unit => {
 			quote! {
 				#name => {
+					#guard
 					Box::pin(context.#handler()).await
 				},
 			}
@@ -80,3 +89,5 @@ fn dispatch_arm(v: &Variant) -> Result<TokenStream2> {
 
 	Ok(res)
 }
+
+fn is_mutating(attr: &Attribute) -> bool { attr.path().is_ident("mutating") }
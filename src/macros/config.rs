@@ -79,13 +79,28 @@ fn generate_example(input: &ItemStruct, args: &[Meta], write: bool) -> Result<To
 	}
 
 	let mut summary: Vec<TokenStream2> = Vec::new();
+	let mut changes: Vec<TokenStream2> = Vec::new();
+	let mut merges: Vec<TokenStream2> = Vec::new();
 	if let Fields::Named(FieldsNamed { named, .. }) = &input.fields {
 		for field in named {
 			let Some(ident) = &field.ident else {
 				continue;
 			};
 
-			if ignore.contains(ident.to_string().as_str()) {
+			let name = ident.to_string();
+			changes.push(quote! {
+				if format!("{:?}", self.#ident) != format!("{:?}", other.#ident) {
+					changed.push(#name);
+				}
+			});
+
+			merges.push(quote! {
+				if names.contains(&#name) {
+					self.#ident.clone_from(&other.#ident);
+				}
+			});
+
+			if ignore.contains(name.as_str()) {
 				continue;
 			}
 
@@ -138,7 +153,6 @@ fn generate_example(input: &ItemStruct, args: &[Meta], write: bool) -> Result<To
 					quote! { format_args!("{:?}", self.#ident) }
 				};
 
-				let name = ident.to_string();
 				summary.push(quote! {
 					writeln!(out, "| {} | {} |", #name, #value)?;
 				});
@@ -163,6 +177,23 @@ fn fmt(&self, out: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 				Ok(())
 			}
 		}
+
+		impl #struct_name {
+			/// The names of the options whose value differs in the other
+			/// configuration.
+			#[must_use]
+			pub fn changed(&self, other: &Self) -> Vec<&'static str> {
+				let mut changed = Vec::new();
+				#( #changes )*
+				changed
+			}
+
+			/// Takes the value of the named options from the other
+			/// configuration.
+			pub fn merge(&mut self, other: &Self, names: &[&str]) {
+				#( #merges )*
+			}
+		}
 	};
 
 	Ok(display)
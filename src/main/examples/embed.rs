@@ -0,0 +1,50 @@
+//! Boots a server in-process on an ephemeral database, creates a user and
+//! posts a message to the admin room without going through HTTP.
+//!
+//! Run with `cargo run --example embed`.
+
+use std::sync::Arc;
+
+use tokio::runtime;
+use tuwunel_core::{Config, Result, Server, log::Log};
+use tuwunel_service::embed::Embedded;
+
+const CONFIG: &str = r#"
+[global]
+server_name = "embed.localhost"
+database_path = "/nonexistent"
+database_ephemeral = true
+allow_federation = false
+"#;
+
+fn main() -> Result {
+	let runtime = runtime::Builder::new_multi_thread()
+		.enable_all()
+		.build()?;
+
+	let mut config = Config::from_toml(CONFIG)?;
+	config.check()?;
+	let _database = config.ephemeral_database()?;
+
+	let server = Arc::new(Server::new(config, Some(runtime.handle().clone()), Log::default()));
+
+	runtime.block_on(async {
+		let services = tuwunel_router::start(&server).await?;
+		let embedded = Embedded::new(services.clone());
+
+		let user_id = embedded
+			.create_user("alice", Some("correct horse battery staple"))
+			.await?;
+
+		let admin_room = services.admin.get_admin_room().await?;
+		let server_user = &services.globals.server_user;
+		let event_id = embedded
+			.send_message(server_user, &admin_room, &format!("Hello from {user_id}, embedded."))
+			.await?;
+
+		println!("Posted {event_id} to the admin room {admin_room}.");
+
+		drop(embedded);
+		tuwunel_router::stop(services).await
+	})
+}
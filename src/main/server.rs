@@ -6,7 +6,10 @@
 	config::Config,
 	info,
 	log::Log,
-	utils::{stream, sys},
+	utils::{
+		stream,
+		sys::{self, EphemeralDir},
+	},
 };
 
 use crate::{clap::Args, logging::TracingFlameGuard};
@@ -26,6 +29,9 @@ pub(crate) struct Server {
 	#[cfg(all(tuwunel_mods, feature = "tuwunel_mods"))]
 	// Module instances; TODO: move to mods::loaded mgmt vector
 	pub(crate) mods: tokio::sync::RwLock<Vec<tuwunel_core::mods::Module>>,
+
+	/// Database directory deleted when the server is dropped, when ephemeral.
+	_ephemeral_dir: Option<EphemeralDir>,
 }
 
 impl Server {
@@ -42,7 +48,7 @@ pub(crate) fn new(
 			.flat_map(<[_]>::iter)
 			.map(PathBuf::as_path);
 
-		let config = Config::load(config_paths)
+		let mut config = Config::load(config_paths)
 			.and_then(|raw| crate::clap::update(raw, args))
 			.and_then(|raw| Config::new(&raw))?;
 
@@ -51,6 +57,8 @@ pub(crate) fn new(
 
 		config.check()?;
 
+		let ephemeral_dir = config.ephemeral_database()?;
+
 		#[cfg(feature = "sentry_telemetry")]
 		let sentry_guard = crate::sentry::init(&config);
 
@@ -83,6 +91,8 @@ pub(crate) fn new(
 
 			#[cfg(all(tuwunel_mods, feature = "tuwunel_mods"))]
 			mods: tokio::sync::RwLock::new(Vec::new()),
+
+			_ephemeral_dir: ephemeral_dir,
 		}))
 	}
 }
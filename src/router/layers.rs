@@ -49,6 +49,12 @@ pub(crate) fn build(services: &Arc<Services>) -> Result<(Router, Guard)> {
 	))]
 	let layers = layers.layer(compression_layer(server));
 
+	#[cfg(feature = "metrics")]
+	let layers = layers.layer(axum::middleware::from_fn_with_state(
+		Arc::clone(services),
+		crate::metrics::record,
+	));
+
 	let services_ = services.clone();
 	let layers = layers
 		.layer(SetSensitiveHeadersLayer::new([header::AUTHORIZATION]))
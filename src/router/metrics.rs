@@ -0,0 +1,81 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+	Router,
+	extract::{MatchedPath, State},
+	response::{IntoResponse, Response},
+	routing::get,
+};
+use http::{HeaderMap, StatusCode, header};
+use tuwunel_api::router::state;
+use tuwunel_core::utils::bytes::eq_constant_time;
+use tuwunel_service::Services;
+
+/// Adds the route serving the metrics when they are enabled.
+pub(crate) fn route(router: Router<state::State>, services: &Services) -> Router<state::State> {
+	if !services.metrics.enabled() {
+		return router;
+	}
+
+	router.route(&services.server.config.metrics.path, get(serve))
+}
+
+/// Records the count, status and latency of each request by its route.
+pub(crate) async fn record(
+	State(services): State<Arc<Services>>,
+	req: http::Request<axum::body::Body>,
+	next: axum::middleware::Next,
+) -> Response {
+	if !services.metrics.enabled() {
+		return next.run(req).await;
+	}
+
+	let method = req.method().clone();
+	let route = req
+		.extensions()
+		.get::<MatchedPath>()
+		.map_or("unmatched", MatchedPath::as_str)
+		.to_owned();
+
+	let timer = Instant::now();
+	let response = next.run(req).await;
+	let elapsed = timer.elapsed();
+
+	let labels = [("method", method.as_str()), ("route", route.as_str())];
+	services.metrics.observe(
+		"tuwunel_http_request_duration_seconds",
+		"Time taken to respond to requests.",
+		&labels,
+		elapsed,
+	);
+
+	let status = response.status();
+	services
+		.metrics
+		.inc("tuwunel_http_requests_total", "Requests handled.", &[
+			labels[0],
+			labels[1],
+			("status", status.as_str()),
+		]);
+
+	response
+}
+
+async fn serve(State(services): State<state::State>, headers: HeaderMap) -> Response {
+	if let Some(token) = &services.server.config.metrics.token {
+		let bearer = headers
+			.get(header::AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.strip_prefix("Bearer "));
+
+		if !bearer.is_some_and(|bearer| eq_constant_time(bearer.as_bytes(), token.as_bytes())) {
+			return StatusCode::UNAUTHORIZED.into_response();
+		}
+	}
+
+	match services.metrics.render() {
+		| Ok(body) =>
+			([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+		| Err(e) => e.into_response(),
+	}
+}
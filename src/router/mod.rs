@@ -1,6 +1,8 @@
 #![type_length_limit = "32768"] //TODO: reduce me
 
 mod layers;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod request;
 mod router;
 mod run;
@@ -10,10 +10,12 @@
 pub(crate) fn build(services: &Arc<Services>) -> (Router, Guard) {
 	let router = Router::<state::State>::new();
 	let (state, guard) = state::create(services.clone());
-	let router = tuwunel_api::router::build(router, &services.server)
-		.route("/", get(it_works))
-		.fallback(not_found)
-		.with_state(state);
+	let router = tuwunel_api::router::build(router, &services.server).route("/", get(it_works));
+
+	#[cfg(feature = "metrics")]
+	let router = crate::metrics::route(router, services);
+
+	let router = router.fallback(not_found).with_state(state);
 
 	(router, guard)
 }
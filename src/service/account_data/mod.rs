@@ -2,7 +2,7 @@
 
 use futures::{Stream, StreamExt, TryFutureExt};
 use ruma::{
-	RoomId, UserId,
+	OwnedRoomId, RoomId, UserId,
 	events::{
 		AnyGlobalAccountDataEvent, AnyRawAccountDataEvent, AnyRoomAccountDataEvent,
 		GlobalAccountDataEventType, RoomAccountDataEventType,
@@ -11,10 +11,10 @@
 };
 use serde::Deserialize;
 use tuwunel_core::{
-	Err, Result, err, implement,
+	Err, Result, at, err, implement,
 	utils::{ReadyExt, result::LogErr, stream::TryIgnore},
 };
-use tuwunel_database::{Deserialized, Handle, Ignore, Json, Map};
+use tuwunel_database::{Deserialized, Handle, Ignore, Interfix, Json, Map};
 
 pub struct Service {
 	services: Arc<crate::services::OnceServices>,
@@ -77,6 +77,12 @@ pub async fn update(
 		self.db.roomuserdataid_accountdata.remove(&prev);
 	}
 
+	if room_id.is_none()
+		&& event_type.to_string() == GlobalAccountDataEventType::PushRules.to_string()
+	{
+		self.services.pusher.forget_ruleset(user_id);
+	}
+
 	Ok(())
 }
 
@@ -159,3 +165,43 @@ pub fn changes_since<'a>(
 		})
 		.ignore_err()
 }
+
+/// Returns the type of each account data entry of the user, along with the
+/// room of room account data. Room account data is looked up in the rooms the
+/// user is joined to, invited to, knocking on or has left.
+#[implement(Service)]
+pub async fn user_data_types(&self, user_id: &UserId) -> Vec<(Option<OwnedRoomId>, String)> {
+	let state_cache = &self.services.state_cache;
+	let rooms: Vec<_> = state_cache
+		.rooms_joined(user_id)
+		.map(ToOwned::to_owned)
+		.chain(state_cache.rooms_invited(user_id).map(at!(0)))
+		.chain(state_cache.rooms_knocked(user_id).map(at!(0)))
+		.chain(state_cache.rooms_left(user_id).map(at!(0)))
+		.collect()
+		.await;
+
+	let mut types: Vec<_> = self.data_types(None, user_id).collect().await;
+	for room_id in &rooms {
+		self.data_types(Some(room_id), user_id)
+			.ready_for_each(|kind| types.push(kind))
+			.await;
+	}
+
+	types
+}
+
+#[implement(Service)]
+fn data_types<'a>(
+	&'a self,
+	room_id: Option<&'a RoomId>,
+	user_id: &'a UserId,
+) -> impl Stream<Item = (Option<OwnedRoomId>, String)> + Send + 'a {
+	type Key<'a> = (Option<&'a RoomId>, &'a UserId, &'a str);
+
+	self.db
+		.roomusertype_roomuserdataid
+		.keys_prefix(&(room_id, user_id, Interfix))
+		.ignore_err()
+		.map(|(room_id, _, kind): Key<'_>| (room_id.map(ToOwned::to_owned), kind.to_owned()))
+}
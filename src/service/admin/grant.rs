@@ -1,8 +1,8 @@
 use std::collections::BTreeMap;
 
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt};
 use ruma::{
-	RoomId, UserId,
+	OwnedUserId, RoomId, UserId,
 	events::{
 		RoomAccountDataEventType, StateEventType,
 		room::{
@@ -14,7 +14,8 @@
 	},
 };
 use tuwunel_core::{
-	Err, Result, debug_info, debug_warn, error, implement, matrix::pdu::PduBuilder,
+	Err, Result, debug_info, debug_warn, error, implement, info, matrix::pdu::PduBuilder,
+	messages::Message, utils::stream::TryIgnore, warn,
 };
 
 /// Invite the user to the tuwunel admin room.
@@ -165,7 +166,7 @@ pub async fn make_user_admin(&self, user_id: &UserId) -> Result {
 }
 
 #[implement(super::Service)]
-async fn set_room_tag(&self, room_id: &RoomId, user_id: &UserId, tag: &str) -> Result {
+pub(crate) async fn set_room_tag(&self, room_id: &RoomId, user_id: &UserId, tag: &str) -> Result {
 	let mut event = self
 		.services
 		.account_data
@@ -225,12 +226,18 @@ pub async fn revoke_admin(&self, user_id: &UserId) -> Result {
 		},
 	};
 
+	let reason = self
+		.services
+		.users
+		.message(user_id, Message::AdminRevoked, &[])
+		.await;
+
 	self.services
 		.timeline
 		.build_and_append_pdu(
 			PduBuilder::state(user_id.to_string(), &RoomMemberEventContent {
 				membership: Leave,
-				reason: Some("Admin Revoked".into()),
+				reason: Some(reason),
 				is_direct: None,
 				join_authorized_via_users_server: None,
 				third_party_invite: None,
@@ -244,3 +251,77 @@ pub async fn revoke_admin(&self, user_id: &UserId) -> Result {
 		.await
 		.map(|_| ())
 }
+
+/// Grants admin to the users listed in `admin_users`, creating the admin room
+/// if missing, and revokes it from the users granted this way but no longer
+/// listed. Admins granted otherwise are never revoked here.
+#[implement(super::Service)]
+pub(super) async fn grant_configured_admins(&self) {
+	if self.services.db.is_read_only() {
+		return;
+	}
+
+	let configured = &self.services.server.config.admin_users;
+	let granted: Vec<OwnedUserId> = self
+		.db
+		.userid_autoadmin
+		.keys()
+		.ignore_err()
+		.map(|user_id: &UserId| user_id.to_owned())
+		.collect()
+		.await;
+
+	if configured.is_empty() && granted.is_empty() {
+		return;
+	}
+
+	if !configured.is_empty()
+		&& self.get_admin_room().await.is_err()
+		&& let Err(e) = super::create_admin_room(self.services.get()).await
+	{
+		error!("Failed to create the admin room for admin_users: {e}");
+		return;
+	}
+
+	for user_id in configured {
+		if !self.services.globals.user_is_local(user_id) {
+			warn!(%user_id, "Skipping remote user listed in admin_users");
+			continue;
+		}
+
+		if !self.services.users.exists(user_id).await {
+			warn!(%user_id, "Skipping nonexistent user listed in admin_users");
+			continue;
+		}
+
+		if self.user_is_admin(user_id).await {
+			continue;
+		}
+
+		match self.make_user_admin(user_id).await {
+			| Err(e) => error!(%user_id, "Failed to grant admin to user of admin_users: {e}"),
+			| Ok(()) => {
+				self.db
+					.userid_autoadmin
+					.insert(user_id.as_str(), []);
+				info!(%user_id, "Granted admin to user of admin_users");
+			},
+		}
+	}
+
+	for user_id in granted
+		.iter()
+		.filter(|&user_id| !configured.contains(user_id))
+	{
+		if self.user_is_admin(user_id).await {
+			if let Err(e) = self.revoke_admin(user_id).await {
+				error!(%user_id, "Failed to revoke admin from user removed from admin_users: {e}");
+				continue;
+			}
+
+			info!(%user_id, "Revoked admin from user removed from admin_users");
+		}
+
+		self.db.userid_autoadmin.remove(user_id.as_str());
+	}
+}
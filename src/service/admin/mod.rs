@@ -2,6 +2,8 @@
 pub mod create;
 mod execute;
 mod grant;
+#[cfg(test)]
+mod tests;
 
 use std::{
 	pin::Pin,
@@ -19,11 +21,13 @@
 use tuwunel_core::{
 	Err, Error, Event, Result, debug, err, error, error::default_log, pdu::PduBuilder,
 };
+use tuwunel_database::Map;
 
 use crate::rooms::state::RoomMutexGuard;
 
 pub struct Service {
 	services: Arc<crate::services::OnceServices>,
+	db: Data,
 	channel: StdRwLock<Option<mpsc::Sender<CommandInput>>>,
 	pub handle: RwLock<Option<Processor>>,
 	pub complete: StdRwLock<Option<Completer>>,
@@ -31,6 +35,10 @@ pub struct Service {
 	pub console: Arc<console::Console>,
 }
 
+struct Data {
+	userid_autoadmin: Arc<Map>,
+}
+
 /// Inputs to a command are a multi-line string and optional reply_id.
 #[derive(Clone, Debug, Default)]
 pub struct CommandInput {
@@ -66,6 +74,9 @@ impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			services: args.services.clone(),
+			db: Data {
+				userid_autoadmin: args.db["userid_autoadmin"].clone(),
+			},
 			channel: StdRwLock::new(None),
 			handle: RwLock::new(None),
 			complete: StdRwLock::new(None),
@@ -84,6 +95,7 @@ async fn worker(self: Arc<Self>) -> Result {
 			.insert(sender);
 
 		self.startup_execute().await?;
+		self.grant_configured_admins().await;
 		self.console_auto_start().await;
 
 		loop {
@@ -0,0 +1,77 @@
+use ruma::{OwnedUserId, owned_user_id};
+use tuwunel_core::Config;
+
+use crate::{Services, fixture::Fixture};
+
+/// Replaces `admin_users` of the running config.
+fn set_admin_users(services: &Services, admin_users: Vec<OwnedUserId>) {
+	let config = Config {
+		admin_users,
+		..Config::clone(&services.server.config)
+	};
+
+	services
+		.server
+		.config
+		.update(config)
+		.expect("config updated");
+}
+
+/// The listed users are granted admin, and revoked once unlisted; admins
+/// granted otherwise are kept, and unusable entries are skipped.
+#[tokio::test]
+async fn configured_admins_granted_and_revoked() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+
+	let mut users = Vec::new();
+	for localpart in ["ops", "manual"] {
+		let user_id = embedded
+			.create_user(localpart, None)
+			.await
+			.expect("user created");
+
+		users.push(user_id);
+	}
+
+	drop(embedded);
+	let [ops, manual] = users.as_slice() else {
+		panic!("two users created");
+	};
+
+	set_admin_users(&fixture, vec![
+		ops.clone(),
+		owned_user_id!("@ghost:fixture.localhost"),
+		owned_user_id!("@remote:example.org"),
+	]);
+
+	fixture.admin.grant_configured_admins().await;
+	assert!(fixture.admin.get_admin_room().await.is_ok(), "admin room created");
+	assert!(fixture.admin.user_is_admin(ops).await, "listed user granted");
+
+	fixture
+		.admin
+		.make_user_admin(manual)
+		.await
+		.expect("admin granted by hand");
+
+	// Granting again leaves the listed admin as it is.
+	fixture.admin.grant_configured_admins().await;
+	assert!(fixture.admin.user_is_admin(ops).await, "listed user still admin");
+
+	set_admin_users(&fixture, Vec::new());
+	fixture.admin.grant_configured_admins().await;
+	assert!(!fixture.admin.user_is_admin(ops).await, "unlisted user revoked");
+	assert!(fixture.admin.user_is_admin(manual).await, "manual admin kept");
+
+	let granted = fixture
+		.admin
+		.db
+		.userid_autoadmin
+		.get(ops.as_str())
+		.await;
+
+	assert!(granted.is_err(), "revoked user forgotten");
+
+	fixture.stop().await;
+}
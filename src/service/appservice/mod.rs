@@ -1,23 +1,40 @@
 mod namespace_regex;
 mod registration_info;
+#[cfg(test)]
+mod tests;
+mod thirdparty;
 
 use std::{
 	collections::{BTreeMap, HashSet},
 	iter::IntoIterator,
 	sync::Arc,
+	time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
 use futures::{Future, FutureExt, Stream, StreamExt, TryStreamExt};
-use ruma::{RoomAliasId, RoomId, UserId, api::appservice::Registration};
+use http::StatusCode;
+use ruma::{
+	OwnedTransactionId, RoomAliasId, RoomId, ServerName, UserId,
+	api::{
+		appservice::{Registration, ping::send_ping},
+		client::error::ErrorKind,
+	},
+};
 use tokio::sync::{RwLock, RwLockReadGuard};
-use tuwunel_core::{Err, Result, debug, err, utils::stream::IterStream};
+use tuwunel_core::{
+	Err, Error, Result, debug, err,
+	utils::{bytes::eq_constant_time, stream::IterStream},
+};
 use tuwunel_database::Map;
 
-pub use self::{namespace_regex::NamespaceRegex, registration_info::RegistrationInfo};
+pub use self::{
+	namespace_regex::NamespaceRegex, registration_info::RegistrationInfo, thirdparty::Protocols,
+};
 
 pub struct Service {
 	registration_info: RwLock<Registrations>,
+	protocols_cache: thirdparty::ProtocolsCache,
 	services: Arc<crate::services::OnceServices>,
 	db: Data,
 }
@@ -33,6 +50,7 @@ impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			registration_info: RwLock::new(BTreeMap::new()),
+			protocols_cache: Default::default(),
 			services: args.services.clone(),
 			db: Data {
 				id_appserviceregistrations: args.db["id_appserviceregistrations"].clone(),
@@ -53,26 +71,7 @@ fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 impl Service {
 	#[tracing::instrument(name = "init", skip(self))]
 	async fn init_registrations(&self) -> Result {
-		// Registrations from configuration file
-		let confs = self
-			.services
-			.server
-			.config
-			.appservice
-			.clone()
-			.into_iter()
-			.stream()
-			.map(|(id, mut reg)| {
-				reg.id.clone_from(&id);
-				reg.sender_localpart
-					.get_or_insert_with(|| id.clone());
-
-				Ok((id, reg))
-			});
-
-		// Registrations from database
-		self.iter_db_ids()
-			.chain(confs.map_ok(|(id, reg)| (id, reg.into())))
+		self.stored_registrations()
 			.try_for_each(async |(id, reg): (_, Registration)| {
 				debug!(?id, ?reg, "appservice registration");
 				self.registration_info
@@ -119,9 +118,107 @@ pub async fn register_appservice(
 			.id_appserviceregistrations
 			.insert(&registration.id, appservice_config_body);
 
+		self.forget_thirdparty_protocols();
+
 		Ok(())
 	}
 
+	/// Replaces a registration in place, either with the given one or by
+	/// re-reading the stored registration when none is given. The new
+	/// registration is validated against the others before it takes effect.
+	pub async fn reload_appservice(
+		&self,
+		id: &str,
+		update: Option<(&Registration, &str)>,
+	) -> Result<Registration> {
+		let registration = match update {
+			| Some((registration, _)) => registration.clone(),
+			| None => self.get_stored_registration(id).await?,
+		};
+
+		if registration.id != id {
+			return Err!(
+				"Registration ID {:?} does not match appservice {id:?}.",
+				registration.id
+			);
+		}
+
+		let info: RegistrationInfo = registration.clone().try_into()?;
+		let server_name = self.services.globals.server_name();
+		let previous = {
+			let mut regs = self.registration_info.write().await;
+			if !regs.contains_key(id) {
+				return Err!("Appservice {id:?} is not registered.");
+			}
+
+			swap_registration(&mut regs, info, server_name)?
+		};
+
+		if let Some((_, appservice_config_body)) = update {
+			self.db
+				.id_appserviceregistrations
+				.insert(id, appservice_config_body);
+		}
+
+		self.services
+			.state_cache
+			.clear_appservice_interest_for(id);
+
+		self.forget_thirdparty_protocols();
+
+		debug!(?id, replaced = previous.is_some(), "Reloaded appservice registration");
+
+		Ok(registration)
+	}
+
+	/// The registration as last stored in the database or, for appservices
+	/// registered in the config file, as currently configured.
+	async fn get_stored_registration(&self, id: &str) -> Result<Registration> {
+		if let Ok(registration) = self.get_db_registration(id).await {
+			return Ok(registration);
+		}
+
+		let mut reg = self
+			.services
+			.server
+			.config
+			.appservice
+			.get(id)
+			.cloned()
+			.ok_or_else(|| err!("Appservice {id:?} has no stored registration."))?;
+
+		reg.id = id.to_owned();
+		reg.sender_localpart
+			.get_or_insert_with(|| id.to_owned());
+
+		Ok(reg.into())
+	}
+
+	/// Pings the appservice to verify it is reachable and returns the round
+	/// trip time. Failures carry the error codes of the client ping endpoint.
+	pub async fn ping(
+		&self,
+		registration: &Registration,
+		transaction_id: Option<OwnedTransactionId>,
+	) -> Result<Duration> {
+		if !url_is_set(registration) {
+			return Err!(Request(UrlNotSet(
+				"Appservice does not have a URL set, there is nothing to ping."
+			)));
+		}
+
+		let timer = Instant::now();
+		self.services
+			.sending
+			.send_appservice_request(registration.clone(), send_ping::v1::Request {
+				transaction_id,
+			})
+			.await
+			.map_err(ping_error)?;
+
+		Ok(timer.elapsed())
+	}
+
 	/// Remove an appservice registration
 	///
 	/// # Arguments
@@ -140,6 +237,12 @@ pub async fn unregister_appservice(&self, appservice_id: &str) -> Result {
 			.id_appserviceregistrations
 			.remove(appservice_id);
 
+		self.services
+			.state_cache
+			.clear_appservice_interest_for(appservice_id);
+
+		self.forget_thirdparty_protocols();
+
 		// deletes all active requests for the appservice if there are any so we stop
 		// sending to the URL
 		self.services
@@ -161,7 +264,9 @@ pub async fn find_from_access_token(&self, token: &str) -> Result<RegistrationIn
 		self.read()
 			.await
 			.values()
-			.find(|info| info.registration.as_token == token)
+			.find(|info| {
+				eq_constant_time(info.registration.as_token.as_bytes(), token.as_bytes())
+			})
 			.cloned()
 			.ok_or_else(|| err!(Request(NotFound("Missing or invalid appservice token"))))
 	}
@@ -201,6 +306,33 @@ pub fn iter_ids(&self) -> impl Stream<Item = String> + Send {
 			.flatten_stream()
 	}
 
+	/// The registrations in the database and the configuration file, whether
+	/// or not they have been loaded yet.
+	pub fn stored_registrations(
+		&self,
+	) -> impl Stream<Item = Result<(String, Registration)>> + Send {
+		// Registrations from configuration file
+		let confs = self
+			.services
+			.server
+			.config
+			.appservice
+			.clone()
+			.into_iter()
+			.stream()
+			.map(|(id, mut reg)| {
+				reg.id.clone_from(&id);
+				reg.sender_localpart
+					.get_or_insert_with(|| id.clone());
+
+				Ok((id, reg))
+			});
+
+		// Registrations from database
+		self.iter_db_ids()
+			.chain(confs.map_ok(|(id, reg)| (id, reg.into())))
+	}
+
 	pub fn iter_db_ids(&self) -> impl Stream<Item = Result<(String, Registration)>> + Send {
 		self.db
 			.id_appserviceregistrations
@@ -223,3 +355,70 @@ pub fn read(&self) -> impl Future<Output = RwLockReadGuard<'_, Registrations>> +
 		self.registration_info.read()
 	}
 }
+
+fn url_is_set(registration: &Registration) -> bool {
+	registration
+		.url
+		.as_deref()
+		.is_some_and(|url| !url.is_empty() && url != "null")
+}
+
+/// Maps a failed ping to the errors the client ping endpoint specifies.
+fn ping_error(e: Error) -> Error {
+	let (kind, status) = match &e {
+		| Error::Reqwest(e) if e.is_timeout() =>
+			(ErrorKind::ConnectionTimeout, StatusCode::GATEWAY_TIMEOUT),
+		| Error::Reqwest(_) => (ErrorKind::ConnectionFailed, StatusCode::BAD_GATEWAY),
+		| _ => (ErrorKind::BadStatus { status: None, body: None }, StatusCode::BAD_GATEWAY),
+	};
+
+	Error::Request(kind, e.message().into(), status)
+}
+
+/// Validates a registration against the others and swaps it into place,
+/// returning the one it replaced. Its `as_token` must be unique and no other
+/// appservice may claim its sender exclusively, nor it theirs.
+fn swap_registration(
+	regs: &mut Registrations,
+	info: RegistrationInfo,
+	server_name: &ServerName,
+) -> Result<Option<RegistrationInfo>> {
+	let id = &info.registration.id;
+	let sender = |info: &RegistrationInfo| {
+		UserId::parse_with_server_name(info.registration.sender_localpart.as_str(), server_name)
+			.map_err(|e| err!("Invalid sender_localpart of appservice {id:?}: {e}"))
+	};
+
+	let own_sender = sender(&info)?;
+	for other in regs
+		.values()
+		.filter(|other| other.registration.id != *id)
+	{
+		if other.registration.as_token == info.registration.as_token {
+			return Err!("Appservice {:?} already uses this as_token.", other.registration.id);
+		}
+
+		if other
+			.users
+			.is_exclusive_match(own_sender.as_str())
+		{
+			return Err!(
+				"Sender {own_sender} is in the exclusive namespace of appservice {:?}.",
+				other.registration.id
+			);
+		}
+
+		let other_sender = sender(other)?;
+		if info
+			.users
+			.is_exclusive_match(other_sender.as_str())
+		{
+			return Err!(
+				"Exclusive namespace claims the sender {other_sender} of appservice {:?}.",
+				other.registration.id
+			);
+		}
+	}
+
+	Ok(regs.insert(id.clone(), info))
+}
@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use http::StatusCode;
+use ruma::{
+	api::{appservice::Registration, client::error::ErrorKind},
+	server_name, user_id,
+};
+use serde_json::json;
+use tuwunel_core::{Error, Result, err};
+
+use super::{
+	Protocols, RegistrationInfo, Registrations, ping_error, swap_registration,
+	thirdparty::{merge_protocol, query_all},
+	url_is_set,
+};
+
+fn registration(id: &str, as_token: &str, users: &str) -> RegistrationInfo {
+	let yaml = format!(
+		r#"
+id: {id}
+url: "http://localhost:8008"
+as_token: {as_token}
+hs_token: hs_{as_token}
+sender_localpart: {id}_bot
+namespaces:
+  users:
+    - exclusive: true
+      regex: "{users}"
+  aliases: []
+  rooms: []
+"#
+	);
+
+	serde_yaml::from_str::<Registration>(&yaml)
+		.expect("valid registration")
+		.try_into()
+		.expect("valid namespaces")
+}
+
+#[test]
+fn reload_changes_namespace() {
+	let server_name = server_name!("example.com");
+	let mut regs = Registrations::new();
+
+	swap_registration(
+		&mut regs,
+		registration("bridge", "token", "@old_.*:example.com"),
+		server_name,
+	)
+	.expect("registered");
+
+	let old_user = user_id!("@old_alice:example.com");
+	let new_user = user_id!("@new_alice:example.com");
+	assert!(regs["bridge"].is_exclusive_user_match(old_user));
+	assert!(!regs["bridge"].is_exclusive_user_match(new_user));
+
+	let previous = swap_registration(
+		&mut regs,
+		registration("bridge", "token", "@new_.*:example.com"),
+		server_name,
+	)
+	.expect("reloaded");
+
+	assert!(previous.is_some(), "reload replaces the registration");
+	assert_eq!(regs.len(), 1);
+	assert!(!regs["bridge"].is_exclusive_user_match(old_user));
+	assert!(regs["bridge"].is_exclusive_user_match(new_user));
+}
+
+#[test]
+fn reload_rejects_duplicate_token() {
+	let server_name = server_name!("example.com");
+	let mut regs = Registrations::new();
+
+	swap_registration(
+		&mut regs,
+		registration("one", "token", "@one_.*:example.com"),
+		server_name,
+	)
+	.expect("registered");
+
+	swap_registration(
+		&mut regs,
+		registration("two", "token", "@two_.*:example.com"),
+		server_name,
+	)
+	.expect_err("as_token must be unique");
+
+	assert!(!regs.contains_key("two"));
+}
+
+#[test]
+fn reload_rejects_claiming_other_sender() {
+	let server_name = server_name!("example.com");
+	let mut regs = Registrations::new();
+
+	swap_registration(
+		&mut regs,
+		registration("one", "token1", "@one_.*:example.com"),
+		server_name,
+	)
+	.expect("registered");
+
+	swap_registration(
+		&mut regs,
+		registration("two", "token2", "@two_.*:example.com"),
+		server_name,
+	)
+	.expect("registered");
+
+	// The sender of "one" is @one_bot:example.com.
+	swap_registration(
+		&mut regs,
+		registration("two", "token2", "@.*_bot:example.com"),
+		server_name,
+	)
+	.expect_err("exclusive namespace must not claim another sender");
+
+	let one_sender = user_id!("@one_bot:example.com");
+	assert!(!regs["two"].is_exclusive_user_match(one_sender));
+}
+
+#[test]
+fn ping_requires_url() {
+	let mut reg = registration("bridge", "token", "@bridge_.*:example.com").registration;
+	assert!(url_is_set(&reg));
+
+	reg.url = Some("null".to_owned());
+	assert!(!url_is_set(&reg));
+
+	reg.url = None;
+	assert!(!url_is_set(&reg));
+}
+
+#[test]
+fn ping_bad_response_is_bad_status() {
+	let error = ping_error(err!(BadServerResponse("Appservice returned 500")));
+	let Error::Request(kind, _, status) = error else {
+		panic!("expected a request error");
+	};
+
+	assert!(matches!(kind, ErrorKind::BadStatus { .. }));
+	assert_eq!(status, StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn thirdparty_query_isolates_timeout() {
+	let registrations = [
+		registration("irc", "token1", "@irc_.*:example.com").registration,
+		registration("slack", "token2", "@slack_.*:example.com").registration,
+	];
+
+	let query = async |registration: &Registration| -> Result<&'static str> {
+		if registration.id == "slack" {
+			tokio::time::sleep(Duration::from_secs(60)).await;
+		}
+
+		Ok("#matrix:irc.example.com")
+	};
+
+	let answers = query_all(&registrations, Duration::from_millis(50), query).await;
+	assert_eq!(
+		answers,
+		[("irc".to_owned(), "#matrix:irc.example.com")],
+		"the appservice answering is kept and the one timing out is left out"
+	);
+}
+
+#[test]
+fn thirdparty_protocols_merged() {
+	let protocol = |network: &str| {
+		json!({
+			"user_fields": ["nick"],
+			"location_fields": ["channel"],
+			"icon": "mxc://example.com/irc",
+			"field_types": {},
+			"instances": [{ "desc": network, "network_id": network, "fields": {} }],
+		})
+	};
+
+	let mut protocols = Protocols::new();
+	merge_protocol(&mut protocols, "libera", "irc", protocol("libera")).expect("valid protocol");
+	merge_protocol(&mut protocols, "oftc", "irc", protocol("oftc")).expect("valid protocol");
+
+	let irc = serde_json::to_value(&protocols["irc"]).expect("serializable protocol");
+	let instance_ids: Vec<_> = irc["instances"]
+		.as_array()
+		.expect("instances")
+		.iter()
+		.map(|instance| instance["instance_id"].as_str())
+		.collect();
+
+	assert_eq!(protocols.len(), 1, "the protocol of both appservices is listed once");
+	assert_eq!(
+		instance_ids,
+		[Some("libera|libera"), Some("oftc|oftc")],
+		"the instances of both appservices are listed with unique IDs"
+	);
+}
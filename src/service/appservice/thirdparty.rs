@@ -0,0 +1,288 @@
+//! Third-party networks bridged by appservices, looked up for clients. Only
+//! the appservices declaring protocols in their registration are asked, each
+//! within `QUERY_TIMEOUT`; one failing or not answering in time only leaves out
+//! its own results. The protocols are cached for `PROTOCOLS_CACHE_TTL`.
+
+use std::{
+	collections::BTreeMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use ruma::{
+	RoomAliasId, UserId,
+	api::appservice::{
+		Registration,
+		thirdparty::{
+			get_location_for_protocol, get_location_for_room_alias, get_protocol,
+			get_user_for_protocol, get_user_for_user_id,
+		},
+	},
+	thirdparty::{Location, Protocol, User},
+};
+use serde_json::Value as JsonValue;
+use tuwunel_core::{Err, Result, debug_warn, err, implement};
+
+/// Longest wait for the answer of one appservice.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the protocols of the appservices are served from the cache.
+const PROTOCOLS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+pub type Protocols = BTreeMap<String, Protocol>;
+
+/// The protocols of the appservices with when they were fetched.
+pub(super) type ProtocolsCache = Mutex<Option<(Instant, Protocols)>>;
+
+/// The protocols of all appservices, merged by protocol.
+#[implement(super::Service)]
+pub async fn thirdparty_protocols(&self) -> Protocols {
+	if let Some((_, protocols)) = self
+		.protocols_cache
+		.lock()
+		.expect("locked")
+		.as_ref()
+		.filter(|(fetched_at, _)| fetched_at.elapsed() < PROTOCOLS_CACHE_TTL)
+	{
+		return protocols.clone();
+	}
+
+	let registrations = self.protocol_registrations(None).await;
+	let query = async |registration: &Registration| self.fetch_protocols(registration).await;
+
+	let mut protocols = Protocols::new();
+	for (id, answers) in query_all(&registrations, QUERY_TIMEOUT, query).await {
+		for (name, protocol) in answers {
+			merge_protocol(&mut protocols, &id, &name, protocol)
+				.unwrap_or_else(|e| debug_warn!(%id, %name, "Invalid protocol: {e}"));
+		}
+	}
+
+	self.protocols_cache
+		.lock()
+		.expect("locked")
+		.replace((Instant::now(), protocols.clone()));
+
+	protocols
+}
+
+/// The protocols the appservice declares, as it describes them.
+#[implement(super::Service)]
+async fn fetch_protocols(&self, registration: &Registration) -> Result<Vec<(String, JsonValue)>> {
+	let names = registration.protocols.iter().flatten();
+	let requests = names.map(|name| async move {
+		let request = get_protocol::v1::Request::new(name.clone());
+		let response = self.send(registration, request).await?;
+		let protocol = serde_json::to_value(response.protocol)?;
+
+		Result::<_>::Ok((name.clone(), protocol))
+	});
+
+	join_all(requests).await.into_iter().collect()
+}
+
+/// Drops the cached protocols, so the next lookup asks the appservices again.
+#[implement(super::Service)]
+pub fn forget_thirdparty_protocols(&self) {
+	self.protocols_cache
+		.lock()
+		.expect("locked")
+		.take();
+}
+
+/// The locations of the protocol matching the fields.
+#[implement(super::Service)]
+pub async fn thirdparty_locations_for_protocol(
+	&self,
+	protocol: &str,
+	fields: &BTreeMap<String, String>,
+) -> Result<Vec<Location>> {
+	let registrations = self.protocol_registrations(Some(protocol)).await;
+	if registrations.is_empty() {
+		return Err!(Request(NotFound("No appservice provides the protocol {protocol:?}.")));
+	}
+
+	let query = async |registration: &Registration| -> Result<Vec<Location>> {
+		let mut request = get_location_for_protocol::v1::Request::new(protocol.to_owned());
+		request.fields.clone_from(fields);
+
+		Ok(self.send(registration, request).await?.locations)
+	};
+
+	Ok(query_all(&registrations, QUERY_TIMEOUT, query)
+		.await
+		.into_iter()
+		.flat_map(|(_, locations)| locations)
+		.collect())
+}
+
+/// The locations of all protocols for the room alias.
+#[implement(super::Service)]
+pub async fn thirdparty_locations_for_alias(&self, alias: &RoomAliasId) -> Vec<Location> {
+	let registrations = self.protocol_registrations(None).await;
+	let query = async |registration: &Registration| -> Result<Vec<Location>> {
+		let request = get_location_for_room_alias::v1::Request::new(alias.to_owned());
+
+		Ok(self.send(registration, request).await?.locations)
+	};
+
+	query_all(&registrations, QUERY_TIMEOUT, query)
+		.await
+		.into_iter()
+		.flat_map(|(_, locations)| locations)
+		.collect()
+}
+
+/// The users of the protocol matching the fields.
+#[implement(super::Service)]
+pub async fn thirdparty_users_for_protocol(
+	&self,
+	protocol: &str,
+	fields: &BTreeMap<String, String>,
+) -> Result<Vec<User>> {
+	let registrations = self.protocol_registrations(Some(protocol)).await;
+	if registrations.is_empty() {
+		return Err!(Request(NotFound("No appservice provides the protocol {protocol:?}.")));
+	}
+
+	let query = async |registration: &Registration| -> Result<Vec<User>> {
+		let mut request = get_user_for_protocol::v1::Request::new(protocol.to_owned());
+		request.fields.clone_from(fields);
+
+		Ok(self.send(registration, request).await?.users)
+	};
+
+	Ok(query_all(&registrations, QUERY_TIMEOUT, query)
+		.await
+		.into_iter()
+		.flat_map(|(_, users)| users)
+		.collect())
+}
+
+/// The users of all protocols for the Matrix user.
+#[implement(super::Service)]
+pub async fn thirdparty_users_for_user_id(&self, user_id: &UserId) -> Vec<User> {
+	let registrations = self.protocol_registrations(None).await;
+	let query = async |registration: &Registration| -> Result<Vec<User>> {
+		let request = get_user_for_user_id::v1::Request::new(user_id.to_owned());
+
+		Ok(self.send(registration, request).await?.users)
+	};
+
+	query_all(&registrations, QUERY_TIMEOUT, query)
+		.await
+		.into_iter()
+		.flat_map(|(_, users)| users)
+		.collect()
+}
+
+/// The registrations declaring protocols, or declaring the protocol when one
+/// is given.
+#[implement(super::Service)]
+async fn protocol_registrations(&self, protocol: Option<&str>) -> Vec<Registration> {
+	self.read()
+		.await
+		.values()
+		.map(|info| &info.registration)
+		.filter(|registration| {
+			registration
+				.protocols
+				.as_ref()
+				.is_some_and(|protocols| match protocol {
+					| Some(protocol) => protocols.iter().any(|name| name == protocol),
+					| None => !protocols.is_empty(),
+				})
+		})
+		.cloned()
+		.collect()
+}
+
+#[implement(super::Service)]
+async fn send<T>(&self, registration: &Registration, request: T) -> Result<T::IncomingResponse>
+where
+	T: ruma::api::OutgoingRequest + std::fmt::Debug + Send,
+{
+	self.services
+		.sending
+		.send_appservice_request(registration.clone(), request)
+		.await?
+		.ok_or_else(|| err!("Appservice {:?} has no URL.", registration.id))
+}
+
+/// The answers of the appservices to the query by appservice ID, asking them
+/// all at once. Those failing or not answering within the timeout are left
+/// out.
+pub(super) async fn query_all<T, F>(
+	registrations: &[Registration],
+	timeout: Duration,
+	query: F,
+) -> Vec<(String, T)>
+where
+	F: AsyncFn(&Registration) -> Result<T>,
+{
+	let answers = registrations.iter().map(|registration| {
+		let answer = tokio::time::timeout(timeout, query(registration));
+		async move {
+			let id = &registration.id;
+			match answer.await {
+				| Ok(Ok(answer)) => Some((id.clone(), answer)),
+				| Ok(Err(e)) => {
+					debug_warn!(%id, "Third-party lookup failed: {e}");
+					None
+				},
+				| Err(_) => {
+					debug_warn!(%id, "Third-party lookup timed out after {timeout:?}");
+					None
+				},
+			}
+		}
+	});
+
+	join_all(answers)
+		.await
+		.into_iter()
+		.flatten()
+		.collect()
+}
+
+/// Adds the protocol of the appservice to the protocols. Appservices name
+/// their instances by network; each gets an ID unique across appservices,
+/// and the instances of a protocol several appservices provide are merged.
+pub(super) fn merge_protocol(
+	protocols: &mut Protocols,
+	appservice_id: &str,
+	name: &str,
+	mut protocol: JsonValue,
+) -> Result {
+	let instances = protocol
+		.get_mut("instances")
+		.and_then(JsonValue::as_array_mut)
+		.into_iter()
+		.flatten()
+		.filter_map(JsonValue::as_object_mut);
+
+	for instance in instances {
+		if instance.contains_key("instance_id") {
+			continue;
+		}
+
+		let network_id = instance
+			.get("network_id")
+			.and_then(JsonValue::as_str)
+			.unwrap_or_default();
+
+		let instance_id = format!("{appservice_id}|{network_id}");
+		instance.insert("instance_id".into(), instance_id.into());
+	}
+
+	let protocol: Protocol = serde_json::from_value(protocol)?;
+	match protocols.get_mut(name) {
+		| Some(merged) => merged.instances.extend(protocol.instances),
+		| None => {
+			protocols.insert(name.to_owned(), protocol);
+		},
+	}
+
+	Ok(())
+}
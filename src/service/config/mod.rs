@@ -4,11 +4,14 @@
 use tuwunel_core::{
 	Result, Server,
 	config::{Config, check},
-	error, implement,
+	err, error, implement,
+	log::EnvFilter,
+	warn,
 };
 
 pub struct Service {
 	server: Arc<Server>,
+	services: Arc<crate::services::OnceServices>,
 }
 
 const SIGNAL: &str = "SIGUSR1";
@@ -16,7 +19,10 @@ pub struct Service {
 #[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
-		Ok(Arc::new(Self { server: args.server.clone() }))
+		Ok(Arc::new(Self {
+			server: args.server.clone(),
+			services: args.services.clone(),
+		}))
 	}
 
 	async fn worker(self: Arc<Self>) -> Result {
@@ -58,8 +64,19 @@ fn handle_reload(&self) -> Result {
 	Ok(())
 }
 
+/// The names of the changed options of a reloaded configuration.
+pub struct Reloaded {
+	/// Options which took effect.
+	pub applied: Vec<&'static str>,
+
+	/// Options which take effect at the next restart.
+	pub restart: Vec<&'static str>,
+}
+
+/// Reloads the configuration, applying all but the options read only at
+/// startup. The configuration is kept as it is when the new one is invalid.
 #[implement(Service)]
-pub fn reload<'a, I>(&self, paths: I) -> Result<Arc<Config>>
+pub fn reload<'a, I>(&self, paths: I) -> Result<Reloaded>
 where
 	I: Iterator<Item = &'a Path>,
 {
@@ -67,5 +84,22 @@ pub fn reload<'a, I>(&self, paths: I) -> Result<Arc<Config>>
 	let new = Config::load(paths).and_then(|raw| Config::new(&raw))?;
 
 	check::reload(&old, &new)?;
-	self.server.config.update(new)
+	let (config, applied, restart) = old.reloaded(&new);
+	if applied.contains(&"log") {
+		let filter = EnvFilter::try_new(&config.log)
+			.map_err(|e| err!(Config("log", "Invalid log level filter: {e}")))?;
+
+		self.server
+			.log
+			.reload
+			.reload(&filter, Some(&["console"]))?;
+	}
+
+	self.server.config.update(config)?;
+	self.services.globals.reload_secrets();
+	if !restart.is_empty() {
+		warn!(?restart, "Reloaded configuration; the other changed options need a restart");
+	}
+
+	Ok(Reloaded { applied, restart })
 }
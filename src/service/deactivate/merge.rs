@@ -0,0 +1,241 @@
+//! Merging a local account into another, e.g. one of two accounts differing
+//! only by the case of their localpart. The plan is worked out before
+//! anything changes so that a dry run reports what a real run does; the real
+//! run reports what it did.
+
+use std::fmt::Write as _;
+
+use futures::{FutureExt, StreamExt};
+use ruma::{OwnedRoomId, OwnedUserId, UserId};
+use serde_json::Value;
+use tuwunel_core::{Result, at, implement, info, utils::stream::IterStream};
+
+/// Room account data holding the tags of the room.
+const TAG: &str = "m.tag";
+
+/// Account data protected by the keys of the account it belongs to.
+const ENCRYPTION_DATA: &[&str] = &["m.cross_signing.", "m.megolm_backup.", "m.secret_storage."];
+
+const NOT_MERGED: &str = "Not merged:\n- Event history: events are signed by the homeserver in \
+                          the name of their sender and cannot be reassigned without breaking \
+                          their signatures and hashes.\n- End-to-end encryption keys: devices, \
+                          cross-signing keys, key backups and secret storage are bound to the \
+                          keys of the source account; the target must verify and back up its \
+                          own sessions.";
+
+/// One account data entry, with the room of room account data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountData {
+	pub room_id: Option<OwnedRoomId>,
+	pub kind: String,
+	pub content: Value,
+}
+
+/// Everything merging the source account into the target does.
+#[derive(Debug)]
+pub struct Merge {
+	pub from: OwnedUserId,
+	pub to: OwnedUserId,
+
+	/// Account data written to the target.
+	pub copied: Vec<AccountData>,
+
+	/// Account data of the source the target has its own of.
+	pub kept: Vec<AccountData>,
+
+	/// Encryption account data of the source, left alone.
+	pub refused: Vec<AccountData>,
+
+	/// Number of uploads by the source attributed to the target.
+	pub media: usize,
+
+	/// Rooms the source leaves or rejects the invite or knock of.
+	pub rooms: Vec<OwnedRoomId>,
+}
+
+/// What merging the source into the target would do, worked out from their
+/// account data, uploads and rooms; nothing is changed.
+#[implement(super::Service)]
+pub async fn plan_merge(&self, from: &UserId, to: &UserId) -> Merge {
+	let mut merge = Merge::new(from.to_owned(), to.to_owned());
+	let source = self.account_data(from).await;
+	let target = self.account_data(to).await;
+	merge.plan_account_data(source, &target);
+	merge.media = self.services.media.count_from_user(from).await;
+
+	let state_cache = &self.services.state_cache;
+	merge.rooms = state_cache
+		.rooms_joined(from)
+		.map(ToOwned::to_owned)
+		.chain(state_cache.rooms_invited(from).map(at!(0)))
+		.chain(state_cache.rooms_knocked(from).map(at!(0)))
+		.collect()
+		.await;
+
+	merge
+}
+
+/// Carries out the plan: copies the account data, attributes the uploads to
+/// the target and deactivates the source. Returns what was done.
+#[implement(super::Service)]
+pub async fn merge(&self, plan: &Merge) -> Result<Merge> {
+	let (from, to) = (&plan.from, &plan.to);
+	let mut done = Merge::new(from.clone(), to.clone());
+	done.kept.clone_from(&plan.kept);
+	done.refused.clone_from(&plan.refused);
+
+	for data in &plan.copied {
+		self.services
+			.account_data
+			.update(data.room_id.as_deref(), to, data.kind.as_str().into(), &data.content)
+			.await?;
+
+		done.copied.push(data.clone());
+	}
+
+	done.media = self
+		.services
+		.media
+		.reassign_from_user(from, to)
+		.await;
+
+	done.rooms = self
+		.full_deactivate(from, false)
+		.boxed()
+		.await?
+		.rooms_left;
+
+	info!("Merged {from} into {to}");
+
+	Ok(done)
+}
+
+/// The account data of the user, with its content.
+#[implement(super::Service)]
+async fn account_data(&self, user_id: &UserId) -> Vec<AccountData> {
+	let account_data = &self.services.account_data;
+	account_data
+		.user_data_types(user_id)
+		.await
+		.into_iter()
+		.stream()
+		.filter_map(async |(room_id, kind)| {
+			let content = account_data
+				.get_raw(room_id.as_deref(), user_id, &kind)
+				.await
+				.ok()
+				.and_then(|handle| serde_json::from_slice(&handle).ok())?;
+
+			Some(AccountData { room_id, kind, content })
+		})
+		.collect()
+		.await
+}
+
+impl Merge {
+	fn new(from: OwnedUserId, to: OwnedUserId) -> Self {
+		Self {
+			from,
+			to,
+			copied: Vec::new(),
+			kept: Vec::new(),
+			refused: Vec::new(),
+			media: 0,
+			rooms: Vec::new(),
+		}
+	}
+
+	/// Sorts the account data of the source into what is copied to the target,
+	/// what the target keeps its own of and what is refused. Room tags are
+	/// merged: the target gains the tags of the source it lacks.
+	fn plan_account_data(&mut self, source: Vec<AccountData>, target: &[AccountData]) {
+		for data in source {
+			if is_encryption_data(&data.kind) {
+				self.refused.push(data);
+				continue;
+			}
+
+			let existing = target
+				.iter()
+				.find(|existing| existing.room_id == data.room_id && existing.kind == data.kind);
+
+			match existing {
+				| None => self.copied.push(data),
+				| Some(existing) if data.kind == TAG => match merge_tags(existing, &data) {
+					| Some(merged) => self.copied.push(merged),
+					| None => self.kept.push(data),
+				},
+				| Some(_) => self.kept.push(data),
+			}
+		}
+	}
+
+	/// The report of the merge; a dry run differs only by its first line.
+	pub fn report(&self, dry_run: bool) -> String {
+		let (from, to) = (&self.from, &self.to);
+		let mut out = if dry_run {
+			format!("Dry run of merging {from} into {to}; nothing was changed.\n\n")
+		} else {
+			format!("Merged {from} into {to}.\n\n")
+		};
+
+		let list = |out: &mut String, title: &str, data: &[AccountData]| {
+			writeln!(out, "{title}: {}", data.len()).expect("written to string");
+			for data in data {
+				match &data.room_id {
+					| Some(room_id) => writeln!(out, "- {} in {room_id}", data.kind),
+					| None => writeln!(out, "- {}", data.kind),
+				}
+				.expect("written to string");
+			}
+		};
+
+		list(&mut out, "Account data copied to the target", &self.copied);
+		list(&mut out, "Account data the target already has", &self.kept);
+		list(&mut out, "Encryption account data left behind", &self.refused);
+
+		writeln!(out, "Uploads attributed to the target: {}", self.media)
+			.expect("written to string");
+
+		writeln!(out, "Rooms left by the source: {}", self.rooms.len())
+			.expect("written to string");
+
+		for room_id in &self.rooms {
+			writeln!(out, "- {room_id}").expect("written to string");
+		}
+
+		writeln!(out, "Source account deactivated.\n\n{NOT_MERGED}").expect("written to string");
+
+		out
+	}
+}
+
+fn is_encryption_data(kind: &str) -> bool {
+	ENCRYPTION_DATA
+		.iter()
+		.any(|prefix| kind.starts_with(prefix))
+}
+
+/// The tags of the target with those of the source it lacks added; `None`
+/// when it lacks none.
+fn merge_tags(target: &AccountData, source: &AccountData) -> Option<AccountData> {
+	let source_tags = source
+		.content
+		.pointer("/content/tags")?
+		.as_object()?;
+	let mut merged = target.clone();
+	let tags = merged
+		.content
+		.pointer_mut("/content/tags")?
+		.as_object_mut()?;
+
+	let mut added = false;
+	for (tag, info) in source_tags {
+		if !tags.contains_key(tag) {
+			tags.insert(tag.clone(), info.clone());
+			added = true;
+		}
+	}
+
+	added.then_some(merged)
+}
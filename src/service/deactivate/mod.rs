@@ -1,3 +1,8 @@
+mod merge;
+mod summary;
+#[cfg(test)]
+mod tests;
+
 use std::sync::Arc;
 
 use futures::{FutureExt, StreamExt};
@@ -5,7 +10,12 @@
 	OwnedRoomId, UserId,
 	events::{StateEventType, room::power_levels::RoomPowerLevelsEventContent},
 };
-use tuwunel_core::{Event, Result, info, pdu::PduBuilder, utils::ReadyExt, warn};
+use tuwunel_core::{Event, Result, debug_warn, info, pdu::PduBuilder, utils::ReadyExt};
+
+pub use self::{
+	merge::{AccountData, Merge},
+	summary::Deactivation,
+};
 
 pub struct Service {
 	services: Arc<crate::services::OnceServices>,
@@ -27,12 +37,24 @@ impl Service {
 	/// - Removing avatar URL and blurhash
 	/// - Removing all profile data
 	/// - Leaving all rooms (and forgets all of them)
-	pub async fn full_deactivate(&self, user_id: &UserId) -> Result {
+	/// - Deleting all uploaded media when `purge_media` is set
+	///
+	/// Steps failing past the first do not fail the deactivation; they are
+	/// listed in the summary returned, which is also noticed to the admin room.
+	pub async fn full_deactivate(
+		&self,
+		user_id: &UserId,
+		purge_media: bool,
+	) -> Result<Deactivation> {
 		self.services
 			.users
 			.deactivate_account(user_id)
 			.await?;
 
+		let mut summary = Deactivation::new(user_id.to_owned());
+
+		self.services.threepid.remove_all(user_id).await;
+
 		let all_joined_rooms: Vec<OwnedRoomId> = self
 			.services
 			.state_cache
@@ -105,10 +127,11 @@ pub async fn full_deactivate(&self, user_id: &UserId) -> Result {
 					.await
 				{
 					| Err(e) => {
-						warn!(%room_id, %user_id, "Failed to demote user's own power level: {e}");
+						debug_warn!(%room_id, %user_id, "Failed to demote user's own power level: {e}");
+						summary.fail(format!("demoting in {room_id}: {e}"));
 					},
 					| _ => {
-						info!("Demoted {user_id} in {room_id} as part of account deactivation");
+						summary.demoted.push(room_id.clone());
 					},
 				}
 			}
@@ -149,7 +172,8 @@ pub async fn full_deactivate(&self, user_id: &UserId) -> Result {
 				.boxed()
 				.await
 			{
-				warn!(%user_id, "Failed to leave {room_id} remotely: {e}");
+				debug_warn!(%user_id, "Failed to leave {room_id} remotely: {e}");
+				summary.fail(format!("leaving {room_id}: {e}"));
 			}
 
 			drop(state_lock);
@@ -157,8 +181,37 @@ pub async fn full_deactivate(&self, user_id: &UserId) -> Result {
 			self.services
 				.state_cache
 				.forget(&room_id, user_id);
+
+			summary.rooms_left.push(room_id);
+		}
+
+		if purge_media {
+			match self
+				.services
+				.media
+				.delete_from_user(user_id)
+				.await
+			{
+				| Ok(count) => summary.media_purged = Some(count),
+				| Err(e) => summary.fail(format!("purging media: {e}")),
+			}
+		}
+
+		info!(
+			rooms_left = summary.rooms_left.len(),
+			demoted = summary.demoted.len(),
+			media_purged = ?summary.media_purged,
+			failures = summary.failures.len(),
+			"Deactivated {user_id}"
+		);
+
+		if self.services.server.config.admin_room_notices {
+			self.services
+				.admin
+				.notice(&summary.to_string())
+				.await;
 		}
 
-		Ok(())
+		Ok(summary)
 	}
 }
@@ -0,0 +1,67 @@
+use std::fmt;
+
+use ruma::{OwnedRoomId, OwnedUserId};
+
+/// What deactivating an account did.
+#[derive(Debug)]
+pub struct Deactivation {
+	pub user_id: OwnedUserId,
+
+	/// Rooms left and forgotten, including invites and knocks.
+	pub rooms_left: Vec<OwnedRoomId>,
+
+	/// Rooms where the user removed their own power level.
+	pub demoted: Vec<OwnedRoomId>,
+
+	/// Uploads deleted; `None` unless media was purged.
+	pub media_purged: Option<usize>,
+
+	/// Steps which failed without failing the deactivation.
+	pub failures: Vec<String>,
+}
+
+impl Deactivation {
+	#[must_use]
+	pub fn new(user_id: OwnedUserId) -> Self {
+		Self {
+			user_id,
+			rooms_left: Vec::new(),
+			demoted: Vec::new(),
+			media_purged: None,
+			failures: Vec::new(),
+		}
+	}
+
+	pub(super) fn fail(&mut self, failure: String) { self.failures.push(failure); }
+}
+
+impl fmt::Display for Deactivation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Deactivated {}.", self.user_id)?;
+		writeln!(f)?;
+		write_rooms(f, "Rooms left", &self.rooms_left)?;
+		write_rooms(f, "Power levels demoted", &self.demoted)?;
+		if let Some(count) = self.media_purged {
+			writeln!(f, "- Media purged: {count}")?;
+		}
+
+		if !self.failures.is_empty() {
+			writeln!(f)?;
+			writeln!(f, "Failures:")?;
+			for failure in &self.failures {
+				writeln!(f, "- {failure}")?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn write_rooms(f: &mut fmt::Formatter<'_>, label: &str, rooms: &[OwnedRoomId]) -> fmt::Result {
+	if rooms.is_empty() {
+		return writeln!(f, "- {label}: 0");
+	}
+
+	let rooms: Vec<_> = rooms.iter().map(OwnedRoomId::as_str).collect();
+	writeln!(f, "- {label}: {} ({})", rooms.len(), rooms.join(", "))
+}
@@ -0,0 +1,249 @@
+use futures::StreamExt;
+use ruma::{Mxc, RoomId, UserId, owned_room_id, owned_user_id};
+use serde_json::{Value, json};
+use tuwunel_core::utils::random_string;
+
+use super::Deactivation;
+use crate::fixture::Fixture;
+
+/// A user with two uploads who was in three rooms, able to demote
+/// themselves in one and unable to reach another.
+fn fixture() -> Deactivation {
+	let mut summary = Deactivation::new(owned_user_id!("@alice:example.com"));
+	summary.rooms_left = vec![
+		owned_room_id!("!a:example.com"),
+		owned_room_id!("!b:example.com"),
+		owned_room_id!("!c:remote.example"),
+	];
+	summary.demoted = vec![owned_room_id!("!a:example.com")];
+	summary.media_purged = Some(2);
+	summary.fail("leaving !c:remote.example: unreachable".to_owned());
+	summary
+}
+
+#[test]
+fn summary_lists_every_action() {
+	let notice = fixture().to_string();
+
+	assert!(
+		notice.starts_with("Deactivated @alice:example.com."),
+		"names the user: {notice}"
+	);
+	assert!(
+		notice.contains("- Rooms left: 3 (!a:example.com, !b:example.com, !c:remote.example)"),
+		"lists the rooms left: {notice}"
+	);
+	assert!(
+		notice.contains("- Power levels demoted: 1 (!a:example.com)"),
+		"lists the demotions: {notice}"
+	);
+	assert!(notice.contains("- Media purged: 2"), "counts the uploads: {notice}");
+	assert!(
+		notice.contains("Failures:\n- leaving !c:remote.example: unreachable"),
+		"lists the failures: {notice}"
+	);
+}
+
+#[test]
+fn summary_omits_what_was_not_done() {
+	let mut summary = fixture();
+	summary.media_purged = None;
+	summary.failures.clear();
+	let notice = summary.to_string();
+
+	assert!(!notice.contains("Media purged"), "media was kept: {notice}");
+	assert!(!notice.contains("Failures"), "nothing failed: {notice}");
+
+	let notice = Deactivation::new(owned_user_id!("@bob:example.com")).to_string();
+	assert!(notice.contains("- Rooms left: 0\n"), "no rooms to leave: {notice}");
+}
+
+#[tokio::test]
+async fn deactivate_purges_uploads_and_leaves_rooms() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+
+	let own = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room of alice");
+	for _ in 0..2 {
+		let room_id = embedded
+			.create_room(&bob, None)
+			.await
+			.expect("room of bob");
+		embedded
+			.join_room(&alice, &room_id)
+			.await
+			.expect("alice joins");
+	}
+
+	for _ in 0..2 {
+		let media_id = random_string(32);
+		let mxc = Mxc {
+			server_name: fixture.globals.server_name(),
+			media_id: &media_id,
+		};
+		fixture
+			.media
+			.create(&mxc, Some(&alice), None, Some("text/plain"), b"upload")
+			.await
+			.expect("upload");
+	}
+	assert_eq!(fixture.media.count_from_user(&alice).await, 2, "two uploads");
+
+	let summary = fixture
+		.deactivate
+		.full_deactivate(&alice, true)
+		.await
+		.expect("deactivated");
+
+	assert_eq!(summary.rooms_left.len(), 3, "left every room: {summary}");
+	assert_eq!(summary.demoted, [own], "demoted only where able: {summary}");
+	assert_eq!(summary.media_purged, Some(2), "purged both uploads: {summary}");
+	assert!(summary.failures.is_empty(), "nothing failed: {summary}");
+
+	assert_eq!(fixture.media.count_from_user(&alice).await, 0, "uploads deleted");
+	assert_eq!(
+		fixture
+			.state_cache
+			.rooms_joined(&alice)
+			.count()
+			.await,
+		0,
+		"no rooms joined"
+	);
+	assert!(
+		fixture
+			.users
+			.is_deactivated(&alice)
+			.await
+			.expect("alice exists"),
+		"alice is deactivated"
+	);
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn merge_does_what_dry_run_reports() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let source = embedded
+		.create_user("old_alice", Some("password"))
+		.await
+		.expect("source");
+	let target = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("target");
+	let room_id = embedded
+		.create_room(&target, None)
+		.await
+		.expect("room");
+	embedded
+		.join_room(&source, &room_id)
+		.await
+		.expect("source joins");
+
+	let put = async |room_id: Option<&RoomId>, user_id: &UserId, kind: &str, content| {
+		fixture
+			.account_data
+			.update(room_id, user_id, kind.into(), &json!({ "type": kind, "content": content }))
+			.await
+			.expect("account data set");
+	};
+
+	put(None, &source, "m.direct", json!({})).await;
+	put(None, &source, "m.secret_storage.default_key", json!({ "key": "key" })).await;
+	put(
+		Some(&room_id),
+		&source,
+		"m.tag",
+		json!({ "tags": { "m.favourite": {}, "u.work": {} } }),
+	)
+	.await;
+	put(Some(&room_id), &target, "m.tag", json!({ "tags": { "m.favourite": {} } })).await;
+
+	let media_id = random_string(32);
+	let mxc = Mxc {
+		server_name: fixture.globals.server_name(),
+		media_id: &media_id,
+	};
+	fixture
+		.media
+		.create(&mxc, Some(&source), None, Some("text/plain"), b"upload")
+		.await
+		.expect("upload");
+
+	let plan = fixture
+		.deactivate
+		.plan_merge(&source, &target)
+		.await;
+	let kinds = |data: &[super::AccountData]| {
+		data.iter()
+			.map(|data| data.kind.as_str())
+			.collect::<Vec<_>>()
+	};
+	assert_eq!(kinds(&plan.copied), ["m.direct", "m.tag"], "missing data and tags copied");
+	assert_eq!(kinds(&plan.refused), ["m.secret_storage.default_key"], "keys stay behind");
+	assert_eq!(plan.media, 1, "the upload is counted");
+	assert_eq!(plan.rooms, [room_id.clone()], "the room is counted");
+
+	let dry_run = plan.report(true);
+	let done = fixture
+		.deactivate
+		.merge(&plan)
+		.await
+		.expect("merged");
+	let real_run = done.report(false);
+
+	assert!(dry_run.starts_with("Dry run"), "dry run announced: {dry_run}");
+	assert_eq!(
+		dry_run.split_once('\n').map(|(_, body)| body),
+		real_run.split_once('\n').map(|(_, body)| body),
+		"the merge did what the dry run reported"
+	);
+
+	let target_data = async |room_id: Option<&RoomId>, kind: &str| {
+		fixture
+			.account_data
+			.get_raw(room_id, &target, kind)
+			.await
+			.ok()
+			.and_then(|handle| serde_json::from_slice::<Value>(&handle).ok())
+	};
+	assert!(target_data(None, "m.direct").await.is_some(), "account data copied");
+	assert!(
+		target_data(None, "m.secret_storage.default_key")
+			.await
+			.is_none(),
+		"encryption account data not copied"
+	);
+	assert!(
+		target_data(Some(&room_id), "m.tag")
+			.await
+			.and_then(|tag| tag.pointer("/content/tags/u.work").cloned())
+			.is_some(),
+		"the target gains the tag it lacked"
+	);
+	assert_eq!(fixture.media.count_from_user(&target).await, 1, "upload reassigned");
+	assert!(
+		fixture
+			.users
+			.is_deactivated(&source)
+			.await
+			.expect("source exists"),
+		"source deactivated"
+	);
+
+	fixture.stop().await;
+}
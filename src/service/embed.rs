@@ -0,0 +1,151 @@
+//! Driving a running server from the same process rather than over HTTP, for
+//! tests, harnesses and custom binaries. The server is started and stopped
+//! with `start` and `stop` of the router; this facade covers common operations
+//! on the `Services` it returns.
+//!
+//! This interface is not covered by semver and may change in any release.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use futures::FutureExt;
+use ruma::{
+	OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
+	events::room::{
+		create::RoomCreateEventContent,
+		history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+		join_rules::{JoinRule, RoomJoinRulesEventContent},
+		member::{MembershipState, RoomMemberEventContent},
+		message::RoomMessageEventContent,
+		name::RoomNameEventContent,
+		power_levels::RoomPowerLevelsEventContent,
+	},
+};
+use tuwunel_core::{Err, Result, err, pdu::PduBuilder};
+
+use crate::Services;
+
+/// Common operations on a running server.
+#[derive(Clone)]
+pub struct Embedded {
+	pub services: Arc<Services>,
+}
+
+impl Embedded {
+	#[must_use]
+	pub fn new(services: Arc<Services>) -> Self { Self { services } }
+
+	/// Registers a local user with the password, or without one so it cannot
+	/// log in.
+	pub async fn create_user(
+		&self,
+		localpart: &str,
+		password: Option<&str>,
+	) -> Result<OwnedUserId> {
+		let user_id =
+			UserId::parse_with_server_name(localpart, self.services.globals.server_name())
+				.map_err(|e| err!(Request(InvalidUsername("Invalid localpart: {e}"))))?;
+
+		if self.services.users.exists(&user_id).await {
+			return Err!(Request(UserInUse("{user_id} is already taken.")));
+		}
+
+		self.services
+			.users
+			.create(&user_id, password, None)
+			.await?;
+
+		self.services
+			.users
+			.set_displayname(&user_id, Some(localpart.to_owned()));
+
+		Ok(user_id)
+	}
+
+	/// Creates a public room joined by the creator.
+	pub async fn create_room(&self, creator: &UserId, name: Option<&str>) -> Result<OwnedRoomId> {
+		let services = &self.services;
+		let room_id = RoomId::new_v1(services.globals.server_name());
+
+		let _short_id = services
+			.short
+			.get_or_create_shortroomid(&room_id)
+			.await;
+
+		let state_lock = services.state.mutex.lock(&room_id).await;
+
+		let users = BTreeMap::from_iter([(creator.to_owned(), 100.into())]);
+		let name = name.map(|name| RoomNameEventContent::new(name.to_owned()));
+		let events = [
+			PduBuilder::state(String::new(), &RoomCreateEventContent {
+				room_version: RoomVersionId::V11,
+				..RoomCreateEventContent::new_v11()
+			}),
+			PduBuilder::state(
+				String::from(creator),
+				&RoomMemberEventContent::new(MembershipState::Join),
+			),
+			PduBuilder::state(String::new(), &RoomPowerLevelsEventContent {
+				users,
+				..Default::default()
+			}),
+			PduBuilder::state(String::new(), &RoomJoinRulesEventContent::new(JoinRule::Public)),
+			PduBuilder::state(
+				String::new(),
+				&RoomHistoryVisibilityEventContent::new(HistoryVisibility::Shared),
+			),
+		];
+
+		for pdu in events
+			.into_iter()
+			.chain(name.map(|name| PduBuilder::state(String::new(), &name)))
+		{
+			services
+				.timeline
+				.build_and_append_pdu(pdu, creator, &room_id, &state_lock)
+				.boxed()
+				.await?;
+		}
+
+		Ok(room_id)
+	}
+
+	/// Joins the local user to the room, over federation when this server is
+	/// not in it.
+	pub async fn join_room(&self, user_id: &UserId, room_id: &RoomId) -> Result {
+		let state_lock = self.services.state.mutex.lock(room_id).await;
+
+		self.services
+			.membership
+			.join(user_id, room_id, None, &[], &None, &state_lock)
+			.boxed()
+			.await
+	}
+
+	/// Leaves the room as the local user.
+	pub async fn leave_room(&self, user_id: &UserId, room_id: &RoomId) -> Result {
+		let state_lock = self.services.state.mutex.lock(room_id).await;
+
+		self.services
+			.membership
+			.leave(user_id, room_id, None, false, &state_lock)
+			.boxed()
+			.await
+	}
+
+	/// Sends a plain text message to the room.
+	pub async fn send_message(
+		&self,
+		sender: &UserId,
+		room_id: &RoomId,
+		body: &str,
+	) -> Result<OwnedEventId> {
+		let content = RoomMessageEventContent::text_plain(body);
+		let state_lock = self.services.state.mutex.lock(room_id).await;
+
+		self.services
+			.timeline
+			.build_and_append_pdu(PduBuilder::timeline(&content), sender, room_id, &state_lock)
+			.boxed()
+			.await
+	}
+}
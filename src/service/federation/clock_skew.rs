@@ -0,0 +1,257 @@
+//! Clock skew of other servers, estimated from the timestamps they state: the
+//! `origin_server_ts` of their transactions and the `Date` of their responses.
+//! Delays only ever make a clock seem behind ours, so of the recent samples of
+//! an origin the one closest to ours is taken as its skew. When most origins
+//! are skewed the same way it is more likely our own clock which is wrong;
+//! the admins are told at most once a day unless the direction changes.
+
+use std::{
+	collections::VecDeque,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use lru_cache::LruCache;
+use ruma::{OwnedServerName, ServerName};
+use tuwunel_core::{implement, utils::millis_since_unix_epoch, warn};
+
+/// Samples kept per origin.
+const SAMPLES_MAX: usize = 8;
+
+/// Origins kept; the least recently seen is dropped beyond this.
+pub(super) const ORIGINS_MAX: usize = 512;
+
+/// Origins needed before our own clock can be implicated.
+const OWN_ORIGINS_MIN: usize = 5;
+
+/// Time before our own skew is reported again in the same direction.
+const OWN_NOTICE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub(super) type ClockSkew = Mutex<Detector>;
+
+/// Recent skews by origin, in milliseconds their clock is ahead of ours.
+pub(super) struct Detector {
+	origins: LruCache<OwnedServerName, VecDeque<i64>>,
+	threshold: i64,
+
+	/// When our own skew was last reported, and whether we were ahead.
+	own_noticed: Option<(Instant, bool)>,
+}
+
+/// Result of an observation worth reporting.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(super) struct Observed {
+	/// The sample exceeded the threshold.
+	pub(super) skewed: bool,
+
+	/// The estimate of the origin newly exceeded the threshold.
+	pub(super) origin_skewed: Option<i64>,
+
+	/// Our own clock seems to be off by this much, and it was not reported
+	/// recently.
+	pub(super) own_skewed: Option<i64>,
+}
+
+/// Records the time stated by the origin at about the time it was received,
+/// warning when the clock of the origin or our own seems to be wrong.
+#[implement(super::Service)]
+pub async fn observe_clock(&self, origin: &ServerName, origin_ts: u64) {
+	let skew = skew(origin_ts, millis_since_unix_epoch());
+	let observed = self
+		.clock_skew
+		.lock()
+		.expect("locked")
+		.observe(origin, skew, Instant::now());
+
+	if observed.skewed {
+		self.services.metrics.inc(
+			"tuwunel_federation_clock_skew_total",
+			"Timestamps from other servers over the clock skew threshold.",
+			&[],
+		);
+	}
+
+	if let Some(skew) = observed.origin_skewed {
+		warn!(
+			%origin,
+			skew_ms = skew,
+			"Clock of {origin} is {} ours; signatures and keys from it may fail to verify.",
+			describe_skew(skew),
+		);
+	}
+
+	if let Some(own_skew) = observed.own_skewed {
+		let notice = format!(
+			"The clock of this server seems to be {} most other servers. Signatures and keys \
+			 may fail to verify; check your system clock.",
+			describe_skew(own_skew),
+		);
+
+		warn!(skew_ms = own_skew, "{notice}");
+		self.services.admin.notice(&notice).await;
+	}
+}
+
+/// The skew of the origin in milliseconds ahead of our clock, if seen.
+#[implement(super::Service)]
+#[must_use]
+pub fn clock_skew(&self, origin: &ServerName) -> Option<i64> {
+	self.clock_skew
+		.lock()
+		.expect("locked")
+		.estimate(origin)
+}
+
+/// The skews of all recently seen origins.
+#[implement(super::Service)]
+#[must_use]
+pub fn clock_skews(&self) -> Vec<(OwnedServerName, i64)> {
+	self.clock_skew
+		.lock()
+		.expect("locked")
+		.estimates()
+}
+
+/// How far our own clock seems off from most other servers, if it does.
+#[implement(super::Service)]
+#[must_use]
+pub fn own_clock_skew(&self) -> Option<i64> { self.clock_skew.lock().expect("locked").own_skew() }
+
+impl Detector {
+	#[must_use]
+	pub(super) fn new(origins_max: usize, threshold: Duration) -> Self {
+		Self {
+			origins: LruCache::new(origins_max),
+			threshold: threshold
+				.as_millis()
+				.try_into()
+				.unwrap_or(i64::MAX),
+			own_noticed: None,
+		}
+	}
+
+	pub(super) fn observe(&mut self, origin: &ServerName, skew: i64, now: Instant) -> Observed {
+		let was_skewed = self
+			.estimate(origin)
+			.is_some_and(|skew| self.exceeds(skew));
+
+		if let Some(samples) = self.origins.get_mut(origin) {
+			if samples.len() >= SAMPLES_MAX {
+				samples.pop_front();
+			}
+
+			samples.push_back(skew);
+		} else {
+			self.origins
+				.insert(origin.to_owned(), [skew].into());
+		}
+
+		let origin_skewed = self
+			.estimate(origin)
+			.filter(|skew| !was_skewed && self.exceeds(*skew));
+
+		let own_skewed = self.own_skew().filter(|own_skew| {
+			self.own_noticed
+				.is_none_or(|(noticed, was_ahead)| {
+					was_ahead != (*own_skew > 0)
+						|| now.saturating_duration_since(noticed) >= OWN_NOTICE_INTERVAL
+				})
+		});
+
+		if let Some(own_skew) = own_skewed {
+			self.own_noticed = Some((now, own_skew > 0));
+		}
+
+		Observed {
+			skewed: self.exceeds(skew),
+			origin_skewed,
+			own_skewed,
+		}
+	}
+
+	/// The skew of the origin: the sample closest to our clock.
+	#[must_use]
+	pub(super) fn estimate(&mut self, origin: &ServerName) -> Option<i64> {
+		self.origins.get_mut(origin).and_then(estimate)
+	}
+
+	/// Our own skew, when at least three in four origins are skewed the same
+	/// way: the median of theirs, reversed.
+	#[must_use]
+	pub(super) fn own_skew(&self) -> Option<i64> {
+		let estimates: Vec<i64> = self
+			.origins
+			.iter()
+			.filter_map(|(_, samples)| estimate(samples))
+			.collect();
+
+		if estimates.len() < OWN_ORIGINS_MIN {
+			return None;
+		}
+
+		let mut ahead: Vec<i64> = estimates
+			.iter()
+			.copied()
+			.filter(|skew| *skew > 0 && self.exceeds(*skew))
+			.collect();
+
+		let mut behind: Vec<i64> = estimates
+			.iter()
+			.copied()
+			.filter(|skew| *skew < 0 && self.exceeds(*skew))
+			.collect();
+
+		let quorum = estimates.len().saturating_mul(3).div_ceil(4);
+		let skewed = if ahead.len() >= quorum {
+			&mut ahead
+		} else if behind.len() >= quorum {
+			&mut behind
+		} else {
+			return None;
+		};
+
+		skewed.sort_unstable();
+		let median = skewed[skewed.len() / 2];
+
+		Some(median.saturating_neg())
+	}
+
+	#[must_use]
+	pub(super) fn estimates(&self) -> Vec<(OwnedServerName, i64)> {
+		self.origins
+			.iter()
+			.filter_map(|(origin, samples)| Some((origin.clone(), estimate(samples)?)))
+			.collect()
+	}
+
+	#[must_use]
+	pub(super) fn len(&self) -> usize { self.origins.len() }
+
+	fn exceeds(&self, skew: i64) -> bool { skew.unsigned_abs() > self.threshold.unsigned_abs() }
+}
+
+fn estimate(samples: &VecDeque<i64>) -> Option<i64> {
+	samples
+		.iter()
+		.copied()
+		.min_by_key(|skew| skew.unsigned_abs())
+}
+
+/// Milliseconds the stated time is ahead of ours.
+#[must_use]
+pub(super) fn skew(origin_ts: u64, now: u64) -> i64 {
+	let origin_ts = i64::try_from(origin_ts).unwrap_or(i64::MAX);
+	let now = i64::try_from(now).unwrap_or(i64::MAX);
+
+	origin_ts.saturating_sub(now)
+}
+
+/// The skew in words, e.g. "5.0 minutes ahead of" for a clock 5 minutes ahead
+/// of the one it is compared to.
+#[must_use]
+pub fn describe_skew(skew: i64) -> String {
+	let duration = tuwunel_core::utils::time::pretty(Duration::from_millis(skew.unsigned_abs()));
+	let direction = if skew > 0 { "ahead of" } else { "behind" };
+
+	format!("{duration} {direction}")
+}
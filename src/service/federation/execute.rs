@@ -1,7 +1,10 @@
 use std::{fmt::Debug, mem};
 
 use bytes::Bytes;
-use http::{HeaderValue, header::AUTHORIZATION};
+use http::{
+	HeaderValue,
+	header::{AUTHORIZATION, DATE},
+};
 use ipaddress::IPAddress;
 use reqwest::{Client, Method, Request, Response, Url};
 use ruma::{
@@ -14,8 +17,12 @@
 	serde::Base64,
 };
 use tuwunel_core::{
-	Err, Error, Result, debug, debug::INFO_SPAN_LEVEL, debug_error, debug_warn, err,
-	error::inspect_debug_log, implement, trace, utils::string::EMPTY,
+	Err, Error, Result, debug,
+	debug::INFO_SPAN_LEVEL,
+	debug_error, debug_warn, err,
+	error::inspect_debug_log,
+	implement, trace,
+	utils::{string::EMPTY, time::millis_from_http_date},
 };
 
 use crate::resolver::actual::ActualDest;
@@ -103,7 +110,18 @@ async fn perform<T>(
 
 	debug!(?method, ?url, "Sending request");
 	match client.execute(request).await {
-		| Ok(response) => handle_response::<T>(dest, actual, &method, &url, response).await,
+		| Ok(response) => {
+			if let Some(date) = response
+				.headers()
+				.get(DATE)
+				.and_then(|date| date.to_str().ok())
+				.and_then(|date| millis_from_http_date(date).ok())
+			{
+				self.observe_clock(dest, date).await;
+			}
+
+			handle_response::<T>(dest, actual, &method, &url, response).await
+		},
 		| Err(error) =>
 			Err(handle_error(actual, &method, &url, error).expect_err("always returns error")),
 	}
@@ -1,19 +1,39 @@
+mod clock_skew;
 mod execute;
 mod format;
+#[cfg(test)]
+mod tests;
 
-use std::sync::Arc;
+use std::{fmt::Write, sync::Arc, time::Duration};
 
+use async_trait::async_trait;
 use tuwunel_core::Result;
 
+pub use self::clock_skew::describe_skew;
+use self::clock_skew::{ClockSkew, Detector, ORIGINS_MAX};
 use crate::services::OnceServices;
 
 pub struct Service {
 	services: Arc<OnceServices>,
+	clock_skew: ClockSkew,
 }
 
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
-		Ok(Arc::new(Self { services: args.services.clone() }))
+		let threshold = Duration::from_secs(args.server.config.clock_skew_threshold_s);
+
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			clock_skew: Detector::new(ORIGINS_MAX, threshold).into(),
+		}))
+	}
+
+	async fn memory_usage(&self, out: &mut (dyn Write + Send)) -> Result {
+		let clock_skew = self.clock_skew.lock()?.len();
+		writeln!(out, "clock_skew: {clock_skew}")?;
+
+		Ok(())
 	}
 
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
@@ -0,0 +1,191 @@
+use std::time::{Duration, Instant};
+
+use ruma::{ServerName, server_name};
+
+use super::clock_skew::{Detector, Observed, describe_skew, skew};
+
+const THRESHOLD: Duration = Duration::from_secs(300);
+const NOW: u64 = 1_700_000_000_000;
+const MINUTE: u64 = 60_000;
+
+fn ahead(minutes: u64) -> u64 { NOW.saturating_add(minutes.saturating_mul(MINUTE)) }
+
+fn behind(minutes: u64) -> u64 { NOW.saturating_sub(minutes.saturating_mul(MINUTE)) }
+
+fn observe(detector: &mut Detector, origin: &ServerName, origin_ts: u64) -> Observed {
+	observe_at(detector, origin, origin_ts, Instant::now())
+}
+
+fn observe_at(
+	detector: &mut Detector,
+	origin: &ServerName,
+	origin_ts: u64,
+	now: Instant,
+) -> Observed {
+	detector.observe(origin, skew(origin_ts, NOW), now)
+}
+
+#[test]
+fn skewed_origin_reported_once() {
+	let origin = server_name!("ahead.example");
+	let mut detector = Detector::new(16, THRESHOLD);
+
+	let observed = observe(&mut detector, origin, ahead(1));
+	assert_eq!(observed, Observed::default(), "skew within the threshold is not reported");
+
+	let observed = observe(&mut detector, origin, ahead(10));
+	assert_eq!(observed.origin_skewed, None, "closest sample is still within the threshold");
+	assert!(observed.skewed, "sample over the threshold is counted");
+
+	let mut detector = Detector::new(16, THRESHOLD);
+	let observed = observe(&mut detector, origin, ahead(10));
+	assert_eq!(observed.origin_skewed, Some(600_000), "skewed origin reported with its skew");
+
+	let observed = observe(&mut detector, origin, ahead(11));
+	assert_eq!(observed.origin_skewed, None, "skewed origin is reported once");
+	assert_eq!(detector.estimate(origin), Some(600_000), "closest sample is the estimate");
+}
+
+#[test]
+fn delayed_timestamps_do_not_skew() {
+	let origin = server_name!("slow.example");
+	let mut detector = Detector::new(16, THRESHOLD);
+
+	observe(&mut detector, origin, NOW.saturating_sub(1_000));
+	let observed = observe(&mut detector, origin, behind(60));
+	assert_eq!(observed.origin_skewed, None, "a retried transaction is not skew");
+	assert_eq!(detector.estimate(origin), Some(-1_000), "closest sample is the estimate");
+}
+
+#[test]
+fn own_clock_implicated_by_most_origins() {
+	let origins = [
+		server_name!("a.example"),
+		server_name!("b.example"),
+		server_name!("c.example"),
+		server_name!("d.example"),
+		server_name!("e.example"),
+	];
+
+	let mut detector = Detector::new(16, THRESHOLD);
+	for (origin, minutes) in origins.iter().zip([20, 21, 22, 23]) {
+		let observed = observe(&mut detector, origin, behind(minutes));
+		assert_eq!(observed.own_skewed, None, "too few origins to implicate our clock");
+	}
+
+	let observed = observe(&mut detector, origins[4], NOW);
+	assert_eq!(observed.own_skewed, Some(1_260_000), "our clock is ahead of most origins");
+	assert_eq!(detector.own_skew(), Some(1_260_000), "our skew is the median reversed");
+
+	let observed = observe(&mut detector, origins[4], NOW);
+	assert_eq!(observed.own_skewed, None, "our skew is reported once");
+
+	let mut detector = Detector::new(16, THRESHOLD);
+	for (origin, origin_ts) in
+		origins
+			.iter()
+			.zip([ahead(20), behind(20), ahead(20), behind(20), ahead(20)])
+	{
+		observe(&mut detector, origin, origin_ts);
+	}
+
+	assert_eq!(
+		detector.own_skew(),
+		None,
+		"skew in both directions does not implicate our clock"
+	);
+}
+
+#[test]
+fn own_clock_not_reported_again_when_quorum_flaps() {
+	let behind_origins = [
+		server_name!("a.example"),
+		server_name!("b.example"),
+		server_name!("c.example"),
+		server_name!("d.example"),
+	];
+	let later_behind_origins = [server_name!("g.example"), server_name!("h.example")];
+	let ahead_origins = [
+		server_name!("v.example"),
+		server_name!("w.example"),
+		server_name!("x.example"),
+		server_name!("y.example"),
+		server_name!("z.example"),
+	];
+
+	let start = Instant::now();
+	let later = |hours: u64| {
+		start
+			.checked_add(Duration::from_secs(hours.saturating_mul(3600)))
+			.expect("valid instant")
+	};
+
+	let mut detector = Detector::new(16, THRESHOLD);
+	for origin in behind_origins {
+		observe_at(&mut detector, origin, behind(20), start);
+	}
+
+	let observed = observe_at(&mut detector, server_name!("e.example"), NOW, start);
+	assert!(observed.own_skewed.is_some(), "four in five origins behind implicate our clock");
+
+	// an origin agreeing with our clock breaks the quorum, five in six needed
+	let observed = observe_at(&mut detector, server_name!("f.example"), NOW, later(1));
+	assert_eq!(observed.own_skewed, None, "no quorum");
+	assert_eq!(detector.own_skew(), None, "our clock no longer implicated");
+
+	// more origins behind restore it, six in eight
+	for (origin, hours) in later_behind_origins.into_iter().zip([2, 3]) {
+		let observed = observe_at(&mut detector, origin, behind(20), later(hours));
+		assert_eq!(observed.own_skewed, None, "not reported again within a day");
+	}
+
+	assert!(detector.own_skew().is_some(), "our clock implicated again");
+	let observed = observe_at(&mut detector, behind_origins[0], behind(20), later(27));
+	assert!(observed.own_skewed.is_some(), "reported again a day later");
+
+	// origins ahead of ours replace those behind
+	let mut detector = Detector::new(5, THRESHOLD);
+	for origin in behind_origins {
+		observe_at(&mut detector, origin, behind(20), start);
+	}
+
+	observe_at(&mut detector, server_name!("e.example"), NOW, start);
+	for origin in ahead_origins {
+		let observed = observe_at(&mut detector, origin, ahead(20), later(1));
+		if let Some(own_skew) = observed.own_skewed {
+			assert!(own_skew < 0, "reported again when our clock turns out behind");
+			return;
+		}
+	}
+
+	panic!("the change of direction is reported");
+}
+
+#[test]
+fn skew_described_from_the_clock_compared() {
+	let ahead = skew(ahead(10), NOW);
+	let behind = skew(behind(10), NOW);
+
+	assert_eq!(describe_skew(ahead), "10.0 minutes ahead of", "origin clock ahead of ours");
+	assert_eq!(describe_skew(behind), "10.0 minutes behind", "origin clock behind ours");
+	assert_eq!(
+		describe_skew(behind.saturating_neg()),
+		"10.0 minutes ahead of",
+		"our clock ahead of an origin behind"
+	);
+}
+
+#[test]
+fn origins_capped() {
+	let mut detector = Detector::new(2, THRESHOLD);
+	observe(&mut detector, server_name!("a.example"), NOW);
+	observe(&mut detector, server_name!("b.example"), NOW);
+	observe(&mut detector, server_name!("c.example"), NOW);
+
+	assert_eq!(detector.len(), 2, "origins capped");
+	assert_eq!(
+		detector.estimate(server_name!("a.example")),
+		None,
+		"least recent origin dropped"
+	);
+}
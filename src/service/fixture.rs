@@ -0,0 +1,92 @@
+//! Services started on a throwaway database, for the tests which need
+//! storage. Each fixture has its own ephemeral database, deleted when it is
+//! dropped.
+
+use std::{ops::Deref, sync::Arc};
+
+use tokio::runtime::Handle;
+use tuwunel_core::{Config, Server, log::Log, utils::sys::EphemeralDir};
+
+use crate::{Services, embed::Embedded};
+
+const CONFIG: &str = r#"
+[global]
+server_name = "fixture.localhost"
+database_path = "/nonexistent"
+database_ephemeral = true
+allow_federation = false
+rocksdb_direct_io = false
+startup_netburst = false
+"#;
+
+pub struct Fixture {
+	pub services: Arc<Services>,
+	config: String,
+	database: EphemeralDir,
+}
+
+impl Fixture {
+	/// Builds and starts the services; panics on failure, as only tests call
+	/// it.
+	pub async fn start() -> Self { Self::start_with("").await }
+
+	/// Starts the services with the options added to the fixture's config,
+	/// e.g. `"ip_range_denylist = []"`.
+	pub async fn start_with(options: &str) -> Self {
+		let config = format!("{CONFIG}{options}\n");
+		let mut server_config = self::config(&config);
+		let database = server_config
+			.ephemeral_database()
+			.expect("database directory")
+			.expect("ephemeral database");
+
+		let services = services(server_config).await;
+
+		Self { services, config, database }
+	}
+
+	/// Stops the services and starts them again on the same database.
+	pub async fn restart(self) -> Self {
+		let Self { services, config, database } = self;
+		services.stop().await;
+		drop(services);
+
+		// The same directory again rather than a new one
+		let mut server_config = self::config(&config);
+		server_config.database_ephemeral = false;
+		server_config.database_path = database.path().to_owned();
+
+		let services = self::services(server_config).await;
+
+		Self { services, config, database }
+	}
+
+	/// Common operations on the services, e.g. creating users and rooms.
+	pub fn embedded(&self) -> Embedded { Embedded::new(self.services.clone()) }
+
+	pub async fn stop(self) { self.services.stop().await; }
+}
+
+impl Deref for Fixture {
+	type Target = Services;
+
+	fn deref(&self) -> &Services { &self.services }
+}
+
+fn config(toml: &str) -> Config {
+	let config = Config::from_toml(toml).expect("fixture config");
+	config.check().expect("fixture config is valid");
+
+	config
+}
+
+async fn services(config: Config) -> Arc<Services> {
+	let server = Arc::new(Server::new(config, Some(Handle::current()), Log::default()));
+
+	Services::build(server)
+		.await
+		.expect("services built")
+		.start()
+		.await
+		.expect("services started")
+}
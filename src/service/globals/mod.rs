@@ -14,7 +14,11 @@
 use ruma::{
 	OwnedEventId, OwnedRoomAliasId, OwnedServerName, OwnedUserId, RoomAliasId, ServerName, UserId,
 };
-use tuwunel_core::{Result, Server, error, utils::bytes::pretty};
+use tuwunel_core::{
+	Config, Result, Server, error,
+	messages::{self, Message},
+	utils::bytes::pretty,
+};
 
 use crate::service;
 
@@ -25,8 +29,14 @@ pub struct Service {
 	pub bad_event_ratelimiter: Arc<RwLock<HashMap<OwnedEventId, RateLimitState>>>,
 	pub server_user: OwnedUserId,
 	pub admin_alias: OwnedRoomAliasId,
-	pub turn_secret: String,
-	pub registration_token: Option<String>,
+	secrets: RwLock<Secrets>,
+}
+
+/// Secrets which may be kept in files of their own, read once rather than on
+/// every use.
+struct Secrets {
+	turn_secret: String,
+	registration_token: Option<String>,
 }
 
 type RateLimitState = (Instant, u32); // Time if last failed try, number of failed tries
@@ -35,34 +45,6 @@ pub struct Service {
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		let db = Data::new(&args);
-		let config = &args.server.config;
-
-		let turn_secret = config.turn_secret_file.as_ref().map_or_else(
-			|| config.turn_secret.clone(),
-			|path| {
-				std::fs::read_to_string(path).unwrap_or_else(|e| {
-					error!("Failed to read the TURN secret file: {e}");
-
-					config.turn_secret.clone()
-				})
-			},
-		);
-
-		let registration_token = config
-			.registration_token_file
-			.as_ref()
-			.map_or_else(
-				|| config.registration_token.clone(),
-				|path| {
-					let Ok(token) = std::fs::read_to_string(path).inspect_err(|e| {
-						error!("Failed to read the registration token file: {e}");
-					}) else {
-						return config.registration_token.clone();
-					};
-
-					Some(token)
-				},
-			);
 
 		Ok(Arc::new(Self {
 			db,
@@ -75,8 +57,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				&args.server.name,
 			)
 			.expect("@conduit:server_name is valid"),
-			turn_secret,
-			registration_token,
+			secrets: RwLock::new(Secrets::read(&args.server.config)),
 		}))
 	}
 
@@ -141,6 +122,12 @@ pub fn pending_count(&self) -> Range<u64> { self.db.pending_count() }
 	#[must_use]
 	pub fn server_name(&self) -> &ServerName { self.server.name.as_ref() }
 
+	/// Renders a server-generated message in the `server_language`.
+	#[must_use]
+	pub fn message(&self, message: Message, args: &[(&str, &str)]) -> String {
+		messages::render(&self.server.config, None, message, args)
+	}
+
 	#[inline]
 	#[must_use]
 	pub fn allow_public_room_directory_over_federation(&self) -> bool {
@@ -173,6 +160,17 @@ pub fn trusted_servers(&self) -> &[OwnedServerName] { &self.server.config.truste
 	#[must_use]
 	pub fn turn_password(&self) -> &String { &self.server.config.turn_password }
 
+	/// The TURN secret, read from `turn_secret_file` when it is set; it is
+	/// read again when the configuration is reloaded.
+	#[must_use]
+	pub fn turn_secret(&self) -> String {
+		self.secrets
+			.read()
+			.expect("locked for reading")
+			.turn_secret
+			.clone()
+	}
+
 	#[inline]
 	#[must_use]
 	pub fn turn_ttl(&self) -> u64 { self.server.config.turn_ttl }
@@ -185,6 +183,22 @@ pub fn turn_uris(&self) -> &[String] { &self.server.config.turn_uris }
 	#[must_use]
 	pub fn turn_username(&self) -> &String { &self.server.config.turn_username }
 
+	/// The registration token, read from `registration_token_file` when it is
+	/// set; it is read again when the configuration is reloaded.
+	#[must_use]
+	pub fn registration_token(&self) -> Option<String> {
+		self.secrets
+			.read()
+			.expect("locked for reading")
+			.registration_token
+			.clone()
+	}
+
+	/// Reads the secrets again from the files the configuration names.
+	pub fn reload_secrets(&self) {
+		*self.secrets.write().expect("locked for writing") = Secrets::read(&self.server.config);
+	}
+
 	#[inline]
 	#[must_use]
 	pub fn notification_push_path(&self) -> &String { &self.server.config.notification_push_path }
@@ -268,3 +282,36 @@ pub fn server_is_ours(&self, server_name: &ServerName) -> bool {
 	#[must_use]
 	pub fn is_read_only(&self) -> bool { self.db.db.is_read_only() }
 }
+
+impl Secrets {
+	fn read(config: &Config) -> Self {
+		let turn_secret = config.turn_secret_file.as_ref().map_or_else(
+			|| config.turn_secret.clone(),
+			|path| {
+				std::fs::read_to_string(path).unwrap_or_else(|e| {
+					error!("Failed to read the TURN secret file: {e}");
+
+					config.turn_secret.clone()
+				})
+			},
+		);
+
+		let registration_token = config
+			.registration_token_file
+			.as_ref()
+			.map_or_else(
+				|| config.registration_token.clone(),
+				|path| {
+					let Ok(token) = std::fs::read_to_string(path).inspect_err(|e| {
+						error!("Failed to read the registration token file: {e}");
+					}) else {
+						return config.registration_token.clone();
+					};
+
+					Some(token)
+				},
+			);
+
+		Self { turn_secret, registration_token }
+	}
+}
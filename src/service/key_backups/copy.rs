@@ -0,0 +1,94 @@
+//! Copying the session keys of one backup version of a user into another, for
+//! clients moving to a new version. A key already in the target is only
+//! replaced by a better one, so an interrupted copy resumes by running again.
+
+use std::cmp::Ordering;
+
+use futures::StreamExt;
+use ruma::{RoomId, UInt, UserId, api::client::backup::KeyBackupData, serde::Raw};
+use tuwunel_core::{Err, Result, implement, info, utils::stream::TryIgnore};
+use tuwunel_database::{Ignore, Interfix};
+
+/// Copies the session keys of a backup version of the user into another;
+/// returns the number of keys written.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn copy_backup(&self, user_id: &UserId, from: &str, to: &str) -> Result<usize> {
+	type KeyVal<'a> = ((Ignore, Ignore, &'a RoomId, &'a str), Raw<KeyBackupData>);
+
+	if from == to {
+		return Err!(Request(InvalidParam("Cannot copy a backup version into itself.")));
+	}
+
+	for version in [from, to] {
+		if self.get_backup(user_id, version).await.is_err() {
+			return Err!(Request(NotFound("No backup version {version} of {user_id}.")));
+		}
+	}
+
+	let sessions: Vec<_> = self
+		.db
+		.backupkeyid_backup
+		.stream_prefix(&(user_id, from, Interfix))
+		.ignore_err()
+		.map(|((_, _, room_id, session_id), key_data): KeyVal<'_>| {
+			(room_id.to_owned(), session_id.to_owned(), key_data)
+		})
+		.collect()
+		.await;
+
+	let mut copied: usize = 0;
+	for (room_id, session_id, key_data) in sessions {
+		let current = self
+			.get_session(user_id, to, &room_id, &session_id)
+			.await;
+
+		if current.is_ok_and(|current| !is_better_key(&key_data, &current)) {
+			continue;
+		}
+
+		let key = (user_id, to, &room_id, &session_id);
+		self.db
+			.backupkeyid_backup
+			.put_raw(key, key_data.json().get());
+
+		copied = copied.saturating_add(1);
+	}
+
+	if copied > 0 {
+		let count = self.services.globals.next_count();
+		self.db.backupid_etag.put((user_id, to), *count);
+	}
+
+	info!(copied, "Copied key backup");
+	Ok(copied)
+}
+
+/// Whether the key is better than the one backed up for the same session: a
+/// verified key first, then the lower first message index, then the lower
+/// forwarded count.
+pub(super) fn is_better_key(key: &Raw<KeyBackupData>, current: &Raw<KeyBackupData>) -> bool {
+	let quality = |key: &Raw<KeyBackupData>| {
+		let field = |name| {
+			key.get_field::<UInt>(name)
+				.ok()
+				.flatten()
+				.unwrap_or(UInt::MAX)
+		};
+
+		let verified = key
+			.get_field::<bool>("is_verified")
+			.ok()
+			.flatten()
+			.unwrap_or(false);
+
+		(verified, field("first_message_index"), field("forwarded_count"))
+	};
+
+	let (key, current) = (quality(key), quality(current));
+	key.0
+		.cmp(&current.0)
+		.then_with(|| current.1.cmp(&key.1))
+		.then_with(|| current.2.cmp(&key.2))
+		== Ordering::Greater
+}
@@ -1,3 +1,8 @@
+mod copy;
+mod portable;
+#[cfg(test)]
+mod tests;
+
 use std::{collections::BTreeMap, sync::Arc};
 
 use futures::StreamExt;
@@ -12,6 +17,8 @@
 };
 use tuwunel_database::{Deserialized, Ignore, Interfix, Json, Map};
 
+pub use self::portable::{BackupEnd, BackupHeader, BackupSession};
+
 pub struct Service {
 	db: Data,
 	services: Arc<crate::services::OnceServices>,
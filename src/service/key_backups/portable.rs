@@ -0,0 +1,251 @@
+//! Moving the key backup of a user to a file and back, for account recovery.
+//! The session keys stay encrypted by the client; only the blobs and the
+//! bookkeeping of the backup move. A file holds one JSON object per line: the
+//! backup first, then each of its session keys, then their count. Files are
+//! written and read a line at a time, however large the backup.
+
+use std::path::Path;
+
+use futures::StreamExt;
+use ruma::{
+	OwnedRoomId, RoomId, UserId,
+	api::client::backup::{BackupAlgorithm, KeyBackupData},
+	serde::Raw,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+	fs::File,
+	io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, Lines},
+};
+use tuwunel_core::{Err, Error, Result, err, implement, info, utils::stream::TryIgnore};
+use tuwunel_database::{Deserialized, Ignore, Interfix};
+
+/// First line of a file: the backup the sessions belong to.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BackupHeader {
+	/// Version of the backup exported.
+	pub version: String,
+	pub algorithm: Raw<BackupAlgorithm>,
+	pub etag: u64,
+}
+
+/// Every line but the first and last: the key of a session.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BackupSession {
+	pub room_id: OwnedRoomId,
+	pub session_id: String,
+	pub key_data: Raw<KeyBackupData>,
+}
+
+/// Last line of a file: the number of session keys written, so a truncated
+/// file is not taken for a smaller backup.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackupEnd {
+	pub count: usize,
+}
+
+/// Reads a file line by line.
+pub(super) struct Decoder<R> {
+	lines: Lines<R>,
+	line_number: usize,
+	count: usize,
+}
+
+/// Writes a version of the key backup of the user to the file, by default
+/// the latest; returns the version and number of sessions exported.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn export_backup(
+	&self,
+	user_id: &UserId,
+	version: Option<&str>,
+	path: &Path,
+) -> Result<(String, usize)> {
+	type KeyVal<'a> = ((Ignore, Ignore, &'a RoomId, &'a str), Raw<KeyBackupData>);
+
+	let version = match version {
+		| Some(version) => version.to_owned(),
+		| None => self.get_latest_backup_version(user_id).await?,
+	};
+
+	let algorithm = self
+		.get_backup(user_id, &version)
+		.await
+		.map_err(|_| err!(Request(NotFound("No backup version {version} of {user_id}."))))?;
+
+	let etag: u64 = self
+		.db
+		.backupid_etag
+		.qry(&(user_id, &version))
+		.await
+		.deserialized()?;
+
+	let mut out = BufWriter::new(File::create(path).await?);
+	let header = BackupHeader {
+		version: version.clone(),
+		algorithm,
+		etag,
+	};
+	write_line(&mut out, &header).await?;
+
+	let mut sessions = self
+		.db
+		.backupkeyid_backup
+		.stream_prefix(&(user_id, &version, Interfix))
+		.ignore_err()
+		.map(|((_, _, room_id, session_id), key_data): KeyVal<'_>| BackupSession {
+			room_id: room_id.to_owned(),
+			session_id: session_id.to_owned(),
+			key_data,
+		})
+		.boxed();
+
+	let mut count: usize = 0;
+	while let Some(session) = sessions.next().await {
+		write_line(&mut out, &session).await?;
+		count = count.saturating_add(1);
+	}
+
+	write_line(&mut out, &BackupEnd { count }).await?;
+	out.flush().await?;
+
+	info!(count, "Exported key backup");
+	Ok((version, count))
+}
+
+/// Creates a new backup version of the user holding the backup in the file;
+/// returns the new version and number of sessions imported. The etag of the
+/// exported backup is kept. The new version is removed again unless the whole
+/// file is valid.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn import_backup(&self, user_id: &UserId, path: &Path) -> Result<(String, usize)> {
+	let mut file = Decoder::new(BufReader::new(File::open(path).await?));
+	let header = file.header().await?;
+
+	let version = self.services.globals.next_count().to_string();
+	let key = (user_id, &version);
+
+	let _cork = self.services.db.cork();
+	self.db
+		.backupid_algorithm
+		.put_raw(key, header.algorithm.json().get());
+
+	self.db.backupid_etag.put(key, header.etag);
+
+	let imported = async {
+		while let Some(session) = file.next().await? {
+			let key = (user_id, &version, &session.room_id, &session.session_id);
+			self.db
+				.backupkeyid_backup
+				.put_raw(key, session.key_data.json().get());
+		}
+
+		Ok::<_, Error>(file.count)
+	};
+
+	match imported.await {
+		| Ok(count) => {
+			info!(%version, count, "Imported key backup");
+			Ok((version, count))
+		},
+		| Err(e) => {
+			self.delete_backup(user_id, &version).await;
+			Err(e)
+		},
+	}
+}
+
+/// Serializes one line of JSON.
+pub(super) async fn write_line<W, T>(out: &mut W, line: &T) -> Result
+where
+	W: AsyncWrite + Unpin + Send,
+	T: Serialize + Sync,
+{
+	let mut line = serde_json::to_vec(line)?;
+	line.push(b'\n');
+	out.write_all(&line).await?;
+
+	Ok(())
+}
+
+impl<R: AsyncBufRead + Unpin + Send> Decoder<R> {
+	pub(super) fn new(input: R) -> Self {
+		Self {
+			lines: input.lines(),
+			line_number: 0,
+			count: 0,
+		}
+	}
+
+	/// Parses the backup from the first line.
+	pub(super) async fn header(&mut self) -> Result<BackupHeader> {
+		let Some(header) = self.next_line().await? else {
+			return Err!(Request(InvalidParam("The key backup file is empty.")));
+		};
+
+		serde_json::from_str(&header)
+			.map_err(|e| err!(Request(InvalidParam("Invalid key backup header: {e}"))))
+	}
+
+	/// Parses the next session key; `None` once the count of session keys is
+	/// read and found to match those read.
+	pub(super) async fn next(&mut self) -> Result<Option<BackupSession>> {
+		let Some(line) = self.next_line().await? else {
+			return Err!(Request(InvalidParam(
+				"The key backup file ends after {} session keys without their count; it may be \
+				 truncated.",
+				self.count
+			)));
+		};
+
+		let line_number = self.line_number;
+		let session = match serde_json::from_str(&line) {
+			| Ok(session) => session,
+			| Err(e) => {
+				let Ok(BackupEnd { count }) = serde_json::from_str(&line) else {
+					return Err!(Request(InvalidParam(
+						"Invalid session key on line {line_number}: {e}"
+					)));
+				};
+
+				return self.end(count).await.map(|()| None);
+			},
+		};
+
+		self.count = self.count.saturating_add(1);
+		Ok(Some(session))
+	}
+
+	async fn end(&mut self, expected: usize) -> Result {
+		let found = self.count;
+		if found != expected {
+			return Err!(Request(InvalidParam(
+				"The key backup file holds {found} session keys rather than {expected}."
+			)));
+		}
+
+		let line_number = self.line_number;
+		if self.next_line().await?.is_some() {
+			return Err!(Request(InvalidParam(
+				"The key backup file goes on after the count of session keys on line \
+				 {line_number}."
+			)));
+		}
+
+		Ok(())
+	}
+
+	/// The next line which is not blank.
+	async fn next_line(&mut self) -> Result<Option<String>> {
+		while let Some(line) = self.lines.next_line().await? {
+			self.line_number = self.line_number.saturating_add(1);
+			if !line.trim().is_empty() {
+				return Ok(Some(line));
+			}
+		}
+
+		Ok(None)
+	}
+}
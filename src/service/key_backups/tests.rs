@@ -0,0 +1,228 @@
+use ruma::{api::client::backup::BackupAlgorithm, owned_room_id, serde::Raw};
+use serde_json::{json, value::to_raw_value};
+use tuwunel_core::utils::sys::EphemeralDir;
+
+use super::{
+	BackupEnd, BackupHeader, BackupSession,
+	portable::{Decoder, write_line},
+};
+use crate::fixture::Fixture;
+
+const ALGORITHM: &str = "m.megolm_backup.v1.curve25519-aes-sha2";
+
+fn raw<T>(value: serde_json::Value) -> Raw<T> {
+	Raw::from_json(to_raw_value(&value).expect("valid raw JSON"))
+}
+
+fn key_data<T>(session_id: &str, index: u64) -> Raw<T> {
+	raw(json!({
+		"first_message_index": index,
+		"forwarded_count": 0,
+		"is_verified": true,
+		"session_data": {
+			"ephemeral": "cZ3Ca/8IJz1Lc8L1vbAR5vY5xw5y+LqKDg1hJsyAqUw",
+			"ciphertext": format!("ciphertext of {session_id}"),
+			"mac": "QzKV/fgAs4U",
+		},
+	}))
+}
+
+fn algorithm<T>() -> Raw<T> {
+	raw(json!({
+		"algorithm": ALGORITHM,
+		"auth_data": {
+			"public_key": "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo",
+		},
+	}))
+}
+
+/// A file of three sessions in two rooms, cut off after `lines`.
+async fn file(lines: usize) -> Vec<u8> {
+	let mut out = Vec::new();
+	let header = BackupHeader {
+		version: "17".to_owned(),
+		algorithm: algorithm(),
+		etag: 42,
+	};
+	write_line(&mut out, &header)
+		.await
+		.expect("header written");
+
+	for (room_id, session_id) in [
+		(owned_room_id!("!a:example.com"), "session1"),
+		(owned_room_id!("!a:example.com"), "session2"),
+		(owned_room_id!("!b:example.com"), "session3"),
+	] {
+		let session = BackupSession {
+			room_id,
+			session_id: session_id.to_owned(),
+			key_data: key_data(session_id, 0),
+		};
+
+		write_line(&mut out, &session)
+			.await
+			.expect("session written");
+	}
+
+	write_line(&mut out, &BackupEnd { count: 3 })
+		.await
+		.expect("count written");
+
+	String::from_utf8(out)
+		.expect("JSON lines")
+		.lines()
+		.take(lines)
+		.flat_map(|line| [line, "\n"])
+		.collect::<String>()
+		.into_bytes()
+}
+
+/// Reads the whole file, returning the number of sessions read.
+async fn read(input: &[u8]) -> tuwunel_core::Result<usize> {
+	let mut file = Decoder::new(input);
+	file.header().await?;
+
+	let mut count: usize = 0;
+	while file.next().await?.is_some() {
+		count = count.saturating_add(1);
+	}
+
+	Ok(count)
+}
+
+#[tokio::test]
+async fn backup_moves_to_another_account() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let key_backups = &fixture.key_backups;
+	let directory = EphemeralDir::create("tuwunel-key-backup").expect("directory created");
+	let path = directory.path().join("keys.jsonl");
+
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("user created");
+
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("user created");
+
+	let sessions = [
+		(owned_room_id!("!a:fixture.localhost"), "session1", 0),
+		(owned_room_id!("!a:fixture.localhost"), "session2", 4),
+		(owned_room_id!("!b:fixture.localhost"), "session3", 9),
+	];
+
+	let version = key_backups
+		.create_backup(&alice, &algorithm())
+		.expect("backup created");
+
+	for (room_id, session_id, index) in &sessions {
+		key_backups
+			.add_key(&alice, &version, room_id, session_id, &key_data(session_id, *index))
+			.await
+			.expect("key added");
+	}
+
+	let (exported, count) = key_backups
+		.export_backup(&alice, None, &path)
+		.await
+		.expect("backup exported");
+
+	assert_eq!(exported, version, "latest version exported");
+	assert_eq!(count, sessions.len(), "every session exported");
+
+	let (imported, count) = key_backups
+		.import_backup(&bob, &path)
+		.await
+		.expect("backup imported");
+
+	assert_eq!(count, sessions.len(), "every session imported");
+	assert_eq!(key_backups.count_keys(&bob, &imported).await, sessions.len(), "keys stored");
+	assert_eq!(
+		key_backups.get_etag(&bob, &imported).await,
+		key_backups.get_etag(&alice, &version).await,
+		"etag kept"
+	);
+	assert_eq!(
+		key_backups
+			.get_backup(&bob, &imported)
+			.await
+			.expect("backup exists")
+			.json()
+			.get(),
+		algorithm::<BackupAlgorithm>().json().get(),
+		"algorithm kept"
+	);
+
+	for (room_id, session_id, _) in &sessions {
+		let imported = key_backups
+			.get_session(&bob, &imported, room_id, session_id)
+			.await
+			.expect("session imported");
+
+		let exported = key_backups
+			.get_session(&alice, &version, room_id, session_id)
+			.await
+			.expect("session exported");
+
+		assert_eq!(imported.json().get(), exported.json().get(), "session data untouched");
+	}
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn truncated_backup_not_imported() {
+	let fixture = Fixture::start().await;
+	let directory = EphemeralDir::create("tuwunel-key-backup").expect("directory created");
+	let path = directory.path().join("keys.jsonl");
+
+	let alice = fixture
+		.embedded()
+		.create_user("alice", Some("password"))
+		.await
+		.expect("user created");
+
+	tokio::fs::write(&path, file(3).await)
+		.await
+		.expect("file written");
+
+	assert!(
+		fixture
+			.key_backups
+			.import_backup(&alice, &path)
+			.await
+			.is_err(),
+		"a file without the count of its sessions is truncated"
+	);
+	assert!(
+		fixture
+			.key_backups
+			.get_latest_backup_version(&alice)
+			.await
+			.is_err(),
+		"the sessions read are removed again"
+	);
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn backup_file_checked() {
+	assert_eq!(read(&file(5).await).await.ok(), Some(3), "complete file");
+	assert!(read(&file(4).await).await.is_err(), "count missing");
+	assert!(read(b"").await.is_err(), "empty file");
+
+	let complete = String::from_utf8(file(5).await).expect("JSON lines");
+
+	let miscounted = complete.replace(r#"{"count":3}"#, r#"{"count":2}"#);
+	assert!(read(miscounted.as_bytes()).await.is_err(), "count differs");
+
+	let garbled = complete.replacen("session2", "session2\"", 1);
+	assert!(read(garbled.as_bytes()).await.is_err(), "invalid line");
+
+	let trailing = format!("{complete}{}", complete.lines().nth(1).expect("a session"));
+	assert!(read(trailing.as_bytes()).await.is_err(), "lines after the count");
+}
@@ -14,6 +14,7 @@
 
 pub(crate) struct Manager {
 	manager: Mutex<Option<JoinHandle<Result>>>,
+	trimmer: Mutex<Option<JoinHandle<()>>>,
 	workers: Mutex<Workers>,
 	server: Arc<Server>,
 	services: Arc<Services>,
@@ -25,10 +26,14 @@ pub(crate) struct Manager {
 
 const RESTART_DELAY_MS: u64 = 2500;
 
+/// Shortest interval between cache trimmings.
+const TRIM_INTERVAL_MIN: Duration = Duration::from_secs(60);
+
 impl Manager {
 	pub(super) fn new(services: &Arc<Services>) -> Arc<Self> {
 		Arc::new(Self {
 			manager: Mutex::new(None),
+			trimmer: Mutex::new(None),
 			workers: Mutex::new(JoinSet::new()),
 			server: services.server.clone(),
 			services: services.clone(),
@@ -60,10 +65,28 @@ pub(super) async fn start(self: Arc<Self>) -> Result {
 			self.start_worker(&mut workers, &service).await?;
 		}
 
+		if let Some(interval) = self.server.config.cache_trim_interval {
+			debug!("Starting cache trimming every {interval}s...");
+			let self_ = self.clone();
+			_ = self
+				.trimmer
+				.lock()
+				.await
+				.insert(self.server.runtime().spawn(async move {
+					self_
+						.trim_caches(Duration::from_secs(interval))
+						.await
+				}));
+		}
+
 		Ok(())
 	}
 
 	pub(super) async fn stop(&self) {
+		if let Some(trimmer) = self.trimmer.lock().await.take() {
+			trimmer.abort();
+		}
+
 		if let Some(manager) = self.manager.lock().await.take() {
 			debug!("Waiting for service manager...");
 			if let Err(e) = manager.await {
@@ -88,6 +111,20 @@ async fn worker(&self) -> Result {
 		Ok(())
 	}
 
+	/// Trims the caches of the services at the interval until shutdown.
+	async fn trim_caches(&self, interval: Duration) {
+		let max_age = Duration::from_secs(self.server.config.cache_trim_max_age);
+		while self.server.running() {
+			tokio::select! {
+				() = sleep(interval.max(TRIM_INTERVAL_MIN)) => {
+					trace!("Trimming caches...");
+					self.services.trim_cache(max_age).await;
+				},
+				() = self.server.until_shutdown() => break,
+			}
+		}
+	}
+
 	async fn handle_abort(&self, _workers: &mut WorkersLocked<'_>, error: Error) -> Result {
 		// not supported until service can be associated with abort
 		unimplemented!("unexpected worker task abort {error:?}");
@@ -1,19 +1,21 @@
 use std::{sync::Arc, time::Duration};
 
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use ruma::{Mxc, OwnedMxcUri, UserId, http_headers::ContentDisposition};
 use tuwunel_core::{
 	Err, Result, debug, debug_info, err,
 	utils::{ReadyExt, str_from_bytes, stream::TryIgnore, string_from_bytes},
 };
-use tuwunel_database::{Database, Interfix, Map, serialize_key};
+use tuwunel_database::{Database, Deserialized, Ignore, Interfix, Json, Map, serialize_key};
 
-use super::{preview::UrlPreviewData, thumbnail::Dim};
+use super::{pending::Pending, preview::UrlPreviewData, thumbnail::Dim};
 
 pub(crate) struct Data {
 	mediaid_file: Arc<Map>,
+	mediaid_pending: Arc<Map>,
 	mediaid_user: Arc<Map>,
 	url_previews: Arc<Map>,
+	userid_pendingmediaid: Arc<Map>,
 }
 
 #[derive(Debug)]
@@ -27,8 +29,10 @@ impl Data {
 	pub(super) fn new(db: &Arc<Database>) -> Self {
 		Self {
 			mediaid_file: db["mediaid_file"].clone(),
+			mediaid_pending: db["mediaid_pending"].clone(),
 			mediaid_user: db["mediaid_user"].clone(),
 			url_previews: db["url_previews"].clone(),
+			userid_pendingmediaid: db["userid_pendingmediaid"].clone(),
 		}
 	}
 
@@ -52,6 +56,41 @@ pub(super) fn create_file_metadata(
 		Ok(key.to_vec())
 	}
 
+	pub(super) fn put_pending(&self, mxc: &Mxc<'_>, pending: &Pending) {
+		self.mediaid_pending.put(mxc, Json(pending));
+		self.userid_pendingmediaid
+			.put((&pending.user_id, mxc), pending.created_at);
+	}
+
+	pub(super) async fn get_pending(&self, mxc: &Mxc<'_>) -> Result<Pending> {
+		self.mediaid_pending.qry(mxc).await.deserialized()
+	}
+
+	pub(super) fn delete_pending(&self, mxc: &Mxc<'_>, user_id: &UserId) {
+		self.mediaid_pending.del(mxc);
+		self.userid_pendingmediaid.del((user_id, mxc));
+	}
+
+	/// When each media ID of the user awaiting its upload was created.
+	pub(super) fn pending_created_of<'a>(
+		&'a self,
+		user_id: &'a UserId,
+	) -> impl Stream<Item = u64> + Send + 'a {
+		self.userid_pendingmediaid
+			.stream_prefix(&(user_id, Interfix))
+			.ignore_err()
+			.map(|(_, created_at): ((Ignore, Ignore), u64)| created_at)
+	}
+
+	pub(super) async fn get_all_pending(&self) -> Vec<(OwnedMxcUri, Pending)> {
+		self.mediaid_pending
+			.stream()
+			.ignore_err()
+			.map(|(mxc, pending): (&str, Pending)| (mxc.into(), pending))
+			.collect()
+			.await
+	}
+
 	pub(super) async fn delete_file_mxc(&self, mxc: &Mxc<'_>) {
 		debug!("MXC URI: {mxc}");
 
@@ -153,6 +192,33 @@ pub(super) async fn get_all_user_mxcs(&self, user_id: &UserId) -> Vec<OwnedMxcUr
 			.await
 	}
 
+	/// Gets the keys of all uploads by the user
+	pub(super) async fn get_user_media_keys(&self, user_id: &UserId) -> Vec<Vec<u8>> {
+		self.mediaid_user
+			.raw_stream()
+			.ignore_err()
+			.ready_filter_map(|(key, user)| (user == user_id.as_bytes()).then(|| key.to_vec()))
+			.collect()
+			.await
+	}
+
+	/// Attributes an upload to another user
+	pub(super) fn set_media_user(&self, key: &[u8], user_id: &UserId) {
+		let Some(mxc) = key
+			.iter()
+			.rposition(|&b| b == 0xFF)
+			.map(|pos| &key[..pos])
+		else {
+			return;
+		};
+
+		let new_key = [mxc, &[0xFF], user_id.as_bytes()].concat();
+		self.mediaid_user
+			.insert(&new_key, user_id.as_bytes());
+
+		self.mediaid_user.remove(key);
+	}
+
 	/// Gets all the media keys in our database (this includes all the metadata
 	/// associated with it such as width, height, content-type, etc)
 	pub(crate) async fn get_all_media_keys(&self) -> Vec<Vec<u8>> {
@@ -1,18 +1,24 @@
 pub mod blurhash;
 mod data;
 pub(super) mod migrations;
+mod pending;
 mod preview;
 mod remote;
 mod tests;
 mod thumbnail;
-use std::{path::PathBuf, sync::Arc, time::SystemTime};
+use std::{
+	path::PathBuf,
+	sync::Arc,
+	time::{Duration, SystemTime},
+};
 
 use async_trait::async_trait;
 use base64::{Engine as _, engine::general_purpose};
-use ruma::{Mxc, OwnedMxcUri, UserId, http_headers::ContentDisposition};
+use ruma::{Mxc, OwnedMxcUri, OwnedUserId, UserId, http_headers::ContentDisposition};
 use tokio::{
 	fs,
 	io::{AsyncReadExt, AsyncWriteExt, BufReader},
+	time::sleep,
 };
 use tuwunel_core::{
 	Err, Result, debug, debug_error, debug_info, debug_warn, err, error, trace,
@@ -32,6 +38,7 @@ pub struct FileMeta {
 
 pub struct Service {
 	url_preview_mutex: MutexMap<String, ()>,
+	pending_mutex: MutexMap<OwnedUserId, ()>,
 	pub(super) db: Data,
 	services: Arc<crate::services::OnceServices>,
 }
@@ -45,11 +52,19 @@ pub struct Service {
 /// Default cross-origin resource policy.
 pub const CORP_CROSS_ORIGIN: &str = "cross-origin";
 
+/// Shortest interval between prunings of expired pending media IDs, in
+/// seconds.
+const PENDING_PRUNE_INTERVAL_MIN: u64 = 60;
+
+/// Longest interval between prunings of expired pending media IDs, in seconds.
+const PENDING_PRUNE_INTERVAL_MAX: u64 = 60 * 60;
+
 #[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			url_preview_mutex: MutexMap::new(),
+			pending_mutex: MutexMap::new(),
 			db: Data::new(args.db),
 			services: args.services.clone(),
 		}))
@@ -57,6 +72,25 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 
 	async fn worker(self: Arc<Self>) -> Result {
 		self.create_media_dir().await?;
+		if self.services.db.is_read_only() {
+			return Ok(());
+		}
+
+		let interval = self
+			.services
+			.server
+			.config
+			.unused_media_id_ttl
+			.clamp(PENDING_PRUNE_INTERVAL_MIN, PENDING_PRUNE_INTERVAL_MAX);
+
+		while self.services.server.running() {
+			tokio::select! {
+				() = sleep(Duration::from_secs(interval)) => {
+					self.prune_pending().await;
+				},
+				() = self.services.server.until_shutdown() => break,
+			}
+		}
 
 		Ok(())
 	}
@@ -144,6 +178,23 @@ pub async fn delete_from_user(&self, user: &UserId) -> Result<usize> {
 		Ok(deletion_count)
 	}
 
+	/// Counts the uploads by the specified user
+	pub async fn count_from_user(&self, user: &UserId) -> usize {
+		self.db.get_user_media_keys(user).await.len()
+	}
+
+	/// Attributes all uploads by one user to another, returning how many
+	/// were reassigned
+	pub async fn reassign_from_user(&self, from: &UserId, to: &UserId) -> usize {
+		let keys = self.db.get_user_media_keys(from).await;
+		for key in &keys {
+			self.db.set_media_user(key, to);
+		}
+
+		debug_info!(count = keys.len(), "Reassigned uploads by {from} to {to}");
+		keys.len()
+	}
+
 	/// Downloads a file.
 	pub async fn get(&self, mxc: &Mxc<'_>) -> Result<Option<FileMeta>> {
 		match self
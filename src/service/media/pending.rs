@@ -0,0 +1,185 @@
+//! Media IDs created ahead of their upload (asynchronous uploads). Each is
+//! reserved to its creator in `mediaid_pending` until the content is uploaded
+//! or `unused_media_id_ttl` passes; the worker of the service forgets those
+//! expired.
+
+use std::time::Duration;
+
+use ruma::{
+	Mxc, OwnedMxcUri, OwnedUserId, UserId, api::client::error::ErrorKind,
+	http_headers::ContentDisposition,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{Instant, sleep};
+use tuwunel_core::{Err, Error, Result, debug, http::StatusCode, implement, utils};
+
+use super::{MXC_LENGTH, Service};
+
+/// Interval between checks for the upload of a media ID being downloaded.
+const UPLOAD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A media ID awaiting its upload.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct Pending {
+	pub(super) user_id: OwnedUserId,
+	pub(super) created_at: u64,
+}
+
+impl Pending {
+	#[must_use]
+	pub(super) fn expires_at(&self, ttl_ms: u64) -> u64 { self.created_at.saturating_add(ttl_ms) }
+
+	#[must_use]
+	pub(super) fn is_expired(&self, now: u64, ttl_ms: u64) -> bool {
+		now >= self.expires_at(ttl_ms)
+	}
+}
+
+/// Number of unexpired media IDs awaiting their upload, by when each was
+/// created.
+pub(super) fn count_pending<I>(created: I, now: u64, ttl_ms: u64) -> usize
+where
+	I: IntoIterator<Item = u64>,
+{
+	created
+		.into_iter()
+		.filter(|&created_at| now < created_at.saturating_add(ttl_ms))
+		.count()
+}
+
+/// Creates a media ID for the user to upload to later; returns it with when
+/// it expires, in milliseconds since the epoch.
+#[implement(Service)]
+pub async fn create_pending(&self, user_id: &UserId) -> Result<(OwnedMxcUri, u64)> {
+	let config = &self.services.server.config;
+	let ttl_ms = self.unused_media_id_ttl_ms();
+
+	// The count is checked and the media ID added under the lock, so concurrent
+	// requests of the user cannot exceed the limit.
+	let _lock = self.pending_mutex.lock(user_id).await;
+	let now = utils::millis_since_unix_epoch();
+	let created: Vec<_> = self
+		.db
+		.pending_created_of(user_id)
+		.collect()
+		.await;
+
+	if count_pending(created, now, ttl_ms) >= config.max_pending_media_uploads {
+		return Err(Error::Request(
+			ErrorKind::LimitExceeded { retry_after: None },
+			"Too many media IDs are awaiting their upload.".into(),
+			StatusCode::TOO_MANY_REQUESTS,
+		));
+	}
+
+	let mxc = Mxc {
+		server_name: self.services.globals.server_name(),
+		media_id: &utils::random_string(MXC_LENGTH),
+	};
+
+	let pending = Pending {
+		user_id: user_id.to_owned(),
+		created_at: now,
+	};
+	self.db.put_pending(&mxc, &pending);
+
+	Ok((mxc.to_string().into(), pending.expires_at(ttl_ms)))
+}
+
+/// Uploads the content of a media ID created ahead by the user.
+#[implement(Service)]
+pub async fn upload_pending(
+	&self,
+	mxc: &Mxc<'_>,
+	user_id: &UserId,
+	content_disposition: Option<&ContentDisposition>,
+	content_type: Option<&str>,
+	file: &[u8],
+) -> Result {
+	// Concurrent uploads to the media ID are serialized; all but the first find
+	// it uploaded.
+	let _lock = self.pending_mutex.lock(user_id).await;
+	let Ok(pending) = self.db.get_pending(mxc).await else {
+		if self.get_metadata(mxc).await.is_some() {
+			return Err!(Request(CannotOverwriteMedia("Media has already been uploaded.")));
+		}
+
+		return Err!(Request(NotFound("Unknown media ID.")));
+	};
+
+	if pending.user_id != user_id {
+		return Err!(Request(Forbidden("Media ID was created by another user.")));
+	}
+
+	if pending.is_expired(utils::millis_since_unix_epoch(), self.unused_media_id_ttl_ms()) {
+		self.db.delete_pending(mxc, user_id);
+		return Err!(Request(NotFound("Media ID has expired.")));
+	}
+
+	self.create(mxc, Some(user_id), content_disposition, content_type, file)
+		.await?;
+
+	self.db.delete_pending(mxc, user_id);
+
+	Ok(())
+}
+
+/// Waits up to the timeout for the upload of a media ID created ahead; errors
+/// when it is still awaited after. Media IDs not awaiting an upload return
+/// at once.
+#[implement(Service)]
+pub async fn wait_pending(&self, mxc: &Mxc<'_>, timeout: Duration) -> Result {
+	let deadline = Instant::now().checked_add(timeout);
+	while self.is_pending(mxc).await {
+		if deadline.is_none_or(|deadline| Instant::now() >= deadline) {
+			return Err!(Request(NotYetUploaded("Media has not been uploaded yet.")));
+		}
+
+		sleep(UPLOAD_POLL_INTERVAL).await;
+	}
+
+	Ok(())
+}
+
+/// Whether the media ID was created ahead and still awaits its upload.
+#[implement(Service)]
+pub async fn is_pending(&self, mxc: &Mxc<'_>) -> bool {
+	self.db
+		.get_pending(mxc)
+		.await
+		.is_ok_and(|pending| {
+			!pending.is_expired(utils::millis_since_unix_epoch(), self.unused_media_id_ttl_ms())
+		})
+}
+
+/// Forgets the media IDs whose upload expired; returns the number forgotten.
+#[implement(Service)]
+pub(super) async fn prune_pending(&self) -> usize {
+	let ttl_ms = self.unused_media_id_ttl_ms();
+	let now = utils::millis_since_unix_epoch();
+
+	let mut count: usize = 0;
+	for (mxc, pending) in self.db.get_all_pending().await {
+		if !pending.is_expired(now, ttl_ms) {
+			continue;
+		}
+
+		if let Ok(mxc) = mxc.as_str().try_into() {
+			let _lock = self.pending_mutex.lock(&*pending.user_id).await;
+			self.db.delete_pending(&mxc, &pending.user_id);
+			count = count.saturating_add(1);
+		}
+	}
+
+	debug!(count, "Pruned expired pending media IDs");
+	count
+}
+
+#[implement(Service)]
+fn unused_media_id_ttl_ms(&self) -> u64 {
+	self.services
+		.server
+		.config
+		.unused_media_id_ttl
+		.saturating_mul(1000)
+}
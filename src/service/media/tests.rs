@@ -103,3 +103,30 @@ fn get_url_preview(&self, _url: &str) -> Option<UrlPreviewData> { todo!() }
 		r.to_str().unwrap().len()
 	);
 }
+
+#[test]
+fn pending_media_expiry() {
+	use ruma::owned_user_id;
+
+	use super::pending::Pending;
+
+	let pending = Pending {
+		user_id: owned_user_id!("@alice:example.com"),
+		created_at: 1_000,
+	};
+
+	assert_eq!(pending.expires_at(60_000), 61_000, "expires a ttl after its creation");
+	assert!(!pending.is_expired(60_999, 60_000), "pending until it expires");
+	assert!(pending.is_expired(61_000, 60_000), "expired once the ttl passed");
+}
+
+#[test]
+fn pending_media_count() {
+	use super::pending::count_pending;
+
+	let created = [1_000, 50_000, 50_000];
+
+	assert_eq!(count_pending(created, 2_000, 60_000), 3, "every upload of the user counts");
+	assert_eq!(count_pending(created, 61_000, 60_000), 2, "expired uploads do not count");
+	assert_eq!(count_pending([], 2_000, 60_000), 0, "a user without uploads has none");
+}
@@ -24,6 +24,11 @@ pub async fn invite(
 	reason: Option<&String>,
 	is_direct: bool,
 ) -> Result {
+	self.services
+		.moderation
+		.user_may_invite(sender_user, user_id, room_id)
+		.await?;
+
 	if self.services.globals.user_is_local(user_id) {
 		self.local_invite(sender_user, user_id, room_id, reason, is_direct)
 			.boxed()
@@ -34,6 +39,11 @@ pub async fn invite(
 			.await?;
 	}
 
+	self.services
+		.moderation
+		.invite_sent(sender_user, user_id, room_id)
+		.await;
+
 	Ok(())
 }
 
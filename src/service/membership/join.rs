@@ -6,13 +6,7 @@
 	UserId,
 	api::{client::error::ErrorKind, federation},
 	canonical_json::to_canonical_value,
-	events::{
-		StateEventType,
-		room::{
-			join_rules::RoomJoinRulesEventContent,
-			member::{MembershipState, RoomMemberEventContent},
-		},
-	},
+	events::room::member::{MembershipState, RoomMemberEventContent},
 	room::{AllowRule, JoinRule},
 };
 use tuwunel_core::{
@@ -88,6 +82,17 @@ pub async fn join(
 		}
 	}
 
+	let is_invited = self
+		.services
+		.state_cache
+		.is_invited(sender_user, room_id)
+		.await;
+
+	self.services
+		.moderation
+		.user_may_join_room(sender_user, room_id, is_invited)
+		.await?;
+
 	let server_in_room = self
 		.services
 		.state_cache
@@ -98,6 +103,8 @@ pub async fn join(
 		|| servers.is_empty()
 		|| (servers.len() == 1 && self.services.globals.server_is_ours(&servers[0]));
 
+	// Joining locally needs no request over federation; join_local only tries
+	// the servers when the join fails the local auth rules.
 	if local_join {
 		self.join_local(sender_user, room_id, reason, servers, state_lock)
 			.boxed()
@@ -492,6 +499,11 @@ pub async fn join_remote(
 		.state
 		.set_room_state(room_id, statehash_after_join, state_lock);
 
+	self.services
+		.metadata
+		.record_creation(room_id, Some(remote_server.as_ref()))
+		.await;
+
 	Ok(())
 }
 
@@ -507,29 +519,13 @@ pub async fn join_local(
 ) -> Result {
 	debug_info!("We can join locally");
 
-	let join_rules_event_content = self
+	let join_rule = self
 		.services
 		.state_accessor
-		.room_state_get_content::<RoomJoinRulesEventContent>(
-			room_id,
-			&StateEventType::RoomJoinRules,
-			"",
-		)
+		.get_join_rules(room_id)
 		.await;
 
-	let restriction_rooms = match join_rules_event_content {
-		| Ok(RoomJoinRulesEventContent {
-			join_rule: JoinRule::Restricted(restricted) | JoinRule::KnockRestricted(restricted),
-		}) => restricted
-			.allow
-			.into_iter()
-			.filter_map(|a| match a {
-				| AllowRule::RoomMembership(r) => Some(r.room_id),
-				| _ => None,
-			})
-			.collect(),
-		| _ => Vec::new(),
-	};
+	let restriction_rooms: Vec<_> = allowed_rooms(&join_rule).collect();
 
 	let join_authorized_via_users_server: Option<OwnedUserId> = {
 		if restriction_rooms
@@ -547,12 +543,10 @@ pub async fn join_local(
 				.state_cache
 				.local_users_in_room(room_id)
 				.filter(|user| {
-					self.services.state_accessor.user_can_invite(
-						room_id,
-						user,
-						sender_user,
-						state_lock,
-					)
+					self.services
+						.state_accessor
+						.user_can_invite(room_id, user, sender_user)
+						.map(|result| result.is_ok())
 				})
 				.map(ToOwned::to_owned);
 
@@ -744,6 +738,74 @@ pub async fn join_local(
 	Ok(())
 }
 
+/// Whether the user can join the room without any request over federation:
+/// this server participates in the room and its join rules admit the user.
+/// Joining may still fail the auth rules, e.g. when no local user can
+/// authorize a restricted join.
+#[implement(Service)]
+pub async fn can_join_locally(&self, user_id: &UserId, room_id: &RoomId) -> bool {
+	if !self
+		.services
+		.state_cache
+		.server_in_room(self.services.globals.server_name(), room_id)
+		.await
+	{
+		return false;
+	}
+
+	let join_rule = self
+		.services
+		.state_accessor
+		.get_join_rules(room_id)
+		.await;
+
+	let invited = self
+		.services
+		.state_cache
+		.is_invited(user_id, room_id)
+		.await;
+
+	let in_allowed_room = allowed_rooms(&join_rule)
+		.stream()
+		.any(|allowed_room_id| {
+			self.services
+				.state_cache
+				.is_joined(user_id, allowed_room_id)
+		})
+		.await;
+
+	join_rule_admits(&join_rule, invited, in_allowed_room)
+}
+
+/// Whether the join rule admits the user: anyone joins a public room, the
+/// invited join any room, and members of an allowed room join a restricted
+/// one.
+pub(super) fn join_rule_admits(
+	join_rule: &JoinRule,
+	invited: bool,
+	in_allowed_room: bool,
+) -> bool {
+	match join_rule {
+		| JoinRule::Public => true,
+		| JoinRule::Restricted(_) | JoinRule::KnockRestricted(_) => invited || in_allowed_room,
+		| _ => invited,
+	}
+}
+
+/// The rooms whose members the join rule admits.
+pub(super) fn allowed_rooms(join_rule: &JoinRule) -> impl Iterator<Item = &RoomId> + Send {
+	let allow = match join_rule {
+		| JoinRule::Restricted(restricted) | JoinRule::KnockRestricted(restricted) =>
+			restricted.allow.as_slice(),
+		| _ => &[],
+	};
+
+	allow.iter().filter_map(|rule| match rule {
+		| AllowRule::RoomMembership(membership) => Some(membership.room_id.as_ref()),
+		| _ => None,
+	})
+}
+
 #[implement(Service)]
 #[tracing::instrument(
 	name = "make_join",
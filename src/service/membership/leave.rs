@@ -175,12 +175,18 @@ async fn remote_leave(&self, user_id: &UserId, room_id: &RoomId) -> Result {
 	let mut servers: HashSet<OwnedServerName> = self
 		.services
 		.state_cache
-		.servers_invite_via(room_id)
-		.chain(self.services.state_cache.room_servers(room_id))
+		.room_servers(room_id)
 		.map(ToOwned::to_owned)
 		.collect()
 		.await;
 
+	servers.extend(
+		self.services
+			.state_cache
+			.servers_invite_via(room_id)
+			.await,
+	);
+
 	match self
 		.services
 		.state_cache
@@ -3,6 +3,8 @@
 mod join;
 mod kick;
 mod leave;
+#[cfg(test)]
+mod tests;
 mod unban;
 
 use std::sync::Arc;
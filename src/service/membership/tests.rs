@@ -0,0 +1,197 @@
+use futures::FutureExt;
+use ruma::{
+	OwnedServerName, RoomId, UserId,
+	events::room::{join_rules::RoomJoinRulesEventContent, member::MembershipState},
+	owned_room_id, owned_server_name,
+	room::{AllowRule, JoinRule, Restricted},
+	room_id,
+};
+use tuwunel_core::{Result, matrix::pdu::PduBuilder};
+
+use super::join::{allowed_rooms, join_rule_admits};
+use crate::fixture::Fixture;
+
+fn restricted() -> JoinRule {
+	JoinRule::Restricted(Restricted::new(vec![
+		AllowRule::room_membership(owned_room_id!("!space:example.com")),
+		AllowRule::room_membership(owned_room_id!("!other:example.com")),
+	]))
+}
+
+#[test]
+fn local_join_public() {
+	assert!(join_rule_admits(&JoinRule::Public, false, false), "anyone joins a public room");
+	assert_eq!(allowed_rooms(&JoinRule::Public).count(), 0, "a public room allows no rooms");
+}
+
+#[test]
+fn local_join_invite() {
+	assert!(join_rule_admits(&JoinRule::Invite, true, false), "the invited join");
+	assert!(!join_rule_admits(&JoinRule::Invite, false, false), "others need an invite");
+	assert!(!join_rule_admits(&JoinRule::Invite, false, true), "allowed rooms do not apply");
+	assert!(join_rule_admits(&JoinRule::Knock, true, false), "the invited join a knock room");
+	assert!(!join_rule_admits(&JoinRule::Knock, false, false), "others must knock");
+}
+
+#[test]
+fn local_join_restricted() {
+	let join_rule = restricted();
+	let allowed: Vec<_> = allowed_rooms(&join_rule).collect();
+
+	assert_eq!(
+		allowed,
+		[room_id!("!space:example.com"), room_id!("!other:example.com")],
+		"every allowed room is listed"
+	);
+	assert!(join_rule_admits(&join_rule, false, true), "members of an allowed room join");
+	assert!(join_rule_admits(&join_rule, true, false), "the invited join");
+	assert!(!join_rule_admits(&join_rule, false, false), "others cannot join");
+}
+
+/// Sets the join rule of the room as its creator.
+async fn set_join_rule(fixture: &Fixture, creator: &UserId, room_id: &RoomId, rule: JoinRule) {
+	let state_lock = fixture.state.mutex.lock(room_id).await;
+	fixture
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(String::new(), &RoomJoinRulesEventContent::new(rule)),
+			creator,
+			room_id,
+			&state_lock,
+		)
+		.boxed()
+		.await
+		.expect("join rule set");
+}
+
+/// Joins through a server which cannot be reached: the fixture has no
+/// federation, so only a local join succeeds.
+async fn join(fixture: &Fixture, user_id: &UserId, room_id: &RoomId) -> Result {
+	let servers: [OwnedServerName; 1] = [owned_server_name!("remote.invalid")];
+	let state_lock = fixture.state.mutex.lock(room_id).await;
+
+	fixture
+		.membership
+		.join(user_id, room_id, None, &servers, &None, &state_lock)
+		.boxed()
+		.await
+}
+
+async fn assert_joined(fixture: &Fixture, user_id: &UserId, room_id: &RoomId) {
+	let member = fixture
+		.state_accessor
+		.get_member(room_id, user_id)
+		.await
+		.expect("member event appended");
+
+	assert_eq!(member.membership, MembershipState::Join, "{user_id} joined");
+	assert!(
+		fixture
+			.state_cache
+			.is_joined(user_id, room_id)
+			.await,
+		"{user_id} joined"
+	);
+}
+
+#[tokio::test]
+async fn joined_locally_without_servers() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let membership = &fixture.membership;
+	let mut users = Vec::new();
+	for name in ["alice", "bob", "carol", "dave"] {
+		let user_id = embedded
+			.create_user(name, None)
+			.await
+			.expect("user created");
+
+		users.push(user_id);
+	}
+
+	let [alice, bob, carol, dave] = users.as_slice() else {
+		panic!("four users created");
+	};
+
+	// Public
+	let public = embedded
+		.create_room(alice, None)
+		.await
+		.expect("room");
+	assert!(membership.can_join_locally(bob, &public).await, "anyone joins");
+	join(&fixture, bob, &public)
+		.await
+		.expect("joined the public room");
+	assert_joined(&fixture, bob, &public).await;
+
+	// Invite
+	let private = embedded
+		.create_room(alice, None)
+		.await
+		.expect("room");
+	set_join_rule(&fixture, alice, &private, JoinRule::Invite).await;
+	assert!(!membership.can_join_locally(bob, &private).await, "not invited yet");
+	membership
+		.invite(alice, bob, &private, None, false)
+		.boxed()
+		.await
+		.expect("bob invited");
+	assert!(membership.can_join_locally(bob, &private).await, "the invited join");
+	join(&fixture, bob, &private)
+		.await
+		.expect("joined the room invited to");
+	assert_joined(&fixture, bob, &private).await;
+
+	// Restricted to the members of the public room
+	let restricted = embedded
+		.create_room(alice, None)
+		.await
+		.expect("room");
+	let rule =
+		JoinRule::Restricted(Restricted::new(vec![AllowRule::room_membership(public.clone())]));
+	set_join_rule(&fixture, alice, &restricted, rule).await;
+	embedded
+		.join_room(carol, &public)
+		.await
+		.expect("carol joins the allowed room");
+	assert!(
+		membership
+			.can_join_locally(carol, &restricted)
+			.await,
+		"members of the allowed room join"
+	);
+	join(&fixture, carol, &restricted)
+		.await
+		.expect("joined the restricted room");
+	assert_joined(&fixture, carol, &restricted).await;
+
+	let member = fixture
+		.state_accessor
+		.get_member(&restricted, carol)
+		.await
+		.expect("member event");
+	assert_eq!(
+		member.join_authorized_via_users_server.as_deref(),
+		Some(&**alice),
+		"authorized by a local member who can invite"
+	);
+
+	assert!(
+		!membership
+			.can_join_locally(dave, &restricted)
+			.await,
+		"others cannot join"
+	);
+	join(&fixture, dave, &restricted)
+		.await
+		.expect_err("not admitted, and no server to ask");
+	assert!(
+		!fixture
+			.state_cache
+			.is_joined(dave, &restricted)
+			.await,
+		"dave not joined"
+	);
+
+	fixture.stop().await;
+}
@@ -0,0 +1,223 @@
+mod render;
+#[cfg(test)]
+mod tests;
+
+use std::{
+	collections::BTreeMap,
+	sync::{
+		Arc, RwLock,
+		atomic::{AtomicU64, Ordering},
+	},
+	time::Duration,
+};
+
+use tuwunel_core::{Result, utils::math::usize_from_u64_truncated};
+
+/// Registry of the metrics served in the Prometheus text format. Services
+/// record into it by name; a series is created on first use. Nothing is
+/// recorded unless built with the `metrics` feature and enabled in the
+/// config.
+pub struct Service {
+	services: Arc<crate::services::OnceServices>,
+	families: RwLock<Families>,
+	enabled: bool,
+}
+
+type Families = BTreeMap<&'static str, Family>;
+
+/// All series of one metric, keyed by their rendered labels.
+struct Family {
+	help: &'static str,
+	series: BTreeMap<String, Series>,
+}
+
+enum Series {
+	Counter(AtomicU64),
+	Histogram(Histogram),
+}
+
+/// Upper bounds in seconds of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] =
+	[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+struct Histogram {
+	buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+	count: AtomicU64,
+	sum_micros: AtomicU64,
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			families: RwLock::default(),
+			enabled: cfg!(feature = "metrics") && args.server.config.metrics.enabled,
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+impl Service {
+	/// Whether metrics are recorded and served.
+	#[inline]
+	#[must_use]
+	pub fn enabled(&self) -> bool { self.enabled }
+
+	/// Increments a counter by one.
+	#[inline]
+	pub fn inc(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+		self.add(name, help, labels, 1);
+	}
+
+	/// Increments a counter.
+	pub fn add(
+		&self,
+		name: &'static str,
+		help: &'static str,
+		labels: &[(&str, &str)],
+		value: u64,
+	) {
+		if !self.enabled {
+			return;
+		}
+
+		self.with_series(
+			name,
+			help,
+			labels,
+			|| Series::Counter(AtomicU64::new(0)),
+			|series| {
+				if let Series::Counter(counter) = series {
+					counter.fetch_add(value, Ordering::Relaxed);
+				}
+			},
+		);
+	}
+
+	/// Records a duration into a latency histogram.
+	pub fn observe(
+		&self,
+		name: &'static str,
+		help: &'static str,
+		labels: &[(&str, &str)],
+		duration: Duration,
+	) {
+		if !self.enabled {
+			return;
+		}
+
+		let make = || Series::Histogram(Histogram::default());
+		self.with_series(name, help, labels, make, |series| {
+			if let Series::Histogram(histogram) = series {
+				histogram.observe(duration);
+			}
+		});
+	}
+
+	/// All metrics in the Prometheus text exposition format, including
+	/// gauges sampled from other services at the time of the call.
+	pub fn render(&self) -> Result<String> {
+		let mut out = String::new();
+		render::families(&mut out, &self.families.read().expect("locked for reading"))?;
+
+		let queue_depth = self.services.sending.queue_depth();
+		render::gauge(
+			&mut out,
+			"tuwunel_sending_queue_depth",
+			"Messages dispatched to the federation senders not yet picked up.",
+			&[(String::new(), queue_depth)],
+		)?;
+
+		let snake_connections = self.services.sync.snake_connections_count();
+		render::gauge(
+			&mut out,
+			"tuwunel_sync_connections",
+			"Sliding sync connections with cached state.",
+			&[(String::new(), snake_connections)],
+		)?;
+
+		let map_sizes: Vec<_> = self
+			.services
+			.db
+			.iter()
+			.filter_map(|(name, map)| {
+				let size = map
+					.property_integer(c"rocksdb.estimate-live-data-size")
+					.ok()?;
+
+				Some((render::labels(&[("map", name)]), usize_from_u64_truncated(size)))
+			})
+			.collect();
+
+		render::gauge(
+			&mut out,
+			"tuwunel_database_map_size_bytes",
+			"Estimated size of the live data of each database map.",
+			&map_sizes,
+		)?;
+
+		Ok(out)
+	}
+
+	fn with_series<F, G>(
+		&self,
+		name: &'static str,
+		help: &'static str,
+		labels: &[(&str, &str)],
+		make: F,
+		record: G,
+	) where
+		F: FnOnce() -> Series,
+		G: FnOnce(&Series),
+	{
+		let labels = render::labels(labels);
+		if let Some(series) = self
+			.families
+			.read()
+			.expect("locked for reading")
+			.get(name)
+			.and_then(|family| family.series.get(&labels))
+		{
+			record(series);
+			return;
+		}
+
+		let mut families = self.families.write().expect("locked for writing");
+		let series = families
+			.entry(name)
+			.or_insert_with(|| Family { help, series: BTreeMap::new() })
+			.series
+			.entry(labels)
+			.or_insert_with(make);
+
+		record(series);
+	}
+}
+
+impl Histogram {
+	fn observe(&self, duration: Duration) {
+		let seconds = duration.as_secs_f64();
+		if let Some(bucket) = LATENCY_BUCKETS
+			.iter()
+			.position(|&le| seconds <= le)
+		{
+			self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+		}
+
+		let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+		self.sum_micros
+			.fetch_add(micros, Ordering::Relaxed);
+		self.count.fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+impl Default for Histogram {
+	fn default() -> Self {
+		Self {
+			buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+			count: AtomicU64::new(0),
+			sum_micros: AtomicU64::new(0),
+		}
+	}
+}
@@ -0,0 +1,94 @@
+use std::{fmt::Write, sync::atomic::Ordering, time::Duration};
+
+use tuwunel_core::Result;
+
+use super::{Families, Histogram, LATENCY_BUCKETS, Series};
+
+/// Renders label pairs as `name="value",...` with the values escaped.
+pub(super) fn labels(labels: &[(&str, &str)]) -> String {
+	let mut out = String::new();
+	for (i, (name, value)) in labels.iter().enumerate() {
+		if i > 0 {
+			out.push(',');
+		}
+
+		out.push_str(name);
+		out.push_str("=\"");
+		for c in value.chars() {
+			match c {
+				| '\\' => out.push_str("\\\\"),
+				| '"' => out.push_str("\\\""),
+				| '\n' => out.push_str("\\n"),
+				| c => out.push(c),
+			}
+		}
+
+		out.push('"');
+	}
+
+	out
+}
+
+pub(super) fn families(out: &mut String, families: &Families) -> Result {
+	for (name, family) in families {
+		let kind = match family.series.values().next() {
+			| Some(Series::Counter(_)) => "counter",
+			| Some(Series::Histogram(_)) => "histogram",
+			| None => continue,
+		};
+
+		writeln!(out, "# HELP {name} {}", family.help)?;
+		writeln!(out, "# TYPE {name} {kind}")?;
+		for (labels, series) in &family.series {
+			match series {
+				| Series::Counter(counter) => {
+					let value = counter.load(Ordering::Relaxed);
+					writeln!(out, "{name}{} {value}", braced(labels))?;
+				},
+				| Series::Histogram(histogram) => histogram_series(out, name, labels, histogram)?,
+			}
+		}
+	}
+
+	Ok(())
+}
+
+pub(super) fn gauge(
+	out: &mut String,
+	name: &str,
+	help: &str,
+	series: &[(String, usize)],
+) -> Result {
+	writeln!(out, "# HELP {name} {help}")?;
+	writeln!(out, "# TYPE {name} gauge")?;
+	for (labels, value) in series {
+		writeln!(out, "{name}{} {value}", braced(labels))?;
+	}
+
+	Ok(())
+}
+
+fn histogram_series(out: &mut String, name: &str, labels: &str, histogram: &Histogram) -> Result {
+	let sep = if labels.is_empty() { "" } else { "," };
+	let mut cumulative: u64 = 0;
+	for (le, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+		cumulative = cumulative.saturating_add(bucket.load(Ordering::Relaxed));
+		writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{le}\"}} {cumulative}")?;
+	}
+
+	let count = histogram.count.load(Ordering::Relaxed);
+	let sum = Duration::from_micros(histogram.sum_micros.load(Ordering::Relaxed));
+	writeln!(out, "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {count}")?;
+	writeln!(out, "{name}_sum{} {}", braced(labels), sum.as_secs_f64())?;
+	writeln!(out, "{name}_count{} {count}", braced(labels))?;
+
+	Ok(())
+}
+
+fn braced(labels: &str) -> String {
+	if labels.is_empty() {
+		String::new()
+	} else {
+		format!("{{{labels}}}")
+	}
+}
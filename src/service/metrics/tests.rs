@@ -0,0 +1,57 @@
+use std::{
+	collections::BTreeMap,
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+
+use super::{Families, Family, Histogram, Series, render};
+
+#[test]
+fn labels_escaped() {
+	let labels = render::labels(&[("route", "/a\"b\\c\nd"), ("status", "200")]);
+	assert_eq!(labels, r#"route="/a\"b\\c\nd",status="200""#);
+}
+
+#[test]
+fn counter_rendered() {
+	let counter = AtomicU64::new(0);
+	counter.fetch_add(3, Ordering::Relaxed);
+
+	let families: Families = BTreeMap::from([("requests_total", Family {
+		help: "Requests.",
+		series: BTreeMap::from([(
+			render::labels(&[("status", "200")]),
+			Series::Counter(counter),
+		)]),
+	})]);
+
+	let mut out = String::new();
+	render::families(&mut out, &families).expect("rendered");
+	assert_eq!(
+		out,
+		"# HELP requests_total Requests.\n# TYPE requests_total \
+		 counter\nrequests_total{status=\"200\"} 3\n"
+	);
+}
+
+#[test]
+fn histogram_buckets_cumulative() {
+	let histogram = Histogram::default();
+	histogram.observe(Duration::from_millis(3));
+	histogram.observe(Duration::from_millis(40));
+	histogram.observe(Duration::from_secs(60));
+
+	let families: Families = BTreeMap::from([("latency_seconds", Family {
+		help: "Latency.",
+		series: BTreeMap::from([(String::new(), Series::Histogram(histogram))]),
+	})]);
+
+	let mut out = String::new();
+	render::families(&mut out, &families).expect("rendered");
+	assert!(out.contains("# TYPE latency_seconds histogram\n"));
+	assert!(out.contains("latency_seconds_bucket{le=\"0.005\"} 1\n"));
+	assert!(out.contains("latency_seconds_bucket{le=\"0.05\"} 2\n"));
+	assert!(out.contains("latency_seconds_bucket{le=\"30\"} 2\n"));
+	assert!(out.contains("latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+	assert!(out.contains("latency_seconds_count 3\n"));
+}
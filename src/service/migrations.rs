@@ -3,7 +3,7 @@
 use futures::{FutureExt, StreamExt};
 use itertools::Itertools;
 use ruma::{
-	OwnedUserId, RoomId, UserId,
+	OwnedRoomId, OwnedUserId, RoomId, UserId,
 	events::{
 		GlobalAccountDataEventType, push_rules::PushRulesEvent, room::member::MembershipState,
 	},
@@ -13,13 +13,22 @@
 	Err, Result, debug, debug_info, debug_warn, error, info,
 	result::NotFound,
 	utils::{
-		IterStream, ReadyExt,
+		IterStream, ReadyExt, millis_since_unix_epoch,
 		stream::{TryExpect, TryIgnore},
 	},
 	warn,
 };
 
-use crate::{Services, media};
+use crate::{
+	Services,
+	appservice::RegistrationInfo,
+	media,
+	rooms::{
+		search::SEARCH_TOKENIZER,
+		state_cache::{PENDING_ROOMS_COUNTED, SHARED_ROOMS_INDEXED},
+	},
+	users::device::{DEVICES_SEEN_SINCE, token_hash},
+};
 
 /// The current schema version.
 /// - If database is opened at greater version we reject with error. The
@@ -65,6 +74,16 @@ async fn fresh(services: &Services) -> Result {
 	db["global"].insert(b"retroactively_fix_bad_data_from_roomuserid_joined", []);
 	db["global"].insert(b"fix_referencedevents_missing_sep", []);
 	db["global"].insert(b"fix_readreceiptid_readreceipt_duplicates", []);
+	db["global"].insert(b"hash_tokens_at_rest", []);
+	db["global"].insert(SHARED_ROOMS_INDEXED, []);
+	db["global"].insert(b"populate_roomid_creation", []);
+	db["global"].insert(PENDING_ROOMS_COUNTED, []);
+	db["global"].insert(b"populate_publicroomid_summary", []);
+	db["global"].insert(b"index_useridleftcount_roomid", []);
+	db["global"].insert(b"mark_guest_accounts", []);
+	db["global"].raw_put(DEVICES_SEEN_SINCE, millis_since_unix_epoch());
+	db["global"].insert(SEARCH_TOKENIZER, services.search.tokenizer_name());
+	services.state_cache.set_shared_rooms_indexed();
 
 	// Create the admin room and server user on first run
 	if services.config.create_admin_room {
@@ -144,6 +163,79 @@ async fn migrate(services: &Services) -> Result {
 		fix_readreceiptid_readreceipt_duplicates(services).await?;
 	}
 
+	if db["global"]
+		.get(b"hash_tokens_at_rest")
+		.await
+		.is_not_found()
+	{
+		hash_tokens_at_rest(services).await?;
+	}
+
+	if db["global"]
+		.get(SHARED_ROOMS_INDEXED)
+		.await
+		.is_not_found()
+	{
+		index_serveruserid_sharedroomcount(services).await?;
+	}
+
+	if db["global"]
+		.get(b"populate_roomid_creation")
+		.await
+		.is_not_found()
+	{
+		populate_roomid_creation(services).await?;
+	}
+
+	if db["global"]
+		.get(PENDING_ROOMS_COUNTED)
+		.await
+		.is_not_found()
+	{
+		count_userid_pendingroomscount(services).await?;
+	}
+
+	if db["global"]
+		.get(b"populate_publicroomid_summary")
+		.await
+		.is_not_found()
+	{
+		populate_publicroomid_summary(services).await?;
+	}
+
+	if db["global"]
+		.get(b"index_useridleftcount_roomid")
+		.await
+		.is_not_found()
+	{
+		index_useridleftcount_roomid(services).await?;
+	}
+
+	if db["global"]
+		.get(b"mark_guest_accounts")
+		.await
+		.is_not_found()
+	{
+		mark_guest_accounts(services).await?;
+	}
+
+	if db["global"]
+		.get(DEVICES_SEEN_SINCE)
+		.await
+		.is_not_found()
+	{
+		note_devices_seen_since(services);
+	}
+
+	let tokenizer = services.search.tokenizer_name();
+	if !db["global"]
+		.get(SEARCH_TOKENIZER)
+		.await
+		.is_ok_and(|stored| stored.as_ref() == tokenizer.as_bytes())
+	{
+		rebuild_search_index(services, tokenizer).await?;
+	}
+
 	if services.globals.db.database_version().await < 17 {
 		services.globals.db.bump_database_version(17);
 		info!("Migration: Bumped database version to 17");
@@ -575,3 +667,215 @@ async fn fix_readreceiptid_readreceipt_duplicates(services: &Services) -> Result
 	db["global"].insert(b"fix_readreceiptid_readreceipt_duplicates", []);
 	db.db.sort()
 }
+
+async fn hash_tokens_at_rest(services: &Services) -> Result {
+	// Tokens are printable; their digests are binary with overwhelming
+	// likelihood. This makes the migration safe to repeat if interrupted.
+	fn is_plaintext(token: &[u8]) -> bool { token.iter().all(u8::is_ascii_graphic) }
+
+	warn!("Hashing access and refresh tokens stored in plaintext...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+	let token_userdeviceid = db["token_userdeviceid"].clone();
+
+	let tokens: Vec<(Vec<u8>, Vec<u8>)> = token_userdeviceid
+		.raw_stream()
+		.expect_ok()
+		.ready_filter(|(token, _)| is_plaintext(token))
+		.map(|(token, val)| (token.to_vec(), val.to_vec()))
+		.collect()
+		.await;
+
+	let total = tokens.len();
+	for (token, val) in tokens {
+		token_userdeviceid.insert(&token_hash(&token), val);
+		token_userdeviceid.remove(&token);
+	}
+
+	let mut fixed: usize = 0;
+	for map in ["userdeviceid_token", "userdeviceid_refresh"] {
+		let userdeviceid_token = db[map].clone();
+		let tokens: Vec<(Vec<u8>, Vec<u8>)> = userdeviceid_token
+			.raw_stream()
+			.expect_ok()
+			.ready_filter(|(_, token)| is_plaintext(token))
+			.map(|(userdeviceid, token)| (userdeviceid.to_vec(), token.to_vec()))
+			.collect()
+			.await;
+
+		fixed = fixed.saturating_add(tokens.len());
+		for (userdeviceid, token) in tokens {
+			userdeviceid_token.insert(&userdeviceid, token_hash(&token));
+		}
+	}
+
+	drop(cork);
+	info!(?total, ?fixed, "Hashed tokens in 'token_userdeviceid' and reverse mappings.");
+
+	db["global"].insert(b"hash_tokens_at_rest", []);
+	db.db.sort()
+}
+
+async fn index_serveruserid_sharedroomcount(services: &Services) -> Result {
+	warn!("Building index of rooms shared between servers and local users...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+
+	services.state_cache.rebuild_shared_rooms().await;
+
+	drop(cork);
+	db["global"].insert(SHARED_ROOMS_INDEXED, []);
+	services.state_cache.set_shared_rooms_indexed();
+
+	info!("Built index 'serveruserid_sharedroomcount'.");
+	db.db.sort()
+}
+
+async fn populate_roomid_creation(services: &Services) -> Result {
+	warn!("Recording the creation of known rooms from their create events...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+
+	let room_ids: Vec<_> = services
+		.metadata
+		.iter_ids()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	// The server a remote room was joined through is not known in hindsight.
+	for room_id in &room_ids {
+		services
+			.metadata
+			.record_creation(room_id, None)
+			.await;
+	}
+
+	drop(cork);
+	info!(total = room_ids.len(), "Recorded the creation of known rooms.");
+
+	db["global"].insert(b"populate_roomid_creation", []);
+	db.db.sort()
+}
+
+async fn count_userid_pendingroomscount(services: &Services) -> Result {
+	warn!("Counting the rooms users are invited to and knocking on...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+
+	services.state_cache.rebuild_pending_rooms().await;
+
+	drop(cork);
+	db["global"].insert(PENDING_ROOMS_COUNTED, []);
+
+	info!("Built indexes 'userid_invitedroomscount' and 'userid_knockedroomscount'.");
+	db.db.sort()
+}
+
+async fn populate_publicroomid_summary(services: &Services) -> Result {
+	warn!("Caching the directory summaries of published rooms...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+
+	let total = services.directory.rebuild_summaries().await;
+
+	drop(cork);
+	info!(?total, "Cached the directory summaries of published rooms.");
+
+	db["global"].insert(b"populate_publicroomid_summary", []);
+	db.db.sort()
+}
+
+async fn index_useridleftcount_roomid(services: &Services) -> Result {
+	warn!("Indexing the rooms users left by when they left...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+
+	let total = services.state_cache.index_rooms_left().await;
+
+	drop(cork);
+	info!(?total, "Built index 'useridleftcount_roomid'.");
+
+	db["global"].insert(b"index_useridleftcount_roomid", []);
+	db.db.sort()
+}
+
+/// Guests were registered without a password and without an origin of their
+/// own, and were taken for deactivated accounts.
+async fn mark_guest_accounts(services: &Services) -> Result {
+	warn!("Telling guest accounts apart from deactivated ones...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+
+	// Registrations are not loaded until the services have started.
+	let appservices: Vec<RegistrationInfo> = services
+		.appservice
+		.stored_registrations()
+		.ready_filter_map(Result::ok)
+		.ready_filter_map(|(_, registration)| registration.try_into().ok())
+		.collect()
+		.await;
+
+	let total = services
+		.users
+		.mark_guests(|user_id| {
+			appservices
+				.iter()
+				.any(|info| info.is_user_match(user_id))
+		})
+		.await;
+
+	drop(cork);
+	info!(?total, "Marked guest accounts in 'userid_origin'.");
+
+	db["global"].insert(b"mark_guest_accounts", []);
+	db.db.sort()
+}
+
+/// The search index was built by another tokenizer than the one configured,
+/// or before the tokenizer could be configured; searches would miss messages
+/// until it is built again.
+async fn rebuild_search_index(services: &Services, tokenizer: &'static str) -> Result {
+	warn!(tokenizer, "Rebuilding the search index with the tokenizer configured...");
+
+	let db = &services.db;
+	let cork = db.cork_and_sync();
+
+	let room_ids: Vec<OwnedRoomId> = services
+		.metadata
+		.iter_ids()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut indexed = 0_usize;
+	for room_id in &room_ids {
+		match services.search.rebuild_index(room_id).await {
+			| Ok(count) => indexed = indexed.saturating_add(count),
+			| Err(e) => debug_warn!(%room_id, "Failed to rebuild the search index: {e}"),
+		}
+	}
+
+	drop(cork);
+	info!(rooms = room_ids.len(), indexed, "Rebuilt the search index.");
+
+	db["global"].insert(SEARCH_TOKENIZER, tokenizer);
+	db.db.sort()
+}
+
+/// Devices were only noted as seen when created or renamed before; they are
+/// taken as seen now so none is pruned before being inactive for the whole
+/// duration from now on.
+fn note_devices_seen_since(services: &Services) {
+	let now = millis_since_unix_epoch();
+	services.db["global"].raw_put(DEVICES_SEEN_SINCE, now);
+
+	info!(now, "Noting when devices are seen from now on.");
+}
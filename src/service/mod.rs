@@ -1,6 +1,8 @@
 #![type_length_limit = "8192"]
 #![allow(refining_impl_trait)]
 
+#[cfg(any(test, feature = "test"))]
+pub mod fixture;
 mod manager;
 mod migrations;
 mod once_services;
@@ -13,19 +15,26 @@
 pub mod client;
 pub mod config;
 pub mod deactivate;
+pub mod embed;
 pub mod emergency;
 pub mod federation;
 pub mod globals;
 pub mod key_backups;
 pub mod media;
 pub mod membership;
+pub mod metrics;
+pub mod moderation;
 pub mod presence;
 pub mod pusher;
+pub mod ratelimit;
 pub mod resolver;
 pub mod rooms;
 pub mod sending;
 pub mod server_keys;
+pub mod server_notices;
+pub mod sso;
 pub mod sync;
+pub mod threepid;
 pub mod transaction_ids;
 pub mod uiaa;
 pub mod users;
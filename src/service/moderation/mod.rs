@@ -0,0 +1,244 @@
+mod rules;
+#[cfg(test)]
+mod tests;
+
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use ruma::{RoomId, UserId, api::client::error::ErrorKind};
+use tuwunel_core::{Error, Result, debug_info, http::StatusCode, matrix::pdu::PduEvent};
+
+pub use self::rules::Rules;
+
+/// Pluggable spam checks consulted at the decision points below. Every
+/// method allows by default; implementors override the ones they need.
+#[async_trait]
+pub trait SpamChecker: Send + Sync {
+	/// Checks an event created by a local user before it is appended.
+	async fn check_event_for_spam(&self, _pdu: &PduEvent) -> Decision { Decision::Allow }
+
+	async fn user_may_invite(
+		&self,
+		_inviter: &UserId,
+		_invitee: &UserId,
+		_room_id: &RoomId,
+	) -> Decision {
+		Decision::Allow
+	}
+
+	/// Told of an invite once it was sent, after `user_may_invite` allowed
+	/// it.
+	async fn invite_sent(&self, _inviter: &UserId, _invitee: &UserId, _room_id: &RoomId) {}
+
+	async fn user_may_create_room(&self, _user_id: &UserId) -> Decision { Decision::Allow }
+
+	async fn user_may_join_room(
+		&self,
+		_user_id: &UserId,
+		_room_id: &RoomId,
+		_is_invited: bool,
+	) -> Decision {
+		Decision::Allow
+	}
+
+	/// Checks a file uploaded by a local user before it is stored.
+	async fn check_media_for_spam(
+		&self,
+		_user_id: &UserId,
+		_content_type: Option<&str>,
+		_file: &[u8],
+	) -> Decision {
+		Decision::Allow
+	}
+}
+
+#[derive(Clone, Debug)]
+pub enum Decision {
+	Allow,
+
+	/// Reject the request with the given errcode.
+	Deny(ErrorKind, String),
+
+	/// Pretend the request succeeded without acting on it. Only meaningful for
+	/// events; the other decision points treat it as a denial.
+	SoftFail,
+}
+
+pub struct Service {
+	services: Arc<crate::services::OnceServices>,
+	checkers: RwLock<Vec<Arc<dyn SpamChecker>>>,
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let rules = Rules::new(&args.server.config.moderation);
+
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			checkers: RwLock::new(vec![Arc::new(rules)]),
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+impl Service {
+	/// Adds a checker consulted after the built-in rules and any checkers
+	/// registered before it. The first decision other than `Allow` wins.
+	/// Modules register their checkers once the services are up, the way the
+	/// admin module installs its command handler.
+	pub fn register(&self, checker: Arc<dyn SpamChecker>) {
+		self.checkers
+			.write()
+			.expect("locked for writing")
+			.push(checker);
+	}
+
+	/// Removes a checker previously added with `register`.
+	pub fn unregister(&self, checker: &Arc<dyn SpamChecker>) {
+		self.checkers
+			.write()
+			.expect("locked for writing")
+			.retain(|registered| !Arc::ptr_eq(registered, checker));
+	}
+
+	pub async fn check_event_for_spam(&self, pdu: &PduEvent) -> Decision {
+		if self.services.globals.server_user == pdu.sender {
+			return Decision::Allow;
+		}
+
+		for checker in self.checkers() {
+			let decision = checker.check_event_for_spam(pdu).await;
+			if !matches!(decision, Decision::Allow) {
+				debug_info!(event_id = %pdu.event_id, ?decision, "Event failed spam check");
+				return decision;
+			}
+		}
+
+		Decision::Allow
+	}
+
+	pub async fn user_may_invite(
+		&self,
+		inviter: &UserId,
+		invitee: &UserId,
+		room_id: &RoomId,
+	) -> Result {
+		if self.services.globals.server_user == inviter {
+			return Ok(());
+		}
+
+		for checker in self.checkers() {
+			checker
+				.user_may_invite(inviter, invitee, room_id)
+				.await
+				.check()?;
+		}
+
+		Ok(())
+	}
+
+	pub async fn invite_sent(&self, inviter: &UserId, invitee: &UserId, room_id: &RoomId) {
+		if self.services.globals.server_user == inviter {
+			return;
+		}
+
+		for checker in self.checkers() {
+			checker
+				.invite_sent(inviter, invitee, room_id)
+				.await;
+		}
+	}
+
+	pub async fn user_may_create_room(&self, user_id: &UserId) -> Result {
+		if self.services.globals.server_user == user_id {
+			return Ok(());
+		}
+
+		for checker in self.checkers() {
+			checker
+				.user_may_create_room(user_id)
+				.await
+				.check()?;
+		}
+
+		Ok(())
+	}
+
+	pub async fn user_may_join_room(
+		&self,
+		user_id: &UserId,
+		room_id: &RoomId,
+		is_invited: bool,
+	) -> Result {
+		if self.services.globals.server_user == user_id {
+			return Ok(());
+		}
+
+		for checker in self.checkers() {
+			checker
+				.user_may_join_room(user_id, room_id, is_invited)
+				.await
+				.check()?;
+		}
+
+		Ok(())
+	}
+
+	pub async fn check_media_for_spam(
+		&self,
+		user_id: &UserId,
+		content_type: Option<&str>,
+		file: &[u8],
+	) -> Result {
+		if self.services.globals.server_user == user_id {
+			return Ok(());
+		}
+
+		for checker in self.checkers() {
+			checker
+				.check_media_for_spam(user_id, content_type, file)
+				.await
+				.check()?;
+		}
+
+		Ok(())
+	}
+
+	fn checkers(&self) -> Vec<Arc<dyn SpamChecker>> {
+		self.checkers
+			.read()
+			.expect("locked for reading")
+			.clone()
+	}
+}
+
+impl Decision {
+	#[must_use]
+	pub fn forbidden(reason: &str) -> Self {
+		Self::Deny(ErrorKind::forbidden(), reason.to_owned())
+	}
+
+	/// Converts any decision other than `Allow` into an error: 403 for
+	/// `M_FORBIDDEN`, 429 for `M_LIMIT_EXCEEDED` keeping its `retry_after`,
+	/// and 400 otherwise.
+	pub fn check(self) -> Result {
+		match self {
+			| Self::Allow => Ok(()),
+			| Self::Deny(kind, reason) => {
+				let status = match kind {
+					| ErrorKind::Forbidden { .. } => StatusCode::FORBIDDEN,
+					| ErrorKind::LimitExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+					| _ => StatusCode::BAD_REQUEST,
+				};
+
+				Err(Error::Request(kind, reason.into(), status))
+			},
+			| Self::SoftFail => Err(Error::Request(
+				ErrorKind::forbidden(),
+				"Request was rejected by the server.".into(),
+				StatusCode::FORBIDDEN,
+			)),
+		}
+	}
+}
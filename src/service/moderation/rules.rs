@@ -0,0 +1,165 @@
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use ruma::{
+	OwnedUserId, RoomId, UserId,
+	api::client::error::{ErrorKind, RetryAfter},
+};
+use tuwunel_core::{config::ModerationConfig, matrix::pdu::PduEvent};
+
+use super::{Decision, SpamChecker};
+use crate::rooms::timeline::ExtractBody;
+
+const INVITE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Built-in checker driven by the `[global.moderation]` config section.
+pub struct Rules {
+	config: ModerationConfig,
+	invites: Mutex<HashMap<OwnedUserId, (Instant, u32)>>,
+}
+
+impl Rules {
+	#[must_use]
+	pub fn new(config: &ModerationConfig) -> Self {
+		Self {
+			config: config.clone(),
+			invites: Mutex::default(),
+		}
+	}
+
+	fn check_sender(&self, user_id: &UserId) -> Decision {
+		if self
+			.config
+			.forbidden_senders
+			.is_match(user_id.as_str())
+		{
+			return Decision::forbidden("You are not allowed to do this on this server.");
+		}
+
+		Decision::Allow
+	}
+
+	fn check_room(&self, room_id: &RoomId) -> Decision {
+		if self
+			.config
+			.forbidden_rooms
+			.is_match(room_id.as_str())
+		{
+			return Decision::forbidden("This room is not allowed on this server.");
+		}
+
+		Decision::Allow
+	}
+
+	fn check_invites(&self, inviter: &UserId) -> Decision {
+		let max = self.config.max_invites_per_hour;
+		if max == 0 {
+			return Decision::Allow;
+		}
+
+		let now = Instant::now();
+		let mut invites = self.invites.lock().expect("locked");
+		invites.retain(|_, (start, _)| now.duration_since(*start) < INVITE_WINDOW);
+
+		match invites.get(inviter) {
+			| Some((start, count)) if *count >= max => {
+				let retry_after = INVITE_WINDOW.saturating_sub(now.duration_since(*start));
+				Decision::Deny(
+					ErrorKind::LimitExceeded {
+						retry_after: Some(RetryAfter::Delay(retry_after)),
+					},
+					"Too many invites sent; try again later.".to_owned(),
+				)
+			},
+			| _ => Decision::Allow,
+		}
+	}
+
+	fn count_invite(&self, inviter: &UserId) {
+		if self.config.max_invites_per_hour == 0 {
+			return;
+		}
+
+		let now = Instant::now();
+		let mut invites = self.invites.lock().expect("locked");
+		let (start, count) = invites
+			.entry(inviter.to_owned())
+			.or_insert((now, 0));
+
+		if now.duration_since(*start) >= INVITE_WINDOW {
+			*start = now;
+			*count = 0;
+		}
+
+		*count = count.saturating_add(1);
+	}
+}
+
+#[async_trait]
+impl SpamChecker for Rules {
+	async fn check_event_for_spam(&self, pdu: &PduEvent) -> Decision {
+		// Memberships have checks of their own, and a user must always be able
+		// to leave a room.
+		if pdu.state_key.is_some() {
+			return Decision::Allow;
+		}
+
+		let decision = self.check_sender(&pdu.sender);
+		if !matches!(decision, Decision::Allow) {
+			return decision;
+		}
+
+		let Some(body) = serde_json::from_str::<ExtractBody>(pdu.content.get())
+			.ok()
+			.and_then(|content| content.body)
+		else {
+			return Decision::Allow;
+		};
+
+		if self.config.forbidden_bodies.is_match(&body) {
+			return Decision::forbidden("This message is not allowed on this server.");
+		}
+
+		if self.config.soft_fail_bodies.is_match(&body) {
+			return Decision::SoftFail;
+		}
+
+		Decision::Allow
+	}
+
+	async fn user_may_invite(
+		&self,
+		inviter: &UserId,
+		_invitee: &UserId,
+		room_id: &RoomId,
+	) -> Decision {
+		[self.check_sender(inviter), self.check_room(room_id)]
+			.into_iter()
+			.find(|decision| !matches!(decision, Decision::Allow))
+			.unwrap_or_else(|| self.check_invites(inviter))
+	}
+
+	async fn invite_sent(&self, inviter: &UserId, _invitee: &UserId, _room_id: &RoomId) {
+		self.count_invite(inviter);
+	}
+
+	async fn user_may_create_room(&self, user_id: &UserId) -> Decision {
+		self.check_sender(user_id)
+	}
+
+	async fn user_may_join_room(
+		&self,
+		user_id: &UserId,
+		room_id: &RoomId,
+		_is_invited: bool,
+	) -> Decision {
+		[self.check_sender(user_id), self.check_room(room_id)]
+			.into_iter()
+			.find(|decision| !matches!(decision, Decision::Allow))
+			.unwrap_or(Decision::Allow)
+	}
+}
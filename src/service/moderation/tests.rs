@@ -0,0 +1,360 @@
+use futures::FutureExt;
+use regex::RegexSet;
+use ruma::{
+	UserId,
+	api::client::error::ErrorKind,
+	events::{
+		TimelineEventType,
+		room::member::{MembershipState, RoomMemberEventContent},
+	},
+	room_id, user_id,
+};
+use serde_json::json;
+use tuwunel_core::{
+	config::ModerationConfig,
+	http::StatusCode,
+	matrix::pdu::{PduBuilder, PduEvent},
+};
+
+use super::{Decision, Rules, SpamChecker};
+use crate::fixture::Fixture;
+
+fn message(body: &str) -> PduEvent {
+	PduEvent::fake(
+		"$event:example.com",
+		"@user:example.com",
+		TimelineEventType::RoomMessage,
+		&json!({ "msgtype": "m.text", "body": body }),
+	)
+}
+
+#[tokio::test]
+async fn forbidden_body_denied() {
+	let rules = Rules::new(&ModerationConfig {
+		forbidden_bodies: RegexSet::new(["fortnite ?cards"]).unwrap(),
+		..Default::default()
+	});
+
+	let decision = rules
+		.check_event_for_spam(&message("cheap fortnitecards here"))
+		.await;
+
+	assert!(
+		matches!(decision, Decision::Deny(ErrorKind::Forbidden { .. }, _)),
+		"{decision:?}"
+	);
+
+	let error = decision.check().expect_err("event denied");
+	assert_eq!(error.status_code(), StatusCode::FORBIDDEN, "denials are answered with 403");
+	assert!(matches!(error.kind(), ErrorKind::Forbidden { .. }), "{error:?}");
+
+	let decision = rules
+		.check_event_for_spam(&message("hello"))
+		.await;
+
+	assert!(matches!(decision, Decision::Allow), "{decision:?}");
+}
+
+#[tokio::test]
+async fn soft_fail_body() {
+	let rules = Rules::new(&ModerationConfig {
+		soft_fail_bodies: RegexSet::new(["^buy now"]).unwrap(),
+		..Default::default()
+	});
+
+	let decision = rules
+		.check_event_for_spam(&message("buy now!"))
+		.await;
+
+	assert!(matches!(decision, Decision::SoftFail), "{decision:?}");
+}
+
+#[tokio::test]
+async fn invite_limit_exceeded() {
+	let rules = Rules::new(&ModerationConfig {
+		max_invites_per_hour: 2,
+		..Default::default()
+	});
+
+	let inviter = user_id!("@inviter:example.com");
+	let other = user_id!("@other:example.com");
+	let invitee = user_id!("@invitee:example.com");
+	let room_id = room_id!("!room:example.com");
+
+	let decision = rules
+		.user_may_invite(inviter, invitee, room_id)
+		.await;
+
+	assert!(matches!(decision, Decision::Allow), "{decision:?}");
+
+	// Only invites which were sent count
+	for _ in 0..2 {
+		let decision = rules
+			.user_may_invite(inviter, invitee, room_id)
+			.await;
+
+		assert!(matches!(decision, Decision::Allow), "{decision:?}");
+		rules.invite_sent(inviter, invitee, room_id).await;
+	}
+
+	let decision = rules
+		.user_may_invite(inviter, invitee, room_id)
+		.await;
+
+	assert!(
+		matches!(decision, Decision::Deny(ErrorKind::LimitExceeded { .. }, _)),
+		"{decision:?}"
+	);
+
+	let error = decision.check().expect_err("invite denied");
+	assert_eq!(
+		error.status_code(),
+		StatusCode::TOO_MANY_REQUESTS,
+		"limits are answered with 429"
+	);
+	assert!(
+		matches!(error.kind(), ErrorKind::LimitExceeded { retry_after: Some(_) }),
+		"the error says when to retry: {error:?}"
+	);
+
+	let decision = rules
+		.user_may_invite(other, invitee, room_id)
+		.await;
+
+	assert!(matches!(decision, Decision::Allow), "invite limit is per inviter: {decision:?}");
+}
+
+struct RejectExecutables;
+
+#[async_trait::async_trait]
+impl SpamChecker for RejectExecutables {
+	async fn check_media_for_spam(
+		&self,
+		_user_id: &UserId,
+		content_type: Option<&str>,
+		_file: &[u8],
+	) -> Decision {
+		if content_type == Some("application/x-msdownload") {
+			return Decision::forbidden("Executables are not allowed.");
+		}
+
+		Decision::Allow
+	}
+}
+
+#[tokio::test]
+async fn custom_checker_defaults_allow() {
+	let checker = RejectExecutables;
+	let user_id = user_id!("@user:example.com");
+	let room_id = room_id!("!room:example.com");
+
+	let decision = checker
+		.check_media_for_spam(user_id, Some("application/x-msdownload"), b"MZ")
+		.await;
+
+	assert!(
+		matches!(decision, Decision::Deny(ErrorKind::Forbidden { .. }, _)),
+		"{decision:?}"
+	);
+
+	let decision = checker
+		.check_media_for_spam(user_id, Some("image/png"), b"")
+		.await;
+
+	assert!(matches!(decision, Decision::Allow), "{decision:?}");
+
+	let decision = checker
+		.check_event_for_spam(&message("hello"))
+		.await;
+
+	assert!(matches!(decision, Decision::Allow), "{decision:?}");
+
+	let decision = checker
+		.user_may_join_room(user_id, room_id, false)
+		.await;
+
+	assert!(matches!(decision, Decision::Allow), "{decision:?}");
+}
+
+const MODERATION: &str = r#"
+[global.moderation]
+forbidden_senders = ["^@spammer:"]
+forbidden_bodies = ["fortnite ?cards"]
+max_invites_per_hour = 1
+"#;
+
+#[tokio::test]
+async fn events_checked_when_appended() {
+	let services = Fixture::start_with(MODERATION).await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("alice");
+	let spammer = embedded
+		.create_user("spammer", None)
+		.await
+		.expect("spammer");
+
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("the state events of a new room are not checked");
+
+	let error = embedded
+		.send_message(&alice, &room_id, "fortnite cards")
+		.await
+		.expect_err("forbidden body");
+	assert!(matches!(error.kind(), ErrorKind::Forbidden { .. }), "{error:?}");
+
+	embedded
+		.send_message(&alice, &room_id, "hello")
+		.await
+		.expect("other messages are sent");
+
+	// Joined before the sender was forbidden
+	let state_lock = services.state.mutex.lock(&room_id).await;
+	services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				spammer.to_string(),
+				&RoomMemberEventContent::new(MembershipState::Join),
+			),
+			&spammer,
+			&room_id,
+			&state_lock,
+		)
+		.boxed()
+		.await
+		.expect("memberships are not checked when appended");
+	drop(state_lock);
+
+	let error = embedded
+		.send_message(&spammer, &room_id, "hello")
+		.await
+		.expect_err("forbidden sender");
+	assert!(matches!(error.kind(), ErrorKind::Forbidden { .. }), "{error:?}");
+
+	embedded
+		.leave_room(&spammer, &room_id)
+		.await
+		.expect("a forbidden sender may leave");
+	assert!(
+		!services
+			.state_cache
+			.is_joined(&spammer, &room_id)
+			.await,
+		"the leave is appended"
+	);
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn joins_and_room_creation_checked() {
+	let services = Fixture::start_with(MODERATION).await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", None)
+		.await
+		.expect("bob");
+	let spammer = embedded
+		.create_user("spammer", None)
+		.await
+		.expect("spammer");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	let error = embedded
+		.join_room(&spammer, &room_id)
+		.await
+		.expect_err("forbidden sender may not join");
+	assert!(matches!(error.kind(), ErrorKind::Forbidden { .. }), "{error:?}");
+	assert!(
+		!services
+			.state_cache
+			.is_joined(&spammer, &room_id)
+			.await,
+		"no join appended"
+	);
+
+	embedded
+		.join_room(&bob, &room_id)
+		.await
+		.expect("other users join");
+
+	// As checked by the createRoom route before it creates anything
+	services
+		.moderation
+		.user_may_create_room(&spammer)
+		.await
+		.expect_err("forbidden sender may not create rooms");
+	services
+		.moderation
+		.user_may_create_room(&alice)
+		.await
+		.expect("other users create rooms");
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn only_sent_invites_counted() {
+	let services = Fixture::start_with(MODERATION).await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", None)
+		.await
+		.expect("bob");
+	let carol = embedded
+		.create_user("carol", None)
+		.await
+		.expect("carol");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	let other_room_id = embedded
+		.create_room(&carol, None)
+		.await
+		.expect("room alice is not in");
+
+	services
+		.membership
+		.invite(&alice, &bob, &other_room_id, None, false)
+		.await
+		.expect_err("alice is not in the room");
+
+	services
+		.membership
+		.invite(&alice, &bob, &room_id, None, false)
+		.await
+		.expect("the failed invite is not counted");
+	assert!(
+		services
+			.state_cache
+			.is_invited(&bob, &room_id)
+			.await,
+		"bob is invited"
+	);
+
+	let error = services
+		.membership
+		.invite(&alice, &carol, &room_id, None, false)
+		.await
+		.expect_err("limit reached");
+	assert!(matches!(error.kind(), ErrorKind::LimitExceeded { .. }), "{error:?}");
+
+	services.stop().await;
+}
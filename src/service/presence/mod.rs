@@ -1,20 +1,42 @@
 mod data;
 mod presence;
+mod status;
+#[cfg(test)]
+mod tests;
+mod timeouts;
 
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+	collections::{HashMap, HashSet},
+	sync::{Arc, Mutex},
+	time::Duration,
+};
 
 use async_trait::async_trait;
-use futures::{Stream, StreamExt, TryFutureExt, stream::FuturesUnordered};
-use loole::{Receiver, Sender};
-use ruma::{OwnedUserId, UInt, UserId, events::presence::PresenceEvent, presence::PresenceState};
+use futures::{Stream, StreamExt, TryFutureExt};
+use ruma::{
+	OwnedServerName, OwnedUserId, UInt, UserId, events::presence::PresenceEvent,
+	presence::PresenceState,
+};
 use tokio::{sync::RwLock, time::sleep};
-use tuwunel_core::{Error, Result, checked, debug, debug_warn, error, result::LogErr, trace};
-
-use self::{data::Data, presence::Presence};
+use tuwunel_core::{
+	Result, checked, debug, debug_warn,
+	result::LogErr,
+	trace,
+	utils::{IterStream, millis_since_unix_epoch, stream::ReadyExt},
+};
+
+pub use self::status::federation_state;
+use self::{
+	data::Data,
+	presence::Presence,
+	status::{check_state, truncate_status_msg},
+	timeouts::{Timeouts, next_timeout, timeout_state},
+};
 
 pub struct Service {
-	timer_channel: (Sender<TimerType>, Receiver<TimerType>),
+	timeouts: Mutex<Timeouts>,
 	timeout_remote_users: bool,
+	status_msg_max_len: usize,
 	idle_timeout: u64,
 	offline_timeout: u64,
 	db: Data,
@@ -22,7 +44,8 @@ pub struct Service {
 	last_sync_seen: RwLock<HashMap<OwnedUserId, u64>>,
 }
 
-type TimerType = (OwnedUserId, Duration);
+/// How often presence is checked for having timed out.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(15);
 
 #[async_trait]
 impl crate::Service for Service {
@@ -31,8 +54,9 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		let idle_timeout_s = config.presence_idle_timeout_s;
 		let offline_timeout_s = config.presence_offline_timeout_s;
 		Ok(Arc::new(Self {
-			timer_channel: loole::unbounded(),
+			timeouts: Mutex::default(),
 			timeout_remote_users: config.presence_timeout_remote_users,
+			status_msg_max_len: config.presence_status_msg_max_len,
 			idle_timeout: checked!(idle_timeout_s * 1_000)?,
 			offline_timeout: checked!(offline_timeout_s * 1_000)?,
 			db: Data::new(&args),
@@ -51,21 +75,10 @@ async fn worker(self: Arc<Self>) -> Result {
 				.await;
 		}
 
-		let receiver = self.timer_channel.1.clone();
-
-		let mut presence_timers = FuturesUnordered::new();
-		while !receiver.is_closed() {
+		while self.services.server.running() {
 			tokio::select! {
-				Some(user_id) = presence_timers.next() => {
-					self.process_presence_timer(&user_id).await.log_err().ok();
-				},
-				event = receiver.recv_async() => match event {
-					Err(_) => break,
-					Ok((user_id, timeout)) => {
-						debug!("Adding timer {}: {user_id} timeout:{timeout:?}", presence_timers.len());
-						presence_timers.push(presence_timer(user_id, timeout));
-					},
-				},
+				() = sleep(SWEEP_INTERVAL) => self.sweep(millis_since_unix_epoch()).await,
+				() = self.services.server.until_shutdown() => break,
 			}
 		}
 
@@ -79,11 +92,6 @@ async fn interrupt(&self) {
 				.ping_presence(&self.services.globals.server_user, &PresenceState::Offline)
 				.await;
 		}
-
-		let (timer_sender, _) = &self.timer_channel;
-		if !timer_sender.is_closed() {
-			timer_sender.close();
-		}
 	}
 
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
@@ -93,7 +101,7 @@ impl Service {
 	/// record that a user has just successfully completed a /sync (or
 	/// equivalent activity)
 	pub async fn note_sync(&self, user_id: &UserId) {
-		let now = tuwunel_core::utils::millis_since_unix_epoch();
+		let now = millis_since_unix_epoch();
 		self.last_sync_seen
 			.write()
 			.await
@@ -102,7 +110,7 @@ pub async fn note_sync(&self, user_id: &UserId) {
 
 	/// Returns milliseconds since last observed sync for user (if any)
 	pub async fn last_sync_gap_ms(&self, user_id: &UserId) -> Option<u64> {
-		let now = tuwunel_core::utils::millis_since_unix_epoch();
+		let now = millis_since_unix_epoch();
 		self.last_sync_seen
 			.read()
 			.await
@@ -119,10 +127,13 @@ pub async fn get_presence(&self, user_id: &UserId) -> Result<PresenceEvent> {
 	}
 
 	/// Pings the presence of the given user in the given room, setting the
-	/// specified state.
+	/// specified state. `busy` is refused unless enabled, as when set
+	/// directly.
 	pub async fn ping_presence(&self, user_id: &UserId, new_state: &PresenceState) -> Result {
 		const REFRESH_TIMEOUT: u64 = 60 * 1000;
 
+		check_state(new_state, self.services.server.config.allow_busy_presence)?;
+
 		let last_presence = self.db.get_presence(user_id).await;
 		let state_changed = match last_presence {
 			| Err(_) => true,
@@ -167,6 +178,7 @@ pub async fn set_presence(
 			| &_ => state,
 		};
 
+		let status_msg = truncate_status_msg(status_msg, self.status_msg_max_len);
 		self.db
 			.set_presence(user_id, presence_state, currently_active, last_active_ago, status_msg)
 			.await?;
@@ -174,31 +186,23 @@ pub async fn set_presence(
 		if (self.timeout_remote_users || self.services.globals.user_is_local(user_id))
 			&& user_id != self.services.globals.server_user
 		{
-			let timeout = match presence_state {
-				| PresenceState::Online =>
-					self.services
-						.server
-						.config
-						.presence_idle_timeout_s,
-				| _ =>
-					self.services
-						.server
-						.config
-						.presence_offline_timeout_s,
-			};
+			let last_active = millis_since_unix_epoch()
+				.saturating_sub(last_active_ago.map(u64::from).unwrap_or_default());
 
-			self.timer_channel
-				.0
-				.send((user_id.to_owned(), Duration::from_secs(timeout)))
-				.map_err(|e| {
-					error!("Failed to add presence timer: {}", e);
-					Error::bad_database("Failed to add presence timer")
-				})?;
+			self.schedule_timeout(user_id, presence_state, last_active);
 		}
 
 		Ok(())
 	}
 
+	fn schedule_timeout(&self, user_id: &UserId, state: &PresenceState, last_active: u64) {
+		let mut timeouts = self.timeouts.lock().expect("locked");
+		match next_timeout(state, last_active, self.idle_timeout, self.offline_timeout) {
+			| Some(at) => timeouts.schedule(user_id, at),
+			| None => timeouts.cancel(user_id),
+		}
+	}
+
 	/// Removes the presence record for the given user from the database.
 	///
 	/// TODO: Why is this not used?
@@ -279,43 +283,110 @@ pub async fn from_json_bytes_to_event(
 		Ok(event)
 	}
 
-	async fn process_presence_timer(&self, user_id: &OwnedUserId) -> Result {
-		let mut presence_state = PresenceState::Offline;
-		let mut last_active_ago = None;
-		let mut status_msg = None;
-
-		let presence_event = self.get_presence(user_id).await;
-
-		if let Ok(presence_event) = presence_event {
-			presence_state = presence_event.content.presence;
-			last_active_ago = presence_event.content.last_active_ago;
-			status_msg = presence_event.content.status_msg;
+	/// Times out the presence of the users who have been idle past the
+	/// thresholds, then flushes the updates of local users to federation.
+	async fn sweep(&self, now: u64) {
+		let due = self
+			.timeouts
+			.lock()
+			.expect("locked")
+			.take_due(now);
+		if due.is_empty() {
+			return;
 		}
 
-		let new_state = match (&presence_state, last_active_ago.map(u64::from)) {
-			| (PresenceState::Online, Some(ago)) if ago >= self.idle_timeout =>
-				Some(PresenceState::Unavailable),
-			| (PresenceState::Unavailable, Some(ago)) if ago >= self.offline_timeout =>
-				Some(PresenceState::Offline),
-			| _ => None,
-		};
+		let mut timed_out = Vec::new();
+		for user_id in &due {
+			match self.process_timeout(user_id, now).await {
+				| Ok(true) if self.services.globals.user_is_local(user_id) =>
+					timed_out.push(user_id),
+				| Ok(_) => {},
+				| Err(e) => debug_warn!(?user_id, "Failed to time out presence: {e}"),
+			}
+		}
 
 		debug!(
-			"Processed presence timer for user '{user_id}': Old state = {presence_state}, New \
-			 state = {new_state:?}"
+			due = due.len(),
+			timed_out = timed_out.len(),
+			pending = self.timeouts.lock().expect("locked").len(),
+			"Swept presence timeouts"
 		);
 
-		if let Some(new_state) = new_state {
-			self.set_presence(user_id, &new_state, Some(false), last_active_ago, status_msg)
-				.await?;
+		if timed_out.is_empty()
+			|| !self
+				.services
+				.server
+				.config
+				.allow_outgoing_presence
+		{
+			return;
 		}
 
-		Ok(())
+		let servers: HashSet<OwnedServerName> = timed_out
+			.into_iter()
+			.stream()
+			.flat_map(|user_id| self.services.state_cache.rooms_joined(user_id))
+			.flat_map(|room_id| self.services.state_cache.room_servers(room_id))
+			.ready_filter(|server_name| !self.services.globals.server_is_ours(server_name))
+			.map(ToOwned::to_owned)
+			.collect()
+			.await;
+
+		self.services
+			.sending
+			.flush_servers(servers.iter().map(AsRef::as_ref).stream())
+			.await
+			.log_err()
+			.ok();
 	}
-}
 
-async fn presence_timer(user_id: OwnedUserId, timeout: Duration) -> OwnedUserId {
-	sleep(timeout).await;
+	/// Moves the user's presence on if they have been idle long enough;
+	/// otherwise reschedules it from their latest activity. Returns whether
+	/// the presence changed.
+	async fn process_timeout(&self, user_id: &UserId, now: u64) -> Result<bool> {
+		let presence = self.get_presence(user_id).await?.content;
+		let last_presence = now.saturating_sub(
+			presence
+				.last_active_ago
+				.map(u64::from)
+				.unwrap_or_default(),
+		);
 
-	user_id
+		let last_sync = self
+			.last_sync_seen
+			.read()
+			.await
+			.get(user_id)
+			.copied()
+			.unwrap_or_default();
+
+		let last_active = last_presence.max(last_sync);
+		let idle_for = now.saturating_sub(last_active);
+		let new_state =
+			timeout_state(&presence.presence, idle_for, self.idle_timeout, self.offline_timeout);
+
+		trace!(
+			?user_id,
+			old = ?presence.presence,
+			new = ?new_state,
+			idle_for,
+			"Processed presence timeout"
+		);
+
+		let Some(new_state) = new_state else {
+			self.schedule_timeout(user_id, &presence.presence, last_active);
+			return Ok(false);
+		};
+
+		self.set_presence(
+			user_id,
+			&new_state,
+			Some(false),
+			UInt::new(idle_for),
+			presence.status_msg,
+		)
+		.await?;
+
+		Ok(true)
+	}
 }
@@ -0,0 +1,63 @@
+//! Limits on presence set by clients. The `busy` state of MSC3026 is only
+//! accepted when enabled and is downgraded to `unavailable` for federation;
+//! status messages are held to a maximum length, truncating or rejecting
+//! those of local users per the config and always truncating remote ones.
+
+use ruma::presence::PresenceState;
+use tuwunel_core::{Err, Result, implement};
+
+/// Checks a presence update from a local client before it is set.
+#[implement(super::Service)]
+pub fn check_update(&self, state: &PresenceState, status_msg: Option<&str>) -> Result {
+	let config = &self.services.server.config;
+	check_state(state, config.allow_busy_presence)?;
+	check_status_msg(
+		status_msg,
+		config.presence_status_msg_max_len,
+		config.presence_status_msg_truncate,
+	)
+}
+
+pub(super) fn check_state(state: &PresenceState, allow_busy: bool) -> Result {
+	if *state == PresenceState::Busy && !allow_busy {
+		return Err!(Request(InvalidParam("Busy presence is not enabled on this server.")));
+	}
+
+	Ok(())
+}
+
+/// Rejects a status message over `max_len` characters unless it is to be
+/// truncated when set.
+pub(super) fn check_status_msg(
+	status_msg: Option<&str>,
+	max_len: usize,
+	truncate: bool,
+) -> Result {
+	let exceeds = status_msg.is_some_and(|status_msg| status_msg.chars().nth(max_len).is_some());
+	if exceeds && !truncate {
+		return Err!(Request(TooLarge("Status message is longer than {max_len} characters.")));
+	}
+
+	Ok(())
+}
+
+/// The status message cut down to `max_len` characters.
+#[must_use]
+pub(super) fn truncate_status_msg(status_msg: Option<String>, max_len: usize) -> Option<String> {
+	status_msg.map(|mut status_msg| {
+		if let Some((end, _)) = status_msg.char_indices().nth(max_len) {
+			status_msg.truncate(end);
+		}
+
+		status_msg
+	})
+}
+
+/// The state sent to other servers, as not all of them understand `busy`.
+#[must_use]
+pub fn federation_state(state: PresenceState) -> PresenceState {
+	match state {
+		| PresenceState::Busy => PresenceState::Unavailable,
+		| state => state,
+	}
+}
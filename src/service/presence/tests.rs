@@ -0,0 +1,174 @@
+use ruma::{presence::PresenceState, user_id};
+
+use super::{
+	status::{check_state, check_status_msg, federation_state, truncate_status_msg},
+	timeouts::{Timeouts, next_timeout, timeout_state},
+};
+
+const IDLE: u64 = 300_000;
+const OFFLINE: u64 = 1_800_000;
+
+#[test]
+fn timeouts_due_in_order() {
+	let alice = user_id!("@alice:example.com");
+	let bob = user_id!("@bob:example.com");
+	let mut timeouts = Timeouts::default();
+
+	timeouts.schedule(alice, 200);
+	timeouts.schedule(bob, 100);
+	assert!(timeouts.take_due(99).is_empty());
+	assert_eq!(timeouts.take_due(150), [bob.to_owned()]);
+
+	// rescheduling replaces the earlier timeout
+	timeouts.schedule(alice, 300);
+	assert!(timeouts.take_due(250).is_empty());
+	assert_eq!(timeouts.take_due(300), [alice.to_owned()]);
+	assert_eq!(timeouts.len(), 0);
+
+	timeouts.schedule(alice, 400);
+	timeouts.cancel(alice);
+	assert!(timeouts.take_due(u64::MAX).is_empty());
+}
+
+#[test]
+fn idle_user_goes_unavailable_then_offline() {
+	let alice = user_id!("@alice:example.com");
+	let mut timeouts = Timeouts::default();
+	let last_active = 1_000;
+
+	let mut state = PresenceState::Online;
+	let at = next_timeout(&state, last_active, IDLE, OFFLINE).expect("online times out");
+	timeouts.schedule(alice, at);
+
+	// still active just before the idle threshold
+	let now = at.saturating_sub(1);
+	assert!(timeouts.take_due(now).is_empty());
+	assert_eq!(timeout_state(&state, now.saturating_sub(last_active), IDLE, OFFLINE), None);
+
+	let now = at;
+	assert_eq!(timeouts.take_due(now), [alice.to_owned()]);
+	state = timeout_state(&state, now.saturating_sub(last_active), IDLE, OFFLINE)
+		.expect("idle past threshold");
+	assert_eq!(state, PresenceState::Unavailable);
+
+	let at = next_timeout(&state, last_active, IDLE, OFFLINE).expect("unavailable times out");
+	assert_eq!(at, last_active.saturating_add(OFFLINE));
+	timeouts.schedule(alice, at);
+
+	let now = at;
+	assert_eq!(timeouts.take_due(now), [alice.to_owned()]);
+	state = timeout_state(&state, now.saturating_sub(last_active), IDLE, OFFLINE)
+		.expect("idle past offline threshold");
+	assert_eq!(state, PresenceState::Offline);
+	assert_eq!(next_timeout(&state, last_active, IDLE, OFFLINE), None);
+}
+
+#[test]
+fn long_idle_goes_straight_offline() {
+	assert_eq!(
+		timeout_state(&PresenceState::Online, OFFLINE, IDLE, OFFLINE),
+		Some(PresenceState::Offline)
+	);
+	assert_eq!(timeout_state(&PresenceState::Busy, OFFLINE, IDLE, OFFLINE), None);
+}
+
+#[test]
+fn busy_with_long_status_msg() {
+	let status_msg = "in a meeting until 🕒 three".to_owned();
+
+	assert!(check_state(&PresenceState::Busy, true).is_ok(), "busy accepted when enabled");
+	assert!(check_state(&PresenceState::Busy, false).is_err(), "busy rejected when disabled");
+	assert!(check_state(&PresenceState::Online, false).is_ok(), "others always accepted");
+
+	assert!(check_status_msg(Some(&status_msg), 20, false).is_err(), "long message rejected");
+	assert!(check_status_msg(Some(&status_msg), 20, true).is_ok(), "long message truncated");
+	assert!(check_status_msg(Some(&status_msg), 26, false).is_ok(), "message at the limit");
+	assert!(check_status_msg(None, 0, false).is_ok(), "no message");
+
+	let truncated = truncate_status_msg(Some(status_msg.clone()), 20);
+	assert_eq!(truncated.as_deref(), Some("in a meeting until 🕒"), "cut on a char boundary");
+
+	let unchanged = truncate_status_msg(Some(status_msg.clone()), 26);
+	assert_eq!(unchanged, Some(status_msg), "messages within the limit round-trip");
+}
+
+#[test]
+fn busy_downgraded_for_federation() {
+	assert_eq!(
+		federation_state(PresenceState::Busy),
+		PresenceState::Unavailable,
+		"busy sent as unavailable"
+	);
+	assert_eq!(
+		federation_state(PresenceState::Online),
+		PresenceState::Online,
+		"online unchanged"
+	);
+	assert_eq!(
+		federation_state(PresenceState::Offline),
+		PresenceState::Offline,
+		"offline unchanged"
+	);
+}
+
+#[tokio::test]
+async fn sync_ping_checks_busy() {
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let alice = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let pinged = services
+		.presence
+		.ping_presence(&alice, &PresenceState::Busy)
+		.await;
+	assert!(pinged.is_err(), "busy is refused when not enabled");
+	assert!(
+		services
+			.presence
+			.get_presence(&alice)
+			.await
+			.ok()
+			.is_none_or(|presence| presence.content.presence != PresenceState::Busy),
+		"the refused state is not set"
+	);
+
+	services
+		.presence
+		.ping_presence(&alice, &PresenceState::Online)
+		.await
+		.expect("online is accepted");
+	let presence = services
+		.presence
+		.get_presence(&alice)
+		.await
+		.expect("presence set");
+	assert_eq!(presence.content.presence, PresenceState::Online, "other states are set");
+
+	services.stop().await;
+
+	let services = Fixture::start_with("allow_busy_presence = true").await;
+	let alice = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	services
+		.presence
+		.ping_presence(&alice, &PresenceState::Busy)
+		.await
+		.expect("busy is accepted when enabled");
+	let presence = services
+		.presence
+		.get_presence(&alice)
+		.await
+		.expect("presence set");
+	assert_eq!(presence.content.presence, PresenceState::Busy, "busy is set when enabled");
+
+	services.stop().await;
+}
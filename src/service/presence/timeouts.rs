@@ -0,0 +1,77 @@
+use std::collections::{BTreeSet, HashMap};
+
+use ruma::{OwnedUserId, UserId, presence::PresenceState};
+
+/// Users ordered by when their presence next times out, so a sweep only
+/// visits the users which are due.
+#[derive(Debug, Default)]
+pub(super) struct Timeouts {
+	due: BTreeSet<(u64, OwnedUserId)>,
+	users: HashMap<OwnedUserId, u64>,
+}
+
+impl Timeouts {
+	/// Sets when the user's presence next times out, replacing any earlier
+	/// schedule.
+	pub(super) fn schedule(&mut self, user_id: &UserId, at: u64) {
+		if let Some(prev) = self.users.insert(user_id.to_owned(), at) {
+			self.due.remove(&(prev, user_id.to_owned()));
+		}
+
+		self.due.insert((at, user_id.to_owned()));
+	}
+
+	pub(super) fn cancel(&mut self, user_id: &UserId) {
+		if let Some(prev) = self.users.remove(user_id) {
+			self.due.remove(&(prev, user_id.to_owned()));
+		}
+	}
+
+	/// Removes and returns the users whose presence timed out by `now`.
+	pub(super) fn take_due(&mut self, now: u64) -> Vec<OwnedUserId> {
+		let mut taken = Vec::new();
+		while let Some((at, _)) = self.due.first() {
+			if *at > now {
+				break;
+			}
+
+			let (_, user_id) = self.due.pop_first().expect("first entry exists");
+			self.users.remove(&user_id);
+			taken.push(user_id);
+		}
+
+		taken
+	}
+
+	pub(super) fn len(&self) -> usize { self.users.len() }
+}
+
+/// When a user last active at `last_active` next times out in the given
+/// state; `None` when the state does not time out.
+pub(super) fn next_timeout(
+	state: &PresenceState,
+	last_active: u64,
+	idle_timeout: u64,
+	offline_timeout: u64,
+) -> Option<u64> {
+	match state {
+		| PresenceState::Online => Some(last_active.saturating_add(idle_timeout)),
+		| PresenceState::Unavailable => Some(last_active.saturating_add(offline_timeout)),
+		| _ => None,
+	}
+}
+
+/// The state a user idle for `idle_for` milliseconds times out into, if any.
+pub(super) fn timeout_state(
+	state: &PresenceState,
+	idle_for: u64,
+	idle_timeout: u64,
+	offline_timeout: u64,
+) -> Option<PresenceState> {
+	match state {
+		| PresenceState::Online | PresenceState::Unavailable if idle_for >= offline_timeout =>
+			Some(PresenceState::Offline),
+		| PresenceState::Online if idle_for >= idle_timeout => Some(PresenceState::Unavailable),
+		| _ => None,
+	}
+}
@@ -0,0 +1,113 @@
+//! Consecutive failures of the push gateways of pushers. A pusher whose
+//! gateway keeps failing is removed, as its app is most likely gone. The
+//! failures are stored so a restart does not start the window again.
+
+use std::time::Duration;
+
+use ruma::{UserId, api::push_gateway::send_event_notification};
+use serde::{Deserialize, Serialize};
+use tuwunel_core::{Result, implement, utils::time::now_millis, warn};
+use tuwunel_database::{Deserialized, Json};
+
+/// The deliveries to a pusher which failed in a row, and when the first of
+/// them failed, in milliseconds since the epoch.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(super) struct Failing {
+	pub(super) count: u32,
+	pub(super) since: u64,
+}
+
+impl Failing {
+	/// The failures after another delivery failed at `now`.
+	pub(super) fn fail(previous: Option<Self>, now: u64) -> Self {
+		let Self { count, since } = previous.unwrap_or(Self { count: 0, since: now });
+
+		Self { count: count.saturating_add(1), since }
+	}
+
+	/// For how long the pusher has been failing at `now`.
+	pub(super) fn duration(&self, now: u64) -> Duration {
+		Duration::from_millis(now.saturating_sub(self.since))
+	}
+}
+
+/// Sends the notification to the push gateway at the URL, noting the outcome
+/// for the pusher.
+#[implement(super::Service)]
+pub(super) async fn deliver(
+	&self,
+	user_id: &UserId,
+	pushkey: &str,
+	url: &str,
+	notification: send_event_notification::v1::Notification,
+) -> Result {
+	let request = send_event_notification::v1::Request::new(notification);
+	let result = self.send_request(url, request).await.map(|_| ());
+
+	self.note_delivery(user_id, pushkey, &result, now_millis())
+		.await;
+
+	result
+}
+
+/// Notes the outcome of a delivery to the pusher. The pusher is removed once
+/// its gateway has failed for longer than `pusher_max_failure_duration`.
+#[implement(super::Service)]
+pub(super) async fn note_delivery(
+	&self,
+	user_id: &UserId,
+	pushkey: &str,
+	result: &Result,
+	now: u64,
+) {
+	let previous = self.failing(user_id, pushkey).await;
+	let key = (user_id, pushkey);
+	if result.is_ok() {
+		if previous.is_some() {
+			self.db.senderkey_pushfailure.del(key);
+		}
+
+		return;
+	}
+
+	let failing = Failing::fail(previous, now);
+	let duration = failing.duration(now);
+	let max_duration = Duration::from_secs(
+		self.services
+			.server
+			.config
+			.pusher_max_failure_duration,
+	);
+
+	if duration < max_duration {
+		self.db
+			.senderkey_pushfailure
+			.put(key, Json(failing));
+
+		return;
+	}
+
+	let count = failing.count;
+	warn!(%user_id, %pushkey, count, ?duration, "Removing pusher whose gateway keeps failing");
+	self.delete_pusher(user_id, pushkey).await;
+
+	self.services
+		.admin
+		.notice(&format!(
+			"Removed a pusher of {user_id}: its push gateway has failed {count} deliveries in a \
+			 row over {} hours.",
+			duration.as_secs() / 3600
+		))
+		.await;
+}
+
+/// The failures of the deliveries to the pusher, when the last one failed.
+#[implement(super::Service)]
+pub(super) async fn failing(&self, user_id: &UserId, pushkey: &str) -> Option<Failing> {
+	self.db
+		.senderkey_pushfailure
+		.qry(&(user_id, pushkey))
+		.await
+		.deserialized()
+		.ok()
+}
@@ -1,21 +1,35 @@
-use std::{fmt::Debug, mem, sync::Arc};
+mod failures;
+mod related;
+#[cfg(test)]
+mod tests;
+
+use std::{
+	fmt::{Debug, Write},
+	mem,
+	sync::{Arc, Mutex},
+};
 
+use async_trait::async_trait;
 use bytes::BytesMut;
 use futures::{Stream, StreamExt};
 use ipaddress::IPAddress;
+use lru_cache::LruCache;
 use ruma::{
-	DeviceId, OwnedDeviceId, RoomId, UInt, UserId,
+	DeviceId, OwnedDeviceId, OwnedUserId, RoomId, UInt, UserId,
 	api::{
 		IncomingResponse, MatrixVersion, OutgoingRequest, SendAccessToken, SupportedVersions,
 		client::push::{Pusher, PusherKind, set_pusher},
-		push_gateway::send_event_notification::{
-			self,
-			v1::{Device, Notification, NotificationCounts, NotificationPriority},
+		push_gateway::send_event_notification::v1::{
+			Device, Notification, NotificationCounts, NotificationPriority,
 		},
 	},
-	events::{AnySyncTimelineEvent, TimelineEventType, room::power_levels::RoomPowerLevels},
+	events::{
+		AnySyncTimelineEvent, GlobalAccountDataEventType, TimelineEventType,
+		push_rules::PushRulesEvent, room::power_levels::RoomPowerLevels,
+	},
 	push::{
-		Action, PushConditionPowerLevelsCtx, PushConditionRoomCtx, PushFormat, Ruleset, Tweak,
+		Action, AnyPushRuleRef, PushConditionPowerLevelsCtx, PushConditionRoomCtx, PushFormat,
+		Ruleset, Tweak,
 	},
 	serde::Raw,
 	uint,
@@ -35,24 +49,41 @@
 pub struct Service {
 	db: Data,
 	services: Arc<crate::services::OnceServices>,
+	rulesets: Mutex<LruCache<OwnedUserId, Arc<Ruleset>>>,
 }
 
 struct Data {
 	senderkey_pusher: Arc<Map>,
+	senderkey_pushfailure: Arc<Map>,
 	pushkey_deviceid: Arc<Map>,
 }
 
+/// Most users whose push rules are kept in memory.
+const RULESETS_CACHE_CAPACITY: usize = 1024;
+
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			db: Data {
 				senderkey_pusher: args.db["senderkey_pusher"].clone(),
+				senderkey_pushfailure: args.db["senderkey_pushfailure"].clone(),
 				pushkey_deviceid: args.db["pushkey_deviceid"].clone(),
 			},
 			services: args.services.clone(),
+			rulesets: LruCache::new(RULESETS_CACHE_CAPACITY).into(),
 		}))
 	}
 
+	async fn memory_usage(&self, out: &mut (dyn Write + Send)) -> Result {
+		let rulesets = self.rulesets.lock()?.len();
+		writeln!(out, "push_rulesets: {rulesets}")?;
+
+		Ok(())
+	}
+
+	async fn clear_cache(&self) { self.rulesets.lock().expect("locked").clear(); }
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -128,6 +159,7 @@ pub async fn set_pusher(
 	pub async fn delete_pusher(&self, sender: &UserId, pushkey: &str) {
 		let key = (sender, pushkey);
 		self.db.senderkey_pusher.del(key);
+		self.db.senderkey_pushfailure.del(key);
 		self.db.pushkey_deviceid.remove(pushkey);
 
 		self.services
@@ -296,7 +328,7 @@ pub async fn send_push_notice<E>(
 		user: &UserId,
 		unread: UInt,
 		pusher: &Pusher,
-		ruleset: Ruleset,
+		ruleset: &Ruleset,
 		event: &E,
 	) -> Result
 	where
@@ -313,7 +345,7 @@ pub async fn send_push_notice<E>(
 
 		let serialized = event.to_format();
 		for action in self
-			.get_actions(user, &ruleset, &power_levels, &serialized, event.room_id())
+			.get_actions(user, ruleset, &power_levels, &serialized, event.room_id())
 			.await
 		{
 			let n = match action {
@@ -335,7 +367,7 @@ pub async fn send_push_notice<E>(
 		}
 
 		if notify == Some(true) {
-			self.send_notice(unread, pusher, tweaks, event)
+			self.send_notice(user, unread, pusher, tweaks, event)
 				.await?;
 		}
 		// Else the event triggered no actions
@@ -383,12 +415,64 @@ pub async fn get_actions<'a>(
 			power_levels: Some(power_levels),
 		};
 
-		ruleset.get_actions(pdu, &ctx).await
+		let matched = ruleset.get_match(pdu, &ctx).await;
+		if let Some(rule) = self
+			.get_related_match(ruleset, matched, pdu, &ctx)
+			.await
+		{
+			return rule.actions();
+		}
+
+		matched
+			.map(AnyPushRuleRef::actions)
+			.unwrap_or(&[])
 	}
 
-	#[tracing::instrument(skip(self, unread, pusher, tweaks, event))]
+	/// The push rules of the user, completed with the server default rules
+	/// added since the user last changed theirs. They are cached until the
+	/// user changes them.
+	pub async fn get_ruleset(&self, user: &UserId) -> Arc<Ruleset> {
+		if let Some(ruleset) = self
+			.rulesets
+			.lock()
+			.expect("locked")
+			.get_mut(user)
+		{
+			return ruleset.clone();
+		}
+
+		let ruleset = match self
+			.services
+			.account_data
+			.get_global::<PushRulesEvent>(user, GlobalAccountDataEventType::PushRules)
+			.await
+		{
+			| Ok(event) => {
+				let mut ruleset = event.content.global;
+				ruleset.update_with_server_default(Ruleset::server_default(user));
+				ruleset
+			},
+			| Err(_) => Ruleset::server_default(user),
+		};
+
+		let ruleset = Arc::new(ruleset);
+		self.rulesets
+			.lock()
+			.expect("locked")
+			.insert(user.to_owned(), ruleset.clone());
+
+		ruleset
+	}
+
+	/// Drops the cached push rules of the user after they changed.
+	pub fn forget_ruleset(&self, user: &UserId) {
+		self.rulesets.lock().expect("locked").remove(user);
+	}
+
+	#[tracing::instrument(skip(self, user, unread, pusher, tweaks, event))]
 	async fn send_notice<Pdu: Event>(
 		&self,
+		user: &UserId,
 		unread: UInt,
 		pusher: &Pusher,
 		tweaks: Vec<Tweak>,
@@ -494,10 +578,8 @@ async fn send_notice<Pdu: Event>(
 						.ok();
 				}
 
-				self.send_request(&http.url, send_event_notification::v1::Request::new(notifi))
-					.await?;
-
-				Ok(())
+				self.deliver(user, &pusher.ids.pushkey, &http.url, notifi)
+					.await
 			},
 			// TODO: Handle email
 			//PusherKind::Email(_) => Ok(()),
@@ -505,3 +587,21 @@ async fn send_notice<Pdu: Event>(
 		}
 	}
 }
+
+/// Whether an event the push rules gave the actions to counts as a
+/// notification, and as a highlight. Rules muting a room or keeping only
+/// mentions match without notifying, and a highlight tweak only counts on an
+/// event which notifies.
+#[must_use]
+pub fn notification_tweaks(actions: &[Action]) -> (bool, bool) {
+	let notify = actions
+		.iter()
+		.any(|action| matches!(action, Action::Notify));
+
+	let highlight = notify
+		&& actions
+			.iter()
+			.any(|action| matches!(action, Action::SetTweak(Tweak::Highlight(true))));
+
+	(notify, highlight)
+}
@@ -0,0 +1,232 @@
+//! Evaluation of the `related_event_match` condition of MSC3664, which the
+//! push rules of ruma leave to the server; rules with it never match in ruma.
+//! Every other condition is still evaluated by ruma.
+
+use std::mem;
+
+use ruma::{
+	EventId, RoomId,
+	events::AnySyncTimelineEvent,
+	push::{AnyPushRuleRef, FlattenedJson, PushCondition, PushConditionRoomCtx, Ruleset},
+	serde::Raw,
+};
+use serde_json::Value;
+use tuwunel_core::{Event, implement};
+
+/// Kinds of the condition, stable and unstable.
+const RELATED_EVENT_MATCH: [&str; 2] =
+	["related_event_match", "im.nheko.msc3664.related_event_match"];
+
+/// The rule matching the event which precedes the rule ruma matched, if any,
+/// among those with a `related_event_match` condition.
+#[implement(super::Service)]
+pub(super) async fn get_related_match<'a>(
+	&self,
+	ruleset: &'a Ruleset,
+	matched: Option<AnyPushRuleRef<'a>>,
+	pdu: &Raw<AnySyncTimelineEvent>,
+	ctx: &PushConditionRoomCtx,
+) -> Option<AnyPushRuleRef<'a>> {
+	let mut event: Option<(FlattenedJson, Value)> = None;
+	for rule in ruleset.iter() {
+		if matched.is_some_and(|matched| same_rule(matched, rule)) {
+			break;
+		}
+
+		let conditions = match rule {
+			| AnyPushRuleRef::Override(rule) | AnyPushRuleRef::Underride(rule) if rule.enabled =>
+				&rule.conditions,
+			| _ => continue,
+		};
+
+		if !conditions.iter().any(is_related_event_match) {
+			continue;
+		}
+
+		let (flattened, value) = event.get_or_insert_with(|| {
+			let value = serde_json::from_str(pdu.json().get()).unwrap_or_default();
+			(FlattenedJson::from_raw(pdu), value)
+		});
+
+		let mut applies = true;
+		for condition in conditions {
+			applies = if is_related_event_match(condition) {
+				self.related_event_match(condition, value, &ctx.room_id)
+					.await
+			} else {
+				condition.applies(flattened, ctx).await
+			};
+
+			if !applies {
+				break;
+			}
+		}
+
+		if applies {
+			return Some(rule);
+		}
+	}
+
+	None
+}
+
+/// Whether the event relates to an event of the room as the condition
+/// requires.
+#[implement(super::Service)]
+async fn related_event_match(
+	&self,
+	condition: &PushCondition,
+	event: &Value,
+	room_id: &RoomId,
+) -> bool {
+	let Ok(condition) = serde_json::to_value(condition) else {
+		return false;
+	};
+
+	let Some(rel_type) = condition.get("rel_type").and_then(Value::as_str) else {
+		return false;
+	};
+
+	let include_fallbacks = condition
+		.get("include_fallbacks")
+		.and_then(Value::as_bool)
+		.unwrap_or(false);
+
+	let Some(related_id) = related_event_id(event, rel_type, include_fallbacks) else {
+		return false;
+	};
+
+	// A relation without a key to match is enough.
+	let Some(key) = condition.get("key").and_then(Value::as_str) else {
+		return true;
+	};
+
+	let Some(pattern) = condition.get("pattern").and_then(Value::as_str) else {
+		return false;
+	};
+
+	let Ok(related_id) = <&EventId>::try_from(related_id) else {
+		return false;
+	};
+
+	let Ok(related) = self.services.timeline.get_pdu(related_id).await else {
+		return false;
+	};
+
+	if related.room_id() != room_id {
+		return false;
+	}
+
+	let related: Raw<AnySyncTimelineEvent> = related.to_format();
+	serde_json::from_str(related.json().get())
+		.is_ok_and(|related: Value| event_match(&related, key, pattern))
+}
+
+fn is_related_event_match(condition: &PushCondition) -> bool {
+	serde_json::to_value(condition)
+		.ok()
+		.as_ref()
+		.and_then(|condition| condition.get("kind"))
+		.and_then(Value::as_str)
+		.is_some_and(|kind| RELATED_EVENT_MATCH.contains(&kind))
+}
+
+fn same_rule(a: AnyPushRuleRef<'_>, b: AnyPushRuleRef<'_>) -> bool {
+	mem::discriminant(&a) == mem::discriminant(&b) && a.rule_id() == b.rule_id()
+}
+
+/// The ID of the event the event relates to with the relation type. Replies
+/// falling back from a thread only count when fallbacks are included.
+pub(super) fn related_event_id<'a>(
+	event: &'a Value,
+	rel_type: &str,
+	include_fallbacks: bool,
+) -> Option<&'a str> {
+	let relates_to = event.get("content")?.get("m.relates_to")?;
+	if rel_type == "m.in_reply_to" {
+		let falling_back = relates_to
+			.get("is_falling_back")
+			.and_then(Value::as_bool)
+			.unwrap_or(false);
+
+		if falling_back && !include_fallbacks {
+			return None;
+		}
+
+		return relates_to
+			.get("m.in_reply_to")?
+			.get("event_id")?
+			.as_str();
+	}
+
+	if relates_to.get("rel_type")?.as_str()? != rel_type {
+		return None;
+	}
+
+	relates_to.get("event_id")?.as_str()
+}
+
+/// Whether the string at the dotted path of the event matches the glob
+/// pattern, case-insensitively and in whole.
+pub(super) fn event_match(event: &Value, key: &str, pattern: &str) -> bool {
+	property(event, key)
+		.and_then(Value::as_str)
+		.is_some_and(|value| glob_match(pattern, value))
+}
+
+/// The value at the dotted path; dots and backslashes within a key are
+/// escaped by a backslash.
+pub(super) fn property<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+	let mut value = value;
+	let mut key = String::new();
+	let mut chars = path.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			| '\\' => match chars.next() {
+				| Some(escaped @ ('.' | '\\')) => key.push(escaped),
+				| Some(other) => {
+					key.push('\\');
+					key.push(other);
+				},
+				| None => key.push('\\'),
+			},
+			| '.' => value = value.get(mem::take(&mut key))?,
+			| c => key.push(c),
+		}
+	}
+
+	value.get(key)
+}
+
+/// Matches the whole value against the pattern, where `*` stands for any
+/// characters and `?` for exactly one.
+pub(super) fn glob_match(pattern: &str, value: &str) -> bool {
+	let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+	let value: Vec<char> = value.to_lowercase().chars().collect();
+
+	let (mut p, mut v) = (0, 0);
+	let mut backtrack: Option<(usize, usize)> = None;
+	while v < value.len() {
+		match pattern.get(p) {
+			| Some('*') => {
+				backtrack = Some((p, v));
+				p = p.saturating_add(1);
+			},
+			| Some(&c) if c == '?' || c == value[v] => {
+				p = p.saturating_add(1);
+				v = v.saturating_add(1);
+			},
+			| _ => {
+				let Some((star, from)) = backtrack else {
+					return false;
+				};
+
+				p = star.saturating_add(1);
+				v = from.saturating_add(1);
+				backtrack = Some((star, v));
+			},
+		}
+	}
+
+	pattern[p..].iter().all(|&c| c == '*')
+}
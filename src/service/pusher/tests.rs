@@ -0,0 +1,612 @@
+use std::time::Duration;
+
+use ruma::{
+	EventId,
+	api::{client::push::Pusher, push_gateway::send_event_notification::v1::Notification},
+	event_id,
+	events::AnySyncTimelineEvent,
+	owned_room_id, owned_user_id,
+	push::{Action, PushConditionRoomCtx, Ruleset, Tweak},
+	serde::Raw,
+	uint, user_id,
+};
+use serde_json::{json, value::to_raw_value};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::TcpListener,
+	sync::mpsc::{self, UnboundedReceiver},
+	time::timeout,
+};
+use tuwunel_core::{Err, Result};
+use tuwunel_database::Json;
+
+use super::{
+	notification_tweaks,
+	related::{event_match, glob_match, property, related_event_id},
+};
+use crate::fixture::Fixture;
+
+fn ctx() -> PushConditionRoomCtx {
+	PushConditionRoomCtx {
+		room_id: owned_room_id!("!room:example.com"),
+		member_count: uint!(3),
+		user_id: owned_user_id!("@alice:example.com"),
+		user_display_name: "Alice".to_owned(),
+		power_levels: None,
+	}
+}
+
+fn mention() -> Raw<AnySyncTimelineEvent> {
+	Raw::from_json(
+		to_raw_value(&json!({
+			"event_id": "$event:example.com",
+			"sender": "@bob:example.com",
+			"origin_server_ts": 0,
+			"type": "m.room.message",
+			"content": {
+				"msgtype": "m.text",
+				"body": "hello there",
+				"m.mentions": { "user_ids": ["@alice:example.com"] },
+			},
+		}))
+		.expect("valid event"),
+	)
+}
+
+fn message() -> Raw<AnySyncTimelineEvent> {
+	Raw::from_json(
+		to_raw_value(&json!({
+			"event_id": "$message:example.com",
+			"sender": "@bob:example.com",
+			"origin_server_ts": 0,
+			"type": "m.room.message",
+			"content": {
+				"msgtype": "m.text",
+				"body": "hello everyone",
+			},
+		}))
+		.expect("valid event"),
+	)
+}
+
+/// The push rules of alice with her own rules added to the server defaults.
+fn user_ruleset(rules: serde_json::Value) -> Ruleset {
+	let mut ruleset: Ruleset = serde_json::from_value(rules).expect("valid ruleset");
+	ruleset.update_with_server_default(Ruleset::server_default(user_id!("@alice:example.com")));
+	ruleset
+}
+
+fn highlights(actions: &[Action]) -> bool {
+	actions
+		.iter()
+		.any(|action| matches!(action, Action::SetTweak(Tweak::Highlight(true))))
+}
+
+#[tokio::test]
+async fn user_mention_highlights() {
+	let ruleset = Ruleset::server_default(user_id!("@alice:example.com"));
+	let actions = ruleset.get_actions(&mention(), &ctx()).await;
+
+	assert!(highlights(actions), "a mention in m.mentions highlights: {actions:?}");
+}
+
+#[tokio::test]
+async fn user_mention_after_default_update() {
+	let mut ruleset = Ruleset::new();
+	let actions = ruleset.get_actions(&mention(), &ctx()).await;
+	assert!(actions.is_empty(), "rules stored without the mention rule miss it");
+
+	ruleset.update_with_server_default(Ruleset::server_default(user_id!("@alice:example.com")));
+	let actions = ruleset.get_actions(&mention(), &ctx()).await;
+	assert!(highlights(actions), "the server default rules are added: {actions:?}");
+}
+
+#[tokio::test]
+async fn muted_room_counts_nothing() {
+	let ruleset = user_ruleset(json!({
+		"override": [{
+			"rule_id": "!room:example.com",
+			"default": false,
+			"enabled": true,
+			"conditions": [{
+				"kind": "event_match",
+				"key": "room_id",
+				"pattern": "!room:example.com",
+			}],
+			"actions": [],
+		}],
+	}));
+
+	let actions = ruleset.get_actions(&message(), &ctx()).await;
+	assert_eq!(
+		notification_tweaks(actions),
+		(false, false),
+		"messages in a muted room are not counted: {actions:?}"
+	);
+
+	let actions = Ruleset::server_default(user_id!("@alice:example.com"))
+		.get_actions(&message(), &ctx())
+		.await
+		.to_vec();
+	assert_eq!(
+		notification_tweaks(&actions),
+		(true, false),
+		"messages in other rooms notify without highlighting: {actions:?}"
+	);
+}
+
+#[tokio::test]
+async fn mentions_only_room() {
+	let ruleset = user_ruleset(json!({
+		"room": [{
+			"rule_id": "!room:example.com",
+			"default": false,
+			"enabled": true,
+			"actions": [],
+		}],
+	}));
+
+	let actions = ruleset.get_actions(&message(), &ctx()).await;
+	assert_eq!(
+		notification_tweaks(actions),
+		(false, false),
+		"messages without a mention are not counted: {actions:?}"
+	);
+
+	let actions = ruleset.get_actions(&mention(), &ctx()).await;
+	assert_eq!(
+		notification_tweaks(actions),
+		(true, true),
+		"mentions notify and highlight: {actions:?}"
+	);
+}
+
+#[test]
+fn highlight_needs_notify() {
+	let sound = Action::SetTweak(Tweak::Sound("default".into()));
+	let highlight = Action::SetTweak(Tweak::Highlight(true));
+
+	assert_eq!(
+		notification_tweaks(&[Action::Notify, sound.clone()]),
+		(true, false),
+		"a sound alone does not highlight"
+	);
+	assert_eq!(
+		notification_tweaks(&[Action::Notify, sound, highlight.clone()]),
+		(true, true),
+		"the highlight tweak highlights"
+	);
+	assert_eq!(
+		notification_tweaks(&[Action::Notify, Action::SetTweak(Tweak::Highlight(false))]),
+		(true, false),
+		"a false highlight tweak does not highlight"
+	);
+	assert_eq!(
+		notification_tweaks(&[highlight]),
+		(false, false),
+		"a highlight without notify is not counted"
+	);
+}
+
+#[test]
+fn property_path() {
+	let event = json!({
+		"content": {
+			"m.relates_to": { "rel_type": "m.thread" },
+			"a\\b": { "c": "escaped" },
+		},
+	});
+
+	assert_eq!(
+		property(&event, "content.m\\.relates_to.rel_type"),
+		Some(&json!("m.thread")),
+		"escaped dots stay within the key"
+	);
+	assert_eq!(
+		property(&event, "content.a\\\\b.c"),
+		Some(&json!("escaped")),
+		"escaped backslashes stay within the key"
+	);
+	assert_eq!(property(&event, "content.m.relates_to"), None, "dots separate keys");
+	assert!(
+		event_match(&event, "content.m\\.relates_to.rel_type", "M.THR*"),
+		"patterns match nested values"
+	);
+}
+
+#[test]
+fn glob_patterns() {
+	assert!(glob_match("*", ""), "a star matches nothing");
+	assert!(glob_match("m.*", "m.room.message"), "a star matches the rest");
+	assert!(glob_match("*ll*", "hello"), "stars match either side");
+	assert!(glob_match("h?llo", "HELLO"), "matching ignores case");
+	assert!(!glob_match("hell", "hello"), "the whole value must match");
+	assert!(!glob_match("h?", "h"), "a question mark needs a character");
+	assert!(glob_match("a*b*c", "aXbYbZc"), "stars backtrack");
+}
+
+#[test]
+fn related_reply_fallback() {
+	let reply = json!({
+		"content": {
+			"m.relates_to": {
+				"rel_type": "m.thread",
+				"event_id": "$root:example.com",
+				"is_falling_back": true,
+				"m.in_reply_to": { "event_id": "$latest:example.com" },
+			},
+		},
+	});
+
+	assert_eq!(
+		related_event_id(&reply, "m.thread", false),
+		Some("$root:example.com"),
+		"the relation type selects the event"
+	);
+	assert_eq!(
+		related_event_id(&reply, "m.in_reply_to", false),
+		None,
+		"fallback replies are skipped"
+	);
+	assert_eq!(
+		related_event_id(&reply, "m.in_reply_to", true),
+		Some("$latest:example.com"),
+		"fallback replies are included when asked"
+	);
+	assert_eq!(related_event_id(&reply, "m.annotation", true), None, "other types do not match");
+}
+
+#[tokio::test]
+async fn related_event_match_rule() {
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let bot = embedded
+		.create_user("bot", None)
+		.await
+		.expect("user created");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+	embedded
+		.join_room(&bot, &room_id)
+		.await
+		.expect("bot joined");
+
+	let from_bot = embedded
+		.send_message(&bot, &room_id, "beep")
+		.await
+		.expect("message sent");
+	let from_alice = embedded
+		.send_message(&alice, &room_id, "hello")
+		.await
+		.expect("message sent");
+
+	let reply_to = |event_id: &EventId| -> Raw<AnySyncTimelineEvent> {
+		Raw::from_json(
+			to_raw_value(&json!({
+				"event_id": "$reply:fixture.localhost",
+				"sender": alice,
+				"origin_server_ts": 0,
+				"type": "m.room.message",
+				"content": {
+					"msgtype": "m.text",
+					"body": "a reply",
+					"m.relates_to": { "m.in_reply_to": { "event_id": event_id } },
+				},
+			}))
+			.expect("valid event"),
+		)
+	};
+
+	let ruleset = user_ruleset(json!({
+		"override": [{
+			"rule_id": "reply_to_bot",
+			"default": false,
+			"enabled": true,
+			"conditions": [{
+				"kind": "related_event_match",
+				"rel_type": "m.in_reply_to",
+				"key": "sender",
+				"pattern": bot,
+			}],
+			"actions": ["notify", { "set_tweak": "highlight" }],
+		}],
+	}));
+
+	let ctx = PushConditionRoomCtx { room_id: room_id.clone(), ..ctx() };
+
+	let reply = reply_to(&from_bot);
+	let matched = ruleset.get_match(&reply, &ctx).await;
+	let related = services
+		.pusher
+		.get_related_match(&ruleset, matched, &reply, &ctx)
+		.await;
+	assert_eq!(
+		related.map(|rule| rule.rule_id()),
+		Some("reply_to_bot"),
+		"a reply to the bot matches the rule preceding the one ruma matched"
+	);
+
+	let reply = reply_to(&from_alice);
+	let matched = ruleset.get_match(&reply, &ctx).await;
+	let related = services
+		.pusher
+		.get_related_match(&ruleset, matched, &reply, &ctx)
+		.await;
+	assert!(related.is_none(), "a reply to another sender does not match the rule");
+
+	let reply = reply_to(event_id!("$missing:fixture.localhost"));
+	let related = services
+		.pusher
+		.get_related_match(&ruleset, None, &reply, &ctx)
+		.await;
+	assert!(related.is_none(), "a reply to an unknown event does not match the rule");
+
+	services.stop().await;
+}
+
+/// A push gateway on a local port answering the notifications with the
+/// statuses in turn; returns its URL and the bodies of the notifications.
+async fn gateway(statuses: Vec<u16>) -> (String, UnboundedReceiver<serde_json::Value>) {
+	let listener = TcpListener::bind("127.0.0.1:0")
+		.await
+		.expect("gateway bound");
+
+	let address = listener.local_addr().expect("gateway address");
+	let (sender, receiver) = mpsc::unbounded_channel();
+	tokio::spawn(async move {
+		for status in statuses {
+			let (mut stream, _) = listener.accept().await.expect("request accepted");
+			let mut request = Vec::new();
+			while request_body(&request).is_none() {
+				let mut buf = [0_u8; 4096];
+				let read = stream.read(&mut buf).await.expect("request read");
+				assert_ne!(read, 0, "the request ended early");
+				request.extend_from_slice(&buf[..read]);
+			}
+
+			let start = request_body(&request).expect("request complete");
+			let body = serde_json::from_slice(&request[start..]).expect("notification body");
+			sender.send(body).ok();
+
+			let body = r#"{"rejected":[]}"#;
+			let response = format!(
+				"HTTP/1.1 {status} Gateway\r\ncontent-type: application/json\r\ncontent-length: \
+				 {}\r\nconnection: close\r\n\r\n{body}",
+				body.len()
+			);
+
+			stream
+				.write_all(response.as_bytes())
+				.await
+				.expect("response written");
+		}
+	});
+
+	(format!("http://{address}/_matrix/push/v1/notify"), receiver)
+}
+
+/// Where the body of the request starts, once the bytes hold its headers and
+/// the whole body.
+fn request_body(request: &[u8]) -> Option<usize> {
+	let end = request
+		.windows(4)
+		.position(|window| window == b"\r\n\r\n")?;
+
+	let length = String::from_utf8_lossy(&request[..end])
+		.lines()
+		.find_map(|line| {
+			let (name, value) = line.split_once(':')?;
+			name.eq_ignore_ascii_case("content-length")
+				.then(|| value.trim().parse::<usize>().ok())?
+		})
+		.unwrap_or(0);
+
+	let start = end.saturating_add(4);
+	(request.len() >= start.saturating_add(length)).then_some(start)
+}
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+#[tokio::test]
+async fn gateway_failures_kept_until_removal() {
+	let services = Fixture::start_with("ip_range_denylist = []").await;
+	let user_id = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let (url, _) = gateway(vec![502, 502, 200, 502]).await;
+	let pusher: Pusher = serde_json::from_value(json!({
+		"pushkey": "pushkey",
+		"app_id": "org.example.app",
+		"app_display_name": "App",
+		"device_display_name": "Phone",
+		"lang": "en",
+		"kind": "http",
+		"data": { "url": url },
+	}))
+	.expect("valid pusher");
+
+	// Stored directly, as setting a pusher through the service takes a request
+	services
+		.pusher
+		.db
+		.senderkey_pusher
+		.put((&*user_id, "pushkey"), Json(&pusher));
+
+	let mut failing = Vec::new();
+	for _ in 0..4 {
+		let notification = Notification::new(Vec::new());
+		let result = services
+			.pusher
+			.deliver(&user_id, "pushkey", &url, notification)
+			.await;
+
+		let count = services
+			.pusher
+			.failing(&user_id, "pushkey")
+			.await
+			.map(|failing| failing.count);
+
+		failing.push((result.is_ok(), count));
+	}
+
+	assert_eq!(
+		failing,
+		[(false, Some(1)), (false, Some(2)), (true, None), (false, Some(1))],
+		"a successful delivery ends the failures of the pusher"
+	);
+
+	let services = services.restart().await;
+	let since = services
+		.pusher
+		.failing(&user_id, "pushkey")
+		.await
+		.expect("the failures are kept across a restart")
+		.since;
+
+	let failed: Result = Err!("Gateway down");
+	services
+		.pusher
+		.note_delivery(&user_id, "pushkey", &failed, since.saturating_add(6 * DAY_MILLIS))
+		.await;
+
+	assert!(
+		services
+			.pusher
+			.get_pusher(&user_id, "pushkey")
+			.await
+			.is_ok(),
+		"the pusher is kept while failing for less than a week"
+	);
+
+	services
+		.pusher
+		.note_delivery(&user_id, "pushkey", &failed, since.saturating_add(8 * DAY_MILLIS))
+		.await;
+
+	assert!(
+		services
+			.pusher
+			.get_pusher(&user_id, "pushkey")
+			.await
+			.is_err(),
+		"the pusher failing for over a week is removed"
+	);
+	assert_eq!(
+		services.pusher.failing(&user_id, "pushkey").await,
+		None,
+		"the failures of the removed pusher are removed"
+	);
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn ignored_and_own_events_not_pushed() {
+	let services = Fixture::start_with("ip_range_denylist = []").await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let bob = embedded
+		.create_user("bob", None)
+		.await
+		.expect("user created");
+	let carol = embedded
+		.create_user("carol", None)
+		.await
+		.expect("user created");
+
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+	for user_id in [&bob, &carol] {
+		embedded
+			.join_room(user_id, &room_id)
+			.await
+			.expect("user joined");
+	}
+
+	let (url, mut notifications) = gateway(vec![200]).await;
+	let pusher: Pusher = serde_json::from_value(json!({
+		"pushkey": "pushkey",
+		"app_id": "org.example.app",
+		"app_display_name": "App",
+		"device_display_name": "Phone",
+		"lang": "en",
+		"kind": "http",
+		"data": { "url": url },
+	}))
+	.expect("valid pusher");
+
+	services
+		.pusher
+		.db
+		.senderkey_pusher
+		.put((&*bob, "pushkey"), Json(&pusher));
+
+	services
+		.account_data
+		.update(
+			None,
+			&bob,
+			"m.ignored_user_list".into(),
+			&json!({
+				"type": "m.ignored_user_list",
+				"content": { "ignored_users": { carol.as_str(): {} } },
+			}),
+		)
+		.await
+		.expect("carol ignored");
+
+	for sender in [&carol, &bob] {
+		embedded
+			.send_message(sender, &room_id, "not for bob")
+			.await
+			.expect("message sent");
+	}
+
+	let from_alice = embedded
+		.send_message(&alice, &room_id, "for bob")
+		.await
+		.expect("message sent");
+
+	let notification = timeout(Duration::from_secs(10), notifications.recv())
+		.await
+		.expect("notification pushed in time")
+		.expect("notification pushed");
+
+	assert_eq!(
+		notification["notification"]["event_id"],
+		json!(from_alice),
+		"the events of ignored users and their own are not pushed"
+	);
+	assert_eq!(
+		services
+			.user
+			.notification_count(&bob, &room_id)
+			.await,
+		1,
+		"the events of ignored users and their own are not counted"
+	);
+	assert_eq!(
+		services
+			.user
+			.notification_count(&alice, &room_id)
+			.await,
+		2,
+		"the events of other users are counted"
+	);
+
+	services.stop().await;
+}
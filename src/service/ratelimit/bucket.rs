@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use tuwunel_core::config::RateLimitConfig;
+
+use super::Action;
+
+/// Capacity and refill rate of the buckets of an action.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Limit {
+	pub(super) per_second: f64,
+	pub(super) burst: u32,
+}
+
+/// Requests left to a requester for an action, as of the last request.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+impl Limit {
+	pub(super) fn new(config: &RateLimitConfig, action: Action) -> Self {
+		let (per_second, burst) = match action {
+			| Action::Login => (config.login_per_second, config.login_burst),
+			| Action::Registration => (config.registration_per_second, config.registration_burst),
+			| Action::Joins => (config.joins_per_second, config.joins_burst),
+			| Action::Messages => (config.messages_per_second, config.messages_burst),
+			| Action::Media => (config.media_per_second, config.media_burst),
+			| Action::Email => (config.email_per_second, config.email_burst),
+		};
+
+		Self { per_second, burst }
+	}
+
+	pub(super) fn is_unlimited(self) -> bool { self.burst == 0 }
+}
+
+impl Bucket {
+	/// A full bucket.
+	pub(super) fn new(limit: Limit, now: Instant) -> Self {
+		Self {
+			tokens: f64::from(limit.burst),
+			updated: now,
+		}
+	}
+
+	/// Refills the bucket for the time since the last request and takes a
+	/// token from it. When it holds less than one, the time until it does is
+	/// returned instead; `None` when it never refills.
+	pub(super) fn take(&mut self, limit: Limit, now: Instant) -> Result<(), Option<Duration>> {
+		let elapsed = now.saturating_duration_since(self.updated);
+		self.tokens = elapsed
+			.as_secs_f64()
+			.mul_add(limit.per_second, self.tokens)
+			.min(f64::from(limit.burst));
+
+		self.updated = now;
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			return Ok(());
+		}
+
+		Err(Duration::try_from_secs_f64((1.0 - self.tokens) / limit.per_second).ok())
+	}
+}
@@ -0,0 +1,154 @@
+mod bucket;
+#[cfg(test)]
+mod tests;
+
+use std::{
+	fmt::Write,
+	mem::size_of,
+	net::{IpAddr, Ipv6Addr},
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lru_cache::LruCache;
+use ruma::{
+	OwnedUserId, UserId,
+	api::client::error::{ErrorKind, RetryAfter},
+};
+use tuwunel_core::{Error, Result, debug_info, http::StatusCode, utils::bytes::pretty};
+
+use self::bucket::{Bucket, Limit};
+
+/// Class of requests limited by a bucket of its own.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+	Login,
+	Registration,
+	Joins,
+	Messages,
+	Media,
+	Email,
+}
+
+/// Who a bucket is kept for: the sender of the request, or its client address
+/// when made before logging in.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Requester {
+	User(OwnedUserId),
+	Client(IpAddr),
+}
+
+type Buckets = LruCache<(Requester, Action), Bucket>;
+
+pub struct Service {
+	services: Arc<crate::services::OnceServices>,
+	buckets: Mutex<Buckets>,
+}
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let capacity = args.server.config.rate_limit.capacity;
+
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			buckets: LruCache::new(capacity).into(),
+		}))
+	}
+
+	async fn memory_usage(&self, out: &mut (dyn Write + Send)) -> Result {
+		let len = self.buckets.lock().expect("locked").len();
+		let bytes = len.saturating_mul(size_of::<((Requester, Action), Bucket)>());
+		writeln!(out, "rate_limit_buckets: {len} ({})", pretty(bytes))?;
+
+		Ok(())
+	}
+
+	async fn clear_cache(&self) { self.buckets.lock().expect("locked").clear(); }
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+impl Service {
+	/// Takes a request for the action from the bucket of the user, or of the
+	/// client address when no user is given. Fails with M_LIMIT_EXCEEDED when
+	/// the bucket is empty.
+	pub async fn check(
+		&self,
+		action: Action,
+		user_id: Option<&UserId>,
+		client: IpAddr,
+		is_appservice: bool,
+	) -> Result {
+		let config = &self.services.config.rate_limit;
+		let limit = Limit::new(config, action);
+		if !config.enabled || limit.is_unlimited() {
+			return Ok(());
+		}
+
+		if is_appservice && config.exempt_appservices {
+			return Ok(());
+		}
+
+		if let Some(user_id) = user_id
+			&& config.exempt_admins
+			&& self.services.users.is_admin(user_id).await
+		{
+			return Ok(());
+		}
+
+		let requester = user_id.map_or_else(
+			|| Requester::Client(client_network(client)),
+			|user_id| Requester::User(user_id.to_owned()),
+		);
+
+		self.take(requester, action, limit, Instant::now())
+			.map_err(|retry_after| {
+				debug_info!(?action, %client, ?user_id, ?retry_after, "Rate limit exceeded");
+				Error::Request(
+					ErrorKind::LimitExceeded {
+						retry_after: retry_after.map(RetryAfter::Delay),
+					},
+					"Too many requests; try again later.".into(),
+					StatusCode::TOO_MANY_REQUESTS,
+				)
+			})
+	}
+
+	fn take(
+		&self,
+		requester: Requester,
+		action: Action,
+		limit: Limit,
+		now: Instant,
+	) -> Result<(), Option<Duration>> {
+		let mut buckets = self.buckets.lock().expect("locked");
+		let key = (requester, action);
+		if let Some(bucket) = buckets.get_mut(&key) {
+			return bucket.take(limit, now);
+		}
+
+		let mut bucket = Bucket::new(limit, now);
+		let taken = bucket.take(limit, now);
+		buckets.insert(key, bucket);
+
+		taken
+	}
+}
+
+/// The network whose clients share a bucket: the address of an IPv4 client,
+/// or the /64 of an IPv6 client, as those are usually given a whole /64 and
+/// could otherwise take a bucket for each address.
+fn client_network(client: IpAddr) -> IpAddr {
+	match client {
+		| IpAddr::V4(_) => client,
+		| IpAddr::V6(addr) => match addr.to_ipv4_mapped() {
+			| Some(addr) => addr.into(),
+			| None => {
+				let [a, b, c, d, ..] = addr.segments();
+				Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0).into()
+			},
+		},
+	}
+}
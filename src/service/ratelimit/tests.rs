@@ -0,0 +1,146 @@
+use std::{
+	net::IpAddr,
+	time::{Duration, Instant},
+};
+
+use tuwunel_core::http::StatusCode;
+
+use super::{
+	Action,
+	bucket::{Bucket, Limit},
+};
+use crate::fixture::Fixture;
+
+const LIMIT: Limit = Limit { per_second: 0.5, burst: 3 };
+
+fn at(start: Instant, millis: u64) -> Instant {
+	start
+		.checked_add(Duration::from_millis(millis))
+		.expect("instant in range")
+}
+
+fn millis(retry_after: Result<(), Option<Duration>>) -> u128 {
+	retry_after
+		.expect_err("bucket should be empty")
+		.expect("bucket should refill")
+		.as_millis()
+}
+
+#[test]
+fn burst_then_limited() {
+	let start = Instant::now();
+	let mut bucket = Bucket::new(LIMIT, start);
+
+	for _ in 0..3 {
+		assert_eq!(bucket.take(LIMIT, start), Ok(()), "burst should be allowed");
+	}
+
+	assert_eq!(millis(bucket.take(LIMIT, start)), 2000, "one token refills in two seconds");
+}
+
+#[test]
+fn refill_across_boundary() {
+	let start = Instant::now();
+	let mut bucket = Bucket::new(LIMIT, start);
+	for _ in 0..3 {
+		bucket.take(LIMIT, start).expect("burst");
+	}
+
+	let before = at(start, 1500);
+	assert_eq!(millis(bucket.take(LIMIT, before)), 500, "less than one token refilled");
+
+	let after = at(start, 2000);
+	assert_eq!(bucket.take(LIMIT, after), Ok(()), "one token refilled");
+	assert_eq!(millis(bucket.take(LIMIT, after)), 2000, "refilled token was taken");
+}
+
+#[test]
+fn refill_capped_at_burst() {
+	let start = Instant::now();
+	let mut bucket = Bucket::new(LIMIT, start);
+	bucket.take(LIMIT, start).expect("burst");
+
+	let later = at(start, 3_600_000);
+	for _ in 0..3 {
+		assert_eq!(bucket.take(LIMIT, later), Ok(()), "bucket should be full");
+	}
+
+	assert!(bucket.take(LIMIT, later).is_err(), "bucket holds no more than the burst");
+}
+
+#[test]
+fn never_refills() {
+	let limit = Limit { per_second: 0.0, burst: 1 };
+	let start = Instant::now();
+	let mut bucket = Bucket::new(limit, start);
+
+	assert_eq!(bucket.take(limit, start), Ok(()), "burst should be allowed");
+	assert_eq!(
+		bucket.take(limit, at(start, 3_600_000)),
+		Err(None),
+		"no time until a bucket without refill has a token"
+	);
+}
+
+#[tokio::test]
+async fn email_tokens_limited_per_client() {
+	let fixture =
+		Fixture::start_with("[global.rate_limit]\nenabled = true\nemail_burst = 2").await;
+	let client = IpAddr::from([192, 0, 2, 1]);
+	let other = IpAddr::from([192, 0, 2, 2]);
+
+	for _ in 0..2 {
+		fixture
+			.ratelimit
+			.check(Action::Email, None, client, false)
+			.await
+			.expect("burst should be allowed");
+	}
+
+	let error = fixture
+		.ratelimit
+		.check(Action::Email, None, client, false)
+		.await
+		.expect_err("bucket of the client should be empty");
+	assert_eq!(error.status_code(), StatusCode::TOO_MANY_REQUESTS, "limit exceeded");
+
+	fixture
+		.ratelimit
+		.check(Action::Email, None, other, false)
+		.await
+		.expect("other client has a bucket of its own");
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn ipv6_clients_limited_per_network() {
+	let fixture =
+		Fixture::start_with("[global.rate_limit]\nenabled = true\nlogin_burst = 1").await;
+	let check = async |client: &str| {
+		let client: IpAddr = client.parse().expect("valid address");
+		fixture
+			.ratelimit
+			.check(Action::Login, None, client, false)
+			.await
+	};
+
+	check("2001:db8:1:2::1")
+		.await
+		.expect("burst should be allowed");
+	check("2001:db8:1:2::ffff")
+		.await
+		.expect_err("an address of the same /64 shares the bucket");
+	check("2001:db8:1:3::1")
+		.await
+		.expect("another /64 has a bucket of its own");
+
+	check("::ffff:192.0.2.1")
+		.await
+		.expect("burst should be allowed");
+	check("::ffff:192.0.2.2")
+		.await
+		.expect("mapped IPv4 clients are limited per address");
+
+	fixture.stop().await;
+}
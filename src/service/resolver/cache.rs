@@ -7,7 +7,7 @@
 	Result,
 	arrayvec::ArrayVec,
 	at, err, implement,
-	utils::{math::Expected, rand, stream::TryIgnore},
+	utils::{ReadyExt, math::Expected, rand, stream::TryIgnore},
 };
 use tuwunel_database::{Cbor, Deserialized, Map};
 
@@ -54,6 +54,24 @@ pub(super) fn new(args: &crate::Args<'_>) -> Arc<Self> {
 #[implement(Cache)]
 pub async fn clear_overrides(&self) { self.overrides.clear().await; }
 
+/// Removes the destinations and overrides which expired. They would be
+/// resolved again on their next use anyway, but otherwise stay stored until
+/// then, or forever for servers never contacted again.
+#[implement(Cache)]
+pub async fn remove_expired(&self) {
+	let destinations = self
+		.destinations()
+		.ready_filter(|(_, dest)| !dest.valid())
+		.ready_for_each(|(name, _)| self.del_destination(name));
+
+	let overrides = self
+		.overrides()
+		.ready_filter(|(_, over)| !over.valid())
+		.ready_for_each(|(name, _)| self.del_override(name));
+
+	join(destinations, overrides).await;
+}
+
 #[implement(Cache)]
 pub fn del_destination(&self, name: &ServerName) { self.destinations.remove(name); }
 
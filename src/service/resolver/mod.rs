@@ -6,7 +6,7 @@
 mod tests;
 mod well_known;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use tuwunel_core::{Result, arrayvec::ArrayString, utils::MutexMap};
@@ -45,5 +45,9 @@ async fn clear_cache(&self) {
 		self.cache.clear().await;
 	}
 
+	/// The destinations carry their own expiry; those expired are evicted
+	/// whatever their age.
+	async fn trim_cache(&self, _max_age: Duration) { self.cache.remove_expired().await; }
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
@@ -0,0 +1,250 @@
+//! Validation of `m.room.canonical_alias` events sent by clients, whose aliases
+//! must resolve to the room, and detection and repair of events naming local
+//! aliases which no longer do, e.g. after the alias was deleted or the server
+//! renamed. Aliases of other servers are not checked for repair.
+
+use std::time::Duration;
+
+use futures::{FutureExt, StreamExt};
+use ruma::{
+	OwnedRoomAliasId, OwnedRoomId, RoomAliasId, RoomId,
+	events::{StateEventType, room::canonical_alias::RoomCanonicalAliasEventContent},
+};
+use tokio::time::timeout;
+use tuwunel_core::{Err, Result, debug_warn, implement, matrix::pdu::PduBuilder, warn};
+
+/// How long an alias of another server is given to resolve.
+const REMOTE_RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks every alias of the content resolves to the room before a client
+/// sends it. Aliases of other servers are resolved best effort; unless
+/// `canonical_alias_remote_strict` is set, those which cannot be resolved in
+/// time are accepted.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self, content), level = "debug")]
+pub async fn validate_canonical_alias(
+	&self,
+	room_id: &RoomId,
+	content: &RoomCanonicalAliasEventContent,
+) -> Result {
+	let strict_remote = self
+		.services
+		.server
+		.config
+		.canonical_alias_remote_strict;
+
+	for alias in content.alias.iter().chain(&content.alt_aliases) {
+		let local = self
+			.services
+			.globals
+			.server_is_ours(alias.server_name());
+
+		let resolved = if local {
+			self.resolve_alias(alias, None)
+				.await
+				.map(|(room_id, _)| room_id)
+		} else {
+			match timeout(REMOTE_RESOLVE_TIMEOUT, self.remote_resolve(alias, Vec::new())).await {
+				| Ok(resolved) => resolved.map(|(room_id, _)| room_id),
+				| Err(_) => Err!(Request(NotFound("Timed out resolving the alias."))),
+			}
+		};
+
+		check_resolved(alias, room_id, resolved, local, strict_remote)?;
+	}
+
+	Ok(())
+}
+
+/// Whether an alias resolved to the room. An alias of another server which
+/// could not be resolved is accepted unless strict.
+pub(super) fn check_resolved(
+	alias: &RoomAliasId,
+	room_id: &RoomId,
+	resolved: Result<OwnedRoomId>,
+	local: bool,
+	strict_remote: bool,
+) -> Result {
+	match resolved {
+		| Ok(resolved) if resolved == room_id => Ok(()),
+		| Ok(resolved) => Err!(Request(BadAlias(
+			"Room alias {alias} points to {resolved}, not to room {room_id}."
+		))),
+		| Err(e) if local || strict_remote => {
+			Err!(Request(BadAlias("Failed resolving alias \"{alias}\": {e}")))
+		},
+		| Err(e) => {
+			debug_warn!(%alias, %room_id, "Accepting alias which failed to resolve: {e}");
+			Ok(())
+		},
+	}
+}
+
+/// A room whose canonical alias event names dangling aliases.
+#[derive(Debug)]
+pub struct DanglingAliases {
+	pub room_id: OwnedRoomId,
+	pub dangling: Vec<OwnedRoomAliasId>,
+	pub repaired: RoomCanonicalAliasEventContent,
+}
+
+/// Checks the canonical alias of every room with local members.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn check_canonical_aliases(&self) -> Vec<DanglingAliases> {
+	let room_ids: Vec<OwnedRoomId> = self
+		.services
+		.state_cache
+		.server_rooms(self.services.globals.server_name())
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut found = Vec::new();
+	for room_id in &room_ids {
+		if let Some(dangling) = self.check_canonical_alias(room_id).await {
+			found.push(dangling);
+		}
+	}
+
+	found
+}
+
+/// Checks the canonical alias of the room; `None` when all its local aliases
+/// resolve to it.
+#[implement(super::Service)]
+pub async fn check_canonical_alias(&self, room_id: &RoomId) -> Option<DanglingAliases> {
+	let content: RoomCanonicalAliasEventContent = self
+		.services
+		.state_accessor
+		.room_state_get_content(room_id, &StateEventType::RoomCanonicalAlias, "")
+		.await
+		.ok()?;
+
+	let mut dangling_aliases = Vec::new();
+	for alias in content.alias.iter().chain(&content.alt_aliases) {
+		if self.is_dangling(alias, room_id).await {
+			dangling_aliases.push(alias.clone());
+		}
+	}
+
+	let (dangling, repaired) =
+		repair(&content, |alias| dangling_aliases.iter().any(|a| **a == *alias))?;
+
+	Some(DanglingAliases {
+		room_id: room_id.to_owned(),
+		dangling,
+		repaired,
+	})
+}
+
+/// Replaces the canonical alias event of the room with its repaired content,
+/// sent by the server user. Fails when the server user may not do so.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn repair_canonical_alias(&self, dangling: &DanglingAliases) -> Result {
+	let room_id = &dangling.room_id;
+	let server_user = &self.services.globals.server_user;
+	if !self
+		.services
+		.state_cache
+		.is_joined(server_user, room_id)
+		.await
+	{
+		return Err!(Request(Forbidden("The server user is not in {room_id}.")));
+	}
+
+	let permitted = self
+		.services
+		.state_accessor
+		.get_power_levels(room_id)
+		.await
+		.is_ok_and(|power_levels| {
+			power_levels.user_can_send_state(server_user, StateEventType::RoomCanonicalAlias)
+		});
+
+	if !permitted {
+		return Err!(Request(Forbidden(
+			"The server user may not change the canonical alias of {room_id}."
+		)));
+	}
+
+	let state_lock = self.services.state.mutex.lock(room_id).await;
+	self.services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(String::new(), &dangling.repaired),
+			server_user,
+			room_id,
+			&state_lock,
+		)
+		.boxed()
+		.await?;
+
+	Ok(())
+}
+
+/// Reports the dangling canonical aliases of rooms at startup.
+#[implement(super::Service)]
+pub(super) async fn startup_check_canonical_aliases(&self) {
+	for dangling in self.check_canonical_aliases().await {
+		warn!(
+			room_id = %dangling.room_id,
+			aliases = ?dangling.dangling,
+			"Canonical alias event names aliases no longer resolving to the room. Run `!admin \
+			 server check-canonical-aliases --fix` to repair it.",
+		);
+	}
+}
+
+/// Whether an alias of the configured server name no longer resolves to the
+/// room.
+#[implement(super::Service)]
+async fn is_dangling(&self, alias: &RoomAliasId, room_id: &RoomId) -> bool {
+	if alias.server_name() != self.services.server.config.server_name {
+		return false;
+	}
+
+	self.resolve_local_alias(alias)
+		.await
+		.ok()
+		.is_none_or(|resolved| resolved != room_id)
+}
+
+/// The canonical alias content without the dangling aliases along with those
+/// removed; a dangling main alias is replaced by the first remaining alt
+/// alias. `None` when no alias is dangling.
+pub(super) fn repair<F>(
+	content: &RoomCanonicalAliasEventContent,
+	is_dangling: F,
+) -> Option<(Vec<OwnedRoomAliasId>, RoomCanonicalAliasEventContent)>
+where
+	F: Fn(&RoomAliasId) -> bool,
+{
+	let dangling: Vec<OwnedRoomAliasId> = content
+		.alias
+		.iter()
+		.chain(&content.alt_aliases)
+		.filter(|alias| is_dangling(alias))
+		.cloned()
+		.collect();
+
+	if dangling.is_empty() {
+		return None;
+	}
+
+	let mut alt_aliases: Vec<OwnedRoomAliasId> = content
+		.alt_aliases
+		.iter()
+		.filter(|alias| !is_dangling(alias))
+		.cloned()
+		.collect();
+
+	let alias = match &content.alias {
+		| Some(alias) if !is_dangling(alias) => Some(alias.clone()),
+		| _ if !alt_aliases.is_empty() => Some(alt_aliases.remove(0)),
+		| _ => None,
+	};
+
+	Some((dangling, RoomCanonicalAliasEventContent { alias, alt_aliases }))
+}
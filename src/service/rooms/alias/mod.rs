@@ -1,7 +1,11 @@
+mod canonical;
 mod remote;
+#[cfg(test)]
+mod tests;
 
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use futures::{Stream, StreamExt};
 use ruma::{
 	OwnedRoomId, OwnedServerName, OwnedUserId, RoomAliasId, RoomId, RoomOrAliasId, UserId,
@@ -14,6 +18,7 @@
 };
 use tuwunel_database::{Deserialized, Ignore, Interfix, Map};
 
+pub use self::canonical::DanglingAliases;
 use crate::appservice::RegistrationInfo;
 
 pub struct Service {
@@ -27,6 +32,7 @@ struct Data {
 	aliasid_alias: Arc<Map>,
 }
 
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
@@ -39,6 +45,19 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		}))
 	}
 
+	async fn worker(self: Arc<Self>) -> Result {
+		if self
+			.services
+			.server
+			.config
+			.canonical_alias_startup_check
+		{
+			self.startup_check_canonical_aliases().await;
+		}
+
+		Ok(())
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -70,6 +89,14 @@ pub fn set_alias(&self, alias: &RoomAliasId, room_id: &RoomId, user_id: &UserId)
 			.aliasid_alias
 			.insert(&aliasid, alias.as_bytes());
 
+		self.services
+			.directory
+			.invalidate_summary(room_id);
+
+		self.services
+			.state_cache
+			.appservice_room_changed(room_id);
+
 		Ok(())
 	}
 
@@ -95,6 +122,17 @@ pub async fn remove_alias(&self, alias: &RoomAliasId, user_id: &UserId) -> Resul
 		self.db.alias_roomid.remove(alias.as_bytes());
 		self.db.alias_userid.remove(alias.as_bytes());
 
+		// The directory shows the canonical alias only while it resolves.
+		if let Ok(room_id) = (&room_id).deserialized::<OwnedRoomId>() {
+			self.services
+				.directory
+				.invalidate_summary(&room_id);
+
+			self.services
+				.state_cache
+				.appservice_room_changed(&room_id);
+		}
+
 		Ok(())
 	}
 
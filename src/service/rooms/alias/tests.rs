@@ -0,0 +1,192 @@
+use ruma::{
+	RoomAliasId, api::client::error::ErrorKind,
+	events::room::canonical_alias::RoomCanonicalAliasEventContent, owned_room_id, room_alias_id,
+	room_id,
+};
+use tuwunel_core::{Error, err};
+
+use super::canonical::{check_resolved, repair};
+
+fn content(
+	alias: Option<&RoomAliasId>,
+	alt_aliases: &[&RoomAliasId],
+) -> RoomCanonicalAliasEventContent {
+	RoomCanonicalAliasEventContent {
+		alias: alias.map(ToOwned::to_owned),
+		alt_aliases: alt_aliases
+			.iter()
+			.copied()
+			.map(ToOwned::to_owned)
+			.collect(),
+	}
+}
+
+#[test]
+fn removed_alias_replaced_by_alt_alias() {
+	let removed = room_alias_id!("#removed:example.com");
+	let alt = room_alias_id!("#alt:example.com");
+	let remote = room_alias_id!("#remote:other.example");
+
+	// the alias record of #removed was deleted
+	let content = content(Some(removed), &[remote, alt]);
+	let (dangling, repaired) = repair(&content, |alias| alias == removed).expect("dangling");
+
+	assert_eq!(dangling, [removed.to_owned()]);
+	assert_eq!(repaired.alias.as_deref(), Some(remote));
+	assert_eq!(repaired.alt_aliases, [alt.to_owned()]);
+}
+
+#[test]
+fn removed_alt_alias_dropped() {
+	let main = room_alias_id!("#main:example.com");
+	let removed = room_alias_id!("#removed:example.com");
+
+	let content = content(Some(main), &[removed]);
+	let (dangling, repaired) = repair(&content, |alias| alias == removed).expect("dangling");
+
+	assert_eq!(dangling, [removed.to_owned()]);
+	assert_eq!(repaired.alias.as_deref(), Some(main));
+	assert!(repaired.alt_aliases.is_empty());
+}
+
+#[test]
+fn all_removed_clears_alias() {
+	let removed = room_alias_id!("#removed:example.com");
+
+	let content = content(Some(removed), &[]);
+	let (_, repaired) = repair(&content, |_| true).expect("dangling");
+	assert!(repaired.alias.is_none());
+}
+
+#[test]
+fn resolving_aliases_untouched() {
+	let main = room_alias_id!("#main:example.com");
+
+	let content = content(Some(main), &[]);
+	assert!(repair(&content, |_| false).is_none());
+}
+
+#[test]
+fn correct_alias_accepted() {
+	let alias = room_alias_id!("#main:example.com");
+	let room_id = room_id!("!room:example.com");
+
+	let resolved = Ok(owned_room_id!("!room:example.com"));
+	assert!(
+		check_resolved(alias, room_id, resolved, true, false).is_ok(),
+		"an alias of the room is accepted"
+	);
+}
+
+#[test]
+fn stale_alias_rejected() {
+	let alias = room_alias_id!("#main:example.com");
+	let room_id = room_id!("!room:example.com");
+	let is_bad_alias = |result: Result<(), Error>| {
+		result.is_err_and(|e| matches!(e.kind(), ErrorKind::BadAlias))
+	};
+
+	let moved = || Ok(owned_room_id!("!other:example.com"));
+	assert!(
+		is_bad_alias(check_resolved(alias, room_id, moved(), true, false)),
+		"an alias now pointing at another room is rejected"
+	);
+	assert!(
+		is_bad_alias(check_resolved(alias, room_id, moved(), false, false)),
+		"even when another server says so"
+	);
+
+	let missing = || Err(err!(Request(NotFound("Room with alias not found."))));
+	assert!(
+		is_bad_alias(check_resolved(alias, room_id, missing(), true, false)),
+		"a deleted local alias is rejected"
+	);
+}
+
+#[test]
+fn unresolved_remote_alias_lenient() {
+	let alias = room_alias_id!("#main:other.example");
+	let room_id = room_id!("!room:example.com");
+	let unreachable = || Err(err!(Request(NotFound("Timed out resolving the alias."))));
+
+	assert!(
+		check_resolved(alias, room_id, unreachable(), false, false).is_ok(),
+		"an unreachable server does not block the event"
+	);
+	assert!(
+		check_resolved(alias, room_id, unreachable(), false, true).is_err(),
+		"unless strict"
+	);
+}
+
+#[tokio::test]
+async fn removed_alias_record_found_dangling() {
+	use futures::FutureExt;
+	use tuwunel_core::matrix::pdu::PduBuilder;
+
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+
+	let main = room_alias_id!("#main:fixture.localhost");
+	let alt = room_alias_id!("#alt:fixture.localhost");
+	let remote = room_alias_id!("#remote:example.org");
+	for alias in [main, alt] {
+		services
+			.alias
+			.set_alias(alias, &room_id, &alice)
+			.expect("alias set");
+	}
+
+	let state_lock = services.state.mutex.lock(&room_id).await;
+	services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(String::new(), &content(Some(main), &[alt, remote])),
+			&alice,
+			&room_id,
+			&state_lock,
+		)
+		.boxed()
+		.await
+		.expect("canonical alias sent");
+	drop(state_lock);
+
+	let dangling = services
+		.alias
+		.check_canonical_alias(&room_id)
+		.await;
+	assert!(dangling.is_none(), "every alias resolves: {dangling:?}");
+
+	services
+		.alias
+		.remove_alias(main, &alice)
+		.await
+		.expect("alias removed");
+
+	let dangling = services
+		.alias
+		.check_canonical_alias(&room_id)
+		.await
+		.expect("removed alias found");
+	assert_eq!(dangling.dangling, [main.to_owned()], "only the removed alias dangles");
+	assert_eq!(dangling.repaired.alias.as_deref(), Some(alt), "the alt alias moves up");
+	assert_eq!(dangling.repaired.alt_aliases, [remote.to_owned()], "the remote alias is kept");
+
+	let repaired = services
+		.alias
+		.repair_canonical_alias(&dangling)
+		.await;
+	assert!(repaired.is_err(), "rooms the server user is not in are only reported");
+
+	services.stop().await;
+}
@@ -3,7 +3,8 @@
 use futures::{FutureExt, StreamExt, pin_mut};
 use ruma::RoomId;
 use tuwunel_core::{
-	Result, debug,
+	Err, Result, debug, debug_info,
+	messages::Message,
 	result::LogErr,
 	trace,
 	utils::{ReadyExt, future::BoolExt},
@@ -81,10 +82,16 @@ pub async fn delete_room(
 				 evicting admins too)",
 			);
 
+			let reason = self
+				.services
+				.users
+				.message(user_id, Message::RoomDeleted, &[])
+				.await;
+
 			if let Err(e) = self
 				.services
 				.membership
-				.leave(user_id, room_id, Some("Room Deleted".into()), true, &state_lock)
+				.leave(user_id, room_id, Some(reason), true, &state_lock)
 				.boxed()
 				.await
 			{
@@ -107,73 +114,87 @@ pub async fn delete_room(
 			.await;
 
 		debug!("Removing/unpublishing room from our room directory");
-		self.services.directory.set_not_public(room_id);
+		self.services
+			.directory
+			.set_not_public(room_id, &self.services.globals.server_user)
+			.await;
+
+		debug!("Removing room from sliding sync connections");
+		self.services.sync.forget_snake_sync_room(room_id);
 
 		debug!("Deleting room's threads from database");
-		self.services
-			.threads
-			.delete_all_rooms_threads(room_id)
-			.await
-			.log_err()
-			.ok();
+		let threads = self.deleted(
+			self.services
+				.threads
+				.delete_all_rooms_threads(room_id)
+				.await,
+		)?;
 
 		debug!("Deleting all the room's search token IDs from our database");
-		self.services
-			.search
-			.delete_all_search_tokenids_for_room(room_id)
-			.await
-			.log_err()
-			.ok();
+		let search_tokens = self.deleted(
+			self.services
+				.search
+				.delete_all_search_tokenids_for_room(room_id)
+				.await,
+		)?;
 
 		debug!("Deleting all room's forward extremities from our database");
-		self.services
-			.state
-			.delete_all_rooms_forward_extremities(room_id)
-			.await
-			.log_err()
-			.ok();
+		let extremities = self.deleted(
+			self.services
+				.state
+				.delete_all_rooms_forward_extremities(room_id)
+				.await,
+		)?;
 
 		debug!("Deleting all the room's event (PDU) references");
-		self.services
-			.pdu_metadata
-			.delete_all_referenced_for_room(room_id)
-			.await
-			.log_err()
-			.ok();
+		let references = self.deleted(
+			self.services
+				.pdu_metadata
+				.delete_all_referenced_for_room(room_id)
+				.await,
+		)?;
+
+		debug!("Deleting the room's thread notification counts");
+		let thread_notifications = self.deleted(
+			self.services
+				.user
+				.delete_room_thread_notification_counts(room_id)
+				.await,
+		)?;
 
 		debug!("Deleting all the room's member counts");
-		self.services
-			.state_cache
-			.delete_room_join_counts(room_id, force)
-			.await
-			.log_err()
-			.ok();
+		let memberships = self.deleted(
+			self.services
+				.state_cache
+				.delete_room_join_counts(room_id, force)
+				.await,
+		)?;
 
 		debug!("Deleting all the room's private read receipts");
-		self.services
-			.read_receipt
-			.delete_all_read_receipts(room_id)
-			.await
-			.log_err()
-			.ok();
+		let receipts = self.deleted(
+			self.services
+				.read_receipt
+				.delete_all_read_receipts(room_id)
+				.await,
+		)?;
 
 		debug!("Deleting the room's last notifications read.");
-		self.services
-			.user
-			.delete_room_notification_read(room_id)
-			.await
-			.log_err()
-			.ok();
+		let notifications_read = self.deleted(
+			self.services
+				.user
+				.delete_room_notification_read(room_id)
+				.await,
+		)?;
 
 		debug!("Final stages of deleting the room");
 
 		debug!("Deleting room sync tokens from our database");
-		self.services
-			.user
-			.delete_room_synctokens(room_id)
-			.await
-			.log_err()
-			.ok();
+		let sync_tokens = self.deleted(
+			self.services
+				.user
+				.delete_room_synctokens(room_id)
+				.await,
+		)?;
 
 		debug!("Deleting room state hash from our database");
 		self.services
@@ -183,13 +204,19 @@ pub async fn delete_room(
 			.log_err()
 			.ok();
 
+		debug!("Deleting the state at the room's events");
+		let event_states = self.deleted(
+			self.services
+				.state
+				.delete_all_rooms_event_states(room_id)
+				.await,
+		)?;
+
 		debug!("Deleting PDUs");
-		self.services
-			.timeline
-			.delete_pdus(room_id)
-			.await
-			.log_err()
-			.ok();
+		let pdus = self.deleted(self.services.timeline.delete_pdus(room_id).await)?;
+
+		debug!("Deleting room creation metadata");
+		self.services.metadata.forget_creation(room_id);
 
 		debug!("Deleting internal room ID from our database");
 		self.services
@@ -199,7 +226,33 @@ pub async fn delete_room(
 			.log_err()
 			.ok();
 
-		debug!("Successfully deleted room {room_id} from our database");
+		debug_info!(
+			%room_id,
+			threads,
+			search_tokens,
+			extremities,
+			references,
+			memberships,
+			receipts,
+			notifications_read,
+			thread_notifications,
+			sync_tokens,
+			event_states,
+			pdus,
+			"Successfully deleted room from our database"
+		);
+
 		Ok(())
 	}
+
+	/// Logs a failed deletion step and carries on with the others, unless the
+	/// server is shutting down in which case the room deletion stops here.
+	fn deleted(&self, result: Result<usize>) -> Result<usize> {
+		let deleted = result.log_err().unwrap_or(0);
+		if self.services.server.is_stopping() {
+			return Err!("Room deletion interrupted by server shutdown.");
+		}
+
+		Ok(deleted)
+	}
 }
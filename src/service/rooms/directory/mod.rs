@@ -1,22 +1,54 @@
+mod summary;
+#[cfg(test)]
+mod tests;
+
 use std::sync::Arc;
 
-use futures::Stream;
-use ruma::{RoomId, api::client::room::Visibility};
-use tuwunel_core::{Result, implement, utils::stream::TryIgnore};
-use tuwunel_database::Map;
+use futures::{Stream, StreamExt};
+use ruma::{
+	OwnedRoomId, OwnedUserId, RoomId, UserId, api::client::room::Visibility,
+	events::StateEventType,
+};
+use serde::{Deserialize, Serialize};
+use tuwunel_core::{
+	Err, Event, Result, implement,
+	utils::{
+		millis_since_unix_epoch,
+		stream::{ReadyExt, TryIgnore},
+	},
+};
+use tuwunel_database::{Json, Map};
+
+pub use self::summary::affects_summary;
 
 pub struct Service {
+	services: Arc<crate::services::OnceServices>,
 	db: Data,
 }
 
 struct Data {
+	directory_audit: Arc<Map>,
+	publicroomid_summary: Arc<Map>,
 	publicroomids: Arc<Map>,
 }
 
+/// A change of the visibility of a room in the directory.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEntry {
+	pub room_id: OwnedRoomId,
+	pub user_id: OwnedUserId,
+	pub old: Visibility,
+	pub new: Visibility,
+	pub ts: u64,
+}
+
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
+			services: args.services.clone(),
 			db: Data {
+				directory_audit: args.db["directory_audit"].clone(),
+				publicroomid_summary: args.db["publicroomid_summary"].clone(),
 				publicroomids: args.db["publicroomids"].clone(),
 			},
 		}))
@@ -25,11 +57,88 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
+/// Publishes the room, recording the acting user in the audit log.
+#[implement(Service)]
+pub async fn set_public(&self, room_id: &RoomId, user_id: &UserId) {
+	self.audit(room_id, user_id, Visibility::Public)
+		.await;
+
+	self.db.publicroomids.insert(room_id, []);
+}
+
+/// Unpublishes the room, recording the acting user in the audit log.
 #[implement(Service)]
-pub fn set_public(&self, room_id: &RoomId) { self.db.publicroomids.insert(room_id, []); }
+pub async fn set_not_public(&self, room_id: &RoomId, user_id: &UserId) {
+	self.audit(room_id, user_id, Visibility::Private)
+		.await;
+
+	self.db.publicroomids.remove(room_id);
+	self.invalidate_summary(room_id);
+}
 
 #[implement(Service)]
-pub fn set_not_public(&self, room_id: &RoomId) { self.db.publicroomids.remove(room_id); }
+async fn audit(&self, room_id: &RoomId, user_id: &UserId, new: Visibility) {
+	let old = self.visibility(room_id).await;
+	if old == new {
+		return;
+	}
+
+	let entry = AuditEntry {
+		room_id: room_id.to_owned(),
+		user_id: user_id.to_owned(),
+		old,
+		new,
+		ts: millis_since_unix_epoch(),
+	};
+
+	let count = self.services.globals.next_count();
+	self.db.directory_audit.put(*count, Json(entry));
+}
+
+/// Whether the user may change the visibility of the room: server admins
+/// always may, others when they may change its history visibility, or when
+/// they created a room without power levels.
+#[implement(Service)]
+pub async fn user_can_publish(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool> {
+	if self.services.users.is_admin(user_id).await {
+		return Ok(true);
+	}
+
+	match self
+		.services
+		.state_accessor
+		.get_power_levels(room_id)
+		.await
+	{
+		| Ok(power_levels) =>
+			Ok(power_levels.user_can_send_state(user_id, StateEventType::RoomHistoryVisibility)),
+		| _ => {
+			match self
+				.services
+				.state_accessor
+				.room_state_get(room_id, &StateEventType::RoomCreate, "")
+				.await
+			{
+				| Ok(event) => Ok(event.sender() == user_id),
+				| _ => Err!(Request(Forbidden("User is not allowed to publish this room"))),
+			}
+		},
+	}
+}
+
+/// Visibility changes, most recent first, optionally of one room only.
+#[implement(Service)]
+pub fn audit_log<'a>(
+	&'a self,
+	room_id: Option<&'a RoomId>,
+) -> impl Stream<Item = AuditEntry> + Send + 'a {
+	self.db
+		.directory_audit
+		.rev_stream()
+		.ignore_err()
+		.map(|(_, entry): (u64, AuditEntry)| entry)
+		.ready_filter(move |entry| room_id.is_none_or(|room_id| entry.room_id == room_id))
+}
 
 #[implement(Service)]
 pub fn public_rooms(&self) -> impl Stream<Item = &RoomId> + Send {
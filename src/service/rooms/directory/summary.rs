@@ -0,0 +1,194 @@
+//! Summaries of published rooms as listed in the directory, cached in
+//! `publicroomid_summary` so listing the directory takes no state lookups. An
+//! entry is dropped whenever an event changing the summary is appended to the
+//! room and rebuilt on the next listing; the member count is always read
+//! live.
+
+use futures::{
+	FutureExt, StreamExt, TryFutureExt,
+	future::{join, join4, join5},
+};
+use ruma::{
+	OwnedRoomId, RoomId, UInt,
+	directory::PublicRoomsChunk,
+	events::{
+		StateEventType, TimelineEventType,
+		room::join_rules::{JoinRule, RoomJoinRulesEventContent},
+	},
+	uint,
+};
+use tuwunel_core::{
+	implement,
+	utils::{TryFutureExtExt, result::FlatOk},
+};
+use tuwunel_database::{Deserialized, Json};
+
+/// Whether a state event of the type changes the summary of its room.
+#[must_use]
+pub fn affects_summary(kind: &TimelineEventType) -> bool {
+	matches!(
+		kind,
+		TimelineEventType::RoomAvatar
+			| TimelineEventType::RoomCanonicalAlias
+			| TimelineEventType::RoomCreate
+			| TimelineEventType::RoomGuestAccess
+			| TimelineEventType::RoomHistoryVisibility
+			| TimelineEventType::RoomJoinRules
+			| TimelineEventType::RoomName
+			| TimelineEventType::RoomTopic
+	)
+}
+
+/// The summary of the room for the directory, from the cache when present.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "trace")]
+pub async fn public_room_summary(&self, room_id: OwnedRoomId) -> PublicRoomsChunk {
+	let num_joined_members = self.joined_members(&room_id);
+	let cached = self
+		.db
+		.publicroomid_summary
+		.get(room_id.as_bytes())
+		.map(|handle| handle.deserialized::<PublicRoomsChunk>().ok());
+
+	let (num_joined_members, cached) = join(num_joined_members, cached).await;
+	let mut chunk = match cached {
+		| Some(chunk) => chunk,
+		| None => self.cache_summary(room_id).await,
+	};
+
+	chunk.num_joined_members = num_joined_members;
+	chunk
+}
+
+/// Drops the cached summary of the room.
+#[implement(super::Service)]
+pub fn invalidate_summary(&self, room_id: &RoomId) {
+	self.db.publicroomid_summary.remove(room_id);
+}
+
+/// Rebuilds the cached summaries of all published rooms.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn rebuild_summaries(&self) -> usize {
+	self.db.publicroomid_summary.clear().await;
+
+	let room_ids: Vec<OwnedRoomId> = self
+		.public_rooms()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for room_id in &room_ids {
+		self.cache_summary(room_id.clone()).await;
+	}
+
+	room_ids.len()
+}
+
+/// Builds the summary from the state of the room, caching it if the room is
+/// published. The state lock is held so an event being appended cannot
+/// invalidate the entry before its state is visible.
+#[implement(super::Service)]
+async fn cache_summary(&self, room_id: OwnedRoomId) -> PublicRoomsChunk {
+	let state_lock = self.services.state.mutex.lock(&room_id).await;
+	let chunk = self.build_summary(room_id).await;
+	if self.is_public_room(&chunk.room_id).await {
+		self.db
+			.publicroomid_summary
+			.raw_put(chunk.room_id.as_bytes(), Json(&chunk));
+	}
+
+	drop(state_lock);
+	chunk
+}
+
+#[implement(super::Service)]
+async fn joined_members(&self, room_id: &RoomId) -> UInt {
+	self.services
+		.state_cache
+		.room_joined_count(room_id)
+		.await
+		.map(TryInto::try_into)
+		.map(Result::ok)
+		.flat_ok()
+		.unwrap_or_else(|| uint!(0))
+}
+
+#[implement(super::Service)]
+async fn build_summary(&self, room_id: OwnedRoomId) -> PublicRoomsChunk {
+	let services = &self.services;
+	let name = services.state_accessor.get_name(&room_id).ok();
+
+	let room_type = services
+		.state_accessor
+		.get_room_type(&room_id)
+		.ok();
+
+	let canonical_alias = services
+		.state_accessor
+		.get_canonical_alias(&room_id)
+		.ok()
+		.then(async |alias| {
+			if let Some(alias) = alias
+				&& services.globals.alias_is_local(&alias)
+				&& let Ok(alias_room_id) = services.alias.resolve_local_alias(&alias).await
+				&& alias_room_id == room_id
+			{
+				Some(alias)
+			} else {
+				None
+			}
+		});
+
+	let avatar_url = services
+		.state_accessor
+		.get_avatar(&room_id)
+		.map_ok(|content| content.url)
+		.ok();
+
+	let topic = services
+		.state_accessor
+		.get_room_topic(&room_id)
+		.ok();
+
+	let world_readable = services
+		.state_accessor
+		.is_world_readable(&room_id);
+
+	let join_rule = services
+		.state_accessor
+		.room_state_get_content(&room_id, &StateEventType::RoomJoinRules, "")
+		.map_ok(|c: RoomJoinRulesEventContent| match c.join_rule {
+			| JoinRule::Public => "public".into(),
+			| JoinRule::Knock => "knock".into(),
+			| JoinRule::KnockRestricted(_) => "knock_restricted".into(),
+			| _ => "invite".into(),
+		});
+
+	let guest_can_join = services.state_accessor.guest_can_join(&room_id);
+
+	let num_joined_members = self.joined_members(&room_id);
+
+	let (
+		(avatar_url, canonical_alias, guest_can_join, join_rule, name),
+		(num_joined_members, room_type, topic, world_readable),
+	) = join(
+		join5(avatar_url, canonical_alias, guest_can_join, join_rule, name),
+		join4(num_joined_members, room_type, topic, world_readable),
+	)
+	.boxed()
+	.await;
+
+	PublicRoomsChunk {
+		avatar_url: avatar_url.flatten(),
+		canonical_alias,
+		guest_can_join,
+		join_rule: join_rule.unwrap_or_default(),
+		name,
+		num_joined_members,
+		room_id,
+		room_type,
+		topic,
+		world_readable,
+	}
+}
@@ -0,0 +1,98 @@
+use futures::StreamExt;
+use ruma::{UserId, api::client::room::Visibility};
+
+use crate::fixture::Fixture;
+
+#[tokio::test]
+async fn admin_may_publish_any_room() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+	let admin = embedded
+		.create_user("admin", Some("password"))
+		.await
+		.expect("admin");
+	fixture
+		.admin
+		.make_user_admin(&admin)
+		.await
+		.expect("admin granted");
+
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	let can_publish = async |user_id: &UserId| {
+		fixture
+			.directory
+			.user_can_publish(user_id, &room_id)
+			.await
+			.expect("room has power levels")
+	};
+
+	assert!(can_publish(&alice).await, "the creator may publish");
+	assert!(!can_publish(&bob).await, "others may not");
+	assert!(can_publish(&admin).await, "server admins may, without any power level");
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn visibility_changes_audited() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	let other_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("other room");
+	let server_user = &fixture.globals.server_user;
+
+	let directory = &fixture.directory;
+	assert!(!directory.is_public_room(&room_id).await, "rooms start unpublished");
+
+	directory.set_not_public(&room_id, &alice).await;
+	directory.set_public(&room_id, &alice).await;
+	directory.set_public(&room_id, &alice).await;
+	directory.set_public(&other_id, &alice).await;
+	directory
+		.set_not_public(&room_id, server_user)
+		.await;
+
+	let changes: Vec<_> = directory
+		.audit_log(Some(&room_id))
+		.map(|entry| (entry.user_id, entry.old, entry.new))
+		.collect()
+		.await;
+
+	assert_eq!(
+		changes,
+		[
+			(server_user.clone(), Visibility::Public, Visibility::Private),
+			(alice.clone(), Visibility::Private, Visibility::Public),
+		],
+		"changes of the room, newest first, without those changing nothing"
+	);
+	assert_eq!(
+		directory.audit_log(Some(&other_id)).count().await,
+		1,
+		"changes of the other room kept apart"
+	);
+
+	fixture.stop().await;
+}
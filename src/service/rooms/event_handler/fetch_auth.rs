@@ -1,5 +1,6 @@
 use std::{
 	collections::{HashSet, VecDeque},
+	iter::once,
 	ops::Range,
 	time::Duration,
 };
@@ -10,7 +11,7 @@
 	ServerName, api::federation::event::get_event,
 };
 use tuwunel_core::{
-	debug, debug_error, debug_warn, implement,
+	Result, at, debug, debug_error, debug_warn, err, implement,
 	matrix::{PduEvent, event::gen_event_id_canonical_json},
 	trace,
 	utils::stream::{BroadbandExt, IterStream, ReadyExt},
@@ -95,6 +96,34 @@ pub(super) async fn fetch_auth<'a, Events>(
 		.await
 }
 
+/// Finds the event, fetching it from the origin and authenticating it as an
+/// outlier when unknown.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn fetch_outlier(
+	&self,
+	origin: &ServerName,
+	room_id: &RoomId,
+	event_id: &EventId,
+) -> Result<PduEvent> {
+	let room_version = self
+		.services
+		.state
+		.get_room_version(room_id)
+		.await?;
+
+	let fetch = self.fetch_auth(origin, room_id, once(event_id), &room_version);
+
+	Box::pin(fetch)
+		.await
+		.into_iter()
+		.map(at!(0))
+		.find(|pdu| *pdu.event_id == *event_id)
+		.ok_or_else(|| {
+			err!(Request(NotFound("Event {event_id} could not be fetched from {origin}.")))
+		})
+}
+
 #[implement(super::Service)]
 async fn fetch_auth_chain(
 	&self,
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use futures::{
 	FutureExt, TryFutureExt, TryStreamExt,
 	future::{OptionFuture, try_join5},
@@ -9,7 +11,7 @@
 	err, implement,
 	matrix::{Event, room_version},
 	trace,
-	utils::stream::IterStream,
+	utils::{millis_since_unix_epoch, stream::IterStream},
 	warn,
 };
 
@@ -57,6 +59,34 @@ pub async fn handle_incoming_pdu<'a>(
 	event_id: &'a EventId,
 	pdu: CanonicalJsonObject,
 	is_timeline_event: bool,
+) -> Result<Option<RawPduId>> {
+	let result = self
+		.process_incoming_pdu(origin, room_id, event_id, pdu, is_timeline_event)
+		.await;
+
+	let result_label = match &result {
+		| Ok(Some(_)) => "accepted",
+		| Ok(None) => "outlier",
+		| Err(_) => "rejected",
+	};
+
+	self.services.metrics.inc(
+		"tuwunel_federation_pdus_total",
+		"Incoming federation PDUs by how they were handled.",
+		&[("result", result_label)],
+	);
+
+	result
+}
+
+#[implement(super::Service)]
+async fn process_incoming_pdu<'a>(
+	&'a self,
+	origin: &'a ServerName,
+	room_id: &'a RoomId,
+	event_id: &'a EventId,
+	pdu: CanonicalJsonObject,
+	is_timeline_event: bool,
 ) -> Result<Option<RawPduId>> {
 	// 1. Skip the PDU if we already have it as a timeline event
 	if let Ok(pdu_id) = self.services.timeline.get_pdu_id(event_id).await {
@@ -118,6 +148,40 @@ pub async fn handle_incoming_pdu<'a>(
 		.handle_outlier_pdu(origin, room_id, event_id, pdu, &room_version, false)
 		.await?;
 
+	// Soft fail replayed ancient events before fetching their prev events; those
+	// backfilled are handled as outliers and exempt
+	let max_age = self
+		.services
+		.config
+		.reject_events_older_than
+		.map(Duration::from_secs);
+
+	if exceeds_age_limit(
+		max_age,
+		millis_since_unix_epoch(),
+		incoming_pdu.origin_server_ts.into(),
+		incoming_pdu.state_key.is_some(),
+		is_timeline_event,
+	) {
+		self.services
+			.pdu_metadata
+			.mark_event_soft_failed(&incoming_pdu, "Older than reject_events_older_than");
+
+		self.services.metrics.inc(
+			"tuwunel_federation_pdus_too_old_total",
+			"Incoming federation PDUs soft failed for their age.",
+			&[("origin", origin.as_str())],
+		);
+
+		warn!(
+			%origin,
+			origin_server_ts = ?incoming_pdu.origin_server_ts,
+			"Event is older than reject_events_older_than; soft failing"
+		);
+
+		return Err!(Request(InvalidParam("Event has been soft failed")));
+	}
+
 	// 8. if not timeline event: stop
 	if !is_timeline_event {
 		return Ok(None);
@@ -182,3 +246,22 @@ pub async fn handle_incoming_pdu<'a>(
 	.boxed()
 	.await
 }
+
+/// Whether an event is too old to enter the timeline given the maximum age
+/// of events accepted. Only timeline events which are not state events are
+/// limited; those received otherwise, e.g. while backfilling, are expected to
+/// be old.
+pub(super) fn exceeds_age_limit(
+	max_age: Option<Duration>,
+	now_millis: u64,
+	origin_server_ts: u64,
+	is_state_event: bool,
+	is_timeline_event: bool,
+) -> bool {
+	let Some(max_age) = max_age else {
+		return false;
+	};
+
+	let max_age = u64::try_from(max_age.as_millis()).unwrap_or(u64::MAX);
+	is_timeline_event && !is_state_event && now_millis.saturating_sub(origin_server_ts) > max_age
+}
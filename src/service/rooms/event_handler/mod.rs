@@ -5,9 +5,12 @@
 mod handle_incoming_pdu;
 mod handle_outlier_pdu;
 mod handle_prev_pdu;
+mod origin_stats;
 mod parse_incoming_pdu;
 mod resolve_state;
 mod state_at_incoming;
+#[cfg(test)]
+mod tests;
 mod upgrade_outlier_pdu;
 
 use std::{
@@ -26,9 +29,13 @@
 	utils::{MutexMap, continue_exponential_backoff},
 };
 
+use self::origin_stats::{ORIGINS_MAX, Recorder, SAMPLES_MAX};
+pub use self::origin_stats::{Sample, Summary};
+
 pub struct Service {
 	pub mutex_federation: RoomMutexMap,
 	services: Arc<crate::services::OnceServices>,
+	origin_stats: origin_stats::OriginStats,
 }
 
 type RoomMutexMap = MutexMap<OwnedRoomId, ()>;
@@ -39,6 +46,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			mutex_federation: RoomMutexMap::new(),
 			services: args.services.clone(),
+			origin_stats: Recorder::new(ORIGINS_MAX, SAMPLES_MAX).into(),
 		}))
 	}
 
@@ -46,9 +54,14 @@ async fn memory_usage(&self, out: &mut (dyn Write + Send)) -> Result {
 		let mutex_federation = self.mutex_federation.len();
 		writeln!(out, "federation_mutex: {mutex_federation}")?;
 
+		let origin_stats = self.origin_stats.lock()?.len();
+		writeln!(out, "origin_stats: {origin_stats}")?;
+
 		Ok(())
 	}
 
+	async fn clear_cache(&self) { self.origin_stats.lock().expect("locked").clear(); }
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
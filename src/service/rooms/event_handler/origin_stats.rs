@@ -0,0 +1,172 @@
+//! Processing times of the PDUs each origin sent us over `/send`, answering
+//! which server is making the event handler slow. Each origin keeps its most
+//! recent samples in a fixed-size ring; only the most recently active origins
+//! are kept. The slowest PDUs are picked from the samples still in the ring.
+
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use lru_cache::LruCache;
+use ruma::{EventId, OwnedEventId, OwnedServerName, ServerName};
+use tuwunel_core::implement;
+
+/// Samples kept per origin.
+pub(super) const SAMPLES_MAX: usize = 128;
+
+/// Origins kept; the least recently active is dropped beyond this.
+pub(super) const ORIGINS_MAX: usize = 256;
+
+/// Slowest PDUs listed per origin.
+const SLOWEST_MAX: usize = 5;
+
+pub(super) type OriginStats = Mutex<Recorder>;
+
+/// Rings of samples by origin.
+pub(super) struct Recorder {
+	origins: LruCache<OwnedServerName, VecDeque<Sample>>,
+	samples_max: usize,
+}
+
+/// One processed PDU.
+#[derive(Clone, Debug)]
+pub struct Sample {
+	pub event_id: OwnedEventId,
+	pub kind: String,
+	pub elapsed: Duration,
+}
+
+/// Summary of the samples of an origin.
+#[derive(Debug)]
+pub struct Summary {
+	pub count: usize,
+	pub mean: Duration,
+	pub p50: Duration,
+	pub p90: Duration,
+	pub p99: Duration,
+	pub max: Duration,
+
+	/// Slowest samples, slowest first.
+	pub slowest: Vec<Sample>,
+}
+
+/// Records the time taken processing a PDU sent by the origin.
+#[implement(super::Service)]
+pub fn record_pdu(&self, origin: &ServerName, event_id: &EventId, kind: &str, elapsed: Duration) {
+	self.origin_stats
+		.lock()
+		.expect("locked")
+		.record(origin, event_id, kind, elapsed);
+}
+
+/// Summary of the recent PDUs of the origin.
+#[implement(super::Service)]
+#[must_use]
+pub fn origin_summary(&self, origin: &ServerName) -> Option<Summary> {
+	self.origin_stats
+		.lock()
+		.expect("locked")
+		.summary(origin)
+}
+
+/// Summaries of all origins, slowest mean first.
+#[implement(super::Service)]
+#[must_use]
+pub fn origin_summaries(&self) -> Vec<(OwnedServerName, Summary)> {
+	let mut summaries = self
+		.origin_stats
+		.lock()
+		.expect("locked")
+		.summaries();
+
+	summaries.sort_by(|(_, a), (_, b)| b.mean.cmp(&a.mean));
+	summaries
+}
+
+impl Recorder {
+	#[must_use]
+	pub(super) fn new(origins_max: usize, samples_max: usize) -> Self {
+		Self {
+			origins: LruCache::new(origins_max),
+			samples_max,
+		}
+	}
+
+	pub(super) fn record(
+		&mut self,
+		origin: &ServerName,
+		event_id: &EventId,
+		kind: &str,
+		elapsed: Duration,
+	) {
+		let sample = Sample {
+			event_id: event_id.to_owned(),
+			kind: kind.to_owned(),
+			elapsed,
+		};
+
+		if let Some(samples) = self.origins.get_mut(origin) {
+			if samples.len() >= self.samples_max {
+				samples.pop_front();
+			}
+
+			samples.push_back(sample);
+			return;
+		}
+
+		let mut samples = VecDeque::with_capacity(self.samples_max);
+		samples.push_back(sample);
+		self.origins.insert(origin.to_owned(), samples);
+	}
+
+	#[must_use]
+	pub(super) fn summary(&mut self, origin: &ServerName) -> Option<Summary> {
+		self.origins
+			.get_mut(origin)
+			.and_then(|samples| summarize(samples))
+	}
+
+	#[must_use]
+	pub(super) fn summaries(&self) -> Vec<(OwnedServerName, Summary)> {
+		self.origins
+			.iter()
+			.filter_map(|(origin, samples)| Some((origin.clone(), summarize(samples)?)))
+			.collect()
+	}
+
+	#[must_use]
+	pub(super) fn len(&self) -> usize { self.origins.len() }
+
+	pub(super) fn clear(&mut self) { self.origins.clear(); }
+}
+
+fn summarize(samples: &VecDeque<Sample>) -> Option<Summary> {
+	let mut sorted: Vec<&Sample> = samples.iter().collect();
+	sorted.sort_by(|a, b| b.elapsed.cmp(&a.elapsed));
+
+	let count = sorted.len();
+	let total: Duration = sorted.iter().map(|sample| sample.elapsed).sum();
+	let mean = total.checked_div(u32::try_from(count).ok()?)?;
+
+	// Nearest rank of the quantile among samples sorted slowest first.
+	let quantile = |q: usize| {
+		let rank = count
+			.saturating_mul(q)
+			.div_ceil(100)
+			.clamp(1, count);
+
+		sorted[count.saturating_sub(rank)].elapsed
+	};
+
+	Some(Summary {
+		count,
+		mean,
+		p50: quantile(50),
+		p90: quantile(90),
+		p99: quantile(99),
+		max: sorted[0].elapsed,
+		slowest: sorted
+			.into_iter()
+			.take(SLOWEST_MAX)
+			.cloned()
+			.collect(),
+	})
+}
@@ -0,0 +1,199 @@
+use std::time::{Duration, SystemTime};
+
+use ruma::{
+	CanonicalJsonObject, MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, UserId, event_id,
+	events::room::message::RoomMessageEventContent, server_name,
+};
+use tuwunel_core::{
+	Event,
+	matrix::pdu::{PduBuilder, format::into_outgoing_federation},
+};
+
+use super::{handle_incoming_pdu::exceeds_age_limit, origin_stats::Recorder};
+use crate::fixture::Fixture;
+
+const DAY: u64 = 24 * 60 * 60 * 1000;
+const NOW: u64 = 1_000 * DAY;
+const DAY_AGO: u64 = NOW - DAY;
+const YEAR_AGO: u64 = NOW - 365 * DAY;
+const MAX_AGE: Option<Duration> = Some(Duration::from_secs(30 * 24 * 60 * 60));
+
+#[test]
+fn old_timeline_event_soft_failed() {
+	assert!(exceeds_age_limit(MAX_AGE, NOW, YEAR_AGO, false, true));
+	assert!(!exceeds_age_limit(MAX_AGE, NOW, DAY_AGO, false, true));
+}
+
+#[test]
+fn old_event_accepted_when_backfilled() {
+	assert!(!exceeds_age_limit(MAX_AGE, NOW, YEAR_AGO, false, false));
+}
+
+#[test]
+fn old_state_event_accepted() {
+	assert!(!exceeds_age_limit(MAX_AGE, NOW, YEAR_AGO, true, true));
+}
+
+#[test]
+fn unlimited_by_default() {
+	assert!(!exceeds_age_limit(None, NOW, 0, false, true));
+}
+
+/// A message by the sender dated a year ago, signed by the fixture's server
+/// and as another server would send it over federation.
+async fn year_old_message(
+	fixture: &Fixture,
+	sender: &UserId,
+	room_id: &RoomId,
+) -> (OwnedEventId, CanonicalJsonObject) {
+	let year_ago = SystemTime::now()
+		.checked_sub(Duration::from_secs(365 * 24 * 60 * 60))
+		.and_then(MilliSecondsSinceUnixEpoch::from_system_time);
+
+	let state_lock = fixture.state.mutex.lock(room_id).await;
+	let (pdu, json) = fixture
+		.timeline
+		.create_hash_and_sign_event(
+			PduBuilder {
+				timestamp: year_ago,
+				..PduBuilder::timeline(&RoomMessageEventContent::text_plain("replayed"))
+			},
+			sender,
+			room_id,
+			&state_lock,
+		)
+		.await
+		.expect("event created");
+
+	let room_version = fixture
+		.state
+		.get_room_version(room_id)
+		.await
+		.expect("room version");
+
+	(pdu.event_id().to_owned(), into_outgoing_federation(json, &room_version))
+}
+
+#[tokio::test]
+async fn old_incoming_event_soft_failed_unless_backfilled() {
+	let fixture = Fixture::start_with("reject_events_older_than = 86400").await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	let origin = fixture.globals.server_name();
+
+	let (incoming_id, incoming) = year_old_message(&fixture, &alice, &room_id).await;
+	let result = fixture
+		.event_handler
+		.handle_incoming_pdu(origin, &room_id, &incoming_id, incoming, true)
+		.await;
+	assert!(result.is_err(), "old timeline event refused");
+	assert!(
+		fixture
+			.pdu_metadata
+			.is_event_soft_failed(&incoming_id)
+			.await,
+		"old timeline event soft failed"
+	);
+
+	let (backfilled_id, backfilled) = year_old_message(&fixture, &alice, &room_id).await;
+	let result = fixture
+		.event_handler
+		.handle_incoming_pdu(origin, &room_id, &backfilled_id, backfilled, false)
+		.await;
+	assert!(
+		matches!(result, Ok(None)),
+		"old backfilled event kept as an outlier: {result:?}"
+	);
+	assert!(
+		!fixture
+			.pdu_metadata
+			.is_event_soft_failed(&backfilled_id)
+			.await,
+		"old backfilled event not soft failed"
+	);
+
+	fixture.stop().await;
+}
+
+fn ms(millis: u64) -> Duration { Duration::from_millis(millis) }
+
+#[test]
+fn origin_stats_summary() {
+	let origin = server_name!("slow.example");
+	let mut recorder = Recorder::new(4, 128);
+	for millis in 1..=99 {
+		recorder.record(origin, event_id!("$a:slow.example"), "m.room.message", ms(millis));
+	}
+
+	recorder.record(origin, event_id!("$slowest:slow.example"), "m.room.member", ms(550));
+
+	let summary = recorder.summary(origin).expect("origin recorded");
+	assert_eq!(summary.count, 100, "every sample counted");
+	assert_eq!(summary.mean, ms(55), "mean of all samples");
+	assert_eq!(summary.p50, ms(50), "median by nearest rank");
+	assert_eq!(summary.p90, ms(90), "p90 by nearest rank");
+	assert_eq!(summary.p99, ms(99), "p99 by nearest rank");
+	assert_eq!(summary.max, ms(550), "slowest sample is the max");
+
+	let slowest: Vec<_> = summary
+		.slowest
+		.iter()
+		.map(|s| s.elapsed)
+		.collect();
+	assert_eq!(slowest, [ms(550), ms(99), ms(98), ms(97), ms(96)], "slowest first");
+	assert_eq!(summary.slowest[0].kind, "m.room.member", "type of the slowest PDU kept");
+	assert_eq!(
+		summary.slowest[0].event_id,
+		event_id!("$slowest:slow.example"),
+		"id of the slowest PDU kept"
+	);
+
+	assert!(
+		recorder
+			.summary(server_name!("other.example"))
+			.is_none(),
+		"unknown origin"
+	);
+}
+
+#[test]
+fn origin_stats_ring_overflow() {
+	let origin = server_name!("busy.example");
+	let mut recorder = Recorder::new(4, 3);
+	for millis in [900, 10, 20, 30] {
+		recorder.record(origin, event_id!("$a:busy.example"), "m.room.message", ms(millis));
+	}
+
+	let summary = recorder.summary(origin).expect("origin recorded");
+	assert_eq!(summary.count, 3, "ring holds at most its capacity");
+	assert_eq!(summary.max, ms(30), "oldest sample dropped from the ring");
+	assert_eq!(summary.mean, ms(20), "mean of the samples in the ring");
+}
+
+#[test]
+fn origin_stats_evicts_least_recent_origin() {
+	let event_id = event_id!("$a:example.com");
+	let (a, b, c) =
+		(server_name!("a.example"), server_name!("b.example"), server_name!("c.example"));
+
+	let mut recorder = Recorder::new(2, 8);
+	recorder.record(a, event_id, "m.room.message", ms(1));
+	recorder.record(b, event_id, "m.room.message", ms(2));
+	recorder.record(a, event_id, "m.room.message", ms(3));
+	recorder.record(c, event_id, "m.room.message", ms(4));
+
+	assert_eq!(recorder.len(), 2, "origins capped");
+	assert!(recorder.summary(b).is_none(), "least recently active origin evicted");
+	assert_eq!(recorder.summary(a).map(|s| s.count), Some(2), "active origin kept");
+	assert_eq!(recorder.summary(c).map(|s| s.count), Some(1), "new origin kept");
+
+	recorder.clear();
+	assert_eq!(recorder.len(), 0, "cleared");
+}
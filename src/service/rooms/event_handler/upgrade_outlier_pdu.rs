@@ -254,7 +254,7 @@ pub(super) async fn upgrade_outlier_to_timeline_pdu(
 	if soft_fail {
 		self.services
 			.pdu_metadata
-			.mark_event_soft_failed(incoming_pdu.event_id());
+			.mark_event_soft_failed(&incoming_pdu, "Sender may not redact the target event");
 
 		drop(state_lock);
 		warn!(
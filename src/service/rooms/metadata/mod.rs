@@ -1,15 +1,23 @@
+#[cfg(test)]
+mod tests;
+
 use std::sync::Arc;
 
 use futures::{FutureExt, Stream, StreamExt, pin_mut};
-use ruma::{OwnedRoomId, RoomId, events::room::join_rules::JoinRule};
+use ruma::{
+	MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, ServerName,
+	events::room::join_rules::JoinRule, room_version_rules::AuthorizationRules,
+};
+use serde::{Deserialize, Serialize};
 use tuwunel_core::{
-	Result, implement,
+	Result, debug_warn, implement,
+	matrix::{Event, room_version, state_res::events::RoomCreateEvent},
 	utils::{
 		future::BoolExt,
 		stream::{TryIgnore, WidebandExt},
 	},
 };
-use tuwunel_database::Map;
+use tuwunel_database::{Deserialized, Json, Map};
 
 pub struct Service {
 	db: Data,
@@ -19,16 +27,36 @@ pub struct Service {
 struct Data {
 	disabledroomids: Arc<Map>,
 	bannedroomids: Arc<Map>,
+	roomid_creation: Arc<Map>,
 	roomid_shortroomid: Arc<Map>,
 	pduid_pdu: Arc<Map>,
 }
 
+/// How a room came to be known to this server.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Creation {
+	/// Creator of the room according to its create event.
+	pub creator: OwnedUserId,
+
+	/// `origin_server_ts` of the create event.
+	pub created_at: MilliSecondsSinceUnixEpoch,
+
+	/// Whether the room was created on this server rather than joined over
+	/// federation.
+	pub local: bool,
+
+	/// Server the room was joined through, when joined over federation and
+	/// known.
+	pub created_via: Option<OwnedServerName>,
+}
+
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			db: Data {
 				disabledroomids: args.db["disabledroomids"].clone(),
 				bannedroomids: args.db["bannedroomids"].clone(),
+				roomid_creation: args.db["roomid_creation"].clone(),
 				roomid_shortroomid: args.db["roomid_shortroomid"].clone(),
 				pduid_pdu: args.db["pduid_pdu"].clone(),
 			},
@@ -124,3 +152,68 @@ pub async fn is_disabled(&self, room_id: &RoomId) -> bool {
 pub async fn is_banned(&self, room_id: &RoomId) -> bool {
 	self.db.bannedroomids.get(room_id).await.is_ok()
 }
+
+/// Records how the room came to be known from its create event, unless
+/// already recorded. `via` is the server a remote room was joined through.
+#[implement(Service)]
+pub async fn record_creation(&self, room_id: &RoomId, via: Option<&ServerName>) {
+	if self.creation(room_id).await.is_ok() {
+		return;
+	}
+
+	let creation = async {
+		let create = self
+			.services
+			.state_accessor
+			.get_create(room_id)
+			.await?;
+
+		let rules = room_version::rules(&create.room_version()?)?;
+		let local = self
+			.services
+			.globals
+			.user_is_local(create.sender());
+
+		creation_from(&create, &rules.authorization, local, via)
+	};
+
+	match creation.await {
+		| Ok(creation) => self
+			.db
+			.roomid_creation
+			.put(room_id, Json(creation)),
+		| Err(e) => debug_warn!(%room_id, "Failed to record room creation: {e}"),
+	}
+}
+
+#[implement(Service)]
+pub async fn creation(&self, room_id: &RoomId) -> Result<Creation> {
+	self.db
+		.roomid_creation
+		.get(room_id)
+		.await
+		.deserialized()
+}
+
+#[implement(Service)]
+pub fn creations(&self) -> impl Stream<Item = (&RoomId, Creation)> + Send + '_ {
+	self.db.roomid_creation.stream().ignore_err()
+}
+
+#[implement(Service)]
+#[inline]
+pub fn forget_creation(&self, room_id: &RoomId) { self.db.roomid_creation.remove(room_id); }
+
+fn creation_from<E: Event>(
+	create: &RoomCreateEvent<E>,
+	rules: &AuthorizationRules,
+	local: bool,
+	via: Option<&ServerName>,
+) -> Result<Creation> {
+	Ok(Creation {
+		creator: create.creator(rules)?.into_owned(),
+		created_at: create.origin_server_ts(),
+		local,
+		created_via: via.filter(|_| !local).map(ToOwned::to_owned),
+	})
+}
@@ -0,0 +1,50 @@
+use ruma::{
+	MilliSecondsSinceUnixEpoch, RoomVersionId, UInt, events::TimelineEventType,
+	owned_server_name, owned_user_id, server_name,
+};
+use serde_json::json;
+use tuwunel_core::matrix::{PduEvent, room_version, state_res::events::RoomCreateEvent};
+
+use super::{Creation, creation_from};
+
+const CREATED_AT: u32 = 1_700_000_000;
+
+fn create_event(sender: &str, content: serde_json::Value) -> RoomCreateEvent<PduEvent> {
+	RoomCreateEvent::new(PduEvent {
+		origin_server_ts: UInt::from(CREATED_AT),
+		state_key: Some("".into()),
+		..PduEvent::fake("$create:example.com", sender, TimelineEventType::RoomCreate, &content)
+	})
+}
+
+#[test]
+fn locally_created_room() {
+	let create = create_event("@alice:example.com", json!({ "room_version": "11" }));
+	let rules = room_version::rules(&RoomVersionId::V11).unwrap();
+
+	let creation = creation_from(&create, &rules.authorization, true, None).unwrap();
+	assert_eq!(creation, Creation {
+		creator: owned_user_id!("@alice:example.com"),
+		created_at: MilliSecondsSinceUnixEpoch(UInt::from(CREATED_AT)),
+		local: true,
+		created_via: None,
+	});
+}
+
+#[test]
+fn remotely_joined_room() {
+	let create = create_event(
+		"@bob:remote.example",
+		json!({ "creator": "@bob:remote.example", "room_version": "6" }),
+	);
+	let rules = room_version::rules(&RoomVersionId::V6).unwrap();
+	let via = server_name!("via.example");
+
+	let creation = creation_from(&create, &rules.authorization, false, Some(via)).unwrap();
+	assert_eq!(creation, Creation {
+		creator: owned_user_id!("@bob:remote.example"),
+		created_at: MilliSecondsSinceUnixEpoch(UInt::from(CREATED_AT)),
+		local: false,
+		created_via: Some(owned_server_name!("via.example")),
+	});
+}
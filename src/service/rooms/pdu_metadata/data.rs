@@ -1,7 +1,8 @@
 use std::{mem::size_of, sync::Arc};
 
 use futures::{Stream, StreamExt};
-use ruma::{EventId, RoomId, UserId, api::Direction};
+use ruma::{EventId, OwnedUserId, RoomId, UserId, api::Direction};
+use serde::{Deserialize, Serialize};
 use tuwunel_core::{
 	Result,
 	arrayvec::ArrayVec,
@@ -14,7 +15,7 @@
 		u64_from_u8,
 	},
 };
-use tuwunel_database::{Interfix, Map};
+use tuwunel_database::{Interfix, Json, Map};
 
 use crate::rooms::{
 	short::{ShortEventId, ShortRoomId},
@@ -25,9 +26,20 @@ pub(super) struct Data {
 	tofrom_relation: Arc<Map>,
 	referencedevents: Arc<Map>,
 	softfailedeventids: Arc<Map>,
+	roomid_softfailedeventids: Arc<Map>,
 	services: Arc<crate::services::OnceServices>,
 }
 
+/// Why and by whom an event was soft-failed, keyed by the room, the time it
+/// was soft-failed and the event.
+#[derive(Deserialize, Serialize)]
+pub(super) struct SoftFailure {
+	pub(super) sender: OwnedUserId,
+	pub(super) reason: String,
+}
+
+pub(super) type SoftFailedKey<'a> = (&'a RoomId, u64, &'a EventId);
+
 impl Data {
 	pub(super) fn new(args: &crate::Args<'_>) -> Self {
 		let db = &args.db;
@@ -35,6 +47,7 @@ pub(super) fn new(args: &crate::Args<'_>) -> Self {
 			tofrom_relation: db["tofrom_relation"].clone(),
 			referencedevents: db["referencedevents"].clone(),
 			softfailedeventids: db["softfailedeventids"].clone(),
+			roomid_softfailedeventids: db["roomid_softfailedeventids"].clone(),
 			services: args.services.clone(),
 		}
 	}
@@ -116,9 +129,33 @@ pub(super) async fn is_event_referenced(&self, room_id: &RoomId, event_id: &Even
 		self.referencedevents.qry(&key).await.is_ok()
 	}
 
-	#[inline]
-	pub(super) fn mark_event_soft_failed(&self, event_id: &EventId) {
+	pub(super) fn mark_event_soft_failed(
+		&self,
+		key: SoftFailedKey<'_>,
+		sender: &UserId,
+		reason: &str,
+	) {
+		let (_, _, event_id) = key;
 		self.softfailedeventids.insert(event_id, []);
+		self.roomid_softfailedeventids.put(
+			key,
+			Json(SoftFailure {
+				sender: sender.to_owned(),
+				reason: reason.to_owned(),
+			}),
+		);
+	}
+
+	pub(super) fn soft_failed_events<'a>(
+		&'a self,
+		room_id: &'a RoomId,
+	) -> impl Stream<Item = (SoftFailedKey<'a>, SoftFailure)> + Send + 'a {
+		self.roomid_softfailedeventids
+			.rev_stream_from(&(room_id, u64::MAX))
+			.ignore_err()
+			.ready_take_while(move |((room, ..), _): &(SoftFailedKey<'_>, SoftFailure)| {
+				*room == room_id
+			})
 	}
 
 	#[inline]
@@ -130,18 +167,40 @@ pub(super) async fn is_event_soft_failed(&self, event_id: &EventId) -> bool {
 	}
 
 	#[inline]
-	pub(super) async fn delete_all_referenced_for_room(&self, room_id: &RoomId) -> Result {
-		let prefix = (room_id, Interfix);
+	pub(super) fn delete_referenced(&self, room_id: &RoomId, event_id: &EventId) {
+		let key = (room_id, event_id);
+		self.referencedevents.del(key);
+	}
+
+	pub(super) async fn delete_relations(&self, count: u64, target: Option<u64>) {
+		const BUFSIZE: usize = size_of::<u64>() * 2;
 
-		self.referencedevents
-			.keys_prefix_raw(&prefix)
+		if let Some(to) = target {
+			let key: &[u64] = &[to, count];
+			self.tofrom_relation.adel::<BUFSIZE, _>(key);
+		}
+
+		self.tofrom_relation
+			.keys_prefix_raw(&count)
 			.ignore_err()
 			.ready_for_each(|key| {
 				trace!("Removing key: {key:?}");
-				self.referencedevents.remove(key);
+				self.tofrom_relation.remove(key);
 			})
 			.await;
+	}
+
+	#[inline]
+	pub(super) async fn delete_all_referenced_for_room(&self, room_id: &RoomId) -> Result<usize> {
+		let prefix = (room_id, Interfix);
+
+		let soft_failed = self
+			.roomid_softfailedeventids
+			.del_prefix(&prefix)
+			.await?;
+
+		let referenced = self.referencedevents.del_prefix(&prefix).await?;
 
-		Ok(())
+		Ok(referenced.saturating_add(soft_failed))
 	}
 }
@@ -1,20 +1,35 @@
 mod data;
+#[cfg(test)]
+mod tests;
+
 use std::sync::Arc;
 
-use futures::{StreamExt, future::try_join};
-use ruma::{EventId, RoomId, UserId, api::Direction};
+use futures::{Stream, StreamExt, future::try_join};
+use ruma::{EventId, OwnedEventId, OwnedUserId, RoomId, UserId, api::Direction};
 use tuwunel_core::{
 	Result,
 	matrix::{Event, PduCount},
+	utils::millis_since_unix_epoch,
 };
 
-use self::data::Data;
+use self::data::{Data, SoftFailure};
 
 pub struct Service {
 	services: Arc<crate::services::OnceServices>,
 	db: Data,
 }
 
+/// An event soft-failed in a room, with the sender and why.
+#[derive(Debug)]
+pub struct SoftFailedEvent {
+	pub event_id: OwnedEventId,
+	pub sender: OwnedUserId,
+	pub reason: String,
+
+	/// When it was soft-failed, in milliseconds since the epoch.
+	pub soft_failed_at: u64,
+}
+
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
@@ -117,9 +132,28 @@ pub async fn is_event_referenced(&self, room_id: &RoomId, event_id: &EventId) ->
 			.await
 	}
 
-	#[tracing::instrument(skip(self), level = "debug")]
-	pub fn mark_event_soft_failed(&self, event_id: &EventId) {
-		self.db.mark_event_soft_failed(event_id);
+	/// Marks the event soft-failed, recording the sender and the reason so
+	/// room moderators can review it.
+	#[tracing::instrument(skip_all, fields(event_id = %event.event_id()), level = "debug")]
+	pub fn mark_event_soft_failed<E: Event>(&self, event: &E, reason: &str) {
+		let key = (event.room_id(), millis_since_unix_epoch(), event.event_id());
+		self.db
+			.mark_event_soft_failed(key, event.sender(), reason);
+	}
+
+	/// The events soft-failed in the room, most recent first.
+	pub fn soft_failed_events<'a>(
+		&'a self,
+		room_id: &'a RoomId,
+	) -> impl Stream<Item = SoftFailedEvent> + Send + 'a {
+		self.db.soft_failed_events(room_id).map(
+			|((_, soft_failed_at, event_id), SoftFailure { sender, reason })| SoftFailedEvent {
+				event_id: event_id.to_owned(),
+				sender,
+				reason,
+				soft_failed_at,
+			},
+		)
 	}
 
 	#[tracing::instrument(skip(self), level = "debug")]
@@ -128,9 +162,30 @@ pub async fn is_event_soft_failed(&self, event_id: &EventId) -> bool {
 	}
 
 	#[tracing::instrument(skip(self), level = "debug")]
-	pub async fn delete_all_referenced_for_room(&self, room_id: &RoomId) -> Result {
+	pub async fn delete_all_referenced_for_room(&self, room_id: &RoomId) -> Result<usize> {
 		self.db
 			.delete_all_referenced_for_room(room_id)
 			.await
 	}
+
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub fn delete_referenced(&self, room_id: &RoomId, event_id: &EventId) {
+		self.db.delete_referenced(room_id, event_id);
+	}
+
+	/// Removes the relations of a deleted event: the one to its target and
+	/// those of any events relating to it.
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub async fn delete_relations(&self, count: PduCount, target: Option<PduCount>) {
+		let PduCount::Normal(count) = count else {
+			return;
+		};
+
+		let target = match target {
+			| Some(PduCount::Normal(target)) => Some(target),
+			| _ => None,
+		};
+
+		self.db.delete_relations(count, target).await;
+	}
 }
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use ruma::{OwnedEventId, RoomId, UserId, events::room::message::RoomMessageEventContent};
+use tuwunel_core::{Event, matrix::pdu::PduBuilder};
+
+use crate::fixture::Fixture;
+
+/// Stores a message by the sender as an outlier and soft-fails it, as the
+/// event handler does with an event failing the checks against the current
+/// state.
+async fn soft_fail(
+	fixture: &Fixture,
+	sender: &UserId,
+	room_id: &RoomId,
+	body: &str,
+) -> OwnedEventId {
+	let state_lock = fixture.state.mutex.lock(room_id).await;
+	let (pdu, json) = fixture
+		.timeline
+		.create_hash_and_sign_event(
+			PduBuilder::timeline(&RoomMessageEventContent::text_plain(body)),
+			sender,
+			room_id,
+			&state_lock,
+		)
+		.await
+		.expect("event created");
+
+	fixture
+		.timeline
+		.add_pdu_outlier(pdu.event_id(), &json);
+	fixture
+		.pdu_metadata
+		.mark_event_soft_failed(&pdu, "Banned");
+
+	// Soft failures are ordered by the millisecond they were recorded at.
+	tokio::time::sleep(Duration::from_millis(2)).await;
+
+	pdu.event_id().to_owned()
+}
+
+#[tokio::test]
+async fn soft_failed_listed_newest_first() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	let other_room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("other room");
+
+	let first = soft_fail(&fixture, &alice, &room_id, "first").await;
+	let second = soft_fail(&fixture, &alice, &room_id, "second").await;
+	let other = soft_fail(&fixture, &alice, &other_room_id, "other").await;
+
+	let listed: Vec<_> = fixture
+		.pdu_metadata
+		.soft_failed_events(&room_id)
+		.collect()
+		.await;
+
+	let event_ids: Vec<_> = listed
+		.iter()
+		.map(|event| event.event_id.clone())
+		.collect();
+	assert_eq!(event_ids, [second, first.clone()], "only the room's events, newest first");
+	assert!(
+		listed
+			.iter()
+			.all(|event| event.sender == alice && event.reason == "Banned"),
+		"sender and reason are recorded"
+	);
+
+	let newest: Vec<_> = fixture
+		.pdu_metadata
+		.soft_failed_events(&other_room_id)
+		.map(|event| event.event_id)
+		.collect()
+		.await;
+	assert_eq!(newest, [other], "the last room's events are found too");
+
+	assert!(
+		fixture
+			.pdu_metadata
+			.is_event_soft_failed(&first)
+			.await,
+		"marked soft-failed"
+	);
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn soft_failed_event_redacted() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	embedded
+		.join_room(&bob, &room_id)
+		.await
+		.expect("bob joins");
+
+	let event_id = soft_fail(&fixture, &bob, &room_id, "evading a ban").await;
+
+	let state_lock = fixture.state.mutex.lock(&room_id).await;
+	fixture
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::redaction(&event_id, Some("Ban evasion".to_owned())),
+			&alice,
+			&room_id,
+			&state_lock,
+		)
+		.await
+		.expect("redaction sent");
+	drop(state_lock);
+
+	let pdu = fixture
+		.timeline
+		.get_outlier_pdu(&event_id)
+		.await
+		.expect("outlier kept");
+	assert!(pdu.is_redacted(), "the soft-failed event is redacted");
+	assert!(pdu.get_content_as_value().get("body").is_none(), "the body is removed");
+
+	fixture.stop().await;
+}
@@ -2,17 +2,20 @@
 
 use futures::{Stream, StreamExt};
 use ruma::{
-	CanonicalJsonObject, RoomId, UserId,
+	CanonicalJsonObject, EventId, OwnedEventId, OwnedUserId, RoomId, UserId,
 	events::{AnySyncEphemeralRoomEvent, receipt::ReceiptEvent},
 	serde::Raw,
 };
+use serde::{Deserialize, Serialize};
 use tuwunel_core::{
-	Result, trace,
+	Result,
 	utils::{ReadyExt, stream::TryIgnore},
 };
-use tuwunel_database::{Deserialized, Interfix, Json, Map};
+use tuwunel_database::{Deserialized, Ignore, Interfix, Json, Map};
 
 pub(super) struct Data {
+	roomeventuserid_pendingreceipt: Arc<Map>,
+	roomuserid_pendingreceipt: Arc<Map>,
 	roomuserid_privateread: Arc<Map>,
 	roomuserid_lastprivatereadupdate: Arc<Map>,
 	services: Arc<crate::services::OnceServices>,
@@ -21,10 +24,20 @@ pub(super) struct Data {
 
 pub(super) type ReceiptItem<'a> = (&'a UserId, u64, Raw<AnySyncEphemeralRoomEvent>);
 
+/// A read receipt for an event which had not arrived when it was sent; a user
+/// has at most one per room, the latest.
+#[derive(Debug, Deserialize, Serialize)]
+pub(super) struct PendingReceipt {
+	pub(super) event_id: OwnedEventId,
+	pub(super) private: bool,
+}
+
 impl Data {
 	pub(super) fn new(args: &crate::Args<'_>) -> Self {
 		let db = &args.db;
 		Self {
+			roomeventuserid_pendingreceipt: db["roomeventuserid_pendingreceipt"].clone(),
+			roomuserid_pendingreceipt: db["roomuserid_pendingreceipt"].clone(),
 			roomuserid_privateread: db["roomuserid_privateread"].clone(),
 			roomuserid_lastprivatereadupdate: db["roomuserid_lastprivatereadupdate"].clone(),
 			readreceiptid_readreceipt: db["readreceiptid_readreceipt"].clone(),
@@ -121,37 +134,92 @@ pub(super) async fn last_privateread_update(
 			.unwrap_or(0)
 	}
 
-	#[inline]
-	pub(super) async fn delete_all_read_receipts(&self, room_id: &RoomId) -> Result {
-		let prefix = (room_id, Interfix);
+	/// Replaces the pending receipt of the user in the room; a receipt for
+	/// the same event stays private if either is.
+	pub(super) async fn pending_receipt_add(
+		&self,
+		room_id: &RoomId,
+		user_id: &UserId,
+		event_id: &EventId,
+		private: bool,
+	) {
+		let private = match self.pending_receipt(room_id, user_id).await {
+			| Some(previous) if previous.event_id == event_id => private || previous.private,
+			| Some(previous) => {
+				self.roomeventuserid_pendingreceipt
+					.del((room_id, &previous.event_id, user_id));
+
+				private
+			},
+			| None => private,
+		};
+
+		let receipt = PendingReceipt { event_id: event_id.to_owned(), private };
+		self.roomuserid_pendingreceipt
+			.put((room_id, user_id), Json(receipt));
+		self.roomeventuserid_pendingreceipt
+			.put_raw((room_id, event_id, user_id), []);
+	}
 
-		self.roomuserid_privateread
-			.keys_prefix_raw(&prefix)
-			.ignore_err()
-			.ready_for_each(|key| {
-				trace!("Removing key: {key:?}");
-				self.roomuserid_privateread.remove(key);
-			})
-			.await;
+	pub(super) async fn pending_receipt(
+		&self,
+		room_id: &RoomId,
+		user_id: &UserId,
+	) -> Option<PendingReceipt> {
+		self.roomuserid_pendingreceipt
+			.qry(&(room_id, user_id))
+			.await
+			.deserialized()
+			.ok()
+	}
 
-		self.roomuserid_lastprivatereadupdate
-			.keys_prefix_raw(&prefix)
+	/// Removes and returns the receipts in the room waiting for the event.
+	pub(super) async fn pending_receipts_take(
+		&self,
+		room_id: &RoomId,
+		event_id: &EventId,
+	) -> Vec<(OwnedUserId, bool)> {
+		let prefix = (room_id, event_id, Interfix);
+		let user_ids: Vec<OwnedUserId> = self
+			.roomeventuserid_pendingreceipt
+			.keys_prefix(&prefix)
 			.ignore_err()
-			.ready_for_each(|key| {
-				trace!("Removing key: {key:?}");
-				self.roomuserid_lastprivatereadupdate.remove(key);
-			})
+			.map(|(_, _, user_id): (Ignore, Ignore, &UserId)| user_id.to_owned())
+			.collect()
 			.await;
 
-		self.readreceiptid_readreceipt
-			.keys_prefix_raw(&prefix)
-			.ignore_err()
-			.ready_for_each(|key| {
-				trace!("Removing key: {key:?}");
-				self.readreceiptid_readreceipt.remove(key);
-			})
-			.await;
+		let mut pending = Vec::with_capacity(user_ids.len());
+		for user_id in user_ids {
+			let private = self
+				.pending_receipt(room_id, &user_id)
+				.await
+				.is_some_and(|receipt| receipt.private);
+
+			self.roomuserid_pendingreceipt
+				.del((room_id, &user_id));
+			self.roomeventuserid_pendingreceipt
+				.del((room_id, event_id, &user_id));
+
+			pending.push((user_id, private));
+		}
+
+		pending
+	}
+
+	pub(super) async fn delete_all_read_receipts(&self, room_id: &RoomId) -> Result<usize> {
+		let prefix = (room_id, Interfix);
+		let mut deleted: usize = 0;
+
+		for map in [
+			&self.roomuserid_privateread,
+			&self.roomuserid_lastprivatereadupdate,
+			&self.readreceiptid_readreceipt,
+			&self.roomuserid_pendingreceipt,
+			&self.roomeventuserid_pendingreceipt,
+		] {
+			deleted = deleted.saturating_add(map.del_prefix(&prefix).await?);
+		}
 
-		Ok(())
+		Ok(deleted)
 	}
 }
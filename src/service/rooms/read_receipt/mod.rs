@@ -1,10 +1,12 @@
 mod data;
+#[cfg(test)]
+mod tests;
 
 use std::{collections::BTreeMap, sync::Arc};
 
 use futures::{Stream, TryFutureExt, try_join};
 use ruma::{
-	OwnedEventId, OwnedUserId, RoomId, UserId,
+	EventId, OwnedEventId, OwnedUserId, RoomId, UserId,
 	events::{
 		AnySyncEphemeralRoomEvent, SyncEphemeralRoomEvent,
 		receipt::{ReceiptEvent, ReceiptEventContent, Receipts},
@@ -146,7 +148,60 @@ pub async fn last_privateread_update(&self, user_id: &UserId, room_id: &RoomId)
 			.await
 	}
 
-	pub async fn delete_all_read_receipts(&self, room_id: &RoomId) -> Result {
+	/// Remembers a read receipt for an event we don't have yet, so it takes
+	/// effect once the event arrives. Only the latest is kept per user per
+	/// room.
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub async fn pending_receipt_add(
+		&self,
+		room_id: &RoomId,
+		user_id: &UserId,
+		event_id: &EventId,
+		private: bool,
+	) {
+		self.db
+			.pending_receipt_add(room_id, user_id, event_id, private)
+			.await;
+	}
+
+	/// Applies the receipts which were waiting for an event now stored at
+	/// `count`. Receipts for backfilled events are discarded since those are
+	/// older than anything the user could have unread.
+	#[tracing::instrument(skip(self), level = "debug")]
+	pub async fn pending_receipts_resolve(
+		&self,
+		room_id: &RoomId,
+		event_id: &EventId,
+		count: PduCount,
+	) {
+		let pending = self
+			.db
+			.pending_receipts_take(room_id, event_id)
+			.await;
+
+		let PduCount::Normal(count) = count else {
+			return;
+		};
+
+		for (user_id, private) in pending {
+			if private
+				&& self
+					.private_read_get_count(room_id, &user_id)
+					.await
+					.ok()
+					.is_none_or(|current| current < count)
+			{
+				self.private_read_set(room_id, &user_id, count);
+			}
+
+			self.services
+				.user
+				.reset_all_notification_counts(&user_id, room_id)
+				.await;
+		}
+	}
+
+	pub async fn delete_all_read_receipts(&self, room_id: &RoomId) -> Result<usize> {
 		self.db.delete_all_read_receipts(room_id).await
 	}
 }
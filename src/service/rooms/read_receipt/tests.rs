@@ -0,0 +1,212 @@
+use std::iter::once;
+
+use futures::FutureExt;
+use ruma::{
+	CanonicalJsonObject, OwnedRoomId, OwnedUserId, RoomId, UserId, event_id,
+	events::room::message::RoomMessageEventContent,
+};
+use serde_json::value::to_raw_value;
+use tuwunel_core::{
+	PduCount,
+	matrix::pdu::{PduBuilder, PduEvent},
+};
+
+use crate::fixture::Fixture;
+
+/// Creates a message without appending it, so its ID is known before it
+/// arrives.
+async fn prepare(
+	services: &Fixture,
+	sender: &UserId,
+	room_id: &RoomId,
+) -> (PduEvent, CanonicalJsonObject) {
+	let content = RoomMessageEventContent::text_plain("hello");
+	let state_lock = services.state.mutex.lock(room_id).await;
+
+	services
+		.timeline
+		.create_hash_and_sign_event(PduBuilder::timeline(&content), sender, room_id, &state_lock)
+		.await
+		.expect("event created")
+}
+
+/// Alice's room, joined by Bob who has notifications from her messages.
+async fn notified_room(services: &Fixture) -> (OwnedUserId, OwnedUserId, OwnedRoomId) {
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("user created");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("user created");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+	embedded
+		.join_room(&bob, &room_id)
+		.await
+		.expect("bob joins");
+
+	for _ in 0..2 {
+		embedded
+			.send_message(&alice, &room_id, "ping")
+			.await
+			.expect("message sent");
+	}
+
+	assert_eq!(
+		services
+			.user
+			.notification_count(&bob, &room_id)
+			.await,
+		2,
+		"bob was notified of the messages"
+	);
+
+	(alice, bob, room_id)
+}
+
+#[tokio::test]
+async fn pending_receipts_applied_on_arrival() {
+	let services = Fixture::start().await;
+	let (alice, bob, room_id) = notified_room(&services).await;
+
+	let read_receipt = &services.read_receipt;
+	let unknown = event_id!("$unknown:fixture.localhost");
+	let (pdu, pdu_json) = prepare(&services, &alice, &room_id).await;
+	let later = &pdu.event_id;
+
+	read_receipt
+		.pending_receipt_add(&room_id, &bob, unknown, false)
+		.await;
+	read_receipt
+		.pending_receipt_add(&room_id, &bob, later, true)
+		.await;
+
+	let pending = read_receipt
+		.db
+		.pending_receipt(&room_id, &bob)
+		.await
+		.expect("receipt pending");
+	assert_eq!(&pending.event_id, later, "the latest receipt replaces the earlier one");
+	assert!(pending.private, "the latest receipt is private");
+
+	let taken = read_receipt
+		.db
+		.pending_receipts_take(&room_id, unknown)
+		.await;
+	assert!(taken.is_empty(), "the replaced receipt no longer waits for its event");
+
+	// The event arrives
+	let state_lock = services.state.mutex.lock(&room_id).await;
+	let statehashid = services
+		.state
+		.append_to_state(&pdu)
+		.await
+		.expect("state appended");
+	services
+		.timeline
+		.append_pdu(&pdu, pdu_json, once(&**later), &state_lock)
+		.boxed()
+		.await
+		.expect("event appended");
+	services
+		.state
+		.set_room_state(&room_id, statehashid, &state_lock);
+	drop(state_lock);
+
+	let PduCount::Normal(count) = services
+		.timeline
+		.get_pdu_count(later)
+		.await
+		.expect("event stored")
+	else {
+		panic!("event appended to the timeline");
+	};
+
+	let private_read = read_receipt
+		.private_read_get_count(&room_id, &bob)
+		.await
+		.expect("private read marker set");
+	assert_eq!(
+		private_read, count,
+		"the private read marker moves to the event once it arrives"
+	);
+	assert_eq!(
+		services
+			.user
+			.notification_count(&bob, &room_id)
+			.await,
+		0,
+		"the notifications up to the event are read"
+	);
+
+	let pending = read_receipt
+		.db
+		.pending_receipt(&room_id, &bob)
+		.await;
+	assert!(pending.is_none(), "the receipt is removed once delivered");
+
+	let taken = read_receipt
+		.db
+		.pending_receipts_take(&room_id, later)
+		.await;
+	assert!(taken.is_empty(), "nothing waits for the event after it arrived");
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn pending_receipts_discarded_on_backfill() {
+	let services = Fixture::start().await;
+	let (alice, bob, room_id) = notified_room(&services).await;
+
+	let read_receipt = &services.read_receipt;
+	let (pdu, pdu_json) = prepare(&services, &alice, &room_id).await;
+	read_receipt
+		.pending_receipt_add(&room_id, &bob, &pdu.event_id, true)
+		.await;
+
+	// The event arrives through backfill
+	let origin = services.globals.server_name();
+	let raw = to_raw_value(&pdu_json).expect("event serialized");
+	services
+		.timeline
+		.backfill_pdu(&room_id, origin, raw)
+		.boxed()
+		.await
+		.expect("event backfilled");
+
+	let count = services
+		.timeline
+		.get_pdu_count(&pdu.event_id)
+		.await
+		.expect("event stored");
+	assert!(matches!(count, PduCount::Backfilled(_)), "{count:?}");
+
+	let pending = read_receipt
+		.db
+		.pending_receipt(&room_id, &bob)
+		.await;
+	assert!(pending.is_none(), "the receipt is discarded");
+	assert!(
+		read_receipt
+			.private_read_get_count(&room_id, &bob)
+			.await
+			.is_err(),
+		"no read marker at a backfilled event"
+	);
+	assert_eq!(
+		services
+			.user
+			.notification_count(&bob, &room_id)
+			.await,
+		2,
+		"the notifications stay unread"
+	);
+
+	services.stop().await;
+}
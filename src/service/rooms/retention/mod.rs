@@ -0,0 +1,214 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+	sync::{Arc, Mutex},
+	time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use ruma::{RoomId, events::StateEventType};
+use serde::Deserialize;
+use tokio::time::sleep;
+use tuwunel_core::{
+	Result, debug, debug_info, implement,
+	matrix::pdu::{PduId, RawPduId},
+	utils::{
+		millis_since_unix_epoch,
+		stream::{ReadyExt, TryIgnore},
+	},
+	warn,
+};
+
+/// Number of events deleted under one lock of the room.
+const PURGE_BATCH_SIZE: usize = 256;
+
+pub struct Service {
+	services: Arc<crate::services::OnceServices>,
+	last_run: Mutex<Option<Summary>>,
+}
+
+/// Effective retention of a room.
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+	pub max_lifetime: Duration,
+	pub min_lifetime: Duration,
+}
+
+/// Outcome of the last scan of all rooms.
+#[derive(Clone, Debug)]
+pub struct Summary {
+	pub finished: SystemTime,
+	pub rooms: usize,
+	pub purged: usize,
+}
+
+/// Content of the `m.room.retention` state event (MSC1763).
+#[derive(Deserialize)]
+struct RetentionEventContent {
+	max_lifetime: Option<u64>,
+	min_lifetime: Option<u64>,
+}
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			last_run: Mutex::default(),
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		let config = &self.services.server.config.retention;
+		if !config.enabled || self.services.db.is_read_only() {
+			return Ok(());
+		}
+
+		let interval = Duration::from_secs(config.purge_interval);
+		while self.services.server.running() {
+			tokio::select! {
+				() = sleep(interval) => self.purge_all().await,
+				() = self.services.server.until_shutdown() => break,
+			}
+		}
+
+		Ok(())
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Purges expired events from every room with a retention policy.
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn purge_all(&self) {
+	let (mut rooms, mut purged) = (0_usize, 0_usize);
+	let mut room_ids = self.services.metadata.iter_ids().boxed();
+
+	while let Some(room_id) = room_ids.next().await {
+		if !self.services.server.running() {
+			break;
+		}
+
+		match self.purge_room(room_id).await {
+			| Ok(0) => {},
+			| Ok(count) => {
+				rooms = rooms.saturating_add(1);
+				purged = purged.saturating_add(count);
+			},
+			| Err(e) => warn!(%room_id, "Failed to purge expired events: {e}"),
+		}
+	}
+
+	debug_info!(rooms, purged, "Purged expired events");
+	*self.last_run.lock().expect("locked") = Some(Summary {
+		finished: SystemTime::now(),
+		rooms,
+		purged,
+	});
+}
+
+/// Deletes timeline events of the room older than its retention policy
+/// allows; returns the number of events deleted. The whole timeline is
+/// scanned, as timestamps are set by the senders and need not be in order.
+/// The room is locked only while a batch is deleted, so the most recent event
+/// cannot change under the batch while the room stays usable during the scan.
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn purge_room(&self, room_id: &RoomId) -> Result<usize> {
+	let Some(policy) = self.room_policy(room_id).await else {
+		return Ok(0);
+	};
+
+	let lifetime = policy.max_lifetime.max(policy.min_lifetime);
+	let lifetime = u64::try_from(lifetime.as_millis()).unwrap_or(u64::MAX);
+	let cutoff = millis_since_unix_epoch().saturating_sub(lifetime);
+
+	let shortroomid = self
+		.services
+		.short
+		.get_shortroomid(room_id)
+		.await?;
+
+	let mut expired = self
+		.services
+		.timeline
+		.pdus(None, room_id, None)
+		.ignore_err()
+		.ready_filter(|(_, pdu)| {
+			pdu.state_key.is_none() && u64::from(pdu.origin_server_ts) < cutoff
+		})
+		.ready_chunks(PURGE_BATCH_SIZE)
+		.boxed();
+
+	let mut purged = 0_usize;
+	while let Some(batch) = expired.next().await {
+		let _state_lock = self.services.state.mutex.lock(room_id).await;
+		let latest = self
+			.services
+			.timeline
+			.last_timeline_count(None, room_id, None)
+			.await?;
+
+		// Relations may target events of the same batch, so their targets are
+		// resolved before any is deleted.
+		let mut targets = Vec::with_capacity(batch.len());
+		for (_, pdu) in &batch {
+			targets.push(self.services.timeline.relation_target(pdu).await);
+		}
+
+		for ((count, pdu), target) in batch.iter().zip(targets) {
+			if *count == latest {
+				continue;
+			}
+
+			let pdu_id: RawPduId = PduId { shortroomid, shorteventid: *count }.into();
+			self.services
+				.timeline
+				.delete_pdu(&pdu_id, pdu, target)
+				.await;
+
+			purged = purged.saturating_add(1);
+		}
+	}
+
+	debug!(%room_id, purged, ?policy, "Purged expired events");
+
+	Ok(purged)
+}
+
+/// The retention of a room from its `m.room.retention` state event or the
+/// configured default; `None` when its events are kept forever.
+#[implement(Service)]
+pub async fn room_policy(&self, room_id: &RoomId) -> Option<Policy> {
+	let config = &self.services.config.retention;
+	let content: Option<RetentionEventContent> = self
+		.services
+		.state_accessor
+		.room_state_get_content(room_id, &StateEventType::from("m.room.retention"), "")
+		.await
+		.ok();
+
+	let max_lifetime = content
+		.as_ref()
+		.and_then(|content| content.max_lifetime)
+		.map(Duration::from_millis)
+		.or_else(|| {
+			config
+				.default_max_lifetime
+				.map(Duration::from_secs)
+		})?;
+
+	let min_lifetime = content
+		.and_then(|content| content.min_lifetime)
+		.map(Duration::from_millis)
+		.unwrap_or_default()
+		.max(Duration::from_secs(config.min_lifetime));
+
+	Some(Policy { max_lifetime, min_lifetime })
+}
+
+#[implement(Service)]
+pub fn last_run(&self) -> Option<Summary> { self.last_run.lock().expect("locked").clone() }
@@ -0,0 +1,161 @@
+use std::time::{Duration, SystemTime};
+
+use futures::FutureExt;
+use ruma::{
+	EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, UserId,
+	events::{
+		StateEventType,
+		room::{message::RoomMessageEventContent, topic::RoomTopicEventContent},
+	},
+};
+use tuwunel_core::matrix::{Event, pdu::PduBuilder};
+
+use crate::fixture::Fixture;
+
+const RETENTION: &str = r#"
+[global.retention]
+default_max_lifetime = 3600
+min_lifetime = 0
+"#;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// Appends the event as if sent at the time; `None` for now.
+async fn send_at(
+	fixture: &Fixture,
+	sender: &UserId,
+	room_id: &RoomId,
+	builder: PduBuilder,
+	time: Option<SystemTime>,
+) -> OwnedEventId {
+	let timestamp = time.map(|time| {
+		MilliSecondsSinceUnixEpoch::from_system_time(time).expect("time after the epoch")
+	});
+	let state_lock = fixture.state.mutex.lock(room_id).await;
+
+	fixture
+		.timeline
+		.build_and_append_pdu(PduBuilder { timestamp, ..builder }, sender, room_id, &state_lock)
+		.boxed()
+		.await
+		.expect("event appended")
+}
+
+async fn message_at(
+	fixture: &Fixture,
+	sender: &UserId,
+	room_id: &RoomId,
+	time: Option<SystemTime>,
+) -> OwnedEventId {
+	let content = RoomMessageEventContent::text_plain("hello");
+	send_at(fixture, sender, room_id, PduBuilder::timeline(&content), time).await
+}
+
+async fn in_timeline(fixture: &Fixture, event_id: &EventId) -> bool {
+	fixture
+		.timeline
+		.get_non_outlier_pdu(event_id)
+		.await
+		.is_ok()
+}
+
+#[tokio::test]
+async fn expired_events_purged() {
+	let fixture = Fixture::start_with(RETENTION).await;
+	let alice = fixture
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("alice");
+	let room_id = fixture
+		.embedded()
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	let now = SystemTime::now();
+	let old = now.checked_sub(HOUR.saturating_mul(2));
+	let future = now.checked_add(HOUR);
+
+	let expired = message_at(&fixture, &alice, &room_id, old).await;
+	let ahead = message_at(&fixture, &alice, &room_id, future).await;
+	let topic = RoomTopicEventContent::new("old topic".to_owned());
+	let old_state =
+		send_at(&fixture, &alice, &room_id, PduBuilder::state(String::new(), &topic), old).await;
+	let expired_after_future = message_at(&fixture, &alice, &room_id, old).await;
+	let recent = message_at(&fixture, &alice, &room_id, None).await;
+	let latest = message_at(&fixture, &alice, &room_id, old).await;
+
+	let purged = fixture
+		.retention
+		.purge_room(&room_id)
+		.await
+		.expect("purged");
+	assert_eq!(purged, 2, "only the expired messages are purged");
+
+	assert!(!in_timeline(&fixture, &expired).await, "expired message purged");
+	assert!(
+		!in_timeline(&fixture, &expired_after_future).await,
+		"an event from the future does not end the scan"
+	);
+	assert!(in_timeline(&fixture, &ahead).await, "event from the future kept");
+	assert!(in_timeline(&fixture, &recent).await, "recent message kept");
+	assert!(
+		in_timeline(&fixture, &latest).await,
+		"the latest event is kept even when expired"
+	);
+	assert!(in_timeline(&fixture, &old_state).await, "expired state event kept");
+
+	let create = fixture
+		.state_accessor
+		.room_state_get(&room_id, &StateEventType::RoomCreate, "")
+		.await
+		.expect("room create event in state");
+	assert!(in_timeline(&fixture, create.event_id()).await, "the create event is kept");
+
+	let purged = fixture
+		.retention
+		.purge_room(&room_id)
+		.await
+		.expect("purged again");
+	assert_eq!(purged, 0, "nothing left to purge");
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn rooms_without_policy_kept() {
+	let fixture = Fixture::start().await;
+	let alice = fixture
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("alice");
+	let room_id = fixture
+		.embedded()
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	let old = SystemTime::now().checked_sub(HOUR.saturating_mul(2));
+	let expired = message_at(&fixture, &alice, &room_id, old).await;
+	message_at(&fixture, &alice, &room_id, None).await;
+
+	assert!(
+		fixture
+			.retention
+			.room_policy(&room_id)
+			.await
+			.is_none(),
+		"no policy"
+	);
+	let purged = fixture
+		.retention
+		.purge_room(&room_id)
+		.await
+		.expect("purged");
+	assert_eq!(purged, 0, "events kept forever without a policy");
+	assert!(in_timeline(&fixture, &expired).await, "old message kept");
+
+	fixture.stop().await;
+}
@@ -1,23 +1,30 @@
-use std::sync::Arc;
+#[cfg(test)]
+mod tests;
+mod tokenizer;
 
-use futures::{Stream, StreamExt};
-use ruma::{RoomId, UserId, api::client::search::search_events::v3::Criteria};
+use std::{future::ready, sync::Arc};
+
+use futures::{Stream, StreamExt, TryStreamExt, pin_mut};
+use ruma::{
+	RoomId, UserId, api::client::search::search_events::v3::Criteria, events::TimelineEventType,
+};
 use tuwunel_core::{
 	PduCount, Result,
 	arrayvec::ArrayVec,
-	implement,
+	config::SearchTokenizer,
+	debug, implement,
 	matrix::event::{Event, Matches},
-	trace,
 	utils::{
 		ArrayVecExt, IterStream, ReadyExt, set,
 		stream::{TryIgnore, WidebandExt},
 	},
 };
-use tuwunel_database::{Interfix, Map, keyval::Val};
+use tuwunel_database::{Map, keyval::Val};
 
+use self::tokenizer::{Usage, tokenize};
 use crate::rooms::{
 	short::ShortRoomId,
-	timeline::{PduId, RawPduId},
+	timeline::{ExtractBody, PduId, RawPduId},
 };
 
 pub struct Service {
@@ -44,6 +51,12 @@ pub struct RoomQuery<'a> {
 	size_of::<ShortRoomId>() + WORD_MAX_LEN + 1 + size_of::<RawPduId>();
 const WORD_MAX_LEN: usize = 50;
 
+/// Events indexed between yielding to other tasks during a rebuild.
+const REBUILD_BATCH_SIZE: usize = 1024;
+
+/// Key in the `global` map of the tokenizer the search index was built with.
+pub(crate) const SEARCH_TOKENIZER: &[u8] = b"search_tokenizer";
+
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
@@ -57,7 +70,9 @@ fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 
 #[implement(Service)]
 pub fn index_pdu(&self, shortroomid: ShortRoomId, pdu_id: &RawPduId, message_body: &str) {
-	let batch = tokenize(message_body)
+	let batch = self
+		.tokenize(message_body, Usage::Index)
+		.into_iter()
 		.map(|word| {
 			let mut key = shortroomid.to_be_bytes().to_vec();
 			key.extend_from_slice(word.as_bytes());
@@ -74,19 +89,25 @@ pub fn index_pdu(&self, shortroomid: ShortRoomId, pdu_id: &RawPduId, message_bod
 
 #[implement(Service)]
 pub fn deindex_pdu(&self, shortroomid: ShortRoomId, pdu_id: &RawPduId, message_body: &str) {
-	let batch = tokenize(message_body).map(|word| {
-		let mut key = shortroomid.to_be_bytes().to_vec();
-		key.extend_from_slice(word.as_bytes());
-		key.push(0xFF);
-		key.extend_from_slice(pdu_id.as_ref()); // TODO: currently we save the room id a second time here
-		key
-	});
+	let batch = self
+		.tokenize(message_body, Usage::Index)
+		.into_iter()
+		.map(|word| {
+			let mut key = shortroomid.to_be_bytes().to_vec();
+			key.extend_from_slice(word.as_bytes());
+			key.push(0xFF);
+			key.extend_from_slice(pdu_id.as_ref()); // TODO: currently we save the room id a second time here
+			key
+		});
 
 	for token in batch {
 		self.db.tokenids.remove(&token);
 	}
 }
 
+/// The events of the room matching the query which the user may see, with
+/// how many there are. Events the user could not see when they were sent are
+/// dropped before counting and paginating.
 #[implement(Service)]
 pub async fn search_pdus<'a>(
 	&'a self,
@@ -95,8 +116,7 @@ pub async fn search_pdus<'a>(
 	let pdu_ids: Vec<_> = self.search_pdu_ids(query).await?.collect().await;
 
 	let filter = &query.criteria.filter;
-	let count = pdu_ids.len();
-	let pdus = pdu_ids
+	let pdus: Vec<_> = pdu_ids
 		.into_iter()
 		.stream()
 		.wide_filter_map(async |result_pdu_id: RawPduId| {
@@ -108,15 +128,24 @@ pub async fn search_pdus<'a>(
 		})
 		.ready_filter(|pdu| !pdu.is_redacted())
 		.ready_filter(move |pdu| filter.matches(pdu))
-		.wide_filter_map(async |pdu| {
+		.collect()
+		.await;
+
+	let pdus = match query.user_id {
+		| Some(user_id) =>
 			self.services
 				.state_accessor
-				.user_can_see_event(query.user_id?, pdu.room_id(), pdu.event_id())
-				.await
-				.then_some(pdu)
-		})
+				.user_can_see_events(user_id, query.room_id, pdus)
+				.await,
+		| None => Vec::new(),
+	};
+
+	let count = pdus.len();
+	let pdus = pdus
+		.into_iter()
 		.skip(query.skip)
-		.take(query.limit);
+		.take(query.limit)
+		.stream();
 
 	Ok((count, pdus))
 }
@@ -149,7 +178,8 @@ async fn search_pdu_ids_query_room(
 	query: &RoomQuery<'_>,
 	shortroomid: ShortRoomId,
 ) -> Vec<Vec<RawPduId>> {
-	tokenize(&query.criteria.search_term)
+	self.tokenize(&query.criteria.search_term, Usage::Query)
+		.into_iter()
 		.stream()
 		.wide_then(async |word| {
 			self.search_pdu_ids_query_words(shortroomid, &word)
@@ -198,32 +228,74 @@ fn search_pdu_ids_query_word(
 		.ready_take_while(move |key| key.starts_with(&prefix))
 }
 
+/// Replaces the index of the room with one made from its messages by the
+/// tokenizer configured; returns the number of messages indexed.
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn rebuild_index(&self, room_id: &RoomId) -> Result<usize> {
+	let shortroomid = self
+		.services
+		.short
+		.get_shortroomid(room_id)
+		.await?;
+
+	let deleted = self.db.tokenids.del_prefix(&shortroomid).await?;
+
+	debug!(deleted, "Deleted search index of the room");
+
+	let pdus = self
+		.services
+		.timeline
+		.pdus(None, room_id, None)
+		.try_filter(|(_, pdu)| ready(*pdu.kind() == TimelineEventType::RoomMessage));
+
+	pin_mut!(pdus);
+	let mut indexed = 0_usize;
+	while let Some((count, pdu)) = pdus.try_next().await? {
+		let Some(body) = pdu
+			.get_content::<ExtractBody>()
+			.ok()
+			.and_then(|content| content.body)
+		else {
+			continue;
+		};
+
+		let pdu_id: RawPduId = PduId { shortroomid, shorteventid: count }.into();
+		self.index_pdu(shortroomid, &pdu_id, &body);
+
+		indexed = indexed.saturating_add(1);
+		if indexed.is_multiple_of(REBUILD_BATCH_SIZE) {
+			tokio::task::yield_now().await;
+		}
+	}
+
+	Ok(indexed)
+}
+
 #[implement(Service)]
-pub async fn delete_all_search_tokenids_for_room(&self, room_id: &RoomId) -> Result {
-	let prefix = (room_id, Interfix);
+pub async fn delete_all_search_tokenids_for_room(&self, room_id: &RoomId) -> Result<usize> {
+	let Ok(shortroomid) = self.services.short.get_shortroomid(room_id).await else {
+		return Ok(0);
+	};
 
-	self.db
-		.tokenids
-		.keys_prefix_raw(&prefix)
-		.ignore_err()
-		.ready_for_each(|key| {
-			trace!("Removing key: {key:?}");
-			self.db.tokenids.remove(key);
-		})
-		.await;
+	self.db.tokenids.del_prefix(&shortroomid).await
+}
 
-	Ok(())
+/// Name of the tokenizer configured, as stored under `SEARCH_TOKENIZER`.
+#[implement(Service)]
+#[must_use]
+pub(crate) fn tokenizer_name(&self) -> &'static str {
+	match self.services.server.config.search.tokenizer {
+		| SearchTokenizer::Words => "words",
+		| SearchTokenizer::Ngram => "ngram",
+	}
 }
 
-/// Splits a string into tokens used as keys in the search inverted index
-///
-/// This may be used to tokenize both message bodies (for indexing) or search
-/// queries (for querying).
-fn tokenize(body: &str) -> impl Iterator<Item = String> + Send + '_ {
-	body.split_terminator(|c: char| !c.is_alphanumeric())
-		.filter(|s| !s.is_empty())
-		.filter(|word| word.len() <= WORD_MAX_LEN)
-		.map(str::to_lowercase)
+/// Splits a message body or a search term into the words of the index with
+/// the tokenizer configured.
+#[implement(Service)]
+fn tokenize(&self, text: &str, usage: Usage) -> Vec<String> {
+	tokenize(self.services.server.config.search.tokenizer, text, usage)
 }
 
 fn make_tokenid(shortroomid: ShortRoomId, word: &str, pdu_id: &RawPduId) -> TokenId {
@@ -0,0 +1,126 @@
+use tuwunel_core::config::SearchTokenizer;
+
+use super::tokenizer::{Usage, tokenize};
+
+fn contains_all(index: &[String], query: &[String]) -> bool {
+	query.iter().all(|word| index.contains(word))
+}
+
+#[test]
+fn tokenize_accented_words() {
+	let index = tokenize(SearchTokenizer::Words, "Café crème, s'il vous plaît!", Usage::Index);
+	assert_eq!(index, ["café", "crème", "s'il", "vous", "plaît"], "accents stay in the word");
+
+	let query = tokenize(SearchTokenizer::Words, "CAFÉ Crème", Usage::Query);
+	assert_eq!(query, ["café", "crème"], "the query is lowercased alike");
+	assert!(contains_all(&index, &query), "the query finds the message");
+
+	let ngram = tokenize(SearchTokenizer::Ngram, "Café crème", Usage::Index);
+	assert_eq!(ngram, ["café", "crème"], "spaced words are not split into n-grams");
+}
+
+#[test]
+fn tokenize_japanese() {
+	let body = "東京タワーに行きました";
+	let words = tokenize(SearchTokenizer::Words, body, Usage::Index);
+	assert!(!words.is_empty(), "the word splitter keeps the text");
+
+	let index = tokenize(SearchTokenizer::Ngram, body, Usage::Index);
+	assert!(index.contains(&"東".to_owned()), "single characters are indexed");
+	assert!(index.contains(&"東京".to_owned()), "pairs of characters are indexed");
+
+	for term in ["東京", "タワー", "東京タワー", "行き", "東"] {
+		let query = tokenize(SearchTokenizer::Ngram, term, Usage::Query);
+		assert!(!query.is_empty(), "the term {term} has words");
+		assert!(contains_all(&index, &query), "the term {term} finds the message");
+	}
+
+	let query = tokenize(SearchTokenizer::Ngram, "東京タワー", Usage::Query);
+	assert_eq!(query, ["東京", "京タ", "タワ", "ワー"], "a term matches by its pairs alone");
+
+	let query = tokenize(SearchTokenizer::Ngram, "大阪", Usage::Query);
+	assert!(!contains_all(&index, &query), "another term does not find the message");
+}
+
+#[test]
+fn tokenize_mixed_scripts() {
+	let index = tokenize(SearchTokenizer::Ngram, "Tuwunelは速い。Matrix!", Usage::Index);
+	for word in ["tuwunel", "は", "速い", "matrix"] {
+		assert!(index.contains(&word.to_owned()), "{word} is indexed");
+	}
+
+	assert!(!index.iter().any(|word| word.contains('。')), "punctuation is not indexed");
+	assert!(!index.contains(&"い。".to_owned()), "a run ends at punctuation");
+}
+
+#[test]
+fn tokenize_long_words() {
+	let long = "a".repeat(super::WORD_MAX_LEN.saturating_add(1));
+	let body = format!("short {long}");
+	for tokenizer in [SearchTokenizer::Words, SearchTokenizer::Ngram] {
+		let words = tokenize(tokenizer, &body, Usage::Index);
+		assert_eq!(words, ["short"], "words too long for the index are dropped");
+	}
+}
+
+#[tokio::test]
+async fn index_rebuilt_at_startup_after_tokenizer_change() {
+	use futures::StreamExt;
+	use ruma::api::client::search::search_events::v3::Criteria;
+
+	use super::{RoomQuery, SEARCH_TOKENIZER};
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+	embedded
+		.send_message(&alice, &room_id, "indexed again at startup")
+		.await
+		.expect("message sent");
+
+	// As if the index had been built by another tokenizer.
+	services
+		.search
+		.delete_all_search_tokenids_for_room(&room_id)
+		.await
+		.expect("index deleted");
+	services.db["global"].insert(SEARCH_TOKENIZER, "ngram");
+
+	let services = services.restart().await;
+	let stored = services.db["global"]
+		.get(SEARCH_TOKENIZER)
+		.await
+		.expect("tokenizer stored");
+	assert_eq!(
+		stored.as_ref(),
+		b"words",
+		"the configured tokenizer is stored after the rebuild"
+	);
+
+	let criteria = Criteria::new("startup".to_owned());
+	let query = RoomQuery {
+		room_id: &room_id,
+		user_id: None,
+		criteria: &criteria,
+		limit: 10,
+		skip: 0,
+	};
+	let found: Vec<_> = services
+		.search
+		.search_pdu_ids(&query)
+		.await
+		.expect("room has an index")
+		.collect()
+		.await;
+	assert_eq!(found.len(), 1, "the message is found in the rebuilt index");
+
+	services.stop().await;
+}
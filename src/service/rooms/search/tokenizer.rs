@@ -0,0 +1,76 @@
+//! Splitting text into the words of the search index. Message bodies and
+//! search terms go through the same tokenizer so their words compare equal;
+//! the index must be rebuilt when the tokenizer is changed.
+
+use tuwunel_core::config::SearchTokenizer;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::WORD_MAX_LEN;
+
+/// What the words are made for. A search term made of adjacent characters
+/// is matched by their pairs alone, while the index also holds each single
+/// character for terms only one character long.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum Usage {
+	Index,
+	Query,
+}
+
+/// Splits a message body or a search term into lowercase words.
+pub(super) fn tokenize(tokenizer: SearchTokenizer, text: &str, usage: Usage) -> Vec<String> {
+	let mut words = Vec::new();
+	match tokenizer {
+		| SearchTokenizer::Words => text
+			.unicode_words()
+			.for_each(|word| push(&mut words, word.to_lowercase())),
+
+		| SearchTokenizer::Ngram => {
+			let mut run = Vec::new();
+			for segment in text.split_word_bounds() {
+				if segment.chars().all(is_cjk) {
+					run.extend(segment.chars());
+					continue;
+				}
+
+				ngrams(&mut words, &run, usage);
+				run.clear();
+				if segment.chars().any(char::is_alphanumeric) {
+					push(&mut words, segment.to_lowercase());
+				}
+			}
+
+			ngrams(&mut words, &run, usage);
+		},
+	}
+
+	words
+}
+
+/// Words of a run of characters written without spaces between words.
+fn ngrams(words: &mut Vec<String>, run: &[char], usage: Usage) {
+	if usage == Usage::Index || run.len() == 1 {
+		run.iter()
+			.for_each(|c| push(words, c.to_string()));
+	}
+
+	run.windows(2)
+		.for_each(|pair| push(words, pair.iter().collect()));
+}
+
+fn push(words: &mut Vec<String>, word: String) {
+	if !word.is_empty() && word.len() <= WORD_MAX_LEN {
+		words.push(word);
+	}
+}
+
+/// Characters of scripts written without spaces between words.
+fn is_cjk(c: char) -> bool {
+	matches!(c,
+		'\u{3040}'..='\u{30FF}' // Hiragana, Katakana
+		| '\u{3400}'..='\u{4DBF}' // CJK Unified Ideographs Extension A
+		| '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+		| '\u{AC00}'..='\u{D7AF}' // Hangul Syllables
+		| '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+		| '\u{FF66}'..='\u{FF9F}' // Halfwidth Katakana
+	)
+}
@@ -8,7 +8,8 @@
 use futures::{FutureExt, Stream, StreamExt, TryFutureExt, pin_mut, stream::FuturesUnordered};
 use lru_cache::LruCache;
 use ruma::{
-	OwnedEventId, OwnedRoomId, OwnedServerName, RoomId, ServerName, UserId,
+	MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedServerName, RoomId, ServerName,
+	UserId,
 	api::{
 		client::space::SpaceHierarchyRoomsChunk,
 		federation::{self, space::SpaceHierarchyParentSummary},
@@ -20,6 +21,8 @@
 	room::{JoinRuleSummary, RoomSummary},
 	serde::Raw,
 };
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
 use tokio::sync::{Mutex, MutexGuard};
 use tuwunel_core::{
 	Err, Error, Event, PduEvent, Result, implement,
@@ -91,6 +94,17 @@ async fn clear_cache(&self) {
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
+/// Drops the cached summary of the space after its `m.space.child` state
+/// changed, whether a child was added, removed, redacted or only its
+/// `suggested` flag or `order` changed; each alters the hierarchy pages.
+#[implement(Service)]
+pub async fn invalidate_hierarchy(&self, room_id: &RoomId) {
+	self.roomid_spacehierarchy_cache
+		.lock()
+		.await
+		.remove(room_id);
+}
+
 /// Gets the summary of a space using solely local information
 #[implement(Service)]
 pub async fn get_summary_and_children_local(
@@ -485,22 +499,78 @@ async fn is_accessible_child<'a, I>(
 }
 
 /// Returns the children of a SpaceHierarchyParentSummary, making use of the
-/// children_state field
+/// children_state field. Children without a `via` were removed from the space
+/// and are skipped. Unsuggested children are dropped first when
+/// `suggested_only`; the rest are ordered as the spec mandates: by their
+/// `order`, where valid, then by the timestamp of their `m.space.child` event
+/// and finally by room ID.
 pub fn get_parent_children_via(
 	parent: &SpaceHierarchyParentSummary,
 	suggested_only: bool,
 ) -> impl DoubleEndedIterator<
 	Item = (OwnedRoomId, impl Iterator<Item = OwnedServerName> + Send + use<>),
 > + '_ {
-	parent
+	let mut children: Vec<_> = parent
 		.children_state
 		.iter()
-		.map(Raw::deserialize)
+		.map(|child| child.deserialize_as_unchecked::<SpaceChild>())
 		.filter_map(Result::ok)
-		.filter_map(move |ce| {
-			(!suggested_only || ce.content.suggested)
-				.then_some((ce.state_key, ce.content.via.into_iter()))
-		})
+		.filter(|child| !child.content.via.is_empty())
+		.filter(|child| !suggested_only || child.content.suggested)
+		.collect();
+
+	children.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+	children
+		.into_iter()
+		.map(|child| (child.state_key, child.content.via.into_iter()))
+}
+
+/// The parts of an `m.space.child` event relevant to ordering and filtering
+/// the children of a space; lenient so an invalid `order` does not discard the
+/// child. A missing `via` reads as empty, which marks a removed child.
+#[derive(Deserialize)]
+struct SpaceChild {
+	state_key: OwnedRoomId,
+	origin_server_ts: MilliSecondsSinceUnixEpoch,
+	content: SpaceChildContent,
+}
+
+#[derive(Deserialize)]
+struct SpaceChildContent {
+	#[serde(default)]
+	via: Vec<OwnedServerName>,
+
+	#[serde(default)]
+	suggested: bool,
+
+	#[serde(default)]
+	order: Option<JsonValue>,
+}
+
+impl SpaceChild {
+	fn sort_key(&self) -> (bool, Option<&str>, MilliSecondsSinceUnixEpoch, &RoomId) {
+		let order = self
+			.content
+			.order
+			.as_ref()
+			.and_then(JsonValue::as_str)
+			.and_then(space_child_order);
+
+		(order.is_none(), order, self.origin_server_ts, &self.state_key)
+	}
+}
+
+/// Validates the `order` of an `m.space.child` event: at most 50 characters
+/// within the printable ASCII range. `None` when it must be treated as absent.
+fn space_child_order(order: &str) -> Option<&str> {
+	const MAX_LEN: usize = 50;
+
+	let valid = order.len() <= MAX_LEN
+		&& order
+			.chars()
+			.all(|c| ('\x20'..='\x7E').contains(&c));
+
+	valid.then_some(order)
 }
 
 #[implement(Service)]
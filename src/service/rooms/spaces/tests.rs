@@ -7,7 +7,7 @@
 	room::{JoinRuleSummary, RoomSummary},
 };
 
-use crate::rooms::spaces::{PaginationToken, get_parent_children_via};
+use crate::rooms::spaces::{PaginationToken, get_parent_children_via, space_child_order};
 
 #[test]
 fn get_summary_children() {
@@ -145,3 +145,250 @@ fn pagination_token_to_string() {
 		"9,34_3_1_true"
 	);
 }
+
+#[test]
+fn space_child_order_validated() {
+	assert_eq!(space_child_order("a"), Some("a"));
+	assert_eq!(space_child_order(" ~"), Some(" ~"));
+	assert_eq!(space_child_order(&"a".repeat(50)).map(str::len), Some(50));
+	assert_eq!(space_child_order(&"a".repeat(51)), None);
+	assert_eq!(space_child_order("tab\t"), None);
+	assert_eq!(space_child_order("ümlaut"), None);
+}
+
+#[test]
+fn children_ordered_across_pages() {
+	let child = |room_id: &str, ts: u64, content: &str| {
+		serde_json::from_str(&format!(
+			r#"{{
+				"content": {{ "via": ["example.org"]{content} }},
+				"origin_server_ts": {ts},
+				"sender": "@alice:example.org",
+				"state_key": "{room_id}",
+				"type": "m.space.child"
+			}}"#
+		))
+		.unwrap()
+	};
+
+	let summary = SpaceHierarchyParentSummary {
+		summary: RoomSummary::new(
+			owned_room_id!("!root:example.org"),
+			JoinRuleSummary::Public,
+			true,
+			UInt::from(1_u32),
+			true,
+		),
+		children_state: vec![
+			child("!unordered2:example.org", 20, ""),
+			child("!invalid:example.org", 10, r#", "order": "bad\n", "suggested": true"#),
+			child("!second:example.org", 40, r#", "order": "b""#),
+			child("!number:example.org", 10, r#", "order": 1"#),
+			child("!first:example.org", 50, r#", "order": "a", "suggested": true"#),
+			child("!tie:example.org", 30, r#", "order": "b""#),
+		],
+	};
+
+	let children: Vec<_> = get_parent_children_via(&summary, false)
+		.map(|(room_id, _)| room_id)
+		.collect();
+
+	let (first_page, second_page) = children.split_at(3);
+	assert_eq!(first_page, [
+		owned_room_id!("!first:example.org"),
+		owned_room_id!("!tie:example.org"),
+		owned_room_id!("!second:example.org"),
+	]);
+	assert_eq!(second_page, [
+		owned_room_id!("!invalid:example.org"),
+		owned_room_id!("!number:example.org"),
+		owned_room_id!("!unordered2:example.org"),
+	]);
+
+	let suggested: Vec<_> = get_parent_children_via(&summary, true)
+		.map(|(room_id, _)| room_id)
+		.collect();
+
+	assert_eq!(suggested, [
+		owned_room_id!("!first:example.org"),
+		owned_room_id!("!invalid:example.org"),
+	]);
+}
+
+#[test]
+fn removed_children_skipped() {
+	let child = |room_id: &str, content: &str| {
+		serde_json::from_str(&format!(
+			r#"{{
+				"content": {content},
+				"origin_server_ts": 0,
+				"sender": "@alice:example.org",
+				"state_key": "{room_id}",
+				"type": "m.space.child"
+			}}"#
+		))
+		.unwrap()
+	};
+
+	let summary = SpaceHierarchyParentSummary {
+		summary: RoomSummary::new(
+			owned_room_id!("!root:example.org"),
+			JoinRuleSummary::Public,
+			true,
+			UInt::from(1_u32),
+			true,
+		),
+		children_state: vec![
+			child("!present:example.org", r#"{ "via": ["example.org"] }"#),
+			child("!empty:example.org", r#"{ "via": [] }"#),
+			child("!missing:example.org", r#"{ "suggested": true }"#),
+		],
+	};
+
+	let children: Vec<_> = get_parent_children_via(&summary, false)
+		.map(|(room_id, _)| room_id)
+		.collect();
+
+	assert_eq!(
+		children,
+		[owned_room_id!("!present:example.org")],
+		"children with a missing or empty via were removed"
+	);
+}
+
+/// Every change to a child of a space drops the cached summary, whether it
+/// changed the content, redacted the child or removed it by a state reset.
+#[tokio::test]
+async fn hierarchy_invalidated_by_child_changes() {
+	use std::{collections::BTreeSet, sync::Arc};
+
+	use futures::FutureExt;
+	use ruma::{
+		OwnedServerName, RoomId, UserId,
+		events::{StateEventType, space::child::SpaceChildEventContent},
+	};
+	use tuwunel_core::pdu::PduBuilder;
+
+	use crate::{Services, fixture::Fixture, rooms::spaces::Identifier};
+
+	async fn is_cached(services: &Services, space: &RoomId) -> bool {
+		services
+			.spaces
+			.roomid_spacehierarchy_cache
+			.lock()
+			.await
+			.contains_key(space)
+	}
+
+	async fn load(services: &Services, space: &RoomId, user: &UserId) {
+		services
+			.spaces
+			.get_summary_and_children_local(space, &Identifier::UserId(user))
+			.await
+			.expect("summary loaded")
+			.expect("space is known");
+
+		assert!(is_cached(services, space).await, "summary cached");
+	}
+
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let space = embedded
+		.create_room(&alice, Some("space"))
+		.await
+		.expect("space created");
+
+	let child = embedded
+		.create_room(&alice, Some("child"))
+		.await
+		.expect("child created");
+
+	let via: Vec<OwnedServerName> = vec![fixture.globals.server_name().to_owned()];
+	let send_child = async |content: SpaceChildEventContent| {
+		let state_lock = fixture.state.mutex.lock(&space).await;
+		fixture
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(child.to_string(), &content),
+				&alice,
+				&space,
+				&state_lock,
+			)
+			.boxed()
+			.await
+			.expect("child event sent")
+	};
+
+	let without_child = fixture
+		.state
+		.get_room_shortstatehash(&space)
+		.await
+		.expect("space has state");
+
+	send_child(SpaceChildEventContent::new(via.clone())).await;
+	load(&fixture, &space, &alice).await;
+
+	// Only the suggested flag changes; the child stays in the space.
+	let child_event = send_child(SpaceChildEventContent {
+		suggested: true,
+		..SpaceChildEventContent::new(via)
+	})
+	.await;
+
+	assert!(!is_cached(&fixture, &space).await, "a content change invalidates");
+
+	load(&fixture, &space, &alice).await;
+	let state_lock = fixture.state.mutex.lock(&space).await;
+	fixture
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::redaction(&child_event, None),
+			&alice,
+			&space,
+			&state_lock,
+		)
+		.boxed()
+		.await
+		.expect("child redacted");
+
+	drop(state_lock);
+	assert!(!is_cached(&fixture, &space).await, "a redaction invalidates");
+
+	// Reset the space to its state before the child was added, which drops
+	// the child without any event of its own.
+	load(&fixture, &space, &alice).await;
+	let shortstatekey = fixture
+		.short
+		.get_shortstatekey(&StateEventType::SpaceChild, child.as_str())
+		.await
+		.expect("child state key");
+
+	let removed = fixture
+		.state_compressor
+		.compress_state_event(shortstatekey, &child_event)
+		.await;
+
+	let state_lock = fixture.state.mutex.lock(&space).await;
+	fixture
+		.state
+		.force_state(
+			&space,
+			without_child,
+			Arc::new(BTreeSet::new()),
+			Arc::new(BTreeSet::from([removed])),
+			&state_lock,
+		)
+		.await
+		.expect("state reset");
+
+	drop(state_lock);
+	assert!(!is_cached(&fixture, &space).await, "a removal by state reset invalidates");
+
+	drop(embedded);
+	fixture.stop().await;
+}
@@ -1,7 +1,14 @@
+#[cfg(test)]
+mod tests;
+
 use std::{collections::HashMap, fmt::Write, iter::once, sync::Arc};
 
 use async_trait::async_trait;
-use futures::{FutureExt, Stream, StreamExt, TryStreamExt, future::join_all, pin_mut};
+use futures::{
+	FutureExt, Stream, StreamExt, TryStreamExt,
+	future::{OptionFuture, join, join_all},
+	pin_mut,
+};
 use ruma::{
 	EventId, OwnedEventId, OwnedRoomId, RoomId, RoomVersionId, UserId,
 	events::{
@@ -11,12 +18,12 @@
 	room_version_rules::AuthorizationRules,
 	serde::Raw,
 };
+use serde_json::value::to_raw_value;
 use tuwunel_core::{
 	Event, PduEvent, Result, err,
 	matrix::{RoomVersionRules, StateKey, TypeStateKey, room_version},
 	result::{AndThenRef, FlatOk},
-	state_res::{StateMap, auth_types_for_event},
-	trace,
+	state_res::{AuthTypes, StateMap, auth_types_for_event},
 	utils::{
 		IterStream, MutexMap, MutexMapGuard, ReadyExt, calculate_hash,
 		mutex_map::Guard,
@@ -34,6 +41,10 @@
 	services::OnceServices,
 };
 
+/// Type of the synthetic stripped state event added to invite and knock state
+/// when `invite_state_member_count` is enabled.
+pub const MEMBER_COUNT_STRIPPED_TYPE: &str = "m.room.member_count";
+
 pub struct Service {
 	pub mutex: RoomMutexMap,
 	services: Arc<OnceServices>,
@@ -89,9 +100,15 @@ pub async fn force_state(
 		room_id: &RoomId,
 		shortstatehash: u64,
 		statediffnew: Arc<CompressedState>,
-		_statediffremoved: Arc<CompressedState>,
+		statediffremoved: Arc<CompressedState>,
 		state_lock: &RoomMutexGuard,
 	) -> Result {
+		// Never point the room at a state which cannot be loaded.
+		self.services
+			.state_compressor
+			.load_room_shortstatehash_info(room_id, shortstatehash)
+			.await?;
+
 		let event_ids = statediffnew
 			.iter()
 			.stream()
@@ -140,15 +157,33 @@ pub async fn force_state(
 				| TimelineEventType::SpaceChild => {
 					self.services
 						.spaces
-						.roomid_spacehierarchy_cache
-						.lock()
-						.await
-						.remove(&pdu.room_id);
+						.invalidate_hierarchy(&pdu.room_id)
+						.await;
 				},
 				| _ => continue,
 			}
 		}
 
+		// Children dropped without a replacement event, as by a state reset.
+		let space_child_removed = statediffremoved
+			.iter()
+			.stream()
+			.map(|&removed| parse_compressed_state_event(removed).0)
+			.then(|shortstatekey| {
+				self.services
+					.short
+					.get_statekey_from_short(shortstatekey)
+			})
+			.ignore_err()
+			.ready_any(|(event_type, _)| event_type == StateEventType::SpaceChild);
+
+		if space_child_removed.await {
+			self.services
+				.spaces
+				.invalidate_hierarchy(room_id)
+				.await;
+		}
+
 		self.services
 			.state_cache
 			.update_joined_count(room_id)
@@ -201,7 +236,7 @@ pub async fn set_event_state(
 				| Ok(p) =>
 					self.services
 						.state_compressor
-						.load_shortstatehash_info(p)
+						.load_room_shortstatehash_info(room_id, p)
 						.await?,
 				| _ => Vec::new(),
 			};
@@ -279,7 +314,7 @@ pub async fn append_to_state(&self, new_pdu: &PduEvent) -> Result<u64> {
 					| Ok(p) =>
 						self.services
 							.state_compressor
-							.load_shortstatehash_info(p)
+							.load_room_shortstatehash_info(&new_pdu.room_id, p)
 							.await?,
 					| _ => Vec::new(),
 				};
@@ -370,24 +405,53 @@ pub async fn get_auth_events(
 		StateEventType: Send + Sync,
 		StateKey: Send + Sync,
 	{
-		let Ok(shortstatehash) = self.get_room_shortstatehash(room_id).await else {
-			return Ok(StateMap::new());
+		let mut auth_events = match self.get_room_shortstatehash(room_id).await {
+			| Ok(shortstatehash) =>
+				self.state_auth_events(
+					shortstatehash,
+					auth_types_for_event(
+						kind,
+						sender,
+						state_key,
+						content,
+						auth_rules,
+						include_create,
+					)?,
+				)
+				.await,
+			| Err(_) => StateMap::new(),
 		};
 
-		let sauthevents: HashMap<ShortStateKey, TypeStateKey> =
-			auth_types_for_event(kind, sender, state_key, content, auth_rules, include_create)?
-				.into_iter()
-				.stream()
-				.broad_filter_map(async |(event_type, state_key): TypeStateKey| {
-					self.services
-						.short
-						.get_shortstatekey(&event_type, &state_key)
-						.await
-						.map(move |sstatekey| (sstatekey, (event_type, state_key)))
-						.ok()
-				})
-				.collect()
-				.await;
+		// The create event of rooms identified by it may not be mapped into the state
+		// yet for their first events; it is found through the room ID instead.
+		if missing_create(&auth_events, auth_rules, include_create) {
+			let create_id = room_id.as_event_id()?;
+			if let Ok(create) = self.services.timeline.get_pdu(&create_id).await {
+				auth_events.insert((StateEventType::RoomCreate, "".into()), create);
+			}
+		}
+
+		Ok(auth_events)
+	}
+
+	async fn state_auth_events(
+		&self,
+		shortstatehash: ShortStateHash,
+		auth_types: AuthTypes,
+	) -> StateMap<PduEvent> {
+		let sauthevents: HashMap<ShortStateKey, TypeStateKey> = auth_types
+			.into_iter()
+			.stream()
+			.broad_filter_map(async |(event_type, state_key): TypeStateKey| {
+				self.services
+					.short
+					.get_shortstatekey(&event_type, &state_key)
+					.await
+					.map(move |sstatekey| (sstatekey, (event_type, state_key)))
+					.ok()
+			})
+			.collect()
+			.await;
 
 		self.services
 			.state_accessor
@@ -413,7 +477,6 @@ pub async fn get_auth_events(
 				Some(((ty.clone(), sk.clone()), pdu.ok()?))
 			})
 			.collect()
-			.map(Ok)
 			.await
 	}
 
@@ -439,15 +502,56 @@ pub async fn summary_stripped<Pdu: Event>(
 				.room_state_get(event.room_id(), event_type, state_key)
 		});
 
-		join_all(fetches)
-			.await
+		let member_count: OptionFuture<_> = self
+			.services
+			.config
+			.invite_state_member_count
+			.then(|| self.member_count_stripped(event.room_id()))
+			.into();
+
+		let (state, member_count) = join(join_all(fetches), member_count).await;
+
+		state
 			.into_iter()
 			.filter_map(Result::ok)
 			.map(Event::into_format)
 			.chain(once(event.to_format()))
+			.chain(member_count.flatten())
 			.collect()
 	}
 
+	/// The synthetic stripped state event carrying the member counts of a
+	/// room. It exists only in stripped state; no PDU may have its type.
+	async fn member_count_stripped(
+		&self,
+		room_id: &RoomId,
+	) -> Option<Raw<AnyStrippedStateEvent>> {
+		let joined = self
+			.services
+			.state_cache
+			.room_joined_count(room_id);
+		let invited = self
+			.services
+			.state_cache
+			.room_invited_count(room_id);
+		let (joined, invited) = join(joined, invited).await;
+
+		let event = serde_json::json!({
+			"type": MEMBER_COUNT_STRIPPED_TYPE,
+			"state_key": "",
+			"sender": self.services.globals.server_user,
+			"content": {
+				"m.joined_member_count": joined.unwrap_or(0),
+				"m.invited_member_count": invited.unwrap_or(0),
+			},
+		});
+
+		to_raw_value(&event)
+			.map(Raw::from_json)
+			.inspect_err(|e| warn!(%room_id, "Failed to serialize member count: {e}"))
+			.ok()
+	}
+
 	/// Returns the room's version rules
 	#[inline]
 	pub async fn get_room_version_rules(&self, room_id: &RoomId) -> Result<RoomVersionRules> {
@@ -499,6 +603,15 @@ pub fn get_forward_extremities<'a>(
 			.ignore_err()
 	}
 
+	/// Number of forward extremities of the room, counting no further than
+	/// `max` so a pathological room costs no more than that.
+	pub async fn forward_extremity_count(&self, room_id: &RoomId, max: usize) -> usize {
+		self.get_forward_extremities(room_id)
+			.take(max)
+			.count()
+			.await
+	}
+
 	#[tracing::instrument(
 		level = "debug"
 		skip_all,
@@ -526,20 +639,47 @@ pub async fn set_forward_extremities<'a, I>(
 		}
 	}
 
-	pub(super) async fn delete_all_rooms_forward_extremities(&self, room_id: &RoomId) -> Result {
+	pub(super) async fn delete_all_rooms_forward_extremities(
+		&self,
+		room_id: &RoomId,
+	) -> Result<usize> {
 		let prefix = (room_id, Interfix);
 
-		self.db
-			.roomid_pduleaves
-			.keys_prefix_raw(&prefix)
+		self.db.roomid_pduleaves.del_prefix(&prefix).await
+	}
+
+	/// Forgets the state at each event of the room's timeline, so the state
+	/// groups only those events referred to are orphaned once the room is
+	/// deleted.
+	pub(super) async fn delete_all_rooms_event_states(&self, room_id: &RoomId) -> Result<usize> {
+		let mut pdus = self
+			.services
+			.timeline
+			.pdus(None, room_id, None)
 			.ignore_err()
-			.ready_for_each(|key| {
-				trace!("Removing key: {key:?}");
-				self.db.roomid_pduleaves.remove(key);
-			})
-			.await;
+			.boxed();
+
+		let mut deleted = 0_usize;
+		while let Some((_, pdu)) = pdus.next().await {
+			if let Ok(shorteventid) = self
+				.services
+				.short
+				.get_shorteventid(&pdu.event_id)
+				.await
+			{
+				self.db
+					.shorteventid_shortstatehash
+					.remove(&shorteventid.to_be_bytes());
+
+				deleted = deleted.saturating_add(1);
+			}
 
-		Ok(())
+			if self.services.server.is_stopping() {
+				break;
+			}
+		}
+
+		Ok(deleted)
 	}
 
 	pub(super) async fn delete_room_shortstatehash(
@@ -552,3 +692,16 @@ pub(super) async fn delete_room_shortstatehash(
 		Ok(())
 	}
 }
+
+/// Whether the create event was asked for but is absent from the auth events
+/// found in the state; only rooms identified by their create event can
+/// recover it.
+fn missing_create<T>(
+	auth_events: &StateMap<T>,
+	auth_rules: &AuthorizationRules,
+	include_create: bool,
+) -> bool {
+	include_create
+		&& auth_rules.room_create_event_id_as_room_id
+		&& !auth_events.contains_key(&(StateEventType::RoomCreate, "".into()))
+}
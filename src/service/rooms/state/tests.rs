@@ -0,0 +1,84 @@
+use ruma::{
+	UInt,
+	events::{
+		AnyStrippedStateEvent, StateEventType, TimelineEventType,
+		room::member::{MembershipState, RoomMemberEventContent},
+	},
+	owned_user_id,
+	room_version_rules::AuthorizationRules,
+	serde::Raw,
+};
+use tuwunel_core::{
+	matrix::{Event, pdu::PduEvent},
+	state_res::StateMap,
+};
+
+use super::missing_create;
+
+#[test]
+fn early_event_in_room_identified_by_create() {
+	// the snapshot of an early event lacks the create mapping
+	let mut auth_events = StateMap::<()>::new();
+	auth_events.insert((StateEventType::RoomPowerLevels, "".into()), ());
+
+	assert!(missing_create(&auth_events, &AuthorizationRules::V12, true));
+	assert!(!missing_create(&auth_events, &AuthorizationRules::V12, false));
+
+	auth_events.insert((StateEventType::RoomCreate, "".into()), ());
+	assert!(!missing_create(&auth_events, &AuthorizationRules::V12, true));
+}
+
+#[test]
+fn create_not_recovered_before_v12() {
+	let auth_events = StateMap::<()>::new();
+	assert!(!missing_create(&auth_events, &AuthorizationRules::V11, true));
+}
+
+#[test]
+fn stripped_invite_keeps_member_content() {
+	let content = RoomMemberEventContent {
+		reason: Some("Come and chat".to_owned()),
+		is_direct: Some(true),
+		join_authorized_via_users_server: Some(owned_user_id!("@admin:example.com")),
+		..RoomMemberEventContent::new(MembershipState::Invite)
+	};
+
+	let invite = PduEvent {
+		state_key: Some("@bob:example.com".into()),
+		depth: UInt::from(3_u32),
+		..PduEvent::fake(
+			"$invite:example.com",
+			"@alice:example.com",
+			TimelineEventType::RoomMember,
+			&content,
+		)
+	};
+
+	// Stored in userroomid_invitestate, then loaded for sync.
+	let stripped: Vec<Raw<AnyStrippedStateEvent>> = vec![invite.to_format()];
+	let stored = serde_json::to_vec(&stripped).expect("serializes");
+	let synced: Vec<Raw<AnyStrippedStateEvent>> =
+		serde_json::from_slice::<Raw<Vec<AnyStrippedStateEvent>>>(&stored)
+			.expect("stored as an array")
+			.deserialize_as_unchecked()
+			.expect("array of stripped events");
+
+	let json = serde_json::to_value(&synced[0]).expect("serializes");
+	assert_eq!(json["content"]["reason"], "Come and chat", "reason survives: {json}");
+	assert_eq!(json["content"]["is_direct"], true, "is_direct survives: {json}");
+	assert_eq!(
+		json["content"]["join_authorised_via_users_server"], "@admin:example.com",
+		"authorising user survives: {json}"
+	);
+
+	let Ok(AnyStrippedStateEvent::RoomMember(member)) = synced[0].deserialize() else {
+		panic!("stripped member event expected: {json}");
+	};
+
+	assert_eq!(member.state_key.as_str(), "@bob:example.com", "the invited user");
+	assert_eq!(member.content.reason.as_deref(), Some("Come and chat"), "typed reason");
+	assert_eq!(
+		member.content.join_authorized_via_users_server, content.join_authorized_via_users_server,
+		"typed authorising user"
+	);
+}
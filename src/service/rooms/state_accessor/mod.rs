@@ -1,14 +1,16 @@
 mod room_state;
 mod server_can;
 mod state;
+#[cfg(test)]
+mod tests;
 mod user_can;
 
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use futures::{FutureExt, TryFutureExt, future::try_join};
+use futures::{FutureExt, Stream, TryFutureExt, future::try_join};
 use ruma::{
-	EventEncryptionAlgorithm, OwnedRoomAliasId, RoomId, UserId,
+	EventEncryptionAlgorithm, OwnedRoomAliasId, OwnedRoomId, RoomId, UserId,
 	events::{
 		StateEventType,
 		room::{
@@ -30,6 +32,7 @@
 use tuwunel_core::{
 	Result, err,
 	matrix::{Event, room_version, state_res::events::RoomCreateEvent},
+	utils::stream::BroadbandExt,
 };
 use tuwunel_database::Map;
 
@@ -155,6 +158,22 @@ pub async fn get_room_type(&self, room_id: &RoomId) -> Result<RoomType> {
 			})
 	}
 
+	/// Gets the types of many rooms concurrently; `None` for rooms without a
+	/// type. Yields in no particular order.
+	pub fn get_room_types<'a, S>(
+		&'a self,
+		room_ids: S,
+	) -> impl Stream<Item = (OwnedRoomId, Option<RoomType>)> + Send + 'a
+	where
+		S: Stream<Item = OwnedRoomId> + Send + 'a,
+	{
+		room_ids.broad_then(async |room_id| {
+			let room_type = self.get_room_type(&room_id).await.ok();
+
+			(room_id, room_type)
+		})
+	}
+
 	/// Gets the room's encryption algorithm if `m.room.encryption` state event
 	/// is found
 	pub async fn get_room_encryption(
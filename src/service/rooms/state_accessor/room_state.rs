@@ -1,7 +1,7 @@
 use std::borrow::Borrow;
 
 use futures::{Stream, StreamExt, TryFutureExt};
-use ruma::{EventId, RoomId, events::StateEventType};
+use ruma::{EventId, OwnedMxcUri, OwnedUserId, RoomId, events::StateEventType};
 use serde::Deserialize;
 use tuwunel_core::{
 	Result, err, implement,
@@ -39,6 +39,26 @@ pub fn room_state_full<'a>(
 		.try_flatten_stream()
 }
 
+/// Returns the joined members of the room with the displayname and avatar_url
+/// of their member event.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub fn room_members_with_profiles<'a>(
+	&'a self,
+	room_id: &'a RoomId,
+) -> impl Stream<Item = Result<(OwnedUserId, Option<String>, Option<OwnedMxcUri>)>> + Send + 'a {
+	self.services
+		.state
+		.get_room_shortstatehash(room_id)
+		.map_ok(|shortstatehash| {
+			self.state_joined_members(shortstatehash)
+				.map(|(user_id, content)| Ok((user_id, content.displayname, content.avatar_url)))
+				.boxed()
+		})
+		.map_err(move |e| err!(Database("Missing state for {room_id:?}: {e:?}")))
+		.try_flatten_stream()
+}
+
 /// Returns the full room state pdus
 #[implement(super::Service)]
 #[tracing::instrument(skip(self), level = "debug")]
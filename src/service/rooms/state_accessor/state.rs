@@ -2,7 +2,7 @@
 
 use futures::{FutureExt, Stream, StreamExt, TryFutureExt, future::try_join, pin_mut};
 use ruma::{
-	EventId, OwnedEventId, UserId,
+	EventId, OwnedEventId, OwnedUserId, UserId,
 	events::{
 		StateEventType,
 		room::member::{MembershipState, RoomMemberEventContent},
@@ -349,6 +349,64 @@ pub fn state_full_pdus(
 		})
 }
 
+/// Returns the joined members at this state with the content of their
+/// member event. Other state is filtered out by its shortstatekey before any
+/// event is loaded, and the member events are loaded in batches.
+#[implement(super::Service)]
+pub fn state_joined_members(
+	&self,
+	shortstatehash: ShortStateHash,
+) -> impl Stream<Item = (OwnedUserId, RoomMemberEventContent)> + Send + '_ {
+	async move {
+		let (shortstatekeys, shorteventids): (Vec<_>, Vec<_>) = self
+			.state_full_shortids(shortstatehash)
+			.ignore_err()
+			.unzip()
+			.await;
+
+		let (user_ids, shorteventids): (Vec<_>, Vec<_>) = self
+			.services
+			.short
+			.multi_get_statekey_from_short(shortstatekeys.into_iter().stream())
+			.zip(shorteventids.into_iter().stream())
+			.ready_filter_map(|(statekey, shorteventid)| {
+				let (event_type, state_key) = statekey.ok()?;
+				if event_type != StateEventType::RoomMember {
+					return None;
+				}
+
+				let user_id = UserId::parse(state_key.as_str()).ok()?;
+				Some((user_id, shorteventid))
+			})
+			.unzip()
+			.await;
+
+		let (user_ids, event_ids): (Vec<_>, Vec<OwnedEventId>) = self
+			.services
+			.short
+			.multi_get_eventid_from_short(shorteventids.into_iter().stream())
+			.zip(user_ids.into_iter().stream())
+			.ready_filter_map(|(event_id, user_id)| Some((user_id, event_id.ok()?)))
+			.unzip()
+			.await;
+
+		let members: Vec<_> = self
+			.services
+			.timeline
+			.multi_get_pdus(event_ids.iter().map(Deref::deref))
+			.zip(user_ids.into_iter().stream())
+			.ready_filter_map(|(pdu, user_id)| {
+				let content: RoomMemberEventContent = pdu.ok()?.get_content().ok()?;
+				(content.membership == MembershipState::Join).then_some((user_id, content))
+			})
+			.collect()
+			.await;
+
+		members.into_iter().stream()
+	}
+	.flatten_stream()
+}
+
 /// Builds a StateMap by iterating over all keys that start
 /// with state_hash, this gives the full state for the given state_hash.
 #[implement(super::Service)]
@@ -0,0 +1,444 @@
+use ruma::{
+	OwnedUserId,
+	events::room::{member::MembershipState, power_levels::RoomPowerLevels},
+	owned_user_id,
+	room_version_rules::AuthorizationRules,
+	user_id,
+};
+use serde_json::json;
+
+use super::user_can::{MembershipChange, membership_change_allowed};
+
+/// Power levels where kicking needs 50 and banning and inviting need 75.
+fn power_levels(rules: &AuthorizationRules, creators: Vec<OwnedUserId>) -> RoomPowerLevels {
+	let content = serde_json::from_value(json!({
+		"ban": 75,
+		"invite": 75,
+		"kick": 50,
+		"users": {
+			"@admin:example.com": 100,
+			"@mod:example.com": 75,
+			"@helper:example.com": 50,
+		},
+	}))
+	.expect("valid power levels");
+
+	RoomPowerLevels::new(Some(content).into(), rules, creators)
+}
+
+fn allowed(
+	power_levels: &RoomPowerLevels,
+	sender: &str,
+	target: &str,
+	target_membership: &MembershipState,
+	change: MembershipChange,
+) -> bool {
+	let sender = sender.try_into().expect("valid user ID");
+	let target = target.try_into().expect("valid user ID");
+
+	membership_change_allowed(
+		power_levels,
+		(sender, &MembershipState::Join),
+		(target, target_membership),
+		change,
+	)
+	.is_ok()
+}
+
+#[test]
+fn kick_and_ban_need_level_and_higher_power() {
+	let power_levels = power_levels(&AuthorizationRules::V11, Vec::new());
+	let join = &MembershipState::Join;
+
+	assert!(
+		allowed(
+			&power_levels,
+			"@helper:example.com",
+			"@user:example.com",
+			join,
+			MembershipChange::Kick
+		),
+		"the kick level suffices to kick a user of lower power"
+	);
+	assert!(
+		!allowed(
+			&power_levels,
+			"@helper:example.com",
+			"@user:example.com",
+			join,
+			MembershipChange::Ban
+		),
+		"banning needs the ban level"
+	);
+	assert!(
+		!allowed(
+			&power_levels,
+			"@helper:example.com",
+			"@mod:example.com",
+			join,
+			MembershipChange::Kick
+		),
+		"a user of higher power may not be kicked"
+	);
+
+	let error = membership_change_allowed(
+		&power_levels,
+		(user_id!("@mod:example.com"), join),
+		(user_id!("@admin:example.com"), join),
+		MembershipChange::Ban,
+	)
+	.expect_err("a user of higher power may not be banned");
+
+	assert!(
+		error
+			.message()
+			.contains("You cannot ban a user with equal or higher power level"),
+		"the error tells why: {error}"
+	);
+}
+
+#[test]
+fn unban_needs_ban_level() {
+	let power_levels = power_levels(&AuthorizationRules::V11, Vec::new());
+	let ban = &MembershipState::Ban;
+
+	assert!(
+		!allowed(
+			&power_levels,
+			"@helper:example.com",
+			"@user:example.com",
+			ban,
+			MembershipChange::Unban
+		),
+		"the kick level does not suffice to unban"
+	);
+	assert!(
+		!allowed(
+			&power_levels,
+			"@helper:example.com",
+			"@user:example.com",
+			ban,
+			MembershipChange::Kick
+		),
+		"kicking a banned user unbans them, which needs the ban level"
+	);
+	assert!(
+		allowed(
+			&power_levels,
+			"@mod:example.com",
+			"@user:example.com",
+			ban,
+			MembershipChange::Unban
+		),
+		"the ban level suffices to unban"
+	);
+}
+
+#[test]
+fn invite_needs_level_and_absent_target() {
+	let power_levels = power_levels(&AuthorizationRules::V11, Vec::new());
+
+	assert!(
+		allowed(
+			&power_levels,
+			"@mod:example.com",
+			"@user:example.com",
+			&MembershipState::Leave,
+			MembershipChange::Invite
+		),
+		"the invite level suffices to invite"
+	);
+	assert!(
+		!allowed(
+			&power_levels,
+			"@helper:example.com",
+			"@user:example.com",
+			&MembershipState::Leave,
+			MembershipChange::Invite
+		),
+		"inviting needs the invite level"
+	);
+	assert!(
+		!allowed(
+			&power_levels,
+			"@mod:example.com",
+			"@user:example.com",
+			&MembershipState::Ban,
+			MembershipChange::Invite
+		),
+		"banned users may not be invited"
+	);
+	assert!(
+		!allowed(
+			&power_levels,
+			"@mod:example.com",
+			"@user:example.com",
+			&MembershipState::Join,
+			MembershipChange::Invite
+		),
+		"members may not be invited"
+	);
+
+	let sender = user_id!("@admin:example.com");
+	assert!(
+		membership_change_allowed(
+			&power_levels,
+			(sender, &MembershipState::Leave),
+			(user_id!("@user:example.com"), &MembershipState::Leave),
+			MembershipChange::Invite,
+		)
+		.is_err(),
+		"only members may invite"
+	);
+}
+
+#[test]
+fn creators_outrank_everyone() {
+	let creator = owned_user_id!("@creator:example.com");
+	let power_levels = power_levels(&AuthorizationRules::V12, vec![creator.clone()]);
+	let join = &MembershipState::Join;
+
+	assert!(
+		allowed(
+			&power_levels,
+			creator.as_str(),
+			"@admin:example.com",
+			join,
+			MembershipChange::Ban
+		),
+		"the creator may ban anyone"
+	);
+	assert!(
+		!allowed(
+			&power_levels,
+			"@admin:example.com",
+			creator.as_str(),
+			join,
+			MembershipChange::Kick
+		),
+		"nobody may kick the creator"
+	);
+
+	let power_levels = power_levels(&AuthorizationRules::V11, vec![creator.clone()]);
+	assert!(
+		!allowed(
+			&power_levels,
+			creator.as_str(),
+			"@admin:example.com",
+			join,
+			MembershipChange::Ban
+		),
+		"creators are not privileged before room version 12"
+	);
+}
+
+/// A user who joined a room with `joined` history visibility midway searches
+/// for its messages; the older one was sent before they joined.
+#[tokio::test]
+async fn filter_visible_joined_midway() {
+	use std::{
+		collections::HashMap,
+		sync::atomic::{AtomicUsize, Ordering},
+	};
+
+	use super::user_can::filter_visible;
+
+	const BEFORE_JOIN: u64 = 1;
+	const AFTER_JOIN: u64 = 2;
+
+	let membership = HashMap::from([
+		(BEFORE_JOIN, MembershipState::Leave),
+		(AFTER_JOIN, MembershipState::Join),
+	]);
+
+	let checks = AtomicUsize::new(0);
+	let visible_at = async |shortstatehash| {
+		checks.fetch_add(1, Ordering::Relaxed);
+		membership[&shortstatehash] == MembershipState::Join
+	};
+
+	let events = vec![
+		("older message", Some(BEFORE_JOIN)),
+		("newer message", Some(AFTER_JOIN)),
+		("newest message", Some(AFTER_JOIN)),
+		("outlier", None),
+	];
+
+	let visible = filter_visible(events, visible_at).await;
+	assert_eq!(
+		visible,
+		["newer message", "newest message", "outlier"],
+		"the message sent before the user joined is dropped"
+	);
+	assert_eq!(checks.load(Ordering::Relaxed), 2, "each state is checked once");
+}
+
+/// The state at a message sent before the topic changed, as `/state?at=`
+/// reads it.
+#[tokio::test]
+async fn state_before_topic_change() {
+	use futures::StreamExt;
+	use ruma::events::{StateEventType, TimelineEventType, room::topic::RoomTopicEventContent};
+	use tuwunel_core::{Event, matrix::pdu::PduBuilder};
+
+	use crate::fixture::Fixture;
+
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	let set_topic = async |topic: &str| {
+		let state_lock = fixture.state.mutex.lock(&room_id).await;
+		fixture
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(String::new(), &RoomTopicEventContent::new(topic.to_owned())),
+				&alice,
+				&room_id,
+				&state_lock,
+			)
+			.await
+			.expect("topic set");
+	};
+
+	set_topic("Before").await;
+	let message = embedded
+		.send_message(&alice, &room_id, "hello")
+		.await
+		.expect("message sent");
+	set_topic("After").await;
+	embedded
+		.join_room(&bob, &room_id)
+		.await
+		.expect("bob joins");
+
+	let shortstatehash = fixture
+		.state_accessor
+		.pdu_shortstatehash(&message)
+		.await
+		.expect("state at the message");
+
+	let topics: Vec<RoomTopicEventContent> = fixture
+		.state_accessor
+		.state_full_pdus(shortstatehash)
+		.filter_map(async |pdu| {
+			(*pdu.kind() == TimelineEventType::RoomTopic)
+				.then(|| pdu.get_content().ok())
+				.flatten()
+		})
+		.collect()
+		.await;
+	assert_eq!(topics.len(), 1, "one topic in the state");
+	assert_eq!(topics[0].topic, "Before", "the topic before the change");
+
+	let current: RoomTopicEventContent = fixture
+		.state_accessor
+		.room_state_get_content(&room_id, &StateEventType::RoomTopic, "")
+		.await
+		.expect("current topic");
+	assert_eq!(current.topic, "After", "the current topic is the new one");
+
+	assert!(
+		fixture
+			.state_accessor
+			.user_was_joined(shortstatehash, &alice)
+			.await,
+		"alice was joined then"
+	);
+	assert!(
+		!fixture
+			.state_accessor
+			.user_was_joined(shortstatehash, &bob)
+			.await,
+		"bob joined later"
+	);
+
+	fixture.stop().await;
+}
+
+/// `/joined_members` of a room with more members than fit in a batch, read
+/// in a bounded number of queries with the profiles of the member events.
+#[tokio::test]
+async fn members_with_profiles_batched() {
+	use futures::TryStreamExt;
+
+	use crate::fixture::Fixture;
+
+	const MEMBERS: usize = 48;
+
+	// Batches of 32 keys, so the members take more than one
+	let fixture = Fixture::start_with("stream_amplification = 32").await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("alice");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	for i in 0..MEMBERS {
+		let member = embedded
+			.create_user(&format!("member{i}"), None)
+			.await
+			.expect("member created");
+
+		fixture
+			.users
+			.set_displayname(&member, Some(format!("Member {i}")));
+
+		embedded
+			.join_room(&member, &room_id)
+			.await
+			.expect("member joined");
+	}
+
+	let renamed = user_id!("@member0:fixture.localhost");
+	fixture
+		.users
+		.set_displayname(renamed, Some("Renamed".to_owned()));
+
+	let engine = &fixture.db.db;
+	let queries = engine.query_count();
+	let members: Vec<_> = fixture
+		.state_accessor
+		.room_members_with_profiles(&room_id)
+		.try_collect()
+		.await
+		.expect("members read");
+
+	let queries = engine.query_count().saturating_sub(queries);
+	let queries = usize::try_from(queries).expect("query count");
+
+	assert_eq!(members.len(), MEMBERS.saturating_add(1), "every joined member is listed");
+	// Reading them one by one takes a pdu ID and a pdu per member
+	assert!(
+		queries < MEMBERS,
+		"the members are read in batches, not one by one: {queries} queries"
+	);
+
+	let (_, displayname, _) = members
+		.iter()
+		.find(|(user_id, ..)| *user_id == renamed)
+		.expect("member listed");
+
+	assert_eq!(
+		displayname.as_deref(),
+		Some("Member 0"),
+		"the profile comes from the member event"
+	);
+
+	fixture.stop().await;
+}
@@ -1,16 +1,20 @@
+use std::collections::HashMap;
+
+use futures::{FutureExt, StreamExt, future::join3};
 use ruma::{
 	EventId, RoomId, UserId,
 	events::{
 		StateEventType, TimelineEventType,
 		room::{
 			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
-			member::{MembershipState, RoomMemberEventContent},
+			member::MembershipState,
+			power_levels::{RoomPowerLevels, UserPowerLevel},
 		},
 	},
 };
-use tuwunel_core::{Err, Result, implement, matrix::Event, pdu::PduBuilder};
+use tuwunel_core::{Err, Result, implement, matrix::Event, utils::IterStream};
 
-use crate::rooms::state::RoomMutexGuard;
+use crate::rooms::short::ShortStateHash;
 
 /// Checks if a given user can redact a given event
 ///
@@ -93,6 +97,90 @@ pub async fn user_can_see_event(
 		.is_joined(user_id, room_id)
 		.await;
 
+	self.user_can_see_at(user_id, shortstatehash, currently_member)
+		.await
+}
+
+/// The events of the room the user is allowed to see, in their order, based
+/// on the room's history_visibility at each event's state. The visibility is
+/// checked once for each state the events are at rather than for each event.
+#[implement(super::Service)]
+#[tracing::instrument(skip_all, level = "trace")]
+pub async fn user_can_see_events<E>(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	events: Vec<E>,
+) -> Vec<E>
+where
+	E: Event + Send,
+{
+	let currently_member = self
+		.services
+		.state_cache
+		.is_joined(user_id, room_id)
+		.await;
+
+	let events: Vec<_> = events
+		.into_iter()
+		.stream()
+		.then(async |event| {
+			let shortstatehash = self
+				.pdu_shortstatehash(event.event_id())
+				.await
+				.ok();
+			(event, shortstatehash)
+		})
+		.collect()
+		.await;
+
+	filter_visible(events, async |shortstatehash| {
+		self.user_can_see_at(user_id, shortstatehash, currently_member)
+			.await
+	})
+	.await
+}
+
+/// The events visible at their state, checking each state once. Events
+/// without a state are visible, as for `user_can_see_event`.
+pub(super) async fn filter_visible<E, F, Fut>(
+	events: Vec<(E, Option<ShortStateHash>)>,
+	visible_at: F,
+) -> Vec<E>
+where
+	F: Fn(ShortStateHash) -> Fut,
+	Fut: Future<Output = bool>,
+{
+	let mut visible: HashMap<ShortStateHash, bool> = HashMap::new();
+	let mut out = Vec::with_capacity(events.len());
+	for (event, shortstatehash) in events {
+		let is_visible = match shortstatehash {
+			| None => true,
+			| Some(shortstatehash) => match visible.get(&shortstatehash) {
+				| Some(&is_visible) => is_visible,
+				| None => {
+					let is_visible = visible_at(shortstatehash).await;
+					visible.insert(shortstatehash, is_visible);
+					is_visible
+				},
+			},
+		};
+
+		if is_visible {
+			out.push(event);
+		}
+	}
+
+	out
+}
+
+#[implement(super::Service)]
+async fn user_can_see_at(
+	&self,
+	user_id: &UserId,
+	shortstatehash: ShortStateHash,
+	currently_member: bool,
+) -> bool {
 	let history_visibility = self
 		.state_get_content(shortstatehash, &StateEventType::RoomHistoryVisibility, "")
 		.await
@@ -153,25 +241,132 @@ pub async fn user_can_see_state_events(&self, user_id: &UserId, room_id: &RoomId
 	}
 }
 
+/// A change by a member of the membership of another user.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum MembershipChange {
+	Invite,
+	Kick,
+	Ban,
+	Unban,
+}
+
+/// Errors with why the sender may not invite the target to the room.
 #[implement(super::Service)]
 pub async fn user_can_invite(
 	&self,
 	room_id: &RoomId,
 	sender: &UserId,
-	target_user: &UserId,
-	state_lock: &RoomMutexGuard,
-) -> bool {
-	self.services
-		.timeline
-		.create_hash_and_sign_event(
-			PduBuilder::state(
-				target_user.as_str(),
-				&RoomMemberEventContent::new(MembershipState::Invite),
-			),
-			sender,
-			room_id,
-			state_lock,
-		)
+	target: &UserId,
+) -> Result {
+	self.user_can_change_membership(room_id, sender, target, MembershipChange::Invite)
 		.await
-		.is_ok()
+}
+
+/// Errors with why the sender may not kick the target from the room.
+#[implement(super::Service)]
+pub async fn user_can_kick(&self, room_id: &RoomId, sender: &UserId, target: &UserId) -> Result {
+	self.user_can_change_membership(room_id, sender, target, MembershipChange::Kick)
+		.await
+}
+
+/// Errors with why the sender may not ban the target from the room.
+#[implement(super::Service)]
+pub async fn user_can_ban(&self, room_id: &RoomId, sender: &UserId, target: &UserId) -> Result {
+	self.user_can_change_membership(room_id, sender, target, MembershipChange::Ban)
+		.await
+}
+
+/// Errors with why the sender may not unban the target from the room.
+#[implement(super::Service)]
+pub async fn user_can_unban(&self, room_id: &RoomId, sender: &UserId, target: &UserId) -> Result {
+	self.user_can_change_membership(room_id, sender, target, MembershipChange::Unban)
+		.await
+}
+
+#[implement(super::Service)]
+async fn user_can_change_membership(
+	&self,
+	room_id: &RoomId,
+	sender: &UserId,
+	target: &UserId,
+	change: MembershipChange,
+) -> Result {
+	let membership = |user_id| {
+		self.get_member(room_id, user_id)
+			.map(|content| content.map_or(MembershipState::Leave, |content| content.membership))
+	};
+
+	let (power_levels, sender_membership, target_membership) =
+		join3(self.get_power_levels(room_id), membership(sender), membership(target)).await;
+
+	membership_change_allowed(
+		&power_levels?,
+		(sender, &sender_membership),
+		(target, &target_membership),
+		change,
+	)
+}
+
+/// Whether the sender may apply the change to the membership of the target,
+/// with the power levels and memberships of the room. Follows the
+/// authorization rules of membership events so the client is told why before
+/// the event is built; the creators of rooms with privileged creators
+/// outrank everyone through their power level.
+pub(super) fn membership_change_allowed(
+	power_levels: &RoomPowerLevels,
+	(sender, sender_membership): (&UserId, &MembershipState),
+	(target, target_membership): (&UserId, &MembershipState),
+	change: MembershipChange,
+) -> Result {
+	if *sender_membership != MembershipState::Join {
+		return Err!(Request(Forbidden("You are not joined to this room.")));
+	}
+
+	let sender_power = power_levels.for_user(sender);
+	let target_power = power_levels.for_user(target);
+	let (required, action) = match change {
+		| MembershipChange::Invite => {
+			match target_membership {
+				| MembershipState::Join =>
+					return Err!(Request(Forbidden("User is already in the room."))),
+				| MembershipState::Ban =>
+					return Err!(Request(Forbidden("User is banned from this room."))),
+				| _ => {},
+			}
+
+			if sender_power < UserPowerLevel::Int(power_levels.invite) {
+				return Err!(Request(Forbidden(
+					"You do not have the power level required to invite users."
+				)));
+			}
+
+			return Ok(());
+		},
+		| MembershipChange::Ban => (power_levels.ban, "ban"),
+		| MembershipChange::Kick if *target_membership != MembershipState::Ban =>
+			(power_levels.kick, "kick"),
+		| MembershipChange::Kick | MembershipChange::Unban => {
+			if sender_power < UserPowerLevel::Int(power_levels.ban) {
+				return Err!(Request(Forbidden(
+					"You do not have the power level required to unban users."
+				)));
+			}
+
+			(power_levels.kick, "unban")
+		},
+	};
+
+	if sender_power < UserPowerLevel::Int(required) {
+		return Err!(Request(Forbidden(
+			"You do not have the power level required to {action} users."
+		)));
+	}
+
+	if target_power >= sender_power {
+		return Err!(Request(Forbidden(
+			"You cannot {action} a user with equal or higher power level."
+		)));
+	}
+
+	Ok(())
 }
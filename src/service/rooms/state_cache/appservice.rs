@@ -0,0 +1,283 @@
+//! Rooms classified for each appservice by whether their events concern it:
+//! rooms where one of its users is joined, or whose ID or a local alias
+//! matches its namespaces. Sending looks the room of each event up here
+//! before matching any namespace, classifying rooms on first use. A join of
+//! one of its users marks the room right away; leaves and alias changes leave
+//! the room to be classified again. Classifications older than the cache
+//! trimming age are dropped, to be made again on next use. Every forgetting
+//! bumps a generation, so a classification made across it is not kept.
+
+use std::{
+	collections::HashMap,
+	fmt::Write,
+	sync::RwLock,
+	time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use ruma::{OwnedRoomId, RoomId, UserId, events::TimelineEventType};
+use tuwunel_core::{Result, implement, result::LogErr, utils::ReadyExt};
+
+use crate::appservice::RegistrationInfo;
+
+pub(super) type AppserviceInterest = RwLock<Classified>;
+
+/// Classified rooms by appservice ID, with the number of times any were
+/// forgotten.
+#[derive(Debug, Default)]
+pub(super) struct Classified {
+	appservices: HashMap<String, Interest>,
+	generation: u64,
+}
+
+/// Rooms of interest and of no interest to one appservice, with when they
+/// were classified.
+#[derive(Debug, Default)]
+pub(super) struct Interest {
+	interested: HashMap<OwnedRoomId, Instant>,
+	uninterested: HashMap<OwnedRoomId, Instant>,
+}
+
+/// Whether events in the room concern the appservice.
+#[implement(super::Service)]
+#[tracing::instrument(level = "trace", skip_all)]
+pub async fn appservice_interested(
+	&self,
+	room_id: &RoomId,
+	appservice: &RegistrationInfo,
+) -> bool {
+	let id = &appservice.registration.id;
+	let (classified, generation) = self.appservice_classification(id, room_id);
+	if let Some(interested) = classified {
+		return interested;
+	}
+
+	let interested = appservice.rooms.is_match(room_id.as_str())
+		|| self.appservice_in_room(room_id, appservice).await
+		|| self
+			.services
+			.alias
+			.local_aliases_for_room(room_id)
+			.ready_any(|alias| appservice.aliases.is_match(alias.as_str()))
+			.await;
+
+	self.classify_for_appservice(id, room_id, interested, generation)
+}
+
+/// The classification of the room for the appservice, if any, and the
+/// generation to classify it at otherwise.
+#[implement(super::Service)]
+pub(super) fn appservice_classification(
+	&self,
+	appservice_id: &str,
+	room_id: &RoomId,
+) -> (Option<bool>, u64) {
+	let classified = self.appservice_interest.read().expect("locked");
+	let interested = classified
+		.appservices
+		.get(appservice_id)
+		.and_then(|interest| interest.get(room_id));
+
+	(interested, classified.generation)
+}
+
+/// Keeps the classification made at the generation unless the room was
+/// classified meanwhile, e.g. by a join which is more recent, or anything was
+/// forgotten since, which the classification may predate. Returns whether
+/// the room is of interest.
+#[implement(super::Service)]
+pub(super) fn classify_for_appservice(
+	&self,
+	appservice_id: &str,
+	room_id: &RoomId,
+	interested: bool,
+	generation: u64,
+) -> bool {
+	let mut classified = self.appservice_interest.write().expect("locked");
+	if classified.generation != generation {
+		return classified
+			.appservices
+			.get(appservice_id)
+			.and_then(|interest| interest.get(room_id))
+			.unwrap_or(interested);
+	}
+
+	let interest = classified
+		.appservices
+		.entry(appservice_id.to_owned())
+		.or_default();
+
+	if let Some(interested) = interest.get(room_id) {
+		return interested;
+	}
+
+	interest.set(room_id, interested);
+	interested
+}
+
+/// Whether the sender or one of the users of the appservice is joined to the
+/// room.
+#[implement(super::Service)]
+#[tracing::instrument(level = "trace", skip_all)]
+pub async fn appservice_in_room(&self, room_id: &RoomId, appservice: &RegistrationInfo) -> bool {
+	let bridge_user_id = UserId::parse_with_server_name(
+		appservice.registration.sender_localpart.as_str(),
+		self.services.globals.server_name(),
+	);
+
+	let Ok(bridge_user_id) = bridge_user_id.log_err() else {
+		return false;
+	};
+
+	self.is_joined(&bridge_user_id, room_id).await
+		|| self
+			.room_members(room_id)
+			.ready_any(|user_id| appservice.users.is_match(user_id.as_str()))
+			.await
+}
+
+/// Marks the room as of interest to the appservices the joined user belongs
+/// to.
+#[implement(super::Service)]
+pub(super) async fn appservice_user_joined(&self, user_id: &UserId, room_id: &RoomId) {
+	let is_local = self.services.globals.user_is_local(user_id);
+	let appservices = self.services.appservice.read().await;
+	let mut classified = self.appservice_interest.write().expect("locked");
+
+	for appservice in appservices.values() {
+		let is_sender =
+			is_local && appservice.registration.sender_localpart == user_id.localpart();
+		if is_sender || appservice.users.is_match(user_id.as_str()) {
+			classified
+				.appservices
+				.entry(appservice.registration.id.clone())
+				.or_default()
+				.set(room_id, true);
+		}
+	}
+}
+
+/// Forgets the classification of the room for all appservices, e.g. after a
+/// member left or its aliases changed.
+#[implement(super::Service)]
+#[tracing::instrument(level = "trace", skip(self))]
+pub fn appservice_room_changed(&self, room_id: &RoomId) {
+	let mut classified = self.appservice_interest.write().expect("locked");
+	classified.forgot();
+	classified
+		.appservices
+		.values_mut()
+		.for_each(|interest| interest.forget(room_id));
+}
+
+#[implement(super::Service)]
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn clear_appservice_interest(&self) {
+	let mut classified = self.appservice_interest.write().expect("locked");
+	classified.forgot();
+	classified.appservices.clear();
+}
+
+/// Forgets the classified rooms of one appservice, e.g. after its namespaces
+/// changed.
+#[implement(super::Service)]
+#[tracing::instrument(level = "debug", skip(self))]
+pub fn clear_appservice_interest_for(&self, appservice_id: &str) {
+	let mut classified = self.appservice_interest.write().expect("locked");
+	classified.forgot();
+	classified.appservices.remove(appservice_id);
+}
+
+/// Forgets the rooms classified longer ago than the age.
+#[implement(super::Service)]
+#[tracing::instrument(level = "debug", skip(self))]
+pub(super) fn trim_appservice_interest(&self, max_age: Duration) {
+	let now = Instant::now();
+	let mut classified = self.appservice_interest.write().expect("locked");
+
+	classified
+		.appservices
+		.values_mut()
+		.for_each(|interest| interest.trim(max_age, now));
+
+	classified
+		.appservices
+		.retain(|_, interest| interest.counts() != (0, 0));
+}
+
+#[implement(super::Service)]
+pub(super) fn appservice_interest_usage(&self, out: &mut (dyn Write + Send)) -> Result {
+	let classified = self.appservice_interest.read()?;
+	for (id, interest) in &classified.appservices {
+		let (interested, uninterested) = interest.counts();
+		writeln!(
+			out,
+			"appservice_interest[{id}]: {interested} interested, {uninterested} uninterested"
+		)?;
+	}
+
+	Ok(())
+}
+
+/// Whether an event concerns an appservice given the interest of its room.
+/// Events in rooms of no interest only concern it when they are membership
+/// events targeting its users, e.g. invites; only for those is
+/// `targets_appservice` evaluated.
+pub fn event_concerns_appservice<F>(
+	room_interested: bool,
+	kind: &TimelineEventType,
+	targets_appservice: F,
+) -> bool
+where
+	F: FnOnce() -> bool,
+{
+	room_interested || (*kind == TimelineEventType::RoomMember && targets_appservice())
+}
+
+impl Classified {
+	fn forgot(&mut self) { self.generation = self.generation.wrapping_add(1); }
+}
+
+impl Interest {
+	/// Whether the room is of interest; `None` when not classified.
+	pub(super) fn get(&self, room_id: &RoomId) -> Option<bool> {
+		if self.interested.contains_key(room_id) {
+			Some(true)
+		} else if self.uninterested.contains_key(room_id) {
+			Some(false)
+		} else {
+			None
+		}
+	}
+
+	pub(super) fn set(&mut self, room_id: &RoomId, interested: bool) {
+		let (into, from) = if interested {
+			(&mut self.interested, &mut self.uninterested)
+		} else {
+			(&mut self.uninterested, &mut self.interested)
+		};
+
+		from.remove(room_id);
+		into.insert(room_id.to_owned(), Instant::now());
+	}
+
+	pub(super) fn forget(&mut self, room_id: &RoomId) {
+		self.interested.remove(room_id);
+		self.uninterested.remove(room_id);
+	}
+
+	/// Forgets the rooms classified longer ago than the age at the time.
+	pub(super) fn trim(&mut self, max_age: Duration, now: Instant) {
+		let fresh =
+			|classified: &mut Instant| now.saturating_duration_since(*classified) <= max_age;
+
+		self.interested
+			.retain(|_, classified| fresh(classified));
+		self.uninterested
+			.retain(|_, classified| fresh(classified));
+	}
+
+	pub(super) fn counts(&self) -> (usize, usize) {
+		(self.interested.len(), self.uninterested.len())
+	}
+}
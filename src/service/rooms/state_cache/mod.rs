@@ -1,11 +1,18 @@
+mod appservice;
+mod pending;
+mod shared;
+#[cfg(test)]
+mod tests;
 mod update;
 mod via;
 
 use std::{
-	collections::HashMap,
-	sync::{Arc, RwLock},
+	fmt::Write,
+	sync::{Arc, atomic::AtomicBool},
+	time::Duration,
 };
 
+use async_trait::async_trait;
 use futures::{Stream, StreamExt, future::join5, pin_mut};
 use ruma::{
 	OwnedRoomId, RoomId, ServerName, UserId,
@@ -13,20 +20,29 @@
 	serde::Raw,
 };
 use tuwunel_core::{
-	Result, implement,
-	result::LogErr,
-	trace,
-	utils::{ReadyExt, stream::TryIgnore},
+	Result, implement, trace,
+	utils::{
+		ReadyExt,
+		stream::{BroadbandExt, DELETE_BATCH_SIZE, TryIgnore, delete_batched},
+	},
 	warn,
 };
 use tuwunel_database::{Deserialized, Ignore, Interfix, Map};
 
-use crate::appservice::RegistrationInfo;
+use self::appservice::AppserviceInterest;
+pub use self::{
+	appservice::event_concerns_appservice,
+	pending::PENDING_ROOMS_COUNTED,
+	shared::{SHARED_ROOMS_INDEXED, SharedRoomsMismatch},
+};
 
 pub struct Service {
-	appservice_in_room_cache: AppServiceInRoomCache,
+	appservice_interest: AppserviceInterest,
 	services: Arc<crate::services::OnceServices>,
 	db: Data,
+	shared_rooms_indexed: AtomicBool,
+	shared_rooms_lock: tokio::sync::Mutex<()>,
+	pending_rooms_lock: tokio::sync::Mutex<()>,
 }
 
 struct Data {
@@ -41,20 +57,24 @@ struct Data {
 	roomuserid_knockedcount: Arc<Map>,
 	roomuseroncejoinedids: Arc<Map>,
 	serverroomids: Arc<Map>,
+	serveruserid_sharedroomcount: Arc<Map>,
+	userid_invitedroomscount: Arc<Map>,
+	userid_knockedroomscount: Arc<Map>,
+	useridleftcount_roomid: Arc<Map>,
 	userroomid_invitestate: Arc<Map>,
 	userroomid_joined: Arc<Map>,
 	userroomid_leftstate: Arc<Map>,
 	userroomid_knockedstate: Arc<Map>,
 }
 
-type AppServiceInRoomCache = RwLock<HashMap<OwnedRoomId, HashMap<String, bool>>>;
 type StrippedStateEventItem = (OwnedRoomId, Vec<Raw<AnyStrippedStateEvent>>);
 type SyncStateEventItem = (OwnedRoomId, Vec<Raw<AnySyncStateEvent>>);
 
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
-			appservice_in_room_cache: RwLock::new(HashMap::new()),
+			appservice_interest: AppserviceInterest::default(),
 			services: args.services.clone(),
 			db: Data {
 				roomid_knockedcount: args.db["roomid_knockedcount"].clone(),
@@ -68,73 +88,33 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				roomuserid_knockedcount: args.db["roomuserid_knockedcount"].clone(),
 				roomuseroncejoinedids: args.db["roomuseroncejoinedids"].clone(),
 				serverroomids: args.db["serverroomids"].clone(),
+				serveruserid_sharedroomcount: args.db["serveruserid_sharedroomcount"].clone(),
+				userid_invitedroomscount: args.db["userid_invitedroomscount"].clone(),
+				userid_knockedroomscount: args.db["userid_knockedroomscount"].clone(),
+				useridleftcount_roomid: args.db["useridleftcount_roomid"].clone(),
 				userroomid_invitestate: args.db["userroomid_invitestate"].clone(),
 				userroomid_joined: args.db["userroomid_joined"].clone(),
 				userroomid_leftstate: args.db["userroomid_leftstate"].clone(),
 				userroomid_knockedstate: args.db["userroomid_knockedstate"].clone(),
 			},
+			shared_rooms_indexed: args.db["global"]
+				.get_blocking(SHARED_ROOMS_INDEXED)
+				.is_ok()
+				.into(),
+			shared_rooms_lock: tokio::sync::Mutex::default(),
+			pending_rooms_lock: tokio::sync::Mutex::default(),
 		}))
 	}
 
-	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
-}
-
-#[implement(Service)]
-#[tracing::instrument(level = "trace", skip_all)]
-pub async fn appservice_in_room(&self, room_id: &RoomId, appservice: &RegistrationInfo) -> bool {
-	if let Some(cached) = self
-		.appservice_in_room_cache
-		.read()
-		.expect("locked")
-		.get(room_id)
-		.and_then(|map| map.get(&appservice.registration.id))
-		.copied()
-	{
-		return cached;
+	async fn memory_usage(&self, out: &mut (dyn Write + Send)) -> Result {
+		self.appservice_interest_usage(out)
 	}
 
-	let bridge_user_id = UserId::parse_with_server_name(
-		appservice.registration.sender_localpart.as_str(),
-		self.services.globals.server_name(),
-	);
+	async fn clear_cache(&self) { self.clear_appservice_interest(); }
 
-	let Ok(bridge_user_id) = bridge_user_id.log_err() else {
-		return false;
-	};
+	async fn trim_cache(&self, max_age: Duration) { self.trim_appservice_interest(max_age); }
 
-	let in_room = self.is_joined(&bridge_user_id, room_id).await
-		|| self
-			.room_members(room_id)
-			.ready_any(|user_id| appservice.users.is_match(user_id.as_str()))
-			.await;
-
-	self.appservice_in_room_cache
-		.write()
-		.expect("locked")
-		.entry(room_id.into())
-		.or_default()
-		.insert(appservice.registration.id.clone(), in_room);
-
-	in_room
-}
-
-#[implement(Service)]
-pub fn get_appservice_in_room_cache_usage(&self) -> (usize, usize) {
-	let cache = self
-		.appservice_in_room_cache
-		.read()
-		.expect("locked");
-
-	(cache.len(), cache.capacity())
-}
-
-#[implement(Service)]
-#[tracing::instrument(level = "debug", skip_all)]
-pub fn clear_appservice_in_room_cache(&self) {
-	self.appservice_in_room_cache
-		.write()
-		.expect("locked")
-		.clear();
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
 /// Returns an iterator of all servers participating in this room.
@@ -179,6 +159,10 @@ pub fn server_rooms<'a>(
 #[implement(Service)]
 #[tracing::instrument(skip(self), level = "trace")]
 pub async fn server_sees_user(&self, server: &ServerName, user_id: &UserId) -> bool {
+	if self.shared_rooms_indexed() && self.services.globals.user_is_local(user_id) {
+		return self.shared_room_count(server, user_id).await > 0;
+	}
+
 	self.server_rooms(server)
 		.any(|room_id| self.is_joined(user_id, room_id))
 		.await
@@ -498,6 +482,52 @@ pub fn rooms_left<'a>(
 		.ignore_err()
 }
 
+/// The rooms the user left after `since` and by `until`, in the order they
+/// were left. A leave followed by a join or another leave is skipped.
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub fn rooms_left_between<'a>(
+	&'a self,
+	user_id: &'a UserId,
+	since: u64,
+	until: u64,
+) -> impl Stream<Item = &'a RoomId> + Send + 'a {
+	type KeyVal<'a> = ((&'a UserId, u64), &'a RoomId);
+
+	let start = (user_id, since.saturating_add(1));
+	self.db
+		.useridleftcount_roomid
+		.stream_from(&start)
+		.ignore_err()
+		.ready_take_while(move |&((user, count), _): &KeyVal<'_>| {
+			user == user_id && count <= until
+		})
+		.broad_filter_map(async |((_, count), room_id): KeyVal<'_>| {
+			let left_count = self.get_left_count(room_id, user_id).await.ok();
+
+			(left_count == Some(count)).then_some(room_id)
+		})
+}
+
+/// Indexes the leaves recorded before the index of leaves by count existed.
+#[implement(Service)]
+pub async fn index_rooms_left(&self) -> usize {
+	type KeyVal<'a> = ((&'a RoomId, &'a UserId), u64);
+
+	self.db
+		.roomuserid_leftcount
+		.stream()
+		.ignore_err()
+		.ready_fold(0_usize, |indexed, ((room_id, user_id), count): KeyVal<'_>| {
+			self.db
+				.useridleftcount_roomid
+				.put((user_id, count), room_id);
+
+			indexed.saturating_add(1)
+		})
+		.await
+}
+
 #[implement(Service)]
 #[tracing::instrument(skip(self), level = "trace")]
 pub async fn user_membership(
@@ -577,8 +607,9 @@ pub async fn is_left(&self, user_id: &UserId, room_id: &RoomId) -> bool {
 
 #[implement(Service)]
 #[tracing::instrument(skip(self), level = "trace")]
-pub async fn delete_room_join_counts(&self, room_id: &RoomId, force: bool) -> Result {
+pub async fn delete_room_join_counts(&self, room_id: &RoomId, force: bool) -> Result<usize> {
 	let prefix = (room_id, Interfix);
+	let cancelled = || self.services.server.is_stopping();
 
 	self.db.roomid_knockedcount.remove(room_id);
 
@@ -588,78 +619,86 @@ pub async fn delete_room_join_counts(&self, room_id: &RoomId, force: bool) -> Re
 
 	self.db.roomid_joinedcount.remove(room_id);
 
-	self.db
-		.roomserverids
-		.keys_prefix(&prefix)
-		.ignore_err()
-		.ready_for_each(|key: (&RoomId, &ServerName)| {
-			trace!("Removing key: {key:?}");
-			self.db.roomserverids.del(key);
-
-			let reverse_key = (key.1, key.0);
-			trace!("Removing reverse key: {reverse_key:?}");
-			self.db.serverroomids.del(reverse_key);
-		})
+	let servers: Vec<_> = self
+		.room_servers(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
 		.await;
 
-	self.db
-		.roomuserid_invitecount
-		.keys_prefix(&prefix)
-		.ignore_err()
-		.ready_for_each(|key: (&RoomId, &UserId)| {
-			trace!("Removing key: {key:?}");
-			self.db.roomuserid_invitecount.del(key);
-
-			let reverse_key = (key.1, key.0);
-			trace!("Removing reverse key: {reverse_key:?}");
-			self.db.userroomid_invitestate.del(reverse_key);
-		})
-		.await;
-
-	self.db
-		.roomuserid_joined
-		.keys_prefix(&prefix)
-		.ignore_err()
-		.ready_for_each(|key: (&RoomId, &UserId)| {
-			trace!("Removing key: {key:?}");
-			self.db.roomuserid_joined.del(key);
-
-			let reverse_key = (key.1, key.0);
-			trace!("Removing reverse key: {reverse_key:?}");
-			self.db.userroomid_joined.del(reverse_key);
-		})
-		.await;
+	for server in &servers {
+		self.shared_rooms_server_removed(server, room_id)
+			.await;
+	}
 
-	self.db
-		.roomuserid_knockedcount
-		.keys_prefix(&prefix)
-		.ignore_err()
-		.ready_for_each(|key: (&RoomId, &UserId)| {
+	let mut deleted = delete_batched(
+		self.db
+			.roomserverids
+			.keys_prefix(&prefix)
+			.ignore_err(),
+		DELETE_BATCH_SIZE,
+		|key: (&RoomId, &ServerName)| {
 			trace!("Removing key: {key:?}");
-			self.db.roomuserid_knockedcount.del(key);
+			self.db.roomserverids.del(key);
 
 			let reverse_key = (key.1, key.0);
 			trace!("Removing reverse key: {reverse_key:?}");
-			self.db.userroomid_knockedstate.del(reverse_key);
-		})
-		.await;
-
-	self.db
-		.roomuserid_leftcount
-		.keys_prefix(&prefix)
-		.ignore_err()
-		.ready_filter(|(_, user_id): &(&RoomId, &UserId)| {
-			force || !self.services.globals.user_is_local(user_id)
-		})
-		.ready_for_each(|key: (&RoomId, &UserId)| {
-			trace!("Removing key: {key:?}");
-			self.db.roomuserid_leftcount.del(key);
+			self.db.serverroomids.del(reverse_key);
+			Ok(())
+		},
+		cancelled,
+	)
+	.await?;
+
+	self.pending_room_deleted(room_id).await;
+	self.appservice_room_changed(room_id);
+
+	for (map, reverse) in [
+		(&self.db.roomuserid_invitecount, &self.db.userroomid_invitestate),
+		(&self.db.roomuserid_joined, &self.db.userroomid_joined),
+		(&self.db.roomuserid_knockedcount, &self.db.userroomid_knockedstate),
+	] {
+		deleted = deleted.saturating_add(
+			delete_batched(
+				map.keys_prefix(&prefix).ignore_err(),
+				DELETE_BATCH_SIZE,
+				|key: (&RoomId, &UserId)| {
+					trace!("Removing key: {key:?}");
+					map.del(key);
+
+					let reverse_key = (key.1, key.0);
+					trace!("Removing reverse key: {reverse_key:?}");
+					reverse.del(reverse_key);
+					Ok(())
+				},
+				cancelled,
+			)
+			.await?,
+		);
+	}
 
-			let reverse_key = (key.1, key.0);
-			trace!("Removing reverse key: {reverse_key:?}");
-			self.db.userroomid_leftstate.del(reverse_key);
-		})
-		.await;
+	deleted = deleted.saturating_add(
+		delete_batched(
+			self.db
+				.roomuserid_leftcount
+				.keys_prefix(&prefix)
+				.ignore_err()
+				.ready_filter(|(_, user_id): &(&RoomId, &UserId)| {
+					force || !self.services.globals.user_is_local(user_id)
+				}),
+			DELETE_BATCH_SIZE,
+			|key: (&RoomId, &UserId)| {
+				trace!("Removing key: {key:?}");
+				self.db.roomuserid_leftcount.del(key);
+
+				let reverse_key = (key.1, key.0);
+				trace!("Removing reverse key: {reverse_key:?}");
+				self.db.userroomid_leftstate.del(reverse_key);
+				Ok(())
+			},
+			cancelled,
+		)
+		.await?,
+	);
 
-	Ok(())
+	Ok(deleted)
 }
@@ -0,0 +1,163 @@
+//! Number of rooms each user is invited to and knocking on, keyed by user ID.
+//! The totals follow the entries of the user in `userroomid_invitestate` and
+//! `userroomid_knockedstate`, letting sync skip those sections entirely for
+//! the common case of a user with none; they must be adjusted wherever such
+//! an entry is added or removed.
+
+use std::{collections::HashMap, sync::Arc};
+
+use futures::StreamExt;
+use ruma::{OwnedUserId, RoomId, UserId};
+use tuwunel_core::{
+	debug_info, implement,
+	utils::stream::{ReadyExt, TryIgnore},
+};
+use tuwunel_database::{Deserialized, Ignore, Map};
+
+/// Key under `global` recording that the totals were counted.
+pub const PENDING_ROOMS_COUNTED: &[u8] = b"count_userid_pendingroomscount";
+
+/// Whether a user is invited to and knocking on a room.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(super) struct Pending {
+	invited: bool,
+	knocked: bool,
+}
+
+/// Number of rooms the user is currently invited to.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "trace")]
+pub async fn invited_rooms_count(&self, user_id: &UserId) -> u64 {
+	self.db
+		.userid_invitedroomscount
+		.get(user_id)
+		.await
+		.deserialized()
+		.unwrap_or(0)
+}
+
+/// Number of rooms the user is currently knocking on.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "trace")]
+pub async fn knocked_rooms_count(&self, user_id: &UserId) -> u64 {
+	self.db
+		.userid_knockedroomscount
+		.get(user_id)
+		.await
+		.deserialized()
+		.unwrap_or(0)
+}
+
+#[implement(super::Service)]
+pub(super) async fn pending(&self, user_id: &UserId, room_id: &RoomId) -> Pending {
+	Pending {
+		invited: self.is_invited(user_id, room_id).await,
+		knocked: self.is_knocked(user_id, room_id).await,
+	}
+}
+
+/// Adjusts the totals of the user to a membership change in one room, given
+/// its pending state before and after the change. Only actual additions and
+/// removals count, so repeated invites or rejections of a room no longer
+/// pending leave the totals as they are.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub(super) async fn pending_changed(&self, user_id: &UserId, before: Pending, after: Pending) {
+	if before == after {
+		return;
+	}
+
+	let _lock = self.pending_rooms_lock.lock().await;
+	for (map, before, after) in [
+		(&self.db.userid_invitedroomscount, before.invited, after.invited),
+		(&self.db.userid_knockedroomscount, before.knocked, after.knocked),
+	] {
+		if before == after {
+			continue;
+		}
+
+		let count = map
+			.get(user_id)
+			.await
+			.deserialized()
+			.unwrap_or(0_u64);
+
+		put_count(map, user_id, adjusted_count(count, before, after));
+	}
+}
+
+/// Uncounts a room for each of its pending members, before all membership of
+/// the room is deleted.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub(super) async fn pending_room_deleted(&self, room_id: &RoomId) {
+	let invited: Vec<OwnedUserId> = self
+		.room_members_invited(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let knocked: Vec<OwnedUserId> = self
+		.room_members_knocked(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let before = Pending { invited: true, knocked: false };
+	for user_id in &invited {
+		self.pending_changed(user_id, before, Pending::default())
+			.await;
+	}
+
+	let before = Pending { invited: false, knocked: true };
+	for user_id in &knocked {
+		self.pending_changed(user_id, before, Pending::default())
+			.await;
+	}
+}
+
+/// Recounts the totals of all users from scratch.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn rebuild_pending_rooms(&self) {
+	let _lock = self.pending_rooms_lock.lock().await;
+	for (map, states) in [
+		(&self.db.userid_invitedroomscount, &self.db.userroomid_invitestate),
+		(&self.db.userid_knockedroomscount, &self.db.userroomid_knockedstate),
+	] {
+		map.clear().await;
+
+		let mut counts: HashMap<OwnedUserId, u64> = HashMap::new();
+		states
+			.keys()
+			.ignore_err()
+			.ready_for_each(|(user_id, _): (&UserId, Ignore)| {
+				let count = counts.entry(user_id.to_owned()).or_default();
+				*count = count.saturating_add(1);
+			})
+			.await;
+
+		for (user_id, count) in &counts {
+			put_count(map, user_id, *count);
+		}
+
+		debug_info!(users = counts.len(), "Recounted pending rooms");
+	}
+}
+
+fn put_count(map: &Arc<Map>, user_id: &UserId, count: u64) {
+	if count > 0 {
+		map.raw_aput::<8, _, _>(user_id, count);
+	} else {
+		map.remove(user_id);
+	}
+}
+
+/// The total after a room went from pending or not `before` to `after`.
+pub(super) fn adjusted_count(count: u64, before: bool, after: bool) -> u64 {
+	match (before, after) {
+		| (false, true) => count.saturating_add(1),
+		| (true, false) => count.saturating_sub(1),
+		| _ => count,
+	}
+}
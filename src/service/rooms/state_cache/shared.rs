@@ -0,0 +1,245 @@
+//! Index of the number of rooms each server shares with each local user,
+//! keyed by `(server, user_id)`. The count of a pair is the number of rooms
+//! the user is joined to which list the server in `roomserverids`; it must be
+//! adjusted wherever either side of that relation changes.
+
+use std::{
+	collections::{HashMap, HashSet},
+	sync::atomic::Ordering,
+};
+
+use futures::StreamExt;
+use ruma::{OwnedServerName, OwnedUserId, RoomId, ServerName, UserId};
+use tuwunel_core::{
+	debug_info, implement,
+	utils::stream::{ReadyExt, TryIgnore},
+};
+use tuwunel_database::{Deserialized, Ignore};
+
+/// Key under `global` recording that the index was built.
+pub const SHARED_ROOMS_INDEXED: &[u8] = b"index_serveruserid_sharedroomcount";
+
+/// A pair whose indexed count differs from its recomputation as
+/// `(server, user_id, indexed, computed)`.
+pub type SharedRoomsMismatch = (OwnedServerName, OwnedUserId, u64, u64);
+
+/// Whether the index can be relied upon; false until it was built.
+#[implement(super::Service)]
+#[inline]
+pub fn shared_rooms_indexed(&self) -> bool { self.shared_rooms_indexed.load(Ordering::Acquire) }
+
+#[implement(super::Service)]
+pub fn set_shared_rooms_indexed(&self) {
+	self.shared_rooms_indexed
+		.store(true, Ordering::Release);
+}
+
+/// Number of rooms joined by the local user which the server participates in.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "trace")]
+pub async fn shared_room_count(&self, server: &ServerName, user_id: &UserId) -> u64 {
+	self.db
+		.serveruserid_sharedroomcount
+		.qry(&(server, user_id))
+		.await
+		.deserialized()
+		.unwrap_or(0)
+}
+
+/// Counts a room just joined by a user for every server already in it.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub(super) async fn shared_rooms_joined(&self, user_id: &UserId, room_id: &RoomId) {
+	let servers: Vec<OwnedServerName> = self
+		.room_servers(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for server in &servers {
+		self.adjust_shared_room_count(server, user_id, true)
+			.await;
+	}
+}
+
+/// Uncounts a room just left by a user for every server still in it.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub(super) async fn shared_rooms_left(&self, user_id: &UserId, room_id: &RoomId) {
+	let servers: Vec<OwnedServerName> = self
+		.room_servers(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for server in &servers {
+		self.adjust_shared_room_count(server, user_id, false)
+			.await;
+	}
+}
+
+/// Counts a room for each of its local members after a server joined it.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub(super) async fn shared_rooms_server_added(&self, server: &ServerName, room_id: &RoomId) {
+	let users: Vec<OwnedUserId> = self
+		.local_users_in_room(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for user_id in &users {
+		self.adjust_shared_room_count(server, user_id, true)
+			.await;
+	}
+}
+
+/// Uncounts a room for each of its local members after a server left it.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub(super) async fn shared_rooms_server_removed(&self, server: &ServerName, room_id: &RoomId) {
+	let users: Vec<OwnedUserId> = self
+		.local_users_in_room(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for user_id in &users {
+		self.adjust_shared_room_count(server, user_id, false)
+			.await;
+	}
+}
+
+#[implement(super::Service)]
+async fn adjust_shared_room_count(&self, server: &ServerName, user_id: &UserId, increment: bool) {
+	let key = (server, user_id);
+	let _lock = self.shared_rooms_lock.lock().await;
+	let count = self.shared_room_count(server, user_id).await;
+	let count = if increment {
+		count.saturating_add(1)
+	} else {
+		count.saturating_sub(1)
+	};
+
+	if count > 0 {
+		self.db
+			.serveruserid_sharedroomcount
+			.put_aput::<8, _, _>(key, count);
+	} else {
+		self.db.serveruserid_sharedroomcount.del(key);
+	}
+}
+
+/// Computes the shared room counts of a local user from the room indexes.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn compute_shared_room_counts(
+	&self,
+	user_id: &UserId,
+) -> HashMap<OwnedServerName, u64> {
+	let room_ids: Vec<_> = self
+		.rooms_joined(user_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut counts = HashMap::new();
+	for room_id in &room_ids {
+		self.room_servers(room_id)
+			.ready_for_each(|server| {
+				let count: &mut u64 = counts.entry(server.to_owned()).or_default();
+				*count = count.saturating_add(1);
+			})
+			.await;
+	}
+
+	counts
+}
+
+/// The local users joined to any room, each once; the users the index has
+/// entries for. Taken from the memberships rather than the accounts, which
+/// would miss those without a password, e.g. the users of appservices.
+#[implement(super::Service)]
+pub async fn local_joined_users(&self) -> Vec<OwnedUserId> {
+	let mut users: Vec<OwnedUserId> = self
+		.db
+		.userroomid_joined
+		.keys()
+		.ignore_err()
+		.ready_filter_map(|(user_id, _): (&UserId, Ignore)| {
+			self.services
+				.globals
+				.user_is_local(user_id)
+				.then(|| user_id.to_owned())
+		})
+		.collect()
+		.await;
+
+	// Keyed by the user first, so the rooms of a user are adjacent.
+	users.dedup();
+	users
+}
+
+/// Rebuilds the index from scratch for all local users.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn rebuild_shared_rooms(&self) {
+	let _lock = self.shared_rooms_lock.lock().await;
+	self.db.serveruserid_sharedroomcount.clear().await;
+
+	let users = self.local_joined_users().await;
+
+	let mut entries: usize = 0;
+	for user_id in &users {
+		for (server, count) in self.compute_shared_room_counts(user_id).await {
+			entries = entries.saturating_add(1);
+			self.db
+				.serveruserid_sharedroomcount
+				.put_aput::<8, _, _>((&server, user_id), count);
+		}
+	}
+
+	debug_info!(users = users.len(), entries, "Rebuilt shared rooms index");
+}
+
+/// Compares the index against a recomputation for the given local users.
+#[implement(super::Service)]
+#[tracing::instrument(skip_all, level = "debug")]
+pub async fn check_shared_rooms(&self, users: &[OwnedUserId]) -> Vec<SharedRoomsMismatch> {
+	let sampled: HashSet<&UserId> = users.iter().map(AsRef::as_ref).collect();
+
+	let mut indexed: HashMap<(OwnedServerName, OwnedUserId), u64> = HashMap::new();
+	self.db
+		.serveruserid_sharedroomcount
+		.stream()
+		.ignore_err()
+		.ready_filter(|((_, user_id), _): &((&ServerName, &UserId), u64)| {
+			sampled.contains(user_id)
+		})
+		.ready_for_each(|((server, user_id), count)| {
+			indexed.insert((server.to_owned(), user_id.to_owned()), count);
+		})
+		.await;
+
+	let mut mismatches = Vec::new();
+	for user_id in users {
+		for (server, computed) in self.compute_shared_room_counts(user_id).await {
+			let indexed = indexed
+				.remove(&(server.clone(), user_id.clone()))
+				.unwrap_or(0);
+
+			if indexed != computed {
+				mismatches.push((server, user_id.clone(), indexed, computed));
+			}
+		}
+	}
+
+	// Whatever remains is indexed for servers no longer sharing a room.
+	mismatches.extend(
+		indexed
+			.into_iter()
+			.map(|((server, user_id), count)| (server, user_id, count, 0)),
+	);
+
+	mismatches
+}
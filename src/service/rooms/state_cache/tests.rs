@@ -0,0 +1,558 @@
+use std::time::{Duration, Instant};
+
+use ruma::{
+	OwnedServerName, RoomAliasId,
+	events::room::member::{MembershipState, RoomMemberEventContent},
+	room_id, server_name, user_id,
+};
+use tuwunel_core::matrix::pdu::PduBuilder;
+
+use super::{
+	SHARED_ROOMS_INDEXED,
+	appservice::Interest,
+	pending::adjusted_count,
+	update::forget_on_leave,
+	via::{InviteVia, add_vias, live_vias, parse_legacy_vias},
+};
+use crate::{appservice::RegistrationInfo, fixture::Fixture};
+
+#[test]
+fn invite_then_reject_counts_zero() {
+	// invite, reject, then a repeated rejection of the retracted invite
+	let count = adjusted_count(0, false, true);
+	assert_eq!(count, 1);
+
+	let count = adjusted_count(count, true, false);
+	assert_eq!(count, 0);
+
+	let count = adjusted_count(count, false, false);
+	assert_eq!(count, 0);
+}
+
+#[test]
+fn repeated_invite_counts_once() {
+	let count = adjusted_count(2, false, true);
+	let count = adjusted_count(count, true, true);
+	assert_eq!(count, 3);
+
+	// a knock replacing the invite leaves the room pending as knocked only
+	let count = adjusted_count(count, true, false);
+	assert_eq!(count, 2);
+}
+
+#[test]
+fn uncount_saturates() {
+	assert_eq!(adjusted_count(0, true, false), 0);
+}
+
+const BRIDGE: &str = r##"
+[global.appservice.bridge]
+as_token = "bridge_as_token"
+hs_token = "bridge_hs_token"
+sender_localpart = "bridge"
+
+[[global.appservice.bridge.users]]
+regex = "@bridge_.*:fixture\\.localhost"
+
+[[global.appservice.bridge.aliases]]
+regex = "#bridged_.*:fixture\\.localhost"
+"##;
+
+/// The bridge's registration, once the appservice service has read it.
+async fn bridge(fixture: &Fixture) -> RegistrationInfo {
+	for _ in 0..100 {
+		if let Some(info) = fixture.appservice.read().await.get("bridge") {
+			return info.clone();
+		}
+
+		tokio::time::sleep(Duration::from_millis(10)).await;
+	}
+
+	panic!("bridge registration not loaded");
+}
+
+#[tokio::test]
+async fn appservice_interest_follows_room() {
+	let fixture = Fixture::start_with(BRIDGE).await;
+	let bridge = bridge(&fixture).await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bridged = embedded
+		.create_user("bridge_bob", Some("password"))
+		.await
+		.expect("bridged user");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	let classification = || {
+		fixture
+			.state_cache
+			.appservice_classification("bridge", &room_id)
+			.0
+	};
+
+	assert!(
+		!fixture
+			.state_cache
+			.appservice_interested(&room_id, &bridge)
+			.await,
+		"none of the bridge's users are in the room"
+	);
+	assert_eq!(classification(), Some(false), "classified on first use");
+
+	embedded
+		.join_room(&bridged, &room_id)
+		.await
+		.expect("bridged user joins");
+	assert_eq!(classification(), Some(true), "a join of a bridged user marks the room");
+
+	embedded
+		.leave_room(&bridged, &room_id)
+		.await
+		.expect("bridged user leaves");
+	assert_eq!(classification(), None, "a leave forgets the room");
+	assert!(
+		!fixture
+			.state_cache
+			.appservice_interested(&room_id, &bridge)
+			.await,
+		"classified again without the bridged user"
+	);
+
+	let alias = RoomAliasId::parse("#bridged_room:fixture.localhost").expect("valid alias");
+	fixture
+		.alias
+		.set_alias(&alias, &room_id, &alice)
+		.expect("alias set");
+	assert_eq!(classification(), None, "an alias change forgets the room");
+	assert!(
+		fixture
+			.state_cache
+			.appservice_interested(&room_id, &bridge)
+			.await,
+		"an alias in the bridge's namespace"
+	);
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn appservice_classification_across_forget_dropped() {
+	let fixture = Fixture::start_with(BRIDGE).await;
+	let state_cache = &fixture.state_cache;
+	let room_id = room_id!("!room:fixture.localhost");
+
+	let (classified, generation) = state_cache.appservice_classification("bridge", room_id);
+	assert_eq!(classified, None, "rooms start unclassified");
+
+	// a member leaves while the room is being classified
+	state_cache.appservice_room_changed(room_id);
+	assert!(
+		state_cache.classify_for_appservice("bridge", room_id, true, generation),
+		"the classification made is still used"
+	);
+	assert_eq!(
+		state_cache
+			.appservice_classification("bridge", room_id)
+			.0,
+		None,
+		"but not kept, as it may predate the leave"
+	);
+
+	let (_, generation) = state_cache.appservice_classification("bridge", room_id);
+	state_cache.classify_for_appservice("bridge", room_id, false, generation);
+	assert_eq!(
+		state_cache
+			.appservice_classification("bridge", room_id)
+			.0,
+		Some(false),
+		"kept when nothing was forgotten meanwhile"
+	);
+
+	// the bridge's namespaces are reloaded while the room is being classified
+	let (_, generation) = state_cache.appservice_classification("bridge", room_id);
+	state_cache.clear_appservice_interest_for("bridge");
+	state_cache.classify_for_appservice("bridge", room_id, true, generation);
+	assert_eq!(
+		state_cache
+			.appservice_classification("bridge", room_id)
+			.0,
+		None,
+		"classifications against the old namespaces are not kept"
+	);
+
+	fixture.stop().await;
+}
+
+#[test]
+fn trim_forgets_old_classifications() {
+	let room_id = room_id!("!room:example.com");
+	let other_id = room_id!("!other:example.com");
+	let mut interest = Interest::default();
+
+	interest.set(room_id, true);
+	interest.set(other_id, false);
+	let max_age = Duration::from_secs(60);
+
+	interest.trim(max_age, Instant::now());
+	assert_eq!(interest.counts(), (1, 1), "recent classifications are kept");
+
+	let later = Instant::now()
+		.checked_add(Duration::from_secs(61))
+		.expect("valid instant");
+
+	interest.trim(max_age, later);
+	assert_eq!(interest.get(room_id), None, "old classifications are forgotten");
+	assert_eq!(interest.counts(), (0, 0), "whether interested or not");
+}
+
+#[test]
+fn leave_forgets_when_configured() {
+	let leave = MembershipState::Leave;
+
+	assert!(forget_on_leave(&leave, true, true, false), "leaving forgets the room");
+	assert!(!forget_on_leave(&leave, true, false, true), "unless disabled");
+	assert!(!forget_on_leave(&leave, false, true, false), "a kick keeps the room");
+	assert!(!forget_on_leave(&MembershipState::Ban, true, true, false), "so does a ban");
+	assert!(forget_on_leave(&leave, false, false, true), "unless kicks are forgotten");
+	assert!(forget_on_leave(&MembershipState::Ban, false, false, true), "as are bans");
+	assert!(!forget_on_leave(&MembershipState::Join, true, true, true), "joins never forget");
+}
+
+fn server(name: &str) -> OwnedServerName {
+	OwnedServerName::parse(name).expect("valid server name")
+}
+
+fn servers(vias: &[InviteVia]) -> Vec<&str> {
+	vias.iter()
+		.map(|via| via.server.as_str())
+		.collect()
+}
+
+#[test]
+fn invite_via_keeps_newest() {
+	let mut vias = Vec::new();
+	for i in 0..50_u64 {
+		vias = add_vias(vias, [server(&format!("server{i}.example.com"))], i);
+	}
+
+	let expected: Vec<String> = (40..50)
+		.rev()
+		.map(|i| format!("server{i}.example.com"))
+		.collect();
+
+	assert_eq!(servers(&vias), expected, "the newest ten survive, most recent first");
+}
+
+#[test]
+fn invite_via_dedups_case_insensitively() {
+	let vias = add_vias(Vec::new(), [server("a.example.com"), server("b.example.com")], 1);
+	let vias = add_vias(vias, [server("B.Example.com")], 2);
+
+	assert_eq!(
+		servers(&vias),
+		["B.Example.com", "a.example.com"],
+		"a repeated server moves first"
+	);
+	assert_eq!(vias[0].added_at, 2, "the repeated server is renewed");
+}
+
+#[test]
+fn invite_via_ttl() {
+	let vias = add_vias(Vec::new(), [server("old.example.com")], 1_000);
+	let vias = add_vias(vias, [server("new.example.com")], 5_000);
+
+	assert_eq!(
+		servers(&live_vias(vias.clone(), 6_000, 2_000)),
+		["new.example.com"],
+		"old servers are skipped"
+	);
+	assert_eq!(live_vias(vias, 6_000, 0).len(), 2, "a zero TTL keeps every server");
+}
+
+#[test]
+fn invite_via_legacy_value() {
+	let value = [b"a.example.com".as_slice(), b"b.example.com"].join(&0xFF);
+	let vias = parse_legacy_vias(&value, 7);
+
+	assert_eq!(
+		servers(&vias),
+		["a.example.com", "b.example.com"],
+		"servers joined by 0xFF are read"
+	);
+	assert!(vias.iter().all(|via| via.added_at == 7), "they count as added now");
+}
+
+#[tokio::test]
+async fn invite_via_legacy_value_rewritten() {
+	let fixture = Fixture::start_with("invite_via_servers_ttl = 1").await;
+	let room_id = room_id!("!legacy:fixture.localhost");
+	let value = [b"a.example.com".as_slice(), b"b.example.com"].join(&0xFF);
+	fixture
+		.state_cache
+		.db
+		.roomid_inviteviaservers
+		.insert(room_id, &value);
+
+	let servers = fixture
+		.state_cache
+		.servers_invite_via(room_id)
+		.await;
+	assert_eq!(servers.len(), 2, "the legacy servers are read");
+
+	let stored = fixture
+		.state_cache
+		.db
+		.roomid_inviteviaservers
+		.get(room_id)
+		.await
+		.expect("still stored");
+	let stored: Vec<InviteVia> =
+		serde_json::from_slice(&stored).expect("rewritten with timestamps");
+	assert_eq!(stored.len(), 2, "both servers are kept");
+
+	tokio::time::sleep(Duration::from_millis(1100)).await;
+	let servers = fixture
+		.state_cache
+		.servers_invite_via(room_id)
+		.await;
+	assert!(servers.is_empty(), "the TTL runs from the first read");
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn leaving_forgets_but_kick_keeps() {
+	let fixture = Fixture::start_with("forget_rooms_on_leave = true").await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+	let carol = embedded
+		.create_user("carol", Some("password"))
+		.await
+		.expect("carol");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	for user_id in [&bob, &carol] {
+		embedded
+			.join_room(user_id, &room_id)
+			.await
+			.expect("joined");
+	}
+
+	embedded
+		.leave_room(&bob, &room_id)
+		.await
+		.expect("bob leaves");
+	assert!(!fixture.state_cache.is_left(&bob, &room_id).await, "bob forgot the room");
+
+	let state_lock = fixture.state.mutex.lock(&room_id).await;
+	fixture
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				carol.to_string(),
+				&RoomMemberEventContent::new(MembershipState::Leave),
+			),
+			&alice,
+			&room_id,
+			&state_lock,
+		)
+		.await
+		.expect("carol kicked");
+	drop(state_lock);
+	assert!(
+		fixture
+			.state_cache
+			.is_left(&carol, &room_id)
+			.await,
+		"carol keeps the room they were kicked from"
+	);
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn shared_rooms_follow_memberships() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let state_cache = &fixture.state_cache;
+	let remote = server_name!("remote.example");
+	let bob = user_id!("@bob:remote.example");
+
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let carol = embedded
+		.create_user("carol", Some("password"))
+		.await
+		.expect("carol");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+
+	let remote_membership = async |membership| {
+		state_cache
+			.update_membership(
+				&room_id,
+				bob,
+				RoomMemberEventContent::new(membership),
+				bob,
+				None,
+				None,
+				true,
+			)
+			.await
+			.expect("membership updated");
+	};
+
+	assert!(state_cache.shared_rooms_indexed(), "a new database is indexed");
+	assert!(!state_cache.server_sees_user(remote, &alice).await, "no room shared yet");
+
+	remote_membership(MembershipState::Join).await;
+	assert_eq!(
+		state_cache
+			.shared_room_count(remote, &alice)
+			.await,
+		1,
+		"server added"
+	);
+	assert!(state_cache.server_sees_user(remote, &alice).await, "room shared");
+
+	embedded
+		.join_room(&carol, &room_id)
+		.await
+		.expect("carol joins");
+	assert_eq!(
+		state_cache
+			.shared_room_count(remote, &carol)
+			.await,
+		1,
+		"user joined"
+	);
+
+	embedded
+		.leave_room(&carol, &room_id)
+		.await
+		.expect("carol leaves");
+	assert_eq!(
+		state_cache
+			.shared_room_count(remote, &carol)
+			.await,
+		0,
+		"user left"
+	);
+	assert!(!state_cache.server_sees_user(remote, &carol).await, "room no longer shared");
+
+	remote_membership(MembershipState::Leave).await;
+	assert_eq!(
+		state_cache
+			.shared_room_count(remote, &alice)
+			.await,
+		0,
+		"server removed"
+	);
+	assert!(!state_cache.server_sees_user(remote, &alice).await, "room no longer shared");
+
+	assert!(
+		state_cache
+			.check_shared_rooms(&[alice, carol])
+			.await
+			.is_empty(),
+		"the index matches its recomputation"
+	);
+
+	fixture.stop().await;
+}
+
+/// Users without a password, e.g. of appservices, are counted when the index
+/// is built on an existing database.
+#[tokio::test]
+async fn shared_rooms_built_for_users_without_password() {
+	let fixture = Fixture::start_with(BRIDGE).await;
+	let embedded = fixture.embedded();
+	let remote = server_name!("remote.example");
+	let bob = user_id!("@bob:remote.example");
+
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bridged = embedded
+		.create_user("bridge_carol", None)
+		.await
+		.expect("bridged user");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	embedded
+		.join_room(&bridged, &room_id)
+		.await
+		.expect("bridged user joins");
+	fixture
+		.state_cache
+		.update_membership(
+			&room_id,
+			bob,
+			RoomMemberEventContent::new(MembershipState::Join),
+			bob,
+			None,
+			None,
+			true,
+		)
+		.await
+		.expect("remote user joins");
+
+	// As on a database from before the index
+	fixture.db["serveruserid_sharedroomcount"]
+		.clear()
+		.await;
+	fixture.db["global"].remove(SHARED_ROOMS_INDEXED);
+
+	let fixture = fixture.restart().await;
+	let state_cache = &fixture.state_cache;
+	assert!(state_cache.shared_rooms_indexed(), "the index is built on start");
+	assert_eq!(
+		state_cache
+			.shared_room_count(remote, &bridged)
+			.await,
+		1,
+		"the user without a password is counted"
+	);
+	assert_eq!(
+		state_cache
+			.shared_room_count(remote, &alice)
+			.await,
+		1,
+		"the user with a password is counted"
+	);
+
+	let users = state_cache.local_joined_users().await;
+	assert_eq!(users.len(), 2, "each joined user once");
+	assert!(
+		state_cache
+			.check_shared_rooms(&users)
+			.await
+			.is_empty(),
+		"the index matches its recomputation"
+	);
+
+	fixture.stop().await;
+}
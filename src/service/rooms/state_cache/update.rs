@@ -2,7 +2,7 @@
 
 use futures::StreamExt;
 use ruma::{
-	OwnedServerName, RoomId, UserId,
+	OwnedServerName, OwnedUserId, RoomId, UserId,
 	events::{
 		AnyStrippedStateEvent, AnySyncStateEvent, GlobalAccountDataEventType,
 		RoomAccountDataEventType, StateEventType,
@@ -14,7 +14,7 @@
 	},
 	serde::Raw,
 };
-use tuwunel_core::{Result, implement, is_not_empty, utils::ReadyExt, warn};
+use tuwunel_core::{Result, implement, is_not_empty, result::LogErr, utils::ReadyExt, warn};
 use tuwunel_database::{Json, serialize_key};
 
 /// Update current membership data.
@@ -54,8 +54,16 @@ pub async fn update_membership(
 				.create(user_id, None, None)
 				.await?;
 		}
+
+		// A new member event may carry a new profile.
+		self.services.users.forget_remote_profile(user_id);
 	}
 
+	// Only local users are tracked by the shared rooms index.
+	let local = self.services.globals.user_is_local(user_id);
+	let was_joined = local && self.is_joined(user_id, room_id).await;
+	let was_pending = self.pending(user_id, room_id).await;
+
 	match &membership {
 		| MembershipState::Join => {
 			// Increment the counter for a unique value and hold the guard even though the
@@ -144,12 +152,33 @@ pub async fn update_membership(
 			self.mark_as_invited(user_id, room_id, last_state, invite_via)
 				.await;
 		},
+		| MembershipState::Knock => {
+			self.mark_as_knocked(user_id, room_id, last_state);
+		},
 		| MembershipState::Leave | MembershipState::Ban => {
 			self.mark_as_left(user_id, room_id);
+			if local {
+				self.services
+					.user
+					.delete_thread_notification_counts(user_id, room_id)
+					.await
+					.log_err()
+					.ok();
+			} else {
+				self.services
+					.users
+					.forget_unshared_remote_devices(user_id)
+					.await;
+			}
 
+			let config = &self.services.config;
 			if self.services.globals.user_is_local(user_id)
-				&& (self.services.config.forget_forced_upon_leave
-					|| self.services.metadata.is_banned(room_id).await
+				&& (forget_on_leave(
+					&membership,
+					sender == user_id,
+					config.forget_rooms_on_leave,
+					config.forget_rooms_on_kick_ban,
+				) || self.services.metadata.is_banned(room_id).await
 					|| self.services.metadata.is_disabled(room_id).await)
 			{
 				self.forget(room_id, user_id);
@@ -158,6 +187,26 @@ pub async fn update_membership(
 		| _ => {},
 	}
 
+	if local {
+		match (was_joined, self.is_joined(user_id, room_id).await) {
+			| (false, true) => self.shared_rooms_joined(user_id, room_id).await,
+			| (true, false) => self.shared_rooms_left(user_id, room_id).await,
+			| _ => {},
+		}
+	}
+
+	let is_pending = self.pending(user_id, room_id).await;
+	self.pending_changed(user_id, was_pending, is_pending)
+		.await;
+
+	match &membership {
+		| MembershipState::Join =>
+			self.appservice_user_joined(user_id, room_id)
+				.await,
+		| MembershipState::Leave | MembershipState::Ban => self.appservice_room_changed(room_id),
+		| _ => {},
+	}
+
 	if update_joined_count {
 		self.update_joined_count(room_id).await;
 	}
@@ -206,21 +255,47 @@ pub async fn update_joined_count(&self, room_id: &RoomId) {
 		.roomid_knockedcount
 		.raw_put(room_id, knockedcount);
 
+	let mut left_servers = Vec::new();
 	self.room_servers(room_id)
 		.ready_for_each(|old_joined_server| {
-			if joined_servers.remove(old_joined_server) {
-				return;
+			if !joined_servers.remove(old_joined_server) {
+				left_servers.push(old_joined_server.to_owned());
 			}
-
-			// Server not in room anymore
-			let roomserver_id = (room_id, old_joined_server);
-			let serverroom_id = (old_joined_server, room_id);
-
-			self.db.roomserverids.del(roomserver_id);
-			self.db.serverroomids.del(serverroom_id);
 		})
 		.await;
 
+	// Server not in room anymore
+	for server in &left_servers {
+		self.shared_rooms_server_removed(server, room_id)
+			.await;
+
+		let roomserver_id = (room_id, server);
+		let serverroom_id = (server, room_id);
+
+		self.db.roomserverids.del(roomserver_id);
+		self.db.serverroomids.del(serverroom_id);
+	}
+
+	// The device lists of remote users are only followed while we share a room
+	if left_servers
+		.iter()
+		.any(|server| self.services.globals.server_is_ours(server))
+	{
+		let remote_members: Vec<OwnedUserId> = self
+			.room_members(room_id)
+			.ready_filter(|user_id| !self.services.globals.user_is_local(user_id))
+			.map(ToOwned::to_owned)
+			.collect()
+			.await;
+
+		for user_id in &remote_members {
+			self.services
+				.users
+				.forget_unshared_remote_devices(user_id)
+				.await;
+		}
+	}
+
 	// Now only new servers are in joined_servers anymore
 	for server in &joined_servers {
 		let roomserver_id = (room_id, server);
@@ -228,12 +303,10 @@ pub async fn update_joined_count(&self, room_id: &RoomId) {
 
 		self.db.roomserverids.put_raw(roomserver_id, []);
 		self.db.serverroomids.put_raw(serverroom_id, []);
-	}
 
-	self.appservice_in_room_cache
-		.write()
-		.expect("locked")
-		.remove(room_id);
+		self.shared_rooms_server_added(server, room_id)
+			.await;
+	}
 }
 
 /// Direct DB function to directly mark a user as joined. It is not
@@ -294,6 +367,9 @@ pub(crate) fn mark_as_left(&self, user_id: &UserId, room_id: &RoomId) {
 	self.db
 		.roomuserid_leftcount
 		.raw_aput::<8, _, _>(&roomuser_id, *count);
+	self.db
+		.useridleftcount_roomid
+		.put((user_id, *count), room_id);
 
 	self.db.userroomid_joined.remove(&userroom_id);
 	self.db.roomuserid_joined.remove(&roomuser_id);
@@ -320,7 +396,7 @@ pub(crate) fn mark_as_left(&self, user_id: &UserId, room_id: &RoomId) {
 /// `update_membership` instead
 #[implement(super::Service)]
 #[tracing::instrument(skip(self), level = "debug")]
-pub(crate) fn _mark_as_knocked(
+pub(crate) fn mark_as_knocked(
 	&self,
 	user_id: &UserId,
 	room_id: &RoomId,
@@ -368,6 +444,22 @@ pub fn forget(&self, room_id: &RoomId, user_id: &UserId) {
 	self.db.roomuserid_leftcount.del(roomuser_id);
 }
 
+/// Whether a local user leaving the room forgets it right away; a user who
+/// was kicked or banned keeps the room to see why unless configured
+/// otherwise.
+pub(super) fn forget_on_leave(
+	membership: &MembershipState,
+	by_self: bool,
+	on_leave: bool,
+	on_kick_ban: bool,
+) -> bool {
+	match membership {
+		| MembershipState::Leave if by_self => on_leave,
+		| MembershipState::Leave | MembershipState::Ban => on_kick_ban,
+		| _ => false,
+	}
+}
+
 #[implement(super::Service)]
 #[tracing::instrument(level = "debug", skip(self))]
 fn mark_as_once_joined(&self, user_id: &UserId, room_id: &RoomId) {
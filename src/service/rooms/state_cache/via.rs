@@ -1,39 +1,36 @@
-use futures::{Stream, StreamExt, stream::iter};
+use std::collections::HashSet;
+
 use itertools::Itertools;
 use ruma::{
-	OwnedServerName, RoomId, ServerName,
+	OwnedServerName, RoomId,
 	events::{StateEventType, room::power_levels::RoomPowerLevelsEventContent},
 	int,
 };
-use tuwunel_core::{
-	Result, implement,
-	utils::{StreamTools, stream::TryIgnore},
-	warn,
-};
-use tuwunel_database::Ignore;
+use serde::{Deserialize, Serialize};
+use tuwunel_core::{Result, implement, utils, utils::StreamTools};
+use tuwunel_database::Json;
+
+/// Most servers kept to join a room through from invites to it.
+const INVITE_VIA_MAX: usize = 10;
+
+/// A server to join a room through from an invite, with when it was added in
+/// milliseconds since the epoch.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct InviteVia {
+	pub(super) server: OwnedServerName,
+	pub(super) added_at: u64,
+}
 
 #[implement(super::Service)]
 #[tracing::instrument(level = "debug", skip(self, servers))]
 pub async fn add_servers_invite_via(&self, room_id: &RoomId, servers: Vec<OwnedServerName>) {
-	let mut servers: Vec<_> = self
-		.servers_invite_via(room_id)
-		.map(ToOwned::to_owned)
-		.chain(iter(servers.into_iter()))
-		.collect()
-		.await;
-
-	servers.sort_unstable();
-	servers.dedup();
-
-	let servers = servers
-		.iter()
-		.map(|server| server.as_bytes())
-		.collect_vec()
-		.join(&[0xFF][..]);
+	let now = utils::millis_since_unix_epoch();
+	let vias = self.invite_vias(room_id, now).await;
+	let vias = add_vias(vias, servers, now);
 
 	self.db
 		.roomid_inviteviaservers
-		.insert(room_id.as_bytes(), &servers);
+		.raw_put(room_id.as_bytes(), Json(&vias));
 }
 
 /// Gets up to five servers that are likely to be in the room in the
@@ -76,17 +73,84 @@ pub async fn servers_route_via(&self, room_id: &RoomId) -> Result<Vec<OwnedServe
 	Ok(servers)
 }
 
+/// Servers to join the room through from invites to it, most recent first.
+/// Those added longer than `invite_via_servers_ttl` ago are forgotten.
 #[implement(super::Service)]
 #[tracing::instrument(skip(self), level = "debug")]
-pub fn servers_invite_via<'a>(
-	&'a self,
-	room_id: &'a RoomId,
-) -> impl Stream<Item = &ServerName> + Send + 'a {
-	type KeyVal<'a> = (Ignore, Vec<&'a ServerName>);
+pub async fn servers_invite_via(&self, room_id: &RoomId) -> Vec<OwnedServerName> {
+	let ttl = self
+		.services
+		.server
+		.config
+		.invite_via_servers_ttl
+		.saturating_mul(1000);
+
+	let now = utils::millis_since_unix_epoch();
+	let vias = self.invite_vias(room_id, now).await;
+	let known = vias.len();
+	let vias = live_vias(vias, now, ttl);
+
+	if vias.is_empty() && known > 0 {
+		self.db.roomid_inviteviaservers.remove(room_id);
+	} else if vias.len() < known {
+		self.db
+			.roomid_inviteviaservers
+			.raw_put(room_id.as_bytes(), Json(&vias));
+	}
 
+	vias.into_iter().map(|via| via.server).collect()
+}
+
+#[implement(super::Service)]
+async fn invite_vias(&self, room_id: &RoomId, now: u64) -> Vec<InviteVia> {
+	let Ok(value) = self.db.roomid_inviteviaservers.get(room_id).await else {
+		return Vec::new();
+	};
+
+	if let Ok(vias) = serde_json::from_slice(&value) {
+		return vias;
+	}
+
+	// Rewritten on the first read so the TTL runs from then rather than
+	// restarting on every read.
+	let vias = parse_legacy_vias(&value, now);
 	self.db
 		.roomid_inviteviaservers
-		.stream_raw_prefix(room_id)
-		.ignore_err()
-		.map(|(_, servers): KeyVal<'_>| *servers.last().expect("at least one server"))
+		.raw_put(room_id.as_bytes(), Json(&vias));
+
+	vias
+}
+
+/// The servers stored joined by 0xFF, before each had a timestamp; they count
+/// as added now.
+pub(super) fn parse_legacy_vias(value: &[u8], now: u64) -> Vec<InviteVia> {
+	value
+		.split(|&b| b == 0xFF)
+		.filter_map(|server| str::from_utf8(server).ok())
+		.filter_map(|server| OwnedServerName::parse(server).ok())
+		.map(|server| InviteVia { server, added_at: now })
+		.collect()
+}
+
+/// Adds the servers ahead of those known, keeping the most recent entry of
+/// each server regardless of case, up to the most kept.
+pub(super) fn add_vias<I>(vias: Vec<InviteVia>, servers: I, now: u64) -> Vec<InviteVia>
+where
+	I: IntoIterator<Item = OwnedServerName>,
+{
+	let mut seen = HashSet::new();
+	servers
+		.into_iter()
+		.map(|server| InviteVia { server, added_at: now })
+		.chain(vias)
+		.filter(|via| seen.insert(via.server.as_str().to_ascii_lowercase()))
+		.take(INVITE_VIA_MAX)
+		.collect()
+}
+
+/// The servers added within the TTL; a TTL of zero keeps them all.
+pub(super) fn live_vias(vias: Vec<InviteVia>, now: u64, ttl: u64) -> Vec<InviteVia> {
+	vias.into_iter()
+		.filter(|via| ttl == 0 || via.added_at.saturating_add(ttl) > now)
+		.collect()
 }
@@ -0,0 +1,91 @@
+//! Detection of corrupt state groups. A group which fails to load, because its
+//! diff or that of a group beneath it does not decode, is reported against the
+//! room rather than taking it down; `debug room-integrity` then checks every
+//! group of the room.
+
+use std::collections::HashSet;
+
+use ruma::RoomId;
+use tuwunel_core::{Error, Result, error, implement};
+
+use super::ShortStateInfoVec;
+use crate::rooms::short::ShortStateHash;
+
+/// Outcome of checking the state groups of a room.
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+	/// State groups decoded, including the layers beneath those checked.
+	pub checked: usize,
+
+	/// State groups which failed to decode, with the error.
+	pub corrupt: Vec<(ShortStateHash, String)>,
+}
+
+/// Loads the state group of the room as `load_shortstatehash_info` does; a
+/// failure is logged and reported against the room.
+#[implement(super::Service)]
+pub async fn load_room_shortstatehash_info(
+	&self,
+	room_id: &RoomId,
+	shortstatehash: ShortStateHash,
+) -> Result<ShortStateInfoVec> {
+	self.load_shortstatehash_info(shortstatehash)
+		.await
+		.inspect_err(|e| self.report_corrupt(room_id, shortstatehash, e))
+}
+
+/// Marks the room for `debug room-integrity`.
+#[implement(super::Service)]
+pub fn report_corrupt(&self, room_id: &RoomId, shortstatehash: ShortStateHash, e: &Error) {
+	error!(%room_id, shortstatehash, "Failed to load state: {e}");
+	self.corrupt
+		.lock()
+		.expect("locked")
+		.entry(room_id.to_owned())
+		.or_default()
+		.insert(shortstatehash);
+}
+
+/// The state groups reported corrupt in the room since it last checked clean.
+#[implement(super::Service)]
+pub fn reported_corrupt(&self, room_id: &RoomId) -> HashSet<ShortStateHash> {
+	self.corrupt
+		.lock()
+		.expect("locked")
+		.get(room_id)
+		.cloned()
+		.unwrap_or_default()
+}
+
+/// Decodes the state groups and every layer beneath them, bypassing the
+/// cache. A clean check clears the reports against the room.
+#[implement(super::Service)]
+pub async fn check_integrity<I>(&self, room_id: &RoomId, shortstatehashes: I) -> IntegrityReport
+where
+	I: IntoIterator<Item = ShortStateHash> + Send,
+	I::IntoIter: Send,
+{
+	let mut report = IntegrityReport::default();
+	let mut checked = HashSet::new();
+	for shortstatehash in shortstatehashes {
+		let mut next = Some(shortstatehash);
+		while let Some(shortstatehash) = next.take_if(|next| checked.insert(*next)) {
+			match self.get_statediff(shortstatehash).await {
+				| Ok(diff) => next = diff.parent,
+				| Err(e) => report
+					.corrupt
+					.push((shortstatehash, e.to_string())),
+			}
+		}
+	}
+
+	report.checked = checked.len();
+	if report.corrupt.is_empty() {
+		self.corrupt
+			.lock()
+			.expect("locked")
+			.remove(room_id);
+	}
+
+	report
+}
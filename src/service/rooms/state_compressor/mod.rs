@@ -1,5 +1,10 @@
+mod integrity;
+mod purge;
+#[cfg(test)]
+mod tests;
+
 use std::{
-	collections::{BTreeSet, HashMap},
+	collections::{BTreeSet, HashMap, HashSet},
 	fmt::{Debug, Write},
 	mem::size_of,
 	sync::{Arc, Mutex},
@@ -8,25 +13,30 @@
 use async_trait::async_trait;
 use futures::{Stream, StreamExt};
 use lru_cache::LruCache;
-use ruma::{EventId, RoomId};
+use ruma::{EventId, OwnedRoomId, RoomId};
 use tuwunel_core::{
-	Result,
+	Err, Result,
 	arrayvec::ArrayVec,
-	at, checked, err, expected, implement, utils,
+	at, checked, err, implement, utils,
 	utils::{bytes, math::usize_from_f64, stream::IterStream},
 };
 use tuwunel_database::Map;
 
+pub use self::{integrity::IntegrityReport, purge::PurgeStats};
 use crate::rooms::short::{ShortEventId, ShortId, ShortStateHash, ShortStateKey};
 
 pub struct Service {
 	pub stateinfo_cache: Mutex<StateInfoLruCache>,
+	corrupt: Mutex<HashMap<OwnedRoomId, HashSet<ShortStateHash>>>,
 	db: Data,
 	services: Arc<crate::services::OnceServices>,
 }
 
 struct Data {
 	shortstatehash_statediff: Arc<Map>,
+	shorteventid_shortstatehash: Arc<Map>,
+	roomsynctoken_shortstatehash: Arc<Map>,
+	statehash_shortstatehash: Arc<Map>,
 }
 
 #[derive(Clone)]
@@ -66,8 +76,12 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			f64::from(config.stateinfo_cache_capacity) * config.cache_capacity_modifier;
 		Ok(Arc::new(Self {
 			stateinfo_cache: LruCache::new(usize_from_f64(cache_capacity)?).into(),
+			corrupt: Mutex::default(),
 			db: Data {
 				shortstatehash_statediff: args.db["shortstatehash_statediff"].clone(),
+				shorteventid_shortstatehash: args.db["shorteventid_shortstatehash"].clone(),
+				roomsynctoken_shortstatehash: args.db["roomsynctoken_shortstatehash"].clone(),
+				statehash_shortstatehash: args.db["statehash_shortstatehash"].clone(),
 			},
 			services: args.services.clone(),
 		}))
@@ -406,8 +420,10 @@ pub async fn save_state(
 		});
 	}
 
+	// A corrupt previous state is reported and skipped; the new state is then
+	// saved in full rather than as a diff to it.
 	let states_parents = if let Some(p) = previous_shortstatehash {
-		self.load_shortstatehash_info(p)
+		self.load_room_shortstatehash_info(room_id, p)
 			.await
 			.unwrap_or_default()
 	} else {
@@ -452,7 +468,6 @@ pub async fn save_state(
 #[tracing::instrument(skip(self), level = "debug", name = "get")]
 async fn get_statediff(&self, shortstatehash: ShortStateHash) -> Result<StateDiff> {
 	const BUFSIZE: usize = size_of::<ShortStateHash>();
-	const STRIDE: usize = size_of::<ShortStateHash>();
 
 	let value = self
 		.db
@@ -463,30 +478,56 @@ async fn get_statediff(&self, shortstatehash: ShortStateHash) -> Result<StateDif
 			err!(Database("Failed to find StateDiff from short {shortstatehash:?}: {e}"))
 		})?;
 
-	let parent = utils::u64_from_bytes(&value[0..size_of::<u64>()])
-		.ok()
-		.take_if(|parent| *parent != 0);
+	parse_statediff(&value)
+		.map_err(|e| err!(Database("Corrupt StateDiff from short {shortstatehash:?}: {e}")))
+}
+
+#[implement(Service)]
+fn save_statediff(&self, shortstatehash: ShortStateHash, diff: &StateDiff) {
+	self.db
+		.shortstatehash_statediff
+		.insert(&shortstatehash.to_be_bytes(), &serialize_statediff(diff));
+}
+
+/// Decodes a stored StateDiff: the parent, or zero without one, followed by
+/// the added events and, when any were removed, a zero marker and the removed
+/// events. Truncated or garbled values are an error rather than misparsed.
+fn parse_statediff(value: &[u8]) -> Result<StateDiff> {
+	const STRIDE: usize = size_of::<ShortStateHash>();
+	const ENTRY: usize = size_of::<CompressedStateEvent>();
+	const MARKER: [u8; STRIDE] = 0_u64.to_be_bytes();
+
+	let Some((parent, mut rest)) = value.split_first_chunk::<STRIDE>() else {
+		return Err!(Database("Too short for a parent: {} bytes", value.len()));
+	};
 
-	debug_assert!(value.len().is_multiple_of(STRIDE), "value not aligned to stride");
-	let _num_values = value.len() / STRIDE;
+	let parent = Some(u64::from_be_bytes(*parent)).filter(|parent| *parent != 0);
 
-	let mut add_mode = true;
 	let mut added = CompressedState::new();
 	let mut removed = CompressedState::new();
+	let mut marked = false;
+	while !rest.is_empty() {
+		if rest.starts_with(&MARKER) {
+			if marked {
+				return Err!(Database("Repeated marker {} bytes from the end", rest.len()));
+			}
 
-	let mut i = STRIDE;
-	while let Some(v) = value.get(i..expected!(i + 2 * STRIDE)) {
-		if add_mode && v.starts_with(&0_u64.to_be_bytes()) {
-			add_mode = false;
-			i = expected!(i + STRIDE);
+			marked = true;
+			rest = &rest[STRIDE..];
 			continue;
 		}
-		if add_mode {
-			added.insert(v.try_into()?);
+
+		let Some((entry, tail)) = rest.split_first_chunk::<ENTRY>() else {
+			return Err!(Database("Truncated entry of {} bytes", rest.len()));
+		};
+
+		if marked {
+			removed.insert(*entry);
 		} else {
-			removed.insert(v.try_into()?);
+			added.insert(*entry);
 		}
-		i = expected!(i + 2 * STRIDE);
+
+		rest = tail;
 	}
 
 	Ok(StateDiff {
@@ -496,8 +537,7 @@ async fn get_statediff(&self, shortstatehash: ShortStateHash) -> Result<StateDif
 	})
 }
 
-#[implement(Service)]
-fn save_statediff(&self, shortstatehash: ShortStateHash, diff: &StateDiff) {
+fn serialize_statediff(diff: &StateDiff) -> Vec<u8> {
 	let mut value = Vec::<u8>::with_capacity(
 		2_usize
 			.saturating_add(diff.added.len())
@@ -518,9 +558,7 @@ fn save_statediff(&self, shortstatehash: ShortStateHash, diff: &StateDiff) {
 		}
 	}
 
-	self.db
-		.shortstatehash_statediff
-		.insert(&shortstatehash.to_be_bytes(), &value);
+	value
 }
 
 #[inline]
@@ -541,7 +579,7 @@ pub(crate) fn compress_state_event(
 
 #[inline]
 #[must_use]
-pub(crate) fn parse_compressed_state_event(
+pub fn parse_compressed_state_event(
 	compressed_event: CompressedStateEvent,
 ) -> (ShortStateKey, ShortEventId) {
 	use utils::u64_from_u8;
@@ -0,0 +1,288 @@
+//! Purging state groups nothing refers to anymore. A group is kept when the
+//! current state of a room, the state at an event or a sync token refers to
+//! it, or when a kept group is layered upon it; the rest are orphans, left
+//! behind by state resets and deleted rooms. Groups created after a purge
+//! started are never touched and a deleted group is unreachable from whatever
+//! remains, so a purge can be interrupted and run again.
+//!
+//! Computing the state of a room reuses the group of the same state by its
+//! hash, under the state lock of the room. The hashes of the orphans are
+//! forgotten first so none can be reused anymore; the references are then
+//! looked up again after taking the lock of every room, by when any reuse
+//! begun before was stored, and the orphans referred to since are kept.
+
+use std::{
+	collections::{HashMap, HashSet},
+	iter::successors,
+};
+
+use futures::StreamExt;
+use ruma::{OwnedRoomId, RoomId};
+use tuwunel_core::{
+	Result, debug, implement, info,
+	utils::{self, ReadyExt, stream::TryIgnore},
+};
+
+use crate::rooms::short::ShortStateHash;
+
+/// Rows deleted between yielding to other tasks.
+const BATCH_SIZE: usize = 1024;
+
+/// Parent and size of each state group.
+pub(super) type Layers = HashMap<ShortStateHash, Layer>;
+
+pub(super) struct Layer {
+	pub(super) parent: Option<ShortStateHash>,
+	pub(super) bytes: usize,
+}
+
+/// Outcome of a purge.
+#[derive(Debug, Default)]
+pub struct PurgeStats {
+	/// State groups in the database.
+	pub groups: usize,
+
+	/// State groups found orphaned.
+	pub orphans: usize,
+
+	/// Estimated size of the orphaned groups.
+	pub bytes: usize,
+
+	/// Orphaned groups deleted; none on a dry run.
+	pub deleted: usize,
+}
+
+/// Deletes the state groups nothing refers to. With a room, only orphans
+/// layered upon the current state of that room are considered; orphans of
+/// rooms which are gone entirely are only found without one.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "info")]
+pub async fn purge_orphaned_states(
+	&self,
+	room_id: Option<&RoomId>,
+	dry_run: bool,
+) -> Result<PurgeStats> {
+	let horizon = self.services.globals.current_count();
+	let layers = self.layers().await;
+	let roots = self.roots().await;
+	let kept = reachable(&layers, roots);
+
+	let mut orphans = orphans(&layers, &kept, horizon);
+	if let Some(room_id) = room_id {
+		let current = self
+			.services
+			.state
+			.get_room_shortstatehash(room_id)
+			.await?;
+
+		let room = reachable(&layers, [current]);
+		orphans.retain(|&shortstatehash| descends_from(&layers, shortstatehash, &room));
+	}
+
+	let bytes = orphans
+		.iter()
+		.filter_map(|shortstatehash| layers.get(shortstatehash))
+		.map(|layer| layer.bytes)
+		.fold(0_usize, usize::saturating_add);
+
+	let deleted = if dry_run {
+		0
+	} else {
+		self.forget_hashes(&orphans).await;
+
+		let layers = self.layers().await;
+		let kept = reachable(&layers, self.roots().await);
+		orphans.retain(|shortstatehash| !kept.contains(shortstatehash));
+
+		self.delete_states(&orphans).await
+	};
+
+	info!(
+		groups = layers.len(),
+		orphans = orphans.len(),
+		bytes,
+		deleted,
+		"Purged orphaned state groups"
+	);
+
+	Ok(PurgeStats {
+		groups: layers.len(),
+		orphans: orphans.len(),
+		bytes,
+		deleted,
+	})
+}
+
+/// Loads the parent and size of every state group.
+#[implement(super::Service)]
+async fn layers(&self) -> Layers {
+	self.db
+		.shortstatehash_statediff
+		.raw_stream()
+		.ignore_err()
+		.ready_filter_map(|(key, value)| {
+			let shortstatehash = utils::u64_from_bytes(key).ok()?;
+			let parent = value
+				.get(..size_of::<ShortStateHash>())
+				.and_then(|parent| utils::u64_from_bytes(parent).ok())
+				.filter(|parent| *parent != 0);
+
+			let bytes = key.len().saturating_add(value.len());
+			Some((shortstatehash, Layer { parent, bytes }))
+		})
+		.collect()
+		.await
+}
+
+/// The state groups referred to directly. The current state of each room is
+/// read under the state lock of the room, before the state at events and
+/// sync tokens, so the references stored by state computations holding any
+/// of the locks before are all seen.
+#[implement(super::Service)]
+async fn roots(&self) -> HashSet<ShortStateHash> {
+	let room_ids: Vec<OwnedRoomId> = self
+		.services
+		.metadata
+		.iter_ids()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut roots = HashSet::new();
+	for room_id in &room_ids {
+		let _state_lock = self.services.state.mutex.lock(room_id).await;
+		if let Ok(shortstatehash) = self
+			.services
+			.state
+			.get_room_shortstatehash(room_id)
+			.await
+		{
+			roots.insert(shortstatehash);
+		}
+	}
+
+	self.db
+		.shorteventid_shortstatehash
+		.raw_stream()
+		.chain(self.db.roomsynctoken_shortstatehash.raw_stream())
+		.ignore_err()
+		.ready_filter_map(|(_, shortstatehash)| utils::u64_from_bytes(shortstatehash).ok())
+		.ready_for_each(|shortstatehash| {
+			roots.insert(shortstatehash);
+		})
+		.await;
+
+	roots
+}
+
+/// Forgets the hashes naming the groups, so no state computation reuses them
+/// from now on.
+#[implement(super::Service)]
+async fn forget_hashes(&self, orphans: &[ShortStateHash]) {
+	let orphaned: HashSet<_> = orphans.iter().copied().collect();
+	let hashes: Vec<Vec<u8>> = self
+		.db
+		.statehash_shortstatehash
+		.raw_stream()
+		.ignore_err()
+		.ready_filter_map(|(hash, shortstatehash)| {
+			utils::u64_from_bytes(shortstatehash)
+				.ok()
+				.filter(|shortstatehash| orphaned.contains(shortstatehash))
+				.map(|_| hash.to_vec())
+		})
+		.collect()
+		.await;
+
+	for batch in hashes.chunks(BATCH_SIZE) {
+		if !self.services.server.running() {
+			return;
+		}
+
+		let _cork = self.services.db.cork();
+		for hash in batch {
+			self.db.statehash_shortstatehash.remove(hash);
+		}
+
+		tokio::task::yield_now().await;
+	}
+}
+
+/// Deletes the groups in batches, stopping early on shutdown.
+#[implement(super::Service)]
+async fn delete_states(&self, orphans: &[ShortStateHash]) -> usize {
+	let mut deleted = 0_usize;
+	for batch in orphans.chunks(BATCH_SIZE) {
+		if !self.services.server.running() {
+			break;
+		}
+
+		let _cork = self.services.db.cork();
+		for shortstatehash in batch {
+			self.db
+				.shortstatehash_statediff
+				.remove(&shortstatehash.to_be_bytes());
+		}
+
+		deleted = deleted.saturating_add(batch.len());
+		debug!(deleted, total = orphans.len(), "Deleting orphaned state groups");
+		tokio::task::yield_now().await;
+	}
+
+	self.stateinfo_cache
+		.lock()
+		.expect("locked")
+		.clear();
+
+	deleted
+}
+
+/// The roots along with every group they are layered upon.
+pub(super) fn reachable<I>(layers: &Layers, roots: I) -> HashSet<ShortStateHash>
+where
+	I: IntoIterator<Item = ShortStateHash>,
+{
+	let mut reachable = HashSet::new();
+	for root in roots {
+		let mut next = Some(root);
+		while let Some(shortstatehash) = next.take_if(|next| reachable.insert(*next)) {
+			next = layers
+				.get(&shortstatehash)
+				.and_then(|layer| layer.parent);
+		}
+	}
+
+	reachable
+}
+
+/// The unreachable groups no newer than the horizon, in ascending order.
+pub(super) fn orphans(
+	layers: &Layers,
+	reachable: &HashSet<ShortStateHash>,
+	horizon: u64,
+) -> Vec<ShortStateHash> {
+	let mut orphans: Vec<_> = layers
+		.keys()
+		.copied()
+		.filter(|shortstatehash| *shortstatehash <= horizon)
+		.filter(|shortstatehash| !reachable.contains(shortstatehash))
+		.collect();
+
+	orphans.sort_unstable();
+	orphans
+}
+
+/// Whether the group is layered upon any of the ancestors, directly or not.
+pub(super) fn descends_from(
+	layers: &Layers,
+	shortstatehash: ShortStateHash,
+	ancestors: &HashSet<ShortStateHash>,
+) -> bool {
+	successors(Some(shortstatehash), |shortstatehash| {
+		layers
+			.get(shortstatehash)
+			.and_then(|layer| layer.parent)
+	})
+	.take(layers.len().saturating_add(1))
+	.any(|shortstatehash| ancestors.contains(&shortstatehash))
+}
@@ -0,0 +1,173 @@
+use std::{collections::HashSet, sync::Arc};
+
+use super::{
+	CompressedState, StateDiff, compress_state_event, parse_statediff,
+	purge::{Layer, Layers, descends_from, orphans, reachable},
+	serialize_statediff,
+};
+
+/// Groups 1 <- 2 <- 3 and 1 <- 4, a reset branch 2 <- 5 <- 6, and 7 of a
+/// purged room.
+fn layers() -> Layers {
+	[
+		(1, None),
+		(2, Some(1)),
+		(3, Some(2)),
+		(4, Some(1)),
+		(5, Some(2)),
+		(6, Some(5)),
+		(7, None),
+	]
+	.into_iter()
+	.map(|(shortstatehash, parent)| (shortstatehash, Layer { parent, bytes: 16 }))
+	.collect()
+}
+
+#[test]
+fn purge_reachable_follows_parents() {
+	let layers = layers();
+	let kept = reachable(&layers, [3, 4]);
+
+	assert_eq!(kept, HashSet::from([1, 2, 3, 4]), "roots keep every layer beneath them");
+	assert_eq!(
+		orphans(&layers, &kept, u64::MAX),
+		[5, 6, 7],
+		"unreferenced branches and rooms are orphaned"
+	);
+}
+
+#[test]
+fn purge_spares_newer_groups() {
+	let layers = layers();
+	let kept = reachable(&layers, [3]);
+
+	assert_eq!(orphans(&layers, &kept, 5), [4, 5], "groups past the horizon are spared");
+	assert_eq!(orphans(&layers, &kept, 0), [0_u64; 0], "nothing precedes a zero horizon");
+}
+
+#[test]
+fn purge_rerun_finds_nothing() {
+	let mut layers = layers();
+	let kept = reachable(&layers, [3, 4]);
+	let first = orphans(&layers, &kept, u64::MAX);
+
+	// An interrupted run deleted only part of the orphans.
+	layers.remove(&first[0]);
+	let kept = reachable(&layers, [3, 4]);
+	assert_eq!(orphans(&layers, &kept, u64::MAX), first[1..], "the rest is found again");
+
+	for shortstatehash in &first {
+		layers.remove(shortstatehash);
+	}
+
+	let kept = reachable(&layers, [3, 4]);
+	assert!(
+		orphans(&layers, &kept, u64::MAX).is_empty(),
+		"a finished purge leaves no orphans"
+	);
+	assert_eq!(kept.len(), layers.len(), "every remaining group is kept");
+}
+
+#[test]
+fn purge_room_branches() {
+	let layers = layers();
+	let room = reachable(&layers, [3]);
+
+	assert!(descends_from(&layers, 6, &room), "a reset branch descends from the room");
+	assert!(!descends_from(&layers, 7, &room), "another room does not");
+
+	let mut cyclic = layers;
+	cyclic.insert(8, Layer { parent: Some(9), bytes: 16 });
+	cyclic.insert(9, Layer { parent: Some(8), bytes: 16 });
+	assert!(!descends_from(&cyclic, 8, &room), "a corrupt cycle ends the walk");
+}
+
+fn statediff(parent: Option<u64>, added: &[(u64, u64)], removed: &[(u64, u64)]) -> StateDiff {
+	let compress = |events: &[(u64, u64)]| -> CompressedState {
+		events
+			.iter()
+			.map(|&(shortstatekey, shorteventid)| {
+				compress_state_event(shortstatekey, shorteventid)
+			})
+			.collect()
+	};
+
+	StateDiff {
+		parent,
+		added: Arc::new(compress(added)),
+		removed: Arc::new(compress(removed)),
+	}
+}
+
+#[test]
+fn statediff_round_trip() {
+	for diff in [
+		statediff(None, &[], &[]),
+		statediff(None, &[(1, 10), (2, 20)], &[]),
+		statediff(Some(7), &[(1, 11)], &[(1, 10), (3, 30)]),
+		statediff(Some(7), &[], &[(3, 30)]),
+	] {
+		let parsed = parse_statediff(&serialize_statediff(&diff)).expect("valid statediff");
+
+		assert_eq!(parsed.parent, diff.parent, "parent survives");
+		assert_eq!(parsed.added, diff.added, "added events survive");
+		assert_eq!(parsed.removed, diff.removed, "removed events survive");
+	}
+}
+
+#[test]
+fn statediff_truncated() {
+	let diff = statediff(Some(7), &[(1, 11), (2, 20)], &[(1, 10), (3, 30)]);
+	let value = serialize_statediff(&diff);
+
+	for len in 0..value.len() {
+		let Ok(parsed) = parse_statediff(&value[..len]) else {
+			continue;
+		};
+
+		// Cut at an entry boundary, the rest is a valid, smaller diff.
+		assert!(len >= 8, "a value without a parent is an error");
+		assert!(parsed.added.is_subset(&diff.added), "no event is invented: {len}");
+		assert!(parsed.removed.is_subset(&diff.removed), "no event is invented: {len}");
+	}
+
+	for len in [0, 7, 9, 23, value.len().saturating_sub(1)] {
+		assert!(parse_statediff(&value[..len]).is_err(), "a partial entry is an error: {len}");
+	}
+}
+
+#[test]
+fn statediff_garbage() {
+	// xorshift, for garbage which is the same on every run
+	let mut state = 0x2545_F491_4F6C_DD1D_u64;
+	let mut next = move || {
+		state ^= state.wrapping_shl(13);
+		state ^= state.wrapping_shr(7);
+		state ^= state.wrapping_shl(17);
+		state
+	};
+
+	for _ in 0..1024 {
+		let len = usize::try_from(next() % 96).expect("small length");
+		let value: Vec<u8> = (0..len)
+			.map(|_| next().to_be_bytes()[0])
+			.collect();
+
+		// Must not panic; whatever decodes re-encodes within the input.
+		if let Ok(parsed) = parse_statediff(&value) {
+			let events = parsed
+				.added
+				.len()
+				.saturating_add(parsed.removed.len());
+
+			assert!(
+				serialize_statediff(&parsed).len() <= value.len(),
+				"decoded {events} events from {len} bytes"
+			);
+		}
+	}
+
+	let marker = 0_u64.to_be_bytes();
+	let repeated: Vec<u8> = [[0, 0, 0, 0, 0, 0, 0, 7], marker, marker].concat();
+	assert!(parse_statediff(&repeated).is_err(), "a repeated marker is an error");
+}
@@ -9,7 +9,6 @@
 use tuwunel_core::{
 	Event, Result, err,
 	matrix::pdu::{PduCount, PduEvent, PduId, RawPduId},
-	trace,
 	utils::{
 		ReadyExt,
 		stream::{TryIgnore, WidebandExt},
@@ -193,19 +192,14 @@ pub(super) async fn get_participants(&self, root_id: &RawPduId) -> Result<Vec<Ow
 			.deserialized()
 	}
 
-	pub(super) async fn delete_all_rooms_threads(&self, room_id: &RoomId) -> Result {
-		let prefix = (room_id, Interfix);
+	/// Forgets the thread of the root event, deleted from the timeline.
+	pub(super) fn delete_thread(&self, root_id: &RawPduId) {
+		self.db.threadid_userids.remove(root_id);
+	}
 
-		self.db
-			.threadid_userids
-			.keys_prefix_raw(&prefix)
-			.ignore_err()
-			.ready_for_each(|key| {
-				trace!("Removing key: {key:?}");
-				self.db.threadid_userids.remove(key);
-			})
-			.await;
+	pub(super) async fn delete_all_rooms_threads(&self, room_id: &RoomId) -> Result<usize> {
+		let prefix = (room_id, Interfix);
 
-		Ok(())
+		self.db.threadid_userids.del_prefix(&prefix).await
 	}
 }
@@ -5,30 +5,38 @@
 
 use futures::StreamExt;
 use ruma::{
-	CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+	CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedUserId, RoomId,
+	RoomVersionId, UserId,
 	events::{
-		GlobalAccountDataEventType, TimelineEventType,
-		push_rules::PushRulesEvent,
+		TimelineEventType,
 		room::{
 			encrypted::Relation,
 			member::{MembershipState, RoomMemberEventContent},
 			redaction::RoomRedactionEventContent,
 		},
 	},
-	push::{Action, Ruleset, Tweak},
 };
 use tuwunel_core::{
-	Result, err, error, implement,
+	Result, at, err, error, implement,
 	matrix::{
 		event::Event,
 		pdu::{PduCount, PduEvent, PduId, RawPduId},
 	},
-	utils::{self, ReadyExt},
+	utils::{self, ReadyExt, stream::TryIgnore},
 };
 use tuwunel_database::{Json, Map};
 
 use super::{ExtractBody, ExtractRelatesTo, ExtractRelatesToEventId, RoomMutexGuard};
-use crate::{appservice::NamespaceRegex, rooms::state_compressor::CompressedState};
+use crate::{
+	pusher::notification_tweaks,
+	rooms::{
+		directory::affects_summary, state_cache::event_concerns_appservice,
+		state_compressor::CompressedState,
+	},
+};
+
+/// Most events after the one read recounted by `recount_notifications`.
+const RECOUNT_MAX: usize = 1000;
 
 /// Append the incoming event setting the state snapshot to the state from
 /// the server that sent the event.
@@ -173,9 +181,18 @@ pub async fn append_pdu<'a, Leafs>(
 		.read_receipt
 		.private_read_set(pdu.room_id(), pdu.sender(), *next_count2);
 
-	self.services
-		.user
-		.reset_notification_counts(pdu.sender(), pdu.room_id());
+	// Events in a thread are counted for the thread, apart from the main timeline.
+	let thread_root = thread_root(pdu);
+	match &thread_root {
+		| Some(thread_root) => self
+			.services
+			.user
+			.reset_thread_notification_counts(pdu.sender(), pdu.room_id(), thread_root),
+		| None => self
+			.services
+			.user
+			.reset_notification_counts(pdu.sender(), pdu.room_id()),
+	}
 
 	let count = PduCount::Normal(*next_count1);
 	let pdu_id: RawPduId = PduId { shortroomid, shorteventid: count }.into();
@@ -185,21 +202,11 @@ pub async fn append_pdu<'a, Leafs>(
 
 	drop(insert_lock);
 
-	// Don't notify the sender of their own events, and dont send from ignored users
 	let mut push_target: HashSet<_> = self
 		.services
 		.state_cache
 		.active_local_users_in_room(pdu.room_id())
 		.map(ToOwned::to_owned)
-		.ready_filter(|user| *user != pdu.sender())
-		.filter_map(async |recipient_user| {
-			self.services
-				.users
-				.user_is_ignored(pdu.sender(), &recipient_user)
-				.await
-				.eq(&false)
-				.then_some(recipient_user)
-		})
 		.collect()
 		.await;
 
@@ -223,18 +230,19 @@ pub async fn append_pdu<'a, Leafs>(
 
 	let serialized = pdu.to_format();
 	for user in &push_target {
-		let rules_for_user = self
-			.services
-			.account_data
-			.get_global(user, GlobalAccountDataEventType::PushRules)
-			.await
-			.map_or_else(
-				|_| Ruleset::server_default(user),
-				|ev: PushRulesEvent| ev.content.global,
-			);
+		// Don't notify the sender of their own events (from any of their devices),
+		// nor anyone ignoring the sender; this also covers membership targets.
+		if *user == pdu.sender()
+			|| self
+				.services
+				.users
+				.user_is_ignored(pdu.sender(), user)
+				.await
+		{
+			continue;
+		}
 
-		let mut highlight = false;
-		let mut notify = false;
+		let rules_for_user = self.services.pusher.get_ruleset(user).await;
 
 		let power_levels = self
 			.services
@@ -242,25 +250,15 @@ pub async fn append_pdu<'a, Leafs>(
 			.get_power_levels(pdu.room_id())
 			.await?;
 
-		for action in self
+		let actions = self
 			.services
 			.pusher
 			.get_actions(user, &rules_for_user, &power_levels, &serialized, pdu.room_id())
-			.await
-		{
-			match action {
-				| Action::Notify => notify = true,
-				| Action::SetTweak(Tweak::Highlight(true)) => {
-					highlight = true;
-				},
-				| _ => {},
-			}
+			.await;
 
-			// Break early if both conditions are true
-			if notify && highlight {
-				break;
-			}
-		}
+		// Room rules muting the room or keeping only mentions leave out the
+		// events they match.
+		let (notify, highlight) = notification_tweaks(actions);
 
 		if notify {
 			notifies.push(user.clone());
@@ -282,7 +280,23 @@ pub async fn append_pdu<'a, Leafs>(
 			.await;
 	}
 
-	self.increment_notification_counts(pdu.room_id(), notifies, highlights);
+	self.increment_notification_counts(
+		pdu.room_id(),
+		thread_root.as_deref(),
+		notifies,
+		highlights,
+	);
+
+	self.services
+		.read_receipt
+		.pending_receipts_resolve(pdu.room_id(), pdu.event_id(), count)
+		.await;
+
+	if pdu.state_key().is_some() && affects_summary(pdu.kind()) {
+		self.services
+			.directory
+			.invalidate_summary(pdu.room_id());
+	}
 
 	match *pdu.kind() {
 		| TimelineEventType::RoomRedaction => {
@@ -325,13 +339,11 @@ pub async fn append_pdu<'a, Leafs>(
 			}
 		},
 		| TimelineEventType::SpaceChild =>
-			if let Some(_state_key) = pdu.state_key() {
+			if pdu.state_key().is_some() {
 				self.services
 					.spaces
-					.roomid_spacehierarchy_cache
-					.lock()
-					.await
-					.remove(pdu.room_id());
+					.invalidate_hierarchy(pdu.room_id())
+					.await;
 			},
 		| TimelineEventType::RoomMember => {
 			if let Some(state_key) = pdu.state_key() {
@@ -426,57 +438,33 @@ pub async fn append_pdu<'a, Leafs>(
 	drop(next_count2);
 
 	for appservice in self.services.appservice.read().await.values() {
-		if self
+		let room_interested = self
 			.services
 			.state_cache
-			.appservice_in_room(pdu.room_id(), appservice)
-			.await
-		{
-			self.services
-				.sending
-				.send_pdu_appservice(appservice.registration.id.clone(), pdu_id)?;
-
-			continue;
-		}
+			.appservice_interested(pdu.room_id(), appservice)
+			.await;
 
-		// If the RoomMember event has a non-empty state_key, it is targeted at someone.
-		// If it is our appservice user, we send this PDU to it.
-		if *pdu.kind() == TimelineEventType::RoomMember {
-			if let Some(state_key_uid) = &pdu
+		// Membership events of its users concern the appservice even in rooms it
+		// has no interest in yet, e.g. invites of its users.
+		let targets_appservice = || {
+			let is_sender = pdu
 				.state_key
-				.as_ref()
-				.and_then(|state_key| UserId::parse(state_key.as_str()).ok())
-			{
-				let appservice_uid = appservice.registration.sender_localpart.as_str();
-				if state_key_uid == &appservice_uid {
-					self.services
-						.sending
-						.send_pdu_appservice(appservice.registration.id.clone(), pdu_id)?;
-
-					continue;
-				}
-			}
-		}
-
-		let matching_users = |users: &NamespaceRegex| {
-			appservice.users.is_match(pdu.sender().as_str())
-				|| *pdu.kind() == TimelineEventType::RoomMember
-					&& pdu
-						.state_key
-						.as_ref()
-						.is_some_and(|state_key| users.is_match(state_key))
-		};
-		let matching_aliases = |aliases: NamespaceRegex| {
-			self.services
-				.alias
-				.local_aliases_for_room(pdu.room_id())
-				.ready_any(move |room_alias| aliases.is_match(room_alias.as_str()))
+				.as_deref()
+				.and_then(|state_key| UserId::parse(state_key).ok())
+				.is_some_and(|target| {
+					self.services.globals.user_is_local(&target)
+						&& target.localpart() == appservice.registration.sender_localpart
+				});
+
+			is_sender
+				|| appservice.users.is_match(pdu.sender().as_str())
+				|| pdu
+					.state_key
+					.as_deref()
+					.is_some_and(|state_key| appservice.users.is_match(state_key))
 		};
 
-		if matching_aliases(appservice.aliases.clone()).await
-			|| appservice.rooms.is_match(pdu.room_id().as_str())
-			|| matching_users(&appservice.users)
-		{
+		if event_concerns_appservice(room_interested, pdu.kind(), targets_appservice) {
 			self.services
 				.sending
 				.send_pdu_appservice(appservice.registration.id.clone(), pdu_id)?;
@@ -511,23 +499,120 @@ fn append_pdu_json(
 fn increment_notification_counts(
 	&self,
 	room_id: &RoomId,
+	thread_root: Option<&EventId>,
 	notifies: Vec<OwnedUserId>,
 	highlights: Vec<OwnedUserId>,
 ) {
 	let _cork = self.db.db.cork();
 
+	let (notification_counts, highlight_counts) = match thread_root {
+		| Some(_) => (
+			&self.db.userroomthreadid_notificationcount,
+			&self.db.userroomthreadid_highlightcount,
+		),
+		| None => (&self.db.userroomid_notificationcount, &self.db.userroomid_highlightcount),
+	};
+
 	for user in notifies {
-		let mut userroom_id = user.as_bytes().to_vec();
-		userroom_id.push(0xFF);
-		userroom_id.extend_from_slice(room_id.as_bytes());
-		increment(&self.db.userroomid_notificationcount, &userroom_id);
+		increment(notification_counts, &counter_key(&user, room_id, thread_root));
 	}
 
 	for user in highlights {
-		let mut userroom_id = user.as_bytes().to_vec();
-		userroom_id.push(0xFF);
-		userroom_id.extend_from_slice(room_id.as_bytes());
-		increment(&self.db.userroomid_highlightcount, &userroom_id);
+		increment(highlight_counts, &counter_key(&user, room_id, thread_root));
+	}
+}
+
+/// Recounts the notifications and highlights of the main timeline of the room
+/// after the event read by the user, deciding each event as appending it
+/// does. Counts bumped wrongly before, e.g. for events of users ignored
+/// since, are thereby corrected by the next receipt. At most `RECOUNT_MAX`
+/// events after the one read are counted.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn recount_notifications(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	read: &EventId,
+) -> Result {
+	let read = self.get_pdu_count(read).await?;
+	let ruleset = self.services.pusher.get_ruleset(user_id).await;
+	let power_levels = self
+		.services
+		.state_accessor
+		.get_power_levels(room_id)
+		.await?;
+
+	let unread: Vec<PduEvent> = self
+		.pdus(Some(user_id), room_id, Some(read))
+		.ignore_err()
+		.ready_filter(|(count, _)| *count > read)
+		.take(RECOUNT_MAX)
+		.map(at!(1))
+		.ready_filter(|pdu| pdu.sender() != user_id && thread_root(pdu).is_none())
+		.collect()
+		.await;
+
+	let (mut notifications, mut highlights) = (0_u64, 0_u64);
+	for pdu in &unread {
+		if self
+			.services
+			.users
+			.user_is_ignored(pdu.sender(), user_id)
+			.await
+		{
+			continue;
+		}
+
+		let actions = self
+			.services
+			.pusher
+			.get_actions(user_id, &ruleset, &power_levels, &pdu.to_format(), room_id)
+			.await;
+
+		let (notify, highlight) = notification_tweaks(actions);
+		notifications = notifications.saturating_add(notify.into());
+		highlights = highlights.saturating_add(highlight.into());
+	}
+
+	self.services
+		.user
+		.reset_notification_counts(user_id, room_id);
+
+	let userroom_id = (user_id, room_id);
+	self.db
+		.userroomid_notificationcount
+		.put(userroom_id, notifications);
+	self.db
+		.userroomid_highlightcount
+		.put(userroom_id, highlights);
+
+	Ok(())
+}
+
+/// The key of the counter of the user in the room, or in the thread of the
+/// room with the root.
+fn counter_key(user_id: &UserId, room_id: &RoomId, thread_root: Option<&EventId>) -> Vec<u8> {
+	let mut key = user_id.as_bytes().to_vec();
+	key.push(0xFF);
+	key.extend_from_slice(room_id.as_bytes());
+	if let Some(thread_root) = thread_root {
+		key.push(0xFF);
+		key.extend_from_slice(thread_root.as_bytes());
+	}
+
+	key
+}
+
+/// The root of the thread the event is part of, from its `m.thread` relation.
+fn thread_root<E: Event>(event: &E) -> Option<OwnedEventId> {
+	match event
+		.get_content::<ExtractRelatesTo>()
+		.ok()?
+		.relates_to
+	{
+		| Relation::Thread(thread) => Some(thread.event_id),
+		| _ => None,
 	}
 }
 
@@ -206,6 +206,11 @@ pub async fn backfill_pdu(
 	self.prepend_backfill_pdu(&pdu_id, &event_id, &value);
 	drop(insert_lock);
 
+	self.services
+		.read_receipt
+		.pending_receipts_resolve(room_id, &event_id, pdu_id.pdu_count())
+		.await;
+
 	if pdu.kind == TimelineEventType::RoomMessage {
 		let content: ExtractBody = pdu.get_content()?;
 		if let Some(body) = content.body {
@@ -231,3 +236,31 @@ fn prepend_backfill_pdu(
 	self.db.eventid_pduid.insert(event_id, pdu_id);
 	self.db.eventid_outlierpdu.remove(event_id);
 }
+
+/// Bounds the size of the events answering another server's backfill. The
+/// first event is always admitted so the requester makes progress however
+/// large it is.
+#[derive(Debug)]
+pub struct ResponseBudget {
+	remaining: usize,
+	admitted: usize,
+}
+
+impl ResponseBudget {
+	#[must_use]
+	pub fn new(max_bytes: usize) -> Self { Self { remaining: max_bytes, admitted: 0 } }
+
+	/// Whether the serialized event still fits; once one does not, no more
+	/// are admitted so the response stays a contiguous page.
+	pub fn admit(&mut self, event: &RawJsonValue) -> bool {
+		let len = event.get().len();
+		if self.admitted > 0 && len > self.remaining {
+			self.remaining = 0;
+			return false;
+		}
+
+		self.remaining = self.remaining.saturating_sub(len);
+		self.admitted = self.admitted.saturating_add(1);
+		true
+	}
+}
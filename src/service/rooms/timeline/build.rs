@@ -18,6 +18,7 @@
 };
 
 use super::RoomMutexGuard;
+use crate::moderation::Decision;
 
 /// Creates a new persisted data unit and adds it to a room. This function
 /// takes a roomid_mutex_state, meaning that only this function is able to
@@ -55,6 +56,23 @@ pub async fn build_and_append_pdu(
 			.await?;
 	}
 
+	match self
+		.services
+		.moderation
+		.check_event_for_spam(&pdu)
+		.await
+	{
+		| Decision::Allow => {},
+		| Decision::SoftFail => {
+			self.services
+				.pdu_metadata
+				.mark_event_soft_failed(&pdu, "Matched a moderation rule");
+
+			return Ok(pdu.event_id().to_owned());
+		},
+		| decision @ Decision::Deny(..) => decision.check()?,
+	}
+
 	// If redaction event is not authorized, do not append it to the timeline
 	if *pdu.kind() == TimelineEventType::RoomRedaction {
 		use RoomVersionId::*;
@@ -172,6 +190,47 @@ pub async fn build_and_append_pdu(
 	Ok(pdu.event_id().to_owned())
 }
 
+/// Creates a new persisted data unit and adds it to a room without checking
+/// the sender is authorized to send it, so the server can take back control
+/// of a room its moderators abandoned. The event is hashed and signed as
+/// usual. Refused when any other server is in the room, as it would reject
+/// the event.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self, state_lock), level = "info", ret)]
+pub async fn append_privileged_pdu(
+	&self,
+	pdu_builder: PduBuilder,
+	sender: &UserId,
+	room_id: &RoomId,
+	state_lock: &RoomMutexGuard,
+) -> Result<OwnedEventId> {
+	let federated = self
+		.services
+		.state_cache
+		.room_servers(room_id)
+		.ready_any(|server| !self.services.globals.server_is_ours(server))
+		.await;
+
+	if federated {
+		return Err!(Request(Forbidden("Room has members of other servers.")));
+	}
+
+	let (pdu, pdu_json) = self
+		.create_event(pdu_builder, sender, room_id, state_lock, false)
+		.await?;
+
+	let statehashid = self.services.state.append_to_state(&pdu).await?;
+
+	self.append_pdu(&pdu, pdu_json, once(pdu.event_id()), state_lock)
+		.await?;
+
+	self.services
+		.state
+		.set_room_state(pdu.room_id(), statehashid, state_lock);
+
+	Ok(pdu.event_id().to_owned())
+}
+
 #[implement(super::Service)]
 #[tracing::instrument(skip_all, level = "debug")]
 async fn check_pdu_for_admin_room<Pdu>(&self, pdu: &Pdu, sender: &UserId) -> Result
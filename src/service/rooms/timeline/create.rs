@@ -10,7 +10,7 @@
 };
 use serde_json::value::to_raw_value;
 use tuwunel_core::{
-	Error, Result, err, implement,
+	Err, Error, Result, err, implement,
 	matrix::{
 		event::{Event, StateKey, TypeExt},
 		pdu::{EventHash, PduBuilder, PduEvent},
@@ -24,6 +24,7 @@
 };
 
 use super::RoomMutexGuard;
+use crate::rooms::state::MEMBER_COUNT_STRIPPED_TYPE;
 
 #[implement(super::Service)]
 pub async fn create_hash_and_sign_event(
@@ -32,7 +33,22 @@ pub async fn create_hash_and_sign_event(
 	sender: &UserId,
 	room_id: &RoomId,
 	// Take mutex guard to make sure users get the room state mutex
+	mutex_lock: &RoomMutexGuard,
+) -> Result<(PduEvent, CanonicalJsonObject)> {
+	self.create_event(pdu_builder, sender, room_id, mutex_lock, true)
+		.await
+}
+
+/// Creates, hashes and signs the event; the sender is only checked to be
+/// authorized to send it when `check_auth` is set.
+#[implement(super::Service)]
+pub(super) async fn create_event(
+	&self,
+	pdu_builder: PduBuilder,
+	sender: &UserId,
+	room_id: &RoomId,
 	_mutex_lock: &RoomMutexGuard,
+	check_auth: bool,
 ) -> Result<(PduEvent, CanonicalJsonObject)> {
 	let PduBuilder {
 		event_type,
@@ -43,6 +59,12 @@ pub async fn create_hash_and_sign_event(
 		timestamp,
 	} = pdu_builder;
 
+	if event_type.to_string() == MEMBER_COUNT_STRIPPED_TYPE {
+		return Err!(Request(Forbidden(
+			"Events of type {MEMBER_COUNT_STRIPPED_TYPE} only exist in stripped state."
+		)));
+	}
+
 	let prev_events: Vec<OwnedEventId> = self
 		.services
 		.state
@@ -162,13 +184,15 @@ pub async fn create_hash_and_sign_event(
 			.ok_or_else(|| err!(Request(NotFound("Missing auth events"))))
 	};
 
-	state_res::auth_check(
-		&version_rules,
-		&pdu,
-		&async |event_id: OwnedEventId| self.get_pdu(&event_id).await,
-		&auth_fetch,
-	)
-	.await?;
+	if check_auth {
+		state_res::auth_check(
+			&version_rules,
+			&pdu,
+			&async |event_id: OwnedEventId| self.get_pdu(&event_id).await,
+			&auth_fetch,
+		)
+		.await?;
+	}
 
 	// Hash and sign
 	let mut pdu_json = to_canonical_object(&pdu).map_err(|e| {
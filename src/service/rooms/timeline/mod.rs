@@ -2,19 +2,24 @@
 mod backfill;
 mod build;
 mod create;
+mod purge;
 mod redact;
+#[cfg(test)]
+mod tests;
+mod timestamp;
 
 use std::{borrow::Borrow, fmt::Write, sync::Arc};
 
 use async_trait::async_trait;
 use futures::{
-	Stream, TryFutureExt, TryStreamExt,
+	FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt,
 	future::{
 		Either::{Left, Right},
 		select_ok,
 	},
 	pin_mut,
 };
+use lru_cache::LruCache;
 use ruma::{
 	CanonicalJsonObject, EventId, OwnedEventId, OwnedRoomId, RoomId, UserId, api::Direction,
 	events::room::encrypted::Relation,
@@ -26,28 +31,33 @@
 	matrix::pdu::{PduCount, PduEvent},
 	trace,
 	utils::{
-		MutexMap, MutexMapGuard,
+		IterStream, MutexMap, MutexMapGuard,
 		result::{LogErr, NotFound},
-		stream::{TryIgnore, TryReadyExt},
+		stream::{DELETE_BATCH_SIZE, TryIgnore, TryReadyExt, delete_batched},
 	},
 	warn,
 };
-use tuwunel_database::{Database, Deserialized, Json, KeyVal, Map};
+use tuwunel_database::{Database, Deserialized, Get, Json, KeyVal, Map};
 
+pub use self::{backfill::ResponseBudget, purge::PurgeSummary};
 use crate::rooms::short::ShortRoomId;
 
 pub struct Service {
 	services: Arc<crate::services::OnceServices>,
 	db: Data,
 	pub mutex_insert: RoomMutexMap,
+	timestamp_misses: timestamp::Misses,
 }
 
 struct Data {
 	eventid_outlierpdu: Arc<Map>,
 	eventid_pduid: Arc<Map>,
 	pduid_pdu: Arc<Map>,
+	roomid_purgedcount: Arc<Map>,
 	userroomid_highlightcount: Arc<Map>,
 	userroomid_notificationcount: Arc<Map>,
+	userroomthreadid_highlightcount: Arc<Map>,
+	userroomthreadid_notificationcount: Arc<Map>,
 	db: Arc<Database>,
 }
 
@@ -68,9 +78,10 @@ struct ExtractRelatesToEventId {
 	relates_to: ExtractEventId,
 }
 
+/// The `body` of the content of a message event.
 #[derive(Deserialize)]
-struct ExtractBody {
-	body: Option<String>,
+pub(crate) struct ExtractBody {
+	pub(crate) body: Option<String>,
 }
 
 type RoomMutexMap = MutexMap<OwnedRoomId, ()>;
@@ -86,11 +97,17 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				eventid_outlierpdu: args.db["eventid_outlierpdu"].clone(),
 				eventid_pduid: args.db["eventid_pduid"].clone(),
 				pduid_pdu: args.db["pduid_pdu"].clone(),
+				roomid_purgedcount: args.db["roomid_purgedcount"].clone(),
 				userroomid_highlightcount: args.db["userroomid_highlightcount"].clone(),
 				userroomid_notificationcount: args.db["userroomid_notificationcount"].clone(),
+				userroomthreadid_highlightcount: args.db["userroomthreadid_highlightcount"]
+					.clone(),
+				userroomthreadid_notificationcount: args.db["userroomthreadid_notificationcount"]
+					.clone(),
 				db: args.db.clone(),
 			},
 			mutex_insert: RoomMutexMap::new(),
+			timestamp_misses: LruCache::new(timestamp::MISSES_CAPACITY).into(),
 		}))
 	}
 
@@ -98,9 +115,19 @@ async fn memory_usage(&self, out: &mut (dyn Write + Send)) -> Result {
 		let mutex_insert = self.mutex_insert.len();
 		writeln!(out, "insert_mutex: {mutex_insert}")?;
 
+		let timestamp_misses = self.timestamp_misses.lock()?.len();
+		writeln!(out, "timestamp_misses: {timestamp_misses}")?;
+
 		Ok(())
 	}
 
+	async fn clear_cache(&self) {
+		self.timestamp_misses
+			.lock()
+			.expect("locked")
+			.clear();
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -299,6 +326,54 @@ pub async fn get_pdu(&self, event_id: &EventId) -> Result<PduEvent> {
 		.map(at!(0))
 }
 
+/// Returns the pdus of the events in the given order. The timeline is read
+/// with a batched multi-get of each table rather than point gets per event;
+/// events not found there are looked up among the outliers.
+#[implement(Service)]
+pub fn multi_get_pdus<'a, I>(
+	&'a self,
+	event_ids: I,
+) -> impl Stream<Item = Result<PduEvent>> + Send + 'a
+where
+	I: Iterator<Item = &'a EventId> + Clone + Send + 'a,
+{
+	event_ids
+		.clone()
+		.stream()
+		.get(&self.db.eventid_pduid)
+		.map(|result| result.ok().map(|pdu_id| RawPduId::from(&*pdu_id)))
+		.collect::<Vec<_>>()
+		.map(move |pdu_ids| {
+			pdu_ids
+				.clone()
+				.into_iter()
+				.flatten()
+				.stream()
+				.get(&self.db.pduid_pdu)
+				.map(Deserialized::deserialized)
+				.collect::<Vec<Result<PduEvent>>>()
+				.map(move |pdus| (pdu_ids, pdus))
+		})
+		.flatten()
+		.map(move |(pdu_ids, pdus)| {
+			let mut pdus = pdus.into_iter();
+			pdu_ids
+				.into_iter()
+				.zip(event_ids)
+				.stream()
+				.then(move |(pdu_id, event_id)| {
+					let pdu = pdu_id.and_then(|_| pdus.next());
+					async move {
+						match pdu {
+							| Some(pdu) => pdu,
+							| None => self.get_outlier_pdu(event_id).await,
+						}
+					}
+				})
+		})
+		.flatten_stream()
+}
+
 /// Returns the pdu.
 ///
 /// Checks the `eventid_outlierpdu` Tree if not found in the timeline.
@@ -416,28 +491,91 @@ pub async fn get_pdu_id(&self, event_id: &EventId) -> Result<RawPduId> {
 }
 
 #[implement(Service)]
-pub async fn delete_pdus(&self, room_id: &RoomId) -> Result {
-	self.count_to_id(room_id, PduCount::min(), Direction::Forward)
-		.map_ok(move |current| {
-			let prefix = current.shortroomid();
-			self.db
-				.pduid_pdu
-				.raw_stream_from(&current)
-				.ready_try_take_while(move |(key, _)| Ok(key.starts_with(&prefix)))
-				.ready_try_for_each(|(key, value)| {
-					trace!("Removing PDU {key:?}");
-					self.db.pduid_pdu.remove(key);
-					let pdu = serde_json::from_slice::<PduEvent>(value)?;
-
-					let event_id = &pdu.event_id;
-					let room_id2 = &pdu.room_id;
-					trace!("Removed {event_id} {room_id2}");
-					self.db.eventid_pduid.remove(event_id);
-					self.db.eventid_outlierpdu.remove(event_id);
-					Ok(())
-				})
-		})
-		.try_flatten()
+pub async fn delete_pdus(&self, room_id: &RoomId) -> Result<usize> {
+	let current = self
+		.count_to_id(room_id, PduCount::min(), Direction::Forward)
 		.await?;
-	Ok(())
+
+	self.db.roomid_purgedcount.remove(room_id);
+
+	let prefix = current.shortroomid();
+	let pdus = self
+		.db
+		.pduid_pdu
+		.raw_stream_from(&current)
+		.ready_try_take_while(move |(key, _)| Ok(key.starts_with(&prefix)));
+
+	delete_batched(
+		pdus,
+		DELETE_BATCH_SIZE,
+		|pdu| {
+			let (key, value) = pdu?;
+			trace!("Removing PDU {key:?}");
+			self.db.pduid_pdu.remove(key);
+			let pdu = serde_json::from_slice::<PduEvent>(value)?;
+
+			let event_id = &pdu.event_id;
+			let room_id2 = &pdu.room_id;
+			trace!("Removed {event_id} {room_id2}");
+			self.db.eventid_pduid.remove(event_id);
+			self.db.eventid_outlierpdu.remove(event_id);
+			Ok(())
+		},
+		|| self.services.server.is_stopping(),
+	)
+	.await
+}
+
+/// The count of the event the PDU relates to or replies to, if in the
+/// timeline.
+#[implement(Service)]
+pub async fn relation_target(&self, pdu: &PduEvent) -> Option<PduCount> {
+	let target = match pdu.get_content::<ExtractRelatesToEventId>() {
+		| Ok(content) => Some(content.relates_to.event_id),
+		| Err(_) => pdu
+			.get_content::<ExtractRelatesTo>()
+			.ok()
+			.and_then(|content| match content.relates_to {
+				| Relation::Reply { in_reply_to } => Some(in_reply_to.event_id),
+				| _ => None,
+			}),
+	}?;
+
+	self.get_pdu_count(&target).await.ok()
+}
+
+/// Removes a single timeline PDU along with its search tokens and relations.
+/// The caller must ensure the event is not part of the room state. `target`
+/// is its `relation_target`, resolved before deleting any event which it
+/// could be.
+#[implement(Service)]
+#[tracing::instrument(skip(self, pdu), level = "debug")]
+pub async fn delete_pdu(&self, pdu_id: &RawPduId, pdu: &PduEvent, target: Option<PduCount>) {
+	let PduId { shortroomid, shorteventid: count } = (*pdu_id).into();
+
+	if let Some(body) = pdu
+		.get_content::<ExtractBody>()
+		.ok()
+		.and_then(|content| content.body)
+	{
+		self.services
+			.search
+			.deindex_pdu(shortroomid, pdu_id, &body);
+	}
+
+	self.services
+		.pdu_metadata
+		.delete_relations(count, target)
+		.await;
+
+	self.services
+		.pdu_metadata
+		.delete_referenced(&pdu.room_id, &pdu.event_id);
+
+	self.services.threads.delete_thread(pdu_id);
+
+	trace!("Removing PDU {pdu_id:?}");
+	self.db.pduid_pdu.remove(pdu_id);
+	self.db.eventid_pduid.remove(&pdu.event_id);
+	self.db.eventid_outlierpdu.remove(&pdu.event_id);
 }
@@ -0,0 +1,259 @@
+//! Purging the history of a room before a point of its timeline while keeping
+//! the room functional: the create event, the forward extremities, the auth
+//! chain of the events kept and, unless asked otherwise, the events of the
+//! current state are kept. The point is recorded in `roomid_purgedcount` so
+//! pagination stops there.
+
+use std::collections::{HashMap, HashSet};
+
+use futures::{StreamExt, stream::BoxStream};
+use ruma::{MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, events::TimelineEventType};
+use tuwunel_core::{
+	Err, Result, at, debug_info, implement,
+	matrix::pdu::{PduCount, PduEvent, PduId, RawPduId},
+	utils::stream::{ReadyExt, TryIgnore},
+};
+use tuwunel_database::Deserialized;
+
+use super::PdusIterItem;
+
+/// Outcome of purging the history of a room.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PurgeSummary {
+	/// Events deleted, or which would be on a dry run.
+	pub purged: usize,
+
+	/// Events before the cutoff kept as part of the current state.
+	pub kept_state: usize,
+
+	/// Events before the cutoff kept as the create event, a forward
+	/// extremity or in the auth chain of the events kept.
+	pub kept_required: usize,
+
+	/// Outliers of the room older than the events before the cutoff deleted,
+	/// or which would be on a dry run.
+	pub outliers: usize,
+}
+
+/// Why an event before the cutoff is kept rather than purged.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum Keep {
+	Purge,
+	State,
+	Required,
+}
+
+/// Deletes the events of the room before `before`, with their search tokens,
+/// relations and threads, and the outliers of the room older than them. Events
+/// of the current state are kept unless `purge_state`; the create event, the
+/// forward extremities and the auth chain of the events kept always are.
+/// Purging the state leaves the room without it, so it is refused while local
+/// users are in the room.
+#[implement(super::Service)]
+pub async fn purge_history(
+	&self,
+	room_id: &RoomId,
+	before: PduCount,
+	purge_state: bool,
+) -> Result<PurgeSummary> {
+	self.purge(room_id, before, purge_state, false)
+		.await
+}
+
+/// What purging the history of the room would delete, deleting nothing.
+#[implement(super::Service)]
+pub async fn purge_history_dry_run(
+	&self,
+	room_id: &RoomId,
+	before: PduCount,
+	purge_state: bool,
+) -> Result<PurgeSummary> {
+	self.purge(room_id, before, purge_state, true)
+		.await
+}
+
+/// The point the history of the room was purged before, if ever.
+#[implement(super::Service)]
+pub async fn purged_before(&self, room_id: &RoomId) -> Option<PduCount> {
+	self.db
+		.roomid_purgedcount
+		.get(room_id)
+		.await
+		.deserialized::<i64>()
+		.map(PduCount::from_signed)
+		.ok()
+}
+
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+async fn purge(
+	&self,
+	room_id: &RoomId,
+	before: PduCount,
+	purge_state: bool,
+	dry_run: bool,
+) -> Result<PurgeSummary> {
+	let shortroomid = self
+		.services
+		.short
+		.get_shortroomid(room_id)
+		.await?;
+
+	if purge_state
+		&& self
+			.services
+			.state_cache
+			.server_in_room(self.services.globals.server_name(), room_id)
+			.await
+	{
+		return Err!(
+			"Purging the current state of {room_id} would break it for its local members; purge \
+			 without it."
+		);
+	}
+
+	let extremities: HashSet<OwnedEventId> = self
+		.services
+		.state
+		.get_forward_extremities(room_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let state: HashSet<OwnedEventId> = if purge_state {
+		HashSet::new()
+	} else {
+		let shortstatehash = self
+			.services
+			.state
+			.get_room_shortstatehash(room_id)
+			.await?;
+
+		self.services
+			.state_accessor
+			.state_full_ids(shortstatehash)
+			.map(at!(1))
+			.collect()
+			.await
+	};
+
+	// The events kept must still be authorized: their auth chain is kept with
+	// them, as are the auth events of those after the cutoff.
+	let mut kept: HashSet<OwnedEventId> = extremities.union(&state).cloned().collect();
+	self.pdus(None, room_id, Some(before.saturating_sub(1)))
+		.ignore_err()
+		.ready_for_each(|(_, pdu)| kept.extend(pdu.auth_events))
+		.await;
+
+	let kept: Vec<_> = kept.into_iter().collect();
+	let mut auth: HashSet<OwnedEventId> = self
+		.services
+		.auth_chain
+		.event_ids_iter(room_id, kept.iter().map(AsRef::as_ref))
+		.ignore_err()
+		.collect()
+		.await;
+
+	auth.extend(kept);
+
+	// The events are deleted oldest first, so the events their relations
+	// target are resolved before any is deleted.
+	let mut targets: HashMap<PduCount, PduCount> = HashMap::new();
+	if !dry_run {
+		let mut pdus = self.history(room_id, before);
+		while let Some((count, pdu)) = pdus.next().await {
+			if keep(&pdu, &extremities, &auth, &state) != Keep::Purge {
+				continue;
+			}
+
+			if let Some(target) = self.relation_target(&pdu).await {
+				targets.insert(count, target);
+			}
+		}
+	}
+
+	let mut summary = PurgeSummary::default();
+	let mut newest = MilliSecondsSinceUnixEpoch(0_u32.into());
+	let mut pdus = self.history(room_id, before);
+	while let Some((count, pdu)) = pdus.next().await {
+		newest = newest.max(pdu.origin_server_ts);
+		match keep(&pdu, &extremities, &auth, &state) {
+			| Keep::Required => summary.kept_required = summary.kept_required.saturating_add(1),
+			| Keep::State => summary.kept_state = summary.kept_state.saturating_add(1),
+			| Keep::Purge => {
+				summary.purged = summary.purged.saturating_add(1);
+				if !dry_run {
+					let pdu_id: RawPduId = PduId { shortroomid, shorteventid: count }.into();
+					let target = targets.get(&count).copied();
+					self.delete_pdu(&pdu_id, &pdu, target).await;
+				}
+			},
+		}
+
+		if self.services.server.is_stopping() {
+			return Err!("History purge interrupted by server shutdown.");
+		}
+	}
+
+	// Outliers have no place in the timeline, so those older than the newest
+	// event before the cutoff are purged, unless the events kept need them.
+	let outliers: Vec<OwnedEventId> = self
+		.db
+		.eventid_outlierpdu
+		.raw_stream()
+		.ignore_err()
+		.ready_filter_map(|(_, value)| serde_json::from_slice::<PduEvent>(value).ok())
+		.ready_filter(|pdu| *pdu.room_id == *room_id && pdu.origin_server_ts < newest)
+		.ready_filter(|pdu| keep(pdu, &extremities, &auth, &state) == Keep::Purge)
+		.map(|pdu| pdu.event_id)
+		.collect()
+		.await;
+
+	summary.outliers = outliers.len();
+	if !dry_run {
+		// Pagination stops at the latest point purged before.
+		let cutoff = self
+			.purged_before(room_id)
+			.await
+			.map_or(before, |purged| purged.max(before));
+
+		self.db
+			.roomid_purgedcount
+			.raw_put(room_id, cutoff.into_signed());
+
+		for event_id in &outliers {
+			self.db.eventid_outlierpdu.remove(event_id);
+		}
+
+		debug_info!(%room_id, %before, ?summary, "Purged room history");
+	}
+
+	Ok(summary)
+}
+
+/// The events of the room before `before`, oldest first.
+#[implement(super::Service)]
+fn history<'a>(&'a self, room_id: &'a RoomId, before: PduCount) -> BoxStream<'a, PdusIterItem> {
+	self.pdus(None, room_id, None)
+		.ignore_err()
+		.ready_take_while(move |(count, _)| *count < before)
+		.boxed()
+}
+
+pub(super) fn keep(
+	pdu: &PduEvent,
+	extremities: &HashSet<OwnedEventId>,
+	auth: &HashSet<OwnedEventId>,
+	state: &HashSet<OwnedEventId>,
+) -> Keep {
+	if pdu.kind == TimelineEventType::RoomCreate
+		|| extremities.contains(&pdu.event_id)
+		|| auth.contains(&pdu.event_id)
+	{
+		Keep::Required
+	} else if state.contains(&pdu.event_id) {
+		Keep::State
+	} else {
+		Keep::Purge
+	}
+}
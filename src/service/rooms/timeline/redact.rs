@@ -1,4 +1,4 @@
-use ruma::EventId;
+use ruma::{EventId, events::TimelineEventType};
 use tuwunel_core::{
 	Result, err, implement,
 	matrix::event::Event,
@@ -19,8 +19,9 @@ pub async fn redact_pdu<Pdu: Event + Send + Sync>(
 ) -> Result {
 	// TODO: Don't reserialize, keep original json
 	let Ok(pdu_id) = self.get_pdu_id(event_id).await else {
-		// If event does not exist, just noop
-		return Ok(());
+		// Outliers, e.g. soft-failed events, are kept out of the timeline but
+		// are still served, so they are redacted where they are.
+		return self.redact_outlier(event_id, reason).await;
 	};
 
 	let mut pdu = self
@@ -51,5 +52,44 @@ pub async fn redact_pdu<Pdu: Event + Send + Sync>(
 		err!(Database(error!(?event_id, ?e, "Failed to convert PDU to canonical JSON")))
 	})?;
 
-	self.replace_pdu(&pdu_id, &obj).await
+	self.replace_pdu(&pdu_id, &obj).await?;
+
+	// A redacted child loses its `via` and with it its place in the space.
+	if *pdu.kind() == TimelineEventType::SpaceChild && pdu.state_key().is_some() {
+		self.services
+			.spaces
+			.invalidate_hierarchy(pdu.room_id())
+			.await;
+	}
+
+	Ok(())
+}
+
+/// Replace an outlier with the redacted form; noop when the event is not
+/// stored at all.
+#[implement(super::Service)]
+async fn redact_outlier<Pdu: Event + Send + Sync>(
+	&self,
+	event_id: &EventId,
+	reason: &Pdu,
+) -> Result {
+	let Ok(mut pdu) = self.get_outlier_pdu(event_id).await else {
+		return Ok(());
+	};
+
+	let room_version_id = self
+		.services
+		.state
+		.get_room_version(pdu.room_id())
+		.await?;
+
+	pdu.redact(&room_version_id, reason.to_value())?;
+
+	let obj = utils::to_canonical_object(&pdu).map_err(|e| {
+		err!(Database(error!(?event_id, ?e, "Failed to convert PDU to canonical JSON")))
+	})?;
+
+	self.add_pdu_outlier(event_id, &obj);
+
+	Ok(())
 }
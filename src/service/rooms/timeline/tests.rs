@@ -0,0 +1,413 @@
+use futures::stream;
+use ruma::{MilliSecondsSinceUnixEpoch, UInt, api::Direction};
+use serde_json::value::RawValue as RawJsonValue;
+use tuwunel_core::{Err, Result};
+
+use super::{
+	ResponseBudget,
+	purge::{Keep, keep},
+	timestamp::{closest_remote, search_newest_first},
+};
+
+fn millis(ts: u32) -> MilliSecondsSinceUnixEpoch { MilliSecondsSinceUnixEpoch(UInt::from(ts)) }
+
+#[tokio::test]
+async fn timestamp_local_search() {
+	// Newest first, as the timeline is scanned.
+	let events =
+		|| stream::iter([("e", 500_u64), ("d", 400), ("c", 300), ("b", 200), ("a", 100)]);
+
+	let found = search_newest_first(events(), 250, Direction::Backward, 10).await;
+	assert_eq!(found, Some(("b", 200)), "closest event before the timestamp");
+
+	let found = search_newest_first(events(), 250, Direction::Forward, 10).await;
+	assert_eq!(found, Some(("c", 300)), "closest event after the timestamp");
+
+	let found = search_newest_first(events(), 300, Direction::Backward, 10).await;
+	assert_eq!(found, Some(("c", 300)), "an event at the timestamp matches backwards");
+
+	let found = search_newest_first(events(), 300, Direction::Forward, 10).await;
+	assert_eq!(found, Some(("c", 300)), "an event at the timestamp matches forwards");
+
+	let found = search_newest_first(events(), 50, Direction::Backward, 10).await;
+	assert_eq!(found, None, "nothing local before the oldest event");
+
+	let found = search_newest_first(events(), 600, Direction::Forward, 10).await;
+	assert_eq!(found, None, "nothing local after the newest event");
+
+	let found = search_newest_first(events(), 150, Direction::Backward, 3).await;
+	assert_eq!(found, None, "events past the limit are not searched");
+
+	let found = search_newest_first(events(), 150, Direction::Forward, 3).await;
+	assert_eq!(found, None, "the closest event may lie past the limit");
+
+	let found = search_newest_first(events(), 350, Direction::Forward, 3).await;
+	assert_eq!(found, Some(("d", 400)), "an answer within the limit is kept");
+}
+
+#[tokio::test]
+async fn timestamp_remote_fallback() {
+	let servers = ["a.example", "b.example", "c.example"];
+	let query = async |server: &&str| -> Result<(&'static str, MilliSecondsSinceUnixEpoch)> {
+		match *server {
+			| "a.example" => Ok(("$far", millis(120))),
+			| "b.example" => Err!(Request(NotFound("No event"))),
+			| _ => Ok(("$near", millis(105))),
+		}
+	};
+
+	let found = closest_remote(&servers, 100, Direction::Forward, query).await;
+	assert_eq!(found, Some(("$near", millis(105))), "closest answer of all servers wins");
+
+	let found = closest_remote(&servers, 110, Direction::Forward, query).await;
+	assert_eq!(found, Some(("$far", millis(120))), "answers on the wrong side are skipped");
+
+	let found = closest_remote(&servers, 100, Direction::Backward, query).await;
+	assert_eq!(found, None, "no answer on the requested side");
+
+	let found = closest_remote(&servers[1..2], 100, Direction::Forward, query).await;
+	assert_eq!(found, None, "failing servers give no answer");
+}
+
+#[tokio::test]
+async fn timestamp_of_local_events() {
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+	let event_id = embedded
+		.send_message(&alice, &room_id, "hello")
+		.await
+		.expect("message sent");
+
+	let sent = services
+		.timeline
+		.get_pdu(&event_id)
+		.await
+		.expect("message stored")
+		.origin_server_ts();
+
+	let found = services
+		.timeline
+		.local_event_by_timestamp(&room_id, sent, Direction::Backward)
+		.await;
+	assert_eq!(found, Some((event_id.clone(), sent)), "the message is found at its timestamp");
+
+	let after = MilliSecondsSinceUnixEpoch(sent.get().saturating_add(UInt::from(1_u32)));
+	let found = services
+		.timeline
+		.local_event_by_timestamp(&room_id, after, Direction::Forward)
+		.await;
+	assert_eq!(found, None, "no event is newer than the message");
+
+	services.stop().await;
+}
+
+/// An event serializing to about `len` bytes.
+fn large_event(len: usize) -> Box<RawJsonValue> {
+	RawJsonValue::from_string(format!(r#"{{"content":"{}"}}"#, "x".repeat(len)))
+		.expect("valid json")
+}
+
+/// Pages through the events as a requester would, each page starting after
+/// the last event returned.
+fn paginate(events: &[Box<RawJsonValue>], max_bytes: usize) -> Vec<usize> {
+	let mut pages = Vec::new();
+	let mut from = 0;
+	while from < events.len() {
+		let mut budget = ResponseBudget::new(max_bytes);
+		let page = events[from..]
+			.iter()
+			.take_while(|event| budget.admit(event))
+			.count();
+
+		pages.push(page);
+		from = from.saturating_add(page);
+	}
+
+	pages
+}
+
+#[test]
+fn backfill_budget_cuts_off_large_events() {
+	let events: Vec<_> = (0..10).map(|_| large_event(60_000)).collect();
+
+	assert_eq!(
+		paginate(&events, 200_000),
+		[3, 3, 3, 1],
+		"pages stop before exceeding the budget"
+	);
+	assert_eq!(paginate(&events, usize::MAX), [10], "a large budget leaves the count limit");
+}
+
+#[test]
+fn backfill_budget_always_progresses() {
+	let events = [large_event(100), large_event(2_000_000), large_event(100)];
+
+	assert_eq!(
+		paginate(&events, 1024),
+		[1, 1, 1],
+		"an event larger than the budget is returned alone"
+	);
+
+	let mut budget = ResponseBudget::new(0);
+	assert!(budget.admit(&events[0]), "the first event is always admitted");
+	assert!(!budget.admit(&events[2]), "nothing fits after it");
+}
+
+#[test]
+fn purge_keeps_required_and_state() {
+	use std::collections::HashSet;
+
+	use ruma::{OwnedEventId, events::TimelineEventType};
+	use serde_json::json;
+	use tuwunel_core::matrix::pdu::PduEvent;
+
+	let pdu = |id: &str, kind| PduEvent::fake(id, "@alice:example.com", kind, &json!({}));
+
+	let id = |id: &str| -> OwnedEventId { id.try_into().expect("valid event id") };
+	let extremities: HashSet<_> = [id("$leaf:example.com")].into();
+	let auth: HashSet<_> = [id("$power:example.com")].into();
+	let state: HashSet<_> = [id("$name:example.com"), id("$leaf:example.com")].into();
+	let none = HashSet::new();
+
+	let create = pdu("$create:example.com", TimelineEventType::RoomCreate);
+	assert_eq!(
+		keep(&create, &extremities, &auth, &state),
+		Keep::Required,
+		"the create event is kept"
+	);
+	assert_eq!(
+		keep(&create, &none, &none, &none),
+		Keep::Required,
+		"the create event is kept with the state purged"
+	);
+
+	let leaf = pdu("$leaf:example.com", TimelineEventType::RoomMessage);
+	assert_eq!(
+		keep(&leaf, &extremities, &auth, &state),
+		Keep::Required,
+		"forward extremities are kept before the state"
+	);
+
+	let power = pdu("$power:example.com", TimelineEventType::RoomPowerLevels);
+	assert_eq!(
+		keep(&power, &extremities, &auth, &none),
+		Keep::Required,
+		"the auth chain of the events kept is kept with the state purged"
+	);
+
+	let name = pdu("$name:example.com", TimelineEventType::RoomName);
+	assert_eq!(keep(&name, &extremities, &auth, &state), Keep::State, "current state is kept");
+	assert_eq!(
+		keep(&name, &extremities, &auth, &none),
+		Keep::Purge,
+		"current state is purged when asked"
+	);
+
+	let message = pdu("$message:example.com", TimelineEventType::RoomMessage);
+	assert_eq!(
+		keep(&message, &extremities, &auth, &state),
+		Keep::Purge,
+		"other events are purged"
+	);
+}
+
+#[tokio::test]
+async fn receipt_recounts_notifications() {
+	use serde_json::json;
+
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let bob = embedded
+		.create_user("bob", None)
+		.await
+		.expect("user created");
+	let carol = embedded
+		.create_user("carol", None)
+		.await
+		.expect("user created");
+
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+	for user_id in [&bob, &carol] {
+		embedded
+			.join_room(user_id, &room_id)
+			.await
+			.expect("user joined");
+	}
+
+	let first = embedded
+		.send_message(&carol, &room_id, "first")
+		.await
+		.expect("message sent");
+	embedded
+		.send_message(&carol, &room_id, "second")
+		.await
+		.expect("message sent");
+	embedded
+		.send_message(&bob, &room_id, "own")
+		.await
+		.expect("message sent");
+	let last = embedded
+		.send_message(&alice, &room_id, "last")
+		.await
+		.expect("message sent");
+
+	let count = services
+		.user
+		.notification_count(&bob, &room_id)
+		.await;
+	assert_eq!(count, 3, "the messages of others are counted");
+
+	services
+		.account_data
+		.update(
+			None,
+			&bob,
+			"m.ignored_user_list".into(),
+			&json!({
+				"type": "m.ignored_user_list",
+				"content": { "ignored_users": { carol.as_str(): {} } },
+			}),
+		)
+		.await
+		.expect("carol ignored");
+
+	services
+		.timeline
+		.recount_notifications(&bob, &room_id, &first)
+		.await
+		.expect("notifications recounted");
+
+	let count = services
+		.user
+		.notification_count(&bob, &room_id)
+		.await;
+	assert_eq!(count, 1, "users ignored since and their own events are not counted");
+
+	services
+		.timeline
+		.recount_notifications(&bob, &room_id, &last)
+		.await
+		.expect("notifications recounted");
+
+	let count = services
+		.user
+		.notification_count(&bob, &room_id)
+		.await;
+	assert_eq!(count, 0, "nothing is unread after the last event");
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn purge_deletes_relations_and_outliers() {
+	use std::time::Duration;
+
+	use futures::StreamExt;
+	use ruma::events::{
+		reaction::ReactionEventContent, relation::Annotation,
+		room::message::RoomMessageEventContent,
+	};
+	use tuwunel_core::{Event, matrix::pdu::PduBuilder};
+
+	use crate::fixture::Fixture;
+
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+
+	let first = embedded
+		.send_message(&alice, &room_id, "first")
+		.await
+		.expect("message sent");
+
+	let state_lock = fixture.state.mutex.lock(&room_id).await;
+	let (outlier, json) = fixture
+		.timeline
+		.create_hash_and_sign_event(
+			PduBuilder::timeline(&RoomMessageEventContent::text_plain("outlier")),
+			&alice,
+			&room_id,
+			&state_lock,
+		)
+		.await
+		.expect("event created");
+
+	fixture
+		.timeline
+		.add_pdu_outlier(outlier.event_id(), &json);
+
+	// The outlier is older than the events purged.
+	tokio::time::sleep(Duration::from_millis(2)).await;
+	let reaction = ReactionEventContent::new(Annotation::new(first.clone(), "👍".to_owned()));
+	fixture
+		.timeline
+		.build_and_append_pdu(PduBuilder::timeline(&reaction), &alice, &room_id, &state_lock)
+		.await
+		.expect("reaction sent");
+
+	drop(state_lock);
+	let relations = || fixture.db["tofrom_relation"].raw_keys().count();
+	assert_eq!(relations().await, 1, "the reaction relates to the message");
+
+	let later = embedded
+		.send_message(&alice, &room_id, "later")
+		.await
+		.expect("message sent");
+
+	let before = fixture
+		.timeline
+		.get_pdu_count(&later)
+		.await
+		.expect("count of the later message");
+
+	let summary = fixture
+		.timeline
+		.purge_history(&room_id, before, false)
+		.await
+		.expect("history purged");
+
+	assert_eq!(summary.purged, 2, "the message and its reaction are purged");
+	assert_eq!(summary.outliers, 1, "with the outlier older than them");
+	assert_eq!(relations().await, 0, "the relation is deleted with the events");
+	assert!(
+		fixture
+			.timeline
+			.get_pdu(outlier.event_id())
+			.await
+			.is_err(),
+		"the outlier is deleted"
+	);
+	assert!(
+		fixture.timeline.get_pdu(&later).await.is_ok(),
+		"the events after the cutoff are kept"
+	);
+
+	drop(embedded);
+	fixture.stop().await;
+}
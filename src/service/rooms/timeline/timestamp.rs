@@ -0,0 +1,214 @@
+//! Finding the event closest to a point in time for `timestamp_to_event`. The
+//! newest events of the local timeline are searched; when they hold no event
+//! on the requested side of the timestamp, a few servers in the room are asked
+//! and the closest event they name is fetched and verified. Timestamps none of
+//! them answered for are not asked about again for a while.
+
+use std::{
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use futures::{Stream, StreamExt, future::join_all, pin_mut};
+use lru_cache::LruCache;
+use ruma::{
+	MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedServerName, RoomId, ServerName,
+	api::{Direction, federation::event::get_event_by_timestamp},
+};
+use tuwunel_core::{
+	Err, Event, Result, debug_warn, implement,
+	utils::stream::{ReadyExt, TryIgnore},
+};
+
+/// Most servers asked when the local timeline has no answer.
+const REMOTE_SERVERS_MAX: usize = 3;
+
+/// Most events of the local timeline searched, newest first.
+const LOCAL_EVENTS_MAX: usize = 10_000;
+
+/// How long a timestamp no server answered for is not asked about again.
+const MISS_TTL: Duration = Duration::from_secs(60);
+
+/// Timestamps by room and direction no server answered for.
+pub(super) type Misses = Mutex<LruCache<(OwnedRoomId, u64, bool), Instant>>;
+
+pub(super) const MISSES_CAPACITY: usize = 1024;
+
+/// The event closest to the timestamp in the direction along with its own
+/// timestamp, asking other servers in the room when none is known locally.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn event_by_timestamp(
+	&self,
+	room_id: &RoomId,
+	ts: MilliSecondsSinceUnixEpoch,
+	dir: Direction,
+) -> Result<(OwnedEventId, MilliSecondsSinceUnixEpoch)> {
+	if let Some(found) = self
+		.local_event_by_timestamp(room_id, ts, dir)
+		.await
+	{
+		return Ok(found);
+	}
+
+	let key = (room_id.to_owned(), u64::from(ts.get()), matches!(dir, Direction::Forward));
+	if self
+		.timestamp_misses
+		.lock()
+		.expect("locked")
+		.get_mut(&key)
+		.is_some_and(|missed| missed.elapsed() < MISS_TTL)
+	{
+		return Err!(Request(NotFound("No event found near the timestamp.")));
+	}
+
+	let servers: Vec<OwnedServerName> = self
+		.services
+		.state_cache
+		.room_servers(room_id)
+		.ready_filter(|server| !self.services.globals.server_is_ours(server))
+		.take(REMOTE_SERVERS_MAX)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let query = async |server: &OwnedServerName| {
+		self.remote_event_by_timestamp(server, room_id, ts, dir)
+			.await
+			.inspect_err(|e| debug_warn!(%server, "Failed to find event by timestamp: {e}"))
+	};
+
+	let Some(found) = closest_remote(&servers, u64::from(ts.get()), dir, query).await else {
+		self.timestamp_misses
+			.lock()
+			.expect("locked")
+			.insert(key, Instant::now());
+
+		return Err!(Request(NotFound("No event found near the timestamp.")));
+	};
+
+	Ok(found)
+}
+
+/// The event of the local timeline closest to the timestamp in the
+/// direction, among its newest `LOCAL_EVENTS_MAX` events.
+#[implement(super::Service)]
+pub async fn local_event_by_timestamp(
+	&self,
+	room_id: &RoomId,
+	ts: MilliSecondsSinceUnixEpoch,
+	dir: Direction,
+) -> Option<(OwnedEventId, MilliSecondsSinceUnixEpoch)> {
+	let events = self
+		.pdus_rev(None, room_id, None)
+		.ignore_err()
+		.map(|(_, pdu)| {
+			let event_ts = u64::from(pdu.origin_server_ts().get());
+			(pdu.event_id, event_ts)
+		});
+
+	search_newest_first(events, u64::from(ts.get()), dir, LOCAL_EVENTS_MAX)
+		.await
+		.map(|(event_id, event_ts)| (event_id, millis(event_ts)))
+}
+
+/// Asks the server for the event closest to the timestamp, fetching and
+/// authenticating the event it names unless known.
+#[implement(super::Service)]
+async fn remote_event_by_timestamp(
+	&self,
+	server: &ServerName,
+	room_id: &RoomId,
+	ts: MilliSecondsSinceUnixEpoch,
+	dir: Direction,
+) -> Result<(OwnedEventId, MilliSecondsSinceUnixEpoch)> {
+	let request = get_event_by_timestamp::v1::Request { room_id: room_id.to_owned(), ts, dir };
+
+	let response = self
+		.services
+		.sending
+		.send_federation_request(server, request)
+		.await?;
+
+	let pdu = self
+		.services
+		.event_handler
+		.fetch_outlier(server, room_id, &response.event_id)
+		.await?;
+
+	if pdu.room_id() != room_id {
+		return Err!(Request(Forbidden("{server} named an event of another room.")));
+	}
+
+	let event_ts = pdu.origin_server_ts();
+	if !on_side(u64::from(ts.get()), dir, u64::from(event_ts.get())) {
+		return Err!(Request(Forbidden("{server} named an event on the wrong side.")));
+	}
+
+	Ok((pdu.event_id, event_ts))
+}
+
+/// Finds the event closest to `ts` in the direction among the first `limit`
+/// events ordered newest first; before `ts` the first one at or before it,
+/// after `ts` the last one of those at or after it. None when the answer may
+/// lie past the limit.
+pub(super) async fn search_newest_first<S, T>(
+	events: S,
+	ts: u64,
+	dir: Direction,
+	limit: usize,
+) -> Option<(T, u64)>
+where
+	S: Stream<Item = (T, u64)> + Send,
+	T: Send,
+{
+	let events = events.take(limit);
+	pin_mut!(events);
+	match dir {
+		| Direction::Backward =>
+			events
+				.ready_find(|(_, event_ts)| *event_ts <= ts)
+				.await,
+		| Direction::Forward => {
+			let (closest, searched) = events
+				.ready_take_while(|(_, event_ts)| *event_ts >= ts)
+				.ready_fold((None, 0_usize), |(_, searched), event| {
+					(Some(event), searched.saturating_add(1))
+				})
+				.await;
+
+			closest.filter(|_| searched < limit)
+		},
+	}
+}
+
+/// Asks each server at once and picks the closest answer on the requested
+/// side of `ts`; failures and answers on the wrong side are skipped.
+pub(super) async fn closest_remote<S, T, F>(
+	servers: &[S],
+	ts: u64,
+	dir: Direction,
+	query: F,
+) -> Option<(T, MilliSecondsSinceUnixEpoch)>
+where
+	F: AsyncFn(&S) -> Result<(T, MilliSecondsSinceUnixEpoch)>,
+{
+	join_all(servers.iter().map(|server| query(server)))
+		.await
+		.into_iter()
+		.filter_map(Result::ok)
+		.filter(|(_, event_ts)| on_side(ts, dir, u64::from(event_ts.get())))
+		.min_by_key(|(_, event_ts)| ts.abs_diff(u64::from(event_ts.get())))
+}
+
+/// Whether an event at `event_ts` lies on the `dir` side of `ts`.
+pub(super) fn on_side(ts: u64, dir: Direction, event_ts: u64) -> bool {
+	match dir {
+		| Direction::Backward => event_ts <= ts,
+		| Direction::Forward => event_ts >= ts,
+	}
+}
+
+fn millis(ts: u64) -> MilliSecondsSinceUnixEpoch {
+	MilliSecondsSinceUnixEpoch(ts.try_into().unwrap_or_default())
+}
@@ -1,11 +1,16 @@
+#[cfg(test)]
+mod tests;
+
 use std::sync::Arc;
 
-use ruma::{RoomId, UserId};
+use futures::{Stream, StreamExt, future::join};
+use ruma::{EventId, OwnedEventId, OwnedUserId, RoomId, UserId};
 use tuwunel_core::{
-	Result, implement, trace,
-	utils::stream::{ReadyExt, TryIgnore},
+	Result, implement,
+	result::LogErr,
+	utils::{ReadyExt, stream::TryIgnore},
 };
-use tuwunel_database::{Database, Deserialized, Interfix, Map};
+use tuwunel_database::{Database, Deserialized, Ignore, Interfix, Map};
 
 use crate::rooms::short::ShortStateHash;
 
@@ -18,6 +23,8 @@ struct Data {
 	db: Arc<Database>,
 	userroomid_notificationcount: Arc<Map>,
 	userroomid_highlightcount: Arc<Map>,
+	userroomthreadid_notificationcount: Arc<Map>,
+	userroomthreadid_highlightcount: Arc<Map>,
 	roomuserid_lastnotificationread: Arc<Map>,
 	roomsynctoken_shortstatehash: Arc<Map>,
 }
@@ -29,6 +36,10 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				db: args.db.clone(),
 				userroomid_notificationcount: args.db["userroomid_notificationcount"].clone(),
 				userroomid_highlightcount: args.db["userroomid_highlightcount"].clone(),
+				userroomthreadid_notificationcount: args.db["userroomthreadid_notificationcount"]
+					.clone(),
+				userroomthreadid_highlightcount: args.db["userroomthreadid_highlightcount"]
+					.clone(),
 				roomuserid_lastnotificationread: args.db["userroomid_highlightcount"].clone(),
 				roomsynctoken_shortstatehash: args.db["roomsynctoken_shortstatehash"].clone(),
 			},
@@ -39,6 +50,8 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
+/// Resets the counts of the main timeline of the room; the counts of its
+/// threads are kept.
 #[implement(Service)]
 pub fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
 	let count = self.services.globals.next_count();
@@ -57,8 +70,120 @@ pub fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
 		.put(roomuser_id, *count);
 }
 
+/// Resets the counts of the thread of the room with the root.
+#[implement(Service)]
+pub fn reset_thread_notification_counts(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+	thread_root: &EventId,
+) {
+	let count = self.services.globals.next_count();
+
+	let userroomthread_id = (user_id, room_id, thread_root);
+	self.db
+		.userroomthreadid_highlightcount
+		.del(userroomthread_id);
+	self.db
+		.userroomthreadid_notificationcount
+		.del(userroomthread_id);
+
+	let roomuser_id = (room_id, user_id);
+	self.db
+		.roomuserid_lastnotificationread
+		.put(roomuser_id, *count);
+}
+
+/// Resets the counts of the main timeline and of every thread of the room,
+/// as an unthreaded receipt reads them all.
+#[implement(Service)]
+pub async fn reset_all_notification_counts(&self, user_id: &UserId, room_id: &RoomId) {
+	self.delete_thread_notification_counts(user_id, room_id)
+		.await
+		.log_err()
+		.ok();
+
+	self.reset_notification_counts(user_id, room_id);
+}
+
+/// Removes the counts of every thread of the room for the user.
+#[implement(Service)]
+pub async fn delete_thread_notification_counts(
+	&self,
+	user_id: &UserId,
+	room_id: &RoomId,
+) -> Result<usize> {
+	let prefix = (user_id, room_id, Interfix);
+	let highlights = self
+		.db
+		.userroomthreadid_highlightcount
+		.del_prefix(&prefix)
+		.await?;
+
+	let notifications = self
+		.db
+		.userroomthreadid_notificationcount
+		.del_prefix(&prefix)
+		.await?;
+
+	Ok(highlights.saturating_add(notifications))
+}
+
+/// Removes the counts of every thread of the room for the local users who
+/// were ever joined to it.
+#[implement(Service)]
+pub async fn delete_room_thread_notification_counts(&self, room_id: &RoomId) -> Result<usize> {
+	let users: Vec<OwnedUserId> = self
+		.services
+		.state_cache
+		.room_useroncejoined(room_id)
+		.ready_filter(|user_id| self.services.globals.user_is_local(user_id))
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut deleted = 0_usize;
+	for user_id in &users {
+		let user_deleted = self
+			.delete_thread_notification_counts(user_id, room_id)
+			.await?;
+
+		deleted = deleted.saturating_add(user_deleted);
+	}
+
+	Ok(deleted)
+}
+
+/// The notification count of the room, of its main timeline and threads.
 #[implement(Service)]
 pub async fn notification_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
+	let threads = self
+		.thread_notification_counts(user_id, room_id)
+		.map(|(_, notification_count, _)| notification_count)
+		.ready_fold(0_u64, u64::saturating_add);
+
+	let (main, threads) = join(self.main_notification_count(user_id, room_id), threads).await;
+
+	main.saturating_add(threads)
+}
+
+/// The highlight count of the room, of its main timeline and threads.
+#[implement(Service)]
+pub async fn highlight_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
+	let threads = self
+		.thread_notification_counts(user_id, room_id)
+		.map(|(_, _, highlight_count)| highlight_count)
+		.ready_fold(0_u64, u64::saturating_add);
+
+	let (main, threads) = join(self.main_highlight_count(user_id, room_id), threads).await;
+
+	main.saturating_add(threads)
+}
+
+/// The notification count of the main timeline of the room, without its
+/// threads.
+#[implement(Service)]
+pub async fn main_notification_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
 	let key = (user_id, room_id);
 	self.db
 		.userroomid_notificationcount
@@ -68,8 +193,9 @@ pub async fn notification_count(&self, user_id: &UserId, room_id: &RoomId) -> u6
 		.unwrap_or(0)
 }
 
+/// The highlight count of the main timeline of the room, without its threads.
 #[implement(Service)]
-pub async fn highlight_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
+pub async fn main_highlight_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
 	let key = (user_id, room_id);
 	self.db
 		.userroomid_highlightcount
@@ -79,6 +205,36 @@ pub async fn highlight_count(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
 		.unwrap_or(0)
 }
 
+/// The notification and highlight counts of the threads of the room with
+/// unread notifications, by thread root.
+#[implement(Service)]
+pub fn thread_notification_counts<'a>(
+	&'a self,
+	user_id: &'a UserId,
+	room_id: &'a RoomId,
+) -> impl Stream<Item = (OwnedEventId, u64, u64)> + Send + 'a {
+	type KeyVal<'a> = ((Ignore, Ignore, &'a EventId), u64);
+
+	self.db
+		.userroomthreadid_notificationcount
+		.stream_prefix(&(user_id, room_id, Interfix))
+		.ignore_err()
+		.map(|((_, _, thread_root), notification_count): KeyVal<'_>| {
+			(thread_root.to_owned(), notification_count)
+		})
+		.then(move |(thread_root, notification_count)| async move {
+			let highlight_count = self
+				.db
+				.userroomthreadid_highlightcount
+				.qry(&(user_id, room_id, &thread_root))
+				.await
+				.deserialized()
+				.unwrap_or(0);
+
+			(thread_root, notification_count, highlight_count)
+		})
+}
+
 #[implement(Service)]
 pub async fn last_notification_read(&self, user_id: &UserId, room_id: &RoomId) -> u64 {
 	let key = (room_id, user_id);
@@ -91,21 +247,12 @@ pub async fn last_notification_read(&self, user_id: &UserId, room_id: &RoomId) -
 }
 
 #[implement(Service)]
-pub async fn delete_room_notification_read(&self, room_id: &RoomId) -> Result {
+pub async fn delete_room_notification_read(&self, room_id: &RoomId) -> Result<usize> {
 	let key = (room_id, Interfix);
 	self.db
 		.roomuserid_lastnotificationread
-		.keys_prefix_raw(&key)
-		.ignore_err()
-		.ready_for_each(|key| {
-			trace!("Removing key: {key:?}");
-			self.db
-				.roomuserid_lastnotificationread
-				.remove(key);
-		})
-		.await;
-
-	Ok(())
+		.del_prefix(&key)
+		.await
 }
 
 #[implement(Service)]
@@ -151,7 +298,7 @@ pub async fn get_token_shortstatehash(
 }
 
 #[implement(Service)]
-pub async fn delete_room_synctokens(&self, room_id: &RoomId) -> Result {
+pub async fn delete_room_synctokens(&self, room_id: &RoomId) -> Result<usize> {
 	let shortroomid = self
 		.services
 		.short
@@ -160,13 +307,6 @@ pub async fn delete_room_synctokens(&self, room_id: &RoomId) -> Result {
 
 	self.db
 		.roomsynctoken_shortstatehash
-		.keys_prefix_raw(&shortroomid)
-		.ignore_err()
-		.ready_for_each(|key| {
-			trace!("Removing key: {key:?}");
-			self.db.roomsynctoken_shortstatehash.remove(key);
-		})
-		.await;
-
-	Ok(())
+		.del_prefix(&shortroomid)
+		.await
 }
@@ -0,0 +1,144 @@
+use futures::StreamExt;
+use ruma::{
+	EventId, OwnedEventId, RoomId, UserId,
+	events::{
+		relation::Thread,
+		room::message::{Relation, RoomMessageEventContent},
+	},
+};
+use tuwunel_core::matrix::pdu::PduBuilder;
+
+use crate::fixture::Fixture;
+
+async fn reply_in_thread(
+	fixture: &Fixture,
+	sender: &UserId,
+	room_id: &RoomId,
+	root: &EventId,
+) -> OwnedEventId {
+	let mut content = RoomMessageEventContent::text_plain("reply");
+	content.relates_to = Some(Relation::Thread(Thread::plain(root.to_owned(), root.to_owned())));
+
+	let state_lock = fixture.state.mutex.lock(room_id).await;
+	fixture
+		.timeline
+		.build_and_append_pdu(PduBuilder::timeline(&content), sender, room_id, &state_lock)
+		.await
+		.expect("reply sent")
+}
+
+/// Two threads with a reply each, one of them read by the receiver.
+#[tokio::test]
+async fn thread_counts_read_and_unread() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	embedded
+		.join_room(&bob, &room_id)
+		.await
+		.expect("bob joins");
+
+	let read = embedded
+		.send_message(&alice, &room_id, "read thread")
+		.await
+		.expect("root sent");
+	let unread = embedded
+		.send_message(&alice, &room_id, "unread thread")
+		.await
+		.expect("root sent");
+	reply_in_thread(&fixture, &alice, &room_id, &read).await;
+	reply_in_thread(&fixture, &alice, &room_id, &unread).await;
+
+	fixture
+		.user
+		.reset_thread_notification_counts(&bob, &room_id, &read);
+
+	let counts: Vec<_> = fixture
+		.user
+		.thread_notification_counts(&bob, &room_id)
+		.collect()
+		.await;
+	assert_eq!(counts, [(unread.clone(), 1, 0)], "only the unread thread is counted");
+
+	let main = fixture
+		.user
+		.main_notification_count(&bob, &room_id)
+		.await;
+	assert_eq!(
+		fixture
+			.user
+			.notification_count(&bob, &room_id)
+			.await,
+		main.saturating_add(1),
+		"the room count includes the unread thread"
+	);
+
+	let deleted = fixture
+		.user
+		.delete_room_thread_notification_counts(&room_id)
+		.await
+		.expect("deleted");
+	assert_eq!(deleted, 1, "the unread thread's count is deleted with the room");
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn thread_counts_removed_on_leave() {
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room");
+	embedded
+		.join_room(&bob, &room_id)
+		.await
+		.expect("bob joins");
+
+	let root = embedded
+		.send_message(&alice, &room_id, "thread")
+		.await
+		.expect("root sent");
+	reply_in_thread(&fixture, &alice, &room_id, &root).await;
+
+	let counted = fixture
+		.user
+		.thread_notification_counts(&bob, &room_id)
+		.count()
+		.await;
+	assert_eq!(counted, 1, "the thread is counted while joined");
+
+	embedded
+		.leave_room(&bob, &room_id)
+		.await
+		.expect("bob leaves");
+
+	let counted = fixture
+		.user
+		.thread_notification_counts(&bob, &room_id)
+		.count()
+		.await;
+	assert_eq!(counted, 0, "leaving removes the thread's counts");
+
+	fixture.stop().await;
+}
@@ -1,14 +1,18 @@
 use std::{fmt::Debug, sync::Arc};
 
 use futures::{Stream, StreamExt};
-use ruma::{OwnedServerName, ServerName, UserId};
+use ruma::{
+	OwnedServerName, ServerName, UserId, api::appservice::event::push_events::v1::EphemeralData,
+	events::AnyTimelineEvent, serde::Raw,
+};
+use serde::{Deserialize, Serialize};
 use tuwunel_core::{
 	Error, Result, at, utils,
 	utils::{ReadyExt, stream::TryIgnore},
 };
-use tuwunel_database::{Database, Deserialized, Map};
+use tuwunel_database::{Database, Deserialized, Ignore, Interfix, Json, Map};
 
-use super::{Destination, SendingEvent};
+use super::{Destination, SendingEvent, health::DestinationHealth};
 
 pub(super) type OutgoingItem = (Key, SendingEvent, Destination);
 pub(super) type SendingItem = (Key, SendingEvent);
@@ -16,20 +20,36 @@
 pub(super) type Key = Vec<u8>;
 
 pub struct Data {
+	appserviceidtxnid_txn: Arc<Map>,
 	servercurrentevent_data: Arc<Map>,
 	servernameevent_data: Arc<Map>,
 	servername_educount: Arc<Map>,
+	servername_health: Arc<Map>,
 	pub(super) db: Arc<Database>,
 	services: Arc<crate::services::OnceServices>,
 }
 
+/// A transaction to an appservice, kept until the appservice accepts it so
+/// that it is sent again, under the same ID, after a failure or a restart.
+/// Transactions are keyed by a count, so they are sent in the order made.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct AppserviceTxn {
+	/// Hash of the events the transaction was made of, recognizing a retry
+	/// of the same events.
+	pub(super) digest: String,
+	pub(super) events: Vec<Raw<AnyTimelineEvent>>,
+	pub(super) ephemeral: Vec<Raw<EphemeralData>>,
+}
+
 impl Data {
 	pub(super) fn new(args: &crate::Args<'_>) -> Self {
 		let db = &args.db;
 		Self {
+			appserviceidtxnid_txn: db["appserviceidtxnid_txn"].clone(),
 			servercurrentevent_data: db["servercurrentevent_data"].clone(),
 			servernameevent_data: db["servernameevent_data"].clone(),
 			servername_educount: db["servername_educount"].clone(),
+			servername_health: db["servername_health"].clone(),
 			db: args.db.clone(),
 			services: args.services.clone(),
 		}
@@ -164,6 +184,103 @@ pub fn queued_requests(
 			})
 	}
 
+	/// Drops the oldest events queued for the destination beyond the number
+	/// to keep, returning how many were dropped. Queued events end in the
+	/// count they were queued at, or of their PDU.
+	pub(super) async fn trim_queued_requests(
+		&self,
+		destination: &Destination,
+		keep: usize,
+	) -> usize {
+		let prefix = destination.get_prefix();
+		let mut keys: Vec<Key> = self
+			.servernameevent_data
+			.raw_keys_prefix(&prefix)
+			.ignore_err()
+			.map(<[u8]>::to_vec)
+			.collect()
+			.await;
+
+		let Some(excess) = keys
+			.len()
+			.checked_sub(keep)
+			.filter(|&excess| excess > 0)
+		else {
+			return 0;
+		};
+
+		keys.sort_by_key(|key| queued_count(key));
+		keys.iter()
+			.take(excess)
+			.for_each(|key| self.servernameevent_data.remove(key));
+
+		excess
+	}
+
+	pub(super) fn put_appservice_txn(&self, id: &str, txn_id: u64, txn: &AppserviceTxn) {
+		self.appserviceidtxnid_txn
+			.put((id, txn_id), Json(txn));
+	}
+
+	pub(super) fn delete_appservice_txn(&self, id: &str, txn_id: u64) {
+		self.appserviceidtxnid_txn.del((id, txn_id));
+	}
+
+	/// Whether a transaction of the events with the digest is kept.
+	pub(super) async fn has_appservice_txn(&self, id: &str, digest: &str) -> bool {
+		self.appservice_txns(id)
+			.ready_any(|(_, txn)| txn.digest == digest)
+			.await
+	}
+
+	pub(super) async fn delete_appservice_txns(&self, id: &str) {
+		self.appserviceidtxnid_txn
+			.keys_prefix_raw(&(id, Interfix))
+			.ignore_err()
+			.ready_for_each(|key| self.appserviceidtxnid_txn.remove(key))
+			.await;
+	}
+
+	/// The transactions to the appservice not yet accepted by it, oldest
+	/// first.
+	pub(super) fn appservice_txns<'a>(
+		&'a self,
+		id: &'a str,
+	) -> impl Stream<Item = (u64, AppserviceTxn)> + Send + 'a {
+		type KeyVal = ((Ignore, u64), AppserviceTxn);
+
+		self.appserviceidtxnid_txn
+			.stream_prefix(&(id, Interfix))
+			.ignore_err()
+			.map(|((_, txn_id), txn): KeyVal| (txn_id, txn))
+	}
+
+	/// The appservices with transactions not yet accepted by them.
+	pub(super) fn appservices_with_txns(&self) -> impl Stream<Item = String> + Send + '_ {
+		self.appserviceidtxnid_txn
+			.keys()
+			.ignore_err()
+			.map(|(id, _): (&str, Ignore)| id.to_owned())
+	}
+
+	pub(super) fn destinations_health(
+		&self,
+	) -> impl Stream<Item = (OwnedServerName, DestinationHealth)> + Send + '_ {
+		self.servername_health
+			.stream()
+			.ignore_err()
+			.map(|(server, health): (&ServerName, DestinationHealth)| (server.to_owned(), health))
+	}
+
+	pub(super) fn put_health(&self, server_name: &ServerName, health: &DestinationHealth) {
+		self.servername_health
+			.raw_put(server_name, Json(health));
+	}
+
+	pub(super) fn del_health(&self, server_name: &ServerName) {
+		self.servername_health.remove(server_name);
+	}
+
 	pub(super) fn set_latest_educount(&self, server_name: &ServerName, last_count: u64) {
 		self.servername_educount
 			.raw_put(server_name, last_count);
@@ -178,6 +295,15 @@ pub async fn get_latest_educount(&self, server_name: &ServerName) -> u64 {
 	}
 }
 
+/// The count ending the key of a queued event, ordering them by age.
+pub(super) fn queued_count(key: &[u8]) -> u64 {
+	key.len()
+		.checked_sub(size_of::<u64>())
+		.and_then(|start| key.get(start..))
+		.and_then(|count| count.try_into().ok())
+		.map_or(0, u64::from_be_bytes)
+}
+
 fn parse_servercurrentevent(key: &[u8], value: &[u8]) -> Result<(Destination, SendingEvent)> {
 	// Appservices start with a plus
 	Ok::<_, Error>(if key.starts_with(b"+") {
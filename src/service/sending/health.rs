@@ -0,0 +1,243 @@
+//! Health of the federation destinations. A destination failing every
+//! transaction `sender_dormant_failures` times in a row, over at least
+//! `sender_dormant_after`, becomes dormant: its events keep being queued, up to
+//! `sender_dormant_queue_max` with the oldest dropped, but none are sent until
+//! the server is heard from again by a transaction or a key query. Once
+//! `sender_dormant_probation` passed, new events try the destination again; a
+//! failure puts it back to sleep for another probation. The health is kept in
+//! `servername_health` so a restart does not start the backoff over.
+
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use ruma::{OwnedServerName, ServerName};
+use serde::{Deserialize, Serialize};
+use tuwunel_core::{
+	Error, debug, implement, info, result::LogErr, utils::millis_since_unix_epoch, warn,
+};
+
+use super::{Destination, Msg, SendingEvent};
+
+/// Messages queued for a dormant destination between trimmings of its queue.
+const DORMANT_TRIM_BATCH: usize = 64;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DestinationHealth {
+	/// Transactions failed in a row.
+	pub failures: u32,
+
+	/// When the first of those failed, in milliseconds since the epoch.
+	pub failing_since: Option<u64>,
+
+	/// When the destination went to sleep, in milliseconds since the epoch.
+	pub dormant_since: Option<u64>,
+
+	/// When the destination was last woken, and what woke it.
+	pub woken: Option<(u64, String)>,
+
+	/// When the destination was last woken since startup, so the sender
+	/// retries at once rather than after its backoff.
+	#[serde(skip)]
+	woken_at: Option<Instant>,
+
+	/// Messages queued since the queue was last trimmed while dormant.
+	#[serde(skip)]
+	queued: usize,
+}
+
+/// Loads the health of the destinations kept since before the restart.
+#[implement(super::Service)]
+pub(super) async fn load_health(&self) {
+	let health: Vec<_> = self.db.destinations_health().collect().await;
+
+	let dormant = health
+		.iter()
+		.filter(|(_, health)| health.dormant_since.is_some())
+		.count();
+
+	debug!(destinations = health.len(), dormant, "Loaded federation destination health");
+	self.health.lock().expect("locked").extend(health);
+}
+
+/// The health of the destination; `None` when it has not failed lately.
+#[implement(super::Service)]
+#[must_use]
+pub fn destination_health(&self, server: &ServerName) -> Option<DestinationHealth> {
+	self.health
+		.lock()
+		.expect("locked")
+		.get(server)
+		.cloned()
+}
+
+/// The destinations which failed lately, dormant or not.
+#[implement(super::Service)]
+#[must_use]
+pub fn destinations_health(&self) -> Vec<(OwnedServerName, DestinationHealth)> {
+	let mut health: Vec<_> = self
+		.health
+		.lock()
+		.expect("locked")
+		.iter()
+		.map(|(server, health)| (server.clone(), health.clone()))
+		.collect();
+
+	health.sort_by(|(a, _), (b, _)| a.cmp(b));
+	health
+}
+
+/// Wakes the dormant destination after hearing from it, sending its queued
+/// events.
+#[implement(super::Service)]
+pub fn wake_destination(&self, server: &ServerName, reason: &str) {
+	let woken = {
+		let mut health = self.health.lock().expect("locked");
+		let Some(health) = health.get_mut(server) else {
+			return;
+		};
+
+		let woken = health.wake(millis_since_unix_epoch(), reason);
+		if woken {
+			self.db.put_health(server, health);
+		}
+
+		woken
+	};
+
+	if woken {
+		info!(%server, %reason, "Dormant federation destination woken");
+		self.dispatch(Msg {
+			dest: Destination::Federation(server.to_owned()),
+			event: SendingEvent::Flush,
+			queue_id: Vec::new(),
+		})
+		.log_err()
+		.ok();
+	}
+}
+
+/// Whether the destination is dormant and not due a probe.
+#[implement(super::Service)]
+pub(super) fn is_asleep(&self, server: &ServerName) -> bool {
+	let probation = Duration::from_secs(self.server.config.sender_dormant_probation);
+
+	self.health
+		.lock()
+		.expect("locked")
+		.get(server)
+		.is_some_and(|health| health.asleep(millis_since_unix_epoch(), probation))
+}
+
+/// Whether the destination was woken after the time, e.g. of its last failure.
+#[implement(super::Service)]
+pub(super) fn woken_after(&self, server: &ServerName, time: Instant) -> bool {
+	self.health
+		.lock()
+		.expect("locked")
+		.get(server)
+		.and_then(|health| health.woken_at)
+		.is_some_and(|woken_at| woken_at > time)
+}
+
+#[implement(super::Service)]
+pub(super) fn note_failure(&self, server: &ServerName, error: &Error) {
+	let config = &self.server.config;
+	let after = Duration::from_secs(config.sender_dormant_after);
+
+	let mut health = self.health.lock().expect("locked");
+	let health = health.entry(server.to_owned()).or_default();
+	if health.fail(millis_since_unix_epoch(), config.sender_dormant_failures, after) {
+		warn!(
+			%server,
+			failures = health.failures,
+			"Federation destination is dormant until heard from: {error}"
+		);
+	}
+
+	self.db.put_health(server, health);
+}
+
+#[implement(super::Service)]
+pub(super) fn note_success(&self, server: &ServerName) {
+	if self
+		.health
+		.lock()
+		.expect("locked")
+		.remove(server)
+		.is_some()
+	{
+		self.db.del_health(server);
+	}
+}
+
+/// Drops the oldest events queued for the dormant destination beyond
+/// `sender_dormant_queue_max`, every few messages queued.
+#[implement(super::Service)]
+pub(super) async fn bound_dormant_queue(&self, server: &ServerName) {
+	let due = self
+		.health
+		.lock()
+		.expect("locked")
+		.get_mut(server)
+		.is_some_and(|health| {
+			health.queued = health.queued.saturating_add(1);
+			health.queued >= DORMANT_TRIM_BATCH && {
+				health.queued = 0;
+				true
+			}
+		});
+
+	if !due {
+		return;
+	}
+
+	let dest = Destination::Federation(server.to_owned());
+	let keep = self.server.config.sender_dormant_queue_max;
+	let dropped = self.db.trim_queued_requests(&dest, keep).await;
+	if dropped > 0 {
+		debug!(%server, dropped, "Dropped oldest events queued for dormant destination");
+	}
+}
+
+impl DestinationHealth {
+	/// Counts a failed transaction. Returns true when the destination just
+	/// went to sleep; zero `failures` never puts it to sleep.
+	pub(super) fn fail(&mut self, now: u64, failures: u32, after: Duration) -> bool {
+		self.failures = self.failures.saturating_add(1);
+		let failing_since = *self.failing_since.get_or_insert(now);
+		let failing_for = now.saturating_sub(failing_since);
+
+		let after = u64::try_from(after.as_millis()).unwrap_or(u64::MAX);
+		if failures == 0 || self.failures < failures || failing_for < after {
+			return false;
+		}
+
+		// A failed probe after the probation puts it back to sleep.
+		self.dormant_since.replace(now).is_none()
+	}
+
+	/// Whether the destination is dormant and its probation not over.
+	#[must_use]
+	pub fn asleep(&self, now: u64, probation: Duration) -> bool {
+		let probation = u64::try_from(probation.as_millis()).unwrap_or(u64::MAX);
+
+		self.dormant_since
+			.is_some_and(|since| now.saturating_sub(since) < probation)
+	}
+
+	/// Wakes the dormant destination, starting its failures over. Returns
+	/// false when it was not dormant.
+	pub(super) fn wake(&mut self, now: u64, reason: &str) -> bool {
+		if self.dormant_since.take().is_none() {
+			return false;
+		}
+
+		self.failures = 0;
+		self.failing_since = None;
+		self.woken = Some((now, reason.to_owned()));
+		self.woken_at = Some(Instant::now());
+		self.queued = 0;
+
+		true
+	}
+}
@@ -1,19 +1,23 @@
 mod appservice;
 mod data;
 mod dest;
+mod health;
 mod sender;
+#[cfg(test)]
+mod tests;
 
 use std::{
+	collections::HashMap,
 	fmt::Debug,
 	hash::{DefaultHasher, Hash, Hasher},
 	iter::once,
-	sync::Arc,
+	sync::{Arc, Mutex},
 };
 
 use async_trait::async_trait;
 use futures::{FutureExt, Stream, StreamExt};
 use ruma::{
-	RoomId, ServerName, UserId,
+	OwnedServerName, RoomId, ServerName, UserId,
 	api::{OutgoingRequest, appservice::Registration},
 };
 use tokio::{task, task::JoinSet};
@@ -27,6 +31,7 @@
 use self::data::Data;
 pub use self::{
 	dest::Destination,
+	health::DestinationHealth,
 	sender::{EDU_LIMIT, PDU_LIMIT},
 };
 use crate::rooms::timeline::RawPduId;
@@ -36,6 +41,29 @@ pub struct Service {
 	server: Arc<Server>,
 	services: Arc<crate::services::OnceServices>,
 	channels: Vec<(loole::Sender<Msg>, loole::Receiver<Msg>)>,
+	appservice_failures: Mutex<HashMap<String, AppserviceFailure>>,
+	health: Mutex<HashMap<OwnedServerName, DestinationHealth>>,
+}
+
+/// Delivery of the transactions to an appservice.
+#[derive(Clone, Debug, Default)]
+pub struct AppserviceQueue {
+	/// Events awaiting a transaction.
+	pub queued: usize,
+	/// Events in the transaction being delivered.
+	pub active: usize,
+	/// Transactions kept until the appservice accepts them.
+	pub transactions: usize,
+	/// Failures since the last transaction accepted.
+	pub failures: u32,
+	/// The last failure, in milliseconds since the epoch, with its error.
+	pub last_error: Option<(u64, String)>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct AppserviceFailure {
+	count: u32,
+	last_error: Option<(u64, String)>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -70,10 +98,14 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 			channels: (0..num_senders)
 				.map(|_| loole::unbounded())
 				.collect(),
+			appservice_failures: Mutex::default(),
+			health: Mutex::default(),
 		}))
 	}
 
 	async fn worker(self: Arc<Self>) -> Result {
+		self.load_health().await;
+
 		let mut senders =
 			self.channels
 				.iter()
@@ -120,6 +152,36 @@ fn unconstrained(&self) -> bool { true }
 }
 
 impl Service {
+	/// Number of messages dispatched to the sender workers not yet received
+	/// by them.
+	#[must_use]
+	pub fn queue_depth(&self) -> usize {
+		self.channels
+			.iter()
+			.map(|(sender, _)| sender.len())
+			.sum()
+	}
+
+	/// Delivery of the transactions to the appservice.
+	pub async fn appservice_queue(&self, id: &str) -> AppserviceQueue {
+		let dest = Destination::Appservice(id.to_owned());
+		let failure = self
+			.appservice_failures
+			.lock()
+			.expect("locked")
+			.get(id)
+			.cloned()
+			.unwrap_or_default();
+
+		AppserviceQueue {
+			queued: self.db.queued_requests(&dest).count().await,
+			active: self.db.active_requests_for(&dest).count().await,
+			transactions: self.db.appservice_txns(id).count().await,
+			failures: failure.count,
+			last_error: failure.last_error,
+		}
+	}
+
 	#[tracing::instrument(skip(self, pdu_id, user, pushkey), level = "debug")]
 	pub fn send_pdu_push(&self, pdu_id: &RawPduId, user: &UserId, pushkey: String) -> Result {
 		let dest = Destination::Push(user.to_owned(), pushkey);
@@ -346,6 +408,10 @@ pub async fn cleanup_events(
 					.delete_all_requests_for(&Destination::Appservice(appservice_id.to_owned()))
 					.await;
 
+				self.db
+					.delete_appservice_txns(appservice_id)
+					.await;
+
 				Ok(())
 			},
 			| _ => {
@@ -17,9 +17,10 @@
 };
 use ruma::{
 	MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, ServerName,
-	UInt,
+	UInt, UserId,
 	api::{
-		appservice::event::push_events::v1::EphemeralData,
+		appservice::{Registration, event::push_events},
+		client::push::Pusher,
 		federation::transactions::{
 			edu::{
 				DeviceListUpdateContent, Edu, PresenceContent, PresenceUpdate, ReceiptContent,
@@ -29,21 +30,19 @@
 		},
 	},
 	device_id,
-	events::{
-		AnySyncEphemeralRoomEvent, GlobalAccountDataEventType, push_rules::PushRulesEvent,
-		receipt::ReceiptType,
-	},
+	events::{AnySyncEphemeralRoomEvent, receipt::ReceiptType},
 	presence::PresenceState,
-	push,
 	serde::Raw,
 	uint,
 };
+use tokio::time::sleep;
 use tuwunel_core::{
-	Error, Event, Result, debug, err, error,
+	Error, Event, Result, at, debug, err, error,
+	matrix::PduEvent,
 	result::LogErr,
 	trace,
 	utils::{
-		ReadyExt, calculate_hash, continue_exponential_backoff_secs,
+		self, ReadyExt, calculate_hash, continue_exponential_backoff_secs,
 		future::TryExtExt,
 		stream::{BroadbandExt, IterStream, WidebandExt},
 	},
@@ -51,8 +50,10 @@
 };
 
 use super::{
-	Destination, EduBuf, EduVec, Msg, SendingEvent, Service, appservice, data::QueueItem,
+	Destination, EduBuf, EduVec, Msg, SendingEvent, Service, appservice,
+	data::{AppserviceTxn, QueueItem},
 };
+use crate::presence::federation_state;
 
 #[derive(Debug)]
 enum TransactionStatus {
@@ -140,10 +141,37 @@ async fn handle_response<'a>(
 			| Ok(dest) =>
 				self.handle_response_ok(&dest, futures, statuses)
 					.await,
-			| Err((dest, e)) => Self::handle_response_err(dest, statuses, &e),
+			| Err((dest, e)) => {
+				Self::handle_response_err(dest.clone(), statuses, &e);
+				match dest {
+					| Destination::Appservice(id) => self.retry_appservice(id, futures, statuses),
+					| Destination::Federation(server) => self.note_failure(&server, &e),
+					| Destination::Push(..) => {},
+				}
+			},
 		}
 	}
 
+	/// Sends the transactions to the appservice again after a delay growing
+	/// with each failure, up to `appservice_max_backoff`.
+	fn retry_appservice<'a>(
+		&'a self,
+		id: String,
+		futures: &mut SendingFutures<'a>,
+		statuses: &mut CurTransactionStatus,
+	) {
+		let dest = Destination::Appservice(id.clone());
+		let Some(&TransactionStatus::Failed(tries, _)) = statuses.get(&dest) else {
+			return;
+		};
+
+		let delay = appservice_backoff(tries, self.server.config.appservice_max_backoff);
+		debug!(?dest, tries, ?delay, "Retrying appservice transactions");
+
+		statuses.insert(dest, TransactionStatus::Retrying(tries));
+		futures.push(self.resend_appservice(id, delay).boxed());
+	}
+
 	fn handle_response_err(dest: Destination, statuses: &mut CurTransactionStatus, e: &Error) {
 		debug!(dest = ?dest, "{e:?}");
 		statuses.entry(dest).and_modify(|e| {
@@ -165,6 +193,10 @@ async fn handle_response_ok<'a>(
 		futures: &mut SendingFutures<'a>,
 		statuses: &mut CurTransactionStatus,
 	) {
+		if let Destination::Federation(server) = dest {
+			self.note_success(server);
+		}
+
 		let _cork = self.db.db.cork();
 		self.db.delete_all_active_requests_for(dest).await;
 
@@ -199,6 +231,14 @@ async fn handle_request<'a>(
 		futures: &mut SendingFutures<'a>,
 		statuses: &mut CurTransactionStatus,
 	) {
+		// Events for dormant destinations stay queued until they are heard from.
+		if let Destination::Federation(server) = &msg.dest
+			&& self.is_asleep(server)
+		{
+			self.bound_dormant_queue(server).await;
+			return;
+		}
+
 		let iv = vec![(msg.queue_id, msg.event)];
 		if let Ok(Some(events)) = self.select_events(&msg.dest, iv, statuses).await {
 			if !events.is_empty() {
@@ -263,6 +303,14 @@ async fn startup_netburst<'a>(
 				continue;
 			}
 
+			// Left to be retried once the dormant destination is heard from.
+			if let Destination::Federation(server) = &dest
+				&& self.is_asleep(server)
+			{
+				statuses.insert(dest, TransactionStatus::Failed(1, Instant::now()));
+				continue;
+			}
+
 			let entry = txns.entry(dest.clone()).or_default();
 			if self.server.config.startup_netburst_keep >= 0 && entry.len() >= keep {
 				warn!("Dropping unsent event {dest:?} {:?}", String::from_utf8_lossy(&key));
@@ -272,12 +320,35 @@ async fn startup_netburst<'a>(
 			}
 		}
 
+		// Transactions appservices had not accepted before the restart.
+		let mut appservices: HashSet<String> = self
+			.db
+			.appservices_with_txns()
+			.ready_filter(|appservice| {
+				self.shard_id(&Destination::Appservice(appservice.clone())) == id
+			})
+			.collect()
+			.await;
+
 		for (dest, events) in txns {
 			if self.server.config.startup_netburst && !events.is_empty() {
+				if let Destination::Appservice(appservice) = &dest {
+					appservices.remove(appservice);
+				}
+
 				statuses.insert(dest.clone(), TransactionStatus::Running);
 				futures.push(self.send_events(dest.clone(), events));
 			}
 		}
+
+		for appservice in appservices {
+			statuses
+				.insert(Destination::Appservice(appservice.clone()), TransactionStatus::Running);
+			futures.push(
+				self.resend_appservice(appservice, Duration::ZERO)
+					.boxed(),
+			);
+		}
 	}
 
 	#[tracing::instrument(
@@ -352,8 +423,14 @@ fn select_events_current(
 					// Fail if a request has failed recently (exponential backoff)
 					let min = self.server.config.sender_timeout;
 					let max = self.server.config.sender_retry_backoff_limit;
+					let woken = match dest {
+						| Destination::Federation(server) => self.woken_after(server, *time),
+						| _ => false,
+					};
+
 					if continue_exponential_backoff_secs(min, max, time.elapsed(), *tries)
 						&& !matches!(dest, Destination::Appservice(_))
+						&& !woken
 					{
 						allow = false;
 					} else {
@@ -641,7 +718,7 @@ async fn select_edus_presence(
 
 			let update = PresenceUpdate {
 				user_id: user_id.into(),
-				presence: presence_event.content.presence,
+				presence: federation_state(presence_event.content.presence),
 				currently_active: presence_event
 					.content
 					.currently_active
@@ -714,19 +791,97 @@ async fn send_events_dest_appservice(
 			));
 		};
 
-		let mut pdu_jsons = Vec::with_capacity(
-			events
-				.iter()
-				.filter(|event| matches!(event, SendingEvent::Pdu(_)))
-				.count(),
-		);
-		let mut edu_jsons: Vec<Raw<EphemeralData>> = Vec::with_capacity(
-			events
-				.iter()
-				.filter(|event| matches!(event, SendingEvent::Edu(_)))
-				.count(),
-		);
-		for event in &events {
+		// The transaction is kept before it is sent, under the next count; a
+		// retry of the same events sends what was kept under the same ID.
+		if !events.is_empty() {
+			let txn_hash = calculate_hash(events.iter().filter_map(|e| match e {
+				| SendingEvent::Edu(b) => Some(b.as_ref()),
+				| SendingEvent::Pdu(b) => Some(b.as_ref()),
+				| SendingEvent::Flush => None,
+			}));
+
+			let digest = URL_SAFE_NO_PAD.encode(txn_hash);
+			if !self.db.has_appservice_txn(&id, &digest).await {
+				let txn = self
+					.appservice_txn(&appservice, &events, digest)
+					.await;
+
+				let txn_id = self.services.globals.next_count();
+				self.db.put_appservice_txn(&id, *txn_id, &txn);
+			}
+		}
+
+		let result = self.send_appservice_txns(&id, &appservice).await;
+
+		self.record_appservice_result(&id, result.as_ref().err());
+		match result {
+			| Ok(()) => Ok(Destination::Appservice(id)),
+			| Err(e) => Err((Destination::Appservice(id), e)),
+		}
+	}
+
+	/// Sends the transactions kept for the appservice, stopping at the first
+	/// it does not accept; those accepted are forgotten.
+	async fn send_appservice_txns(&self, id: &str, appservice: &Registration) -> Result {
+		let txns: Vec<_> = self.db.appservice_txns(id).collect().await;
+
+		let client = &self.services.client.appservice;
+		for (txn_id, txn) in txns {
+			appservice::send_request(client, appservice.clone(), push_events::v1::Request {
+				txn_id: txn_id.to_string().into(),
+				events: txn.events,
+				ephemeral: txn.ephemeral,
+				to_device: Vec::new(), // TODO
+			})
+			.await?;
+
+			self.db.delete_appservice_txn(id, txn_id);
+		}
+
+		Ok(())
+	}
+
+	/// Sends the transactions of the appservice again after the delay, with
+	/// the events of the transaction being delivered.
+	async fn resend_appservice(&self, id: String, delay: Duration) -> SendingResult {
+		tokio::select! {
+			() = sleep(delay) => {},
+			() = self.server.until_shutdown() => {
+				return Err((
+					Destination::Appservice(id),
+					err!("Shutting down before retrying appservice transactions"),
+				));
+			},
+		}
+
+		let dest = Destination::Appservice(id.clone());
+		let events: Vec<_> = self
+			.db
+			.active_requests_for(&dest)
+			.map(at!(1))
+			.collect()
+			.await;
+
+		// Nothing is left to send once the appservice was unregistered.
+		if events.is_empty() && self.db.appservice_txns(&id).count().await == 0 {
+			return Ok(dest);
+		}
+
+		self.send_events_dest_appservice(id, events).await
+	}
+
+	async fn appservice_txn(
+		&self,
+		appservice: &Registration,
+		events: &[SendingEvent],
+		digest: String,
+	) -> AppserviceTxn {
+		let mut txn = AppserviceTxn {
+			digest,
+			events: Vec::new(),
+			ephemeral: Vec::new(),
+		};
+		for event in events {
 			match event {
 				| SendingEvent::Pdu(pdu_id) => {
 					if let Ok(pdu) = self
@@ -735,7 +890,7 @@ async fn send_events_dest_appservice(
 						.get_pdu_from_id(pdu_id)
 						.await
 					{
-						pdu_jsons.push(pdu.to_format());
+						txn.events.push(pdu.to_format());
 					}
 				},
 				| SendingEvent::Edu(edu) =>
@@ -743,39 +898,27 @@ async fn send_events_dest_appservice(
 						if let Ok(edu) =
 							serde_json::from_slice(edu).and_then(|edu| Raw::new(&edu))
 						{
-							edu_jsons.push(edu);
+							txn.ephemeral.push(edu);
 						}
 					},
 				| SendingEvent::Flush => {}, // flush only; no new content
 			}
 		}
 
-		let txn_hash = calculate_hash(events.iter().filter_map(|e| match e {
-			| SendingEvent::Edu(b) => Some(b.as_ref()),
-			| SendingEvent::Pdu(b) => Some(b.as_ref()),
-			| SendingEvent::Flush => None,
-		}));
+		txn
+	}
 
-		let txn_id = &*URL_SAFE_NO_PAD.encode(txn_hash);
+	fn record_appservice_result(&self, id: &str, error: Option<&Error>) {
+		let mut failures = self.appservice_failures.lock().expect("locked");
 
-		//debug_assert!(pdu_jsons.len() + edu_jsons.len() > 0, "sending empty
-		// transaction");
-		let client = &self.services.client.appservice;
-		match appservice::send_request(
-			client,
-			appservice,
-			ruma::api::appservice::event::push_events::v1::Request {
-				txn_id: txn_id.into(),
-				events: pdu_jsons,
-				ephemeral: edu_jsons,
-				to_device: Vec::new(), // TODO
-			},
-		)
-		.await
-		{
-			| Ok(_) => Ok(Destination::Appservice(id)),
-			| Err(e) => Err((Destination::Appservice(id), e)),
-		}
+		let Some(error) = error else {
+			failures.remove(id);
+			return;
+		};
+
+		let failure = failures.entry(id.to_owned()).or_default();
+		failure.count = failure.count.saturating_add(1);
+		failure.last_error = Some((utils::millis_since_unix_epoch(), error.to_string()));
 	}
 
 	#[tracing::instrument(
@@ -792,112 +935,109 @@ async fn send_events_dest_push(
 		pushkey: String,
 		events: Vec<SendingEvent>,
 	) -> SendingResult {
+		let dest = Destination::Push(user_id.clone(), pushkey.clone());
 		let Ok(pusher) = self
 			.services
 			.pusher
 			.get_pusher(&user_id, &pushkey)
 			.await
 		else {
-			return Err((
-				Destination::Push(user_id.clone(), pushkey.clone()),
-				err!(Database(error!(?user_id, ?pushkey, "Missing pusher"))),
-			));
+			return Err((dest, err!(Database(error!(?user_id, ?pushkey, "Missing pusher")))));
 		};
 
-		let mut pdus = Vec::with_capacity(
-			events
-				.iter()
-				.filter(|event| matches!(event, SendingEvent::Pdu(_)))
-				.count(),
-		);
 		for event in &events {
-			match event {
-				| SendingEvent::Pdu(pdu_id) => {
-					if let Ok(pdu) = self
-						.services
-						.timeline
-						.get_pdu_from_id(pdu_id)
-						.await
-					{
-						pdus.push(pdu);
-					}
-				},
-				| SendingEvent::Edu(_) | SendingEvent::Flush => {
-					// Push gateways don't need EDUs (?) and flush only;
-					// no new content
-				},
-			}
-		}
-
-		for pdu in pdus {
-			// Redacted events are not notification targets (we don't send push for them)
-			if pdu.contains_unsigned_property("redacted_because", serde_json::Value::is_string) {
+			// Push gateways don't need EDUs (?) and flush only; no new content
+			let SendingEvent::Pdu(pdu_id) = event else {
 				continue;
-			}
+			};
 
-			// optional suppression: heuristic combining presence age and recent sync
-			// activity.
-			if self.services.config.suppress_push_when_active
-				&& let Ok(presence) = self
-					.services
-					.presence
-					.get_presence(&user_id)
-					.await
+			if let Ok(pdu) = self
+				.services
+				.timeline
+				.get_pdu_from_id(pdu_id)
+				.await
 			{
-				let is_online = presence.content.presence == PresenceState::Online;
-
-				let presence_age_ms = presence
-					.content
-					.last_active_ago
-					.map(u64::from)
-					.unwrap_or(u64::MAX);
+				// The notices are sent again after a backoff when the gateway failed
+				self.push_pdu(&user_id, &pusher, &pdu)
+					.await
+					.map_err(|e| (dest.clone(), e))?;
+			}
 
-				let sync_gap_ms = self
-					.services
-					.presence
-					.last_sync_gap_ms(&user_id)
-					.await;
+			// Sent or skipped, so a retry after a later failure resumes past it
+			let mut key = dest.get_prefix();
+			key.extend_from_slice(pdu_id.as_ref());
+			self.db.delete_active_request(&key);
+		}
 
-				let considered_active = is_online
-					&& presence_age_ms < 65_000
-					&& sync_gap_ms.is_some_and(|gap| gap < 32_000);
+		Ok(dest)
+	}
 
-				if considered_active {
-					trace!(
-						?user_id,
-						presence_age_ms, sync_gap_ms, "suppressing push: active heuristic"
-					);
-					continue;
-				}
-			}
+	/// Sends the push notice of the event to the pusher of the user, unless
+	/// the event should not notify them.
+	async fn push_pdu(&self, user_id: &UserId, pusher: &Pusher, pdu: &PduEvent) -> Result {
+		// Redacted events are not notification targets (we don't send push for them)
+		if pdu.contains_unsigned_property("redacted_because", serde_json::Value::is_string) {
+			return Ok(());
+		}
 
-			let rules_for_user = self
+		// Never push a user's own events nor events from users they ignore; either
+		// may have changed since the event was queued.
+		if user_id == pdu.sender()
+			|| self
 				.services
-				.account_data
-				.get_global(&user_id, GlobalAccountDataEventType::PushRules)
+				.users
+				.user_is_ignored(pdu.sender(), user_id)
 				.await
-				.map_or_else(
-					|_| push::Ruleset::server_default(&user_id),
-					|ev: PushRulesEvent| ev.content.global,
-				);
+		{
+			return Ok(());
+		}
 
-			let unread: UInt = self
-				.services
-				.user
-				.notification_count(&user_id, pdu.room_id())
-				.await
-				.try_into()
-				.expect("notification count can't go that high");
+		// optional suppression: heuristic combining presence age and recent sync
+		// activity.
+		if self.services.config.suppress_push_when_active
+			&& let Ok(presence) = self.services.presence.get_presence(user_id).await
+		{
+			let is_online = presence.content.presence == PresenceState::Online;
 
-			let _response = self
+			let presence_age_ms = presence
+				.content
+				.last_active_ago
+				.map(u64::from)
+				.unwrap_or(u64::MAX);
+
+			let sync_gap_ms = self
 				.services
-				.pusher
-				.send_push_notice(&user_id, unread, &pusher, rules_for_user, &pdu)
-				.await
-				.map_err(|e| (Destination::Push(user_id.clone(), pushkey.clone()), e));
+				.presence
+				.last_sync_gap_ms(user_id)
+				.await;
+
+			let considered_active = is_online
+				&& presence_age_ms < 65_000
+				&& sync_gap_ms.is_some_and(|gap| gap < 32_000);
+
+			if considered_active {
+				trace!(
+					?user_id,
+					presence_age_ms, sync_gap_ms, "suppressing push: active heuristic"
+				);
+				return Ok(());
+			}
 		}
 
-		Ok(Destination::Push(user_id, pushkey))
+		let rules_for_user = self.services.pusher.get_ruleset(user_id).await;
+
+		let unread: UInt = self
+			.services
+			.user
+			.notification_count(user_id, pdu.room_id())
+			.await
+			.try_into()
+			.expect("notification count can't go that high");
+
+		self.services
+			.pusher
+			.send_push_notice(user_id, unread, pusher, &rules_for_user, pdu)
+			.await
 	}
 
 	async fn send_events_dest_federation(
@@ -976,3 +1116,10 @@ async fn send_events_dest_federation(
 		}
 	}
 }
+
+/// Delay before sending the transactions of an appservice again after they
+/// failed the number of times, doubling from two seconds up to the maximum.
+pub(super) fn appservice_backoff(tries: u32, max_secs: u64) -> Duration {
+	let secs = 1_u64.checked_shl(tries).unwrap_or(u64::MAX);
+	Duration::from_secs(secs.min(max_secs))
+}
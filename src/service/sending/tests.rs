@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use super::{data::queued_count, health::DestinationHealth, sender::appservice_backoff};
+
+const HOUR: u64 = 60 * 60 * 1000;
+
+#[test]
+fn appservice_backoff_doubles() {
+	assert_eq!(
+		appservice_backoff(1, 300),
+		Duration::from_secs(2),
+		"the first retry waits two seconds"
+	);
+	assert_eq!(
+		appservice_backoff(4, 300),
+		Duration::from_secs(16),
+		"each failure doubles the delay"
+	);
+	assert_eq!(appservice_backoff(9, 300), Duration::from_secs(300), "the delay is capped");
+	assert_eq!(
+		appservice_backoff(u32::MAX, 300),
+		Duration::from_secs(300),
+		"many failures do not overflow"
+	);
+}
+
+#[test]
+fn destination_dormant_after_failures_over_time() {
+	let after = Duration::from_secs(24 * 60 * 60);
+	let mut health = DestinationHealth::default();
+
+	for i in 0..9 {
+		assert!(!health.fail(i * HOUR, 3, after), "failures within a day are retried");
+	}
+	assert_eq!(health.failures, 9, "every failure is counted");
+	assert_eq!(health.dormant_since, None, "the destination is still awake");
+
+	assert!(health.fail(25 * HOUR, 3, after), "enough failures over a day put it to sleep");
+	assert!(!health.fail(26 * HOUR, 3, after), "it only goes to sleep once");
+	assert_eq!(health.dormant_since, Some(26 * HOUR), "a failed probe restarts its probation");
+
+	let mut health = DestinationHealth::default();
+	for i in 0..100 {
+		assert!(!health.fail(i * HOUR, 0, after), "no failures threshold never sleeps");
+	}
+}
+
+#[test]
+fn dormant_destination_probation_and_wake() {
+	let probation = Duration::from_secs(12 * 60 * 60);
+	let mut health = DestinationHealth::default();
+	health.fail(0, 1, Duration::ZERO);
+
+	assert!(health.asleep(HOUR, probation), "dormant destinations get no transactions");
+	assert!(!health.asleep(13 * HOUR, probation), "until the probation is over");
+
+	assert!(health.wake(2 * HOUR, "transaction"), "hearing from the server wakes it");
+	assert!(!health.asleep(2 * HOUR, probation), "woken destinations are sent to");
+	assert_eq!(health.failures, 0, "its failures start over");
+	assert_eq!(
+		health
+			.woken
+			.as_ref()
+			.map(|(at, reason)| (*at, reason.as_str())),
+		Some((2 * HOUR, "transaction")),
+		"what woke it is kept"
+	);
+
+	assert!(!health.wake(3 * HOUR, "key query"), "waking an awake destination does nothing");
+}
+
+#[test]
+fn queued_events_ordered_by_count() {
+	let key = |prefix: &[u8], count: u64| [prefix, &count.to_be_bytes()].concat();
+	let mut keys = vec![
+		key(b"example.com\xFF\x00\x00\x00\x00\x00\x00\x00\x09", 7),
+		key(b"example.com\xFF", 3),
+		key(b"example.com\xFF", 12),
+	];
+
+	keys.sort_by_key(|key| queued_count(key));
+	let counts: Vec<_> = keys.iter().map(|key| queued_count(key)).collect();
+
+	assert_eq!(counts, [3, 7, 12], "PDUs and EDUs are ordered by the count ending their key");
+	assert_eq!(queued_count(b"short"), 0, "short keys sort first");
+}
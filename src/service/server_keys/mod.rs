@@ -3,6 +3,8 @@
 mod keypair;
 mod request;
 mod sign;
+#[cfg(test)]
+mod tests;
 mod verify;
 
 use std::{collections::BTreeMap, sync::Arc, time::Duration};
@@ -35,6 +37,8 @@ struct Data {
 	server_signingkeys: Arc<Map>,
 }
 
+pub use self::verify::{SignatureCheck, content_hash, signed_json};
+
 pub type VerifyKeys = BTreeMap<OwnedServerSigningKeyId, VerifyKey>;
 pub type PubKeyMap = PublicKeyMap;
 pub type PubKeys = PublicKeySet;
@@ -0,0 +1,45 @@
+use ruma::{CanonicalJsonObject, CanonicalJsonValue};
+use serde_json::json;
+
+use super::{content_hash, signed_json};
+
+fn event() -> CanonicalJsonObject {
+	serde_json::from_value(json!({
+		"content": { "body": "hello", "msgtype": "m.text" },
+		"hashes": { "sha256": "claimed" },
+		"origin_server_ts": 1_000,
+		"room_id": "!room:example.com",
+		"sender": "@alice:example.com",
+		"signatures": { "example.com": { "ed25519:1": "signature" } },
+		"type": "m.room.message",
+		"unsigned": { "age": 5 },
+	}))
+	.expect("valid event")
+}
+
+#[test]
+fn content_hash_ignores_unhashed_fields() {
+	let (computed, claimed) = content_hash(&event()).expect("hashed");
+	assert_eq!(claimed.as_deref(), Some("claimed"), "claimed hash is reported");
+
+	let mut event = event();
+	event.remove("hashes");
+	event.remove("signatures");
+	event.remove("unsigned");
+	let (unchanged, claimed) = content_hash(&event).expect("hashed");
+	assert_eq!(computed, unchanged, "hashes, signatures and unsigned are not hashed");
+	assert_eq!(claimed, None, "no hash claimed");
+
+	event.insert("depth".into(), CanonicalJsonValue::Integer(1_u32.into()));
+	let (changed, _) = content_hash(&event).expect("hashed");
+	assert_ne!(computed, changed, "other fields are hashed");
+}
+
+#[test]
+fn signed_json_is_canonical() {
+	let signed = signed_json(&event()).expect("canonical");
+	assert!(!signed.contains("signatures"), "signatures are not signed");
+	assert!(!signed.contains("unsigned"), "unsigned is not signed");
+	assert!(signed.starts_with(r#"{"content":{"body":"hello""#), "keys are sorted: {signed}");
+	assert!(!signed.contains(' '), "no insignificant whitespace: {signed}");
+}
@@ -1,12 +1,25 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD};
 use ruma::{
-	CanonicalJsonObject, CanonicalJsonValue, OwnedEventId, RoomVersionId, signatures::Verified,
+	CanonicalJsonObject, CanonicalJsonValue, OwnedEventId, OwnedServerName,
+	OwnedServerSigningKeyId, RoomVersionId, ServerName, signatures::Verified,
 };
 use serde_json::value::RawValue as RawJsonValue;
+use sha2::{Digest, Sha256};
 use tuwunel_core::{
-	Err, Result, implement,
+	Err, Result, err, implement,
 	matrix::{event::gen_event_id_canonical_json, room_version},
 };
 
+use super::{PubKeyMap, PubKeys};
+
+/// Outcome of verifying one signature of an object on its own.
+#[derive(Debug)]
+pub struct SignatureCheck {
+	pub server: OwnedServerName,
+	pub key_id: String,
+	pub result: Result,
+}
+
 #[implement(super::Service)]
 pub async fn validate_and_add_event_id(
 	&self,
@@ -101,3 +114,96 @@ pub async fn verify_json(
 
 	ruma::signatures::verify_json(&event_keys, event).map_err(Into::into)
 }
+
+/// Verifies each signature of the object on its own with the keys of its
+/// server, fetching keys not known; only those of `server` when given. The
+/// object must be as signed, i.e. redacted for events.
+#[implement(super::Service)]
+pub async fn check_signatures(
+	&self,
+	object: &CanonicalJsonObject,
+	server: Option<&ServerName>,
+) -> Result<Vec<SignatureCheck>> {
+	let Some(CanonicalJsonValue::Object(signatures)) = object.get("signatures") else {
+		return Err!(Request(InvalidParam("Object has no signatures.")));
+	};
+
+	let mut checks = Vec::new();
+	for (origin, origin_signatures) in signatures {
+		let origin = OwnedServerName::parse(origin)
+			.map_err(|e| err!(Request(InvalidParam("Invalid server name {origin:?}: {e}"))))?;
+
+		if server.is_some_and(|server| *server != *origin) {
+			continue;
+		}
+
+		let CanonicalJsonValue::Object(origin_signatures) = origin_signatures else {
+			return Err!(Request(InvalidParam("Signatures of {origin} are not an object.")));
+		};
+
+		for (key_id, signature) in origin_signatures {
+			let result = self
+				.check_signature(object, &origin, key_id, signature)
+				.await;
+
+			checks.push(SignatureCheck {
+				server: origin.clone(),
+				key_id: key_id.clone(),
+				result,
+			});
+		}
+	}
+
+	Ok(checks)
+}
+
+#[implement(super::Service)]
+async fn check_signature(
+	&self,
+	object: &CanonicalJsonObject,
+	origin: &ServerName,
+	key_id: &str,
+	signature: &CanonicalJsonValue,
+) -> Result {
+	let key_id = OwnedServerSigningKeyId::try_from(key_id)
+		.map_err(|e| err!(Request(InvalidParam("Invalid key ID: {e}"))))?;
+
+	let verify_key = self.get_verify_key(origin, &key_id).await?;
+
+	let origin_signatures = [(key_id.to_string(), signature.clone())].into();
+	let signatures = [(origin.to_string(), CanonicalJsonValue::Object(origin_signatures))].into();
+
+	let mut object = object.clone();
+	object.insert("signatures".into(), CanonicalJsonValue::Object(signatures));
+
+	let keys: PubKeys = [(key_id.to_string(), verify_key.key)].into();
+	let keys: PubKeyMap = [(origin.to_string(), keys)].into();
+
+	ruma::signatures::verify_json(&keys, &object).map_err(Into::into)
+}
+
+/// The canonical JSON covered by the signatures of the object.
+pub fn signed_json(object: &CanonicalJsonObject) -> Result<String> {
+	ruma::signatures::canonical_json(object).map_err(Into::into)
+}
+
+/// The content hash of the event as computed, along with the one it claims.
+pub fn content_hash(event: &CanonicalJsonObject) -> Result<(String, Option<String>)> {
+	let claimed = match event.get("hashes") {
+		| Some(CanonicalJsonValue::Object(hashes)) => match hashes.get("sha256") {
+			| Some(CanonicalJsonValue::String(hash)) => Some(hash.clone()),
+			| _ => None,
+		},
+		| _ => None,
+	};
+
+	let mut event = event.clone();
+	for field in ["hashes", "signatures", "unsigned"] {
+		event.remove(field);
+	}
+
+	let json = serde_json::to_string(&event)?;
+	let computed = STANDARD_NO_PAD.encode(Sha256::digest(json.as_bytes()));
+
+	Ok((computed, claimed))
+}
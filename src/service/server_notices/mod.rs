@@ -0,0 +1,453 @@
+#[cfg(test)]
+mod tests;
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use futures::FutureExt;
+use ruma::{
+	OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
+	events::{
+		TimelineEventType,
+		room::{
+			avatar::RoomAvatarEventContent,
+			create::RoomCreateEventContent,
+			guest_access::{GuestAccess, RoomGuestAccessEventContent},
+			history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+			join_rules::{JoinRule, RoomJoinRulesEventContent},
+			member::{MembershipState, RoomMemberEventContent},
+			message::RoomMessageEventContent,
+			name::RoomNameEventContent,
+			power_levels::RoomPowerLevelsEventContent,
+		},
+	},
+	room_version_rules::RoomIdFormatVersion,
+};
+use serde_json::value::to_raw_value;
+use tuwunel_core::{
+	Err, Result, debug_info, err, error,
+	matrix::{StateKey, room_version},
+	pdu::PduBuilder,
+	utils::MutexMap,
+};
+use tuwunel_database::{Deserialized, Map};
+
+use crate::rooms::state::RoomMutexGuard;
+
+/// Tag applied to notice rooms so clients can present them specially.
+const SERVER_NOTICE_TAG: &str = "m.server_notice";
+
+/// Key in the `global` map of the notices user this service created.
+const NOTICES_USER: &[u8] = b"server_notices_user";
+
+pub struct Service {
+	services: Arc<crate::services::OnceServices>,
+	db: Data,
+	mutex: MutexMap<OwnedUserId, ()>,
+}
+
+struct Data {
+	global: Arc<Map>,
+	userid_noticeroomid: Arc<Map>,
+}
+
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			db: Data {
+				global: args.db["global"].clone(),
+				userid_noticeroomid: args.db["userid_noticeroomid"].clone(),
+			},
+			mutex: MutexMap::new(),
+		}))
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+impl Service {
+	/// Sends a markdown notice to a local user in their server notices room as
+	/// the server notices user. The room is created on first use and reused
+	/// for all later notices.
+	pub async fn send_notice(&self, user_id: &UserId, body: &str) -> Result<OwnedEventId> {
+		if !self.services.globals.user_is_local(user_id) {
+			return Err!(Request(InvalidParam(
+				"Server notices can only be sent to local users."
+			)));
+		}
+
+		let notices_user = self.notices_user()?;
+		if notices_user == user_id || self.services.globals.server_user == user_id {
+			return Err!(Request(InvalidParam("Cannot send a server notice to a server user.")));
+		}
+
+		self.get_or_create_user(&notices_user).await?;
+
+		let room_id = self
+			.get_or_create_room(user_id, &notices_user)
+			.await?;
+		let state_lock = self.services.state.mutex.lock(&room_id).await;
+
+		self.join_recipient(user_id, &notices_user, &room_id, &state_lock)
+			.await?;
+
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::timeline(&RoomMessageEventContent::notice_markdown(body)),
+				&notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await
+	}
+
+	/// Gets the server notices room of a user, if one was created.
+	pub async fn get_room(&self, user_id: &UserId) -> Result<OwnedRoomId> {
+		self.db
+			.userid_noticeroomid
+			.get(user_id)
+			.await
+			.deserialized()
+	}
+
+	/// The user sending server notices.
+	pub fn notices_user(&self) -> Result<OwnedUserId> {
+		let server_name = self.services.globals.server_name();
+		let localpart = self
+			.services
+			.config
+			.server_notices
+			.mxid_localpart
+			.as_str();
+
+		UserId::parse_with_server_name(localpart, server_name)
+			.map_err(|e| err!(Config("server_notices.mxid_localpart", "Invalid localpart: {e}")))
+	}
+
+	/// Creates the notices user unless this service created it before. An
+	/// account of the same name registered otherwise is refused, as its owner
+	/// would be sending the notices.
+	async fn get_or_create_user(&self, notices_user: &UserId) -> Result {
+		let _lock = self.mutex.lock(notices_user).await;
+
+		let created = self
+			.db
+			.global
+			.get(NOTICES_USER)
+			.await
+			.is_ok_and(|created| created.as_ref() == notices_user.as_bytes());
+
+		match (created, self.services.users.exists(notices_user).await) {
+			| (true, true) => Ok(()),
+			| (_, true) => Err!(Config(
+				"server_notices.mxid_localpart",
+				"{notices_user} is an existing account; choose another localpart."
+			)),
+			| (_, false) => {
+				self.services
+					.users
+					.create(notices_user, None, None)
+					.await?;
+
+				self.db
+					.global
+					.insert(NOTICES_USER, notices_user.as_bytes());
+
+				Ok(())
+			},
+		}
+	}
+
+	/// The room of the user, created unless it exists; under a lock so
+	/// concurrent notices share one room.
+	async fn get_or_create_room(
+		&self,
+		user_id: &UserId,
+		notices_user: &UserId,
+	) -> Result<OwnedRoomId> {
+		let _lock = self.mutex.lock(user_id).await;
+
+		if let Ok(room_id) = self.get_room(user_id).await {
+			// Reuse the room unless it was deleted or abandoned by the notices user.
+			if self
+				.services
+				.state_cache
+				.is_joined(notices_user, &room_id)
+				.await
+			{
+				return Ok(room_id);
+			}
+		}
+
+		let room_id = self.create_room(user_id, notices_user).await?;
+		self.db
+			.userid_noticeroomid
+			.insert(user_id, room_id.as_bytes());
+
+		Ok(room_id)
+	}
+
+	async fn create_room(&self, user_id: &UserId, notices_user: &UserId) -> Result<OwnedRoomId> {
+		let config = &self.services.config.server_notices;
+		let room_version = self.services.config.default_room_version.clone();
+		let version_rules = room_version::rules(&room_version)?;
+
+		// 1. The room create event
+		let (room_id, state_lock) = self
+			.create_create_event(notices_user, room_version, version_rules.room_id_format)
+			.await?;
+
+		// 2. Make the notices user join
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					String::from(notices_user),
+					&RoomMemberEventContent::new(MembershipState::Join),
+				),
+				notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await?;
+
+		// 3. Power levels; the recipient can't invite anyone else into the room. The
+		// notices user outranks everyone as the creator of rooms of newer versions.
+		let users = match version_rules
+			.authorization
+			.explicitly_privilege_room_creators
+		{
+			| true => BTreeMap::new(),
+			| false => BTreeMap::from_iter([(notices_user.into(), 100.into())]),
+		};
+
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(String::new(), &RoomPowerLevelsEventContent {
+					users,
+					invite: 100.into(),
+					kick: 100.into(),
+					ban: 100.into(),
+					redact: 100.into(),
+					state_default: 100.into(),
+					..Default::default()
+				}),
+				notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await?;
+
+		// 4.1 Join Rules
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					String::new(),
+					&RoomJoinRulesEventContent::new(JoinRule::Invite),
+				),
+				notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await?;
+
+		// 4.2 History Visibility
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					String::new(),
+					&RoomHistoryVisibilityEventContent::new(HistoryVisibility::Joined),
+				),
+				notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await?;
+
+		// 4.3 Guest Access
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					String::new(),
+					&RoomGuestAccessEventContent::new(GuestAccess::Forbidden),
+				),
+				notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await?;
+
+		// 5. Name and avatar
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					String::new(),
+					&RoomNameEventContent::new(config.room_name.clone()),
+				),
+				notices_user,
+				&room_id,
+				&state_lock,
+			)
+			.boxed()
+			.await?;
+
+		if let Some(avatar) = config.avatar.clone() {
+			let mut content = RoomAvatarEventContent::new();
+			content.url = Some(avatar);
+			self.services
+				.timeline
+				.build_and_append_pdu(
+					PduBuilder::state(String::new(), &content),
+					notices_user,
+					&room_id,
+					&state_lock,
+				)
+				.boxed()
+				.await?;
+		}
+
+		debug_info!(%user_id, %room_id, "Created server notices room");
+
+		Ok(room_id)
+	}
+
+	/// Appends the create event of a room of the version, returning the room
+	/// and its lock. Rooms of newer versions are identified by the create
+	/// event, so their ID is only known once it is built.
+	async fn create_create_event(
+		&self,
+		notices_user: &UserId,
+		room_version: RoomVersionId,
+		room_id_format: RoomIdFormatVersion,
+	) -> Result<(OwnedRoomId, RoomMutexGuard)> {
+		use RoomVersionId::*;
+
+		let content = RoomCreateEventContent {
+			federate: false,
+			predecessor: None,
+			room_version: room_version.clone(),
+			..match room_version {
+				| V1 | V2 | V3 | V4 | V5 | V6 | V7 | V8 | V9 | V10 =>
+					RoomCreateEventContent::new_v1(notices_user.to_owned()),
+				| _ => RoomCreateEventContent::new_v11(),
+			}
+		};
+
+		let pdu = PduBuilder {
+			event_type: TimelineEventType::RoomCreate,
+			content: to_raw_value(&content)?,
+			state_key: Some(StateKey::new()),
+			..Default::default()
+		};
+
+		if matches!(room_id_format, RoomIdFormatVersion::V1) {
+			let room_id = RoomId::new_v1(self.services.globals.server_name());
+			let _short_id = self
+				.services
+				.short
+				.get_or_create_shortroomid(&room_id)
+				.await;
+
+			let state_lock = self.services.state.mutex.lock(&room_id).await;
+			self.services
+				.timeline
+				.build_and_append_pdu(pdu, notices_user, &room_id, &state_lock)
+				.boxed()
+				.await?;
+
+			return Ok((room_id, state_lock));
+		}
+
+		// A placeholder until the create event gives the room its ID
+		let room_id = ruma::room_id!("!thiswillbereplaced").to_owned();
+		let state_lock = self.services.state.mutex.lock(&room_id).await;
+		let create_event_id = self
+			.services
+			.timeline
+			.build_and_append_pdu(pdu, notices_user, &room_id, &state_lock)
+			.boxed()
+			.await?;
+
+		drop(state_lock);
+
+		let room_id = OwnedRoomId::from_parts('!', create_event_id.localpart(), None)?;
+		let state_lock = self.services.state.mutex.lock(&room_id).await;
+
+		Ok((room_id, state_lock))
+	}
+
+	async fn join_recipient(
+		&self,
+		user_id: &UserId,
+		notices_user: &UserId,
+		room_id: &RoomId,
+		state_lock: &RoomMutexGuard,
+	) -> Result {
+		if self
+			.services
+			.state_cache
+			.is_joined(user_id, room_id)
+			.await
+		{
+			return Ok(());
+		}
+
+		if !self
+			.services
+			.state_cache
+			.is_invited(user_id, room_id)
+			.await
+		{
+			self.services
+				.timeline
+				.build_and_append_pdu(
+					PduBuilder::state(
+						String::from(user_id),
+						&RoomMemberEventContent::new(MembershipState::Invite),
+					),
+					notices_user,
+					room_id,
+					state_lock,
+				)
+				.boxed()
+				.await?;
+		}
+
+		self.services
+			.timeline
+			.build_and_append_pdu(
+				PduBuilder::state(
+					String::from(user_id),
+					&RoomMemberEventContent::new(MembershipState::Join),
+				),
+				user_id,
+				room_id,
+				state_lock,
+			)
+			.boxed()
+			.await?;
+
+		if let Err(e) = self
+			.services
+			.admin
+			.set_room_tag(room_id, user_id, SERVER_NOTICE_TAG)
+			.await
+		{
+			error!(%room_id, %user_id, "Failed to tag server notices room: {e}");
+		}
+
+		Ok(())
+	}
+}
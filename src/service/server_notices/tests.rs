@@ -0,0 +1,117 @@
+use futures::{StreamExt, join};
+use ruma::RoomVersionId;
+
+use crate::fixture::Fixture;
+
+#[tokio::test]
+async fn concurrent_notices_share_one_room() {
+	let services = Fixture::start().await;
+	let alice = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let (first, second) = join!(
+		services
+			.server_notices
+			.send_notice(&alice, "first"),
+		services
+			.server_notices
+			.send_notice(&alice, "second"),
+	);
+	first.expect("first notice sent");
+	second.expect("second notice sent");
+
+	let room_id = services
+		.server_notices
+		.get_room(&alice)
+		.await
+		.expect("notices room recorded");
+
+	let rooms: Vec<_> = services
+		.state_cache
+		.rooms_joined(&alice)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+	assert_eq!(rooms, [room_id.clone()], "both notices are sent to the same room");
+
+	services
+		.server_notices
+		.send_notice(&alice, "third")
+		.await
+		.expect("later notice sent");
+
+	let reused = services
+		.server_notices
+		.get_room(&alice)
+		.await
+		.expect("notices room recorded");
+	assert_eq!(reused, room_id, "later notices reuse the room");
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn existing_account_not_used_as_notices_user() {
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	embedded
+		.create_user("notices", Some("password"))
+		.await
+		.expect("user created");
+
+	let result = services
+		.server_notices
+		.send_notice(&alice, "hello")
+		.await;
+	assert!(result.is_err(), "a registered account does not send the notices");
+
+	let room = services.server_notices.get_room(&alice).await;
+	assert!(room.is_err(), "no notices room is created");
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn notices_room_of_default_version() {
+	let services = Fixture::start_with(r#"default_room_version = "12""#).await;
+	let alice = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	services
+		.server_notices
+		.send_notice(&alice, "hello")
+		.await
+		.expect("notice sent");
+
+	let room_id = services
+		.server_notices
+		.get_room(&alice)
+		.await
+		.expect("notices room recorded");
+
+	let room_version = services
+		.state
+		.get_room_version(&room_id)
+		.await
+		.expect("room version");
+	assert_eq!(room_version, RoomVersionId::V12, "the room has the configured version");
+	assert!(
+		services
+			.state_cache
+			.is_joined(&alice, &room_id)
+			.await,
+		"the recipient joined the room identified by its create event"
+	);
+
+	services.stop().await;
+}
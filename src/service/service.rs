@@ -1,4 +1,4 @@
-use std::{any::Any, fmt::Write, sync::Arc};
+use std::{any::Any, fmt::Write, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use tuwunel_core::{Result, Server, utils::string::SplitInfallible};
@@ -29,6 +29,10 @@ async fn interrupt(&self) {}
 	/// Clear any caches or similar runtime state.
 	async fn clear_cache(&self) {}
 
+	/// Evict cache entries older than the age. Called periodically when
+	/// `cache_trim_interval` is configured; caches opt in by implementing it.
+	async fn trim_cache(&self, _max_age: Duration) {}
+
 	/// Memory usage report in a markdown string.
 	async fn memory_usage(&self, _out: &mut (dyn Write + Send)) -> Result { Ok(()) }
 
@@ -1,9 +1,10 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use futures::{StreamExt, TryStreamExt};
 use tokio::sync::Mutex;
 use tuwunel_core::{
-	Result, Server, debug, debug_info, implement, info, trace, utils::stream::IterStream,
+	Err, Result, Server, debug, debug_info, implement, info, result::LogErr, trace,
+	utils::stream::IterStream,
 };
 use tuwunel_database::Database;
 
@@ -12,9 +13,10 @@
 	account_data, admin, appservice, client, config, deactivate, emergency, federation, globals,
 	key_backups,
 	manager::Manager,
-	media, membership, presence, pusher, resolver, rooms, sending, server_keys,
+	media, membership, metrics, moderation, presence, pusher, ratelimit, resolver, rooms,
+	sending, server_keys, server_notices,
 	service::{Args, Service},
-	sync, transaction_ids, uiaa, users,
+	sso, sync, threepid, transaction_ids, uiaa, users,
 };
 
 pub struct Services {
@@ -27,6 +29,7 @@ pub struct Services {
 	pub globals: Arc<globals::Service>,
 	pub key_backups: Arc<key_backups::Service>,
 	pub media: Arc<media::Service>,
+	pub metrics: Arc<metrics::Service>,
 	pub presence: Arc<presence::Service>,
 	pub pusher: Arc<pusher::Service>,
 	pub resolver: Arc<resolver::Service>,
@@ -39,6 +42,7 @@ pub struct Services {
 	pub metadata: Arc<rooms::metadata::Service>,
 	pub pdu_metadata: Arc<rooms::pdu_metadata::Service>,
 	pub read_receipt: Arc<rooms::read_receipt::Service>,
+	pub retention: Arc<rooms::retention::Service>,
 	pub search: Arc<rooms::search::Service>,
 	pub short: Arc<rooms::short::Service>,
 	pub spaces: Arc<rooms::spaces::Service>,
@@ -53,12 +57,17 @@ pub struct Services {
 	pub federation: Arc<federation::Service>,
 	pub sending: Arc<sending::Service>,
 	pub server_keys: Arc<server_keys::Service>,
+	pub server_notices: Arc<server_notices::Service>,
 	pub sync: Arc<sync::Service>,
+	pub sso: Arc<sso::Service>,
+	pub threepid: Arc<threepid::Service>,
 	pub transaction_ids: Arc<transaction_ids::Service>,
 	pub uiaa: Arc<uiaa::Service>,
 	pub users: Arc<users::Service>,
 	pub membership: Arc<membership::Service>,
+	pub moderation: Arc<moderation::Service>,
 	pub deactivate: Arc<deactivate::Service>,
+	pub ratelimit: Arc<ratelimit::Service>,
 
 	manager: Mutex<Option<Arc<Manager>>>,
 	pub server: Arc<Server>,
@@ -90,6 +99,7 @@ macro_rules! build {
 		globals: build!(globals::Service),
 		key_backups: build!(key_backups::Service),
 		media: build!(media::Service),
+		metrics: build!(metrics::Service),
 		presence: build!(presence::Service),
 		pusher: build!(pusher::Service),
 		alias: build!(rooms::alias::Service),
@@ -101,6 +111,7 @@ macro_rules! build {
 		metadata: build!(rooms::metadata::Service),
 		pdu_metadata: build!(rooms::pdu_metadata::Service),
 		read_receipt: build!(rooms::read_receipt::Service),
+		retention: build!(rooms::retention::Service),
 		search: build!(rooms::search::Service),
 		short: build!(rooms::short::Service),
 		spaces: build!(rooms::spaces::Service),
@@ -115,12 +126,17 @@ macro_rules! build {
 		federation: build!(federation::Service),
 		sending: build!(sending::Service),
 		server_keys: build!(server_keys::Service),
+		server_notices: build!(server_notices::Service),
 		sync: build!(sync::Service),
+		sso: build!(sso::Service),
+		threepid: build!(threepid::Service),
 		transaction_ids: build!(transaction_ids::Service),
 		uiaa: build!(uiaa::Service),
 		users: build!(users::Service),
 		membership: build!(membership::Service),
+		moderation: build!(moderation::Service),
 		deactivate: build!(deactivate::Service),
+		ratelimit: build!(ratelimit::Service),
 
 		manager: Mutex::new(None),
 		server,
@@ -149,6 +165,7 @@ macro_rules! cast {
 		cast!(self.globals),
 		cast!(self.key_backups),
 		cast!(self.media),
+		cast!(self.metrics),
 		cast!(self.presence),
 		cast!(self.pusher),
 		cast!(self.alias),
@@ -160,6 +177,7 @@ macro_rules! cast {
 		cast!(self.metadata),
 		cast!(self.pdu_metadata),
 		cast!(self.read_receipt),
+		cast!(self.retention),
 		cast!(self.search),
 		cast!(self.short),
 		cast!(self.spaces),
@@ -174,12 +192,17 @@ macro_rules! cast {
 		cast!(self.federation),
 		cast!(self.sending),
 		cast!(self.server_keys),
+		cast!(self.server_notices),
 		cast!(self.sync),
+		cast!(self.sso),
+		cast!(self.threepid),
 		cast!(self.transaction_ids),
 		cast!(self.uiaa),
 		cast!(self.users),
 		cast!(self.membership),
+		cast!(self.moderation),
 		cast!(self.deactivate),
+		cast!(self.ratelimit),
 	]
 	.into_iter()
 }
@@ -243,6 +266,40 @@ pub async fn clear_cache(&self) {
 		.await;
 }
 
+/// Clears the caches of the service by its name, e.g. `rooms::state_cache`.
+#[implement(Services)]
+pub async fn clear_service_cache(&self, name: &str) -> Result {
+	let Some(service) = self
+		.services()
+		.find(|service| service.name() == name)
+	else {
+		let names: Vec<_> = self
+			.services()
+			.map(|service| service.name().to_owned())
+			.collect();
+
+		return Err!("No service named {name:?}; the services are: {}", names.join(", "));
+	};
+
+	service.clear_cache().await;
+
+	Ok(())
+}
+
+/// Evicts the cache entries older than the age from the services supporting
+/// it, then returns unused memory to the system.
+#[implement(Services)]
+pub async fn trim_cache(&self, max_age: Duration) {
+	self.services()
+		.stream()
+		.for_each(async |service| {
+			service.trim_cache(max_age).await;
+		})
+		.await;
+
+	tuwunel_core::alloc::trim(None).log_err().ok();
+}
+
 #[implement(Services)]
 pub async fn memory_usage(&self) -> Result<String> {
 	self.services()
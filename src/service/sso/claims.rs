@@ -0,0 +1,89 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde_json::{Map, Value};
+use tuwunel_core::{Err, Result, err};
+
+pub(super) type Claims = Map<String, Value>;
+
+/// The claims of the ID token, checked to be issued by the issuer to the
+/// client for the login with the nonce, and unexpired. The token comes
+/// straight from the token endpoint over TLS, which stands for checking its
+/// signature.
+#[cfg_attr(not(feature = "sso"), allow(dead_code))]
+pub(super) fn id_token(
+	token: &str,
+	issuer: &str,
+	client_id: &str,
+	nonce: &str,
+	now_secs: u64,
+) -> Result<Claims> {
+	let payload = token
+		.split('.')
+		.nth(1)
+		.ok_or_else(|| err!(BadServerResponse("The ID token is malformed.")))?;
+
+	let payload = URL_SAFE_NO_PAD
+		.decode(payload)
+		.map_err(|e| err!(BadServerResponse("The ID token is malformed: {e}")))?;
+
+	let claims: Claims = serde_json::from_slice(&payload)?;
+
+	if string(&claims, "iss").as_deref() != Some(issuer) {
+		return Err!(Request(Forbidden("The ID token was issued by another provider.")));
+	}
+
+	let audience = match claims.get("aud") {
+		| Some(Value::String(aud)) => aud == client_id,
+		| Some(Value::Array(auds)) => auds
+			.iter()
+			.any(|aud| aud.as_str() == Some(client_id)),
+		| _ => false,
+	};
+
+	if !audience {
+		return Err!(Request(Forbidden("The ID token was issued to another client.")));
+	}
+
+	if string(&claims, "nonce").as_deref() != Some(nonce) {
+		return Err!(Request(Forbidden("The ID token was issued for another login.")));
+	}
+
+	if claims
+		.get("exp")
+		.and_then(Value::as_u64)
+		.is_none_or(|exp| exp <= now_secs)
+	{
+		return Err!(Request(Forbidden("The ID token has expired.")));
+	}
+
+	Ok(claims)
+}
+
+/// The claim as a string; some providers claim numbers as subject.
+#[must_use]
+pub(super) fn string(claims: &Claims, name: &str) -> Option<String> {
+	match claims.get(name)? {
+		| Value::String(value) => Some(value.clone()),
+		| Value::Number(value) => Some(value.to_string()),
+		| _ => None,
+	}
+}
+
+/// The localpart made of the claim: lowercased, with the characters not
+/// allowed in a localpart replaced by underscores.
+#[must_use]
+pub(super) fn localpart(claim: &str) -> Option<String> {
+	let localpart: String = claim
+		.trim()
+		.chars()
+		.flat_map(char::to_lowercase)
+		.map(|c| {
+			if c.is_ascii_alphanumeric() || "._=-/+".contains(c) {
+				c
+			} else {
+				'_'
+			}
+		})
+		.collect();
+
+	(!localpart.is_empty()).then_some(localpart)
+}
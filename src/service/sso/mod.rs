@@ -0,0 +1,402 @@
+//! Single sign-on through an OpenID Connect provider, with the authorization
+//! code flow and PKCE. Starting a login keeps the request in
+//! `ssostate_request` under the state handed to the provider; the callback
+//! takes it back once, before it expires, to exchange the code. The user at
+//! the provider is linked to a local user in `ssosubject_userid` on the first
+//! login. The state is also set in a cookie of the browser starting the login,
+//! so only that browser completes it. A login completes with a login token the
+//! client logs in with, while user-interactive authentication completes the
+//! `m.login.sso` stage of its session.
+
+mod claims;
+mod provider;
+mod request;
+#[cfg(test)]
+mod tests;
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use ruma::{OwnedUserId, UserId};
+use tokio::time::sleep;
+use tuwunel_core::{
+	Err, Result, debug, err, implement, info,
+	utils::{self, ReadyExt, bytes::eq_constant_time, stream::TryIgnore},
+};
+use tuwunel_database::{Deserialized, Json, Map};
+use url::Url;
+
+use self::{
+	claims::Claims,
+	request::{Purpose, Request},
+};
+
+/// Path the provider redirects back to with the code.
+pub const CALLBACK_PATH: &str = "/_tuwunel/sso/callback";
+
+/// Cookie binding the state to the browser starting the login.
+pub const STATE_COOKIE: &str = "tuwunel_sso_state";
+
+/// Length of the state handed to the provider.
+const STATE_LENGTH: usize = 32;
+
+/// Length of the login token the client logs in with.
+const LOGIN_TOKEN_LENGTH: usize = 32;
+
+/// Interval between prunings of expired requests, in seconds.
+const PRUNE_INTERVAL: u64 = 60 * 60;
+
+pub struct Service {
+	services: Arc<crate::services::OnceServices>,
+	db: Data,
+	#[cfg(feature = "sso")]
+	discovery: provider::Discovery,
+}
+
+struct Data {
+	ssostate_request: Arc<Map>,
+	ssosubject_userid: Arc<Map>,
+}
+
+/// Login started at the provider.
+#[derive(Debug)]
+pub struct Started {
+	/// URL of the provider to send the user to.
+	pub location: String,
+
+	/// State to set in the [`STATE_COOKIE`] of the browser.
+	pub state: String,
+}
+
+/// What the provider redirecting back completed.
+#[derive(Debug)]
+pub enum Completion {
+	/// The user logged in; the client is redirected to the URL, which carries
+	/// the login token.
+	Login(String),
+
+	/// The user logged in to a client not in `sso.client_allowlist`; the user
+	/// confirms continuing to the URL, which carries the login token.
+	Confirm {
+		location: String,
+		host: String,
+	},
+
+	/// The user completed the stage of the user-interactive authentication.
+	Uiaa,
+}
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			db: Data {
+				ssostate_request: args.db["ssostate_request"].clone(),
+				ssosubject_userid: args.db["ssosubject_userid"].clone(),
+			},
+			#[cfg(feature = "sso")]
+			discovery: Default::default(),
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		if !self.enabled() || self.services.db.is_read_only() {
+			return Ok(());
+		}
+
+		while self.services.server.running() {
+			tokio::select! {
+				() = sleep(Duration::from_secs(PRUNE_INTERVAL)) => {
+					self.prune_requests().await;
+				},
+				() = self.services.server.until_shutdown() => break,
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn clear_cache(&self) {
+		#[cfg(feature = "sso")]
+		self.discovery.lock().await.take();
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Whether users log in through the provider.
+#[implement(Service)]
+#[must_use]
+pub fn enabled(&self) -> bool { cfg!(feature = "sso") && self.services.server.config.sso.enable }
+
+/// Starts the login of a client. Once logged in, the user is redirected to
+/// `redirect_url` with a login token.
+#[implement(Service)]
+pub async fn start_login(&self, redirect_url: &str) -> Result<Started> {
+	let redirect_url = Url::parse(redirect_url)
+		.map_err(|e| err!(Request(InvalidParam("Invalid redirect URL: {e}"))))?;
+
+	self.start(Purpose::Login { redirect_url: redirect_url.into() })
+		.await
+}
+
+/// Starts the `m.login.sso` stage of the session of user-interactive
+/// authentication.
+#[implement(Service)]
+pub async fn start_uiaa(&self, session: &str) -> Result<Started> {
+	self.services
+		.uiaa
+		.find_uiaa_session(session)
+		.await?;
+
+	self.start(Purpose::Uiaa { session: session.to_owned() })
+		.await
+}
+
+#[implement(Service)]
+async fn start(&self, purpose: Purpose) -> Result<Started> {
+	if !self.services.server.config.sso.enable {
+		return Err!(Request(Unrecognized("Single sign-on is not enabled.")));
+	}
+
+	let ttl = self
+		.services
+		.server
+		.config
+		.sso
+		.state_ttl
+		.saturating_mul(1000);
+
+	let request = Request::new(purpose, utils::millis_since_unix_epoch(), ttl);
+	let state = utils::random_string(STATE_LENGTH);
+	let location = self.authorization_url(&state, &request).await?;
+
+	self.db
+		.ssostate_request
+		.raw_put(&state, Json(&request));
+
+	Ok(Started { location, state })
+}
+
+/// Completes the request the provider redirected back for with the code, in
+/// the browser with the state in its cookie.
+#[implement(Service)]
+#[tracing::instrument(skip_all, level = "debug")]
+pub async fn complete(
+	&self,
+	state: &str,
+	cookie: Option<&str>,
+	code: &str,
+) -> Result<Completion> {
+	if !cookie.is_some_and(|cookie| eq_constant_time(cookie.as_bytes(), state.as_bytes())) {
+		return Err!(Request(Forbidden(
+			"The single sign-on request was started in another browser."
+		)));
+	}
+
+	let request = self.take_request(state).await?;
+	let claims = self.exchange(code, &request).await?;
+
+	let config = &self.services.server.config.sso;
+	let subject = claims::string(&claims, &config.subject_claim)
+		.ok_or_else(|| err!(Request(Forbidden("The provider did not claim the subject."))))?;
+
+	match request.purpose {
+		| Purpose::Login { redirect_url } => {
+			let user_id = self.login_user(&subject, &claims).await?;
+			if self
+				.services
+				.users
+				.is_deactivated(&user_id)
+				.await
+				.unwrap_or(true)
+			{
+				return Err!(Request(UserDeactivated("The account is deactivated.")));
+			}
+
+			let token = utils::random_string(LOGIN_TOKEN_LENGTH);
+			let _ = self
+				.services
+				.users
+				.create_login_token(&user_id, &token);
+
+			let mut redirect_url = Url::parse(&redirect_url)
+				.map_err(|e| err!(Request(InvalidParam("Invalid redirect URL: {e}"))))?;
+
+			redirect_url
+				.query_pairs_mut()
+				.append_pair("loginToken", &token);
+
+			info!(%user_id, "Logged in through single sign-on");
+			if is_allowed_client(&config.client_allowlist, redirect_url.as_str()) {
+				Ok(Completion::Login(redirect_url.into()))
+			} else {
+				let host = redirect_url
+					.host_str()
+					.unwrap_or(redirect_url.scheme())
+					.to_owned();
+
+				Ok(Completion::Confirm { location: redirect_url.into(), host })
+			}
+		},
+		| Purpose::Uiaa { session } => {
+			let user_id = self.linked_user(&subject).await.map_err(|_| {
+				err!(Request(Forbidden("No account is linked to this identity.")))
+			})?;
+
+			self.services
+				.uiaa
+				.sso_auth(&session, &user_id)
+				.await?;
+
+			Ok(Completion::Uiaa)
+		},
+	}
+}
+
+/// URL the provider redirects back to.
+#[implement(Service)]
+#[must_use]
+pub fn callback_url(&self) -> String {
+	let config = &self.services.server.config;
+	let base = config.well_known.client.as_ref().map_or_else(
+		|| format!("https://{}", config.server_name),
+		|client| client.as_str().trim_end_matches('/').to_owned(),
+	);
+
+	format!("{base}{CALLBACK_PATH}")
+}
+
+/// The local user the subject logs in as, linking or registering it on the
+/// first login.
+#[implement(Service)]
+async fn login_user(&self, subject: &str, claims: &Claims) -> Result<OwnedUserId> {
+	if let Ok(user_id) = self.linked_user(subject).await {
+		return Ok(user_id);
+	}
+
+	let config = &self.services.server.config.sso;
+	let localpart = claims::string(claims, &config.localpart_claim)
+		.as_deref()
+		.and_then(claims::localpart)
+		.ok_or_else(|| err!(Request(Forbidden("The provider did not claim a username."))))?;
+
+	let user_id = UserId::parse_with_server_name(localpart, self.services.globals.server_name())
+		.map_err(|e| err!(Request(InvalidUsername("The claimed username is invalid: {e}"))))?;
+
+	if self.services.users.exists(&user_id).await {
+		if !config.allow_existing_users {
+			return Err!(Request(UserInUse("The username is taken by another account.")));
+		}
+	} else {
+		if !config.auto_register {
+			return Err!(Request(Forbidden("No account is linked to this identity.")));
+		}
+
+		if self
+			.services
+			.globals
+			.forbidden_usernames()
+			.is_match(user_id.localpart())
+		{
+			return Err!(Request(Forbidden("Username is forbidden")));
+		}
+
+		self.services
+			.users
+			.create(&user_id, Some("*"), Some("sso"))
+			.await?;
+
+		let displayname = claims::string(claims, &config.displayname_claim);
+		self.services
+			.users
+			.set_displayname(&user_id, displayname);
+
+		info!(%user_id, "Registered through single sign-on");
+	}
+
+	self.db
+		.ssosubject_userid
+		.insert(subject, user_id.as_str());
+
+	Ok(user_id)
+}
+
+/// Whether the client at the URL is trusted with login tokens without the
+/// user confirming; the URL must start with one of the allowed prefixes.
+#[must_use]
+fn is_allowed_client(allowlist: &[String], url: &str) -> bool {
+	allowlist
+		.iter()
+		.any(|prefix| url.starts_with(prefix.as_str()))
+}
+
+#[implement(Service)]
+async fn linked_user(&self, subject: &str) -> Result<OwnedUserId> {
+	self.db
+		.ssosubject_userid
+		.get(subject)
+		.await
+		.deserialized()
+}
+
+/// Takes the request back, once; errors when it is unknown or expired.
+#[implement(Service)]
+async fn take_request(&self, state: &str) -> Result<Request> {
+	let Ok(request) = self
+		.db
+		.ssostate_request
+		.get(state)
+		.await
+		.deserialized::<Request>()
+	else {
+		return Err!(Request(Forbidden("Unknown single sign-on request.")));
+	};
+
+	self.db.ssostate_request.remove(state);
+
+	if request.is_expired(utils::millis_since_unix_epoch()) {
+		return Err!(Request(Forbidden("The single sign-on request has expired.")));
+	}
+
+	Ok(request)
+}
+
+#[cfg(not(feature = "sso"))]
+#[implement(Service)]
+async fn authorization_url(&self, _state: &str, _request: &Request) -> Result<String> {
+	Err!(FeatureDisabled("sso"))
+}
+
+#[cfg(not(feature = "sso"))]
+#[implement(Service)]
+async fn exchange(&self, _code: &str, _request: &Request) -> Result<Claims> {
+	Err!(FeatureDisabled("sso"))
+}
+
+#[implement(Service)]
+async fn prune_requests(&self) -> usize {
+	type KeyVal<'a> = (&'a str, Request);
+
+	let now = utils::millis_since_unix_epoch();
+	let expired: Vec<String> = self
+		.db
+		.ssostate_request
+		.stream()
+		.ignore_err()
+		.ready_filter_map(|(state, request): KeyVal<'_>| {
+			request.is_expired(now).then(|| state.to_owned())
+		})
+		.collect()
+		.await;
+
+	let _cork = self.services.db.cork();
+	for state in &expired {
+		self.db.ssostate_request.remove(state);
+	}
+
+	debug!(count = expired.len(), "Pruned expired single sign-on requests");
+	expired.len()
+}
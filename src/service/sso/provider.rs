@@ -0,0 +1,181 @@
+#![cfg(feature = "sso")]
+
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use serde::{Deserialize, de::DeserializeOwned};
+use tokio::sync::Mutex;
+use tuwunel_core::{Err, Result, config::SsoConfig, debug, err, implement, utils, warn};
+use url::Url;
+
+use super::{
+	claims::{self, Claims},
+	request::Request,
+};
+
+/// How long the configuration discovered from the issuer is used before it is
+/// discovered again.
+const DISCOVERY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Configuration of the provider last discovered, with when.
+pub(super) type Discovery = Mutex<Option<(Instant, Arc<Metadata>)>>;
+
+/// Configuration of the provider, discovered from its issuer.
+#[derive(Debug, Deserialize)]
+pub(super) struct Metadata {
+	issuer: String,
+	authorization_endpoint: String,
+	token_endpoint: String,
+	userinfo_endpoint: Option<String>,
+}
+
+/// Tokens the code is exchanged for.
+#[derive(Deserialize)]
+struct Tokens {
+	access_token: String,
+	id_token: Option<String>,
+}
+
+/// URL of the provider the user logs in at for the request; the provider
+/// redirects back to the callback with the state.
+#[implement(super::Service)]
+pub(super) async fn authorization_url(&self, state: &str, request: &Request) -> Result<String> {
+	let config = &self.services.server.config.sso;
+	let client_id = client_id(config)?;
+	let metadata = self.metadata(config).await?;
+
+	let mut url = Url::parse(&metadata.authorization_endpoint)
+		.map_err(|e| err!(BadServerResponse("Invalid authorization endpoint: {e}")))?;
+
+	url.query_pairs_mut()
+		.append_pair("response_type", "code")
+		.append_pair("client_id", client_id)
+		.append_pair("redirect_uri", &self.callback_url())
+		.append_pair("scope", &config.scopes.join(" "))
+		.append_pair("state", state)
+		.append_pair("nonce", &request.nonce)
+		.append_pair("code_challenge", &request.challenge())
+		.append_pair("code_challenge_method", "S256");
+
+	Ok(url.into())
+}
+
+/// Exchanges the code the provider redirected back with for the claims of
+/// the user, from the ID token and the user info.
+#[implement(super::Service)]
+pub(super) async fn exchange(&self, code: &str, request: &Request) -> Result<Claims> {
+	let config = &self.services.server.config.sso;
+	let client_id = client_id(config)?;
+	let metadata = self.metadata(config).await?;
+	let client = &self.services.client.default;
+
+	let callback_url = self.callback_url();
+	let form = [
+		("grant_type", "authorization_code"),
+		("code", code),
+		("redirect_uri", callback_url.as_str()),
+		("client_id", client_id),
+		("code_verifier", request.verifier.as_str()),
+	];
+
+	let response = client
+		.post(&metadata.token_endpoint)
+		.basic_auth(client_id, config.client_secret.as_deref())
+		.form(&form)
+		.send()
+		.await?;
+
+	let tokens: Tokens = parse(response).await?;
+
+	let now_secs = utils::millis_since_unix_epoch() / 1000;
+	let mut claims = tokens
+		.id_token
+		.as_deref()
+		.map(|token| {
+			claims::id_token(token, &metadata.issuer, client_id, &request.nonce, now_secs)
+		})
+		.transpose()?
+		.unwrap_or_default();
+
+	if let Some(userinfo_endpoint) = &metadata.userinfo_endpoint {
+		let response = client
+			.get(userinfo_endpoint)
+			.bearer_auth(&tokens.access_token)
+			.send()
+			.await?;
+
+		let userinfo: Claims = parse(response).await?;
+		if tokens.id_token.is_some() && userinfo.get("sub") != claims.get("sub") {
+			return Err!(BadServerResponse(
+				"The user info is of another subject than the ID token."
+			));
+		}
+
+		claims.extend(userinfo);
+	} else if tokens.id_token.is_none() {
+		return Err!(BadServerResponse(
+			"The provider returned neither an ID token nor user info."
+		));
+	}
+
+	debug!(claims = ?claims.keys().collect::<Vec<_>>(), "Exchanged single sign-on code");
+	Ok(claims)
+}
+
+/// Configuration of the provider, discovered at most once per
+/// [`DISCOVERY_TTL`] however many logins start.
+#[implement(super::Service)]
+async fn metadata(&self, config: &SsoConfig) -> Result<Arc<Metadata>> {
+	let mut discovery = self.discovery.lock().await;
+	if let Some((discovered, metadata)) = discovery.as_ref()
+		&& discovered.elapsed() < DISCOVERY_TTL
+	{
+		return Ok(metadata.clone());
+	}
+
+	let metadata: Arc<Metadata> = self.discover(config).await?.into();
+	*discovery = Some((Instant::now(), metadata.clone()));
+
+	Ok(metadata)
+}
+
+#[implement(super::Service)]
+async fn discover(&self, config: &SsoConfig) -> Result<Metadata> {
+	let issuer = config.issuer.as_deref().ok_or_else(|| {
+		err!(Config("sso.issuer", "The issuer of the provider is not configured."))
+	})?;
+
+	let url = format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'));
+	let response = self
+		.services
+		.client
+		.default
+		.get(&url)
+		.send()
+		.await?;
+
+	parse(response).await
+}
+
+fn client_id(config: &SsoConfig) -> Result<&str> {
+	config
+		.client_id
+		.as_deref()
+		.ok_or_else(|| err!(Config("sso.client_id", "The client ID is not configured.")))
+}
+
+async fn parse<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+	let status = response.status();
+	let body = response.bytes().await?;
+	if !status.is_success() {
+		return Err!(BadServerResponse(warn!(
+			"The single sign-on provider responded with {status}: {}",
+			String::from_utf8_lossy(&body)
+		)));
+	}
+
+	serde_json::from_slice(&body)
+		.map_err(|e| err!(BadServerResponse("The single sign-on provider responded with: {e}")))
+}
@@ -0,0 +1,65 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tuwunel_core::utils;
+
+/// Length of the PKCE code verifier; the specification allows 43 to 128.
+const VERIFIER_LENGTH: usize = 64;
+
+/// Length of the nonce the ID token must claim.
+const NONCE_LENGTH: usize = 32;
+
+/// What the login at the provider is for.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(super) enum Purpose {
+	/// Logging in a client, redirected back to the URL with a login token.
+	Login {
+		redirect_url: String,
+	},
+
+	/// Completing the `m.login.sso` stage of the session of user-interactive
+	/// authentication.
+	Uiaa {
+		session: String,
+	},
+}
+
+/// Login at the provider under way, kept under the state handed to it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct Request {
+	pub(super) purpose: Purpose,
+
+	/// PKCE code verifier the code is exchanged with.
+	pub(super) verifier: String,
+
+	/// Nonce the ID token must claim.
+	pub(super) nonce: String,
+
+	pub(super) expires_at: u64,
+}
+
+impl Request {
+	#[must_use]
+	pub(super) fn new(purpose: Purpose, now: u64, ttl_ms: u64) -> Self {
+		Self {
+			purpose,
+			verifier: utils::random_string(VERIFIER_LENGTH),
+			nonce: utils::random_string(NONCE_LENGTH),
+			expires_at: now.saturating_add(ttl_ms),
+		}
+	}
+
+	/// PKCE code challenge of the verifier, with the `S256` method.
+	#[must_use]
+	#[cfg_attr(not(feature = "sso"), allow(dead_code))]
+	pub(super) fn challenge(&self) -> String { challenge(&self.verifier) }
+
+	#[must_use]
+	pub(super) fn is_expired(&self, now: u64) -> bool { now >= self.expires_at }
+}
+
+#[must_use]
+#[cfg_attr(not(feature = "sso"), allow(dead_code))]
+pub(super) fn challenge(verifier: &str) -> String {
+	URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
@@ -0,0 +1,145 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde_json::{Value, json};
+
+use super::{
+	claims::{self, Claims},
+	is_allowed_client,
+	request::{Purpose, Request, challenge},
+};
+
+const ISSUER: &str = "https://auth.example.com";
+const CLIENT_ID: &str = "tuwunel";
+const NONCE: &str = "nonce";
+const NOW: u64 = 1_700_000_000;
+
+fn id_token(payload: &Value) -> String {
+	let payload = URL_SAFE_NO_PAD.encode(payload.to_string());
+	format!("eyJhbGciOiJSUzI1NiJ9.{payload}.signature")
+}
+
+fn check(payload: &Value) -> tuwunel_core::Result<Claims> {
+	claims::id_token(&id_token(payload), ISSUER, CLIENT_ID, NONCE, NOW)
+}
+
+fn payload() -> Value {
+	json!({
+		"iss": ISSUER,
+		"aud": CLIENT_ID,
+		"nonce": NONCE,
+		"exp": NOW.saturating_add(60),
+		"sub": "248289761001",
+		"preferred_username": "Alice",
+	})
+}
+
+#[test]
+fn pkce_challenge() {
+	assert_eq!(
+		challenge("tuwunel-pkce-verifier-0123456789abcdefghijklmnopqrstuvwxyz"),
+		"D3VGAvzUKuOhr9LiZgGpIewUv_OnVZgdwQWy6y1T8y8",
+		"the challenge is the unpadded base64url SHA-256 of the verifier"
+	);
+
+	let purpose = Purpose::Uiaa { session: "session".to_owned() };
+	let request = Request::new(purpose, 1000, 600_000);
+
+	assert!(
+		(43..=128).contains(&request.verifier.len()),
+		"the verifier has the length PKCE requires"
+	);
+	assert_eq!(
+		request.challenge(),
+		challenge(&request.verifier),
+		"the challenge is of the verifier"
+	);
+	assert_ne!(request.nonce, request.verifier, "the nonce is not the verifier");
+	assert!(!request.is_expired(600_999), "the request is valid until its expiry");
+	assert!(request.is_expired(601_000), "the request expires after its time to live");
+}
+
+#[test]
+fn id_token_claims() {
+	let claims = check(&payload()).expect("the ID token is valid");
+	assert_eq!(
+		claims::string(&claims, "sub").as_deref(),
+		Some("248289761001"),
+		"the subject is claimed"
+	);
+
+	let mut audiences = payload();
+	audiences["aud"] = json!(["other", CLIENT_ID]);
+	assert!(check(&audiences).is_ok(), "the client may be one of the audiences");
+}
+
+#[test]
+fn id_token_rejected() {
+	for (claim, value, reason) in [
+		("iss", json!("https://evil.example.com"), "another issuer"),
+		("aud", json!("other"), "another client"),
+		("aud", json!(["other"]), "other clients"),
+		("nonce", json!("replayed"), "another login"),
+		("exp", json!(NOW), "an expired token"),
+		("exp", Value::Null, "a token without expiry"),
+	] {
+		let mut payload = payload();
+		payload[claim] = value;
+		assert!(check(&payload).is_err(), "the ID token of {reason} is rejected");
+	}
+
+	assert!(
+		claims::id_token("not a token", ISSUER, CLIENT_ID, NONCE, NOW).is_err(),
+		"a malformed ID token is rejected"
+	);
+}
+
+#[test]
+fn claimed_localpart() {
+	assert_eq!(
+		claims::localpart("Alice").as_deref(),
+		Some("alice"),
+		"the localpart is lowercased"
+	);
+	assert_eq!(
+		claims::localpart(" Jean Dupont ").as_deref(),
+		Some("jean_dupont"),
+		"spaces are trimmed and replaced"
+	);
+	assert_eq!(
+		claims::localpart("élodie@corp").as_deref(),
+		Some("_lodie_corp"),
+		"characters not allowed in a localpart are replaced"
+	);
+	assert_eq!(claims::localpart("  "), None, "a blank claim makes no localpart");
+
+	let claims: Claims =
+		serde_json::from_value(json!({ "sub": 42, "groups": [] })).expect("claims are an object");
+
+	assert_eq!(
+		claims::string(&claims, "sub").as_deref(),
+		Some("42"),
+		"a numeric subject is claimed as a string"
+	);
+	assert_eq!(claims::string(&claims, "groups"), None, "a list is not a string claim");
+}
+
+#[test]
+fn allowed_clients() {
+	let allowlist = ["https://app.element.io/".to_owned(), "im.fluffychat://".to_owned()];
+
+	assert!(
+		is_allowed_client(&allowlist, "https://app.element.io/?loginToken=token"),
+		"a client under an allowed prefix is redirected to straight"
+	);
+	assert!(
+		is_allowed_client(&allowlist, "im.fluffychat://login?loginToken=token"),
+		"an allowed native client is redirected to straight"
+	);
+	assert!(
+		!is_allowed_client(&allowlist, "https://app.element.io.evil.example/?loginToken=token"),
+		"a host extending an allowed one is confirmed"
+	);
+	assert!(
+		!is_allowed_client(&[], "https://app.element.io/?loginToken=token"),
+		"every client is confirmed without an allowlist"
+	);
+}
@@ -0,0 +1,191 @@
+//! What the server holds of each sliding sync connection, for admins debugging
+//! stuck clients. The state requested of rooms may reveal what a user is
+//! interested in, so it is counted rather than listed unless asked for.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use ruma::{
+	OwnedRoomId, UInt, api::client::sync::sync_events::v5::request, events::StateEventType,
+};
+use serde::Serialize;
+use tuwunel_core::implement;
+
+use super::{SnakeConnectionsKey, SnakeSyncCache};
+
+/// Sizes of the cached state of a connection.
+#[derive(Debug, Serialize)]
+pub struct ConnectionSummary {
+	pub lists: usize,
+	pub subscriptions: usize,
+	pub known_rooms: usize,
+	pub extensions: ExtensionToggles,
+}
+
+/// Extensions enabled on a connection.
+#[derive(Debug, Default, Serialize)]
+pub struct ExtensionToggles {
+	pub e2ee: bool,
+	pub to_device: bool,
+	pub account_data: bool,
+	pub receipts: bool,
+	pub typing: bool,
+}
+
+/// The cached state of a connection.
+#[derive(Debug, Serialize)]
+pub struct ConnectionSnapshot {
+	pub lists: BTreeMap<String, ListSnapshot>,
+	pub subscriptions: BTreeMap<OwnedRoomId, RequiredState>,
+
+	/// Rooms sent down each list and the position they were last sent at;
+	/// zero once out of date.
+	pub known_rooms: BTreeMap<String, BTreeMap<OwnedRoomId, u64>>,
+	pub extensions: ExtensionToggles,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListSnapshot {
+	pub ranges: Vec<(UInt, UInt)>,
+	pub timeline_limit: UInt,
+	pub required_state: RequiredState,
+	pub is_invite: Option<bool>,
+	pub not_room_types: usize,
+}
+
+/// State events requested of rooms.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RequiredState {
+	Full(Vec<(String, String)>),
+	Summary {
+		entries: usize,
+	},
+}
+
+impl ConnectionSnapshot {
+	/// Replaces the state requested of rooms with the number of entries.
+	#[must_use]
+	pub fn summarized(mut self) -> Self {
+		let required_state = self
+			.lists
+			.values_mut()
+			.map(|list| &mut list.required_state)
+			.chain(self.subscriptions.values_mut());
+
+		for required_state in required_state {
+			required_state.summarize();
+		}
+
+		self
+	}
+}
+
+impl RequiredState {
+	fn new(required_state: &[(StateEventType, String)]) -> Self {
+		Self::Full(
+			required_state
+				.iter()
+				.map(|(kind, state_key)| (kind.to_string(), state_key.clone()))
+				.collect(),
+		)
+	}
+
+	fn summarize(&mut self) {
+		if let Self::Full(entries) = self {
+			*self = Self::Summary { entries: entries.len() };
+		}
+	}
+}
+
+/// Every cached connection with the size of its state.
+#[implement(super::Service)]
+pub fn iter_snake_connections(&self) -> Vec<(SnakeConnectionsKey, ConnectionSummary)> {
+	let connections: Vec<_> = self
+		.snake_connections
+		.lock()
+		.expect("locked")
+		.iter()
+		.map(|(key, cached)| (key.clone(), Arc::clone(cached)))
+		.collect();
+
+	connections
+		.into_iter()
+		.map(|(key, cached)| (key, summary(&cached.lock().expect("locked"))))
+		.collect()
+}
+
+/// The cached state of the connection, if any.
+#[implement(super::Service)]
+pub fn snapshot_snake_connection(&self, key: &SnakeConnectionsKey) -> Option<ConnectionSnapshot> {
+	let cached = self
+		.snake_connections
+		.lock()
+		.expect("locked")
+		.get(key)
+		.map(Arc::clone)?;
+
+	let cached = cached.lock().expect("locked");
+	Some(snapshot(&cached))
+}
+
+pub(super) fn summary(cached: &SnakeSyncCache) -> ConnectionSummary {
+	ConnectionSummary {
+		lists: cached.lists.len(),
+		subscriptions: cached.subscriptions.len(),
+		known_rooms: cached
+			.known_rooms
+			.values()
+			.map(BTreeMap::len)
+			.sum(),
+		extensions: toggles(&cached.extensions),
+	}
+}
+
+pub(super) fn snapshot(cached: &SnakeSyncCache) -> ConnectionSnapshot {
+	ConnectionSnapshot {
+		lists: cached
+			.lists
+			.iter()
+			.map(|(list_id, list)| (list_id.to_string(), list_snapshot(list)))
+			.collect(),
+		subscriptions: cached
+			.subscriptions
+			.iter()
+			.map(|(room_id, subscription)| {
+				(room_id.clone(), RequiredState::new(&subscription.required_state))
+			})
+			.collect(),
+		known_rooms: cached
+			.known_rooms
+			.iter()
+			.map(|(list_id, rooms)| (list_id.to_string(), rooms.clone()))
+			.collect(),
+		extensions: toggles(&cached.extensions),
+	}
+}
+
+fn list_snapshot(list: &request::List) -> ListSnapshot {
+	ListSnapshot {
+		ranges: list.ranges.clone(),
+		timeline_limit: list.room_details.timeline_limit,
+		required_state: RequiredState::new(&list.room_details.required_state),
+		is_invite: list
+			.filters
+			.as_ref()
+			.and_then(|filters| filters.is_invite),
+		not_room_types: list
+			.filters
+			.as_ref()
+			.map_or(0, |filters| filters.not_room_types.len()),
+	}
+}
+
+fn toggles(extensions: &request::Extensions) -> ExtensionToggles {
+	ExtensionToggles {
+		e2ee: extensions.e2ee.enabled.unwrap_or(false),
+		to_device: extensions.to_device.enabled.unwrap_or(false),
+		account_data: extensions.account_data.enabled.unwrap_or(false),
+		receipts: extensions.receipts.enabled.unwrap_or(false),
+		typing: extensions.typing.enabled.unwrap_or(false),
+	}
+}
@@ -0,0 +1,58 @@
+//! Members of the encrypted rooms a user left, whose devices leave the device
+//! lists of the user.
+
+use std::collections::HashSet;
+
+use futures::StreamExt;
+use ruma::{OwnedUserId, UserId};
+use tuwunel_core::{
+	implement,
+	utils::stream::{BroadbandExt, ReadyExt},
+};
+
+/// Members of the encrypted rooms the user left after `since` and by
+/// `next_batch`. Their devices leave the device lists of the user unless
+/// another encrypted room is still shared with them. Nothing is left on an
+/// initial sync, which tracks no device lists yet.
+#[implement(super::Service)]
+pub async fn left_encrypted_room_members(
+	&self,
+	user_id: &UserId,
+	since: u64,
+	next_batch: u64,
+) -> HashSet<OwnedUserId> {
+	if since == 0 {
+		return HashSet::new();
+	}
+
+	self.services
+		.state_cache
+		.rooms_left_between(user_id, since, next_batch)
+		.map(ToOwned::to_owned)
+		.broad_filter_map(async |room_id| {
+			if !self
+				.services
+				.state_accessor
+				.is_encrypted_room(&room_id)
+				.await
+			{
+				return None;
+			}
+
+			let members: Vec<OwnedUserId> = self
+				.services
+				.state_cache
+				.room_members(&room_id)
+				.ready_filter(|&member| member != user_id)
+				.map(ToOwned::to_owned)
+				.collect()
+				.await;
+
+			Some(members)
+		})
+		.ready_fold(HashSet::new(), |mut left_members, members| {
+			left_members.extend(members);
+			left_members
+		})
+		.await
+}
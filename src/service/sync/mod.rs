@@ -1,3 +1,7 @@
+mod introspect;
+mod left;
+#[cfg(test)]
+mod tests;
 mod watch;
 
 use std::{
@@ -5,13 +9,18 @@
 	sync::{Arc, Mutex, Mutex as StdMutex},
 };
 
+use futures::{StreamExt, future::join3};
 use ruma::{
-	OwnedDeviceId, OwnedRoomId, OwnedUserId,
+	OwnedDeviceId, OwnedRoomId, OwnedUserId, RoomId,
 	api::client::sync::sync_events::v5::{Request, request},
 };
-use tuwunel_core::{Result, implement, smallstr::SmallString};
+use tuwunel_core::{Result, implement, smallstr::SmallString, utils::IterStream};
 use tuwunel_database::Map;
 
+pub use self::introspect::{
+	ConnectionSnapshot, ConnectionSummary, ExtensionToggles, ListSnapshot, RequiredState,
+};
+
 pub struct Service {
 	db: Data,
 	services: Arc<crate::services::OnceServices>,
@@ -26,6 +35,8 @@ pub struct Data {
 	userroomid_knockedstate: Arc<Map>,
 	userroomid_notificationcount: Arc<Map>,
 	userroomid_highlightcount: Arc<Map>,
+	userroomthreadid_notificationcount: Arc<Map>,
+	userroomthreadid_highlightcount: Arc<Map>,
 	pduid_pdu: Arc<Map>,
 	keychangeid_userid: Arc<Map>,
 	roomuserdataid_accountdata: Arc<Map>,
@@ -61,6 +72,10 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				userroomid_knockedstate: args.db["userroomid_knockedstate"].clone(),
 				userroomid_notificationcount: args.db["userroomid_notificationcount"].clone(),
 				userroomid_highlightcount: args.db["userroomid_highlightcount"].clone(),
+				userroomthreadid_notificationcount: args.db["userroomthreadid_notificationcount"]
+					.clone(),
+				userroomthreadid_highlightcount: args.db["userroomthreadid_highlightcount"]
+					.clone(),
 				pduid_pdu: args.db["pduid_pdu"].clone(),
 				keychangeid_userid: args.db["keychangeid_userid"].clone(),
 				roomuserdataid_accountdata: args.db["roomuserdataid_accountdata"].clone(),
@@ -212,6 +227,99 @@ pub fn update_snake_sync_known_rooms(
 	}
 }
 
+/// Drops the rooms the user is no longer joined, invited or knocked to from
+/// all lists of the connection; returns what remains known.
+#[implement(Service)]
+pub async fn prune_snake_sync_known_rooms(&self, key: &SnakeConnectionsKey) -> KnownRooms {
+	let Some(cached) = self
+		.snake_connections
+		.lock()
+		.expect("locked")
+		.get(key)
+		.map(Arc::clone)
+	else {
+		return KnownRooms::new();
+	};
+
+	let known: BTreeSet<OwnedRoomId> = cached
+		.lock()
+		.expect("locked")
+		.known_rooms
+		.values()
+		.flat_map(BTreeMap::keys)
+		.cloned()
+		.collect();
+
+	let (user_id, ..) = key;
+	let departed: BTreeSet<OwnedRoomId> = known
+		.into_iter()
+		.stream()
+		.filter_map(async |room_id| {
+			let state_cache = &self.services.state_cache;
+			let (joined, invited, knocked) = join3(
+				state_cache.is_joined(user_id, &room_id),
+				state_cache.is_invited(user_id, &room_id),
+				state_cache.is_knocked(user_id, &room_id),
+			)
+			.await;
+
+			(!joined && !invited && !knocked).then_some(room_id)
+		})
+		.collect()
+		.await;
+
+	let cached = &mut cached.lock().expect("locked");
+	prune_known_rooms(&mut cached.known_rooms, |room_id| !departed.contains(room_id));
+	cached.known_rooms.clone()
+}
+
+/// Drops a room from the known rooms of every connection, e.g. after it was
+/// deleted.
+#[implement(Service)]
+pub fn forget_snake_sync_room(&self, room_id: &RoomId) {
+	let connections: Vec<_> = self
+		.snake_connections
+		.lock()
+		.expect("locked")
+		.values()
+		.map(Arc::clone)
+		.collect();
+
+	for cached in connections {
+		let known_rooms = &mut cached.lock().expect("locked").known_rooms;
+		prune_known_rooms(known_rooms, |known| known != room_id);
+	}
+}
+
+/// Number of known rooms in each list of each cached connection.
+#[implement(Service)]
+pub fn snake_connections_known_rooms(
+	&self,
+) -> Vec<(SnakeConnectionsKey, BTreeMap<ListId, usize>)> {
+	let connections: Vec<_> = self
+		.snake_connections
+		.lock()
+		.expect("locked")
+		.iter()
+		.map(|(key, cached)| (key.clone(), Arc::clone(cached)))
+		.collect();
+
+	connections
+		.into_iter()
+		.map(|(key, cached)| {
+			let sizes = cached
+				.lock()
+				.expect("locked")
+				.known_rooms
+				.iter()
+				.map(|(list_id, rooms)| (list_id.clone(), rooms.len()))
+				.collect();
+
+			(key, sizes)
+		})
+		.collect()
+}
+
 #[implement(Service)]
 pub fn update_snake_sync_subscriptions(
 	&self,
@@ -239,6 +347,15 @@ pub fn forget_snake_sync_connection(&self, key: &SnakeConnectionsKey) {
 		.remove(key);
 }
 
+/// Number of sliding sync connections whose state is cached.
+#[implement(Service)]
+pub fn snake_connections_count(&self) -> usize {
+	self.snake_connections
+		.lock()
+		.expect("locked")
+		.len()
+}
+
 #[implement(Service)]
 pub fn snake_connection_cached(&self, key: &SnakeConnectionsKey) -> bool {
 	self.snake_connections
@@ -261,6 +378,19 @@ pub fn into_snake_key<U, D, C>(
 	(user_id.into(), device_id.into(), conn_id.map(Into::into))
 }
 
+/// Removes the rooms failing the predicate from every list, and lists left
+/// empty.
+fn prune_known_rooms<F>(known_rooms: &mut KnownRooms, is_active: F)
+where
+	F: Fn(&RoomId) -> bool,
+{
+	for rooms in known_rooms.values_mut() {
+		rooms.retain(|room_id, _| is_active(room_id));
+	}
+
+	known_rooms.retain(|_, rooms| !rooms.is_empty());
+}
+
 /// load params from cache if body doesn't contain it, as long as it's allowed
 /// in some cases we may need to allow an empty list as an actual value
 fn list_or_sticky<T: Clone>(target: &mut Vec<T>, cached: &Vec<T>) {
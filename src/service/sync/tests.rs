@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+
+use ruma::owned_room_id;
+
+/// Rooms left are pruned from the connection as sync does, and deleted rooms
+/// from every connection.
+#[tokio::test]
+async fn left_and_deleted_rooms_pruned() {
+	use std::collections::BTreeSet;
+
+	use futures::FutureExt;
+
+	use super::into_snake_key;
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let bob = embedded
+		.create_user("bob", None)
+		.await
+		.expect("user created");
+
+	let mut rooms = Vec::new();
+	for _ in 0..3 {
+		let room_id = embedded
+			.create_room(&alice, None)
+			.await
+			.expect("room created");
+
+		embedded
+			.join_room(&bob, &room_id)
+			.await
+			.expect("bob joined");
+
+		rooms.push(room_id);
+	}
+
+	let rooms: [_; 3] = rooms.try_into().expect("three rooms");
+	let [kept, left, deleted] = &rooms;
+
+	let key = into_snake_key(bob.clone(), "DEVICE", Some("conn"));
+	let sync = &services.sync;
+	sync.update_snake_sync_known_rooms(&key, "all".into(), rooms.iter().cloned().collect(), 1);
+	sync.update_snake_sync_known_rooms(&key, "left".into(), BTreeSet::from([left.clone()]), 1);
+
+	embedded
+		.leave_room(&bob, left)
+		.await
+		.expect("bob left");
+
+	let known_rooms = sync.prune_snake_sync_known_rooms(&key).await;
+	assert!(!known_rooms.contains_key("left"), "a list of only rooms left is dropped");
+
+	let all = known_rooms.get("all").expect("list kept");
+	assert!(all.contains_key(kept), "a room still joined is kept");
+	assert!(!all.contains_key(left), "a room left is pruned");
+	assert!(all.contains_key(deleted), "a room not yet deleted is kept");
+
+	let state_lock = services.state.mutex.lock(deleted).await;
+	services
+		.delete
+		.delete_room(deleted, false, state_lock)
+		.boxed()
+		.await
+		.expect("room deleted");
+
+	let connections = sync.snake_connections_known_rooms();
+	let (_, sizes) = connections
+		.iter()
+		.find(|(connection, _)| *connection == key)
+		.expect("connection cached");
+
+	assert_eq!(
+		sizes.iter().collect::<Vec<_>>(),
+		[(&"all".into(), &1)],
+		"a deleted room is swept from the connection"
+	);
+
+	services.stop().await;
+}
+
+#[test]
+fn connection_snapshot_summarizes_required_state() {
+	use ruma::api::client::sync::sync_events::v5::request;
+	use serde_json::json;
+
+	use super::{
+		RequiredState, SnakeSyncCache,
+		introspect::{snapshot, summary},
+	};
+
+	let list: request::List = serde_json::from_value(json!({
+		"ranges": [[0, 19]],
+		"timeline_limit": 5,
+		"required_state": [["m.room.topic", ""], ["m.room.member", "$LAZY"]],
+	}))
+	.expect("valid list");
+
+	let mut cached = SnakeSyncCache::default();
+	cached.lists.insert("all".into(), list);
+	cached.extensions.e2ee.enabled = Some(true);
+	cached.known_rooms.insert(
+		"all".into(),
+		BTreeMap::from([
+			(owned_room_id!("!a:example.com"), 7),
+			(owned_room_id!("!b:example.com"), 0),
+		]),
+	);
+
+	let summary = summary(&cached);
+	assert_eq!(summary.lists, 1, "one list");
+	assert_eq!(summary.known_rooms, 2, "known rooms are totalled");
+	assert!(summary.extensions.e2ee, "e2ee is enabled");
+	assert!(!summary.extensions.typing, "typing is not");
+
+	let full = snapshot(&cached);
+	let list = full.lists.get("all").expect("list snapshot");
+	assert!(
+		matches!(&list.required_state, RequiredState::Full(state) if state.len() == 2),
+		"the full snapshot lists the required state"
+	);
+
+	let dump = serde_json::to_string(&full.summarized()).expect("serializable snapshot");
+	assert!(!dump.contains("m.room.topic"), "the summary hides the required state: {dump}");
+	assert!(dump.contains(r#""entries":2"#), "the summary counts it: {dump}");
+	assert!(dump.contains("!a:example.com"), "known rooms are dumped: {dump}");
+}
+
+#[tokio::test]
+async fn left_encrypted_room_members_between_tokens() {
+	use futures::FutureExt;
+	use ruma::{OwnedUserId, events::room::encryption::RoomEncryptionEventContent};
+	use tuwunel_core::pdu::PduBuilder;
+
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let create_user = async |localpart| {
+		embedded
+			.create_user(localpart, None)
+			.await
+			.expect("user created")
+	};
+
+	let alice = create_user("alice").await;
+	let bob = create_user("bob").await;
+	let carol = create_user("carol").await;
+
+	let encrypted = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+
+	let state_lock = services.state.mutex.lock(&encrypted).await;
+	services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				String::new(),
+				&RoomEncryptionEventContent::with_recommended_defaults(),
+			),
+			&alice,
+			&encrypted,
+			&state_lock,
+		)
+		.boxed()
+		.await
+		.expect("encryption enabled");
+	drop(state_lock);
+
+	let unencrypted = embedded
+		.create_room(&carol, None)
+		.await
+		.expect("room created");
+
+	for room_id in [&encrypted, &unencrypted] {
+		embedded
+			.join_room(&bob, room_id)
+			.await
+			.expect("bob joined");
+	}
+
+	let since = services.globals.current_count();
+	for room_id in [&encrypted, &unencrypted] {
+		embedded
+			.leave_room(&bob, room_id)
+			.await
+			.expect("bob left");
+	}
+
+	let next_batch = services.globals.current_count();
+	let left = async |since: u64| -> Vec<OwnedUserId> {
+		services
+			.sync
+			.left_encrypted_room_members(&bob, since, next_batch)
+			.await
+			.into_iter()
+			.collect()
+	};
+
+	assert_eq!(
+		left(since).await,
+		[alice.clone()],
+		"the members of the encrypted room left are reported, not those of other rooms"
+	);
+	assert!(left(next_batch).await.is_empty(), "a leave reported before is not again");
+	assert!(left(0).await.is_empty(), "an initial sync tracks no device lists yet");
+
+	embedded
+		.join_room(&bob, &encrypted)
+		.await
+		.expect("bob joined again");
+
+	let rejoined = services.globals.current_count();
+	assert!(
+		services
+			.sync
+			.left_encrypted_room_members(&bob, since, rejoined)
+			.await
+			.is_empty(),
+		"a room joined again is no longer left"
+	);
+
+	services.stop().await;
+}
+
+/// A knock wakes the sync of the knocking user, whose knocked rooms change.
+#[tokio::test]
+async fn knock_wakes_watcher() {
+	use std::time::Duration;
+
+	use futures::pin_mut;
+	use ruma::{
+		device_id,
+		events::room::member::{MembershipState, RoomMemberEventContent},
+	};
+	use tokio::time::timeout;
+
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let bob = embedded
+		.create_user("bob", None)
+		.await
+		.expect("user created");
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+
+	let watch = services.sync.watch(&bob, device_id!("DEVICE"));
+	pin_mut!(watch);
+	timeout(Duration::from_millis(100), &mut watch)
+		.await
+		.expect_err("nothing changed for bob yet");
+
+	services
+		.state_cache
+		.update_membership(
+			&room_id,
+			&bob,
+			RoomMemberEventContent::new(MembershipState::Knock),
+			&bob,
+			None,
+			None,
+			true,
+		)
+		.await
+		.expect("bob knocks");
+
+	timeout(Duration::from_secs(5), watch)
+		.await
+		.expect("the knock wakes the watcher")
+		.expect("watch ended without error");
+
+	services.stop().await;
+}
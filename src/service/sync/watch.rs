@@ -43,6 +43,14 @@ pub async fn watch(&self, user_id: &UserId, device_id: &DeviceId) -> Result {
 			.userroomid_highlightcount
 			.watch_raw_prefix(&userid_prefix)
 			.boxed(),
+		self.db
+			.userroomthreadid_notificationcount
+			.watch_raw_prefix(&userid_prefix)
+			.boxed(),
+		self.db
+			.userroomthreadid_highlightcount
+			.watch_raw_prefix(&userid_prefix)
+			.boxed(),
 		self.db
 			.roomusertype_roomuserdataid
 			.watch_prefix(&globaluserdata_prefix)
@@ -0,0 +1,127 @@
+//! Delivery of the emails validating addresses. The server mails through SMTP
+//! when built with the `smtp` feature and configured in `[global.smtp]`;
+//! embedders may plug in any other `Mailer`.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ruma::ServerName;
+#[cfg(not(feature = "smtp"))]
+use tuwunel_core::warn;
+use tuwunel_core::{Result, config::SmtpConfig};
+
+use super::session::{Purpose, Session};
+
+/// Sends plain text emails.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+	async fn send(&self, to: &str, subject: &str, body: &str) -> Result;
+}
+
+/// The mailer configured, if any.
+#[cfg(feature = "smtp")]
+pub(super) fn from_config(config: &SmtpConfig) -> Result<Option<Arc<dyn Mailer>>> {
+	if !config.enable {
+		return Ok(None);
+	}
+
+	Ok(Some(Arc::new(smtp::Smtp::new(config)?)))
+}
+
+/// The mailer configured, if any.
+#[cfg(not(feature = "smtp"))]
+pub(super) fn from_config(config: &SmtpConfig) -> Result<Option<Arc<dyn Mailer>>> {
+	if config.enable {
+		warn!("Email is enabled in [global.smtp] but this build lacks the smtp feature.");
+	}
+
+	Ok(None)
+}
+
+/// Subject and body of the email carrying the token of the session.
+pub(super) fn compose(
+	server_name: &ServerName,
+	session: &Session,
+	link: &str,
+) -> (String, String) {
+	let token = &session.token;
+	let (subject, action) = match session.purpose {
+		| Purpose::Add => (
+			format!("Validate your email address on {server_name}"),
+			"add this email address to your account",
+		),
+		| Purpose::PasswordReset => (
+			format!("Reset your password on {server_name}"),
+			"reset the password of your account",
+		),
+	};
+
+	let body = format!(
+		"A request was made to {action} on {server_name}.\n\nTo continue, open the link \
+		 below:\n\n{link}\n\nor enter this token: {token}\n\nIf you did not make this request, \
+		 you can ignore this email.\n"
+	);
+
+	(subject, body)
+}
+
+#[cfg(feature = "smtp")]
+mod smtp {
+	use async_trait::async_trait;
+	use lettre::{
+		AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+		message::{Mailbox, header::ContentType},
+	};
+	use tuwunel_core::{Result, config::SmtpConfig, err};
+
+	pub(super) struct Smtp {
+		transport: AsyncSmtpTransport<Tokio1Executor>,
+		from: Mailbox,
+	}
+
+	impl Smtp {
+		pub(super) fn new(config: &SmtpConfig) -> Result<Self> {
+			let url = config
+				.url
+				.as_deref()
+				.ok_or_else(|| err!(Config("smtp.url", "Required to send email.")))?;
+
+			let from = config
+				.from
+				.as_deref()
+				.ok_or_else(|| err!(Config("smtp.from", "Required to send email.")))?
+				.parse()
+				.map_err(|e| err!(Config("smtp.from", "Invalid sender: {e}")))?;
+
+			let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+				.map_err(|e| err!(Config("smtp.url", "Invalid SMTP URL: {e}")))?
+				.build();
+
+			Ok(Self { transport, from })
+		}
+	}
+
+	#[async_trait]
+	impl super::Mailer for Smtp {
+		async fn send(&self, to: &str, subject: &str, body: &str) -> Result {
+			let to: Mailbox = to
+				.parse()
+				.map_err(|e| err!(Request(InvalidParam("Invalid email address: {e}"))))?;
+
+			let message = Message::builder()
+				.from(self.from.clone())
+				.to(to)
+				.subject(subject)
+				.header(ContentType::TEXT_PLAIN)
+				.body(body.to_owned())
+				.map_err(|e| err!("Failed to build email: {e}"))?;
+
+			self.transport
+				.send(message)
+				.await
+				.map_err(|e| err!("Failed to send email: {e}"))?;
+
+			Ok(())
+		}
+	}
+}
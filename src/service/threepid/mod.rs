@@ -0,0 +1,410 @@
+//! Third party identifiers of accounts; only email addresses are supported.
+//! Proving control of an address starts a session in
+//! `clientsecretsid_threepidsession`, keyed by the client secret and the
+//! session ID, which the token mailed to the address validates. A validated
+//! session either adds the address to the account, recorded in
+//! `userid_threepids` and indexed by address in `threepid_userid`, or
+//! authenticates the owner of the address to reset their password.
+
+mod mailer;
+mod session;
+#[cfg(test)]
+mod tests;
+
+use std::{
+	sync::{Arc, RwLock},
+	time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{Stream, StreamExt, pin_mut};
+use ruma::{
+	MilliSecondsSinceUnixEpoch, OwnedUserId, UInt, UserId,
+	thirdparty::{Medium, ThirdPartyIdentifier, ThirdPartyIdentifierInit},
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use tuwunel_core::{
+	Err, Result, debug, implement,
+	utils::{self, ReadyExt, stream::TryIgnore},
+};
+use tuwunel_database::{Deserialized, Ignore, Interfix, Json, Map};
+
+use self::session::{Session, normalize_email};
+pub use self::{mailer::Mailer, session::Purpose};
+
+/// Length of the session IDs handed to clients.
+const SID_LENGTH: usize = 32;
+
+/// Interval between prunings of expired sessions, in seconds.
+const PRUNE_INTERVAL: u64 = 60 * 60;
+
+/// Path the link in the email validates a session at.
+pub const SUBMIT_TOKEN_PATH: &str = "/_tuwunel/threepid/email/submit_token";
+
+pub struct Service {
+	services: Arc<crate::services::OnceServices>,
+	db: Data,
+	mailer: RwLock<Option<Arc<dyn Mailer>>>,
+}
+
+struct Data {
+	clientsecretsid_threepidsession: Arc<Map>,
+	threepid_userid: Arc<Map>,
+	userid_threepids: Arc<Map>,
+}
+
+/// An address added to an account.
+#[derive(Debug, Deserialize, Serialize)]
+struct Association {
+	added_at: u64,
+	validated_at: u64,
+}
+
+#[async_trait]
+impl crate::Service for Service {
+	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		Ok(Arc::new(Self {
+			services: args.services.clone(),
+			db: Data {
+				clientsecretsid_threepidsession: args.db["clientsecretsid_threepidsession"]
+					.clone(),
+				threepid_userid: args.db["threepid_userid"].clone(),
+				userid_threepids: args.db["userid_threepids"].clone(),
+			},
+			mailer: RwLock::new(mailer::from_config(&args.server.config.smtp)?),
+		}))
+	}
+
+	async fn worker(self: Arc<Self>) -> Result {
+		if self.services.db.is_read_only() {
+			return Ok(());
+		}
+
+		while self.services.server.running() {
+			tokio::select! {
+				() = sleep(Duration::from_secs(PRUNE_INTERVAL)) => {
+					self.prune_sessions().await;
+				},
+				() = self.services.server.until_shutdown() => break,
+			}
+		}
+
+		Ok(())
+	}
+
+	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
+}
+
+/// Replaces the mailer sending the validation emails; `None` disables email.
+#[implement(Service)]
+pub fn set_mailer(&self, mailer: Option<Arc<dyn Mailer>>) {
+	*self.mailer.write().expect("locked for writing") = mailer;
+}
+
+/// Whether email can be sent, so addresses can be validated.
+#[implement(Service)]
+#[must_use]
+pub fn email_enabled(&self) -> bool { self.mailer().is_some() }
+
+#[implement(Service)]
+fn mailer(&self) -> Option<Arc<dyn Mailer>> {
+	self.mailer
+		.read()
+		.expect("locked for reading")
+		.clone()
+}
+
+/// Mails a token validating the address for the purpose; returns the ID of
+/// the session. A retry with the same client secret and send attempt answers
+/// with the session already started rather than mailing again.
+#[implement(Service)]
+#[tracing::instrument(skip(self, client_secret), level = "debug")]
+pub async fn request_email_token(
+	&self,
+	address: &str,
+	client_secret: &str,
+	send_attempt: UInt,
+	purpose: Purpose,
+) -> Result<String> {
+	let Some(mailer) = self.mailer() else {
+		return Err!(Request(ThreepidDenied("Email is not enabled on this server.")));
+	};
+
+	let address = normalize_email(address)?;
+	match (purpose, self.find_user(&address).await.is_ok()) {
+		| (Purpose::Add, true) => {
+			return Err!(Request(ThreepidInUse("The email address is already in use.")));
+		},
+		| (Purpose::PasswordReset, false) => {
+			return Err!(Request(ThreepidNotFound(
+				"The email address is not associated with an account."
+			)));
+		},
+		| _ => (),
+	}
+
+	let now = utils::millis_since_unix_epoch();
+	let send_attempt = u64::from(send_attempt);
+	let existing = self.find_session(client_secret, &address).await;
+
+	if let Some((sid, session)) = &existing
+		&& session.purpose == purpose
+		&& !session.resend(send_attempt, now)
+	{
+		return Ok(sid.clone());
+	}
+
+	let sid = existing.map_or_else(|| utils::random_string(SID_LENGTH), |(sid, _)| sid);
+	let ttl = self
+		.services
+		.server
+		.config
+		.smtp
+		.token_ttl
+		.saturating_mul(1000);
+
+	let session = Session::new(address, purpose, send_attempt, now, ttl);
+	let link = format!(
+		"{}?client_secret={client_secret}&sid={sid}&token={}",
+		self.submit_url(),
+		session.token
+	);
+
+	let server_name = self.services.globals.server_name();
+	let (subject, body) = mailer::compose(server_name, &session, &link);
+	mailer
+		.send(&session.address, &subject, &body)
+		.await?;
+
+	self.db
+		.clientsecretsid_threepidsession
+		.put((client_secret, &sid), Json(&session));
+
+	debug!(?purpose, "Mailed validation token");
+	Ok(sid)
+}
+
+/// Validates the session with the token mailed for it.
+#[implement(Service)]
+pub async fn submit_token(&self, client_secret: &str, sid: &str, token: &str) -> Result {
+	let mut session = self.get_session(client_secret, sid).await?;
+	session.submit(token, utils::millis_since_unix_epoch())?;
+
+	self.db
+		.clientsecretsid_threepidsession
+		.put((client_secret, sid), Json(&session));
+
+	Ok(())
+}
+
+/// Adds the address validated by the session to the account of the user.
+#[implement(Service)]
+pub async fn add(&self, user_id: &UserId, client_secret: &str, sid: &str) -> Result {
+	let session = self.get_session(client_secret, sid).await?;
+	let now = utils::millis_since_unix_epoch();
+	let validated_at = session.validated(Purpose::Add, now)?;
+	let address = &session.address;
+
+	if self
+		.find_user(address)
+		.await
+		.is_ok_and(|owner| owner != user_id)
+	{
+		return Err!(Request(ThreepidInUse("The email address is already in use.")));
+	}
+
+	let medium = Medium::Email.as_str();
+	let _cork = self.services.db.cork();
+	self.db
+		.userid_threepids
+		.put((user_id, medium, address), Json(Association { added_at: now, validated_at }));
+
+	self.db
+		.threepid_userid
+		.put((medium, address), user_id);
+
+	self.db
+		.clientsecretsid_threepidsession
+		.del((client_secret, sid));
+
+	Ok(())
+}
+
+/// The owner of the address validated by the session, which is used up.
+/// Without a user authenticated otherwise, as when resetting a password, the
+/// owner is authenticated; with one, the address must be theirs.
+#[implement(Service)]
+pub async fn authenticate(
+	&self,
+	client_secret: &str,
+	sid: &str,
+	sender_user: Option<&UserId>,
+) -> Result<OwnedUserId> {
+	let session = self.get_session(client_secret, sid).await?;
+	session.validated(Purpose::PasswordReset, utils::millis_since_unix_epoch())?;
+
+	let user_id = self.find_user(&session.address).await?;
+	if sender_user.is_some_and(|sender_user| sender_user != &*user_id) {
+		return Err!(Request(Forbidden("The email address belongs to another user.")));
+	}
+
+	self.db
+		.clientsecretsid_threepidsession
+		.del((client_secret, sid));
+
+	Ok(user_id)
+}
+
+/// Removes the address from the account of the user; false when the account
+/// did not have it.
+#[implement(Service)]
+pub async fn remove(&self, user_id: &UserId, medium: &Medium, address: &str) -> bool {
+	let address = address.to_lowercase();
+	let medium = medium.as_str();
+	let key = (user_id, medium, &address);
+	if self.db.userid_threepids.qry(&key).await.is_err() {
+		return false;
+	}
+
+	let _cork = self.services.db.cork();
+	self.db.userid_threepids.del(key);
+	self.db.threepid_userid.del((medium, &address));
+
+	true
+}
+
+/// Removes every address of the user.
+#[implement(Service)]
+pub async fn remove_all(&self, user_id: &UserId) {
+	let threepids: Vec<(String, String)> = self
+		.db
+		.userid_threepids
+		.keys_prefix(&(user_id, Interfix))
+		.ignore_err()
+		.map(|(_, medium, address): (Ignore, &str, &str)| (medium.to_owned(), address.to_owned()))
+		.collect()
+		.await;
+
+	let _cork = self.services.db.cork();
+	for (medium, address) in &threepids {
+		self.db
+			.userid_threepids
+			.del((user_id, medium, address));
+
+		self.db.threepid_userid.del((medium, address));
+	}
+}
+
+/// The addresses of the user.
+#[implement(Service)]
+pub fn threepids<'a>(
+	&'a self,
+	user_id: &'a UserId,
+) -> impl Stream<Item = ThirdPartyIdentifier> + Send + 'a {
+	type KeyVal<'a> = ((Ignore, &'a str, &'a str), Association);
+
+	self.db
+		.userid_threepids
+		.stream_prefix(&(user_id, Interfix))
+		.ignore_err()
+		.map(|((_, medium, address), association): KeyVal<'_>| {
+			ThirdPartyIdentifierInit {
+				address: address.to_owned(),
+				medium: medium.into(),
+				added_at: millis(association.added_at),
+				validated_at: millis(association.validated_at),
+			}
+			.into()
+		})
+}
+
+/// The user the email address belongs to.
+#[implement(Service)]
+pub async fn find_user(&self, address: &str) -> Result<OwnedUserId> {
+	self.db
+		.threepid_userid
+		.qry(&(Medium::Email.as_str(), address.to_lowercase()))
+		.await
+		.deserialized()
+}
+
+/// URL the validation token is submitted to.
+#[implement(Service)]
+#[must_use]
+pub fn submit_url(&self) -> String {
+	let config = &self.services.server.config;
+	let base = config.well_known.client.as_ref().map_or_else(
+		|| format!("https://{}", config.server_name),
+		|client| client.as_str().trim_end_matches('/').to_owned(),
+	);
+
+	format!("{base}{SUBMIT_TOKEN_PATH}")
+}
+
+#[implement(Service)]
+async fn get_session(&self, client_secret: &str, sid: &str) -> Result<Session> {
+	let Ok(session) = self
+		.db
+		.clientsecretsid_threepidsession
+		.qry(&(client_secret, sid))
+		.await
+		.deserialized::<Session>()
+	else {
+		return Err!(Request(ThreepidAuthFailed("Unknown validation session.")));
+	};
+
+	Ok(session)
+}
+
+/// The session of the client validating the address, if any.
+#[implement(Service)]
+async fn find_session(&self, client_secret: &str, address: &str) -> Option<(String, Session)> {
+	type KeyVal<'a> = ((Ignore, &'a str), Session);
+
+	let sessions = self
+		.db
+		.clientsecretsid_threepidsession
+		.stream_prefix(&(client_secret, Interfix))
+		.ignore_err();
+
+	pin_mut!(sessions);
+	sessions
+		.ready_find(|(_, session): &KeyVal<'_>| session.address == address)
+		.await
+		.map(|((_, sid), session)| (sid.to_owned(), session))
+}
+
+/// Removes the expired sessions; returns the number removed.
+#[implement(Service)]
+async fn prune_sessions(&self) -> usize {
+	type KeyVal<'a> = ((&'a str, &'a str), Session);
+
+	let now = utils::millis_since_unix_epoch();
+	let expired: Vec<(String, String)> = self
+		.db
+		.clientsecretsid_threepidsession
+		.stream()
+		.ignore_err()
+		.ready_filter_map(|((client_secret, sid), session): KeyVal<'_>| {
+			session
+				.is_expired(now)
+				.then(|| (client_secret.to_owned(), sid.to_owned()))
+		})
+		.collect()
+		.await;
+
+	let _cork = self.services.db.cork();
+	for (client_secret, sid) in &expired {
+		self.db
+			.clientsecretsid_threepidsession
+			.del((client_secret, sid));
+	}
+
+	debug!(count = expired.len(), "Pruned expired validation sessions");
+	expired.len()
+}
+
+fn millis(ts: u64) -> MilliSecondsSinceUnixEpoch {
+	MilliSecondsSinceUnixEpoch(ts.try_into().unwrap_or_default())
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use tuwunel_core::{
+	Err, Result,
+	utils::{self, bytes::eq_constant_time},
+};
+
+/// Length of the token mailed to validate an address.
+const TOKEN_LENGTH: usize = 32;
+
+/// What a validated address is for.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Purpose {
+	/// Adding the address to the account of the user.
+	Add,
+
+	/// Resetting the password of the account the address belongs to.
+	PasswordReset,
+}
+
+/// Validation of an address under way.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct Session {
+	pub(super) address: String,
+	pub(super) purpose: Purpose,
+	pub(super) send_attempt: u64,
+	pub(super) token: String,
+	pub(super) expires_at: u64,
+	pub(super) validated_at: Option<u64>,
+}
+
+impl Session {
+	#[must_use]
+	pub(super) fn new(
+		address: String,
+		purpose: Purpose,
+		send_attempt: u64,
+		now: u64,
+		ttl_ms: u64,
+	) -> Self {
+		Self {
+			address,
+			purpose,
+			send_attempt,
+			token: utils::random_string(TOKEN_LENGTH),
+			expires_at: now.saturating_add(ttl_ms),
+			validated_at: None,
+		}
+	}
+
+	/// Whether a request for a token must mail a new one rather than answer
+	/// with this session; the client retries with the same attempt number.
+	#[must_use]
+	pub(super) fn resend(&self, send_attempt: u64, now: u64) -> bool {
+		self.is_expired(now) || send_attempt > self.send_attempt
+	}
+
+	/// Validates the address with the token mailed to it.
+	pub(super) fn submit(&mut self, token: &str, now: u64) -> Result {
+		if self.is_expired(now) {
+			return Err!(Request(ThreepidAuthFailed("The validation session has expired.")));
+		}
+
+		if !eq_constant_time(token.as_bytes(), self.token.as_bytes()) {
+			return Err!(Request(ThreepidAuthFailed("The validation token is incorrect.")));
+		}
+
+		self.validated_at.get_or_insert(now);
+		Ok(())
+	}
+
+	/// When the address was validated for the purpose.
+	pub(super) fn validated(&self, purpose: Purpose, now: u64) -> Result<u64> {
+		if self.is_expired(now) {
+			return Err!(Request(ThreepidAuthFailed("The validation session has expired.")));
+		}
+
+		if self.purpose != purpose {
+			return Err!(Request(ThreepidAuthFailed("The session validated another request.")));
+		}
+
+		self.validated_at.map_or_else(
+			|| Err!(Request(ThreepidAuthFailed("The email address has not been validated."))),
+			Ok,
+		)
+	}
+
+	#[must_use]
+	pub(super) fn is_expired(&self, now: u64) -> bool { now >= self.expires_at }
+}
+
+/// Normalizes an email address for lookups; the domain is case-insensitive and
+/// so, in practice, is the local part.
+pub(super) fn normalize_email(address: &str) -> Result<String> {
+	let address = address.trim();
+	let valid = address
+		.split_once('@')
+		.is_some_and(|(local, domain)| {
+			!local.is_empty() && !domain.is_empty() && !domain.contains('@')
+		}) && !address.contains(char::is_whitespace);
+
+	if !valid {
+		return Err!(Request(InvalidParam("Invalid email address.")));
+	}
+
+	Ok(address.to_lowercase())
+}
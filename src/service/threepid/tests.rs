@@ -0,0 +1,296 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use ruma::{api::client::error::ErrorKind, server_name, uint};
+use tuwunel_core::{Result, utils::hash};
+
+use super::{
+	Mailer, Purpose,
+	mailer::compose,
+	session::{Session, normalize_email},
+};
+use crate::fixture::Fixture;
+
+const TTL: u64 = 60_000;
+
+/// Keeps the emails instead of sending them.
+#[derive(Default)]
+struct Capture(Mutex<Vec<(String, String)>>);
+
+#[async_trait]
+impl Mailer for Capture {
+	async fn send(&self, to: &str, _subject: &str, body: &str) -> Result {
+		self.0
+			.lock()
+			.expect("locked")
+			.push((to.to_owned(), body.to_owned()));
+
+		Ok(())
+	}
+}
+
+impl Capture {
+	/// The token in the last email sent to the address.
+	fn token(&self, to: &str) -> Option<String> {
+		self.0
+			.lock()
+			.expect("locked")
+			.iter()
+			.rev()
+			.find(|(address, _)| address == to)
+			.and_then(|(_, body)| body.split_once("token: "))
+			.and_then(|(_, rest)| rest.split_whitespace().next())
+			.map(ToOwned::to_owned)
+	}
+}
+
+async fn mail(mailer: &dyn Mailer, session: &Session) {
+	let link = format!("https://example.com/submit?token={}", session.token);
+	let (_, body) = compose(server_name!("example.com"), session, &link);
+	mailer
+		.send(&session.address, "subject", &body)
+		.await
+		.expect("sent");
+}
+
+#[tokio::test]
+async fn email_token_validates_session() {
+	let mailer = Capture::default();
+	let mut session = Session::new("alice@example.com".into(), Purpose::Add, 1, 0, TTL);
+	mail(&mailer, &session).await;
+
+	let token = mailer
+		.token("alice@example.com")
+		.expect("token mailed");
+	assert!(session.validated(Purpose::Add, 1).is_err(), "not validated before submitting");
+	assert!(session.submit("wrong", 1).is_err(), "a wrong token is refused");
+	session
+		.submit(&token, 2)
+		.expect("mailed token accepted");
+
+	assert_eq!(session.validated(Purpose::Add, 3).ok(), Some(2), "validated when submitted");
+	assert!(
+		session
+			.validated(Purpose::PasswordReset, 3)
+			.is_err(),
+		"validated for its own purpose only"
+	);
+	assert!(session.validated(Purpose::Add, TTL).is_err(), "the validation expires");
+}
+
+#[tokio::test]
+async fn email_token_expires() {
+	let mailer = Capture::default();
+	let mut session = Session::new("bob@example.com".into(), Purpose::PasswordReset, 1, 0, TTL);
+	mail(&mailer, &session).await;
+
+	let token = mailer
+		.token("bob@example.com")
+		.expect("token mailed");
+	assert!(session.submit(&token, TTL).is_err(), "an expired token is refused");
+	assert!(session.validated_at.is_none(), "nothing validated");
+}
+
+#[test]
+fn email_token_resent_on_new_attempt() {
+	let session = Session::new("alice@example.com".into(), Purpose::Add, 2, 0, TTL);
+
+	assert!(!session.resend(1, 1), "an older attempt is answered with the session");
+	assert!(!session.resend(2, 1), "a retried attempt is answered with the session");
+	assert!(session.resend(3, 1), "a new attempt mails again");
+	assert!(session.resend(2, TTL), "an expired session mails again");
+
+	let other = Session::new("alice@example.com".into(), Purpose::Add, 2, 0, TTL);
+	assert_ne!(session.token, other.token, "each session has its own token");
+}
+
+#[test]
+fn email_address_normalized() {
+	assert_eq!(
+		normalize_email(" Alice@Example.COM ")
+			.ok()
+			.as_deref(),
+		Some("alice@example.com")
+	);
+	assert!(normalize_email("alice").is_err(), "no domain");
+	assert!(normalize_email("@example.com").is_err(), "no local part");
+	assert!(normalize_email("alice@").is_err(), "empty domain");
+	assert!(normalize_email("a@b@example.com").is_err(), "several separators");
+	assert!(normalize_email("al ice@example.com").is_err(), "whitespace");
+}
+
+/// A fixture mailing into the capture.
+async fn mailing() -> (Fixture, Arc<Capture>) {
+	let fixture = Fixture::start().await;
+	let mailer = Arc::new(Capture::default());
+	fixture.threepid.set_mailer(Some(mailer.clone()));
+
+	(fixture, mailer)
+}
+
+/// Requests a token for the address and submits the one mailed; returns the
+/// ID of the validated session.
+async fn validate(
+	fixture: &Fixture,
+	mailer: &Capture,
+	address: &str,
+	client_secret: &str,
+	purpose: Purpose,
+) -> String {
+	let threepid = &fixture.threepid;
+	let sid = threepid
+		.request_email_token(address, client_secret, uint!(1), purpose)
+		.await
+		.expect("token mailed");
+
+	let token = mailer
+		.token(&address.to_lowercase())
+		.expect("token in the email");
+	threepid
+		.submit_token(client_secret, &sid, &token)
+		.await
+		.expect("token accepted");
+
+	sid
+}
+
+#[tokio::test]
+async fn email_added_to_account() {
+	let (fixture, mailer) = mailing().await;
+	let threepid = &fixture.threepid;
+	let alice = fixture
+		.embedded()
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+
+	let sid = threepid
+		.request_email_token("Alice@Example.com", "secret", uint!(1), Purpose::Add)
+		.await
+		.expect("token mailed");
+	let retried = threepid
+		.request_email_token("alice@example.com", "secret", uint!(1), Purpose::Add)
+		.await
+		.expect("retry answered");
+	assert_eq!(retried, sid, "a retry is answered with the session");
+
+	threepid
+		.add(&alice, "secret", &sid)
+		.await
+		.expect_err("the address is not validated yet");
+	threepid
+		.submit_token("secret", &sid, "wrong")
+		.await
+		.expect_err("a wrong token is refused");
+
+	let token = mailer
+		.token("alice@example.com")
+		.expect("token in the email");
+	threepid
+		.submit_token("secret", &sid, &token)
+		.await
+		.expect("token accepted");
+	threepid
+		.add(&alice, "secret", &sid)
+		.await
+		.expect("address added");
+
+	let addresses: Vec<_> = threepid
+		.threepids(&alice)
+		.map(|threepid| threepid.address)
+		.collect()
+		.await;
+	assert_eq!(addresses, ["alice@example.com"], "the address is of the account");
+	assert_eq!(
+		threepid.find_user("ALICE@example.com").await.ok(),
+		Some(alice.clone()),
+		"the account is found by the address"
+	);
+
+	threepid
+		.add(&alice, "secret", &sid)
+		.await
+		.expect_err("the session is used up");
+
+	let error = threepid
+		.request_email_token("alice@example.com", "other", uint!(1), Purpose::Add)
+		.await
+		.expect_err("the address is taken");
+	assert!(matches!(error.kind(), ErrorKind::ThreepidInUse), "{error:?}");
+
+	fixture.stop().await;
+}
+
+#[tokio::test]
+async fn password_reset_through_email() {
+	let (fixture, mailer) = mailing().await;
+	let threepid = &fixture.threepid;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", Some("password"))
+		.await
+		.expect("alice");
+	let bob = embedded
+		.create_user("bob", Some("password"))
+		.await
+		.expect("bob");
+
+	let sid = validate(&fixture, &mailer, "alice@example.com", "secret", Purpose::Add).await;
+	threepid
+		.add(&alice, "secret", &sid)
+		.await
+		.expect("address added");
+
+	let error = threepid
+		.request_email_token("nobody@example.com", "reset", uint!(1), Purpose::PasswordReset)
+		.await
+		.expect_err("no account has the address");
+	assert!(matches!(error.kind(), ErrorKind::ThreepidNotFound), "{error:?}");
+
+	let sid = threepid
+		.request_email_token("alice@example.com", "reset", uint!(1), Purpose::PasswordReset)
+		.await
+		.expect("token mailed");
+	threepid
+		.authenticate("reset", &sid, None)
+		.await
+		.expect_err("the address is not validated yet");
+
+	let token = mailer
+		.token("alice@example.com")
+		.expect("token in the email");
+	threepid
+		.submit_token("reset", &sid, &token)
+		.await
+		.expect("token accepted");
+
+	// The branch of user-interactive authentication by email
+	threepid
+		.authenticate("reset", &sid, Some(&bob))
+		.await
+		.expect_err("the address is of another user");
+	let user_id = threepid
+		.authenticate("reset", &sid, None)
+		.await
+		.expect("the owner is authenticated without an access token");
+	assert_eq!(user_id, alice, "the owner of the address");
+	threepid
+		.authenticate("reset", &sid, None)
+		.await
+		.expect_err("the session is used up");
+
+	fixture
+		.users
+		.set_password(&user_id, Some("new password"))
+		.await
+		.expect("password reset");
+	let hash = fixture
+		.users
+		.password_hash(&alice)
+		.await
+		.expect("password set");
+	assert!(hash::verify_password("new password", &hash).is_ok(), "the new password is set");
+
+	fixture.stop().await;
+}
@@ -1,26 +1,97 @@
-use std::sync::Arc;
+//! Transaction IDs of requests already handled, so a client retrying a request
+//! gets the original response rather than repeating it. The response to each
+//! transaction of a device is kept in `userdevicetxnid_response`, indexed by
+//! when it was handled in `createdat_userdevicetxnid`; the worker of the
+//! service forgets transactions older than `transaction_id_ttl`.
+//!
+//! Transactions of other servers are kept alike: the results of their PDUs in
+//! `origintxnid_response`, indexed in `createdat_origintxnid`, forgotten after
+//! `federation_transaction_ttl`.
 
-use ruma::{DeviceId, TransactionId, UserId};
-use tuwunel_core::{Result, implement};
+#[cfg(test)]
+mod tests;
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use ruma::{DeviceId, OwnedEventId, OwnedServerName, ServerName, TransactionId, UserId};
+use tokio::time::sleep;
+use tuwunel_core::{
+	Err, Result, debug, err, implement,
+	utils::{self, MutexMap, ReadyExt, result::LogErr, stream::TryIgnore},
+};
 use tuwunel_database::{Handle, Map};
 
+/// Shortest interval between prunings of expired transactions, in seconds.
+const PRUNE_INTERVAL_MIN: u64 = 60;
+
+/// Longest interval between prunings of expired transactions, in seconds.
+const PRUNE_INTERVAL_MAX: u64 = 60 * 60;
+
 pub struct Service {
+	/// Serializes the transactions of each server, so a retry arriving while
+	/// the transaction is still handled waits for its results.
+	pub mutex_federation: MutexMap<OwnedServerName, ()>,
 	db: Data,
+	services: Arc<crate::services::OnceServices>,
 }
 
 struct Data {
+	createdat_origintxnid: Arc<Map>,
+	createdat_userdevicetxnid: Arc<Map>,
+	origintxnid_response: Arc<Map>,
 	userdevicetxnid_response: Arc<Map>,
 }
 
+/// The results of the PDUs of a federation transaction, as returned to the
+/// server which sent it.
+pub type PduResults = BTreeMap<OwnedEventId, Result<(), String>>;
+
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
+			mutex_federation: MutexMap::new(),
 			db: Data {
+				createdat_origintxnid: args.db["createdat_origintxnid"].clone(),
+				createdat_userdevicetxnid: args.db["createdat_userdevicetxnid"].clone(),
+				origintxnid_response: args.db["origintxnid_response"].clone(),
 				userdevicetxnid_response: args.db["userdevicetxnid_response"].clone(),
 			},
+			services: args.services.clone(),
 		}))
 	}
 
+	async fn worker(self: Arc<Self>) -> Result {
+		let config = &self.services.server.config;
+		let ttl = [config.transaction_id_ttl, config.federation_transaction_ttl]
+			.into_iter()
+			.filter(|&ttl| ttl > 0)
+			.min();
+
+		let Some(ttl) = ttl else {
+			return Ok(());
+		};
+
+		if self.services.db.is_read_only() {
+			return Ok(());
+		}
+
+		let interval = ttl.clamp(PRUNE_INTERVAL_MIN, PRUNE_INTERVAL_MAX);
+		while self.services.server.running() {
+			tokio::select! {
+				() = sleep(Duration::from_secs(interval)) => {
+					self.prune_txnids().await;
+					self.prune_federation_txns().await;
+				},
+				() = self.services.server.until_shutdown() => break,
+			}
+		}
+
+		Ok(())
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -32,15 +103,13 @@ pub fn add_txnid(
 	txn_id: &TransactionId,
 	data: &[u8],
 ) {
-	let mut key = user_id.as_bytes().to_vec();
-	key.push(0xFF);
-	key.extend_from_slice(
-		device_id
-			.map(DeviceId::as_bytes)
-			.unwrap_or_default(),
-	);
-	key.push(0xFF);
-	key.extend_from_slice(txn_id.as_bytes());
+	let key = txnid_key(user_id, device_id, txn_id);
+	let created_at = utils::millis_since_unix_epoch();
+
+	let _cork = self.services.db.cork();
+	self.db
+		.createdat_userdevicetxnid
+		.insert(&created_key(created_at, &key), []);
 
 	self.db
 		.userdevicetxnid_response
@@ -55,6 +124,196 @@ pub async fn existing_txnid(
 	device_id: Option<&DeviceId>,
 	txn_id: &TransactionId,
 ) -> Result<Handle<'_>> {
-	let key = (user_id, device_id, txn_id);
-	self.db.userdevicetxnid_response.qry(&key).await
+	let key = txnid_key(user_id, device_id, txn_id);
+	let response = self.db.userdevicetxnid_response.get(&key).await?;
+
+	self.services.metrics.inc(
+		"tuwunel_transaction_id_replays_total",
+		"Requests answered from a transaction already handled.",
+		&[],
+	);
+
+	Ok(response)
+}
+
+/// The event sent by the transaction, if it was handled already.
+#[implement(Service)]
+pub async fn existing_event_id(
+	&self,
+	user_id: &UserId,
+	device_id: Option<&DeviceId>,
+	txn_id: &TransactionId,
+) -> Result<Option<OwnedEventId>> {
+	let Ok(response) = self
+		.existing_txnid(user_id, device_id, txn_id)
+		.await
+	else {
+		return Ok(None);
+	};
+
+	// The client might have sent a txnid of the /sendToDevice endpoint
+	// This txnid has no response associated with it
+	if response.is_empty() {
+		return Err!(Request(InvalidParam(
+			"Tried to use txn id already used for an incompatible endpoint."
+		)));
+	}
+
+	utils::string_from_bytes(&response)
+		.map_err(|e| err!(Database("Invalid event_id in txnid data: {e:?}")))?
+		.try_into()
+		.map(Some)
+		.map_err(|e| err!(Database("Invalid event_id in txnid data: {e:?}")))
+}
+
+/// Notes the results of the PDUs of the transaction of the server, which are
+/// returned again when the server retries it.
+#[implement(Service)]
+pub fn add_federation_txn(
+	&self,
+	origin: &ServerName,
+	txn_id: &TransactionId,
+	results: &PduResults,
+) -> Result {
+	let key = origin_txnid_key(origin, txn_id);
+	let created_at = utils::millis_since_unix_epoch();
+	let results = serde_json::to_vec(results)?;
+
+	let _cork = self.services.db.cork();
+	self.db
+		.createdat_origintxnid
+		.insert(&created_key(created_at, &key), []);
+
+	self.db
+		.origintxnid_response
+		.insert(&key, &results);
+
+	Ok(())
+}
+
+/// The results of the PDUs of the transaction of the server, if it was
+/// handled already.
+#[implement(Service)]
+pub async fn existing_federation_txn(
+	&self,
+	origin: &ServerName,
+	txn_id: &TransactionId,
+) -> Option<PduResults> {
+	let key = origin_txnid_key(origin, txn_id);
+	let response = self
+		.db
+		.origintxnid_response
+		.get(&key)
+		.await
+		.ok()?;
+
+	let results = serde_json::from_slice(&response)
+		.map_err(|e| err!(Database("Invalid results of federation transaction: {e}")))
+		.log_err()
+		.ok()?;
+
+	self.services.metrics.inc(
+		"tuwunel_federation_transaction_replays_total",
+		"Federation transactions answered from one already handled.",
+		&[],
+	);
+
+	Some(results)
+}
+
+/// Forgets the transactions handled longer than `transaction_id_ttl` ago;
+/// returns the number forgotten.
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn prune_txnids(&self) -> usize {
+	let ttl = self.services.server.config.transaction_id_ttl;
+	let count = self
+		.prune(&self.db.createdat_userdevicetxnid, &self.db.userdevicetxnid_response, ttl)
+		.await;
+
+	debug!(count, "Pruned expired transaction IDs");
+	count
+}
+
+/// Forgets the federation transactions handled longer than
+/// `federation_transaction_ttl` ago; returns the number forgotten.
+#[implement(Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn prune_federation_txns(&self) -> usize {
+	let ttl = self
+		.services
+		.server
+		.config
+		.federation_transaction_ttl;
+
+	let count = self
+		.prune(&self.db.createdat_origintxnid, &self.db.origintxnid_response, ttl)
+		.await;
+
+	debug!(count, "Pruned expired federation transactions");
+	count
+}
+
+/// Forgets the entries indexed as handled longer than the ttl ago, in
+/// seconds; none when it is 0.
+#[implement(Service)]
+async fn prune(&self, index: &Map, responses: &Map, ttl: u64) -> usize {
+	if ttl == 0 {
+		return 0;
+	}
+
+	let cutoff = utils::millis_since_unix_epoch().saturating_sub(ttl.saturating_mul(1000));
+	let expired: Vec<Vec<u8>> = index
+		.raw_keys()
+		.ignore_err()
+		.ready_take_while(|key| parse_created_key(key).is_some_and(|(at, _)| at < cutoff))
+		.map(<[u8]>::to_vec)
+		.collect()
+		.await;
+
+	let _cork = self.services.db.cork();
+	for key in &expired {
+		if let Some((_, response_key)) = parse_created_key(key) {
+			responses.remove(response_key);
+		}
+
+		index.remove(key);
+	}
+
+	expired.len()
+}
+
+/// Key of a transaction of a device, or of a user without one.
+fn txnid_key(user_id: &UserId, device_id: Option<&DeviceId>, txn_id: &TransactionId) -> Vec<u8> {
+	let mut key = user_id.as_bytes().to_vec();
+	key.push(0xFF);
+	key.extend_from_slice(
+		device_id
+			.map(DeviceId::as_bytes)
+			.unwrap_or_default(),
+	);
+	key.push(0xFF);
+	key.extend_from_slice(txn_id.as_bytes());
+	key
+}
+
+/// Key of a transaction of a server. Server names hold no 0xFF, so the
+/// transactions of different servers are apart.
+fn origin_txnid_key(origin: &ServerName, txn_id: &TransactionId) -> Vec<u8> {
+	let mut key = origin.as_bytes().to_vec();
+	key.push(0xFF);
+	key.extend_from_slice(txn_id.as_bytes());
+	key
+}
+
+/// Key of the index, ordering transactions by when they were handled.
+fn created_key(created_at: u64, txnid_key: &[u8]) -> Vec<u8> {
+	let mut key = created_at.to_be_bytes().to_vec();
+	key.extend_from_slice(txnid_key);
+	key
+}
+
+fn parse_created_key(key: &[u8]) -> Option<(u64, &[u8])> {
+	let (created_at, txnid_key) = key.split_first_chunk()?;
+	Some((u64::from_be_bytes(*created_at), txnid_key))
 }
@@ -0,0 +1,202 @@
+use ruma::{TransactionId, device_id, server_name, user_id};
+
+use super::{created_key, origin_txnid_key, parse_created_key, txnid_key};
+
+#[test]
+fn txnid_keys_are_scoped() {
+	let user = user_id!("@alice:example.com");
+	let txn_id = &TransactionId::new();
+
+	let phone = txnid_key(user, Some(device_id!("PHONE")), txn_id);
+	let laptop = txnid_key(user, Some(device_id!("LAPTOP")), txn_id);
+	let deviceless = txnid_key(user, None, txn_id);
+	let other = txnid_key(user_id!("@bob:example.com"), Some(device_id!("PHONE")), txn_id);
+
+	assert_ne!(phone, laptop, "transactions are scoped by device");
+	assert_ne!(phone, deviceless, "and apart from those without a device");
+	assert_ne!(phone, other, "and by user");
+	assert_eq!(
+		phone,
+		txnid_key(user, Some(device_id!("PHONE")), txn_id),
+		"a retry finds the same key"
+	);
+}
+
+#[test]
+fn created_keys_order_by_time() {
+	let key = txnid_key(user_id!("@alice:example.com"), None, &TransactionId::new());
+	let older = created_key(1_000, &key);
+	let newer = created_key(2_000, b"@aaa:example.com\xFF\xFFtxn");
+
+	assert!(older < newer, "the index is ordered by time before anything else");
+	assert_eq!(parse_created_key(&older), Some((1_000, key.as_slice())), "round trip");
+	assert_eq!(parse_created_key(b"short"), None, "a truncated key is skipped");
+}
+
+#[test]
+fn origin_txnid_keys_are_scoped() {
+	let txn_id: &TransactionId = "1234".into();
+
+	let key = origin_txnid_key(server_name!("example.com"), txn_id);
+	let other = origin_txnid_key(server_name!("example.org"), txn_id);
+	let prefixed = origin_txnid_key(server_name!("example.co"), "m1234".into());
+
+	assert_ne!(key, other, "transactions are scoped by server");
+	assert_ne!(key, prefixed, "a server name is not the prefix of another");
+	assert_eq!(
+		key,
+		origin_txnid_key(server_name!("example.com"), txn_id),
+		"a retry finds the same key"
+	);
+}
+
+/// A client retrying a send after the server restarted gets the event sent
+/// by its first attempt, as the send route does, rather than a second one.
+#[tokio::test]
+async fn retry_after_restart_replayed() {
+	use futures::StreamExt;
+	use ruma::{DeviceId, OwnedEventId, RoomId, UserId, events::TimelineEventType};
+	use tuwunel_core::utils::{ReadyExt, stream::TryIgnore};
+
+	use crate::{Services, embed::Embedded, fixture::Fixture};
+
+	async fn send(
+		embedded: &Embedded,
+		user_id: &UserId,
+		device_id: &DeviceId,
+		room_id: &RoomId,
+		txn_id: &TransactionId,
+	) -> OwnedEventId {
+		let services = &embedded.services;
+		if let Some(event_id) = services
+			.transaction_ids
+			.existing_event_id(user_id, Some(device_id), txn_id)
+			.await
+			.expect("transaction ID usable")
+		{
+			return event_id;
+		}
+
+		let event_id = embedded
+			.send_message(user_id, room_id, "hello")
+			.await
+			.expect("message sent");
+
+		services
+			.transaction_ids
+			.add_txnid(user_id, Some(device_id), txn_id, event_id.as_bytes());
+
+		event_id
+	}
+
+	async fn messages(services: &Services, room_id: &RoomId) -> usize {
+		services
+			.timeline
+			.pdus(None, room_id, None)
+			.ignore_err()
+			.ready_filter(|(_, pdu)| pdu.kind == TimelineEventType::RoomMessage)
+			.count()
+			.await
+	}
+
+	let fixture = Fixture::start().await;
+	let embedded = fixture.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+
+	let txn_id = TransactionId::new();
+	let sent = send(&embedded, &alice, device_id!("PHONE"), &room_id, &txn_id).await;
+
+	drop(embedded);
+	let fixture = fixture.restart().await;
+	let embedded = fixture.embedded();
+
+	let retried = send(&embedded, &alice, device_id!("PHONE"), &room_id, &txn_id).await;
+	assert_eq!(retried, sent, "the retry is answered with the original event");
+	assert_eq!(messages(&fixture, &room_id).await, 1, "and sends nothing more");
+
+	let other = send(&embedded, &alice, device_id!("LAPTOP"), &room_id, &txn_id).await;
+	assert_ne!(other, sent, "the same transaction ID of another device is new");
+	assert_eq!(messages(&fixture, &room_id).await, 2, "and is sent");
+
+	drop(embedded);
+	fixture.stop().await;
+}
+
+/// The results of a transaction are kept per server, so another server using
+/// the same transaction ID is handled anew, and survive a restart, so a
+/// server retrying afterwards gets the results of its first attempt.
+#[tokio::test]
+async fn federation_txn_kept_per_origin_across_restart() {
+	use ruma::event_id;
+
+	use super::PduResults;
+	use crate::fixture::Fixture;
+
+	let txn_id: &TransactionId = "1234".into();
+	let remote = server_name!("remote.example.com");
+	let other = server_name!("other.example.com");
+
+	let accepted: PduResults = [(event_id!("$accepted").to_owned(), Ok(()))].into();
+	let rejected: PduResults =
+		[(event_id!("$rejected").to_owned(), Err("auth check failed".to_owned()))].into();
+
+	let fixture = Fixture::start().await;
+	let transaction_ids = &fixture.transaction_ids;
+	assert_eq!(
+		transaction_ids
+			.existing_federation_txn(remote, txn_id)
+			.await,
+		None,
+		"a new transaction is handled"
+	);
+
+	transaction_ids
+		.add_federation_txn(remote, txn_id, &accepted)
+		.expect("results stored");
+
+	assert_eq!(
+		transaction_ids
+			.existing_federation_txn(other, txn_id)
+			.await,
+		None,
+		"the same transaction ID of another server is new"
+	);
+
+	transaction_ids
+		.add_federation_txn(other, txn_id, &rejected)
+		.expect("results stored");
+
+	let fixture = fixture.restart().await;
+	let transaction_ids = &fixture.transaction_ids;
+	assert_eq!(
+		transaction_ids
+			.existing_federation_txn(remote, txn_id)
+			.await,
+		Some(accepted),
+		"the retry after a restart gets the original results"
+	);
+	assert_eq!(
+		transaction_ids
+			.existing_federation_txn(other, txn_id)
+			.await,
+		Some(rejected),
+		"and each server its own"
+	);
+	assert_eq!(
+		transaction_ids
+			.existing_federation_txn(remote, "5678".into())
+			.await,
+		None,
+		"another transaction of the server is new"
+	);
+
+	fixture.stop().await;
+}
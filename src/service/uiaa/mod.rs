@@ -1,3 +1,6 @@
+#[cfg(test)]
+mod tests;
+
 use std::{
 	collections::{BTreeMap, HashSet},
 	sync::{Arc, RwLock},
@@ -23,6 +26,7 @@ pub struct Service {
 }
 
 struct Data {
+	sessionid_userdevice: Arc<Map>,
 	userdevicesessionid_uiaainfo: Arc<Map>,
 }
 
@@ -36,6 +40,7 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 		Ok(Arc::new(Self {
 			userdevicesessionid_uiaarequest: RwLock::new(RequestMap::new()),
 			db: Data {
+				sessionid_userdevice: args.db["sessionid_userdevice"].clone(),
 				userdevicesessionid_uiaainfo: args.db["userdevicesessionid_uiaainfo"].clone(),
 			},
 			services: args.services.clone(),
@@ -205,6 +210,102 @@ pub async fn try_auth(
 	Ok((true, uiaainfo))
 }
 
+/// Completes a stage of the session from its fallback web page, where only
+/// the ID of the session is known. The session is kept for the client to
+/// finish its flow with; errors when the stage failed.
+#[implement(Service)]
+pub async fn fallback_auth(&self, session: &str, auth: &AuthData) -> Result {
+	let (user_id, device_id, mut uiaainfo) = self.find_uiaa_session(session).await?;
+
+	let stage = match auth {
+		| AuthData::Password(Password { password, .. }) => {
+			let verified = self
+				.services
+				.users
+				.password_hash(&user_id)
+				.await
+				.is_ok_and(|hash| hash::verify_password(password, &hash).is_ok());
+
+			if !verified {
+				return Err!(Request(Forbidden("Invalid password.")));
+			}
+
+			AuthType::Password
+		},
+		| AuthData::RegistrationToken(t) => {
+			if !self.read_tokens().await?.contains(t.token.trim()) {
+				return Err!(Request(Forbidden("Invalid registration token.")));
+			}
+
+			AuthType::RegistrationToken
+		},
+		| _ => return Err!(Request(Unrecognized("Stage has no fallback."))),
+	};
+
+	self.complete_stage(&user_id, &device_id, session, &mut uiaainfo, stage)
+}
+
+/// Completes the `m.login.sso` stage of the session, for the user who logged
+/// in at the provider; errors when the session is of another user.
+#[implement(Service)]
+pub async fn sso_auth(&self, session: &str, user_id: &UserId) -> Result {
+	let (session_user, device_id, mut uiaainfo) = self.find_uiaa_session(session).await?;
+
+	if &*session_user != user_id {
+		return Err!(Request(Forbidden("The session is of another user.")));
+	}
+
+	self.complete_stage(&session_user, &device_id, session, &mut uiaainfo, AuthType::Sso)
+}
+
+#[implement(Service)]
+fn complete_stage(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	session: &str,
+	uiaainfo: &mut UiaaInfo,
+	stage: AuthType,
+) -> Result {
+	if !uiaainfo
+		.flows
+		.iter()
+		.any(|flow| flow.stages.contains(&stage))
+	{
+		return Err!(Request(Forbidden("Stage is not part of the session.")));
+	}
+
+	if !uiaainfo.completed.contains(&stage) {
+		uiaainfo.completed.push(stage);
+	}
+
+	uiaainfo.auth_error = None;
+	self.update_uiaa_session(user_id, device_id, session, Some(uiaainfo));
+
+	Ok(())
+}
+
+/// Finds the session by its ID alone.
+#[implement(Service)]
+pub async fn find_uiaa_session(
+	&self,
+	session: &str,
+) -> Result<(OwnedUserId, OwnedDeviceId, UiaaInfo)> {
+	let (user_id, device_id): (OwnedUserId, OwnedDeviceId) = self
+		.db
+		.sessionid_userdevice
+		.qry(session)
+		.await
+		.deserialized()
+		.map_err(|_| err!(Request(Forbidden("UIAA session does not exist."))))?;
+
+	let uiaainfo = self
+		.get_uiaa_session(&user_id, &device_id, session)
+		.await?;
+
+	Ok((user_id, device_id, uiaainfo))
+}
+
 #[implement(Service)]
 fn set_uiaa_request(
 	&self,
@@ -252,8 +353,12 @@ fn update_uiaa_session(
 		self.db
 			.userdevicesessionid_uiaainfo
 			.put(key, Json(uiaainfo));
+		self.db
+			.sessionid_userdevice
+			.put(session, (user_id, device_id));
 	} else {
 		self.db.userdevicesessionid_uiaainfo.del(key);
+		self.db.sessionid_userdevice.remove(session);
 	}
 }
 
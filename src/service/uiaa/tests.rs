@@ -0,0 +1,74 @@
+use ruma::{
+	CanonicalJsonValue,
+	api::client::uiaa::{
+		AuthData, AuthFlow, AuthType, FallbackAcknowledgement, RegistrationToken, UiaaInfo,
+	},
+	device_id,
+};
+
+use crate::fixture::Fixture;
+
+const SESSION: &str = "fallbacksession";
+
+#[tokio::test]
+async fn fallback_completes_stage() {
+	let services = Fixture::start_with(r#"registration_token = "secret""#).await;
+	let alice = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let device_id = device_id!("DEVICE");
+	let uiaainfo = UiaaInfo {
+		flows: vec![AuthFlow {
+			stages: vec![AuthType::RegistrationToken],
+		}],
+		session: Some(SESSION.to_owned()),
+		..Default::default()
+	};
+	let body = CanonicalJsonValue::Object(Default::default());
+	services
+		.uiaa
+		.create(&alice, device_id, &uiaainfo, &body);
+
+	let wrong = AuthData::RegistrationToken(RegistrationToken::new("wrong".to_owned()));
+	services
+		.uiaa
+		.fallback_auth(SESSION, &wrong)
+		.await
+		.expect_err("a wrong token is refused, for the form to be shown again");
+
+	let (_, _, pending) = services
+		.uiaa
+		.find_uiaa_session(SESSION)
+		.await
+		.expect("session kept after a failed attempt");
+	assert!(pending.completed.is_empty(), "the stage is not completed by a wrong token");
+
+	let right = AuthData::RegistrationToken(RegistrationToken::new("secret".to_owned()));
+	services
+		.uiaa
+		.fallback_auth(SESSION, &right)
+		.await
+		.expect("the token completes the stage");
+
+	let (user_id, _, completed) = services
+		.uiaa
+		.find_uiaa_session(SESSION)
+		.await
+		.expect("session kept for the client to finish with");
+	assert_eq!(user_id, alice, "the session stays with its user");
+	assert_eq!(completed.completed, [AuthType::RegistrationToken], "the stage is completed");
+
+	let acknowledgement =
+		AuthData::FallbackAcknowledgement(FallbackAcknowledgement::new(SESSION.to_owned()));
+	let (done, _) = services
+		.uiaa
+		.try_auth(&alice, device_id, &acknowledgement, &uiaainfo)
+		.await
+		.expect("flow finished");
+	assert!(done, "the client finishes its flow with the session");
+
+	services.stop().await;
+}
@@ -5,14 +5,17 @@
 
 use futures::{FutureExt, Stream, StreamExt, future::join};
 use ruma::{
-	DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedUserId, UserId,
+	DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedUserId, UInt, UserId,
 	api::client::device::Device, events::AnyToDeviceEvent, serde::Raw,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tuwunel_core::{
-	Err, Result, at, implement,
+	Err, Result, at, debug, implement,
 	utils::{
 		self, ReadyExt,
+		hash::sha256,
+		result::LogErr,
 		stream::{IterStream, TryIgnore},
 		time::{duration_since_epoch, timepoint_from_epoch, timepoint_from_now},
 	},
@@ -22,6 +25,38 @@
 /// generated user access token length
 pub const TOKEN_LENGTH: usize = 32;
 
+/// Least time between the updates of when a device was last seen.
+pub const LAST_SEEN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Shortest inactivity for which devices are pruned. It is much longer than
+/// `LAST_SEEN_INTERVAL`, so a device in use is never pruned.
+pub const PRUNE_INACTIVE_MIN: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Key under `global` of when devices started being noted as seen. Devices
+/// are taken as seen then at the latest, so those which made no request since
+/// are only pruned once they were inactive for the whole duration after it.
+pub const DEVICES_SEEN_SINCE: &[u8] = b"devices_seen_since";
+
+/// When and from where a device last made a request, kept in
+/// `userdeviceid_lastseen` apart from its metadata so noting it on requests
+/// never overwrites a concurrent change of the device.
+#[derive(Debug, Deserialize, Serialize)]
+struct LastSeen {
+	ts: MilliSecondsSinceUnixEpoch,
+	ip: Option<String>,
+}
+
+/// Access and refresh tokens are only stored as their hash, so a leaked
+/// database cannot be used to hijack sessions.
+#[inline]
+#[must_use]
+pub fn token_hash<T>(token: T) -> sha256::Digest
+where
+	T: AsRef<[u8]>,
+{
+	sha256::hash(token)
+}
+
 /// Adds a new device to a user.
 #[implement(super::Service)]
 #[tracing::instrument(level = "debug", skip(self))]
@@ -41,15 +76,19 @@ pub async fn create_device(
 	}
 
 	let key = (user_id, device_id);
+	let now = MilliSecondsSinceUnixEpoch::now();
 	let val = Device {
 		device_id: device_id.into(),
 		display_name: initial_device_display_name,
-		last_seen_ip: client_ip,
-		last_seen_ts: Some(MilliSecondsSinceUnixEpoch::now()),
+		last_seen_ip: client_ip.clone(),
+		last_seen_ts: Some(now),
 	};
 
 	increment(&self.db.userid_devicelistversion, user_id.as_bytes());
 	self.db.userdeviceid_metadata.put(key, Json(val));
+	self.db
+		.userdeviceid_lastseen
+		.put(key, Json(LastSeen { ts: now, ip: client_ip }));
 	self.set_access_token(user_id, device_id, access_token, expires_in, refresh_token)
 		.await
 }
@@ -85,15 +124,177 @@ pub async fn remove_device(&self, user_id: &UserId, device_id: &DeviceId) {
 		})
 		.await;
 
-	// TODO: Remove onetimekeys
+	// Remove onetimekeys
+	self.db
+		.onetimekeyid_onetimekeys
+		.keys_prefix_raw(&prefix)
+		.ignore_err()
+		.ready_for_each(|key| self.db.onetimekeyid_onetimekeys.remove(key))
+		.await;
+
+	// Remove fallback keys
+	self.db
+		.userdevicealgorithm_fallbackkey
+		.keys_prefix_raw(&prefix)
+		.ignore_err()
+		.ready_for_each(|key| {
+			self.db
+				.userdevicealgorithm_fallbackkey
+				.remove(key);
+		})
+		.await;
 
 	increment(&self.db.userid_devicelistversion, user_id.as_bytes());
 
 	let userdeviceid = (user_id, device_id);
 	self.db.userdeviceid_metadata.del(userdeviceid);
+	self.db.userdeviceid_lastseen.del(userdeviceid);
 	self.mark_device_key_update(user_id).await;
 }
 
+/// Removes the devices of the user not seen for the duration, taking those
+/// unseen since devices started being noted as seen then; see
+/// [`DEVICES_SEEN_SINCE`]. Returns the IDs of the removed devices.
+#[implement(super::Service)]
+#[tracing::instrument(level = "debug", skip(self))]
+pub async fn prune_inactive_devices(
+	&self,
+	user_id: &UserId,
+	inactive_for: Duration,
+) -> Result<Vec<OwnedDeviceId>> {
+	if inactive_for < PRUNE_INACTIVE_MIN {
+		return Err!("Devices can't be pruned for being inactive less than a day.");
+	}
+
+	let now = MilliSecondsSinceUnixEpoch::now();
+	let since = self.services.db["global"]
+		.get(DEVICES_SEEN_SINCE)
+		.await
+		.deserialized::<u64>()
+		.ok()
+		.and_then(UInt::new)
+		.map_or(now, MilliSecondsSinceUnixEpoch);
+
+	let inactive: Vec<_> = self
+		.all_devices_metadata(user_id)
+		.ready_filter(|device| is_inactive(device.last_seen_ts, since, now, inactive_for))
+		.map(|device| device.device_id)
+		.collect()
+		.await;
+
+	for device_id in &inactive {
+		self.remove_device(user_id, device_id).await;
+	}
+
+	Ok(inactive)
+}
+
+/// Removes the devices of every local user not seen for the duration;
+/// returns the number removed.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn prune_all_inactive_devices(&self, inactive_for: Duration) -> usize {
+	let users: Vec<OwnedUserId> = self
+		.list_local_users()
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	let mut pruned = 0_usize;
+	for user_id in &users {
+		let devices = self
+			.prune_inactive_devices(user_id, inactive_for)
+			.await
+			.log_err()
+			.unwrap_or_default();
+
+		pruned = pruned.saturating_add(devices.len());
+	}
+
+	debug!(pruned, "Pruned inactive devices");
+	pruned
+}
+
+/// Notes the device was seen making a request now, from the address. When it
+/// was last seen is written at most once every `LAST_SEEN_INTERVAL`.
+#[implement(super::Service)]
+pub async fn update_device_last_seen(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	client_ip: Option<String>,
+) {
+	let key = (user_id, device_id);
+	let now = MilliSecondsSinceUnixEpoch::now();
+	let last_seen = self
+		.db
+		.userdeviceid_lastseen
+		.qry(&key)
+		.await
+		.deserialized::<LastSeen>();
+
+	if last_seen
+		.as_ref()
+		.is_ok_and(|last_seen| seen_within(last_seen.ts, now, LAST_SEEN_INTERVAL))
+	{
+		return;
+	}
+
+	if !self.db.userdeviceid_metadata.contains(&key).await {
+		return;
+	}
+
+	let ip = client_ip.or_else(|| last_seen.ok().and_then(|last_seen| last_seen.ip));
+
+	// Not a change of the device list; other users are not told.
+	self.db
+		.userdeviceid_lastseen
+		.put(key, Json(LastSeen { ts: now, ip }));
+}
+
+/// The device with when and from where it was last seen.
+#[implement(super::Service)]
+async fn with_last_seen(&self, user_id: &UserId, mut device: Device) -> Device {
+	if let Ok(last_seen) = self
+		.db
+		.userdeviceid_lastseen
+		.qry(&(user_id, &device.device_id))
+		.await
+		.deserialized::<LastSeen>()
+	{
+		device.last_seen_ts = Some(last_seen.ts);
+		if last_seen.ip.is_some() {
+			device.last_seen_ip = last_seen.ip;
+		}
+	}
+
+	device
+}
+
+/// Whether the device, last seen then if ever, was not seen for the duration;
+/// it is taken as seen `since` at the latest.
+pub(super) fn is_inactive(
+	last_seen: Option<MilliSecondsSinceUnixEpoch>,
+	since: MilliSecondsSinceUnixEpoch,
+	now: MilliSecondsSinceUnixEpoch,
+	inactive_for: Duration,
+) -> bool {
+	let last_seen = last_seen.map_or(since, |last_seen| last_seen.max(since));
+
+	!seen_within(last_seen, now, inactive_for)
+}
+
+/// Whether the time last seen is less than the duration before now.
+pub(super) fn seen_within(
+	last_seen: MilliSecondsSinceUnixEpoch,
+	now: MilliSecondsSinceUnixEpoch,
+	duration: Duration,
+) -> bool {
+	let elapsed = u64::from(now.get()).saturating_sub(last_seen.get().into());
+
+	u128::from(elapsed) < duration.as_millis()
+}
+
 /// Returns an iterator over all device ids of this user.
 #[implement(super::Service)]
 pub fn all_device_ids<'a>(
@@ -115,9 +316,10 @@ pub async fn find_from_token(
 	&self,
 	token: &str,
 ) -> Result<(OwnedUserId, OwnedDeviceId, Option<SystemTime>)> {
+	let token_hash = token_hash(token);
 	self.db
 		.token_userdeviceid
-		.get(token)
+		.get(&token_hash)
 		.await
 		.deserialized()
 		.and_then(|(user_id, device_id, expires_at): (_, _, Option<u64>)| {
@@ -172,6 +374,7 @@ pub async fn set_access_token(
 		.as_ref()
 		.map(Duration::as_secs);
 
+	let access_token = token_hash(access_token);
 	let userdeviceid = (user_id, device_id);
 	let value = (user_id, device_id, expires_at);
 	self.db
@@ -201,16 +404,6 @@ pub async fn remove_access_token(&self, user_id: &UserId, device_id: &DeviceId)
 	Ok(())
 }
 
-#[implement(super::Service)]
-pub async fn get_access_token(&self, user_id: &UserId, device_id: &DeviceId) -> Result<String> {
-	let key = (user_id, device_id);
-	self.db
-		.userdeviceid_token
-		.qry(&key)
-		.await
-		.deserialized()
-}
-
 #[implement(super::Service)]
 pub fn generate_access_token(&self, expires: bool) -> (String, Option<Duration>) {
 	let access_token = utils::random_string(TOKEN_LENGTH);
@@ -237,6 +430,7 @@ pub async fn set_refresh_token(
 		.await
 		.ok();
 
+	let refresh_token = token_hash(refresh_token);
 	let userdeviceid = (user_id, device_id);
 	self.db
 		.token_userdeviceid
@@ -265,16 +459,6 @@ pub async fn remove_refresh_token(&self, user_id: &UserId, device_id: &DeviceId)
 	Ok(())
 }
 
-#[implement(super::Service)]
-pub async fn get_refresh_token(&self, user_id: &UserId, device_id: &DeviceId) -> Result<String> {
-	let key = (user_id, device_id);
-	self.db
-		.userdeviceid_refresh
-		.qry(&key)
-		.await
-		.deserialized()
-}
-
 #[must_use]
 pub fn generate_refresh_token() -> String {
 	format!("refresh_{}", utils::random_string(TOKEN_LENGTH))
@@ -289,17 +473,31 @@ pub async fn add_to_device_event(
 	event_type: &str,
 	content: serde_json::Value,
 ) {
-	let count = self.services.globals.next_count();
-
-	let key = (target_user_id, target_device_id, *count);
-	self.db.todeviceid_events.put(
-		key,
-		Json(json!({
-			"type": event_type,
-			"sender": sender,
-			"content": content,
-		})),
-	);
+	let target = (target_user_id.to_owned(), target_device_id.to_owned(), content);
+	self.add_to_device_events(sender, event_type, [target]);
+}
+
+/// Queues the to-device event of the sender for each of the devices, written
+/// together under one cork.
+#[implement(super::Service)]
+pub fn add_to_device_events<I>(&self, sender: &UserId, event_type: &str, events: I)
+where
+	I: IntoIterator<Item = (OwnedUserId, OwnedDeviceId, serde_json::Value)>,
+{
+	let _cork = self.services.db.cork();
+	for (target_user_id, target_device_id, content) in events {
+		let count = self.services.globals.next_count();
+
+		let key = (&target_user_id, &target_device_id, *count);
+		self.db.todeviceid_events.put(
+			key,
+			Json(json!({
+				"type": event_type,
+				"sender": sender,
+				"content": content,
+			})),
+		);
+	}
 }
 
 #[implement(super::Service)]
@@ -374,11 +572,14 @@ pub async fn get_device_metadata(
 	user_id: &UserId,
 	device_id: &DeviceId,
 ) -> Result<Device> {
-	self.db
+	let device = self
+		.db
 		.userdeviceid_metadata
 		.qry(&(user_id, device_id))
 		.await
-		.deserialized()
+		.deserialized()?;
+
+	Ok(self.with_last_seen(user_id, device).await)
 }
 
 #[implement(super::Service)]
@@ -401,6 +602,7 @@ pub fn all_devices_metadata<'a>(
 		.stream_prefix(&key)
 		.ignore_err()
 		.map(|(_, val): (Ignore, Device)| val)
+		.then(move |device| self.with_last_seen(user_id, device))
 }
 
 //TODO: this is an ABA
@@ -2,17 +2,19 @@
 
 use futures::{Stream, StreamExt, TryFutureExt};
 use ruma::{
-	DeviceId, KeyId, OneTimeKeyAlgorithm, OneTimeKeyId, OneTimeKeyName, OwnedKeyId, RoomId, UInt,
-	UserId,
+	DeviceId, KeyId, OneTimeKeyAlgorithm, OneTimeKeyId, OneTimeKeyName, OwnedDeviceId,
+	OwnedKeyId, OwnedOneTimeKeyId, RoomId, UInt, UserId,
 	api::client::error::ErrorKind,
 	encryption::{CrossSigningKey, DeviceKeys, OneTimeKey},
 	serde::Raw,
 };
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use tuwunel_core::{
 	Err, Error, Result, err, implement,
-	utils::{ReadyExt, stream::TryIgnore, string::Unquoted},
+	utils::{self, MutexMapGuard, ReadyExt, stream::TryIgnore, string::Unquoted},
 };
-use tuwunel_database::{Deserialized, Ignore, Json};
+use tuwunel_database::{Deserialized, Ignore, Interfix, Json};
 
 #[implement(super::Service)]
 pub async fn add_one_time_key(
@@ -124,6 +126,150 @@ pub async fn take_one_time_key(
 	one_time_key.ok_or_else(|| err!(Request(NotFound("No one-time-key found"))))
 }
 
+/// A one-time key claimed for a device.
+pub type ClaimedKey = (OwnedOneTimeKeyId, Raw<OneTimeKey>);
+
+/// The fallback key of a device for one algorithm, handed out when its
+/// one-time keys ran out. It stays until the device uploads another, and is
+/// marked used once claimed so the device knows to replace it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct FallbackKey {
+	pub(super) key_id: OwnedOneTimeKeyId,
+	pub(super) key: Raw<OneTimeKey>,
+	pub(super) used: bool,
+}
+
+/// Claims a one-time key of the device for the algorithm, or its fallback
+/// key when none remain.
+#[implement(super::Service)]
+pub async fn claim_one_time_key(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	key_algorithm: &OneTimeKeyAlgorithm,
+) -> Result<ClaimedKey> {
+	let _lock = self.fallback_key_lock(user_id, device_id).await;
+	let one_time_key = self
+		.take_one_time_key(user_id, device_id, key_algorithm)
+		.await
+		.ok();
+
+	let key = (user_id, device_id, key_algorithm.as_str());
+	let mut fallback_key: Option<FallbackKey> = match one_time_key {
+		| Some(_) => None,
+		| None => self
+			.db
+			.userdevicealgorithm_fallbackkey
+			.qry(&key)
+			.await
+			.deserialized()
+			.ok(),
+	};
+
+	let was_used = fallback_key
+		.as_ref()
+		.is_none_or(|fallback_key| fallback_key.used);
+
+	let claimed = claim_key(one_time_key, fallback_key.as_mut());
+	if let Some(fallback_key) = fallback_key.filter(|_| !was_used) {
+		self.db
+			.userdevicealgorithm_fallbackkey
+			.put(key, Json(fallback_key));
+	}
+
+	claimed.ok_or_else(|| err!(Request(NotFound("No one-time-key found"))))
+}
+
+/// The one-time key when one remains, else the fallback key, marked used but
+/// kept to be claimed again.
+pub(super) fn claim_key(
+	one_time_key: Option<ClaimedKey>,
+	fallback_key: Option<&mut FallbackKey>,
+) -> Option<ClaimedKey> {
+	one_time_key.or_else(|| {
+		let fallback_key = fallback_key?;
+		fallback_key.used = true;
+
+		Some((fallback_key.key_id.clone(), fallback_key.key.clone()))
+	})
+}
+
+/// Stores the fallback key of the device, replacing the one it had for the
+/// algorithm.
+#[implement(super::Service)]
+pub async fn add_fallback_key(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+	key_id: &OneTimeKeyId,
+	key: &Raw<OneTimeKey>,
+) -> Result {
+	if self
+		.db
+		.userdeviceid_metadata
+		.qry(&(user_id, device_id))
+		.await
+		.is_err()
+	{
+		return Err!(Database(error!(
+			?user_id,
+			?device_id,
+			"User does not exist or device has no metadata."
+		)));
+	}
+
+	let _lock = self.fallback_key_lock(user_id, device_id).await;
+	let algorithm = key_id.algorithm();
+	let fallback_key = FallbackKey {
+		key_id: key_id.to_owned(),
+		key: key.clone(),
+		used: false,
+	};
+
+	let count = self.services.globals.next_count();
+	self.db
+		.userdevicealgorithm_fallbackkey
+		.put((user_id, device_id, algorithm.as_str()), Json(fallback_key));
+	self.db
+		.userid_lastonetimekeyupdate
+		.raw_put(user_id, *count);
+
+	Ok(())
+}
+
+/// Serializes the updates of the fallback keys of the device, so a claim
+/// cannot write back a key replaced meanwhile.
+#[implement(super::Service)]
+async fn fallback_key_lock(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+) -> MutexMapGuard<String, ()> {
+	let key = format!("{user_id}\0{device_id}");
+
+	self.fallback_key_mutex.lock(&key).await
+}
+
+/// The algorithms the device has a fallback key for which was not claimed
+/// yet.
+#[implement(super::Service)]
+pub async fn unused_fallback_key_types(
+	&self,
+	user_id: &UserId,
+	device_id: &DeviceId,
+) -> Vec<OneTimeKeyAlgorithm> {
+	let prefix = (user_id, device_id, Interfix);
+	self.db
+		.userdevicealgorithm_fallbackkey
+		.stream_prefix(&prefix)
+		.ignore_err()
+		.ready_filter_map(|(_, fallback_key): (Ignore, FallbackKey)| {
+			(!fallback_key.used).then(|| fallback_key.key_id.algorithm())
+		})
+		.collect()
+		.await
+}
+
 #[implement(super::Service)]
 pub async fn count_one_time_keys(
 	&self,
@@ -208,9 +354,19 @@ pub async fn add_cross_signing_keys(
 	let mut prefix = user_id.as_bytes().to_vec();
 	prefix.push(0xFF);
 
+	let _cork = self.services.db.cork();
+	let mut reset = false;
 	if let Some(master_key) = master_key {
 		let (master_key_key, _) = parse_master_key(user_id, master_key)?;
 
+		if let Ok(old_master_key_key) = self.db.userid_masterkeyid.get(user_id).await
+			&& *old_master_key_key != *master_key_key
+		{
+			self.db.keyid_key.remove(&*old_master_key_key);
+			self.remove_signing_keys(user_id).await;
+			reset = true;
+		}
+
 		self.db
 			.keyid_key
 			.insert(&master_key_key, master_key.json().get().as_bytes());
@@ -268,13 +424,66 @@ pub async fn add_cross_signing_keys(
 			.raw_put(user_id, user_signing_key_key);
 	}
 
-	if notify {
+	if notify || reset {
 		self.mark_device_key_update(user_id).await;
 	}
 
 	Ok(())
 }
 
+/// Removes the self-signing and user-signing keys of a user whose master key
+/// was replaced, along with the signatures the old self-signing key made on
+/// their devices. Signatures the old user-signing key made on other users'
+/// keys are left; they no longer chain to the master key.
+#[implement(super::Service)]
+async fn remove_signing_keys(&self, user_id: &UserId) {
+	if let Ok(user_signing_key_key) = self.db.userid_usersigningkeyid.get(user_id).await {
+		self.db.keyid_key.remove(&*user_signing_key_key);
+		self.db.userid_usersigningkeyid.remove(user_id);
+	}
+
+	let Ok(self_signing_key_key) = self.db.userid_selfsigningkeyid.get(user_id).await else {
+		return;
+	};
+
+	self.db.keyid_key.remove(&*self_signing_key_key);
+	self.db.userid_selfsigningkeyid.remove(user_id);
+
+	let Some(self_signing_key) = self_signing_key_key
+		.strip_prefix(user_id.as_bytes())
+		.and_then(|key| key.strip_prefix(&[0xFF]))
+		.and_then(|key| utils::str_from_bytes(key).ok())
+	else {
+		return;
+	};
+
+	let signature_id = format!("ed25519:{self_signing_key}");
+	let device_ids: Vec<OwnedDeviceId> = self
+		.all_device_ids(user_id)
+		.map(ToOwned::to_owned)
+		.collect()
+		.await;
+
+	for device_id in &device_ids {
+		let Ok(mut device_keys) = self
+			.get_device_keys(user_id, device_id)
+			.await
+			.and_then(|keys| {
+				keys.deserialize_as_unchecked::<JsonValue>()
+					.map_err(Into::into)
+			})
+		else {
+			continue;
+		};
+
+		if remove_signature(&mut device_keys, user_id, &signature_id) {
+			self.db
+				.keyid_key
+				.put((user_id, device_id), Json(device_keys));
+		}
+	}
+}
+
 #[implement(super::Service)]
 pub async fn sign_key(
 	&self,
@@ -540,3 +749,13 @@ fn clean_signatures<F>(
 
 	Ok(cross_signing_key)
 }
+
+/// Removes the user's signature made with the key from the signed object;
+/// returns whether it was present.
+pub(super) fn remove_signature(object: &mut JsonValue, user_id: &UserId, key_id: &str) -> bool {
+	object
+		.get_mut("signatures")
+		.and_then(|signatures| signatures.get_mut(user_id.as_str()))
+		.and_then(JsonValue::as_object_mut)
+		.is_some_and(|signatures| signatures.remove(key_id).is_some())
+}
@@ -1,11 +1,21 @@
 pub mod device;
 mod keys;
 mod ldap;
+mod openid;
 mod profile;
+mod remote_devices;
+mod remote_profile;
+#[cfg(test)]
+mod tests;
+
+use std::{
+	fmt::Write,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
 
-use std::sync::Arc;
-
-use futures::{FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt, future::join3};
+use async_trait::async_trait;
+use futures::{FutureExt, Stream, StreamExt, TryStreamExt, future::join3};
 use ruma::{
 	OwnedMxcUri, OwnedRoomId, OwnedUserId, UserId,
 	api::client::filter::FilterDefinition,
@@ -15,20 +25,30 @@
 		room::member::{MembershipState, RoomMemberEventContent},
 	},
 };
+use tokio::time::interval;
 use tuwunel_core::{
-	Err, Result, debug_warn, err, is_equal_to,
+	Err, Result, err,
 	pdu::PduBuilder,
 	trace,
-	utils::{self, IterStream, ReadyExt, TryFutureExtExt, stream::TryIgnore},
+	utils::{self, IterStream, MutexMap, ReadyExt, TryFutureExtExt, stream::TryIgnore},
 	warn,
 };
-use tuwunel_database::{Deserialized, Json, Map};
+use tuwunel_database::{Deserialized, Get, Json, Map};
 
 pub use self::keys::parse_master_key;
+use self::remote_profile::{ProfileCache, RemoteProfile};
+
+/// Shortest interval between prunings of expired OpenID tokens, in seconds.
+const PRUNE_INTERVAL_MIN: u64 = 60;
+
+/// Interval between prunings of inactive devices.
+const DEVICE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 pub struct Service {
 	services: Arc<crate::services::OnceServices>,
 	db: Data,
+	remote_profiles: Mutex<ProfileCache<RemoteProfile>>,
+	fallback_key_mutex: MutexMap<String, ()>,
 }
 
 struct Data {
@@ -37,8 +57,12 @@ struct Data {
 	onetimekeyid_onetimekeys: Arc<Map>,
 	openidtoken_expiresatuserid: Arc<Map>,
 	logintoken_expiresatuserid: Arc<Map>,
+	remoteuserdeviceid_device: Arc<Map>,
+	remoteuserid_devicelistversion: Arc<Map>,
 	todeviceid_events: Arc<Map>,
 	token_userdeviceid: Arc<Map>,
+	userdevicealgorithm_fallbackkey: Arc<Map>,
+	userdeviceid_lastseen: Arc<Map>,
 	userdeviceid_metadata: Arc<Map>,
 	userdeviceid_token: Arc<Map>,
 	userdeviceid_refresh: Arc<Map>,
@@ -53,11 +77,19 @@ struct Data {
 	userid_origin: Arc<Map>,
 	userid_selfsigningkeyid: Arc<Map>,
 	userid_usersigningkeyid: Arc<Map>,
+	useridexpiresat_openidtoken: Arc<Map>,
 	useridprofilekey_value: Arc<Map>,
 }
 
+#[async_trait]
 impl crate::Service for Service {
 	fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
+		let config = &args.server.config;
+		let remote_profiles_capacity = f64::from(config.remote_profile_cache_capacity);
+		let remote_profiles_capacity = utils::math::usize_from_f64(
+			remote_profiles_capacity * config.cache_capacity_modifier,
+		)?;
+
 		Ok(Arc::new(Self {
 			services: args.services.clone(),
 			db: Data {
@@ -66,8 +98,13 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				onetimekeyid_onetimekeys: args.db["onetimekeyid_onetimekeys"].clone(),
 				openidtoken_expiresatuserid: args.db["openidtoken_expiresatuserid"].clone(),
 				logintoken_expiresatuserid: args.db["logintoken_expiresatuserid"].clone(),
+				remoteuserdeviceid_device: args.db["remoteuserdeviceid_device"].clone(),
+				remoteuserid_devicelistversion: args.db["remoteuserid_devicelistversion"].clone(),
 				todeviceid_events: args.db["todeviceid_events"].clone(),
 				token_userdeviceid: args.db["token_userdeviceid"].clone(),
+				userdevicealgorithm_fallbackkey: args.db["userdevicealgorithm_fallbackkey"]
+					.clone(),
+				userdeviceid_lastseen: args.db["userdeviceid_lastseen"].clone(),
 				userdeviceid_metadata: args.db["userdeviceid_metadata"].clone(),
 				userdeviceid_token: args.db["userdeviceid_token"].clone(),
 				userdeviceid_refresh: args.db["userdeviceid_refresh"].clone(),
@@ -82,11 +119,56 @@ fn build(args: crate::Args<'_>) -> Result<Arc<Self>> {
 				userid_origin: args.db["userid_origin"].clone(),
 				userid_selfsigningkeyid: args.db["userid_selfsigningkeyid"].clone(),
 				userid_usersigningkeyid: args.db["userid_usersigningkeyid"].clone(),
+				useridexpiresat_openidtoken: args.db["useridexpiresat_openidtoken"].clone(),
 				useridprofilekey_value: args.db["useridprofilekey_value"].clone(),
 			},
+			remote_profiles: ProfileCache::new(remote_profiles_capacity).into(),
+			fallback_key_mutex: MutexMap::new(),
 		}))
 	}
 
+	async fn worker(self: Arc<Self>) -> Result {
+		if self.services.db.is_read_only() {
+			return Ok(());
+		}
+
+		let config = &self.services.server.config;
+		let ttl = config.openid_token_ttl;
+		let mut prune_openid = interval(Duration::from_secs(ttl.max(PRUNE_INTERVAL_MIN)));
+		let mut prune_devices = interval(DEVICE_PRUNE_INTERVAL);
+		prune_openid.reset();
+		prune_devices.reset();
+
+		let inactive_for = Duration::from_secs(config.prune_inactive_devices_after);
+		while self.services.server.running() {
+			tokio::select! {
+				_ = prune_openid.tick() => {
+					self.prune_openid_tokens().await;
+				},
+				_ = prune_devices.tick(), if !inactive_for.is_zero() => {
+					self.prune_all_inactive_devices(inactive_for).await;
+				},
+				() = self.services.server.until_shutdown() => break,
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn memory_usage(&self, out: &mut (dyn Write + Send)) -> Result {
+		let remote_profiles = self.remote_profiles.lock()?.len();
+		writeln!(out, "remote_profiles: {remote_profiles}")?;
+
+		Ok(())
+	}
+
+	async fn clear_cache(&self) {
+		self.remote_profiles
+			.lock()
+			.expect("locked")
+			.clear();
+	}
+
 	fn name(&self) -> &str { crate::service::make_name(std::module_path!()) }
 }
 
@@ -145,6 +227,11 @@ pub async fn deactivate_account(&self, user_id: &UserId) -> Result {
 		// account is deactivated.
 		self.set_password(user_id, None).await?;
 
+		// Guests have no password either; a deactivated guest is no longer one.
+		if self.is_guest(user_id).await {
+			self.db.userid_origin.insert(user_id, "password");
+		}
+
 		// TODO: Unhook 3PID
 		Ok(())
 	}
@@ -157,12 +244,64 @@ pub async fn exists(&self, user_id: &UserId) -> bool {
 
 	/// Check if account is deactivated
 	pub async fn is_deactivated(&self, user_id: &UserId) -> Result<bool> {
-		self.db
+		let password = self
+			.db
 			.userid_password
 			.get(user_id)
-			.map_ok(|val| val.is_empty())
-			.map_err(|_| err!(Request(NotFound("User does not exist."))))
 			.await
+			.map_err(|_| err!(Request(NotFound("User does not exist."))))?;
+
+		let origin = self.origin(user_id).await.ok();
+
+		Ok(deactivated(&password, origin.as_deref()))
+	}
+
+	/// Check if account is a guest account, which has no password.
+	pub async fn is_guest(&self, user_id: &UserId) -> bool {
+		self.origin(user_id)
+			.await
+			.is_ok_and(|origin| origin == "guest")
+	}
+
+	/// Records as guests the accounts registered as such before guests had an
+	/// origin of their own: those without a password yet with a device, which
+	/// deactivation removes. Appservice users may have no password either.
+	pub async fn mark_guests<F>(&self, is_appservice_user: F) -> usize
+	where
+		F: Fn(&UserId) -> bool + Send + Sync,
+	{
+		let guests: Vec<OwnedUserId> = self
+			.db
+			.userid_password
+			.stream()
+			.ignore_err()
+			.ready_filter_map(|(user_id, password): (&UserId, &[u8])| {
+				password.is_empty().then_some(user_id)
+			})
+			.ready_filter(|user_id| !is_appservice_user(user_id))
+			.filter_map(async |user_id| {
+				self.origin(user_id)
+					.await
+					.is_ok_and(|origin| origin == "password")
+					.then_some(user_id)
+			})
+			.filter_map(async |user_id| {
+				self.all_device_ids(user_id)
+					.boxed()
+					.next()
+					.await
+					.is_some()
+					.then_some(user_id)
+			})
+			.map(ToOwned::to_owned)
+			.collect()
+			.await;
+
+		for user_id in &guests {
+			self.db.userid_origin.insert(user_id, "guest");
+		}
+
+		guests.len()
 	}
 
 	/// Check if account is active, infallible
@@ -197,13 +336,15 @@ pub fn stream(&self) -> impl Stream<Item = &UserId> + Send {
 	/// Returns a list of local users as list of usernames.
 	///
 	/// A user account is considered `local` if the length of it's password is
-	/// greater then zero.
+	/// greater then zero, or if it is a guest account.
 	pub fn list_local_users(&self) -> impl Stream<Item = &UserId> + Send + '_ {
 		self.db
 			.userid_password
 			.stream()
 			.ignore_err()
-			.ready_filter_map(|(u, p): (&UserId, &[u8])| (!p.is_empty()).then_some(u))
+			.filter_map(async |(u, p): (&UserId, &[u8])| {
+				(!p.is_empty() || self.is_guest(u).await).then_some(u)
+			})
 	}
 
 	/// Returns the origin of the user (password/LDAP/...).
@@ -215,6 +356,13 @@ pub async fn origin(&self, user_id: &UserId) -> Result<String> {
 			.deserialized()
 	}
 
+	/// Whether the user may change their password; LDAP users authenticate
+	/// against the directory instead.
+	pub async fn is_password_changeable(&self, user_id: &UserId) -> bool {
+		let origin = self.origin(user_id).await.ok();
+		password_changeable(origin.as_deref())
+	}
+
 	/// Returns the password hash for the given user.
 	pub async fn password_hash(&self, user_id: &UserId) -> Result<String> {
 		self.db
@@ -229,16 +377,9 @@ pub async fn set_password(&self, user_id: &UserId, password: Option<&str>) -> Re
 		// Cannot change the password of a LDAP user. There are two special cases :
 		// - a `None` password can be used to deactivate a LDAP user
 		// - a "*" password is used as the default password of an active LDAP user
-		if cfg!(feature = "ldap")
-			&& password.is_some()
+		if password.is_some()
 			&& password != Some("*")
-			&& self
-				.db
-				.userid_origin
-				.get(user_id)
-				.await
-				.deserialized::<String>()
-				.is_ok_and(is_equal_to!("ldap"))
+			&& !self.is_password_changeable(user_id).await
 		{
 			return Err!(Request(InvalidParam("Cannot change password of a LDAP user")));
 		}
@@ -287,6 +428,46 @@ pub async fn avatar_url(&self, user_id: &UserId) -> Result<OwnedMxcUri> {
 			.deserialized()
 	}
 
+	/// Returns the displayname and `avatar_url` of each user on this
+	/// homeserver, in the given order. Each is read with batched multi-gets
+	/// rather than a point get per user.
+	pub fn bulk_profiles<'a, S>(
+		&'a self,
+		user_ids: S,
+	) -> impl Stream<Item = (OwnedUserId, Option<String>, Option<OwnedMxcUri>)> + Send + 'a
+	where
+		S: Stream<Item = &'a UserId> + Send + 'a,
+	{
+		user_ids
+			.map(ToOwned::to_owned)
+			.collect::<Vec<OwnedUserId>>()
+			.map(move |user_ids| {
+				let displaynames = user_ids
+					.clone()
+					.into_iter()
+					.stream()
+					.get(&self.db.userid_displayname)
+					.map(|result| result.deserialized().ok());
+
+				let avatar_urls = user_ids
+					.clone()
+					.into_iter()
+					.stream()
+					.get(&self.db.userid_avatarurl)
+					.map(|result| result.deserialized().ok());
+
+				user_ids
+					.into_iter()
+					.stream()
+					.zip(displaynames)
+					.zip(avatar_urls)
+					.map(|((user_id, displayname), avatar_url)| {
+						(user_id, displayname, avatar_url)
+					})
+			})
+			.flatten_stream()
+	}
+
 	/// Sets a new avatar_url or removes it if avatar_url is None.
 	pub fn set_avatar_url(&self, user_id: &UserId, avatar_url: Option<OwnedMxcUri>) {
 		match avatar_url {
@@ -343,57 +524,6 @@ pub async fn get_filter(
 			.deserialized()
 	}
 
-	/// Creates an OpenID token, which can be used to prove that a user has
-	/// access to an account (primarily for integrations)
-	pub fn create_openid_token(&self, user_id: &UserId, token: &str) -> Result<u64> {
-		use std::num::Saturating as Sat;
-
-		let expires_in = self.services.server.config.openid_token_ttl;
-		let expires_at = Sat(utils::millis_since_unix_epoch()) + Sat(expires_in) * Sat(1000);
-
-		let mut value = expires_at.0.to_be_bytes().to_vec();
-		value.extend_from_slice(user_id.as_bytes());
-
-		self.db
-			.openidtoken_expiresatuserid
-			.insert(token.as_bytes(), value.as_slice());
-
-		Ok(expires_in)
-	}
-
-	/// Find out which user an OpenID access token belongs to.
-	pub async fn find_from_openid_token(&self, token: &str) -> Result<OwnedUserId> {
-		let Ok(value) = self
-			.db
-			.openidtoken_expiresatuserid
-			.get(token)
-			.await
-		else {
-			return Err!(Request(Unauthorized("OpenID token is unrecognised")));
-		};
-
-		let (expires_at_bytes, user_bytes) = value.split_at(0_u64.to_be_bytes().len());
-		let expires_at =
-			u64::from_be_bytes(expires_at_bytes.try_into().map_err(|e| {
-				err!(Database("expires_at in openid_userid is invalid u64. {e}"))
-			})?);
-
-		if expires_at < utils::millis_since_unix_epoch() {
-			debug_warn!("OpenID token is expired, removing");
-			self.db
-				.openidtoken_expiresatuserid
-				.remove(token.as_bytes());
-
-			return Err!(Request(Unauthorized("OpenID token is expired")));
-		}
-
-		let user_string = utils::string_from_bytes(user_bytes)
-			.map_err(|e| err!(Database("User ID in openid_userid is invalid unicode. {e}")))?;
-
-		OwnedUserId::try_from(user_string)
-			.map_err(|e| err!(Database("User ID in openid_userid is invalid. {e}")))
-	}
-
 	/// Creates a short-lived login token, which can be used to log in using the
 	/// `m.login.token` mechanism.
 	#[must_use]
@@ -567,3 +697,12 @@ async fn update_all_rooms(&self, user_id: &UserId, rooms: Vec<(PduBuilder, &Owne
 		}
 	}
 }
+
+/// Whether the account is deactivated; guests have no password either.
+fn deactivated(password: &[u8], origin: Option<&str>) -> bool {
+	password.is_empty() && origin != Some("guest")
+}
+
+fn password_changeable(origin: Option<&str>) -> bool {
+	!(cfg!(feature = "ldap") && origin == Some("ldap"))
+}
@@ -0,0 +1,164 @@
+//! OpenID tokens proving access to an account to integrations. Each token in
+//! `openidtoken_expiresatuserid` is indexed by its user and expiry in
+//! `useridexpiresat_openidtoken`, so the oldest tokens of a user are evicted
+//! once the user holds `openid_tokens_per_user` of them. Expired tokens are
+//! pruned periodically by the worker of the service.
+
+use std::num::Saturating as Sat;
+
+use futures::StreamExt;
+use ruma::{OwnedUserId, UserId};
+use tuwunel_core::{
+	Err, Result, debug, debug_warn, err, implement,
+	utils::{self, ReadyExt, stream::TryIgnore},
+};
+use tuwunel_database::{Ignore, Interfix};
+
+/// Creates an OpenID token, which can be used to prove that a user has
+/// access to an account (primarily for integrations). Returns the lifetime of
+/// the token in seconds.
+#[implement(super::Service)]
+pub async fn create_openid_token(&self, user_id: &UserId, token: &str) -> Result<u64> {
+	let config = &self.services.server.config;
+	let expires_in = config.openid_token_ttl;
+	let expires_at = Sat(utils::millis_since_unix_epoch()) + Sat(expires_in) * Sat(1000);
+
+	self.evict_openid_tokens(user_id, config.openid_tokens_per_user.saturating_sub(1))
+		.await;
+
+	let mut value = expires_at.0.to_be_bytes().to_vec();
+	value.extend_from_slice(user_id.as_bytes());
+
+	// The index is written first so the token is never without its entry; an
+	// entry left without its token is dropped when evicted or once expired.
+	let _cork = self.services.db.cork();
+	self.db
+		.useridexpiresat_openidtoken
+		.put_raw((user_id, expires_at.0, token), []);
+
+	self.db
+		.openidtoken_expiresatuserid
+		.insert(token.as_bytes(), value.as_slice());
+
+	Ok(expires_in)
+}
+
+/// Find out which user an OpenID access token belongs to.
+#[implement(super::Service)]
+pub async fn find_from_openid_token(&self, token: &str) -> Result<OwnedUserId> {
+	let Ok(value) = self
+		.db
+		.openidtoken_expiresatuserid
+		.get(token)
+		.await
+	else {
+		return Err!(Request(Unauthorized("OpenID token is unrecognised")));
+	};
+
+	let (expires_at, user_id) = parse_token_value(&value)?;
+	let now = utils::millis_since_unix_epoch();
+	if expires_at < now {
+		debug_warn!("OpenID token is expired, removing");
+		self.remove_openid_token(&user_id, expires_at, token);
+
+		let expired_for = now.saturating_sub(expires_at) / 1000;
+		return Err!(Request(Unauthorized(
+			"OpenID token is expired (expires_in: -{expired_for}s)"
+		)));
+	}
+
+	Ok(user_id)
+}
+
+/// Removes all expired OpenID tokens; returns the number removed.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn prune_openid_tokens(&self) -> usize {
+	let now = utils::millis_since_unix_epoch();
+	let expired: Vec<(Vec<u8>, u64, OwnedUserId)> = self
+		.db
+		.openidtoken_expiresatuserid
+		.raw_stream()
+		.ignore_err()
+		.ready_filter_map(|(token, value)| {
+			let (expires_at, user_id) = parse_token_value(value).ok()?;
+			(expires_at < now).then(|| (token.to_vec(), expires_at, user_id))
+		})
+		.collect()
+		.await;
+
+	let _cork = self.services.db.cork();
+	for (token, expires_at, user_id) in &expired {
+		let Ok(token) = std::str::from_utf8(token) else {
+			self.db.openidtoken_expiresatuserid.remove(token);
+			continue;
+		};
+
+		self.remove_openid_token(user_id, *expires_at, token);
+	}
+
+	debug!(count = expired.len(), "Pruned expired OpenID tokens");
+	expired.len()
+}
+
+/// Evicts the oldest tokens of the user until it holds at most `keep`, along
+/// with any which expired.
+#[implement(super::Service)]
+async fn evict_openid_tokens(&self, user_id: &UserId, keep: usize) {
+	let now = utils::millis_since_unix_epoch();
+	let tokens: Vec<(u64, String)> = self
+		.db
+		.useridexpiresat_openidtoken
+		.keys_prefix(&(user_id, Interfix))
+		.ignore_err()
+		.map(|(_, expires_at, token): (Ignore, u64, &str)| (expires_at, token.to_owned()))
+		.collect()
+		.await;
+
+	let evicted = tokens_to_evict(&tokens, keep, now);
+	for (expires_at, token) in &tokens[..evicted] {
+		self.remove_openid_token(user_id, *expires_at, token);
+	}
+}
+
+#[implement(super::Service)]
+fn remove_openid_token(&self, user_id: &UserId, expires_at: u64, token: &str) {
+	self.db
+		.useridexpiresat_openidtoken
+		.del((user_id, expires_at, token));
+
+	self.db
+		.openidtoken_expiresatuserid
+		.remove(token.as_bytes());
+}
+
+/// The number of tokens to evict from the front of the tokens of a user,
+/// ordered by expiry, so that at most `keep` remain and none of them expired.
+pub(super) fn tokens_to_evict<T>(tokens: &[(u64, T)], keep: usize, now: u64) -> usize {
+	let expired = tokens
+		.iter()
+		.take_while(|(expires_at, _)| *expires_at < now)
+		.count();
+
+	expired.max(tokens.len().saturating_sub(keep))
+}
+
+fn parse_token_value(value: &[u8]) -> Result<(u64, OwnedUserId)> {
+	let (expires_at_bytes, user_bytes) = value
+		.split_at_checked(size_of::<u64>())
+		.ok_or_else(|| err!(Database("Value in openid_userid is too short.")))?;
+
+	let expires_at = u64::from_be_bytes(
+		expires_at_bytes
+			.try_into()
+			.map_err(|e| err!(Database("expires_at in openid_userid is invalid u64. {e}")))?,
+	);
+
+	let user_string = utils::string_from_bytes(user_bytes)
+		.map_err(|e| err!(Database("User ID in openid_userid is invalid unicode. {e}")))?;
+
+	let user_id = OwnedUserId::try_from(user_string)
+		.map_err(|e| err!(Database("User ID in openid_userid is invalid. {e}")))?;
+
+	Ok((expires_at, user_id))
+}
@@ -1,6 +1,10 @@
 use futures::{Stream, StreamExt, TryFutureExt};
 use ruma::UserId;
-use tuwunel_core::{Result, implement, utils::stream::TryIgnore};
+use tuwunel_core::{
+	Result, err, implement,
+	messages::{self, Message},
+	utils::stream::TryIgnore,
+};
 use tuwunel_database::{Deserialized, Ignore, Interfix, Json};
 
 /// Gets a specific user profile key
@@ -54,6 +58,28 @@ pub fn set_profile_key(
 	}
 }
 
+/// Profile field in which a user chooses the language of the messages the
+/// server generates for them.
+pub const LANGUAGE_PROFILE_KEY: &str = "chat.tuwunel.language";
+
+/// Get the language a user chose for server-generated messages.
+#[implement(super::Service)]
+pub async fn language(&self, user_id: &UserId) -> Result<String> {
+	self.profile_key(user_id, LANGUAGE_PROFILE_KEY)
+		.await?
+		.as_str()
+		.map(ToOwned::to_owned)
+		.ok_or_else(|| err!(Request(InvalidParam("The language profile field is not a string."))))
+}
+
+/// Renders a server-generated message in the language of the user.
+#[implement(super::Service)]
+pub async fn message(&self, user_id: &UserId, message: Message, args: &[(&str, &str)]) -> String {
+	let language = self.language(user_id).await.ok();
+
+	messages::render(&self.services.server.config, language.as_deref(), message, args)
+}
+
 /// Get the timezone of a user.
 #[implement(super::Service)]
 pub async fn timezone(&self, user_id: &UserId) -> Result<String> {
@@ -0,0 +1,242 @@
+//! Device lists of remote users, fetched over federation once and then kept
+//! current by the `m.device_list_update` EDUs of their server. The last stream
+//! ID seen for a user is kept in `remoteuserid_devicelistversion`; an update
+//! not following it drops the list, which is fetched again when next queried.
+
+use std::collections::BTreeMap;
+
+use futures::{StreamExt, future::join_all};
+use ruma::{
+	DeviceId, OwnedDeviceId, UserId,
+	api::federation::{device::get_devices, transactions::edu::DeviceListUpdateContent},
+	encryption::DeviceKeys,
+	serde::Raw,
+};
+use tuwunel_core::{
+	Err, Result, debug, implement,
+	utils::{ReadyExt, result::LogDebugErr, stream::TryIgnore},
+};
+use tuwunel_database::{Deserialized, Ignore, Interfix, Json};
+
+/// How an update of the device list of a remote user applies to the list
+/// cached for them.
+#[derive(Debug, Eq, PartialEq)]
+pub(super) enum DeviceListStep {
+	/// The update was already seen.
+	Stale,
+
+	/// The update follows the cached list.
+	Apply,
+
+	/// Updates were missed, or no list is cached; the cached list is dropped.
+	Resync,
+}
+
+/// The step for an update with the stream ID and previous IDs, when the last
+/// stream ID seen for the user is `known`. The update follows when it names
+/// the known ID among its previous ones, and no ID after it.
+pub(super) fn device_list_step(
+	known: Option<u64>,
+	stream_id: u64,
+	prev_id: &[u64],
+) -> DeviceListStep {
+	let Some(known) = known else {
+		return DeviceListStep::Resync;
+	};
+
+	if stream_id <= known {
+		return DeviceListStep::Stale;
+	}
+
+	if prev_id.contains(&known) && prev_id.iter().all(|&id| id <= known) {
+		DeviceListStep::Apply
+	} else {
+		DeviceListStep::Resync
+	}
+}
+
+/// The device keys of the remote user from their cached device list; `None`
+/// when no list is cached.
+#[implement(super::Service)]
+pub async fn remote_device_keys(
+	&self,
+	user_id: &UserId,
+) -> Option<BTreeMap<OwnedDeviceId, Raw<DeviceKeys>>> {
+	self.remote_device_list_version(user_id).await?;
+
+	Some(self.cached_remote_devices(user_id).await)
+}
+
+/// Fetches the device lists of the remote users over federation to cache
+/// them. Users sharing no room with us are skipped, as no update of their list
+/// would reach us.
+#[implement(super::Service)]
+pub async fn cache_remote_devices<'a, I>(&self, users: I)
+where
+	I: IntoIterator<Item = &'a UserId> + Send,
+	I::IntoIter: Send,
+{
+	let fetches = users.into_iter().map(async |user_id| {
+		if self.shares_room(user_id).await {
+			self.fetch_remote_devices(user_id)
+				.await
+				.log_debug_err()
+				.ok();
+		}
+	});
+
+	join_all(fetches).await;
+}
+
+/// Drops the cached device list of the remote user once no room is shared
+/// with them anymore, as updates of it no longer reach us.
+#[implement(super::Service)]
+pub async fn forget_unshared_remote_devices(&self, user_id: &UserId) {
+	if self
+		.remote_device_list_version(user_id)
+		.await
+		.is_none()
+	{
+		return;
+	}
+
+	if !self.shares_room(user_id).await {
+		debug!(%user_id, "Dropping device list of a user sharing no room");
+		self.forget_remote_devices(user_id).await;
+	}
+}
+
+/// Applies an update of the device list of a remote user to the list cached
+/// for them, and notes the change of their keys for local clients.
+#[implement(super::Service)]
+pub async fn update_remote_device_list(&self, content: &DeviceListUpdateContent) {
+	let user_id = &content.user_id;
+	let stream_id: u64 = content.stream_id.into();
+	let prev_id: Vec<u64> = content
+		.prev_id
+		.iter()
+		.copied()
+		.map(Into::into)
+		.collect();
+
+	let known = self.remote_device_list_version(user_id).await;
+	match device_list_step(known, stream_id, &prev_id) {
+		| DeviceListStep::Stale => return,
+		| DeviceListStep::Resync => {
+			debug!(%user_id, ?known, stream_id, ?prev_id, "Dropping device list missing updates");
+			self.forget_remote_devices(user_id).await;
+		},
+		| DeviceListStep::Apply => {
+			let key = (user_id, &content.device_id);
+			if content.deleted == Some(true) {
+				self.db.remoteuserdeviceid_device.del(key);
+			} else if let Some(keys) = &content.keys {
+				self.db
+					.remoteuserdeviceid_device
+					.put(key, Json(keys));
+			}
+
+			self.db
+				.remoteuserid_devicelistversion
+				.raw_put(user_id, stream_id);
+		},
+	}
+
+	self.mark_device_key_update(user_id).await;
+}
+
+/// Drops the cached device list of the remote user.
+#[implement(super::Service)]
+pub async fn forget_remote_devices(&self, user_id: &UserId) {
+	self.db
+		.remoteuserid_devicelistversion
+		.remove(user_id);
+
+	let prefix = (user_id, Interfix);
+	self.db
+		.remoteuserdeviceid_device
+		.keys_prefix_raw(&prefix)
+		.ignore_err()
+		.ready_for_each(|key| self.db.remoteuserdeviceid_device.remove(key))
+		.await;
+}
+
+/// Whether the remote user is joined to a room this server is in.
+#[implement(super::Service)]
+async fn shares_room(&self, user_id: &UserId) -> bool {
+	let server_name = self.services.globals.server_name();
+
+	self.services
+		.state_cache
+		.rooms_joined(user_id)
+		.any(|room_id| {
+			self.services
+				.state_cache
+				.server_in_room(server_name, room_id)
+		})
+		.await
+}
+
+#[implement(super::Service)]
+async fn fetch_remote_devices(&self, user_id: &UserId) -> Result {
+	let response = self
+		.services
+		.sending
+		.send_federation_request(user_id.server_name(), get_devices::v1::Request {
+			user_id: user_id.to_owned(),
+		})
+		.await?;
+
+	if response.user_id != user_id {
+		return Err!(BadServerResponse("Device list of {user_id} is of another user."));
+	}
+
+	self.forget_remote_devices(user_id).await;
+	for device in &response.devices {
+		let key = (user_id, &device.device_id);
+		self.db
+			.remoteuserdeviceid_device
+			.put(key, Json(&device.keys));
+	}
+
+	self.add_cross_signing_keys(
+		user_id,
+		&response.master_key,
+		&response.self_signing_key,
+		&None,
+		false,
+	)
+	.await?;
+
+	self.db
+		.remoteuserid_devicelistversion
+		.raw_put(user_id, u64::from(response.stream_id));
+
+	Ok(())
+}
+
+#[implement(super::Service)]
+async fn cached_remote_devices(
+	&self,
+	user_id: &UserId,
+) -> BTreeMap<OwnedDeviceId, Raw<DeviceKeys>> {
+	type KeyVal<'a> = ((Ignore, &'a DeviceId), Raw<DeviceKeys>);
+
+	self.db
+		.remoteuserdeviceid_device
+		.stream_prefix(&(user_id, Interfix))
+		.ignore_err()
+		.map(|((_, device_id), keys): KeyVal<'_>| (device_id.to_owned(), keys))
+		.collect()
+		.await
+}
+
+#[implement(super::Service)]
+async fn remote_device_list_version(&self, user_id: &UserId) -> Option<u64> {
+	self.db
+		.remoteuserid_devicelistversion
+		.get(user_id)
+		.await
+		.deserialized()
+		.ok()
+}
@@ -0,0 +1,117 @@
+//! Profiles of remote users fetched over federation, cached for a while so
+//! that lists of remote members do not each query their server, and failures
+//! cached for less so that unreachable servers are not queried again at once.
+
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use lru_cache::LruCache;
+use ruma::{OwnedUserId, UserId, api::federation::query::get_profile_information};
+use tuwunel_core::{Result, err, implement};
+
+pub type RemoteProfile = Arc<get_profile_information::v1::Response>;
+
+/// Profiles by user, or their absence when the fetch failed, with when they
+/// were fetched. The least recently used are evicted past the capacity.
+pub(super) struct ProfileCache<V> {
+	entries: LruCache<OwnedUserId, (Instant, Option<V>)>,
+}
+
+impl<V: Clone> ProfileCache<V> {
+	pub(super) fn new(capacity: usize) -> Self { Self { entries: LruCache::new(capacity) } }
+
+	/// The cached profile, or `Some(None)` for a cached failure; `None` when
+	/// nothing fresh is cached.
+	pub(super) fn get(
+		&mut self,
+		user_id: &UserId,
+		now: Instant,
+		ttl: Duration,
+		negative_ttl: Duration,
+	) -> Option<Option<V>> {
+		let (fetched_at, profile) = self.entries.get_mut(user_id)?;
+		let ttl = if profile.is_some() { ttl } else { negative_ttl };
+
+		(now.saturating_duration_since(*fetched_at) < ttl).then(|| profile.clone())
+	}
+
+	pub(super) fn insert(&mut self, user_id: OwnedUserId, profile: Option<V>, now: Instant) {
+		self.entries.insert(user_id, (now, profile));
+	}
+
+	pub(super) fn remove(&mut self, user_id: &UserId) { self.entries.remove(user_id); }
+
+	pub(super) fn len(&self) -> usize { self.entries.len() }
+
+	pub(super) fn clear(&mut self) { self.entries.clear(); }
+}
+
+/// The profile of the remote user, fetched over federation unless cached. A
+/// fetched profile also updates the local copy of the user.
+#[implement(super::Service)]
+#[tracing::instrument(skip(self), level = "debug")]
+pub async fn remote_profile(&self, user_id: &UserId) -> Result<RemoteProfile> {
+	let (ttl, negative_ttl) = self.remote_profile_ttls();
+	let cached = self.remote_profiles.lock().expect("locked").get(
+		user_id,
+		Instant::now(),
+		ttl,
+		negative_ttl,
+	);
+
+	if let Some(profile) = cached {
+		return profile.ok_or_else(|| err!(Request(NotFound("Profile was not found."))));
+	}
+
+	let response = self
+		.services
+		.sending
+		.send_federation_request(user_id.server_name(), get_profile_information::v1::Request {
+			user_id: user_id.to_owned(),
+			field: None,
+		})
+		.await
+		.map(Arc::new);
+
+	self.remote_profiles
+		.lock()
+		.expect("locked")
+		.insert(user_id.to_owned(), response.as_ref().ok().cloned(), Instant::now());
+
+	let response = response?;
+	if !self.exists(user_id).await {
+		self.create(user_id, None, None).await?;
+	}
+
+	self.set_displayname(user_id, response.displayname.clone());
+	self.set_avatar_url(user_id, response.avatar_url.clone());
+	self.set_blurhash(user_id, response.blurhash.clone());
+	self.set_timezone(user_id, response.tz.clone());
+	for (profile_key, profile_key_value) in &response.custom_profile_fields {
+		self.set_profile_key(user_id, profile_key, Some(profile_key_value.clone()));
+	}
+
+	Ok(response)
+}
+
+/// Drops the cached profile of the remote user, so the next lookup fetches
+/// it again.
+#[implement(super::Service)]
+pub fn forget_remote_profile(&self, user_id: &UserId) {
+	self.remote_profiles
+		.lock()
+		.expect("locked")
+		.remove(user_id);
+}
+
+#[implement(super::Service)]
+fn remote_profile_ttls(&self) -> (Duration, Duration) {
+	let config = &self.services.server.config;
+
+	(
+		Duration::from_secs(config.remote_profile_cache_ttl),
+		Duration::from_secs(config.remote_profile_negative_cache_ttl),
+	)
+}
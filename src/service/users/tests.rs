@@ -0,0 +1,942 @@
+use super::{deactivated, password_changeable};
+
+#[test]
+fn ldap_users_cannot_change_password() {
+	assert!(password_changeable(Some("password")));
+	assert!(password_changeable(None));
+	assert_eq!(password_changeable(Some("ldap")), !cfg!(feature = "ldap"));
+}
+
+#[test]
+fn guests_are_not_deactivated() {
+	assert!(deactivated(b"", Some("password")), "no password is deactivated");
+	assert!(deactivated(b"", None), "no password and no origin is deactivated");
+	assert!(!deactivated(b"", Some("guest")), "guests have no password");
+	assert!(!deactivated(b"$argon2id$", Some("password")), "password is active");
+}
+
+#[test]
+fn reset_removes_old_self_signing_signature() {
+	use ruma::user_id;
+	use serde_json::json;
+
+	use super::keys::remove_signature;
+
+	let alice = user_id!("@alice:example.com");
+	let mut device_keys = json!({
+		"user_id": alice,
+		"device_id": "ABCDEF",
+		"signatures": {
+			"@alice:example.com": {
+				"ed25519:ABCDEF": "device",
+				"ed25519:oldssk": "cross-signed",
+			},
+		},
+	});
+
+	assert!(remove_signature(&mut device_keys, alice, "ed25519:oldssk"));
+	assert!(!remove_signature(&mut device_keys, alice, "ed25519:oldssk"));
+	assert_eq!(
+		device_keys["signatures"]["@alice:example.com"],
+		json!({ "ed25519:ABCDEF": "device" })
+	);
+}
+
+#[test]
+fn openid_eviction_drops_expired_and_oldest() {
+	use super::openid::tokens_to_evict;
+
+	let tokens = [(1_000_u64, "a"), (2_000, "b"), (3_000, "c"), (4_000, "d")];
+
+	assert_eq!(tokens_to_evict(&tokens, 9, 500), 0, "below the limit nothing is evicted");
+	assert_eq!(tokens_to_evict(&tokens, 2, 500), 2, "the oldest are evicted down to the limit");
+	assert_eq!(tokens_to_evict(&tokens, 9, 2_500), 2, "expired tokens are always evicted");
+	assert_eq!(tokens_to_evict(&tokens, 1, 2_500), 3, "the limit applies after expiry");
+	assert_eq!(tokens_to_evict(&tokens, 0, 0), 4, "a limit of zero evicts all");
+}
+
+#[test]
+fn remote_profile_negative_cache() {
+	use std::time::{Duration, Instant};
+
+	use ruma::{owned_user_id, user_id};
+
+	use super::remote_profile::ProfileCache;
+
+	let (ttl, negative_ttl) = (Duration::from_secs(300), Duration::from_secs(60));
+	let alice = user_id!("@alice:dead.example.com");
+	let bob = user_id!("@bob:example.com");
+	let start = Instant::now();
+	let later = |secs| {
+		start
+			.checked_add(Duration::from_secs(secs))
+			.expect("valid instant")
+	};
+
+	let mut cache = ProfileCache::<&str>::new(2);
+	assert_eq!(cache.get(alice, start, ttl, negative_ttl), None, "nothing is cached at first");
+
+	cache.insert(alice.to_owned(), None, start);
+	cache.insert(bob.to_owned(), Some("Bob"), start);
+	assert_eq!(
+		cache.get(alice, later(30), ttl, negative_ttl),
+		Some(None),
+		"a failure is cached"
+	);
+	assert_eq!(cache.get(alice, later(60), ttl, negative_ttl), None, "a failure expires sooner");
+	assert_eq!(
+		cache.get(bob, later(60), ttl, negative_ttl),
+		Some(Some("Bob")),
+		"a profile lasts"
+	);
+
+	cache.insert(owned_user_id!("@carol:example.com"), Some("Carol"), later(60));
+	assert_eq!(cache.len(), 2, "the capacity is kept");
+	assert_eq!(
+		cache.get(bob, later(60), ttl, negative_ttl),
+		Some(Some("Bob")),
+		"the least recently used was evicted instead"
+	);
+
+	cache.remove(bob);
+	assert_eq!(
+		cache.get(bob, later(60), ttl, negative_ttl),
+		None,
+		"a membership change drops it"
+	);
+}
+
+#[test]
+fn remote_device_list_updates_in_order() {
+	use super::remote_devices::{DeviceListStep, device_list_step};
+
+	let mut known = Some(3);
+	for (stream_id, prev_id) in [(4, vec![3]), (5, vec![4]), (7, vec![5])] {
+		assert_eq!(
+			device_list_step(known, stream_id, &prev_id),
+			DeviceListStep::Apply,
+			"update {stream_id} follows {known:?}"
+		);
+		known = Some(stream_id);
+	}
+
+	assert_eq!(
+		device_list_step(known, 7, &[5]),
+		DeviceListStep::Stale,
+		"a repeated update is stale"
+	);
+	assert_eq!(
+		device_list_step(known, 6, &[5]),
+		DeviceListStep::Stale,
+		"an older update is stale"
+	);
+}
+
+#[test]
+fn remote_device_list_updates_gapped() {
+	use super::remote_devices::{DeviceListStep, device_list_step};
+
+	assert_eq!(
+		device_list_step(Some(3), 6, &[5]),
+		DeviceListStep::Resync,
+		"an update after a missed one drops the list"
+	);
+	assert_eq!(
+		device_list_step(Some(3), 6, &[3, 5]),
+		DeviceListStep::Resync,
+		"an update also following a missed one drops the list"
+	);
+	assert_eq!(
+		device_list_step(Some(3), 6, &[]),
+		DeviceListStep::Resync,
+		"an update following nothing drops the list"
+	);
+	assert_eq!(
+		device_list_step(None, 6, &[5]),
+		DeviceListStep::Resync,
+		"no list is cached to update"
+	);
+}
+
+#[test]
+fn device_seen_within() {
+	use std::time::Duration;
+
+	use ruma::{MilliSecondsSinceUnixEpoch, UInt};
+
+	use super::device::{LAST_SEEN_INTERVAL, seen_within};
+
+	let at = |millis: u32| MilliSecondsSinceUnixEpoch(UInt::from(millis));
+	let now = at(10 * 60 * 1000);
+
+	assert!(
+		seen_within(at(6 * 60 * 1000), now, LAST_SEEN_INTERVAL),
+		"a device seen four minutes ago is not written again"
+	);
+	assert!(
+		!seen_within(at(4 * 60 * 1000), now, LAST_SEEN_INTERVAL),
+		"a device seen six minutes ago is written again"
+	);
+	assert!(
+		!seen_within(at(0), now, Duration::from_secs(5 * 60)),
+		"a device unseen for longer than the window is inactive"
+	);
+	assert!(
+		seen_within(at(11 * 60 * 1000), now, Duration::from_secs(1)),
+		"a time after now was seen within any window"
+	);
+}
+
+#[test]
+fn fallback_key_after_one_time_keys_run_out() {
+	use ruma::{OwnedOneTimeKeyId, serde::Raw};
+	use serde_json::json;
+
+	use super::keys::{ClaimedKey, FallbackKey, claim_key};
+
+	let key = |id: &str| -> ClaimedKey {
+		let key_id = OwnedOneTimeKeyId::try_from(id).expect("valid key id");
+		let key = Raw::from_json(
+			serde_json::value::to_raw_value(&json!({ "key": id })).expect("valid key"),
+		);
+
+		(key_id, key)
+	};
+
+	let (key_id, key) = key("signed_curve25519:fallback");
+	let mut fallback_key = FallbackKey { key_id, key, used: false };
+	let mut one_time_keys = vec![key("signed_curve25519:b"), key("signed_curve25519:a")];
+
+	let claimed: Vec<_> = (0..4)
+		.map(|_| claim_key(one_time_keys.pop(), Some(&mut fallback_key)))
+		.map(|claimed| claimed.map(|(key_id, _)| key_id.to_string()))
+		.collect();
+
+	assert_eq!(
+		claimed[..2],
+		[Some("signed_curve25519:a".to_owned()), Some("signed_curve25519:b".to_owned())],
+		"one-time keys are claimed first"
+	);
+	assert_eq!(
+		claimed[2..],
+		[
+			Some("signed_curve25519:fallback".to_owned()),
+			Some("signed_curve25519:fallback".to_owned())
+		],
+		"the fallback key is claimed once they run out, and again after"
+	);
+	assert!(fallback_key.used, "the claimed fallback key is marked used");
+}
+
+#[test]
+fn fallback_key_unused_until_claimed() {
+	use ruma::{OwnedOneTimeKeyId, serde::Raw};
+	use serde_json::json;
+
+	use super::keys::{FallbackKey, claim_key};
+
+	let key_id = OwnedOneTimeKeyId::try_from("signed_curve25519:fallback").expect("valid key id");
+	let key = Raw::from_json(
+		serde_json::value::to_raw_value(&json!({ "key": "fallback" })).expect("valid key"),
+	);
+
+	let mut fallback_key = FallbackKey { key_id, key, used: false };
+	let one_time_key = (fallback_key.key_id.clone(), fallback_key.key.clone());
+
+	assert!(claim_key(Some(one_time_key), Some(&mut fallback_key)).is_some());
+	assert!(!fallback_key.used, "claiming a one-time key leaves the fallback key unused");
+
+	assert!(claim_key(None, None).is_none(), "nothing is claimed without a fallback key");
+}
+
+#[test]
+fn device_inactive_after_seen_since() {
+	use std::time::Duration;
+
+	use ruma::{MilliSecondsSinceUnixEpoch, UInt};
+
+	use super::device::is_inactive;
+
+	let day = 24 * 60 * 60 * 1000;
+	let at = |days: u32| MilliSecondsSinceUnixEpoch(UInt::from(days.saturating_mul(day)));
+	let inactive_for = Duration::from_secs(7 * 24 * 60 * 60);
+
+	assert!(
+		!is_inactive(Some(at(1)), at(20), at(25), inactive_for),
+		"a device unseen since before upgrading is kept until the duration after it"
+	);
+	assert!(
+		is_inactive(Some(at(1)), at(20), at(28), inactive_for),
+		"a device unseen for the duration after upgrading is pruned"
+	);
+	assert!(
+		!is_inactive(None, at(20), at(25), inactive_for),
+		"a device never seen is taken as seen when upgrading"
+	);
+	assert!(
+		!is_inactive(Some(at(24)), at(20), at(30), inactive_for),
+		"a device seen after upgrading is kept within the duration"
+	);
+}
+
+#[tokio::test]
+async fn fallback_key_claimed_through_service() {
+	use ruma::{OneTimeKeyAlgorithm, OwnedOneTimeKeyId, device_id, serde::Raw};
+	use serde_json::json;
+
+	use crate::fixture::Fixture;
+
+	let key = |id: &str| {
+		let key_id = OwnedOneTimeKeyId::try_from(id).expect("valid key id");
+		let key = Raw::from_json(
+			serde_json::value::to_raw_value(&json!({ "key": id })).expect("valid key"),
+		);
+
+		(key_id, key)
+	};
+
+	let services = Fixture::start().await;
+	let users = &services.users;
+	let algorithm = OneTimeKeyAlgorithm::SignedCurve25519;
+	let device_id = device_id!("DEVICE");
+	let user_id = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	users
+		.create_device(&user_id, device_id, ("token", None), None, None, None)
+		.await
+		.expect("device created");
+
+	let (key_id, one_time_key) = key("signed_curve25519:a");
+	users
+		.add_one_time_key(&user_id, device_id, &key_id, &one_time_key)
+		.await
+		.expect("one-time key added");
+
+	let (key_id, fallback_key) = key("signed_curve25519:fallback");
+	users
+		.add_fallback_key(&user_id, device_id, &key_id, &fallback_key)
+		.await
+		.expect("fallback key added");
+
+	let claim = async || {
+		users
+			.claim_one_time_key(&user_id, device_id, &algorithm)
+			.await
+			.map(|(key_id, _)| key_id.to_string())
+			.ok()
+	};
+
+	assert_eq!(claim().await.as_deref(), Some("signed_curve25519:a"), "one-time key first");
+	assert_eq!(
+		users
+			.unused_fallback_key_types(&user_id, device_id)
+			.await,
+		[algorithm.clone()],
+		"the fallback key is unused while one-time keys remain"
+	);
+
+	for _ in 0..2 {
+		assert_eq!(
+			claim().await.as_deref(),
+			Some("signed_curve25519:fallback"),
+			"the fallback key is claimed once they run out, and again after"
+		);
+	}
+	assert!(
+		users
+			.unused_fallback_key_types(&user_id, device_id)
+			.await
+			.is_empty(),
+		"the claimed fallback key is marked used"
+	);
+
+	let (key_id, fallback_key) = key("signed_curve25519:replaced");
+	users
+		.add_fallback_key(&user_id, device_id, &key_id, &fallback_key)
+		.await
+		.expect("fallback key replaced");
+
+	assert_eq!(
+		users
+			.unused_fallback_key_types(&user_id, device_id)
+			.await,
+		[algorithm.clone()],
+		"the replacement is unused"
+	);
+	assert_eq!(
+		claim().await.as_deref(),
+		Some("signed_curve25519:replaced"),
+		"the replacement is claimed"
+	);
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn remote_devices_forgotten_without_shared_room() {
+	use ruma::{device_id, encryption::DeviceKeys, serde::Raw, user_id};
+	use serde_json::json;
+	use tuwunel_database::Json;
+
+	use crate::fixture::Fixture;
+
+	let services = Fixture::start().await;
+	let users = &services.users;
+	let bob = user_id!("@bob:remote.example.com");
+	let keys: Raw<DeviceKeys> = Raw::from_json(
+		serde_json::value::to_raw_value(&json!({ "device_id": "BOBPHONE" })).expect("valid keys"),
+	);
+
+	// A list cached while a room was shared
+	users
+		.db
+		.remoteuserdeviceid_device
+		.put((bob, device_id!("BOBPHONE")), Json(&keys));
+	users
+		.db
+		.remoteuserid_devicelistversion
+		.raw_put(bob, 7_u64);
+
+	assert_eq!(
+		users
+			.remote_device_keys(bob)
+			.await
+			.map(|devices| devices.len()),
+		Some(1),
+		"the cached list is served"
+	);
+
+	users.forget_unshared_remote_devices(bob).await;
+	assert!(
+		users.remote_device_keys(bob).await.is_none(),
+		"the list of a user sharing no room is dropped"
+	);
+
+	services.stop().await;
+}
+
+#[tokio::test]
+async fn messages_in_profile_language() {
+	use serde_json::json;
+	use tuwunel_core::messages::Message;
+
+	use super::profile::LANGUAGE_PROFILE_KEY;
+	use crate::fixture::Fixture;
+
+	let services =
+		Fixture::start_with("[global.messages.fr]\nroom_deleted = \"Salon supprimé\"").await;
+	let users = &services.users;
+	let alice = services
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let message = users
+		.message(&alice, Message::RoomDeleted, &[])
+		.await;
+	assert_eq!(message, "Room Deleted", "without a language the message is in English");
+
+	users.set_profile_key(&alice, LANGUAGE_PROFILE_KEY, Some(json!("fr")));
+	let message = users
+		.message(&alice, Message::RoomDeleted, &[])
+		.await;
+	assert_eq!(message, "Salon supprimé", "the language of the profile is used");
+
+	users.set_profile_key(&alice, LANGUAGE_PROFILE_KEY, Some(json!(["fr"])));
+	assert!(users.language(&alice).await.is_err(), "a language must be a string");
+
+	services.stop().await;
+}
+
+/// A key share to 500 devices is written with a single flush, whereas as many
+/// writes one at a time flush each.
+#[tokio::test]
+async fn to_device_fan_out_batched() {
+	use futures::StreamExt;
+	use ruma::{OwnedDeviceId, OwnedUserId, device_id, user_id};
+	use serde_json::json;
+	use tuwunel_database::Json;
+
+	use crate::fixture::Fixture;
+
+	const RECIPIENTS: usize = 500;
+
+	let services = Fixture::start().await;
+	let users = &services.users;
+	let engine = &services.db.db;
+	let sender = user_id!("@alice:fixture.localhost");
+
+	let flushes = engine.flush_count();
+	for count in 0..10_u64 {
+		users
+			.db
+			.todeviceid_events
+			.put((sender, device_id!("UNBATCHED"), count), Json(json!({ "count": count })));
+	}
+	assert!(
+		engine.flush_count() >= flushes.saturating_add(10),
+		"writes outside of a cork flush one at a time"
+	);
+
+	let targets: Vec<(OwnedUserId, OwnedDeviceId, _)> = (0..RECIPIENTS)
+		.map(|i| {
+			let user_id = format!("@user{}:fixture.localhost", i % 50);
+			let user_id = OwnedUserId::try_from(user_id).expect("valid user ID");
+			let device_id = OwnedDeviceId::from(format!("DEVICE{i}"));
+
+			(user_id, device_id, json!({ "algorithm": "m.megolm.v1.aes-sha2", "i": i }))
+		})
+		.collect();
+
+	let flushes = engine.flush_count();
+	users.add_to_device_events(sender, "m.room.encrypted", targets.clone());
+	assert!(
+		engine.flush_count() <= flushes.saturating_add(1),
+		"the messages to all recipients are written as one batch"
+	);
+
+	let mut queued = 0_usize;
+	for (user_id, device_id, _) in &targets {
+		queued = queued.saturating_add(
+			users
+				.get_to_device_events(user_id, device_id, None, None)
+				.count()
+				.await,
+		);
+	}
+	assert_eq!(queued, RECIPIENTS, "every recipient has its message queued");
+
+	services.stop().await;
+}
+
+/// Guests registered before they had an origin of their own are told apart
+/// from deactivated accounts and appservice users on the next start.
+#[tokio::test]
+async fn guests_marked_by_migration() {
+	use ruma::device_id;
+
+	use crate::fixture::Fixture;
+
+	const BRIDGE: &str = r#"
+[global.appservice.bridge]
+as_token = "bridge_as_token"
+hs_token = "bridge_hs_token"
+sender_localpart = "bridge"
+
+[[global.appservice.bridge.users]]
+regex = "@bridge_.*:fixture\\.localhost"
+"#;
+
+	let fixture = Fixture::start_with(BRIDGE).await;
+	let embedded = fixture.embedded();
+
+	let mut accounts = Vec::new();
+	for localpart in ["guest", "bridge_alice", "gone"] {
+		let user_id = embedded
+			.create_user(localpart, None)
+			.await
+			.expect("user created");
+
+		accounts.push(user_id);
+	}
+
+	drop(embedded);
+	let [guest, bridged, gone] = accounts.as_slice() else {
+		unreachable!("three accounts created");
+	};
+
+	for (user_id, token) in [(guest, "guest_token"), (bridged, "bridge_token")] {
+		fixture
+			.users
+			.create_device(user_id, device_id!("DEVICE"), (token, None), None, None, None)
+			.await
+			.expect("device created");
+	}
+
+	assert!(
+		!fixture.users.is_guest(guest).await,
+		"guests used to be registered without an origin"
+	);
+	fixture.db["global"].remove(b"mark_guest_accounts");
+
+	let fixture = fixture.restart().await;
+	let users = &fixture.users;
+
+	assert!(users.is_guest(guest).await, "account without a password yet with a device");
+	assert!(
+		!users
+			.is_deactivated(guest)
+			.await
+			.expect("user exists"),
+		"guest is not deactivated"
+	);
+
+	assert!(!users.is_guest(bridged).await, "appservice users are no guests");
+	assert!(!users.is_guest(gone).await, "deactivated accounts have no device");
+	assert!(
+		users
+			.is_deactivated(gone)
+			.await
+			.expect("user exists"),
+		"still deactivated"
+	);
+
+	fixture.stop().await;
+}
+
+/// Tokens are found by their hash, and the migration hashes the tokens stored
+/// in plaintext once, leaving those already hashed as they are.
+#[tokio::test]
+async fn tokens_hashed_at_rest() {
+	use futures::StreamExt;
+	use ruma::device_id;
+	use tuwunel_core::utils::stream::TryIgnore;
+	use tuwunel_database::Database;
+
+	use super::device::token_hash;
+	use crate::fixture::Fixture;
+
+	async fn rows(db: &Database, map: &str) -> Vec<(Vec<u8>, Vec<u8>)> {
+		db[map]
+			.raw_stream()
+			.ignore_err()
+			.map(|(key, val)| (key.to_vec(), val.to_vec()))
+			.collect()
+			.await
+	}
+
+	let fixture = Fixture::start().await;
+	let alice = fixture
+		.embedded()
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+
+	let (current, legacy) = (device_id!("CURRENT"), device_id!("LEGACY"));
+	for (device_id, token, refresh) in [
+		(current, "access_token", "refresh_token"),
+		(legacy, "legacy_token", "refresh_legacy"),
+	] {
+		fixture
+			.users
+			.create_device(&alice, device_id, (token, None), Some(refresh), None, None)
+			.await
+			.expect("device created");
+	}
+
+	for (token, device_id) in [("access_token", current), ("refresh_token", current)] {
+		let (user_id, found, _) = fixture
+			.users
+			.find_from_token(token)
+			.await
+			.expect("token found by its hash");
+		assert_eq!((&*user_id, &*found), (&*alice, device_id), "token of the device");
+	}
+
+	let token_userdeviceid = &fixture.db["token_userdeviceid"];
+	assert!(
+		token_userdeviceid
+			.get(b"access_token")
+			.await
+			.is_err(),
+		"the token is not stored in plaintext"
+	);
+
+	// As stored before tokens were hashed
+	let hashed = token_hash("legacy_token");
+	let value = token_userdeviceid
+		.get(&hashed)
+		.await
+		.expect("hashed token stored")
+		.to_vec();
+	token_userdeviceid.remove(&hashed);
+	token_userdeviceid.insert(b"legacy_token", value);
+	fixture.db["userdeviceid_token"].put_raw((&alice, legacy), "legacy_token");
+	fixture.db["global"].remove(b"hash_tokens_at_rest");
+
+	let fixture = fixture.restart().await;
+	let users = &fixture.users;
+	let (user_id, device_id, _) = users
+		.find_from_token("legacy_token")
+		.await
+		.expect("legacy token authenticates after the migration");
+	assert_eq!((&*user_id, &*device_id), (&*alice, legacy), "token of the device");
+
+	let token_userdeviceid = &fixture.db["token_userdeviceid"];
+	assert!(
+		token_userdeviceid
+			.get(b"legacy_token")
+			.await
+			.is_err(),
+		"the plaintext token is removed"
+	);
+
+	let before = (
+		rows(&fixture.db, "token_userdeviceid").await,
+		rows(&fixture.db, "userdeviceid_token").await,
+		rows(&fixture.db, "userdeviceid_refresh").await,
+	);
+	assert_eq!(before.0.len(), 4, "two access and two refresh tokens");
+	assert!(
+		before
+			.1
+			.iter()
+			.any(|(_, token)| token.as_slice() == &hashed[..]),
+		"the token of the device is hashed"
+	);
+
+	fixture.db["global"].remove(b"hash_tokens_at_rest");
+	let fixture = fixture.restart().await;
+	let after = (
+		rows(&fixture.db, "token_userdeviceid").await,
+		rows(&fixture.db, "userdeviceid_token").await,
+		rows(&fixture.db, "userdeviceid_refresh").await,
+	);
+	assert_eq!(after, before, "hashed tokens are left as they are when run again");
+
+	for token in ["access_token", "refresh_token", "legacy_token", "refresh_legacy"] {
+		fixture
+			.users
+			.find_from_token(token)
+			.await
+			.expect("token still authenticates");
+	}
+
+	fixture.stop().await;
+}
+
+/// The profiles of 100 users are read with one batched query of each table,
+/// in the order asked.
+#[tokio::test]
+async fn bulk_profiles_batched() {
+	use futures::StreamExt;
+	use ruma::{OwnedMxcUri, OwnedUserId};
+	use tuwunel_core::utils::IterStream;
+
+	use crate::fixture::Fixture;
+
+	const USERS: usize = 100;
+
+	let services = Fixture::start().await;
+	let users = &services.users;
+	let engine = &services.db.db;
+
+	// Asked for in the reverse of the order they are stored in
+	let expected: Vec<_> = (0..USERS)
+		.rev()
+		.map(|i| {
+			let user_id: OwnedUserId = format!("@user{i}:fixture.localhost")
+				.try_into()
+				.expect("valid user ID");
+
+			let displayname = i.is_multiple_of(2).then(|| format!("User {i}"));
+			let avatar_url: Option<OwnedMxcUri> = i
+				.is_multiple_of(3)
+				.then(|| format!("mxc://fixture.localhost/{i}").into());
+
+			users.set_displayname(&user_id, displayname.clone());
+			users.set_avatar_url(&user_id, avatar_url.clone());
+
+			(user_id, displayname, avatar_url)
+		})
+		.collect();
+
+	let queries = engine.query_count();
+	let profiles: Vec<_> = users
+		.bulk_profiles(
+			expected
+				.iter()
+				.map(|(user_id, ..)| &**user_id)
+				.stream(),
+		)
+		.collect()
+		.await;
+
+	let queries = engine.query_count().saturating_sub(queries);
+
+	assert_eq!(profiles, expected, "every profile in the order asked");
+	assert_eq!(queries, 2, "one batched query of each of the two tables");
+
+	services.stop().await;
+}
+
+/// The fixture's database is ephemeral: it outlives restarts of the services
+/// but not the fixture.
+#[tokio::test]
+async fn ephemeral_database_kept_until_dropped() {
+	use crate::fixture::Fixture;
+
+	let fixture = Fixture::start().await;
+	let path = fixture.server.config.database_path.clone();
+	assert!(
+		path.starts_with(std::env::temp_dir()),
+		"the database is in a temporary directory"
+	);
+
+	let alice = fixture
+		.embedded()
+		.create_user("alice", Some("password"))
+		.await
+		.expect("user created");
+
+	let fixture = fixture.restart().await;
+	assert!(fixture.users.exists(&alice).await, "the account outlives a restart");
+
+	fixture.stop().await;
+	assert!(!path.exists(), "the database is deleted with the fixture");
+}
+
+/// Resetting cross-signing removes the old signing keys and their signatures,
+/// and the members of an encrypted room see the user's device list change.
+#[tokio::test]
+async fn cross_signing_reset_seen_by_room_members() {
+	use futures::{FutureExt, StreamExt};
+	use ruma::{
+		OwnedUserId, UserId, device_id, events::room::encryption::RoomEncryptionEventContent,
+		serde::Raw,
+	};
+	use serde_json::{Value as JsonValue, json, value::to_raw_value};
+	use tuwunel_core::matrix::pdu::PduBuilder;
+
+	use crate::fixture::Fixture;
+
+	fn raw<T>(value: JsonValue) -> Raw<T> {
+		Raw::from_json(to_raw_value(&value).expect("valid JSON"))
+	}
+
+	let services = Fixture::start().await;
+	let users = &services.users;
+	let embedded = services.embedded();
+	let alice = embedded
+		.create_user("alice", None)
+		.await
+		.expect("user created");
+	let bob = embedded
+		.create_user("bob", None)
+		.await
+		.expect("user created");
+
+	let room_id = embedded
+		.create_room(&alice, None)
+		.await
+		.expect("room created");
+
+	let state_lock = services.state.mutex.lock(&room_id).await;
+	services
+		.timeline
+		.build_and_append_pdu(
+			PduBuilder::state(
+				String::new(),
+				&RoomEncryptionEventContent::with_recommended_defaults(),
+			),
+			&alice,
+			&room_id,
+			&state_lock,
+		)
+		.boxed()
+		.await
+		.expect("encryption enabled");
+	drop(state_lock);
+
+	embedded
+		.join_room(&bob, &room_id)
+		.await
+		.expect("bob joined");
+
+	let signing_key = |usage: &str, name: &str| {
+		Some(raw(json!({
+			"user_id": alice,
+			"usage": [usage],
+			"keys": { format!("ed25519:{name}"): name },
+			"signatures": {},
+		})))
+	};
+
+	let device_id = device_id!("DEVICE");
+	users
+		.create_device(&alice, device_id, ("token", None), None, None, None)
+		.await
+		.expect("device created");
+
+	users
+		.add_device_keys(
+			&alice,
+			device_id,
+			&raw(json!({
+				"user_id": alice,
+				"device_id": device_id,
+				"algorithms": ["m.megolm.v1.aes-sha2"],
+				"keys": { "ed25519:DEVICE": "device" },
+				"signatures": {
+					alice.as_str(): {
+						"ed25519:DEVICE": "signed by the device",
+						"ed25519:self1": "signed by the self-signing key",
+					},
+				},
+			})),
+		)
+		.await;
+
+	users
+		.add_cross_signing_keys(
+			&alice,
+			&signing_key("master", "master1"),
+			&signing_key("self_signing", "self1"),
+			&signing_key("user_signing", "user1"),
+			true,
+		)
+		.await
+		.expect("cross-signing set up");
+
+	let changed = async |since: u64| -> Vec<OwnedUserId> {
+		users
+			.room_keys_changed(&room_id, since, None)
+			.map(|(user_id, _)| user_id.to_owned())
+			.collect()
+			.await
+	};
+
+	let since = services.globals.current_count();
+	users
+		.add_cross_signing_keys(&alice, &signing_key("master", "master1"), &None, &None, false)
+		.await
+		.expect("same master key uploaded");
+
+	assert!(changed(since).await.is_empty(), "the same master key is no change");
+
+	let since = services.globals.current_count();
+	users
+		.add_cross_signing_keys(&alice, &signing_key("master", "master2"), &None, &None, false)
+		.await
+		.expect("cross-signing reset");
+
+	assert_eq!(changed(since).await, [alice.clone()], "bob sees alice's device list change");
+
+	assert!(
+		users
+			.get_self_signing_key(None, &alice, &|_: &UserId| true)
+			.await
+			.is_err(),
+		"the old self-signing key is removed"
+	);
+
+	let device_keys: JsonValue = users
+		.get_device_keys(&alice, device_id)
+		.await
+		.expect("device keys kept")
+		.deserialize_as_unchecked()
+		.expect("valid device keys");
+
+	assert_eq!(
+		device_keys["signatures"][alice.as_str()],
+		json!({ "ed25519:DEVICE": "signed by the device" }),
+		"only the old self-signing key's signature is removed"
+	);
+
+	services.stop().await;
+}